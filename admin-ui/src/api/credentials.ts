@@ -6,8 +6,21 @@ import type {
   SuccessResponse,
   SetDisabledRequest,
   SetPriorityRequest,
+  RebalancePrioritiesResponse,
+  CredentialDuplicatesResponse,
+  ReorderCredentialsRequest,
   AddCredentialRequest,
   AddCredentialResponse,
+  StatsExport,
+  RefreshAttempt,
+  CredentialHealthResponse,
+  ConnectionDiagnosticsResponse,
+  UserUsageListResponse,
+  AdminEventListResponse,
+  TestFiltersRequest,
+  TestFiltersResponse,
+  LoadBalancingMode,
+  CredentialsQuery,
 } from '@/types/api'
 
 // 创建 axios 实例
@@ -28,8 +41,8 @@ api.interceptors.request.use((config) => {
 })
 
 // 获取所有凭据状态
-export async function getCredentials(): Promise<CredentialsStatusResponse> {
-  const { data } = await api.get<CredentialsStatusResponse>('/credentials')
+export async function getCredentials(query?: CredentialsQuery): Promise<CredentialsStatusResponse> {
+  const { data } = await api.get<CredentialsStatusResponse>('/credentials', { params: query })
   return data
 }
 
@@ -57,6 +70,43 @@ export async function setCredentialPriority(
   return data
 }
 
+// 按给定顺序重排凭据优先级
+export async function reorderCredentials(
+  ids: number[]
+): Promise<CredentialsStatusResponse> {
+  const { data } = await api.post<CredentialsStatusResponse>(
+    '/credentials/reorder',
+    { ids } as ReorderCredentialsRequest
+  )
+  return data
+}
+
+// 将凭据优先级压缩为连续整数（保持相对顺序）
+export async function rebalancePriorities(): Promise<RebalancePrioritiesResponse> {
+  const { data } = await api.post<RebalancePrioritiesResponse>(
+    '/credentials/rebalance-priorities'
+  )
+  return data
+}
+
+// 按 refreshToken 哈希列出重复的凭据
+export async function getCredentialDuplicates(): Promise<CredentialDuplicatesResponse> {
+  const { data } = await api.get<CredentialDuplicatesResponse>('/credentials/duplicates')
+  return data
+}
+
+// 将凭据提升为最高优先级
+export async function promoteCredential(id: number): Promise<CredentialsStatusResponse> {
+  const { data } = await api.post<CredentialsStatusResponse>(`/credentials/${id}/promote`)
+  return data
+}
+
+// 将凭据降为最低优先级
+export async function demoteCredential(id: number): Promise<CredentialsStatusResponse> {
+  const { data } = await api.post<CredentialsStatusResponse>(`/credentials/${id}/demote`)
+  return data
+}
+
 // 重置失败计数
 export async function resetCredentialFailure(
   id: number
@@ -71,11 +121,26 @@ export async function getCredentialBalance(id: number): Promise<BalanceResponse>
   return data
 }
 
+// 获取凭据 Token 刷新记录
+export async function getRefreshHistory(id: number): Promise<RefreshAttempt[]> {
+  const { data } = await api.get<RefreshAttempt[]>(`/credentials/${id}/refresh-history`)
+  return data
+}
+
+// 获取凭据健康评分
+export async function getCredentialHealth(id: number): Promise<CredentialHealthResponse> {
+  const { data } = await api.get<CredentialHealthResponse>(`/credentials/${id}/health`)
+  return data
+}
+
 // 添加新凭据
 export async function addCredential(
-  req: AddCredentialRequest
+  req: AddCredentialRequest,
+  idempotencyKey?: string
 ): Promise<AddCredentialResponse> {
-  const { data } = await api.post<AddCredentialResponse>('/credentials', req)
+  const { data } = await api.post<AddCredentialResponse>('/credentials', req, {
+    headers: idempotencyKey ? { 'Idempotency-Key': idempotencyKey } : undefined,
+  })
   return data
 }
 
@@ -86,13 +151,55 @@ export async function deleteCredential(id: number): Promise<SuccessResponse> {
 }
 
 // 获取负载均衡模式
-export async function getLoadBalancingMode(): Promise<{ mode: 'priority' | 'balanced' }> {
-  const { data } = await api.get<{ mode: 'priority' | 'balanced' }>('/config/load-balancing')
+export async function getLoadBalancingMode(): Promise<{ mode: LoadBalancingMode }> {
+  const { data } = await api.get<{ mode: LoadBalancingMode }>('/config/load-balancing')
   return data
 }
 
 // 设置负载均衡模式
-export async function setLoadBalancingMode(mode: 'priority' | 'balanced'): Promise<{ mode: 'priority' | 'balanced' }> {
-  const { data } = await api.put<{ mode: 'priority' | 'balanced' }>('/config/load-balancing', { mode })
+export async function setLoadBalancingMode(mode: LoadBalancingMode): Promise<{ mode: LoadBalancingMode }> {
+  const { data } = await api.put<{ mode: LoadBalancingMode }>('/config/load-balancing', { mode })
+  return data
+}
+
+// 导出凭据统计数据
+export async function exportStats(): Promise<StatsExport> {
+  const { data } = await api.get<StatsExport>('/stats/export')
+  return data
+}
+
+// 导入凭据统计数据
+export async function importStats(req: StatsExport): Promise<SuccessResponse> {
+  const { data } = await api.post<SuccessResponse>('/stats/import', req)
+  return data
+}
+
+// 试运行响应文本过滤器
+export async function testFilters(req: TestFiltersRequest): Promise<TestFiltersResponse> {
+  const { data } = await api.post<TestFiltersResponse>('/filters/test', req)
+  return data
+}
+
+// 获取按用户统计的用量
+export async function getUserUsage(): Promise<UserUsageListResponse> {
+  const { data } = await api.get<UserUsageListResponse>('/users')
+  return data
+}
+
+// 获取最近的管理事件
+export async function getEvents(): Promise<AdminEventListResponse> {
+  const { data } = await api.get<AdminEventListResponse>('/events')
+  return data
+}
+
+// 获取上游连接诊断信息
+export async function getConnectionDiagnostics(): Promise<ConnectionDiagnosticsResponse> {
+  const { data } = await api.get<ConnectionDiagnosticsResponse>('/diagnostics/connections')
+  return data
+}
+
+// 重建上游 HTTP Client
+export async function resetConnections(): Promise<ConnectionDiagnosticsResponse> {
+  const { data } = await api.post<ConnectionDiagnosticsResponse>('/diagnostics/connections/reset')
   return data
 }
@@ -204,10 +204,21 @@ export function CredentialCard({
               )}
             </div>
             <div>
-              <span className="text-muted-foreground">失败次数：</span>
-              <span className={credential.failureCount > 0 ? 'text-red-500 font-medium' : ''}>
-                {credential.failureCount}
-              </span>
+              {credential.quotaExhaustedAtPercentage != null ? (
+                <>
+                  <span className="text-muted-foreground">状态：</span>
+                  <span className="text-red-500 font-medium">
+                    额度已用尽（{Math.round(credential.quotaExhaustedAtPercentage * 100)}%）
+                  </span>
+                </>
+              ) : (
+                <>
+                  <span className="text-muted-foreground">失败次数：</span>
+                  <span className={credential.failureCount > 0 ? 'text-red-500 font-medium' : ''}>
+                    {credential.failureCount}
+                  </span>
+                </>
+              )}
             </div>
             <div>
               <span className="text-muted-foreground">订阅等级：</span>
@@ -3,7 +3,35 @@ export interface CredentialsStatusResponse {
   total: number
   available: number
   currentId: number
+  fleetHealthScore: number
+  totalEstimatedCost?: number
+  persistWarning?: string
+  autoRecoverAllDisabled: AutoRecoverMode
+  lastSelfHealAt: string | null
   credentials: CredentialStatusItem[]
+  pagination?: CredentialsPagination
+}
+
+// 所有凭据均被自动禁用时的自愈策略
+export type AutoRecoverMode = 'always' | 'with-backoff' | 'never'
+
+// 凭据列表分页信息（携带查询参数时返回）
+export interface CredentialsPagination {
+  filtered: number
+  page: number
+  pageSize: number
+  totalPages: number
+}
+
+// 凭据列表查询参数
+export interface CredentialsQuery {
+  page?: number
+  pageSize?: number
+  search?: string
+  disabled?: boolean
+  authMethod?: string
+  sort?: 'priority' | 'lastUsedAt' | 'successCount' | 'remaining'
+  order?: 'asc' | 'desc'
 }
 
 // 单个凭据状态
@@ -11,7 +39,9 @@ export interface CredentialStatusItem {
   id: number
   priority: number
   disabled: boolean
+  disabledReason: DisabledReason | null
   failureCount: number
+  failureCounts: FailureCounts
   isCurrent: boolean
   expiresAt: string | null
   authMethod: string | null
@@ -20,8 +50,52 @@ export interface CredentialStatusItem {
   refreshTokenHash?: string
   successCount: number
   lastUsedAt: string | null
+  lastLatencyMs: number | null
   hasProxy: boolean
   proxyUrl?: string
+  upstreamBaseUrl?: string
+  refreshAttemptsLastHour: number
+  refreshBackoffSecs: number | null
+  nextRefreshAttemptAt: string | null
+  healthScore: number
+  estimatedCost?: number
+  monthlyRequests: number
+  monthlyRequestLimit?: MonthlyRequestLimit
+  refreshRotations: number
+  // refreshToken 轮换后未能回写凭据文件（新令牌仅在内存中）
+  rotationUnpersisted: boolean
+  // 因额度用尽被禁用时的使用比例（1.0 表示 100%）
+  quotaExhaustedAtPercentage: number | null
+  // 仅 v2（Accept: application/vnd.kiro.admin.v2+json）返回
+  subscriptionTitle?: string | null
+}
+
+// 按类型分类的累计失败次数
+export interface FailureCounts {
+  network: number
+  upstreamServer: number
+  upstreamAuth: number
+  upstreamThrottle: number
+  client: number
+}
+
+// 单个凭据详情（密钥只返回提示信息）
+export interface CredentialDetailResponse extends CredentialStatusItem {
+  profileArn: string | null
+  region: string | null
+  authRegion: string | null
+  apiRegion: string | null
+  clientId: string | null
+  machineId: string | null
+  secrets: CredentialSecretHints
+}
+
+export interface CredentialSecretHints {
+  refreshTokenHint: string | null
+  refreshTokenLength: number | null
+  accessTokenLength: number | null
+  clientSecretHash: string | null
+  hasProxyPassword: boolean
 }
 
 // 余额响应
@@ -33,6 +107,9 @@ export interface BalanceResponse {
   remaining: number
   usagePercentage: number
   nextResetAt: number | null
+  daysUntilReset: number | null
+  usageTrend: number | null
+  dailyBudgetRemaining: number | null
 }
 
 // 成功响应
@@ -58,6 +135,189 @@ export interface SetPriorityRequest {
   priority: number
 }
 
+// 重排凭据优先级请求
+export interface ReorderCredentialsRequest {
+  ids: number[]
+}
+
+// 优先级压缩结果
+export interface PriorityReassignment {
+  id: number
+  old_priority: number
+  new_priority: number
+}
+
+export interface RebalancePrioritiesResponse {
+  reassignments: PriorityReassignment[]
+}
+
+// 重复凭据检测结果
+export interface DuplicateCredentialGroup {
+  refresh_token_hash: string
+  credential_ids: number[]
+}
+
+export interface CredentialDuplicatesResponse {
+  duplicate_groups: DuplicateCredentialGroup[]
+  unchecked_ids: number[]
+}
+
+// 统计数据导出条目
+export interface StatsExportEntry {
+  id: number
+  refreshTokenHash: string
+  successCount: number
+  lastUsedAt: string | null
+}
+
+// Token 刷新记录
+export interface RefreshAttempt {
+  attemptedAt: string
+  succeeded: boolean
+  durationMs: number
+  error: string | null
+  // 刷新响应返回了新的 refreshToken
+  rotated?: boolean
+}
+
+// 凭据健康评分
+export interface HealthFactors {
+  failureFactor: number
+  freshnessFactor: number
+  successRateFactor: number
+  quotaFactor: number
+}
+
+export interface CredentialHealthResponse {
+  id: number
+  healthScore: number
+  factors: HealthFactors
+}
+
+// 上游连接诊断
+export interface PooledClientStats {
+  proxyUrl: string | null
+  instanceId: number
+  createdAt: string
+  requestsServed: number
+  timeoutSecs: number
+  poolIdleTimeoutSecs: number
+}
+
+export interface ConnectionDiagnosticsResponse {
+  clients: PooledClientStats[]
+}
+
+// 按用户统计的用量
+export interface UserUsage {
+  userId: string
+  requestCount: number
+  inputTokens: number
+  outputTokens: number
+  lastRequestAt: string | null
+  dailyRequests: number
+  dailyLimit: number | null
+  estimatedCost?: number
+}
+
+export interface UserUsageListResponse {
+  users: UserUsage[]
+}
+
+// 本地月度请求上限
+export interface MonthlyRequestLimit {
+  softLimit?: number
+  hardLimit?: number
+  timezone?: string
+}
+
+// 管理事件
+export type AdminEventKind =
+  | 'softLimitReached'
+  | 'hardLimitReached'
+  | 'limitCleared'
+  | 'rotationUnpersisted'
+
+export interface AdminEvent {
+  at: string
+  credentialId: number
+  kind: AdminEventKind
+  message: string
+}
+
+export interface AdminEventListResponse {
+  events: AdminEvent[]
+}
+
+// 负载均衡模式
+export type LoadBalancingMode = 'priority' | 'balanced' | 'reset-aware'
+
+// 响应文本过滤器
+export type TextFilterConfig =
+  | { type: 'regex'; pattern: string; replacement?: string; firstMatchOnly?: boolean }
+  | { type: 'stripPrefix'; prefixes: string[] }
+
+// 过滤器试运行请求
+export interface TestFiltersRequest {
+  text: string
+  filters?: TextFilterConfig[]
+  chunkSize?: number
+}
+
+// 过滤器试运行响应
+export interface TestFiltersResponse {
+  output: string
+  changed: boolean
+}
+
+// 统计数据导出
+export interface StatsExport {
+  version: number
+  entries: StatsExportEntry[]
+}
+
+// 运行时状态导出（蓝绿部署迁移）
+export type DisabledReason = 'manual' | 'tooManyFailures' | 'quotaExceeded' | 'localLimitReached'
+
+export interface RefreshLimitState {
+  lastAttemptAgoMs?: number
+  backoffRemainingMs?: number
+  attemptsAgoMs: number[]
+}
+
+export interface CredentialRuntimeState {
+  id: number
+  refreshTokenHash?: string
+  failureCount: number
+  disabledReason?: DisabledReason
+  successCount: number
+  lastUsedAt?: string
+  estimatedCost: number
+  refreshHistory: RefreshAttempt[]
+  recentOutcomes: boolean[]
+  lastLatencyMs?: number
+  failureCounts: FailureCounts
+  refreshLimit?: RefreshLimitState
+  monthlyRequests?: { month: string; count: number }
+}
+
+export interface TokenManagerState {
+  currentId: number
+  loadBalancingMode: LoadBalancingMode
+  credentials: CredentialRuntimeState[]
+}
+
+export type RateLimiterState =
+  | { algorithm: 'tokenBucket'; tokens: number }
+  | { algorithm: 'fixedWindow'; windowRemainingMs: number; count: number }
+
+export interface RuntimeState {
+  version: number
+  exportedAt: string
+  tokenManager: TokenManagerState
+  rateLimiter: RateLimiterState | null
+}
+
 // 添加凭据请求
 export interface AddCredentialRequest {
   refreshToken: string
@@ -71,6 +331,8 @@ export interface AddCredentialRequest {
   proxyUrl?: string
   proxyUsername?: string
   proxyPassword?: string
+  upstreamBaseUrl?: string
+  monthlyRequestLimit?: MonthlyRequestLimit
 }
 
 // 添加凭据响应
@@ -80,3 +342,22 @@ export interface AddCredentialResponse {
   credentialId: number
   email?: string
 }
+
+// 进行中的消息请求
+export interface ActiveRequestItem {
+  id: string
+  model: string
+  credentialId: number | null
+  startedAt: string
+  elapsedMs: number
+  lane: 'interactive' | 'longRunning'
+  stream: boolean
+  timeoutSecs: number | null
+  cancelled: boolean
+}
+
+// 进行中的消息请求列表响应
+export interface ActiveRequestsResponse {
+  total: number
+  requests: ActiveRequestItem[]
+}
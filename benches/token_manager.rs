@@ -0,0 +1,49 @@
+//! MultiTokenManager 基准测试
+//!
+//! 运行：`cargo bench --bench token_manager`
+//!
+//! 使用有效期充足的凭据，测量快速路径（无需刷新 Token）下连续 10,000 次
+//! `acquire_context` 的吞吐量，作为性能回归的基线。
+
+use std::time::Duration;
+
+use chrono::Utc;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use kiro_rs::kiro::model::credentials::KiroCredentials;
+use kiro_rs::kiro::token_manager::MultiTokenManager;
+use kiro_rs::model::config::Config;
+
+/// 每轮迭代调用 acquire_context 的次数
+const CALLS_PER_ITER: u64 = 10_000;
+
+fn bench_acquire_context(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let credentials = KiroCredentials {
+        access_token: Some("token".to_string()),
+        refresh_token: Some("a".repeat(150)),
+        expires_at: Some((Utc::now() + chrono::Duration::days(365)).to_rfc3339()),
+        ..Default::default()
+    };
+    let manager =
+        MultiTokenManager::new(Config::default(), vec![credentials], None, None, false).unwrap();
+
+    let mut group = c.benchmark_group("token_manager");
+    group.throughput(Throughput::Elements(CALLS_PER_ITER));
+    group.measurement_time(Duration::from_secs(10));
+    group.bench_function("acquire_context_valid_token", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                for _ in 0..CALLS_PER_ITER {
+                    manager.acquire_context(None).await.unwrap();
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_acquire_context);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+//! 构建脚本：注入 git commit 与构建时间（供 `/version` 端点与启动日志使用）
+//!
+//! 从源码包（无 `.git`）或未安装 git 的环境构建时，commit 记为 `unknown`；
+//! 也可通过环境变量 `KIRO_GIT_COMMIT` 显式指定。构建时间优先使用 `SOURCE_DATE_EPOCH`。
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+fn main() {
+    let commit = std::env::var("KIRO_GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.trim().is_empty())
+        .or_else(git_commit)
+        .unwrap_or_else(|| "unknown".to_string());
+    let build_time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64)
+        });
+
+    println!("cargo:rustc-env=KIRO_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=KIRO_BUILD_UNIX_TIME={}", build_time);
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=KIRO_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // 切换分支或提交后重新获取 commit
+    for path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -12,6 +12,9 @@ pub enum AdminServiceError {
     /// 凭据不存在
     NotFound { id: u64 },
 
+    /// 进行中的请求不存在（或已结束）
+    RequestNotFound { id: String },
+
     /// 上游服务调用失败（网络、API 错误等）
     UpstreamError(String),
 
@@ -20,6 +23,9 @@ pub enum AdminServiceError {
 
     /// 凭据无效（验证失败）
     InvalidCredential(String),
+
+    /// 请求参数无效
+    InvalidRequest(String),
 }
 
 impl fmt::Display for AdminServiceError {
@@ -28,9 +34,13 @@ impl fmt::Display for AdminServiceError {
             AdminServiceError::NotFound { id } => {
                 write!(f, "凭据不存在: {}", id)
             }
+            AdminServiceError::RequestNotFound { id } => {
+                write!(f, "请求不存在或已结束: {}", id)
+            }
             AdminServiceError::UpstreamError(msg) => write!(f, "上游服务错误: {}", msg),
             AdminServiceError::InternalError(msg) => write!(f, "内部错误: {}", msg),
             AdminServiceError::InvalidCredential(msg) => write!(f, "凭据无效: {}", msg),
+            AdminServiceError::InvalidRequest(msg) => write!(f, "请求无效: {}", msg),
         }
     }
 }
@@ -41,22 +51,27 @@ impl AdminServiceError {
     /// 获取对应的 HTTP 状态码
     pub fn status_code(&self) -> StatusCode {
         match self {
-            AdminServiceError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AdminServiceError::NotFound { .. } | AdminServiceError::RequestNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
             AdminServiceError::UpstreamError(_) => StatusCode::BAD_GATEWAY,
             AdminServiceError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AdminServiceError::InvalidCredential(_) => StatusCode::BAD_REQUEST,
+            AdminServiceError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
         }
     }
 
     /// 转换为 API 错误响应
     pub fn into_response(self) -> AdminErrorResponse {
         match &self {
-            AdminServiceError::NotFound { .. } => AdminErrorResponse::not_found(self.to_string()),
+            AdminServiceError::NotFound { .. } | AdminServiceError::RequestNotFound { .. } => {
+                AdminErrorResponse::not_found(self.to_string())
+            }
             AdminServiceError::UpstreamError(_) => AdminErrorResponse::api_error(self.to_string()),
             AdminServiceError::InternalError(_) => {
                 AdminErrorResponse::internal_error(self.to_string())
             }
-            AdminServiceError::InvalidCredential(_) => {
+            AdminServiceError::InvalidCredential(_) | AdminServiceError::InvalidRequest(_) => {
                 AdminErrorResponse::invalid_request(self.to_string())
             }
         }
@@ -1,24 +1,63 @@
 //! Admin API HTTP 处理器
 
 use axum::{
-    Json,
-    extract::{Path, State},
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, header},
     response::IntoResponse,
 };
+use chrono::Utc;
+
+use crate::kiro::balance_cache::BALANCE_CACHE_TTL_SECS;
+use crate::kiro::token_manager::StatsExport;
 
 use super::{
+    AdminApiVersion,
+    error::AdminServiceError,
+    idempotency::IDEMPOTENCY_KEY_HEADER,
     middleware::AdminState,
+    openapi::openapi_document,
     types::{
-        AddCredentialRequest, SetDisabledRequest, SetLoadBalancingModeRequest, SetPriorityRequest,
-        SuccessResponse,
+        AddCredentialRequest, CredentialsFormat, CredentialsQuery, ReorderCredentialsRequest,
+        RuntimeState, SetDisabledRequest, SetLoadBalancingModeRequest, SetPriorityRequest,
+        SuccessResponse, TestFiltersRequest, TestRedactionRequest,
     },
 };
 
 /// GET /api/admin/credentials
-/// 获取所有凭据状态
-pub async fn get_all_credentials(State(state): State<AdminState>) -> impl IntoResponse {
-    let response = state.service.get_all_credentials();
-    Json(response)
+/// 获取凭据状态（支持分页、搜索、过滤与排序，未携带参数时返回全部）
+///
+/// `format=csv` 时以 CSV 附件返回（不含任何 Token 字段）
+pub async fn get_all_credentials(
+    State(state): State<AdminState>,
+    Extension(version): Extension<AdminApiVersion>,
+    Query(query): Query<CredentialsQuery>,
+) -> impl IntoResponse {
+    let response = state.service.list_credentials(&query);
+    if query.format != Some(CredentialsFormat::Csv) {
+        return Json(response.for_version(version)).into_response();
+    }
+
+    match response.to_csv() {
+        Ok(body) => {
+            let filename = format!("credentials_{}.csv", Utc::now().format("%Y%m%d"));
+            (
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}\"", filename),
+                    ),
+                ],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            let error = AdminServiceError::InternalError(format!("导出 CSV 失败: {}", e));
+            (error.status_code(), Json(error.into_response())).into_response()
+        }
+    }
 }
 
 /// POST /api/admin/credentials/:id/disabled
@@ -54,6 +93,104 @@ pub async fn set_credential_priority(
     }
 }
 
+/// POST /api/admin/credentials/:id/promote
+/// 将凭据提升为最高优先级
+pub async fn promote_credential(
+    State(state): State<AdminState>,
+    Extension(version): Extension<AdminApiVersion>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.promote_credential(id) {
+        Ok(response) => Json(response.for_version(version)).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/credentials/:id/demote
+/// 将凭据降为最低优先级
+pub async fn demote_credential(
+    State(state): State<AdminState>,
+    Extension(version): Extension<AdminApiVersion>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.demote_credential(id) {
+        Ok(response) => Json(response.for_version(version)).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/credentials/reorder
+/// 按给定顺序重排凭据优先级
+pub async fn reorder_credentials(
+    State(state): State<AdminState>,
+    Extension(version): Extension<AdminApiVersion>,
+    Json(payload): Json<ReorderCredentialsRequest>,
+) -> impl IntoResponse {
+    match state.service.reorder_credentials(&payload.ids) {
+        Ok(response) => Json(response.for_version(version)).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/credentials/rebalance-priorities
+/// 将凭据优先级压缩为连续整数（保持相对顺序）
+pub async fn rebalance_priorities(State(state): State<AdminState>) -> impl IntoResponse {
+    match state.service.rebalance_priorities() {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/credentials/duplicates
+/// 按 refreshToken 哈希列出重复的凭据
+pub async fn get_credential_duplicates(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.find_duplicates())
+}
+
+/// GET /api/admin/stats/export
+/// 导出凭据统计数据（用于迁移到新实例）
+pub async fn export_stats(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.export_stats())
+}
+
+/// POST /api/admin/stats/import
+/// 导入凭据统计数据（按 refreshToken 哈希匹配凭据）
+pub async fn import_stats(
+    State(state): State<AdminState>,
+    Json(payload): Json<StatsExport>,
+) -> impl IntoResponse {
+    match state.service.import_stats(payload) {
+        Ok(matched) => Json(SuccessResponse::new(format!(
+            "已导入统计数据，匹配 {} 个凭据",
+            matched
+        )))
+        .into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/state/export
+/// 导出运行时状态（用于蓝绿部署时预热新实例）
+pub async fn export_state(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.export_state())
+}
+
+/// POST /api/admin/state/import
+/// 导入运行时状态（忽略本实例不存在的凭据 ID）
+pub async fn import_state(
+    State(state): State<AdminState>,
+    Json(payload): Json<RuntimeState>,
+) -> impl IntoResponse {
+    match state.service.import_state(payload) {
+        Ok(outcome) => Json(SuccessResponse::new(format!(
+            "已导入运行时状态，匹配 {} 个凭据，跳过 {} 个",
+            outcome.matched, outcome.skipped
+        )))
+        .into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
 /// POST /api/admin/credentials/:id/reset
 /// 重置失败计数并重新启用
 pub async fn reset_failure_count(
@@ -71,29 +208,89 @@ pub async fn reset_failure_count(
 }
 
 /// GET /api/admin/credentials/:id/balance
-/// 获取指定凭据的余额
+/// 获取指定凭据的余额（`X-Cache`、`Age` 与 `Cache-Control` 响应头标明缓存新鲜度）
 pub async fn get_credential_balance(
     State(state): State<AdminState>,
     Path(id): Path<u64>,
 ) -> impl IntoResponse {
     match state.service.get_balance(id).await {
+        Ok(balance) => {
+            // Age 与 max-age 按整秒向下取整
+            let age = balance.cache_age_secs as u64;
+            let max_age = (BALANCE_CACHE_TTL_SECS as u64).saturating_sub(age);
+            (
+                [
+                    (
+                        "x-cache",
+                        if balance.cache_hit { "HIT" } else { "MISS" }.to_string(),
+                    ),
+                    (header::AGE.as_str(), age.to_string()),
+                    (
+                        header::CACHE_CONTROL.as_str(),
+                        format!("max-age={}", max_age),
+                    ),
+                ],
+                Json(balance.data),
+            )
+                .into_response()
+        }
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/credentials/:id/refresh-history
+/// 获取凭据的 Token 刷新记录
+pub async fn get_refresh_history(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.get_refresh_history(id) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/credentials/:id/health
+/// 获取凭据健康评分及各项因子
+pub async fn get_credential_health(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.credential_health(id) {
         Ok(response) => Json(response).into_response(),
         Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
     }
 }
 
 /// POST /api/admin/credentials
-/// 添加新凭据
+/// 添加新凭据（支持 `Idempotency-Key` 请求头，重放时返回首次响应）
 pub async fn add_credential(
     State(state): State<AdminState>,
+    headers: HeaderMap,
     Json(payload): Json<AddCredentialRequest>,
 ) -> impl IntoResponse {
-    match state.service.add_credential(payload).await {
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|value| value.to_str().unwrap_or_default());
+    match state.service.add_credential(payload, idempotency_key).await {
         Ok(response) => Json(response).into_response(),
         Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
     }
 }
 
+/// GET /api/admin/credentials/:id
+/// 获取单个凭据详情（密钥只返回提示信息）
+pub async fn get_credential(
+    State(state): State<AdminState>,
+    Extension(version): Extension<AdminApiVersion>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.get_credential(id) {
+        Ok(response) => Json(response.for_version(version)).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
 /// DELETE /api/admin/credentials/:id
 /// 删除凭据
 pub async fn delete_credential(
@@ -124,3 +321,78 @@ pub async fn set_load_balancing_mode(
         Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
     }
 }
+
+/// POST /api/admin/filters/test
+/// 对样例文本试运行响应文本过滤器
+pub async fn test_filters(
+    State(state): State<AdminState>,
+    Json(payload): Json<TestFiltersRequest>,
+) -> impl IntoResponse {
+    match state.service.test_filters(payload) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/redaction/test
+/// 对样例文本试运行出站脱敏规则
+pub async fn test_redaction(
+    State(state): State<AdminState>,
+    Json(payload): Json<TestRedactionRequest>,
+) -> impl IntoResponse {
+    match state.service.test_redaction(payload) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/users
+/// 获取按用户统计的请求与 token 用量
+pub async fn get_user_usage(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.user_usage())
+}
+
+/// GET /api/admin/events
+/// 获取最近的管理事件
+pub async fn get_events(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.events())
+}
+
+/// GET /api/admin/diagnostics/connections
+/// 获取上游连接诊断信息
+pub async fn get_connection_diagnostics(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.connection_diagnostics())
+}
+
+/// POST /api/admin/diagnostics/connections/reset
+/// 丢弃并重建所有缓存的上游 Client
+pub async fn reset_connections(State(state): State<AdminState>) -> impl IntoResponse {
+    match state.service.reset_connections() {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/requests/active
+/// 列出进行中的消息请求
+pub async fn get_active_requests(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.active_requests())
+}
+
+/// POST /api/admin/requests/:id/cancel
+/// 取消进行中的消息请求
+pub async fn cancel_request(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.service.cancel_request(&id) {
+        Ok(()) => Json(SuccessResponse::new(format!("请求 {} 已取消", id))).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/openapi.json
+/// 获取 Admin API 与 Anthropic 兼容端点的 OpenAPI 文档
+pub async fn get_openapi() -> impl IntoResponse {
+    Json(openapi_document())
+}
@@ -0,0 +1,136 @@
+//! Admin 写操作的幂等键缓存
+//!
+//! 客户端在请求头 `Idempotency-Key` 中携带唯一键，服务端记录首次成功的响应；
+//! 有效期内携带同一键的重放直接返回该响应，不再重复执行。
+//! 只缓存成功响应，失败的请求可以使用同一键重试。
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// 幂等键请求头
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// 幂等键有效期（10 分钟）
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(600);
+
+/// 幂等键最大长度
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// 最多保留的幂等键数量（超出时丢弃最早的记录）
+const MAX_IDEMPOTENCY_KEYS: usize = 256;
+
+/// 幂等键查询结果
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyLookup<T> {
+    /// 未见过或已过期，需要正常执行
+    Miss,
+    /// 重放，返回首次响应
+    Replay(T),
+    /// 同一键已用于内容不同的请求
+    Conflict,
+}
+
+struct IdempotencyEntry<T> {
+    /// 请求内容指纹，用于识别键被复用于不同请求
+    fingerprint: String,
+    response: T,
+    stored_at: Instant,
+}
+
+/// 带 TTL 的幂等键 → 响应缓存
+pub struct IdempotencyCache<T> {
+    entries: Mutex<HashMap<String, IdempotencyEntry<T>>>,
+}
+
+impl<T> Default for IdempotencyCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> IdempotencyCache<T> {
+    /// 查询幂等键（顺带清理过期记录）
+    pub fn lookup(&self, key: &str, fingerprint: &str, now: Instant) -> IdempotencyLookup<T> {
+        let mut entries = self.entries.lock();
+        Self::prune_expired(&mut entries, now);
+        match entries.get(key) {
+            None => IdempotencyLookup::Miss,
+            Some(entry) if entry.fingerprint != fingerprint => IdempotencyLookup::Conflict,
+            Some(entry) => IdempotencyLookup::Replay(entry.response.clone()),
+        }
+    }
+
+    /// 记录成功响应
+    pub fn store(&self, key: String, fingerprint: String, response: T, now: Instant) {
+        let mut entries = self.entries.lock();
+        Self::prune_expired(&mut entries, now);
+        if entries.len() >= MAX_IDEMPOTENCY_KEYS && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            IdempotencyEntry {
+                fingerprint,
+                response,
+                stored_at: now,
+            },
+        );
+    }
+
+    fn prune_expired(entries: &mut HashMap<String, IdempotencyEntry<T>>, now: Instant) {
+        entries.retain(|_, entry| now.duration_since(entry.stored_at) < IDEMPOTENCY_KEY_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_cache_replay_conflict_and_expiry() {
+        let cache = IdempotencyCache::default();
+        let start = Instant::now();
+
+        assert_eq!(cache.lookup("k1", "fp", start), IdempotencyLookup::Miss);
+        cache.store("k1".to_string(), "fp".to_string(), 7u64, start);
+
+        let later = start + Duration::from_secs(60);
+        assert_eq!(
+            cache.lookup("k1", "fp", later),
+            IdempotencyLookup::Replay(7)
+        );
+        assert_eq!(
+            cache.lookup("k1", "other", later),
+            IdempotencyLookup::Conflict
+        );
+
+        let expired = start + IDEMPOTENCY_KEY_TTL;
+        assert_eq!(cache.lookup("k1", "fp", expired), IdempotencyLookup::Miss);
+    }
+
+    #[test]
+    fn test_idempotency_cache_evicts_oldest_when_full() {
+        let cache = IdempotencyCache::default();
+        let start = Instant::now();
+        for i in 0..MAX_IDEMPOTENCY_KEYS as u64 {
+            let now = start + Duration::from_millis(i);
+            cache.store(format!("k{}", i), "fp".to_string(), i, now);
+        }
+
+        let now = start + Duration::from_secs(1);
+        cache.store("new".to_string(), "fp".to_string(), 0, now);
+        assert_eq!(cache.lookup("k0", "fp", now), IdempotencyLookup::Miss);
+        assert_eq!(cache.lookup("k1", "fp", now), IdempotencyLookup::Replay(1));
+        assert_eq!(cache.lookup("new", "fp", now), IdempotencyLookup::Replay(0));
+    }
+}
@@ -5,11 +5,12 @@ use std::sync::Arc;
 use axum::{
     body::Body,
     extract::State,
-    http::{Request, StatusCode},
+    http::{Method, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Json, Response},
 };
 
+use super::negotiate_version;
 use super::service::AdminService;
 use super::types::AdminErrorResponse;
 use crate::common::auth;
@@ -48,3 +49,45 @@ pub async fn admin_auth_middleware(
         }
     }
 }
+
+/// Admin 写操作成功后等待凭据文件落盘再响应（落盘失败返回 500）
+pub async fn flush_credentials_middleware(
+    State(state): State<AdminState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let is_write = request.method() != Method::GET;
+    let response = next.run(request).await;
+    if !is_write || !response.status().is_success() {
+        return response;
+    }
+    match state.service.flush_credentials().await {
+        Ok(()) => response,
+        Err(e) => {
+            let error = AdminErrorResponse::internal_error(format!("凭据文件落盘失败: {}", e));
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+        }
+    }
+}
+
+/// 按 Accept 头协商 Admin API 版本，写入请求扩展供处理器选择响应结构
+pub async fn api_version_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let version = negotiate_version(request.headers());
+    request.extensions_mut().insert(version);
+    next.run(request).await
+}
+
+/// 从实例模式下拒绝 Admin 写操作（凭据文件由持有锁的主实例维护）
+pub async fn secondary_mode_middleware(
+    State(state): State<AdminState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    if request.method() != Method::GET && state.service.is_secondary() {
+        let error = AdminErrorResponse::conflict(
+            "当前实例为从实例（凭据文件已被其他实例锁定），不允许修改凭据",
+        );
+        return (StatusCode::CONFLICT, Json(error)).into_response();
+    }
+    next.run(request).await
+}
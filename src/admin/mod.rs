@@ -8,6 +8,11 @@
 //! - 修改凭据优先级
 //! - 重置失败计数
 //! - 查询凭据余额
+//! - 每日余额报告（`reportSchedule`）
+//!
+//! # 版本协商
+//! 响应结构通过 `Accept: application/vnd.kiro.admin.v{N}+json` 协商，未指定时为 v1。
+//! v1 保持现有字段不变，新增字段只在 v2 中返回（见 [`AdminApiVersion`]）。
 //!
 //! # 使用
 //! ```ignore
@@ -18,7 +23,10 @@
 
 mod error;
 mod handlers;
+mod idempotency;
 mod middleware;
+mod openapi;
+pub mod report;
 mod router;
 mod service;
 pub mod types;
@@ -26,3 +34,96 @@ pub mod types;
 pub use middleware::AdminState;
 pub use router::create_admin_router;
 pub use service::AdminService;
+
+use axum::http::{HeaderMap, header};
+
+/// Admin API 版本媒体类型前缀（完整格式为 `application/vnd.kiro.admin.v{N}+json`）
+const ADMIN_MEDIA_TYPE_PREFIX: &str = "application/vnd.kiro.admin.v";
+
+/// Admin API 响应结构版本
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AdminApiVersion {
+    /// 初始结构（未协商时的默认版本）
+    #[default]
+    V1,
+    /// 凭据状态附带 `subscriptionTitle` 等 v1 之后新增的字段
+    V2,
+}
+
+impl AdminApiVersion {
+    fn from_number(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+/// 按 Accept 头协商 Admin API 版本
+///
+/// 列出多个版本时取支持的最高版本；未携带或均不支持时为 v1
+pub fn negotiate_version(headers: &HeaderMap) -> AdminApiVersion {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media| {
+            let media = media.split(';').next()?.trim().to_ascii_lowercase();
+            let version = media
+                .strip_prefix(ADMIN_MEDIA_TYPE_PREFIX)?
+                .strip_suffix("+json")?;
+            AdminApiVersion::from_number(version.parse().ok()?)
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn negotiate(accept: &[&str]) -> AdminApiVersion {
+        let mut headers = HeaderMap::new();
+        for value in accept {
+            headers.append(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        negotiate_version(&headers)
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate(&[]), AdminApiVersion::V1);
+        assert_eq!(negotiate(&["application/json"]), AdminApiVersion::V1);
+        assert_eq!(
+            negotiate(&["application/vnd.kiro.admin.v1+json"]),
+            AdminApiVersion::V1
+        );
+        assert_eq!(
+            negotiate(&["application/vnd.kiro.admin.v2+json"]),
+            AdminApiVersion::V2
+        );
+        assert_eq!(
+            negotiate(&["application/json, Application/VND.Kiro.Admin.V2+JSON; q=0.9"]),
+            AdminApiVersion::V2
+        );
+        assert_eq!(
+            negotiate(&[
+                "application/vnd.kiro.admin.v1+json",
+                "application/vnd.kiro.admin.v2+json"
+            ]),
+            AdminApiVersion::V2
+        );
+        // 不支持的版本被忽略
+        assert_eq!(
+            negotiate(&["application/vnd.kiro.admin.v9+json"]),
+            AdminApiVersion::V1
+        );
+        assert_eq!(
+            negotiate(&["application/vnd.kiro.admin.vx+json"]),
+            AdminApiVersion::V1
+        );
+    }
+}
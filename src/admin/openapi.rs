@@ -0,0 +1,1548 @@
+//! Admin API 与 Anthropic 兼容端点的 OpenAPI 3 文档
+//!
+//! 文档手工维护，通过 `GET /api/admin/openapi.json` 提供。
+//! 测试会将文档与实际路由逐一比对，新增、删除路由或方法而未同步文档时测试失败。
+
+use serde_json::{Map, Value, json};
+
+use super::idempotency::IDEMPOTENCY_KEY_HEADER;
+
+/// Admin API 挂载前缀
+const ADMIN_PREFIX: &str = "/api/admin";
+
+/// 生成完整的 OpenAPI 文档
+pub fn openapi_document() -> Value {
+    let mut paths = Map::new();
+    for (path, item) in admin_paths() {
+        paths.insert(format!("{}{}", ADMIN_PREFIX, path), item);
+    }
+    for (path, item) in anthropic_paths() {
+        paths.insert(path.to_string(), item);
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "kiro-rs",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Anthropic 兼容 API 与 Admin API。\n\n\
+                Admin API 通过 `Accept: application/vnd.kiro.admin.v{N}+json` 协商响应结构，\
+                未指定时为 v1；v2 额外返回 `subscriptionTitle` 等字段。",
+        },
+        "tags": [
+            { "name": "messages", "description": "Anthropic 兼容端点（API Key 认证）" },
+            { "name": "credentials", "description": "凭据管理（Admin API Key 认证）" },
+            { "name": "admin", "description": "配置、统计、诊断与状态迁移（Admin API Key 认证）" },
+        ],
+        "paths": paths,
+        "components": {
+            "securitySchemes": security_schemes(),
+            "parameters": {
+                "CredentialId": {
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "description": "凭据 ID",
+                    "schema": { "type": "integer", "format": "int64", "minimum": 1 },
+                },
+            },
+            "responses": error_responses(),
+            "schemas": schemas(),
+        },
+    })
+}
+
+/// 认证方式
+///
+/// Anthropic 兼容端点使用 `apiKey`，Admin API 使用 `adminApiKey`，两者均可放在
+/// `x-api-key` 或 `Authorization: Bearer` 请求头中；dry-run 额外接受 `x-admin-key`
+fn security_schemes() -> Value {
+    json!({
+        "ApiKey": {
+            "type": "apiKey",
+            "in": "header",
+            "name": "x-api-key",
+            "description": "config.json 中的 apiKey",
+        },
+        "ApiBearer": {
+            "type": "http",
+            "scheme": "bearer",
+            "description": "config.json 中的 apiKey",
+        },
+        "AdminApiKey": {
+            "type": "apiKey",
+            "in": "header",
+            "name": "x-api-key",
+            "description": "config.json 中的 adminApiKey",
+        },
+        "AdminBearer": {
+            "type": "http",
+            "scheme": "bearer",
+            "description": "config.json 中的 adminApiKey",
+        },
+        "DryRunAdminKey": {
+            "type": "apiKey",
+            "in": "header",
+            "name": "x-admin-key",
+            "description": "config.json 中的 adminApiKey（仅 dry-run 端点）",
+        },
+    })
+}
+
+fn api_security() -> Value {
+    json!([{ "ApiKey": [] }, { "ApiBearer": [] }])
+}
+
+fn admin_security() -> Value {
+    json!([{ "AdminApiKey": [] }, { "AdminBearer": [] }])
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn response_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/responses/{}", name) })
+}
+
+fn json_body(name: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema_ref(name) } },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// 构建 Admin 操作：`errors` 为除 401 外可能返回的错误状态码
+fn admin_op(tag: &str, summary: &str, body: Option<&str>, success: Value, errors: &[u16]) -> Value {
+    let mut responses = Map::new();
+    responses.insert("200".to_string(), success);
+    responses.insert("401".to_string(), response_ref("AdminUnauthorized"));
+    for code in errors {
+        let name = match code {
+            400 => "AdminBadRequest",
+            404 => "AdminNotFound",
+            409 => "AdminSecondaryMode",
+            500 => "AdminInternalError",
+            502 => "AdminBadGateway",
+            _ => unreachable!("未定义的 Admin 错误响应: {}", code),
+        };
+        responses.insert(code.to_string(), response_ref(name));
+    }
+
+    let mut operation = json!({
+        "tags": [tag],
+        "summary": summary,
+        "security": admin_security(),
+        "responses": responses,
+    });
+    if let Some(name) = body {
+        operation["requestBody"] = json_body(name);
+    }
+    operation
+}
+
+/// 带 `{id}` 路径参数的路径项
+fn with_id(mut item: Value) -> Value {
+    item["parameters"] = json!([{ "$ref": "#/components/parameters/CredentialId" }]);
+    item
+}
+
+/// Admin API 路径（相对 `/api/admin`）
+fn admin_paths() -> Vec<(&'static str, Value)> {
+    let success = || json_response("操作成功", schema_ref("SuccessResponse"));
+    let status_list = || json_response("调整后的凭据列表", schema_ref("CredentialsStatusResponse"));
+    // 修改凭据的请求：从实例返回 409，落盘失败返回 500
+    let write = [409, 500];
+
+    let mut list_credentials = admin_op(
+        "credentials",
+        "获取凭据状态列表",
+        None,
+        status_list(),
+        &[400, 500],
+    );
+    list_credentials["description"] = json!("未携带任何查询参数时返回完整列表且不含 `pagination`");
+    list_credentials["parameters"] = json!([
+        { "name": "page", "in": "query", "schema": { "type": "integer", "minimum": 1 }, "description": "页码（从 1 开始）" },
+        { "name": "pageSize", "in": "query", "schema": { "type": "integer", "minimum": 1, "maximum": 100 }, "description": "每页数量（默认 20）" },
+        { "name": "search", "in": "query", "schema": { "type": "string" }, "description": "按邮箱子串搜索（忽略大小写）" },
+        { "name": "disabled", "in": "query", "schema": { "type": "boolean" } },
+        { "name": "authMethod", "in": "query", "schema": { "type": "string" } },
+        { "name": "sort", "in": "query", "schema": { "type": "string", "enum": ["priority", "lastUsedAt", "successCount", "remaining"] } },
+        { "name": "order", "in": "query", "schema": { "type": "string", "enum": ["asc", "desc"] } },
+        { "name": "format", "in": "query", "schema": { "type": "string", "enum": ["json", "csv"] }, "description": "响应格式；`csv` 以附件返回（列：id, priority, disabled, auth_method, email, has_profile_arn, expires_at, success_count, failure_count, last_used_at），不含 Token 字段，忽略分页参数，以 `=`/`+`/`-`/`@` 开头的单元格加 `'` 前缀，不支持从 CSV 导入" },
+    ]);
+    list_credentials["responses"]["200"]["content"]["text/csv"] = json!({
+        "schema": { "type": "string" },
+    });
+
+    let mut cancel_request = admin_op("admin", "取消进行中的消息请求", None, success(), &[404]);
+    cancel_request["description"] = json!(
+        "丢弃该请求的上游调用：尚未开始响应时客户端收到 500 `api_error`，\
+         流式响应以 SSE `error` 事件结束"
+    );
+    cancel_request["parameters"] = json!([{
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "请求 ID（即响应头 `request-id`）",
+        "schema": { "type": "string" },
+    }]);
+
+    let mut add_credential = admin_op(
+        "credentials",
+        "添加凭据",
+        Some("AddCredentialRequest"),
+        json_response("添加成功", schema_ref("AddCredentialResponse")),
+        &[400, 409, 500, 502],
+    );
+    add_credential["description"] = json!(
+        "携带 `Idempotency-Key` 时，10 分钟内使用同一键的重放直接返回首次响应；\
+         同一键用于内容不同的请求返回 400"
+    );
+    add_credential["parameters"] = json!([{
+        "name": IDEMPOTENCY_KEY_HEADER,
+        "in": "header",
+        "schema": { "type": "string", "maxLength": 255 },
+        "description": "幂等键",
+    }]);
+
+    let mut balance = admin_op(
+        "credentials",
+        "获取凭据余额",
+        None,
+        json_response("余额信息", schema_ref("BalanceResponse")),
+        &[404, 502],
+    );
+    balance["responses"]["200"]["headers"] = json!({
+        "X-Cache": {
+            "description": "是否命中余额缓存",
+            "schema": { "type": "string", "enum": ["HIT", "MISS"] },
+        },
+        "Age": {
+            "description": "距最近一次真实拉取的秒数",
+            "schema": { "type": "integer" },
+        },
+        "Cache-Control": {
+            "description": "缓存剩余有效期（`max-age=N`）",
+            "schema": { "type": "string" },
+        },
+    });
+
+    vec![
+        (
+            "/credentials",
+            json!({ "get": list_credentials, "post": add_credential }),
+        ),
+        (
+            "/credentials/reorder",
+            json!({ "post": admin_op(
+                "credentials",
+                "按给定顺序重排凭据优先级",
+                Some("ReorderCredentialsRequest"),
+                status_list(),
+                &[400, 409, 500],
+            ) }),
+        ),
+        (
+            "/credentials/rebalance-priorities",
+            json!({ "post": admin_op(
+                "credentials",
+                "将优先级压缩为连续整数（保持相对顺序）",
+                None,
+                json_response("优先级调整结果", schema_ref("RebalancePrioritiesResponse")),
+                &write,
+            ) }),
+        ),
+        (
+            "/credentials/duplicates",
+            json!({ "get": admin_op(
+                "credentials",
+                "按 refreshToken 哈希列出重复的凭据",
+                None,
+                json_response("重复凭据分组", schema_ref("CredentialDuplicatesResponse")),
+                &[],
+            ) }),
+        ),
+        (
+            "/credentials/{id}",
+            with_id(json!({
+                "get": admin_op(
+                    "credentials",
+                    "获取单个凭据详情（密钥只返回提示信息）",
+                    None,
+                    json_response("凭据详情", schema_ref("CredentialDetailResponse")),
+                    &[404],
+                ),
+                "delete": admin_op(
+                    "credentials",
+                    "删除凭据（仅允许删除已禁用的凭据）",
+                    None,
+                    success(),
+                    &[400, 404, 409, 500],
+                ),
+            })),
+        ),
+        (
+            "/credentials/{id}/disabled",
+            with_id(json!({ "post": admin_op(
+                "credentials",
+                "设置凭据禁用状态",
+                Some("SetDisabledRequest"),
+                success(),
+                &[404, 409, 500],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/priority",
+            with_id(json!({ "post": admin_op(
+                "credentials",
+                "设置凭据优先级",
+                Some("SetPriorityRequest"),
+                success(),
+                &[404, 409, 500],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/promote",
+            with_id(json!({ "post": admin_op(
+                "credentials",
+                "将凭据提升为最高优先级",
+                None,
+                status_list(),
+                &[404, 409, 500],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/demote",
+            with_id(json!({ "post": admin_op(
+                "credentials",
+                "将凭据降为最低优先级",
+                None,
+                status_list(),
+                &[404, 409, 500],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/reset",
+            with_id(json!({ "post": admin_op(
+                "credentials",
+                "重置失败计数并重新启用",
+                None,
+                success(),
+                &[404, 409, 500],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/balance",
+            with_id(json!({ "get": balance })),
+        ),
+        (
+            "/credentials/{id}/refresh-history",
+            with_id(json!({ "get": admin_op(
+                "credentials",
+                "获取凭据的 Token 刷新记录",
+                None,
+                json_response(
+                    "刷新记录（按时间升序）",
+                    json!({ "type": "array", "items": schema_ref("RefreshAttempt") }),
+                ),
+                &[404],
+            ) })),
+        ),
+        (
+            "/credentials/{id}/health",
+            with_id(json!({ "get": admin_op(
+                "credentials",
+                "获取凭据健康评分及各项因子",
+                None,
+                json_response("健康评分", schema_ref("CredentialHealthResponse")),
+                &[404],
+            ) })),
+        ),
+        (
+            "/config/load-balancing",
+            json!({
+                "get": admin_op(
+                    "admin",
+                    "获取负载均衡模式",
+                    None,
+                    json_response("当前模式", schema_ref("LoadBalancingMode")),
+                    &[],
+                ),
+                "put": admin_op(
+                    "admin",
+                    "设置负载均衡模式",
+                    Some("LoadBalancingMode"),
+                    json_response("设置后的模式", schema_ref("LoadBalancingMode")),
+                    &[400, 409, 500],
+                ),
+            }),
+        ),
+        (
+            "/stats/export",
+            json!({ "get": admin_op(
+                "admin",
+                "导出凭据统计数据",
+                None,
+                json_response("统计数据", schema_ref("StatsExport")),
+                &[],
+            ) }),
+        ),
+        (
+            "/stats/import",
+            json!({ "post": admin_op(
+                "admin",
+                "导入凭据统计数据（按 refreshToken 哈希匹配凭据）",
+                Some("StatsExport"),
+                success(),
+                &[400, 409, 500],
+            ) }),
+        ),
+        (
+            "/state/export",
+            json!({ "get": admin_op(
+                "admin",
+                "导出运行时状态",
+                None,
+                json_response("运行时状态", schema_ref("RuntimeState")),
+                &[],
+            ) }),
+        ),
+        (
+            "/state/import",
+            json!({ "post": admin_op(
+                "admin",
+                "导入运行时状态（用于蓝绿部署时预热新实例）",
+                Some("RuntimeState"),
+                success(),
+                &[400],
+            ) }),
+        ),
+        (
+            "/users",
+            json!({ "get": admin_op(
+                "admin",
+                "获取按用户统计的请求与 token 用量",
+                None,
+                json_response("用户用量", schema_ref("UserUsageListResponse")),
+                &[],
+            ) }),
+        ),
+        (
+            "/events",
+            json!({ "get": admin_op(
+                "admin",
+                "获取最近的管理事件（如本地月度请求上限触发与恢复）",
+                None,
+                json_response("管理事件", schema_ref("AdminEventListResponse")),
+                &[],
+            ) }),
+        ),
+        (
+            "/requests/active",
+            json!({ "get": admin_op(
+                "admin",
+                "列出进行中的消息请求",
+                None,
+                json_response("进行中的请求", schema_ref("ActiveRequestsResponse")),
+                &[],
+            ) }),
+        ),
+        ("/requests/{id}/cancel", json!({ "post": cancel_request })),
+        (
+            "/filters/test",
+            json!({ "post": admin_op(
+                "admin",
+                "对样例文本试运行响应文本过滤器",
+                Some("TestFiltersRequest"),
+                json_response("过滤结果", schema_ref("TestFiltersResponse")),
+                &[400],
+            ) }),
+        ),
+        (
+            "/redaction/test",
+            json!({ "post": admin_op(
+                "admin",
+                "对样例文本试运行出站脱敏规则",
+                Some("TestRedactionRequest"),
+                json_response("脱敏结果", schema_ref("TestRedactionResponse")),
+                &[400],
+            ) }),
+        ),
+        (
+            "/diagnostics/connections",
+            json!({ "get": admin_op(
+                "admin",
+                "获取上游连接诊断信息",
+                None,
+                json_response("缓存的 Client 列表", schema_ref("ConnectionDiagnosticsResponse")),
+                &[],
+            ) }),
+        ),
+        (
+            "/diagnostics/connections/reset",
+            json!({ "post": admin_op(
+                "admin",
+                "丢弃并重建所有缓存的上游 Client",
+                None,
+                json_response("重建后的 Client 列表", schema_ref("ConnectionDiagnosticsResponse")),
+                &[500],
+            ) }),
+        ),
+        (
+            "/openapi.json",
+            json!({ "get": admin_op(
+                "admin",
+                "获取本 OpenAPI 文档",
+                None,
+                json_response("OpenAPI 3 文档", json!({ "type": "object" })),
+                &[],
+            ) }),
+        ),
+    ]
+}
+
+/// 构建 Anthropic 兼容操作
+fn messages_op(summary: &str, body: &str, success: Value, errors: &[&str]) -> Value {
+    let mut responses = Map::new();
+    responses.insert("200".to_string(), success);
+    for name in errors {
+        let code = match *name {
+            "ApiBadRequest" => "400",
+            "ApiUnauthorized" => "401",
+            "ApiForbidden" => "403",
+            "ApiRateLimited" => "429",
+            "ApiUpstreamError" => "502",
+            "ApiUnavailable" => "503",
+            _ => unreachable!("未定义的错误响应: {}", name),
+        };
+        responses.insert(code.to_string(), response_ref(name));
+    }
+    json!({
+        "tags": ["messages"],
+        "summary": summary,
+        "security": api_security(),
+        "requestBody": json_body(body),
+        "responses": responses,
+    })
+}
+
+/// Anthropic 兼容端点路径
+fn anthropic_paths() -> Vec<(&'static str, Value)> {
+    let messages = |summary: &str| {
+        let mut operation = messages_op(
+            summary,
+            "MessagesRequest",
+            json!({
+                "description": "非流式请求返回 JSON，`stream: true` 时返回 SSE 事件流",
+                "content": {
+                    "application/json": { "schema": schema_ref("MessagesResponse") },
+                    "text/event-stream": { "schema": { "type": "string" } },
+                },
+            }),
+            &[
+                "ApiBadRequest",
+                "ApiUnauthorized",
+                "ApiRateLimited",
+                "ApiUpstreamError",
+                "ApiUnavailable",
+            ],
+        );
+        operation["parameters"] = json!([{
+            "name": "x-kiro-timeout-secs",
+            "in": "header",
+            "schema": { "type": "integer", "minimum": 1 },
+            "description": "本次请求的上游超时（秒），覆盖默认的 720 秒；超过 `maxRequestTimeoutSecs` 时按上限处理",
+        }]);
+        operation["responses"]["500"] = response_ref("ApiCancelled");
+        operation["responses"]["200"]["headers"] = json!({
+            "x-kiro-unsupported-params": {
+                "description": "`unsupportedParamsPolicy` 为 warn 时列出被忽略的采样参数",
+                "schema": { "type": "string" },
+            },
+            "x-kiro-redactions": {
+                "description": "配置了 `redaction` 且有规则命中时列出各规则的命中次数（如 `aws-account=2, email=1`）",
+                "schema": { "type": "string" },
+            },
+            "Location": {
+                "description": "开启 `enableResponseRetrieval` 时非流式响应的暂存地址（`/v1/messages/{id}`）",
+                "schema": { "type": "string" },
+            },
+        });
+        operation
+    };
+    let count_tokens = || {
+        messages_op(
+            "计算 token 数量",
+            "CountTokensRequest",
+            json_response("输入 token 数", schema_ref("CountTokensResponse")),
+            &["ApiBadRequest", "ApiUnauthorized"],
+        )
+    };
+
+    let mut dry_run = messages_op(
+        "预览转换后的上游请求（不发起网络调用）",
+        "MessagesRequest",
+        json_response("将发往上游的请求", schema_ref("DryRunResponse")),
+        &[
+            "ApiBadRequest",
+            "ApiUnauthorized",
+            "ApiForbidden",
+            "ApiUnavailable",
+        ],
+    );
+    dry_run["description"] = json!("默认需要 `x-admin-key`，开启 `dryRunEnabled` 后也接受 API Key");
+    dry_run["security"] = json!([{ "DryRunAdminKey": [] }, { "ApiKey": [] }, { "ApiBearer": [] }]);
+
+    vec![
+        (
+            "/v1/models",
+            json!({ "get": {
+                "tags": ["messages"],
+                "summary": "获取可用模型列表",
+                "security": api_security(),
+                "responses": {
+                    "200": json_response("模型列表", schema_ref("ModelsResponse")),
+                    "401": response_ref("ApiUnauthorized"),
+                },
+            } }),
+        ),
+        ("/v1/messages", json!({ "post": messages("创建消息") })),
+        (
+            "/v1/messages/count_tokens",
+            json!({ "post": count_tokens() }),
+        ),
+        ("/v1/messages/dry-run", json!({ "post": dry_run })),
+        (
+            "/v1/messages/{id}",
+            json!({ "get": {
+                "tags": ["messages"],
+                "summary": "获取暂存的非流式响应",
+                "description": "需开启 `enableResponseRetrieval`；非流式响应的 `Location` 头指向该地址，保留 `responseRetentionSecs` 秒（受 `responseStoreMaxEntries` / `responseStoreMaxBytes` 限制，超出时淘汰最早的响应），只有创建该响应的 API Key 能取回",
+                "security": api_security(),
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "description": "响应中的消息 ID",
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": json_response("原始响应", schema_ref("MessagesResponse")),
+                    "401": response_ref("ApiUnauthorized"),
+                    "404": response_ref("ApiNotFound"),
+                },
+            } }),
+        ),
+        (
+            "/cc/v1/messages",
+            json!({ "post": messages(
+                "创建消息（Claude Code 兼容：流式响应等待 contextUsageEvent 后再发送 message_start）",
+            ) }),
+        ),
+        (
+            "/cc/v1/messages/count_tokens",
+            json!({ "post": count_tokens() }),
+        ),
+        (
+            "/metrics",
+            json!({ "get": {
+                "tags": ["messages"],
+                "summary": "Prometheus 指标",
+                "security": api_security(),
+                "responses": {
+                    "200": {
+                        "description": "Prometheus 文本格式",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "401": response_ref("ApiUnauthorized"),
+                },
+            } }),
+        ),
+    ]
+}
+
+/// 可复用的错误响应
+fn error_responses() -> Value {
+    let admin = |description: &str, error_type: &str, message: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": {
+                "schema": schema_ref("AdminErrorResponse"),
+                "example": { "error": { "type": error_type, "message": message } },
+            } },
+        })
+    };
+    let api = |description: &str, error_type: &str, message: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": {
+                "schema": schema_ref("ErrorResponse"),
+                "example": { "error": { "type": error_type, "message": message } },
+            } },
+        })
+    };
+
+    json!({
+        "AdminBadRequest": admin("请求参数无效", "invalid_request", "请求无效: priority 超出范围"),
+        "AdminUnauthorized": admin("Admin API Key 缺失或错误", "authentication_error", "Invalid or missing admin API key"),
+        "AdminNotFound": admin("凭据不存在", "not_found", "凭据不存在: 7"),
+        "AdminSecondaryMode": admin("凭据文件被其他实例锁定（从实例模式）", "conflict", "当前实例为从实例（凭据文件已被其他实例锁定），不允许修改凭据"),
+        "AdminInternalError": admin("内部错误（如凭据文件落盘失败）", "internal_error", "内部错误: 写入凭据文件失败"),
+        "AdminBadGateway": admin("上游调用失败", "api_error", "上游服务错误: 刷新 Token 失败"),
+        "ApiBadRequest": api("请求无效", "invalid_request_error", "temperature must be between 0 and 2"),
+        "ApiUnauthorized": api("API Key 缺失或错误", "authentication_error", "Invalid API key"),
+        "ApiForbidden": api("dry-run 需要 Admin Key", "permission_error", "Dry-run requires a valid X-Admin-Key header."),
+        "ApiRateLimited": api("超出速率限制", "rate_limit_error", "Rate limit exceeded"),
+        "ApiNotFound": api("暂存响应不存在或已过期", "not_found_error", "message msg_01 not found or expired"),
+        "ApiUpstreamError": api("上游调用失败", "api_error", "上游 API 调用失败"),
+        "ApiCancelled": api("请求被管理员取消（流式响应已开始时改为 SSE error 事件）", "api_error", "Request was cancelled by an administrator"),
+        "ApiUnavailable": api("未配置上游或无可用凭据", "service_unavailable", "Kiro API provider not configured"),
+    })
+}
+
+/// 请求与响应结构
+fn schemas() -> Value {
+    let mut schemas = Map::new();
+    for part in [
+        admin_schemas(),
+        limit_schemas(),
+        redaction_schemas(),
+        active_request_schemas(),
+        messages_schemas(),
+    ] {
+        if let Value::Object(part) = part {
+            schemas.extend(part);
+        }
+    }
+    Value::Object(schemas)
+}
+
+/// Admin API 的请求与响应结构
+fn admin_schemas() -> Value {
+    json!({
+        "AdminErrorResponse": {
+            "type": "object",
+            "required": ["error"],
+            "properties": { "error": schema_ref("ErrorDetail") },
+        },
+        "ErrorResponse": {
+            "type": "object",
+            "required": ["error"],
+            "properties": { "error": schema_ref("ErrorDetail") },
+        },
+        "ErrorDetail": {
+            "type": "object",
+            "required": ["type", "message"],
+            "properties": {
+                "type": { "type": "string" },
+                "message": { "type": "string" },
+            },
+        },
+        "SuccessResponse": {
+            "type": "object",
+            "required": ["success", "message"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+            },
+            "example": { "success": true, "message": "凭据 #1 已禁用" },
+        },
+        "FailureCounts": {
+            "type": "object",
+            "description": "按类型分类的累计失败次数",
+            "properties": {
+                "network": { "type": "integer" },
+                "upstreamServer": { "type": "integer" },
+                "upstreamAuth": { "type": "integer" },
+                "upstreamThrottle": { "type": "integer" },
+                "client": { "type": "integer" },
+            },
+        },
+        "CredentialStatusItem": credential_status_item_schema(),
+        "CredentialsStatusResponse": {
+            "type": "object",
+            "required": ["total", "available", "currentId", "fleetHealthScore", "credentials"],
+            "properties": {
+                "total": { "type": "integer" },
+                "available": { "type": "integer" },
+                "currentId": { "type": "integer", "format": "int64" },
+                "fleetHealthScore": { "type": "number" },
+                "totalEstimatedCost": { "type": "number" },
+                "persistWarning": { "type": "string", "description": "凭据文件外部修改无法自动合并时的警告" },
+                "autoRecoverAllDisabled": { "type": "string", "enum": ["always", "with-backoff", "never"] },
+                "lastSelfHealAt": {
+                    "type": "string",
+                    "format": "date-time",
+                    "nullable": true,
+                    "description": "最近一次所有凭据被自动禁用后的自愈时间",
+                },
+                "credentials": { "type": "array", "items": schema_ref("CredentialStatusItem") },
+                "pagination": {
+                    "type": "object",
+                    "properties": {
+                        "filtered": { "type": "integer" },
+                        "page": { "type": "integer" },
+                        "pageSize": { "type": "integer" },
+                        "totalPages": { "type": "integer" },
+                    },
+                },
+            },
+        },
+        "CredentialDetailResponse": {
+            "allOf": [
+                schema_ref("CredentialStatusItem"),
+                {
+                    "type": "object",
+                    "properties": {
+                        "profileArn": { "type": "string", "nullable": true },
+                        "region": { "type": "string", "nullable": true },
+                        "authRegion": { "type": "string", "nullable": true },
+                        "apiRegion": { "type": "string", "nullable": true },
+                        "clientId": { "type": "string", "nullable": true },
+                        "machineId": { "type": "string", "nullable": true },
+                        "secrets": {
+                            "type": "object",
+                            "properties": {
+                                "refreshTokenHint": { "type": "string", "nullable": true },
+                                "refreshTokenLength": { "type": "integer", "nullable": true },
+                                "accessTokenLength": { "type": "integer", "nullable": true },
+                                "clientSecretHash": { "type": "string", "nullable": true },
+                                "hasProxyPassword": { "type": "boolean" },
+                            },
+                        },
+                    },
+                },
+            ],
+        },
+        "AddCredentialRequest": {
+            "type": "object",
+            "required": ["refreshToken"],
+            "properties": {
+                "refreshToken": { "type": "string" },
+                "authMethod": { "type": "string", "default": "social", "description": "social 或 idc（支持 iam、builder-id 等别名）" },
+                "clientId": { "type": "string", "description": "IdC 认证需要" },
+                "clientSecret": { "type": "string", "description": "IdC 认证需要" },
+                "priority": { "type": "integer", "default": 0 },
+                "region": { "type": "string" },
+                "authRegion": { "type": "string" },
+                "apiRegion": { "type": "string" },
+                "machineId": { "type": "string", "minLength": 64, "maxLength": 64 },
+                "email": { "type": "string" },
+                "proxyUrl": { "type": "string", "description": "特殊值 direct 表示不使用代理" },
+                "proxyUsername": { "type": "string" },
+                "proxyPassword": { "type": "string" },
+                "upstreamBaseUrl": { "type": "string", "description": "必须以 https:// 开头且不含查询参数" },
+                "monthlyRequestLimit": schema_ref("MonthlyRequestLimit"),
+            },
+            "example": { "refreshToken": "aorAAAAAG...", "authMethod": "social", "priority": 0 },
+        },
+        "AddCredentialResponse": {
+            "type": "object",
+            "required": ["success", "message", "credentialId"],
+            "properties": {
+                "success": { "type": "boolean" },
+                "message": { "type": "string" },
+                "credentialId": { "type": "integer", "format": "int64" },
+                "email": { "type": "string" },
+            },
+            "example": { "success": true, "message": "凭据添加成功，ID: 3", "credentialId": 3 },
+        },
+        "SetDisabledRequest": {
+            "type": "object",
+            "required": ["disabled"],
+            "properties": { "disabled": { "type": "boolean" } },
+            "example": { "disabled": true },
+        },
+        "SetPriorityRequest": {
+            "type": "object",
+            "required": ["priority"],
+            "properties": { "priority": { "type": "integer", "minimum": 0 } },
+            "example": { "priority": 1 },
+        },
+        "ReorderCredentialsRequest": {
+            "type": "object",
+            "required": ["ids"],
+            "properties": {
+                "ids": {
+                    "type": "array",
+                    "items": { "type": "integer", "format": "int64" },
+                    "description": "按期望顺序排列的全部凭据 ID（第一个优先级最高）",
+                },
+            },
+            "example": { "ids": [3, 1, 2] },
+        },
+        "RebalancePrioritiesResponse": {
+            "type": "object",
+            "required": ["reassignments"],
+            "properties": {
+                "reassignments": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "id": { "type": "integer", "format": "int64" },
+                            "old_priority": { "type": "integer" },
+                            "new_priority": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+        "BalanceResponse": {
+            "type": "object",
+            "required": ["id", "currentUsage", "usageLimit", "remaining", "usagePercentage"],
+            "properties": {
+                "id": { "type": "integer", "format": "int64" },
+                "subscriptionTitle": { "type": "string", "nullable": true },
+                "currentUsage": { "type": "number" },
+                "usageLimit": { "type": "number" },
+                "remaining": { "type": "number" },
+                "usagePercentage": { "type": "number" },
+                "nextResetAt": { "type": "number", "nullable": true, "description": "Unix 时间戳" },
+                "daysUntilReset": { "type": "number", "nullable": true },
+                "usageTrend": { "type": "number", "nullable": true },
+                "dailyBudgetRemaining": { "type": "number", "nullable": true },
+            },
+            "example": {
+                "id": 1,
+                "subscriptionTitle": "KIRO PRO",
+                "currentUsage": 120.0,
+                "usageLimit": 1000.0,
+                "remaining": 880.0,
+                "usagePercentage": 12.0,
+                "nextResetAt": 1767225600.0,
+                "daysUntilReset": 11.0,
+                "usageTrend": 6.0,
+                "dailyBudgetRemaining": 80.0,
+            },
+        },
+        "RefreshAttempt": {
+            "type": "object",
+            "required": ["attemptedAt", "succeeded", "durationMs"],
+            "properties": {
+                "attemptedAt": { "type": "string", "format": "date-time" },
+                "succeeded": { "type": "boolean" },
+                "durationMs": { "type": "integer" },
+                "error": { "type": "string", "nullable": true },
+                "rotated": { "type": "boolean", "description": "刷新响应返回了新的 refreshToken（为 false 时省略）" },
+            },
+        },
+        "CredentialHealthResponse": {
+            "type": "object",
+            "required": ["id", "healthScore", "factors"],
+            "properties": {
+                "id": { "type": "integer", "format": "int64" },
+                "healthScore": { "type": "number", "minimum": 0, "maximum": 1 },
+                "factors": {
+                    "type": "object",
+                    "properties": {
+                        "failureFactor": { "type": "number" },
+                        "freshnessFactor": { "type": "number" },
+                        "successRateFactor": { "type": "number" },
+                        "quotaFactor": { "type": "number" },
+                    },
+                },
+            },
+        },
+        "LoadBalancingMode": {
+            "type": "object",
+            "required": ["mode"],
+            "properties": {
+                "mode": { "type": "string", "enum": ["priority", "balanced", "reset-aware"] },
+            },
+            "example": { "mode": "balanced" },
+        },
+        "StatsExport": {
+            "type": "object",
+            "required": ["version", "entries"],
+            "properties": {
+                "version": { "type": "integer" },
+                "entries": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["id", "refreshTokenHash", "successCount"],
+                        "properties": {
+                            "id": { "type": "integer", "format": "int64" },
+                            "refreshTokenHash": { "type": "string" },
+                            "successCount": { "type": "integer" },
+                            "lastUsedAt": { "type": "string", "format": "date-time", "nullable": true },
+                        },
+                    },
+                },
+            },
+        },
+        "RuntimeState": {
+            "type": "object",
+            "required": ["version", "exportedAt", "tokenManager"],
+            "description": "可迁移的运行时状态（不含密钥），结构随版本演进，应原样回传给 /state/import",
+            "properties": {
+                "version": { "type": "integer" },
+                "exportedAt": { "type": "string", "format": "date-time" },
+                "tokenManager": { "type": "object" },
+                "rateLimiter": { "type": "object", "nullable": true },
+            },
+        },
+        "UserUsageListResponse": {
+            "type": "object",
+            "required": ["users"],
+            "properties": {
+                "users": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "userId": { "type": "string" },
+                            "requestCount": { "type": "integer" },
+                            "inputTokens": { "type": "integer" },
+                            "outputTokens": { "type": "integer" },
+                            "lastRequestAt": { "type": "string", "format": "date-time", "nullable": true },
+                            "dailyRequests": { "type": "integer" },
+                            "dailyLimit": { "type": "integer", "nullable": true },
+                        },
+                    },
+                },
+            },
+        },
+        "TestFiltersRequest": {
+            "type": "object",
+            "required": ["text"],
+            "properties": {
+                "text": { "type": "string" },
+                "filters": {
+                    "type": "array",
+                    "items": { "type": "object" },
+                    "description": "不提供时使用当前配置的 postProcessing.filters",
+                },
+                "chunkSize": { "type": "integer", "minimum": 1, "description": "按该字节数切分后逐段送入，模拟流式响应" },
+            },
+            "example": { "text": "Hello Kiro", "filters": [{ "pattern": "Kiro", "replacement": "Claude" }] },
+        },
+        "TestFiltersResponse": {
+            "type": "object",
+            "required": ["output", "changed"],
+            "properties": {
+                "output": { "type": "string" },
+                "changed": { "type": "boolean" },
+            },
+        },
+        "ConnectionDiagnosticsResponse": {
+            "type": "object",
+            "required": ["clients"],
+            "properties": {
+                "clients": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "proxyUrl": { "type": "string", "nullable": true },
+                            "instanceId": { "type": "integer" },
+                            "createdAt": { "type": "string", "format": "date-time" },
+                            "requestsServed": { "type": "integer" },
+                            "timeoutSecs": { "type": "integer" },
+                            "poolIdleTimeoutSecs": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// 凭据状态条目结构（字段较多，单独构建以免超出 `json!` 宏的递归上限）
+fn credential_status_item_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["id", "priority", "disabled", "failureCount", "isCurrent", "successCount", "healthScore"],
+        "properties": {
+            "id": { "type": "integer", "format": "int64" },
+            "priority": { "type": "integer", "description": "数字越小优先级越高" },
+            "disabled": { "type": "boolean" },
+            "disabledReason": schema_ref("DisabledReason"),
+            "failureCount": { "type": "integer", "description": "连续失败次数" },
+            "failureCounts": schema_ref("FailureCounts"),
+            "isCurrent": { "type": "boolean" },
+            "expiresAt": { "type": "string", "format": "date-time", "nullable": true },
+            "authMethod": { "type": "string", "nullable": true },
+            "hasProfileArn": { "type": "boolean" },
+            "refreshTokenHash": { "type": "string", "nullable": true },
+            "email": { "type": "string", "nullable": true },
+            "successCount": { "type": "integer" },
+            "lastUsedAt": { "type": "string", "format": "date-time", "nullable": true },
+            "lastLatencyMs": { "type": "integer", "nullable": true, "description": "最近一次成功调用的耗时（毫秒，自发出请求到收到响应头，不含 Token 刷新）" },
+            "hasProxy": { "type": "boolean" },
+            "proxyUrl": { "type": "string" },
+            "upstreamBaseUrl": { "type": "string" },
+            "refreshAttemptsLastHour": { "type": "integer" },
+            "refreshBackoffSecs": { "type": "integer", "nullable": true },
+            "nextRefreshAttemptAt": { "type": "string", "format": "date-time", "nullable": true, "description": "刷新失败退避结束时间，之前选择凭据时会跳过该凭据" },
+            "healthScore": { "type": "number", "minimum": 0, "maximum": 1 },
+            "estimatedCost": { "type": "number" },
+            "monthlyRequests": { "type": "integer", "description": "当月成功请求次数（本地计数）" },
+            "monthlyRequestLimit": schema_ref("MonthlyRequestLimit"),
+            "refreshRotations": { "type": "integer", "description": "保留的刷新记录中 refreshToken 轮换的次数" },
+            "rotationUnpersisted": { "type": "boolean", "description": "最近一次 refreshToken 轮换后未能回写凭据文件（新令牌仅在内存中，应急副本见管理事件）" },
+            "quotaExhaustedAtPercentage": { "type": "number", "nullable": true, "description": "因额度用尽被禁用时的使用比例（1.0 表示 100%）" },
+            "subscriptionTitle": { "type": "string", "nullable": true, "description": "仅 v2" },
+        },
+    })
+}
+
+/// 出站脱敏试运行相关结构
+fn redaction_schemas() -> Value {
+    json!({
+        "TestRedactionRequest": {
+            "type": "object",
+            "required": ["text"],
+            "properties": {
+                "text": { "type": "string" },
+                "rules": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["name", "pattern"],
+                        "properties": {
+                            "name": { "type": "string" },
+                            "pattern": { "type": "string" },
+                            "replacement": { "type": "string", "default": "[REDACTED]" },
+                            "blocking": { "type": "boolean", "default": false },
+                        },
+                    },
+                    "description": "不提供时使用当前配置的 redaction.rules",
+                },
+                "strict": { "type": "boolean", "description": "不提供时使用当前配置的 redaction.strict" },
+            },
+            "example": {
+                "text": "account 123456789012",
+                "rules": [{ "name": "aws-account", "pattern": "\\b\\d{12}\\b", "replacement": "[AWS_ACCOUNT]" }],
+            },
+        },
+        "TestRedactionResponse": {
+            "type": "object",
+            "required": ["output", "changed", "redactions", "blockedBy"],
+            "properties": {
+                "output": { "type": "string" },
+                "changed": { "type": "boolean" },
+                "redactions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["rule", "count"],
+                        "properties": {
+                            "rule": { "type": "string" },
+                            "count": { "type": "integer" },
+                        },
+                    },
+                },
+                "blockedBy": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "严格模式下命中的 blocking 规则（非空时实际请求会被拒绝）",
+                },
+            },
+        },
+    })
+}
+
+/// 进行中请求相关结构
+fn active_request_schemas() -> Value {
+    json!({
+        "ActiveRequestsResponse": {
+            "type": "object",
+            "required": ["total", "requests"],
+            "properties": {
+                "total": { "type": "integer" },
+                "requests": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["id", "model", "credentialId", "startedAt", "elapsedMs", "lane", "stream", "timeoutSecs", "cancelled"],
+                        "properties": {
+                            "id": { "type": "string", "description": "请求 ID（即响应头 `request-id`）" },
+                            "model": { "type": "string" },
+                            "credentialId": { "type": "integer", "format": "int64", "nullable": true, "description": "上游响应前未知（A/B 路由时为指定的凭据）" },
+                            "startedAt": { "type": "string", "format": "date-time" },
+                            "elapsedMs": { "type": "integer", "format": "int64" },
+                            "lane": { "type": "string", "enum": ["interactive", "longRunning"], "description": "`x-kiro-timeout-secs` 超过默认上游超时（720 秒）的请求为 longRunning" },
+                            "stream": { "type": "boolean" },
+                            "timeoutSecs": { "type": "integer", "nullable": true, "description": "单请求上游超时（未携带 `x-kiro-timeout-secs` 时为 null）" },
+                            "cancelled": { "type": "boolean", "description": "已被取消、等待处理器结束" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// 本地月度请求上限与管理事件相关结构
+fn limit_schemas() -> Value {
+    json!({
+        "DisabledReason": {
+            "type": "string",
+            "nullable": true,
+            "enum": ["manual", "tooManyFailures", "quotaExceeded", "localLimitReached"],
+            "description": "localLimitReached 表示当月请求次数达到本地硬上限，跨月后自动恢复",
+        },
+        "MonthlyRequestLimit": {
+            "type": "object",
+            "nullable": true,
+            "properties": {
+                "softLimit": { "type": "integer", "description": "达到后记录日志与管理事件" },
+                "hardLimit": { "type": "integer", "minimum": 1, "description": "达到后暂停使用至下月" },
+                "timezone": { "type": "string", "description": "划分自然月的 UTC 偏移，如 +08:00，默认 UTC" },
+            },
+        },
+        "CredentialDuplicatesResponse": {
+            "type": "object",
+            "required": ["duplicate_groups", "unchecked_ids"],
+            "properties": {
+                "duplicate_groups": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["refresh_token_hash", "credential_ids"],
+                        "properties": {
+                            "refresh_token_hash": { "type": "string" },
+                            "credential_ids": {
+                                "type": "array",
+                                "items": { "type": "integer", "format": "int64" },
+                            },
+                        },
+                    },
+                },
+                "unchecked_ids": {
+                    "type": "array",
+                    "items": { "type": "integer", "format": "int64" },
+                    "description": "没有 refreshToken、无法检测重复的凭据",
+                },
+            },
+        },
+        "AdminEventListResponse": {
+            "type": "object",
+            "required": ["events"],
+            "properties": {
+                "events": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["at", "credentialId", "kind", "message"],
+                        "properties": {
+                            "at": { "type": "string", "format": "date-time" },
+                            "credentialId": { "type": "integer", "format": "int64" },
+                            "kind": {
+                                "type": "string",
+                                "enum": ["softLimitReached", "hardLimitReached", "limitCleared", "rotationUnpersisted"],
+                            },
+                            "message": { "type": "string" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Anthropic 兼容端点的请求与响应结构
+fn messages_schemas() -> Value {
+    json!({
+        "Message": {
+            "type": "object",
+            "required": ["role", "content"],
+            "properties": {
+                "role": { "type": "string", "enum": ["user", "assistant"] },
+                "content": {
+                    "description": "字符串或内容块数组",
+                    "oneOf": [
+                        { "type": "string" },
+                        { "type": "array", "items": { "type": "object" } },
+                    ],
+                },
+            },
+        },
+        "System": {
+            "description": "字符串或 `{type: text, text}` 数组",
+            "oneOf": [
+                { "type": "string" },
+                {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["text"],
+                        "properties": { "text": { "type": "string" } },
+                    },
+                },
+            ],
+        },
+        "MessagesRequest": {
+            "type": "object",
+            "required": ["model", "max_tokens", "messages"],
+            "properties": {
+                "model": { "type": "string" },
+                "max_tokens": { "type": "integer" },
+                "messages": { "type": "array", "items": schema_ref("Message") },
+                "stream": { "type": "boolean", "default": false },
+                "system": schema_ref("System"),
+                "tools": { "type": "array", "items": { "type": "object" } },
+                "tool_choice": { "type": "object" },
+                "thinking": {
+                    "type": "object",
+                    "properties": {
+                        "type": { "type": "string", "enum": ["enabled", "adaptive", "disabled"] },
+                        "budget_tokens": { "type": "integer", "maximum": 24576 },
+                    },
+                },
+                "output_config": {
+                    "type": "object",
+                    "properties": { "effort": { "type": "string", "default": "high" } },
+                },
+                "metadata": {
+                    "type": "object",
+                    "properties": { "user_id": { "type": "string" } },
+                },
+                "response_format": {
+                    "type": "object",
+                    "description": "`{\"type\": \"json_object\"}` 时启用 JSON 模式",
+                    "properties": { "type": { "type": "string" } },
+                },
+                "temperature": { "type": "number", "minimum": 0, "maximum": 2 },
+                "top_p": { "type": "number", "minimum": 0, "maximum": 1 },
+                "top_k": { "type": "integer", "minimum": 0 },
+            },
+            "example": {
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 1024,
+                "messages": [{ "role": "user", "content": "Hello" }],
+            },
+        },
+        "MessagesResponse": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "type": { "type": "string", "enum": ["message"] },
+                "role": { "type": "string", "enum": ["assistant"] },
+                "model": { "type": "string" },
+                "content": { "type": "array", "items": { "type": "object" } },
+                "stop_reason": { "type": "string", "nullable": true },
+                "stop_sequence": { "type": "string", "nullable": true },
+                "usage": {
+                    "type": "object",
+                    "properties": {
+                        "input_tokens": { "type": "integer" },
+                        "output_tokens": { "type": "integer" },
+                    },
+                },
+            },
+        },
+        "CountTokensRequest": {
+            "type": "object",
+            "required": ["model", "messages"],
+            "properties": {
+                "model": { "type": "string" },
+                "messages": { "type": "array", "items": schema_ref("Message") },
+                "system": schema_ref("System"),
+                "tools": { "type": "array", "items": { "type": "object" } },
+            },
+        },
+        "CountTokensResponse": {
+            "type": "object",
+            "required": ["input_tokens"],
+            "properties": { "input_tokens": { "type": "integer" } },
+            "example": { "input_tokens": 42 },
+        },
+        "ModelsResponse": {
+            "type": "object",
+            "required": ["object", "data"],
+            "properties": {
+                "object": { "type": "string", "enum": ["list"] },
+                "data": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "id": { "type": "string" },
+                            "object": { "type": "string" },
+                            "created": { "type": "integer" },
+                            "owned_by": { "type": "string" },
+                            "display_name": { "type": "string" },
+                            "type": { "type": "string" },
+                            "max_tokens": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+        "DryRunResponse": {
+            "type": "object",
+            "properties": {
+                "model": { "type": "string" },
+                "modelId": { "type": "string", "description": "映射后的 Kiro 模型 ID" },
+                "credentialId": { "type": "integer", "format": "int64" },
+                "profileArn": { "type": "string", "nullable": true },
+                "machineId": { "type": "string" },
+                "url": { "type": "string" },
+                "headers": { "type": "object", "additionalProperties": { "type": "string" } },
+                "systemPrompt": { "type": "string" },
+                "payload": { "type": "object", "description": "Kiro 格式的请求体" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::admin::{AdminService, AdminState, create_admin_router};
+    use crate::anthropic::create_router_with_shared_rate_limiter;
+    use crate::kiro::provider::KiroProvider;
+    use crate::kiro::token_manager::MultiTokenManager;
+    use crate::model::config::Config;
+
+    const METHODS: [&str; 5] = ["get", "post", "put", "delete", "patch"];
+
+    /// 从路由源码中提取注册的路径
+    fn registered_paths(source: &str) -> Vec<String> {
+        let re = regex::Regex::new(r#"\.route\(\s*"([^"]+)""#).unwrap();
+        re.captures_iter(source).map(|c| c[1].to_string()).collect()
+    }
+
+    /// 收集文档中的全部 `$ref` 目标
+    fn collect_refs(value: &Value, refs: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    refs.insert(target.clone());
+                }
+                map.values().for_each(|v| collect_refs(v, refs));
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect_refs(v, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_openapi_document_is_valid() {
+        let document = openapi_document();
+        let parsed: openapiv3::OpenAPI = serde_json::from_value(document.clone()).unwrap();
+        assert!(parsed.openapi.starts_with("3.0"));
+
+        // 所有引用均可解析
+        let mut refs = BTreeSet::new();
+        collect_refs(&document, &mut refs);
+        assert!(!refs.is_empty());
+        for target in refs {
+            let pointer = target.strip_prefix('#').unwrap();
+            assert!(
+                document.pointer(pointer).is_some(),
+                "无法解析的引用: {}",
+                target
+            );
+        }
+
+        // 每个操作都声明了认证方式与成功响应
+        for (path, item) in parsed.paths.iter() {
+            let item = item.as_item().unwrap();
+            for (method, operation) in item.iter() {
+                assert!(
+                    operation.security.is_some(),
+                    "{} {} 缺少 security",
+                    method,
+                    path
+                );
+                assert!(
+                    operation
+                        .responses
+                        .responses
+                        .contains_key(&openapiv3::StatusCode::Code(200)),
+                    "{} {} 缺少 200 响应",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_openapi_covers_registered_routes() {
+        let document = openapi_document();
+        let paths = document["paths"].as_object().unwrap();
+
+        let admin_routes = registered_paths(include_str!("router.rs"));
+        assert!(admin_routes.len() > 10);
+        for route in admin_routes {
+            let path = format!("{}{}", ADMIN_PREFIX, route);
+            assert!(paths.contains_key(&path), "文档缺少 Admin 路由 {}", path);
+        }
+
+        // Anthropic 路由挂载在 /v1、/cc/v1 或根路径下
+        let anthropic_routes = registered_paths(include_str!("../anthropic/router.rs"));
+        assert!(!anthropic_routes.is_empty());
+        for route in anthropic_routes {
+            let documented = ["/v1", "/cc/v1", ""]
+                .iter()
+                .any(|prefix| paths.contains_key(&format!("{}{}", prefix, route)));
+            assert!(documented, "文档缺少 Anthropic 路由 {}", route);
+        }
+    }
+
+    /// 逐一探测文档中每个路径的全部方法：已注册的方法必须出现在文档中，反之亦然
+    #[tokio::test]
+    async fn test_openapi_methods_match_router() {
+        let mut config = Config::default();
+        config.admin_api_key = Some("key".to_string());
+        let manager = Arc::new(MultiTokenManager::new(config, vec![], None, None, false).unwrap());
+        let admin = create_admin_router(AdminState::new("key", AdminService::new(manager.clone())));
+        let app = create_router_with_shared_rate_limiter(
+            "key",
+            Some(KiroProvider::new(manager.clone())),
+            None,
+            None,
+            Arc::default(),
+        )
+        .nest(ADMIN_PREFIX, admin);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let document = openapi_document();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            // 凭据不存在时 Admin 端点返回带错误体的 404，与未注册路由的空 404 区分
+            let url = format!("http://{}{}", addr, path.replace("{id}", "1"));
+            for method in METHODS {
+                let response = client
+                    .request(method.to_uppercase().parse().unwrap(), &url)
+                    .header("x-api-key", "key")
+                    .header("x-admin-key", "key")
+                    .send()
+                    .await
+                    .unwrap();
+                let status = response.status().as_u16();
+                let body = response.bytes().await.unwrap();
+                let routed = status != 405 && !(status == 404 && body.is_empty());
+                assert_eq!(
+                    routed,
+                    item.get(method).is_some(),
+                    "{} {} 的路由与文档不一致（状态码 {}）",
+                    method,
+                    path,
+                    status
+                );
+            }
+        }
+    }
+}
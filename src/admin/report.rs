@@ -0,0 +1,543 @@
+//! 每日余额报告
+//!
+//! 配置 `reportSchedule` 后，主程序启动一个后台任务，每天在指定时间：
+//! 1. 通过 [`AdminService::get_balance`] 刷新所有启用凭据的余额
+//! 2. 与上一次报告对比，计算每个凭据的用量变化
+//! 3. 将报告写入缓存目录下的 `reports/balance-YYYY-MM-DD.json`
+//! 4. 配置了 `reportWebhookUrl` 时将报告 POST 到该地址（失败按指数退避重试）
+//!
+//! 调度时间支持 `HH:MM`（本地时间）或 cron 风格的 `分 时 * * *`。
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Local, TimeZone};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::service::AdminService;
+use super::types::BalanceResponse;
+use crate::kiro::balance_cache::BALANCE_CACHE_TTL_SECS;
+
+/// 报告文件名前缀
+const REPORT_FILE_PREFIX: &str = "balance-";
+
+/// Webhook 首次重试前的等待时间
+const WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Webhook 重试等待时间上限
+const WEBHOOK_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// 每日报告的生成时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportSchedule {
+    hour: u32,
+    minute: u32,
+}
+
+impl ReportSchedule {
+    /// 解析调度配置：`HH:MM` 或 `分 时 * * *`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let spec = spec.trim();
+        let (hour, minute) = if let Some((hour, minute)) = spec.split_once(':') {
+            (hour.trim(), minute.trim())
+        } else {
+            let fields: Vec<&str> = spec.split_whitespace().collect();
+            match fields.as_slice() {
+                [minute, hour, "*", "*", "*"] => (*hour, *minute),
+                _ => anyhow::bail!(
+                    "reportSchedule 格式无效（应为 HH:MM 或 \"分 时 * * *\"）: {}",
+                    spec
+                ),
+            }
+        };
+
+        let hour: u32 = hour
+            .parse()
+            .with_context(|| format!("reportSchedule 小时无效: {}", spec))?;
+        let minute: u32 = minute
+            .parse()
+            .with_context(|| format!("reportSchedule 分钟无效: {}", spec))?;
+        if hour > 23 || minute > 59 {
+            anyhow::bail!("reportSchedule 时间超出范围: {}", spec);
+        }
+        Ok(Self { hour, minute })
+    }
+
+    /// 严格晚于 `now` 的下一次执行时间（跳过夏令时导致不存在的时刻）
+    pub fn next_run_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> DateTime<Tz> {
+        let timezone = now.timezone();
+        let mut date = now.date_naive();
+        loop {
+            let naive = date
+                .and_hms_opt(self.hour, self.minute, 0)
+                .expect("小时与分钟已在解析时校验");
+            let run_at = timezone
+                .from_local_datetime(&naive)
+                .earliest()
+                .filter(|run_at| run_at > now);
+            if let Some(run_at) = run_at {
+                return run_at;
+            }
+            date = date.succ_opt().expect("日期溢出");
+        }
+    }
+}
+
+/// 每日余额报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceReport {
+    /// 报告日期（YYYY-MM-DD，本地时间）
+    pub date: String,
+    /// 生成时间（RFC3339 格式）
+    pub generated_at: String,
+    /// 用于计算变化量的上一次报告的生成时间
+    pub previous_generated_at: Option<String>,
+    /// 各凭据余额及变化量
+    pub credentials: Vec<BalanceReportEntry>,
+    /// 查询失败的凭据
+    pub failures: Vec<BalanceReportFailure>,
+}
+
+/// 单个凭据的余额及相对上一次报告的变化量
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceReportEntry {
+    #[serde(flatten)]
+    pub balance: BalanceResponse,
+    /// 期间新增的使用量（上一次报告中无该凭据时为 None）
+    pub usage_delta: Option<f64>,
+    /// 剩余额度的变化量（上一次报告中无该凭据时为 None）
+    pub remaining_delta: Option<f64>,
+}
+
+/// 余额查询失败的凭据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceReportFailure {
+    pub id: u64,
+    pub error: String,
+}
+
+/// 计算各凭据相对上一次报告的变化量
+///
+/// 使用量减少说明期间发生了额度重置，此时以当前使用量作为新增使用量
+fn compute_deltas(
+    balances: Vec<BalanceResponse>,
+    previous: Option<&BalanceReport>,
+) -> Vec<BalanceReportEntry> {
+    balances
+        .into_iter()
+        .map(|balance| {
+            let prev = previous
+                .and_then(|report| {
+                    report
+                        .credentials
+                        .iter()
+                        .find(|e| e.balance.id == balance.id)
+                })
+                .map(|e| &e.balance);
+            let usage_delta = prev.map(|prev| {
+                if balance.current_usage >= prev.current_usage {
+                    balance.current_usage - prev.current_usage
+                } else {
+                    balance.current_usage
+                }
+            });
+            let remaining_delta = prev.map(|prev| balance.remaining - prev.remaining);
+            BalanceReportEntry {
+                balance,
+                usage_delta,
+                remaining_delta,
+            }
+        })
+        .collect()
+}
+
+/// 报告文件名
+fn report_file_name(date: &str) -> String {
+    format!("{}{}.json", REPORT_FILE_PREFIX, date)
+}
+
+/// 读取指定日期之前最近的一份报告（同一天重复生成时不与自身比较）
+fn load_previous_report(dir: &Path, date: &str) -> Option<BalanceReport> {
+    let current = report_file_name(date);
+    let latest = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| {
+            name.starts_with(REPORT_FILE_PREFIX) && name.ends_with(".json") && *name < current
+        })
+        .max()?;
+
+    let path = dir.join(&latest);
+    match std::fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(serde_json::from_str(&content)?))
+    {
+        Ok(report) => Some(report),
+        Err(e) => {
+            tracing::warn!("读取上一次余额报告失败 {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// 单次 Webhook 推送失败的类型
+#[derive(Debug)]
+enum SendError {
+    /// 网络错误、429 或 5xx，可重试
+    Retryable(anyhow::Error),
+    /// 其他错误（如 4xx），重试无意义
+    Fatal(anyhow::Error),
+}
+
+/// 执行推送，可重试的失败按指数退避最多重试 `max_retries` 次
+async fn send_with_retries<F, Fut>(
+    max_retries: u32,
+    initial_backoff: Duration,
+    mut send: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), SendError>>,
+{
+    let mut backoff = initial_backoff;
+    let mut attempt = 0;
+    loop {
+        match send().await {
+            Ok(()) => return Ok(()),
+            Err(SendError::Fatal(e)) => return Err(e),
+            Err(SendError::Retryable(e)) if attempt >= max_retries => {
+                return Err(e.context(format!("已重试 {} 次", max_retries)));
+            }
+            Err(SendError::Retryable(e)) => {
+                attempt += 1;
+                tracing::warn!(
+                    "余额报告推送失败，{:?} 后进行第 {} 次重试: {}",
+                    backoff,
+                    attempt,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(WEBHOOK_MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// 余额报告 Webhook
+pub struct ReportWebhook {
+    client: Client,
+    url: String,
+    max_retries: u32,
+}
+
+impl ReportWebhook {
+    /// `client` 应通过 [`crate::http_client::build_client`] 构建以使用全局代理
+    pub fn new(client: Client, url: impl Into<String>, max_retries: u32) -> Self {
+        Self {
+            client,
+            url: url.into(),
+            max_retries,
+        }
+    }
+
+    async fn send(&self, report: &BalanceReport) -> anyhow::Result<()> {
+        send_with_retries(self.max_retries, WEBHOOK_INITIAL_BACKOFF, || async {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(report)
+                .send()
+                .await
+                .map_err(|e| SendError::Retryable(e.into()))?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            let err = anyhow::anyhow!("Webhook 返回 HTTP {}", status);
+            if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                Err(SendError::Retryable(err))
+            } else {
+                Err(SendError::Fatal(err))
+            }
+        })
+        .await
+    }
+}
+
+/// 余额报告生成器
+pub struct BalanceReporter {
+    service: Arc<AdminService>,
+    reports_dir: PathBuf,
+    webhook: Option<ReportWebhook>,
+}
+
+impl BalanceReporter {
+    /// 报告写入 `<cache_dir>/reports`
+    pub fn new(service: Arc<AdminService>, cache_dir: &Path) -> Self {
+        Self {
+            service,
+            reports_dir: cache_dir.join("reports"),
+            webhook: None,
+        }
+    }
+
+    /// 设置报告推送 Webhook
+    pub fn with_webhook(mut self, webhook: ReportWebhook) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// 生成一次报告：刷新余额、计算变化量、写入文件并推送 Webhook
+    pub async fn run_once(&self) -> anyhow::Result<BalanceReport> {
+        let now = Local::now();
+        let date = now.format("%Y-%m-%d").to_string();
+
+        // 先批量刷新过期余额，随后逐个读取时直接命中缓存（刷新失败的凭据在读取时重试并记入 failures）
+        let refreshed = self
+            .service
+            .refresh_stale_balances(Duration::from_secs(BALANCE_CACHE_TTL_SECS as u64))
+            .await;
+        tracing::debug!("余额报告刷新了 {} 个过期余额", refreshed);
+
+        let ids: Vec<u64> = self
+            .service
+            .get_all_credentials()
+            .credentials
+            .into_iter()
+            .filter(|c| !c.disabled)
+            .map(|c| c.id)
+            .collect();
+        let mut balances = Vec::with_capacity(ids.len());
+        let mut failures = Vec::new();
+        for id in ids {
+            match self.service.get_balance(id).await {
+                Ok(balance) => balances.push(balance.data),
+                Err(e) => failures.push(BalanceReportFailure {
+                    id,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        let previous = load_previous_report(&self.reports_dir, &date);
+        let report = BalanceReport {
+            date: date.clone(),
+            generated_at: now.to_rfc3339(),
+            previous_generated_at: previous.as_ref().map(|p| p.generated_at.clone()),
+            credentials: compute_deltas(balances, previous.as_ref()),
+            failures,
+        };
+
+        std::fs::create_dir_all(&self.reports_dir)
+            .with_context(|| format!("创建报告目录失败: {:?}", self.reports_dir))?;
+        let path = self.reports_dir.join(report_file_name(&date));
+        let json = serde_json::to_string_pretty(&report).context("序列化余额报告失败")?;
+        std::fs::write(&path, json).with_context(|| format!("写入余额报告失败: {:?}", path))?;
+        tracing::info!(
+            "余额报告已生成: {:?}（{} 个凭据，{} 个失败）",
+            path,
+            report.credentials.len(),
+            report.failures.len()
+        );
+
+        if let Some(webhook) = &self.webhook {
+            match webhook.send(&report).await {
+                Ok(()) => tracing::info!("余额报告已推送到 Webhook"),
+                Err(e) => tracing::warn!("余额报告推送失败: {:#}", e),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// 启动每日余额报告任务（从实例跳过生成，报告由主实例负责）
+pub fn spawn_report_scheduler(
+    schedule: ReportSchedule,
+    reporter: BalanceReporter,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let now = Local::now();
+            let next = schedule.next_run_after(&now);
+            tracing::info!("下一次余额报告时间: {}", next.format("%Y-%m-%d %H:%M"));
+            tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+            if reporter.service.is_secondary() {
+                tracing::debug!("从实例模式，跳过余额报告");
+                continue;
+            }
+            if let Err(e) = reporter.run_once().await {
+                tracing::error!("生成余额报告失败: {:#}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use chrono::Utc;
+
+    fn balance(id: u64, current_usage: f64, usage_limit: f64) -> BalanceResponse {
+        BalanceResponse {
+            id,
+            subscription_title: None,
+            current_usage,
+            usage_limit,
+            remaining: usage_limit - current_usage,
+            usage_percentage: current_usage / usage_limit * 100.0,
+            next_reset_at: None,
+            days_until_reset: None,
+            usage_trend: None,
+            daily_budget_remaining: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        let expected = ReportSchedule {
+            hour: 8,
+            minute: 30,
+        };
+        assert_eq!(ReportSchedule::parse("08:30").unwrap(), expected);
+        assert_eq!(ReportSchedule::parse(" 8:30 ").unwrap(), expected);
+        assert_eq!(ReportSchedule::parse("30 8 * * *").unwrap(), expected);
+
+        for invalid in [
+            "24:00",
+            "12:60",
+            "8",
+            "30 8 * * 1",
+            "*/5 * * * *",
+            "ab:cd",
+            "",
+        ] {
+            assert!(ReportSchedule::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_next_run_after() {
+        let schedule = ReportSchedule::parse("08:30").unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        assert_eq!(
+            schedule.next_run_after(&at("2024-01-01T06:00:00Z")),
+            at("2024-01-01T08:30:00Z")
+        );
+        // 恰好到达执行时间时排到次日，避免同一时刻重复执行
+        assert_eq!(
+            schedule.next_run_after(&at("2024-01-01T08:30:00Z")),
+            at("2024-01-02T08:30:00Z")
+        );
+        assert_eq!(
+            schedule.next_run_after(&at("2024-12-31T23:00:00Z")),
+            at("2025-01-01T08:30:00Z")
+        );
+    }
+
+    #[test]
+    fn test_compute_deltas() {
+        let previous = BalanceReport {
+            date: "2024-01-01".to_string(),
+            generated_at: "2024-01-01T08:30:00+00:00".to_string(),
+            previous_generated_at: None,
+            credentials: compute_deltas(
+                vec![balance(1, 10.0, 100.0), balance(2, 80.0, 100.0)],
+                None,
+            ),
+            failures: Vec::new(),
+        };
+        assert!(previous.credentials.iter().all(|e| e.usage_delta.is_none()));
+
+        let entries = compute_deltas(
+            vec![
+                balance(1, 25.0, 100.0),
+                balance(2, 5.0, 100.0),
+                balance(3, 1.0, 100.0),
+            ],
+            Some(&previous),
+        );
+        assert_eq!(entries[0].usage_delta, Some(15.0));
+        assert_eq!(entries[0].remaining_delta, Some(-15.0));
+        // 期间额度已重置：新增使用量即当前使用量
+        assert_eq!(entries[1].usage_delta, Some(5.0));
+        assert_eq!(entries[1].remaining_delta, Some(75.0));
+        // 新增凭据没有可比较的记录
+        assert_eq!(entries[2].usage_delta, None);
+        assert_eq!(entries[2].remaining_delta, None);
+    }
+
+    #[test]
+    fn test_load_previous_report_skips_same_day() {
+        let dir = std::env::temp_dir().join(format!("kiro-reports-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for date in ["2024-01-01", "2024-01-02", "2024-01-03"] {
+            let report = BalanceReport {
+                date: date.to_string(),
+                generated_at: date.to_string(),
+                previous_generated_at: None,
+                credentials: Vec::new(),
+                failures: Vec::new(),
+            };
+            std::fs::write(
+                dir.join(report_file_name(date)),
+                serde_json::to_string(&report).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let previous = load_previous_report(&dir, "2024-01-03").unwrap();
+        assert_eq!(previous.date, "2024-01-02");
+        assert!(load_previous_report(&dir, "2024-01-01").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_retryable_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retries(3, Duration::ZERO, || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(SendError::Retryable(anyhow::anyhow!("HTTP 503")))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // 超过最大重试次数
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retries(2, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(SendError::Retryable(anyhow::anyhow!("HTTP 503")))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_does_not_retry_fatal_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retries(3, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(SendError::Fatal(anyhow::anyhow!("HTTP 400")))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "HTTP 400");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -2,16 +2,23 @@
 
 use axum::{
     Router, middleware,
-    routing::{delete, get, post},
+    routing::{get, post},
 };
 
 use super::{
     handlers::{
-        add_credential, delete_credential, get_all_credentials, get_credential_balance,
-        get_load_balancing_mode, reset_failure_count, set_credential_disabled,
-        set_credential_priority, set_load_balancing_mode,
+        add_credential, cancel_request, delete_credential, demote_credential, export_state,
+        export_stats, get_active_requests, get_all_credentials, get_connection_diagnostics,
+        get_credential, get_credential_balance, get_credential_duplicates, get_credential_health,
+        get_events, get_load_balancing_mode, get_openapi, get_refresh_history, get_user_usage,
+        import_state, import_stats, promote_credential, rebalance_priorities, reorder_credentials,
+        reset_connections, reset_failure_count, set_credential_disabled, set_credential_priority,
+        set_load_balancing_mode, test_filters, test_redaction,
+    },
+    middleware::{
+        AdminState, admin_auth_middleware, api_version_middleware, flush_credentials_middleware,
+        secondary_mode_middleware,
     },
-    middleware::{AdminState, admin_auth_middleware},
 };
 
 /// 创建 Admin API 路由
@@ -19,13 +26,43 @@ use super::{
 /// # 端点
 /// - `GET /credentials` - 获取所有凭据状态
 /// - `POST /credentials` - 添加新凭据
+/// - `POST /credentials/reorder` - 按给定顺序重排凭据优先级
+/// - `POST /credentials/rebalance-priorities` - 将优先级压缩为连续整数（保持相对顺序）
+/// - `GET /credentials/duplicates` - 按 refreshToken 哈希列出重复的凭据
+/// - `GET /credentials/:id` - 获取单个凭据详情（密钥只返回提示信息）
 /// - `DELETE /credentials/:id` - 删除凭据
 /// - `POST /credentials/:id/disabled` - 设置凭据禁用状态
 /// - `POST /credentials/:id/priority` - 设置凭据优先级
+/// - `POST /credentials/:id/promote` - 将凭据提升为最高优先级
+/// - `POST /credentials/:id/demote` - 将凭据降为最低优先级
 /// - `POST /credentials/:id/reset` - 重置失败计数
 /// - `GET /credentials/:id/balance` - 获取凭据余额
+/// - `GET /credentials/:id/refresh-history` - 获取凭据的 Token 刷新记录
+/// - `GET /credentials/:id/health` - 获取凭据健康评分及各项因子
 /// - `GET /config/load-balancing` - 获取负载均衡模式
 /// - `PUT /config/load-balancing` - 设置负载均衡模式
+/// - `GET /stats/export` - 导出凭据统计数据
+/// - `POST /stats/import` - 导入凭据统计数据
+/// - `GET /state/export` - 导出运行时状态（凭据选择、失败计数、刷新退避、限流状态等）
+/// - `POST /state/import` - 导入运行时状态（用于蓝绿部署时预热新实例）
+/// - `GET /users` - 获取按用户统计的请求与 token 用量
+/// - `GET /events` - 获取最近的管理事件（本地月度请求上限触发与恢复）
+/// - `POST /filters/test` - 对样例文本试运行响应文本过滤器
+/// - `POST /redaction/test` - 对样例文本试运行出站脱敏规则
+/// - `GET /requests/active` - 列出进行中的消息请求（ID、模型、凭据、开始时间、通道）
+/// - `POST /requests/:id/cancel` - 取消进行中的消息请求（流式响应以 SSE error 事件结束）
+/// - `GET /diagnostics/connections` - 获取上游连接诊断信息
+/// - `POST /diagnostics/connections/reset` - 丢弃并重建所有缓存的上游 Client
+/// - `GET /openapi.json` - 获取 Admin API 与 Anthropic 兼容端点的 OpenAPI 文档
+///
+/// # 持久化
+/// 修改凭据的请求在凭据文件落盘后才返回成功，落盘失败返回 500
+///
+/// # 从实例模式
+/// 凭据文件被其他实例锁定时，除 GET、过滤器与脱敏规则试运行、请求取消、连接重置与运行时状态导入以外的请求均返回 409
+///
+/// # 版本协商
+/// 通过 `Accept: application/vnd.kiro.admin.v{N}+json` 选择响应结构，未指定时为 v1
 ///
 /// # 认证
 /// 需要 Admin API Key 认证，支持：
@@ -37,18 +74,998 @@ pub fn create_admin_router(state: AdminState) -> Router {
             "/credentials",
             get(get_all_credentials).post(add_credential),
         )
-        .route("/credentials/{id}", delete(delete_credential))
+        .route("/credentials/reorder", post(reorder_credentials))
+        .route(
+            "/credentials/rebalance-priorities",
+            post(rebalance_priorities),
+        )
+        .route("/credentials/duplicates", get(get_credential_duplicates))
+        .route(
+            "/credentials/{id}",
+            get(get_credential).delete(delete_credential),
+        )
         .route("/credentials/{id}/disabled", post(set_credential_disabled))
         .route("/credentials/{id}/priority", post(set_credential_priority))
+        .route("/credentials/{id}/promote", post(promote_credential))
+        .route("/credentials/{id}/demote", post(demote_credential))
         .route("/credentials/{id}/reset", post(reset_failure_count))
         .route("/credentials/{id}/balance", get(get_credential_balance))
+        .route(
+            "/credentials/{id}/refresh-history",
+            get(get_refresh_history),
+        )
+        .route("/credentials/{id}/health", get(get_credential_health))
         .route(
             "/config/load-balancing",
             get(get_load_balancing_mode).put(set_load_balancing_mode),
         )
+        .route("/stats/export", get(export_stats))
+        .route("/stats/import", post(import_stats))
+        .route("/state/export", get(export_state))
+        .route("/users", get(get_user_usage))
+        .route("/events", get(get_events))
+        .route("/requests/active", get(get_active_requests))
+        .route("/diagnostics/connections", get(get_connection_diagnostics))
+        .route("/openapi.json", get(get_openapi))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            flush_credentials_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            secondary_mode_middleware,
+        ))
+        // 试运行、请求取消、连接重置与运行时状态导入不修改凭据文件，从实例也允许调用
+        // （新实例启动时旧实例可能仍持有锁）
+        .route("/filters/test", post(test_filters))
+        .route("/redaction/test", post(test_redaction))
+        .route("/requests/{id}/cancel", post(cancel_request))
+        .route("/diagnostics/connections/reset", post(reset_connections))
+        .route("/state/import", post(import_state))
+        .layer(middleware::from_fn(api_version_middleware))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             admin_auth_middleware,
         ))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::admin::service::AdminService;
+    use crate::kiro::model::credentials::KiroCredentials;
+    use crate::kiro::token_manager::CallContext;
+    use crate::kiro::token_manager::{MultiTokenManager, sha256_hex};
+    use crate::model::config::Config;
+
+    /// 即将过期（但未过期）的凭据：每次获取上下文都会触发刷新
+    fn expiring_credentials() -> KiroCredentials {
+        KiroCredentials {
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::minutes(7)).to_rfc3339()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_history_endpoint() {
+        let mut config = Config::default();
+        config.min_refresh_interval_secs = 0;
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![expiring_credentials()], None, None, false)
+                .unwrap(),
+        );
+        manager.stub_refresh_results(vec![
+            Ok(expiring_credentials()),
+            Err(anyhow::anyhow!("凭证已过期或无效")),
+            Ok(expiring_credentials()),
+            Err(anyhow::anyhow!("服务器错误")),
+            Ok(expiring_credentials()),
+        ]);
+        for _ in 0..5 {
+            let _ = manager.acquire_context(None).await;
+        }
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let history: Vec<serde_json::Value> = reqwest::Client::new()
+            .get(format!("http://{}/credentials/1/refresh-history", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let succeeded: Vec<bool> = history
+            .iter()
+            .map(|a| a["succeeded"].as_bool().unwrap())
+            .collect();
+        assert_eq!(succeeded, vec![true, false, true, false, true]);
+        assert!(history[0]["error"].is_null());
+        assert_eq!(history[1]["error"], "凭证已过期或无效");
+        assert!(history.iter().all(|a| a["attemptedAt"].is_string()));
+
+        let resp = reqwest::Client::new()
+            .get(format!("http://{}/credentials/99/refresh-history", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_priorities_endpoint() {
+        let creds = [0, 10, 100]
+            .into_iter()
+            .map(|priority| KiroCredentials {
+                priority,
+                ..expiring_credentials()
+            })
+            .collect();
+        let manager =
+            Arc::new(MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap());
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/credentials/rebalance-priorities", addr);
+
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let reassignments: Vec<(u64, u64, u64)> = body["reassignments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["id"].as_u64().unwrap(),
+                    r["old_priority"].as_u64().unwrap(),
+                    r["new_priority"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(reassignments, vec![(1, 0, 0), (2, 10, 1), (3, 100, 2)]);
+
+        let priorities: Vec<(u64, u32)> = manager
+            .snapshot()
+            .entries
+            .iter()
+            .map(|e| (e.id, e.priority))
+            .collect();
+        assert_eq!(priorities, vec![(1, 0), (2, 1), (3, 2)]);
+
+        // 幂等：再次调用优先级不变
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(
+            body["reassignments"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|r| r["old_priority"] == r["new_priority"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_credential_duplicates_endpoint() {
+        let creds = [(1, "a"), (5, "b"), (9, "a")]
+            .into_iter()
+            .map(|(id, seed)| KiroCredentials {
+                id: Some(id),
+                refresh_token: Some(seed.repeat(150)),
+                ..expiring_credentials()
+            })
+            .collect();
+        let manager =
+            Arc::new(MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap());
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!("http://{}/credentials/duplicates", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let groups = body["duplicate_groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0]["credential_ids"], serde_json::json!([1, 9]));
+        assert_eq!(
+            groups[0]["refresh_token_hash"],
+            sha256_hex(&"a".repeat(150))
+        );
+        assert_eq!(body["unchecked_ids"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_credentials_csv_export() {
+        let creds = [
+            (1, 2, Some("b@example.com")),
+            (2, 0, None),
+            (3, 1, Some("a,c@example.com")),
+            (4, 3, Some("=HYPERLINK(\"x\")")),
+        ]
+        .into_iter()
+        .map(|(id, priority, email)| KiroCredentials {
+            id: Some(id),
+            priority,
+            email: email.map(str::to_string),
+            profile_arn: (id == 1).then(|| "arn:aws:profile".to_string()),
+            ..expiring_credentials()
+        })
+        .collect();
+        let manager =
+            Arc::new(MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap());
+        manager.set_disabled(3, true).unwrap();
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let resp = reqwest::Client::new()
+            .get(format!("http://{}/credentials?format=csv", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+        assert_eq!(
+            resp.headers()["content-disposition"],
+            format!(
+                "attachment; filename=\"credentials_{}.csv\"",
+                Utc::now().format("%Y%m%d")
+            )
+            .as_str()
+        );
+        let body = resp.text().await.unwrap();
+        assert!(!body.contains(&"a".repeat(150)));
+        assert!(!body.contains("token"));
+
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "id",
+                "priority",
+                "disabled",
+                "auth_method",
+                "email",
+                "has_profile_arn",
+                "expires_at",
+                "success_count",
+                "failure_count",
+                "last_used_at",
+            ]
+        );
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        // 按优先级排序
+        let ids: Vec<&str> = rows.iter().map(|r| &r[0]).collect();
+        assert_eq!(ids, ["2", "3", "1", "4"]);
+        assert_eq!(&rows[1][2], "true");
+        // 公式开头的单元格加 ' 前缀
+        assert_eq!(&rows[3][4], "'=HYPERLINK(\"x\")");
+        assert_eq!(&rows[1][4], "a,c@example.com");
+        assert_eq!(&rows[2][4], "b@example.com");
+        assert_eq!(&rows[2][5], "true");
+        assert_eq!(&rows[0][4], "");
+        assert_eq!(&rows[0][5], "false");
+
+        // 过滤参数同样适用于 CSV 导出
+        let body = reqwest::Client::new()
+            .get(format!(
+                "http://{}/credentials?format=csv&disabled=true",
+                addr
+            ))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        let ids: Vec<String> = reader
+            .records()
+            .map(|r| r.unwrap()[0].to_string())
+            .collect();
+        assert_eq!(ids, ["3"]);
+
+        // CSV 导出忽略分页参数
+        let body = reqwest::Client::new()
+            .get(format!(
+                "http://{}/credentials?format=csv&sort=priority&page=2&pageSize=1",
+                addr
+            ))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        let ids: Vec<String> = reader
+            .records()
+            .map(|r| r.unwrap()[0].to_string())
+            .collect();
+        assert_eq!(ids, ["2", "3", "1", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_secondary_mode_rejects_mutations() {
+        let manager = Arc::new(
+            MultiTokenManager::new(
+                Config::default(),
+                vec![expiring_credentials()],
+                None,
+                None,
+                false,
+            )
+            .unwrap(),
+        );
+        manager.enter_secondary_mode();
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let client = reqwest::Client::new();
+
+        let resp = client
+            .post(format!("http://{}/credentials/1/priority", addr))
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({ "priority": 5 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "conflict");
+        assert_eq!(manager.snapshot().entries[0].priority, 0);
+
+        let resp = client
+            .delete(format!("http://{}/credentials/1", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+
+        // 读操作不受影响
+        let resp = client
+            .get(format!("http://{}/credentials", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        // 未认证请求仍返回 401
+        let resp = client
+            .post(format!("http://{}/credentials/1/reset", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+    #[tokio::test]
+    async fn test_filters_dry_run_endpoint() {
+        use crate::model::config::TextFilterConfig;
+
+        let mut config = Config::default();
+        config.post_processing.filters = vec![TextFilterConfig::StripPrefix {
+            prefixes: vec!["Sure!".to_string()],
+        }];
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![expiring_credentials()], None, None, false)
+                .unwrap(),
+        );
+        // 试运行不修改状态，从实例同样允许
+        manager.enter_secondary_mode();
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/filters/test", addr);
+
+        // 未提供 filters 时使用配置中的过滤器
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({ "text": "Sure! Hello" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], "Hello");
+        assert_eq!(body["changed"], true);
+
+        // 提供的 filters 覆盖配置，chunkSize 模拟流式分片
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({
+                "text": "I am Kiro, Kiro!",
+                "filters": [{
+                    "type": "regex",
+                    "pattern": "Kiro",
+                    "replacement": "Claude",
+                    "firstMatchOnly": true
+                }],
+                "chunkSize": 3
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], "I am Claude, Kiro!");
+
+        let resp = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({
+                "text": "x",
+                "filters": [{ "type": "regex", "pattern": "(" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_redaction_dry_run_endpoint() {
+        let mut config = Config::default();
+        config.redaction = serde_json::from_value(serde_json::json!({
+            "rules": [{ "name": "email", "pattern": r"\S+@\S+", "blocking": true }]
+        }))
+        .unwrap();
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![expiring_credentials()], None, None, false)
+                .unwrap(),
+        );
+        let router = create_admin_router(AdminState::new("admin-key", AdminService::new(manager)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/redaction/test", addr);
+
+        // 未提供 rules 时使用配置中的规则，非严格模式下 blocking 规则只做替换
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({ "text": "mail a@b.com and c@d.com" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], "mail [REDACTED] and [REDACTED]");
+        assert_eq!(body["changed"], true);
+        assert_eq!(
+            body["redactions"],
+            serde_json::json!([{ "rule": "email", "count": 2 }])
+        );
+        assert_eq!(body["blockedBy"], serde_json::json!([]));
+
+        // strict 覆盖配置时报告会拒绝请求的规则
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({ "text": "a@b.com", "strict": true }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["blockedBy"], serde_json::json!(["email"]));
+
+        // 提供的 rules 覆盖配置
+        let body: serde_json::Value = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({
+                "text": "host db1.corp",
+                "rules": [{ "name": "host", "pattern": r"(\w+)\.corp", "replacement": "<$1>" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], "host <db1>");
+
+        let resp = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&serde_json::json!({
+                "text": "x",
+                "rules": [{ "name": "bad", "pattern": "(" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.contains("redaction.rules[0] (bad)"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_connection_diagnostics_and_reset() {
+        let manager = Arc::new(
+            MultiTokenManager::new(
+                Config::default(),
+                vec![expiring_credentials()],
+                None,
+                None,
+                false,
+            )
+            .unwrap(),
+        );
+        let provider = crate::kiro::provider::KiroProvider::new(manager.clone());
+        let pool = provider.client_pool();
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager).with_client_pool(pool.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/diagnostics/connections", addr);
+
+        pool.get(None).unwrap();
+        pool.get(None).unwrap();
+        let body: serde_json::Value = client
+            .get(&url)
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let clients = body["clients"].as_array().unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0]["proxyUrl"], serde_json::Value::Null);
+        assert_eq!(clients[0]["requestsServed"], 2);
+        assert_eq!(clients[0]["poolIdleTimeoutSecs"], 90);
+        let old_instance = clients[0]["instanceId"].clone();
+
+        let body: serde_json::Value = client
+            .post(format!("{}/reset", url))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_ne!(body["clients"][0]["instanceId"], old_instance);
+        assert_eq!(body["clients"][0]["requestsServed"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_balance_cache_headers() {
+        use crate::kiro::model::usage_limits::UsageLimitsResponse;
+
+        let credentials = KiroCredentials {
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..expiring_credentials()
+        };
+        let manager = Arc::new(
+            MultiTokenManager::new(Config::default(), vec![credentials], None, None, false)
+                .unwrap(),
+        );
+        let usage = |current: f64| -> anyhow::Result<UsageLimitsResponse> {
+            Ok(serde_json::from_value(serde_json::json!({
+                "usageBreakdownList": [{
+                    "currentUsageWithPrecision": current,
+                    "usageLimitWithPrecision": 100.0
+                }]
+            }))
+            .unwrap())
+        };
+        manager.stub_usage_limits(vec![usage(10.0), usage(20.0)]);
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let fetch = || async {
+            let resp = reqwest::Client::new()
+                .get(format!("http://{}/credentials/1/balance", addr))
+                .header("x-api-key", "admin-key")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            let header = |name: &str| resp.headers()[name].to_str().unwrap().to_string();
+            let headers = (header("x-cache"), header("age"), header("cache-control"));
+            let body: serde_json::Value = resp.json().await.unwrap();
+            (headers, body["currentUsage"].as_f64().unwrap())
+        };
+
+        let (headers, usage) = fetch().await;
+        assert_eq!(
+            headers,
+            (
+                "MISS".to_string(),
+                "0".to_string(),
+                "max-age=300".to_string()
+            )
+        );
+        assert_eq!(usage, 10.0);
+
+        // TTL 内再次查询命中缓存
+        manager.age_balance_for_test(1, 30.0);
+        let ((cache, age, cache_control), usage) = fetch().await;
+        assert_eq!(cache, "HIT");
+        let age: u64 = age.parse().unwrap();
+        assert!((30..=31).contains(&age), "{}", age);
+        assert_eq!(cache_control, format!("max-age={}", 300 - age));
+        assert_eq!(usage, 10.0);
+
+        // 超过 TTL 后重新拉取
+        manager.age_balance_for_test(1, 300.0);
+        let ((cache, age, _), usage) = fetch().await;
+        assert_eq!((cache.as_str(), age.as_str()), ("MISS", "0"));
+        assert_eq!(usage, 20.0);
+    }
+
+    #[tokio::test]
+    async fn test_add_credential_idempotency_key_replays_response() {
+        let manager =
+            Arc::new(MultiTokenManager::new(Config::default(), vec![], None, None, true).unwrap());
+        // 只预设一次刷新结果：重放若重新验证会请求真实端点而失败
+        manager.stub_refresh_results(vec![Ok(KiroCredentials {
+            access_token: Some("new-access".to_string()),
+            refresh_token: Some("d".repeat(150)),
+            ..Default::default()
+        })]);
+        manager.stub_usage_limits(vec![Err(anyhow::anyhow!("offline"))]);
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let add = |refresh_token: String| async move {
+            let resp = reqwest::Client::new()
+                .post(format!("http://{}/credentials", addr))
+                .header("x-api-key", "admin-key")
+                .header("idempotency-key", "tab-1")
+                .json(&serde_json::json!({ "refreshToken": refresh_token }))
+                .send()
+                .await
+                .unwrap();
+            let status = resp.status();
+            (status, resp.json::<serde_json::Value>().await.unwrap())
+        };
+
+        let (status, first) = add("d".repeat(150)).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", first);
+        let (status, replay) = add("d".repeat(150)).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", replay);
+        assert_eq!(replay, first);
+        assert_eq!(manager.snapshot().entries.len(), 1);
+
+        // 同一键用于不同凭据
+        let (status, _) = add("e".repeat(150)).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(manager.snapshot().entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_response_depends_on_negotiated_version() {
+        let credentials = KiroCredentials {
+            subscription_title: Some("KIRO PRO+".to_string()),
+            ..expiring_credentials()
+        };
+        let manager = Arc::new(
+            MultiTokenManager::new(Config::default(), vec![credentials], None, None, false)
+                .unwrap(),
+        );
+        let router = create_admin_router(AdminState::new("admin-key", AdminService::new(manager)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let fetch = |accept: Option<&'static str>| {
+            let mut request = reqwest::Client::new()
+                .get(format!("http://{}/credentials", addr))
+                .header("x-api-key", "admin-key");
+            if let Some(accept) = accept {
+                request = request.header("accept", accept);
+            }
+            async move {
+                let body: serde_json::Value = request.send().await.unwrap().json().await.unwrap();
+                body["credentials"][0].clone()
+            }
+        };
+
+        for accept in [None, Some("application/vnd.kiro.admin.v1+json")] {
+            let credential = fetch(accept).await;
+            assert_eq!(credential["id"], 1);
+            assert!(
+                credential.get("subscriptionTitle").is_none(),
+                "{:?}",
+                accept
+            );
+        }
+
+        let credential = fetch(Some("application/vnd.kiro.admin.v2+json")).await;
+        assert_eq!(credential["id"], 1);
+        assert_eq!(credential["subscriptionTitle"], "KIRO PRO+");
+    }
+
+    #[tokio::test]
+    async fn test_runtime_state_export_import_endpoints() {
+        use std::time::Instant;
+
+        use crate::anthropic::rate_limit::{RateLimitAlgorithm, RateLimiter, RateLimiterState};
+
+        let new_limiter = || {
+            Arc::new(RateLimiter::new(
+                5,
+                RateLimitAlgorithm::TokenBucket {
+                    refill_per_sec: 0.0,
+                },
+                Instant::now(),
+            ))
+        };
+        let new_manager = || {
+            Arc::new(
+                MultiTokenManager::new(
+                    Config::default(),
+                    vec![expiring_credentials(), expiring_credentials()],
+                    None,
+                    None,
+                    false,
+                )
+                .unwrap(),
+            )
+        };
+        let spawn_admin = |manager: Arc<MultiTokenManager>, limiter: Arc<RateLimiter>| async move {
+            let router = create_admin_router(AdminState::new(
+                "admin-key",
+                AdminService::new(manager).with_rate_limiter(Some(limiter)),
+            ));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                axum::serve(listener, router).await.unwrap();
+            });
+            addr
+        };
+
+        let source = new_manager();
+        let source_limiter = new_limiter();
+        source.report_success(&CallContext::for_test(2), 0);
+        source.switch_to_next();
+        for _ in 0..3 {
+            source_limiter.check(Instant::now()).unwrap();
+        }
+        let source_addr = spawn_admin(source.clone(), source_limiter).await;
+
+        // 新实例启动时旧实例仍持有凭据文件锁
+        let target = new_manager();
+        target.enter_secondary_mode();
+        let target_limiter = new_limiter();
+        let target_addr = spawn_admin(target.clone(), target_limiter.clone()).await;
+
+        let client = reqwest::Client::new();
+        let mut state: serde_json::Value = client
+            .get(format!("http://{}/state/export", source_addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(state["version"], 1);
+        assert_eq!(state["rateLimiter"]["algorithm"], "tokenBucket");
+        // 不包含任何密钥
+        assert!(!state.to_string().contains(&"a".repeat(150)));
+
+        let url = format!("http://{}/state/import", target_addr);
+        let resp = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&state)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            body["message"],
+            "已导入运行时状态，匹配 2 个凭据，跳过 0 个"
+        );
+
+        let snapshot = target.snapshot();
+        assert_eq!(snapshot.current_id, source.snapshot().current_id);
+        assert_eq!(snapshot.entries[1].success_count, 1);
+        assert_eq!(
+            target_limiter.export_state(Instant::now()),
+            RateLimiterState::TokenBucket { tokens: 2.0 }
+        );
+
+        state["version"] = serde_json::json!(2);
+        let resp = client
+            .post(&url)
+            .header("x-api-key", "admin-key")
+            .json(&state)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_active_requests_list_and_cancel() {
+        use crate::anthropic::active_requests::{ActiveRequest, ActiveRequests, RequestLane};
+
+        let manager =
+            Arc::new(MultiTokenManager::new(Config::default(), vec![], None, None, false).unwrap());
+        let registry = Arc::new(ActiveRequests::default());
+        let guard = registry.register(ActiveRequest {
+            id: "req_batch".to_string(),
+            model: "claude-sonnet-4".to_string(),
+            credential_id: Some(3),
+            started_at: Utc::now(),
+            lane: RequestLane::LongRunning,
+            stream: true,
+            timeout: Some(std::time::Duration::from_secs(1200)),
+            cancelled: false,
+        });
+
+        let router = create_admin_router(AdminState::new(
+            "admin-key",
+            AdminService::new(manager).with_active_requests(registry.clone()),
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let list = |client: reqwest::Client| async move {
+            client
+                .get(format!("http://{}/requests/active", addr))
+                .header("x-api-key", "admin-key")
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        };
+        let active = list(client.clone()).await;
+        assert_eq!(active["total"], 1);
+        let request = &active["requests"][0];
+        assert_eq!(request["id"], "req_batch");
+        assert_eq!(request["credentialId"], 3);
+        assert_eq!(request["lane"], "longRunning");
+        assert_eq!(request["timeoutSecs"], 1200);
+        assert_eq!(request["cancelled"], false);
+
+        let resp = client
+            .post(format!("http://{}/requests/req_missing/cancel", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let resp = client
+            .post(format!("http://{}/requests/req_batch/cancel", addr))
+            .header("x-api-key", "admin-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(list(client.clone()).await["requests"][0]["cancelled"], true);
+
+        drop(guard);
+        assert_eq!(list(client).await["total"], 0);
+    }
+}
@@ -1,58 +1,102 @@
 //! Admin API 业务逻辑服务
 
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 use chrono::Utc;
-use parking_lot::Mutex;
-use serde::{Deserialize, Serialize};
 
+use crate::anthropic::active_requests::ActiveRequests;
+use crate::anthropic::post_processing::TextFilters;
+use crate::anthropic::rate_limit::RateLimiter;
+use crate::anthropic::redaction::Redactor;
+use crate::http_client::ClientPool;
+use crate::kiro::balance_cache::{BALANCE_CACHE_TTL_SECS, UsageSnapshot};
 use crate::kiro::model::credentials::KiroCredentials;
-use crate::kiro::token_manager::MultiTokenManager;
+use crate::kiro::token_manager::{
+    CredentialEntrySnapshot, LOAD_BALANCING_MODES, MultiTokenManager, StateImportOutcome,
+    StatsExport, sha256_hex,
+};
 
 use super::error::AdminServiceError;
+use super::idempotency::{IdempotencyCache, IdempotencyLookup, MAX_IDEMPOTENCY_KEY_LEN};
 use super::types::{
-    AddCredentialRequest, AddCredentialResponse, BalanceResponse, CredentialStatusItem,
-    CredentialsStatusResponse, LoadBalancingModeResponse, SetLoadBalancingModeRequest,
+    ActiveRequestItem, ActiveRequestsResponse, AddCredentialRequest, AddCredentialResponse,
+    AdminEventListResponse, BalanceResponse, BalanceWithMeta, ConnectionDiagnosticsResponse,
+    CredentialDetailResponse, CredentialDuplicatesResponse, CredentialHealthResponse,
+    CredentialSecretHints, CredentialSortKey, CredentialStatusItem, CredentialStatusV2Fields,
+    CredentialsFormat, CredentialsPagination, CredentialsQuery, CredentialsStatusResponse,
+    DuplicateCredentialGroup, LoadBalancingModeResponse, PriorityReassignment,
+    RUNTIME_STATE_VERSION, RebalancePrioritiesResponse, RedactionCount, RefreshAttemptSnapshot,
+    RuntimeState, SetLoadBalancingModeRequest, SortOrder, TestFiltersRequest, TestFiltersResponse,
+    TestRedactionRequest, TestRedactionResponse, UserUsageListResponse,
 };
 
-/// 余额缓存过期时间（秒），5 分钟
-const BALANCE_CACHE_TTL_SECS: i64 = 300;
+/// 计费周期天数（上游未提供上次重置时间，按 30 天估算）
+const BILLING_CYCLE_DAYS: f64 = 30.0;
 
-/// 缓存的余额条目（含时间戳）
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct CachedBalance {
-    /// 缓存时间（Unix 秒）
-    cached_at: f64,
-    /// 缓存的余额数据
-    data: BalanceResponse,
-}
+const SECS_PER_DAY: f64 = 86_400.0;
+
+/// 凭据列表默认每页数量
+const DEFAULT_CREDENTIALS_PAGE_SIZE: usize = 20;
+
+/// 凭据列表每页数量上限
+const MAX_CREDENTIALS_PAGE_SIZE: usize = 100;
 
 /// Admin 服务
 ///
 /// 封装所有 Admin API 的业务逻辑
 pub struct AdminService {
     token_manager: Arc<MultiTokenManager>,
-    balance_cache: Mutex<HashMap<u64, CachedBalance>>,
-    cache_path: Option<PathBuf>,
+    /// 上游 API Client 缓存（用于连接诊断）
+    client_pool: Option<Arc<ClientPool>>,
+    /// 与 Anthropic API 共享的速率限制器（用于运行时状态迁移）
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// 与 Anthropic API 共享的进行中请求登记表（用于列出、取消请求）
+    active_requests: Arc<ActiveRequests>,
+    /// 添加凭据的幂等键缓存（`Idempotency-Key` 请求头）
+    add_credential_keys: IdempotencyCache<AddCredentialResponse>,
 }
 
 impl AdminService {
     pub fn new(token_manager: Arc<MultiTokenManager>) -> Self {
-        let cache_path = token_manager
-            .cache_dir()
-            .map(|d| d.join("kiro_balance_cache.json"));
-
-        let balance_cache = Self::load_balance_cache_from(&cache_path);
-
         Self {
             token_manager,
-            balance_cache: Mutex::new(balance_cache),
-            cache_path,
+            client_pool: None,
+            rate_limiter: None,
+            active_requests: Arc::default(),
+            add_credential_keys: IdempotencyCache::default(),
         }
     }
 
+    /// 设置上游 API Client 缓存
+    pub fn with_client_pool(mut self, client_pool: Arc<ClientPool>) -> Self {
+        self.client_pool = Some(client_pool);
+        self
+    }
+
+    /// 设置与 Anthropic API 共享的速率限制器
+    pub fn with_rate_limiter(mut self, rate_limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// 设置与 Anthropic API 共享的进行中请求登记表
+    pub fn with_active_requests(mut self, active_requests: Arc<ActiveRequests>) -> Self {
+        self.active_requests = active_requests;
+        self
+    }
+
+    /// 是否处于从实例模式（写操作会被拒绝）
+    pub fn is_secondary(&self) -> bool {
+        self.token_manager.is_secondary()
+    }
+
+    /// 等待已提交的凭据修改落盘
+    pub async fn flush_credentials(&self) -> anyhow::Result<()> {
+        self.token_manager.flush_credentials().await
+    }
+
     /// 获取所有凭据状态
     pub fn get_all_credentials(&self) -> CredentialsStatusResponse {
         let snapshot = self.token_manager.snapshot();
@@ -60,22 +104,7 @@ impl AdminService {
         let mut credentials: Vec<CredentialStatusItem> = snapshot
             .entries
             .into_iter()
-            .map(|entry| CredentialStatusItem {
-                id: entry.id,
-                priority: entry.priority,
-                disabled: entry.disabled,
-                failure_count: entry.failure_count,
-                is_current: entry.id == snapshot.current_id,
-                expires_at: entry.expires_at,
-                auth_method: entry.auth_method,
-                has_profile_arn: entry.has_profile_arn,
-                refresh_token_hash: entry.refresh_token_hash,
-                email: entry.email,
-                success_count: entry.success_count,
-                last_used_at: entry.last_used_at.clone(),
-                has_proxy: entry.has_proxy,
-                proxy_url: entry.proxy_url,
-            })
+            .map(|entry| status_item(entry, snapshot.current_id))
             .collect();
 
         // 按优先级排序（数字越小优先级越高）
@@ -85,8 +114,173 @@ impl AdminService {
             total: snapshot.total,
             available: snapshot.available,
             current_id: snapshot.current_id,
+            fleet_health_score: snapshot.fleet_health_score,
+            total_estimated_cost: snapshot.total_estimated_cost,
+            persist_warning: snapshot.persist_warning,
+            auto_recover_all_disabled: snapshot.auto_recover_all_disabled,
+            last_self_heal_at: snapshot.last_self_heal_at,
             credentials,
+            pagination: None,
+        }
+    }
+
+    /// 按 refreshToken 哈希查找重复的凭据
+    ///
+    /// 只返回包含至少两个凭据的分组；没有 refreshToken 的凭据无法判断，单独列出
+    pub fn find_duplicates(&self) -> CredentialDuplicatesResponse {
+        let mut by_hash: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut unchecked_ids = Vec::new();
+        for entry in self.token_manager.snapshot().entries {
+            match entry.refresh_token_hash {
+                Some(hash) => by_hash.entry(hash).or_default().push(entry.id),
+                None => unchecked_ids.push(entry.id),
+            }
         }
+
+        let mut duplicate_groups: Vec<DuplicateCredentialGroup> = by_hash
+            .into_iter()
+            .filter(|(_, ids)| ids.len() >= 2)
+            .map(|(refresh_token_hash, mut credential_ids)| {
+                credential_ids.sort_unstable();
+                DuplicateCredentialGroup {
+                    refresh_token_hash,
+                    credential_ids,
+                }
+            })
+            .collect();
+        duplicate_groups.sort_by_key(|g| g.credential_ids[0]);
+        unchecked_ids.sort_unstable();
+
+        CredentialDuplicatesResponse {
+            duplicate_groups,
+            unchecked_ids,
+        }
+    }
+
+    /// 获取单个凭据详情（refreshToken 等密钥只返回提示信息）
+    pub fn get_credential(&self, id: u64) -> Result<CredentialDetailResponse, AdminServiceError> {
+        let credentials = self
+            .token_manager
+            .credentials_of(id)
+            .map_err(|e| self.classify_error(e, id))?;
+        let snapshot = self.token_manager.snapshot();
+        let entry = snapshot
+            .entries
+            .into_iter()
+            .find(|e| e.id == id)
+            .ok_or(AdminServiceError::NotFound { id })?;
+
+        Ok(CredentialDetailResponse {
+            status: status_item(entry, snapshot.current_id),
+            secrets: secret_hints(&credentials),
+            profile_arn: credentials.profile_arn,
+            region: credentials.region,
+            auth_region: credentials.auth_region,
+            api_region: credentials.api_region,
+            client_id: credentials.client_id,
+            machine_id: credentials.machine_id,
+        })
+    }
+
+    /// 按查询参数过滤、排序并分页凭据列表
+    ///
+    /// 未携带任何查询参数时与 [`Self::get_all_credentials`] 相同（保持旧版客户端兼容）
+    pub fn list_credentials(&self, query: &CredentialsQuery) -> CredentialsStatusResponse {
+        let mut response = self.get_all_credentials();
+        if query.is_empty() {
+            return response;
+        }
+
+        let search = query.search.as_deref().map(str::to_lowercase);
+        let credentials: Vec<CredentialStatusItem> = std::mem::take(&mut response.credentials)
+            .into_iter()
+            .filter(|c| query.disabled.is_none_or(|d| c.disabled == d))
+            .filter(|c| {
+                query.auth_method.as_deref().is_none_or(|method| {
+                    c.auth_method
+                        .as_deref()
+                        .is_some_and(|m| m.eq_ignore_ascii_case(method))
+                })
+            })
+            .filter(|c| {
+                search.as_deref().is_none_or(|search| {
+                    c.email
+                        .as_deref()
+                        .is_some_and(|email| email.to_lowercase().contains(search))
+                })
+            })
+            .collect();
+
+        let credentials = self.sort_credentials(credentials, query);
+
+        // CSV 导出返回过滤、排序后的全部凭据，忽略分页参数
+        if query.format == Some(CredentialsFormat::Csv) {
+            response.credentials = credentials;
+            return response;
+        }
+
+        let filtered = credentials.len();
+        let page_size = query
+            .page_size
+            .unwrap_or(DEFAULT_CREDENTIALS_PAGE_SIZE)
+            .clamp(1, MAX_CREDENTIALS_PAGE_SIZE);
+        let page = query.page.unwrap_or(1).max(1);
+        response.credentials = credentials
+            .into_iter()
+            .skip((page - 1).saturating_mul(page_size))
+            .take(page_size)
+            .collect();
+        response.pagination = Some(CredentialsPagination {
+            filtered,
+            page,
+            page_size,
+            total_pages: filtered.div_ceil(page_size),
+        });
+        response
+    }
+
+    /// 按排序字段排序（缺少数据的凭据始终排在最后，相同时按优先级、ID 排序）
+    fn sort_credentials(
+        &self,
+        credentials: Vec<CredentialStatusItem>,
+        query: &CredentialsQuery,
+    ) -> Vec<CredentialStatusItem> {
+        use std::cmp::Ordering;
+
+        let sort = query.sort.unwrap_or_default();
+        let descending = match query.order {
+            Some(order) => order == SortOrder::Desc,
+            None => sort != CredentialSortKey::Priority,
+        };
+        let key = |c: &CredentialStatusItem| -> Option<f64> {
+            match sort {
+                CredentialSortKey::Priority => Some(f64::from(c.priority)),
+                CredentialSortKey::LastUsedAt => c
+                    .last_used_at
+                    .as_deref()
+                    .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                    .map(|t| t.timestamp_micros() as f64),
+                CredentialSortKey::SuccessCount => Some(c.success_count as f64),
+                CredentialSortKey::Remaining => self
+                    .token_manager
+                    .get_cached_balance(c.id)
+                    .map(|cached| cached.data.remaining()),
+            }
+        };
+
+        let mut keyed: Vec<(Option<f64>, CredentialStatusItem)> =
+            credentials.into_iter().map(|c| (key(&c), c)).collect();
+        keyed.sort_by(|(a_key, a), (b_key, b)| {
+            let ordering = match (a_key, b_key) {
+                (Some(x), Some(y)) if descending => y.total_cmp(x),
+                (Some(x), Some(y)) => x.total_cmp(y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            ordering.then_with(|| (a.priority, a.id).cmp(&(b.priority, b.id)))
+        });
+        keyed.into_iter().map(|(_, c)| c).collect()
     }
 
     /// 设置凭据禁用状态
@@ -113,6 +307,145 @@ impl AdminService {
             .map_err(|e| self.classify_error(e, id))
     }
 
+    /// 将凭据提升为最高优先级，返回调整后的凭据状态
+    pub fn promote_credential(
+        &self,
+        id: u64,
+    ) -> Result<CredentialsStatusResponse, AdminServiceError> {
+        self.token_manager
+            .promote_credential(id)
+            .map_err(|e| self.classify_error(e, id))?;
+        Ok(self.get_all_credentials())
+    }
+
+    /// 将凭据降为最低优先级，返回调整后的凭据状态
+    pub fn demote_credential(
+        &self,
+        id: u64,
+    ) -> Result<CredentialsStatusResponse, AdminServiceError> {
+        self.token_manager
+            .demote_credential(id)
+            .map_err(|e| self.classify_error(e, id))?;
+        Ok(self.get_all_credentials())
+    }
+
+    /// 按给定顺序重排凭据优先级，返回重排后的凭据状态
+    pub fn reorder_credentials(
+        &self,
+        ids: &[u64],
+    ) -> Result<CredentialsStatusResponse, AdminServiceError> {
+        self.token_manager.reorder_credentials(ids).map_err(|e| {
+            let msg = e.to_string();
+            if msg.contains("凭据排序无效") {
+                AdminServiceError::InvalidRequest(msg)
+            } else {
+                AdminServiceError::InternalError(msg)
+            }
+        })?;
+        Ok(self.get_all_credentials())
+    }
+
+    /// 将凭据优先级压缩为连续整数，返回每个凭据的调整结果
+    pub fn rebalance_priorities(&self) -> Result<RebalancePrioritiesResponse, AdminServiceError> {
+        let reassignments = self
+            .token_manager
+            .rebalance_priorities()
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(RebalancePrioritiesResponse {
+            reassignments: reassignments
+                .into_iter()
+                .map(|(id, old_priority, new_priority)| PriorityReassignment {
+                    id,
+                    old_priority,
+                    new_priority,
+                })
+                .collect(),
+        })
+    }
+
+    /// 导出凭据统计数据
+    pub fn export_stats(&self) -> StatsExport {
+        self.token_manager.export_stats()
+    }
+
+    /// 导入凭据统计数据，返回匹配的条目数
+    pub fn import_stats(&self, export: StatsExport) -> Result<usize, AdminServiceError> {
+        self.token_manager
+            .import_stats(export)
+            .map_err(|e| AdminServiceError::InvalidRequest(e.to_string()))
+    }
+
+    /// 导出运行时状态（只读取内存）
+    pub fn export_state(&self) -> RuntimeState {
+        let now = Instant::now();
+        RuntimeState {
+            version: RUNTIME_STATE_VERSION,
+            exported_at: Utc::now().to_rfc3339(),
+            token_manager: self.token_manager.export_state(),
+            rate_limiter: self.rate_limiter.as_ref().map(|l| l.export_state(now)),
+        }
+    }
+
+    /// 导入其他实例导出的运行时状态
+    ///
+    /// 未知的凭据 ID 被忽略；限流算法与本实例配置不一致时不导入限流状态
+    pub fn import_state(
+        &self,
+        state: RuntimeState,
+    ) -> Result<StateImportOutcome, AdminServiceError> {
+        if state.version != RUNTIME_STATE_VERSION {
+            return Err(AdminServiceError::InvalidRequest(format!(
+                "不支持的运行时状态版本: {}（当前支持 {}）",
+                state.version, RUNTIME_STATE_VERSION
+            )));
+        }
+        let limiter_rejected = self
+            .rate_limiter
+            .as_ref()
+            .zip(state.rate_limiter.as_ref())
+            .is_some_and(|(limiter, imported)| !limiter.import_state(imported, Instant::now()));
+        if limiter_rejected {
+            tracing::warn!("导入的限流算法与本实例配置不一致，已忽略限流状态");
+        }
+        Ok(self.token_manager.import_state(state.token_manager))
+    }
+
+    /// 获取凭据的 Token 刷新记录（按时间先后排列）
+    pub fn get_refresh_history(
+        &self,
+        id: u64,
+    ) -> Result<Vec<RefreshAttemptSnapshot>, AdminServiceError> {
+        let history = self
+            .token_manager
+            .refresh_history(id)
+            .map_err(|e| self.classify_error(e, id))?;
+        Ok(history
+            .into_iter()
+            .map(|a| RefreshAttemptSnapshot {
+                attempted_at: a.attempted_at,
+                succeeded: a.succeeded,
+                duration_ms: a.duration_ms,
+                error: a.error,
+            })
+            .collect())
+    }
+
+    /// 获取凭据健康评分及各项因子
+    pub fn credential_health(
+        &self,
+        id: u64,
+    ) -> Result<CredentialHealthResponse, AdminServiceError> {
+        let factors = self
+            .token_manager
+            .health_factors(id)
+            .map_err(|e| self.classify_error(e, id))?;
+        Ok(CredentialHealthResponse {
+            id,
+            health_score: factors.score(),
+            factors,
+        })
+    }
+
     /// 重置失败计数并重新启用
     pub fn reset_and_enable(&self, id: u64) -> Result<(), AdminServiceError> {
         self.token_manager
@@ -120,72 +453,124 @@ impl AdminService {
             .map_err(|e| self.classify_error(e, id))
     }
 
-    /// 获取凭据余额（带缓存）
-    pub async fn get_balance(&self, id: u64) -> Result<BalanceResponse, AdminServiceError> {
-        // 先查缓存
-        {
-            let cache = self.balance_cache.lock();
-            if let Some(cached) = cache.get(&id) {
-                let now = Utc::now().timestamp() as f64;
-                if (now - cached.cached_at) < BALANCE_CACHE_TTL_SECS as f64 {
-                    tracing::debug!("凭据 #{} 余额命中缓存", id);
-                    return Ok(cached.data.clone());
-                }
-            }
-        }
+    /// 刷新缓存缺失或超过 `max_age` 的启用凭据余额，返回成功刷新的数量
+    pub async fn refresh_stale_balances(&self, max_age: std::time::Duration) -> usize {
+        self.token_manager.refresh_stale_balances(max_age).await
+    }
 
-        // 缓存未命中或已过期，从上游获取
-        let balance = self.fetch_balance(id).await?;
+    /// 获取凭据余额（带缓存，缓存由 MultiTokenManager 维护）
+    pub async fn get_balance(&self, id: u64) -> Result<BalanceWithMeta, AdminServiceError> {
+        let now = Utc::now().timestamp_millis() as f64 / 1000.0;
 
-        // 更新缓存
+        // 先查缓存
+        if let Some(cached) = self
+            .token_manager
+            .get_cached_balance(id)
+            .filter(|c| c.is_fresh(BALANCE_CACHE_TTL_SECS as f64, now))
         {
-            let mut cache = self.balance_cache.lock();
-            cache.insert(
-                id,
-                CachedBalance {
-                    cached_at: Utc::now().timestamp() as f64,
-                    data: balance.clone(),
-                },
-            );
+            tracing::debug!("凭据 #{} 余额命中缓存", id);
+            return Ok(BalanceWithMeta {
+                data: Self::build_balance(id, &cached.data, now),
+                cache_hit: true,
+                cache_age_secs: (now - cached.cached_at).max(0.0),
+            });
         }
-        self.save_balance_cache();
 
-        Ok(balance)
-    }
-
-    /// 从上游获取余额（无缓存）
-    async fn fetch_balance(&self, id: u64) -> Result<BalanceResponse, AdminServiceError> {
-        let usage = self
+        // 缓存未命中或已过期，从上游获取（同时更新缓存）
+        let cached = self
             .token_manager
-            .get_usage_limits_for(id)
+            .refresh_balance(id)
             .await
             .map_err(|e| self.classify_balance_error(e, id))?;
 
-        let current_usage = usage.current_usage();
-        let usage_limit = usage.usage_limit();
-        let remaining = (usage_limit - current_usage).max(0.0);
+        Ok(BalanceWithMeta {
+            data: Self::build_balance(
+                id,
+                &cached.data,
+                Utc::now().timestamp_millis() as f64 / 1000.0,
+            ),
+            cache_hit: false,
+            cache_age_secs: 0.0,
+        })
+    }
+
+    /// 根据使用额度快照构建余额响应
+    ///
+    /// 派生字段：
+    /// - `days_until_reset` = (next_reset_at - now) / 86400
+    /// - `usage_trend` = current_usage / days_since_reset，
+    ///   其中上次重置时间按 next_reset_at 往前推一个计费周期估算
+    /// - `daily_budget_remaining` = remaining / days_until_reset
+    fn build_balance(id: u64, usage: &UsageSnapshot, now: f64) -> BalanceResponse {
+        let current_usage = usage.current_usage;
+        let usage_limit = usage.usage_limit;
+        let remaining = usage.remaining();
         let usage_percentage = if usage_limit > 0.0 {
             (current_usage / usage_limit * 100.0).min(100.0)
         } else {
             0.0
         };
 
-        Ok(BalanceResponse {
+        let next_reset_at = usage.next_reset_at;
+        let days_until_reset = next_reset_at.map(|t| ((t - now) / SECS_PER_DAY).max(0.0));
+        let usage_trend = days_until_reset
+            .map(|d| BILLING_CYCLE_DAYS - d)
+            .filter(|days_since_reset| *days_since_reset > 0.0)
+            .map(|days_since_reset| current_usage / days_since_reset);
+        let daily_budget_remaining = days_until_reset.filter(|d| *d > 0.0).map(|d| remaining / d);
+
+        BalanceResponse {
             id,
-            subscription_title: usage.subscription_title().map(|s| s.to_string()),
+            subscription_title: usage.subscription_title.clone(),
             current_usage,
             usage_limit,
             remaining,
             usage_percentage,
-            next_reset_at: usage.next_date_reset,
-        })
+            next_reset_at,
+            days_until_reset,
+            usage_trend,
+            daily_budget_remaining,
+        }
     }
 
     /// 添加新凭据
+    ///
+    /// 携带 `idempotency_key` 时，有效期内的重放直接返回首次成功的响应，不再重新验证和添加；
+    /// 同一键用于不同 refreshToken 的请求返回 400
     pub async fn add_credential(
         &self,
         req: AddCredentialRequest,
+        idempotency_key: Option<&str>,
     ) -> Result<AddCredentialResponse, AdminServiceError> {
+        let idempotency = match idempotency_key {
+            Some(key) => {
+                let key = key.trim();
+                if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+                    return Err(AdminServiceError::InvalidRequest(format!(
+                        "Idempotency-Key 长度必须在 1 ~ {} 之间",
+                        MAX_IDEMPOTENCY_KEY_LEN
+                    )));
+                }
+                let fingerprint = sha256_hex(&req.refresh_token);
+                match self
+                    .add_credential_keys
+                    .lookup(key, &fingerprint, Instant::now())
+                {
+                    IdempotencyLookup::Replay(response) => {
+                        tracing::info!("添加凭据请求重放（Idempotency-Key: {}）", key);
+                        return Ok(response);
+                    }
+                    IdempotencyLookup::Conflict => {
+                        return Err(AdminServiceError::InvalidRequest(
+                            "Idempotency-Key 已用于内容不同的请求".to_string(),
+                        ));
+                    }
+                    IdempotencyLookup::Miss => Some((key.to_string(), fingerprint)),
+                }
+            }
+            None => None,
+        };
+
         // 构建凭据对象
         let email = req.email.clone();
         let new_cred = KiroCredentials {
@@ -207,6 +592,8 @@ impl AdminService {
             proxy_url: req.proxy_url,
             proxy_username: req.proxy_username,
             proxy_password: req.proxy_password,
+            upstream_base_url: req.upstream_base_url,
+            monthly_request_limit: req.monthly_request_limit,
             disabled: false, // 新添加的凭据默认启用
         };
 
@@ -222,28 +609,60 @@ impl AdminService {
             tracing::warn!("添加凭据后获取订阅等级失败（不影响凭据添加）: {}", e);
         }
 
-        Ok(AddCredentialResponse {
+        let response = AddCredentialResponse {
             success: true,
             message: format!("凭据添加成功，ID: {}", credential_id),
             credential_id,
             email,
-        })
+        };
+        if let Some((key, fingerprint)) = idempotency {
+            self.add_credential_keys
+                .store(key, fingerprint, response.clone(), Instant::now());
+        }
+        Ok(response)
     }
 
     /// 删除凭据
     pub fn delete_credential(&self, id: u64) -> Result<(), AdminServiceError> {
         self.token_manager
             .delete_credential(id)
-            .map_err(|e| self.classify_delete_error(e, id))?;
+            .map_err(|e| self.classify_delete_error(e, id))
+    }
 
-        // 清理已删除凭据的余额缓存
-        {
-            let mut cache = self.balance_cache.lock();
-            cache.remove(&id);
+    /// 获取上游连接诊断信息
+    pub fn connection_diagnostics(&self) -> ConnectionDiagnosticsResponse {
+        ConnectionDiagnosticsResponse {
+            clients: self
+                .client_pool
+                .as_ref()
+                .map(|pool| pool.stats())
+                .unwrap_or_default(),
         }
-        self.save_balance_cache();
+    }
 
-        Ok(())
+    /// 丢弃并重建所有缓存的上游 Client，返回重建后的连接诊断信息
+    pub fn reset_connections(&self) -> Result<ConnectionDiagnosticsResponse, AdminServiceError> {
+        let pool = self
+            .client_pool
+            .as_ref()
+            .ok_or_else(|| AdminServiceError::InternalError("上游 HTTP Client 未初始化".into()))?;
+        pool.reset()
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(self.connection_diagnostics())
+    }
+
+    /// 获取按 metadata.user_id 统计的用户用量
+    pub fn user_usage(&self) -> UserUsageListResponse {
+        UserUsageListResponse {
+            users: self.token_manager.user_usage(),
+        }
+    }
+
+    /// 获取最近的管理事件
+    pub fn events(&self) -> AdminEventListResponse {
+        AdminEventListResponse {
+            events: self.token_manager.events(),
+        }
     }
 
     /// 获取负载均衡模式
@@ -259,10 +678,11 @@ impl AdminService {
         req: SetLoadBalancingModeRequest,
     ) -> Result<LoadBalancingModeResponse, AdminServiceError> {
         // 验证模式值
-        if req.mode != "priority" && req.mode != "balanced" {
-            return Err(AdminServiceError::InvalidCredential(
-                "mode 必须是 'priority' 或 'balanced'".to_string(),
-            ));
+        if !LOAD_BALANCING_MODES.contains(&req.mode.as_str()) {
+            return Err(AdminServiceError::InvalidCredential(format!(
+                "mode 必须是 {} 之一",
+                LOAD_BALANCING_MODES.join(" / ")
+            )));
         }
 
         self.token_manager
@@ -272,61 +692,93 @@ impl AdminService {
         Ok(LoadBalancingModeResponse { mode: req.mode })
     }
 
-    // ============ 余额缓存持久化 ============
-
-    fn load_balance_cache_from(cache_path: &Option<PathBuf>) -> HashMap<u64, CachedBalance> {
-        let path = match cache_path {
-            Some(p) => p,
-            None => return HashMap::new(),
-        };
+    /// 对样例文本试运行响应文本过滤器（不修改任何配置）
+    pub fn test_filters(
+        &self,
+        req: TestFiltersRequest,
+    ) -> Result<TestFiltersResponse, AdminServiceError> {
+        let configs = req
+            .filters
+            .as_deref()
+            .unwrap_or(&self.token_manager.config().post_processing.filters);
+        let filters = TextFilters::compile(configs)
+            .map_err(|e| AdminServiceError::InvalidRequest(format!("{:#}", e)))?;
 
-        let content = match std::fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(_) => return HashMap::new(),
+        let output = match req.chunk_size {
+            Some(chunk_size) => filters.apply_in_chunks(&req.text, chunk_size),
+            None => filters.apply(&req.text),
         };
+        Ok(TestFiltersResponse {
+            changed: output != req.text,
+            output,
+        })
+    }
 
-        // 文件中使用字符串 key 以兼容 JSON 格式
-        let map: HashMap<String, CachedBalance> = match serde_json::from_str(&content) {
-            Ok(m) => m,
-            Err(e) => {
-                tracing::warn!("解析余额缓存失败，将忽略: {}", e);
-                return HashMap::new();
-            }
-        };
+    /// 对样例文本试运行出站脱敏规则（不修改任何配置）
+    pub fn test_redaction(
+        &self,
+        req: TestRedactionRequest,
+    ) -> Result<TestRedactionResponse, AdminServiceError> {
+        let mut config = self.token_manager.config().redaction.clone();
+        if let Some(rules) = req.rules {
+            config.rules = rules;
+        }
+        if let Some(strict) = req.strict {
+            config.strict = strict;
+        }
+        let redactor = Redactor::compile(&config)
+            .map_err(|e| AdminServiceError::InvalidRequest(format!("{:#}", e)))?;
 
-        let now = Utc::now().timestamp() as f64;
-        map.into_iter()
-            .filter_map(|(k, v)| {
-                let id = k.parse::<u64>().ok()?;
-                // 丢弃超过 TTL 的条目
-                if (now - v.cached_at) < BALANCE_CACHE_TTL_SECS as f64 {
-                    Some((id, v))
-                } else {
-                    None
-                }
-            })
-            .collect()
+        let (output, summary) = redactor.apply(&req.text);
+        Ok(TestRedactionResponse {
+            changed: output != req.text,
+            output,
+            blocked_by: redactor.blocked_rules(&summary),
+            redactions: summary
+                .counts()
+                .iter()
+                .map(|(rule, count)| RedactionCount {
+                    rule: rule.clone(),
+                    count: *count,
+                })
+                .collect(),
+        })
     }
 
-    fn save_balance_cache(&self) {
-        let path = match &self.cache_path {
-            Some(p) => p,
-            None => return,
-        };
+    // ============ 进行中的请求 ============
 
-        // 持有锁期间完成序列化和写入，防止并发损坏
-        let cache = self.balance_cache.lock();
-        let map: HashMap<String, &CachedBalance> =
-            cache.iter().map(|(k, v)| (k.to_string(), v)).collect();
+    /// 列出进行中的消息请求
+    pub fn active_requests(&self) -> ActiveRequestsResponse {
+        let now = Utc::now();
+        let requests: Vec<ActiveRequestItem> = self
+            .active_requests
+            .list()
+            .into_iter()
+            .map(|r| ActiveRequestItem {
+                elapsed_ms: (now - r.started_at).num_milliseconds().max(0),
+                started_at: r.started_at.to_rfc3339(),
+                id: r.id,
+                model: r.model,
+                credential_id: r.credential_id,
+                lane: r.lane,
+                stream: r.stream,
+                timeout_secs: r.timeout.map(|t| t.as_secs()),
+                cancelled: r.cancelled,
+            })
+            .collect();
+        ActiveRequestsResponse {
+            total: requests.len(),
+            requests,
+        }
+    }
 
-        match serde_json::to_string_pretty(&map) {
-            Ok(json) => {
-                if let Err(e) = std::fs::write(path, json) {
-                    tracing::warn!("保存余额缓存失败: {}", e);
-                }
-            }
-            Err(e) => tracing::warn!("序列化余额缓存失败: {}", e),
+    /// 取消进行中的消息请求（上游调用被丢弃，流式响应以 SSE error 事件结束）
+    pub fn cancel_request(&self, id: &str) -> Result<(), AdminServiceError> {
+        if !self.active_requests.cancel(id) {
+            return Err(AdminServiceError::RequestNotFound { id: id.to_string() });
         }
+        tracing::info!(request_id = %id, "管理员取消进行中的请求");
+        Ok(())
     }
 
     // ============ 错误分类 ============
@@ -382,6 +834,8 @@ impl AdminService {
         let is_invalid_credential = msg.contains("缺少 refreshToken")
             || msg.contains("refreshToken 为空")
             || msg.contains("refreshToken 已被截断")
+            || msg.contains("非法控制字符")
+            || msg.contains("upstreamBaseUrl")
             || msg.contains("凭据已存在")
             || msg.contains("refreshToken 重复")
             || msg.contains("凭证已过期或无效")
@@ -412,3 +866,399 @@ impl AdminService {
         }
     }
 }
+
+/// 将凭据快照转换为 Admin API 状态条目
+fn status_item(entry: CredentialEntrySnapshot, current_id: u64) -> CredentialStatusItem {
+    CredentialStatusItem {
+        id: entry.id,
+        priority: entry.priority,
+        disabled: entry.disabled,
+        disabled_reason: entry.disabled_reason,
+        failure_count: entry.failure_count,
+        failure_counts: entry.failure_counts,
+        is_current: entry.id == current_id,
+        expires_at: entry.expires_at,
+        auth_method: entry.auth_method,
+        has_profile_arn: entry.has_profile_arn,
+        refresh_token_hash: entry.refresh_token_hash,
+        email: entry.email,
+        success_count: entry.success_count,
+        last_used_at: entry.last_used_at,
+        last_latency_ms: entry.last_latency_ms,
+        has_proxy: entry.has_proxy,
+        proxy_url: entry.proxy_url,
+        upstream_base_url: entry.upstream_base_url,
+        refresh_attempts_last_hour: entry.refresh_attempts_last_hour,
+        refresh_backoff_secs: entry.refresh_backoff_secs,
+        next_refresh_attempt_at: entry.next_refresh_attempt_at,
+        health_score: entry.health_score,
+        estimated_cost: entry.estimated_cost,
+        monthly_requests: entry.monthly_requests,
+        monthly_request_limit: entry.monthly_request_limit,
+        refresh_rotations: entry.refresh_rotations,
+        rotation_unpersisted: entry.rotation_unpersisted,
+        quota_exhausted_at_percentage: entry.quota_exhausted_at_percentage,
+        v2: Some(CredentialStatusV2Fields {
+            subscription_title: entry.subscription_title,
+        }),
+    }
+}
+
+/// 密钥提示保留的首尾字符数
+const SECRET_HINT_PREFIX_CHARS: usize = 6;
+const SECRET_HINT_SUFFIX_CHARS: usize = 4;
+
+/// 生成 `首 6 位...末 4 位` 形式的密钥提示
+///
+/// 密钥短于 16 个字符时露出的比例过高，不生成提示
+fn secret_hint(secret: &str) -> Option<String> {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() < 16 {
+        return None;
+    }
+    let suffix_start = chars.len() - SECRET_HINT_SUFFIX_CHARS;
+    let prefix: String = chars[..SECRET_HINT_PREFIX_CHARS].iter().collect();
+    let suffix: String = chars[suffix_start..].iter().collect();
+    Some(format!("{}...{}", prefix, suffix))
+}
+
+/// 构建凭据的密钥提示信息
+fn secret_hints(credentials: &KiroCredentials) -> CredentialSecretHints {
+    let refresh_token = credentials.refresh_token.as_deref();
+    CredentialSecretHints {
+        refresh_token_hint: refresh_token.and_then(secret_hint),
+        refresh_token_length: refresh_token.map(|t| t.chars().count()),
+        access_token_length: credentials
+            .access_token
+            .as_deref()
+            .map(|t| t.chars().count()),
+        client_secret_hash: credentials.client_secret.as_deref().map(sha256_hex),
+        has_proxy_password: credentials.proxy_password.is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::usage_limits::UsageLimitsResponse;
+    use crate::kiro::token_manager::{CallContext, FailureKind};
+
+    fn usage_limits(next_date_reset: f64, current: f64, limit: f64) -> UsageLimitsResponse {
+        serde_json::from_value(serde_json::json!({
+            "nextDateReset": next_date_reset,
+            "subscriptionInfo": { "subscriptionTitle": "KIRO PRO" },
+            "usageBreakdownList": [{
+                "currentUsageWithPrecision": current,
+                "usageLimitWithPrecision": limit
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_build_balance_derived_fields() {
+        let now = 1_700_000_000.0;
+        let usage = usage_limits(now + 10.0 * SECS_PER_DAY, 500.0, 1000.0);
+
+        let balance =
+            AdminService::build_balance(1, &UsageSnapshot::from_usage_limits(&usage, None), now);
+        assert_eq!(balance.remaining, 500.0);
+        assert_eq!(balance.usage_percentage, 50.0);
+
+        let days_until_reset = balance.days_until_reset.unwrap();
+        assert!((days_until_reset - 10.0).abs() < 1e-9);
+        let daily_budget = balance.daily_budget_remaining.unwrap();
+        assert!((daily_budget - balance.remaining / 10.0).abs() < 1e-9);
+        // 已过 20 天，日均使用 500 / 20
+        assert!((balance.usage_trend.unwrap() - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_build_balance_without_reset_date() {
+        let mut usage = usage_limits(0.0, 10.0, 100.0);
+        usage.next_date_reset = None;
+
+        let balance = AdminService::build_balance(
+            1,
+            &UsageSnapshot::from_usage_limits(&usage, None),
+            1_700_000_000.0,
+        );
+        assert!(balance.days_until_reset.is_none());
+        assert!(balance.usage_trend.is_none());
+        assert!(balance.daily_budget_remaining.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_balance_served_from_manager_cache() {
+        let dir = std::env::temp_dir().join(format!("kiro-admin-balance-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = Utc::now().timestamp() as f64;
+        // 旧版 AdminService 写入的缓存格式
+        let legacy = serde_json::json!({
+            "1": {
+                "cached_at": now,
+                "data": {
+                    "id": 1,
+                    "subscriptionTitle": "KIRO PRO",
+                    "currentUsage": 10.0,
+                    "usageLimit": 100.0,
+                    "remaining": 90.0,
+                    "usagePercentage": 10.0,
+                    "nextResetAt": null
+                }
+            }
+        });
+        std::fs::write(dir.join("kiro_balance_cache.json"), legacy.to_string()).unwrap();
+
+        let credentials = KiroCredentials {
+            id: Some(1),
+            refresh_token: Some("a".repeat(150)),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            crate::model::config::Config::default(),
+            vec![credentials],
+            None,
+            Some(dir.join("credentials.json")),
+            true,
+        )
+        .unwrap();
+        let manager = Arc::new(manager);
+        let service = AdminService::new(manager.clone());
+
+        let balance = service.get_balance(1).await.unwrap().data;
+        assert_eq!(balance.subscription_title.as_deref(), Some("KIRO PRO"));
+        assert_eq!(balance.current_usage, 10.0);
+        assert_eq!(balance.remaining, 90.0);
+        assert_eq!(balance.usage_percentage, 10.0);
+
+        // 成功请求后余额在本地乐观更新
+        manager.report_success(&CallContext::for_test(1), 0);
+        let balance = service.get_balance(1).await.unwrap().data;
+        assert_eq!(balance.current_usage, 11.0);
+        assert_eq!(balance.remaining, 89.0);
+
+        // 等待后台统计落盘完成后再清理目录
+        for _ in 0..100 {
+            if dir.join("kiro_stats.json").exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_health_score_with_exhausted_quota_and_failures() {
+        let credentials = KiroCredentials {
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + chrono::Duration::minutes(50)).to_rfc3339()),
+            ..Default::default()
+        };
+        let mut config = crate::model::config::Config::default();
+        config.auth_failure_threshold = 3;
+        let manager = MultiTokenManager::new(config, vec![credentials], None, None, false).unwrap();
+        let manager = Arc::new(manager);
+        let service = AdminService::new(manager.clone());
+
+        let healthy = service.credential_health(1).unwrap();
+        assert!(healthy.health_score > 0.9);
+
+        // 额度用满（100%）且连续失败 2 次
+        manager.store_balance_for_test(1, 100.0, 100.0);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+
+        let health = service.credential_health(1).unwrap();
+        assert!(health.health_score < 0.5, "{:?}", health);
+        assert_eq!(health.factors.quota_factor, 0.0);
+        assert_eq!(health.factors.success_rate_factor, 0.0);
+        assert_eq!(
+            service.get_all_credentials().credentials[0].health_score,
+            health.health_score
+        );
+        assert!(matches!(
+            service.credential_health(9),
+            Err(AdminServiceError::NotFound { id: 9 })
+        ));
+    }
+
+    /// (email, authMethod, priority)
+    fn listing_service() -> (Arc<MultiTokenManager>, AdminService) {
+        let specs = [
+            (Some("Alice@Example.com"), "social", 2),
+            (Some("bob@example.com"), "idc", 0),
+            (Some("carol@test.org"), "social", 1),
+            (None, "idc", 3),
+            (Some("alice.backup@example.com"), "social", 4),
+        ];
+        let credentials = specs
+            .into_iter()
+            .map(|(email, auth_method, priority)| KiroCredentials {
+                email: email.map(str::to_string),
+                auth_method: Some(auth_method.to_string()),
+                priority,
+                ..Default::default()
+            })
+            .collect();
+        let manager = Arc::new(
+            MultiTokenManager::new(
+                crate::model::config::Config::default(),
+                credentials,
+                None,
+                None,
+                false,
+            )
+            .unwrap(),
+        );
+        let service = AdminService::new(manager.clone());
+        (manager, service)
+    }
+
+    fn ids(response: &CredentialsStatusResponse) -> Vec<u64> {
+        response.credentials.iter().map(|c| c.id).collect()
+    }
+
+    fn query(params: &str) -> CredentialsQuery {
+        let uri: axum::http::Uri = format!("/credentials?{}", params).parse().unwrap();
+        axum::extract::Query::try_from_uri(&uri).unwrap().0
+    }
+
+    #[test]
+    fn test_list_credentials_without_params_is_unpaginated() {
+        let (_, service) = listing_service();
+        let response = service.list_credentials(&CredentialsQuery::default());
+        assert_eq!(ids(&response), vec![2, 3, 1, 4, 5]);
+        assert!(response.pagination.is_none());
+    }
+
+    #[test]
+    fn test_list_credentials_filters() {
+        let (manager, service) = listing_service();
+        manager.set_disabled(3, true).unwrap();
+
+        let filtered = |params: &str| ids(&service.list_credentials(&query(params)));
+        assert_eq!(filtered("search=ALICE"), vec![1, 5]);
+        assert_eq!(filtered("disabled=true"), vec![3]);
+        assert_eq!(filtered("disabled=false"), vec![2, 1, 4, 5]);
+        assert_eq!(filtered("authMethod=IDC"), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_list_credentials_sorting() {
+        let (manager, service) = listing_service();
+        manager.report_success(&CallContext::for_test(4), 0);
+        manager.report_success(&CallContext::for_test(4), 0);
+        manager.report_success(&CallContext::for_test(1), 0);
+        manager.store_balance_for_test(3, 10.0, 100.0);
+        manager.store_balance_for_test(5, 50.0, 100.0);
+
+        let sorted = |params: &str| ids(&service.list_credentials(&query(params)));
+        // 最近使用的在前，从未使用的按优先级排在最后
+        assert_eq!(sorted("sort=lastUsedAt"), vec![1, 4, 2, 3, 5]);
+        assert_eq!(sorted("sort=successCount"), vec![4, 1, 2, 3, 5]);
+        // 无缓存余额的排在最后（#1、#4 的余额由成功请求乐观扣减，但没有缓存条目）
+        assert_eq!(sorted("sort=remaining"), vec![3, 5, 2, 1, 4]);
+        assert_eq!(sorted("sort=priority&order=desc"), vec![5, 4, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_list_credentials_pagination_and_combined_filters() {
+        let (manager, service) = listing_service();
+        manager.set_disabled(1, true).unwrap();
+
+        let response = service.list_credentials(&query("page=2&pageSize=2"));
+        assert_eq!(ids(&response), vec![1, 4]);
+        let pagination = response.pagination.unwrap();
+        assert_eq!(
+            (pagination.filtered, pagination.page, pagination.page_size),
+            (5, 2, 2)
+        );
+        assert_eq!(pagination.total_pages, 3);
+        assert_eq!(response.total, 5);
+
+        // 每页数量有上限，超出范围的页为空
+        let response = service.list_credentials(&query("pageSize=1000&page=9"));
+        assert!(response.credentials.is_empty());
+        let page_size = response.pagination.unwrap().page_size;
+        assert_eq!(page_size, MAX_CREDENTIALS_PAGE_SIZE);
+
+        let response = service.list_credentials(&query(
+            "search=example&authMethod=social&disabled=false&sort=priority&pageSize=1",
+        ));
+        assert_eq!(ids(&response), vec![5]);
+        let pagination = response.pagination.unwrap();
+        assert_eq!((pagination.filtered, pagination.total_pages), (1, 1));
+    }
+
+    #[test]
+    fn test_secret_hint_format() {
+        assert_eq!(
+            secret_hint("aorAAAAAGlHHsKbO9xZq").as_deref(),
+            Some("aorAAA...9xZq")
+        );
+        assert_eq!(secret_hint("0123456789abcde"), None);
+        assert_eq!(
+            secret_hint("令牌令牌令牌0123456789").as_deref(),
+            Some("令牌令牌令牌...6789")
+        );
+    }
+
+    #[test]
+    fn test_credential_detail_never_exposes_secrets() {
+        let refresh_token = format!("aorAAAAA{}Zq9x", "s".repeat(120));
+        let client_secret = "client-secret-value-1234567890";
+        let credentials = KiroCredentials {
+            refresh_token: Some(refresh_token.clone()),
+            access_token: Some("access-token-value".to_string()),
+            auth_method: Some("idc".to_string()),
+            client_id: Some("client-id-public".to_string()),
+            client_secret: Some(client_secret.to_string()),
+            machine_id: Some("m".repeat(64)),
+            proxy_url: Some("http://proxy:8080".to_string()),
+            proxy_password: Some("proxy-password-value".to_string()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            crate::model::config::Config::default(),
+            vec![credentials],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let service = AdminService::new(Arc::new(manager));
+
+        let detail = service.get_credential(1).unwrap();
+        assert_eq!(detail.status.id, 1);
+        assert_eq!(detail.client_id.as_deref(), Some("client-id-public"));
+        assert_eq!(detail.machine_id, Some("m".repeat(64)));
+        assert_eq!(
+            detail.secrets.refresh_token_hint.as_deref(),
+            Some("aorAAA...Zq9x")
+        );
+        assert_eq!(detail.secrets.refresh_token_length, Some(132));
+        assert_eq!(detail.secrets.access_token_length, Some(18));
+        assert_eq!(
+            detail.secrets.client_secret_hash,
+            Some(sha256_hex(client_secret))
+        );
+        assert!(detail.secrets.has_proxy_password);
+
+        let json = serde_json::to_string(&detail).unwrap();
+        for secret in [
+            refresh_token.as_str(),
+            client_secret,
+            "access-token-value",
+            "proxy-password-value",
+        ] {
+            assert!(!json.contains(secret), "响应中包含密钥: {}", secret);
+        }
+
+        assert!(matches!(
+            service.get_credential(9),
+            Err(AdminServiceError::NotFound { id: 9 })
+        ));
+    }
+}
@@ -1,7 +1,20 @@
 //! Admin API 类型定义
 
+use std::borrow::Cow;
+
 use serde::{Deserialize, Serialize};
 
+use super::AdminApiVersion;
+use crate::anthropic::active_requests::RequestLane;
+use crate::anthropic::rate_limit::RateLimiterState;
+use crate::http_client::PooledClientStats;
+use crate::kiro::model::credentials::MonthlyRequestLimit;
+use crate::kiro::token_manager::{
+    AdminEvent, DisabledReason, FailureCounts, HealthFactors, TokenManagerState,
+};
+use crate::kiro::user_usage::UserUsageSnapshot;
+use crate::model::config::{AutoRecoverMode, RedactionRuleConfig, TextFilterConfig};
+
 // ============ 凭据状态 ============
 
 /// 所有凭据状态响应
@@ -14,8 +27,104 @@ pub struct CredentialsStatusResponse {
     pub available: usize,
     /// 当前活跃凭据 ID
     pub current_id: u64,
+    /// 所有未禁用凭据健康评分的平均值
+    pub fleet_health_score: f64,
+    /// 所有凭据的累计估算费用（未配置 pricing 时省略）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_estimated_cost: Option<f64>,
+    /// 凭据文件在外部被修改且无法自动合并时的警告（冲突解决并成功回写后消失）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_warning: Option<String>,
+    /// 所有凭据均被自动禁用时的自愈策略
+    pub auto_recover_all_disabled: AutoRecoverMode,
+    /// 最近一次自愈时间（RFC3339 格式，自启动以来未自愈时为 null）
+    pub last_self_heal_at: Option<String>,
     /// 各凭据状态列表
     pub credentials: Vec<CredentialStatusItem>,
+    /// 分页信息（请求携带查询参数时存在）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<CredentialsPagination>,
+}
+
+/// 凭据列表分页信息
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsPagination {
+    /// 过滤后的凭据数量
+    pub filtered: usize,
+    /// 当前页码（从 1 开始）
+    pub page: usize,
+    /// 每页数量
+    pub page_size: usize,
+    /// 总页数
+    pub total_pages: usize,
+}
+
+/// 凭据列表排序字段
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CredentialSortKey {
+    /// 优先级（默认升序）
+    #[default]
+    Priority,
+    /// 最后使用时间（默认降序，从未使用的排在最后）
+    LastUsedAt,
+    /// 成功次数（默认降序）
+    SuccessCount,
+    /// 缓存的剩余额度（默认降序，无缓存的排在最后）
+    Remaining,
+}
+
+/// 排序方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// 凭据列表查询参数（均为可选，未提供任何参数时返回完整列表且不含分页信息）
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsQuery {
+    /// 页码（从 1 开始，默认 1）
+    pub page: Option<usize>,
+    /// 每页数量（默认 20，最大 100）
+    pub page_size: Option<usize>,
+    /// 按邮箱子串搜索（忽略大小写）
+    pub search: Option<String>,
+    /// 按禁用状态过滤
+    pub disabled: Option<bool>,
+    /// 按认证方式过滤（忽略大小写）
+    pub auth_method: Option<String>,
+    /// 排序字段
+    pub sort: Option<CredentialSortKey>,
+    /// 排序方向（默认取决于排序字段）
+    pub order: Option<SortOrder>,
+    /// 响应格式（默认 JSON；CSV 导出忽略分页参数）
+    pub format: Option<CredentialsFormat>,
+}
+
+/// 凭据列表响应格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialsFormat {
+    Json,
+    /// CSV 附件（仅导出，不支持从 CSV 导入）
+    Csv,
+}
+
+impl CredentialsQuery {
+    /// 是否未携带任何过滤、排序或分页参数（`format` 不计入）
+    pub fn is_empty(&self) -> bool {
+        self.page.is_none()
+            && self.page_size.is_none()
+            && self.search.is_none()
+            && self.disabled.is_none()
+            && self.auth_method.is_none()
+            && self.sort.is_none()
+            && self.order.is_none()
+    }
 }
 
 /// 单个凭据的状态信息
@@ -28,8 +137,12 @@ pub struct CredentialStatusItem {
     pub priority: u32,
     /// 是否被禁用
     pub disabled: bool,
+    /// 禁用原因（未禁用时为 null）
+    pub disabled_reason: Option<DisabledReason>,
     /// 连续失败次数
     pub failure_count: u32,
+    /// 按类型分类的累计失败次数
+    pub failure_counts: FailureCounts,
     /// 是否为当前活跃凭据
     pub is_current: bool,
     /// Token 过期时间（RFC3339 格式）
@@ -46,11 +159,171 @@ pub struct CredentialStatusItem {
     pub success_count: u64,
     /// 最后一次 API 调用时间（RFC3339 格式）
     pub last_used_at: Option<String>,
+    /// 最近一次成功调用的耗时（毫秒，重启后为 null）
+    pub last_latency_ms: Option<u64>,
     /// 是否配置了凭据级代理
     pub has_proxy: bool,
     /// 代理 URL（用于前端展示）
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy_url: Option<String>,
+    /// 凭据级上游基础 URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_base_url: Option<String>,
+    /// 最近一小时内的 Token 刷新次数
+    pub refresh_attempts_last_hour: usize,
+    /// 刷新端点 429 退避剩余秒数
+    pub refresh_backoff_secs: Option<u64>,
+    /// 刷新失败退避结束时间（RFC3339）
+    pub next_refresh_attempt_at: Option<String>,
+    /// 综合健康评分（0.0 ~ 1.0）
+    pub health_score: f64,
+    /// 累计估算费用（未配置 pricing 时省略）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+    /// 当月成功请求次数（本地计数）
+    pub monthly_requests: u64,
+    /// 本地月度请求上限（未配置时省略）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_request_limit: Option<MonthlyRequestLimit>,
+    /// 保留的刷新记录中 refreshToken 轮换的次数
+    pub refresh_rotations: usize,
+    /// 最近一次 refreshToken 轮换后未能回写凭据文件（新令牌仅在内存中）
+    pub rotation_unpersisted: bool,
+    /// 因额度用尽被禁用时的使用比例（1.0 表示 100%，未因额度用尽禁用时为 null）
+    pub quota_exhausted_at_percentage: Option<f64>,
+    /// v2 新增字段（v1 响应中省略）
+    #[serde(flatten)]
+    pub v2: Option<CredentialStatusV2Fields>,
+}
+
+/// 仅在 Admin API v2 中返回的凭据字段
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStatusV2Fields {
+    /// 订阅类型
+    pub subscription_title: Option<String>,
+}
+
+impl CredentialsStatusResponse {
+    /// 按协商的 API 版本裁剪响应字段
+    pub fn for_version(mut self, version: AdminApiVersion) -> Self {
+        if version < AdminApiVersion::V2 {
+            for credential in &mut self.credentials {
+                credential.v2 = None;
+            }
+        }
+        self
+    }
+
+    /// 将凭据列表序列化为 CSV（首行为列名，按列表顺序输出）
+    pub fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer.write_record(CREDENTIALS_CSV_COLUMNS)?;
+        for c in &self.credentials {
+            writer.serialize(CredentialCsvRow {
+                id: c.id,
+                priority: c.priority,
+                disabled: c.disabled,
+                auth_method: c.auth_method.as_deref().map(csv_safe_cell),
+                email: c.email.as_deref().map(csv_safe_cell),
+                has_profile_arn: c.has_profile_arn,
+                expires_at: c.expires_at.as_deref(),
+                success_count: c.success_count,
+                failure_count: c.failure_count,
+                last_used_at: c.last_used_at.as_deref(),
+            })?;
+        }
+        Ok(writer.into_inner()?)
+    }
+}
+
+/// 以 `=`、`+`、`-`、`@` 开头的单元格加 `'` 前缀，防止在表格软件中被当作公式执行
+fn csv_safe_cell(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// 凭据列表 CSV 导出的列名（与 [`CredentialCsvRow`] 字段顺序一致）
+const CREDENTIALS_CSV_COLUMNS: &[&str] = &[
+    "id",
+    "priority",
+    "disabled",
+    "auth_method",
+    "email",
+    "has_profile_arn",
+    "expires_at",
+    "success_count",
+    "failure_count",
+    "last_used_at",
+];
+
+/// 凭据列表 CSV 导出的一行（不包含任何 Token 字段）
+#[derive(Debug, Serialize)]
+struct CredentialCsvRow<'a> {
+    id: u64,
+    priority: u32,
+    disabled: bool,
+    auth_method: Option<Cow<'a, str>>,
+    email: Option<Cow<'a, str>>,
+    has_profile_arn: bool,
+    expires_at: Option<&'a str>,
+    success_count: u64,
+    failure_count: u32,
+    last_used_at: Option<&'a str>,
+}
+
+/// 单个凭据详情（密钥类字段只返回提示信息，不返回原文）
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialDetailResponse {
+    /// 与凭据列表相同的状态字段
+    #[serde(flatten)]
+    pub status: CredentialStatusItem,
+    /// Profile ARN
+    pub profile_arn: Option<String>,
+    /// 凭据级 Region
+    pub region: Option<String>,
+    /// 凭据级 Auth Region
+    pub auth_region: Option<String>,
+    /// 凭据级 API Region
+    pub api_region: Option<String>,
+    /// OIDC Client ID（非敏感信息，完整返回）
+    pub client_id: Option<String>,
+    /// 设备指纹
+    pub machine_id: Option<String>,
+    /// 密钥提示信息
+    pub secrets: CredentialSecretHints,
+}
+
+/// 凭据密钥提示信息（用于核对凭据持有的是哪个 Token）
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialSecretHints {
+    /// refreshToken 的首 6 位与末 4 位（如 `aorAAA...x9Zq`，过短时为 None）
+    pub refresh_token_hint: Option<String>,
+    /// refreshToken 长度（字符数）
+    pub refresh_token_length: Option<usize>,
+    /// accessToken 长度（字符数）
+    pub access_token_length: Option<usize>,
+    /// clientSecret 的 SHA-256 哈希
+    pub client_secret_hash: Option<String>,
+    /// 是否配置了代理密码
+    pub has_proxy_password: bool,
+}
+
+impl CredentialDetailResponse {
+    /// 按协商的 API 版本裁剪响应字段
+    pub fn for_version(mut self, version: AdminApiVersion) -> Self {
+        if version < AdminApiVersion::V2 {
+            self.status.v2 = None;
+        }
+        self
+    }
 }
 
 // ============ 操作请求 ============
@@ -71,6 +344,49 @@ pub struct SetPriorityRequest {
     pub priority: u32,
 }
 
+/// 重排凭据优先级请求
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReorderCredentialsRequest {
+    /// 按期望顺序排列的全部凭据 ID（第一个优先级最高）
+    pub ids: Vec<u64>,
+}
+
+/// 单个凭据的优先级调整结果
+#[derive(Debug, Serialize)]
+pub struct PriorityReassignment {
+    /// 凭据 ID
+    pub id: u64,
+    /// 调整前的优先级
+    pub old_priority: u32,
+    /// 调整后的优先级
+    pub new_priority: u32,
+}
+
+/// 优先级压缩响应（按新优先级排列）
+#[derive(Debug, Serialize)]
+pub struct RebalancePrioritiesResponse {
+    pub reassignments: Vec<PriorityReassignment>,
+}
+
+/// 共享同一 refreshToken 的一组凭据
+#[derive(Debug, Serialize)]
+pub struct DuplicateCredentialGroup {
+    /// refreshToken 的 SHA-256 哈希
+    pub refresh_token_hash: String,
+    /// 共享该 refreshToken 的凭据 ID（升序）
+    pub credential_ids: Vec<u64>,
+}
+
+/// 重复凭据检测响应
+#[derive(Debug, Serialize)]
+pub struct CredentialDuplicatesResponse {
+    /// 至少包含两个凭据的分组（按首个凭据 ID 排列）
+    pub duplicate_groups: Vec<DuplicateCredentialGroup>,
+    /// 没有 refreshToken、无法检测重复的凭据 ID
+    pub unchecked_ids: Vec<u64>,
+}
+
 /// 添加凭据请求
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -117,6 +433,12 @@ pub struct AddCredentialRequest {
 
     /// 凭据级代理认证密码（可选）
     pub proxy_password: Option<String>,
+
+    /// 凭据级上游基础 URL（可选，必须以 https:// 开头且不含查询参数）
+    pub upstream_base_url: Option<String>,
+
+    /// 本地月度请求上限（可选）
+    pub monthly_request_limit: Option<MonthlyRequestLimit>,
 }
 
 fn default_auth_method() -> String {
@@ -124,7 +446,7 @@ fn default_auth_method() -> String {
 }
 
 /// 添加凭据成功响应
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AddCredentialResponse {
     pub success: bool,
@@ -156,6 +478,81 @@ pub struct BalanceResponse {
     pub usage_percentage: f64,
     /// 下次重置时间（Unix 时间戳）
     pub next_reset_at: Option<f64>,
+    /// 距离下次重置的天数
+    pub days_until_reset: Option<f64>,
+    /// 本计费周期内的日均使用量
+    pub usage_trend: Option<f64>,
+    /// 剩余额度按剩余天数平摊后的每日可用量
+    pub daily_budget_remaining: Option<f64>,
+}
+
+/// 余额查询结果及缓存元信息（元信息通过响应头返回）
+#[derive(Debug, Clone)]
+pub struct BalanceWithMeta {
+    /// 余额数据
+    pub data: BalanceResponse,
+    /// 是否命中缓存
+    pub cache_hit: bool,
+    /// 距最近一次真实拉取的秒数（刚从上游拉取时为 0）
+    pub cache_age_secs: f64,
+}
+
+// ============ 刷新记录 ============
+
+/// 单次 Token 刷新记录
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshAttemptSnapshot {
+    /// 刷新开始时间（RFC3339 格式）
+    pub attempted_at: String,
+    /// 是否刷新成功
+    pub succeeded: bool,
+    /// 刷新耗时（毫秒）
+    pub duration_ms: u64,
+    /// 失败原因
+    pub error: Option<String>,
+}
+
+// ============ 健康评分 ============
+
+/// 凭据健康评分响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialHealthResponse {
+    /// 凭据 ID
+    pub id: u64,
+    /// 综合健康评分（0.0 ~ 1.0）
+    pub health_score: f64,
+    /// 各项因子（失败次数 40%、Token 新鲜度 30%、近期成功率 20%、额度 10%）
+    pub factors: HealthFactors,
+}
+
+// ============ 连接诊断 ============
+
+/// 上游连接诊断响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDiagnosticsResponse {
+    /// 按代理配置缓存的 Client 列表
+    pub clients: Vec<PooledClientStats>,
+}
+
+// ============ 用户用量 ============
+
+/// 用户用量列表响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsageListResponse {
+    /// 按用户标识排序的用量统计
+    pub users: Vec<UserUsageSnapshot>,
+}
+
+/// 管理事件列表响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminEventListResponse {
+    /// 最近的管理事件（按时间先后排列）
+    pub events: Vec<AdminEvent>,
 }
 
 // ============ 负载均衡配置 ============
@@ -164,7 +561,7 @@ pub struct BalanceResponse {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LoadBalancingModeResponse {
-    /// 当前模式（"priority" 或 "balanced"）
+    /// 当前模式（"priority"、"balanced" 或 "reset-aware"）
     pub mode: String,
 }
 
@@ -172,10 +569,129 @@ pub struct LoadBalancingModeResponse {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SetLoadBalancingModeRequest {
-    /// 模式（"priority" 或 "balanced"）
+    /// 模式（"priority"、"balanced" 或 "reset-aware"）
     pub mode: String,
 }
 
+// ============ 响应文本后处理 ============
+
+/// 过滤器试运行请求
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestFiltersRequest {
+    /// 样例文本
+    pub text: String,
+    /// 待测试的过滤器列表（不提供时使用当前配置的 postProcessing.filters）
+    #[serde(default)]
+    pub filters: Option<Vec<TextFilterConfig>>,
+    /// 按该字节数切分后逐段送入，模拟流式响应（不提供时整段处理）
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+}
+
+/// 过滤器试运行响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestFiltersResponse {
+    /// 过滤后的文本
+    pub output: String,
+    /// 输出是否与输入不同
+    pub changed: bool,
+}
+
+// ============ 出站提示词脱敏 ============
+
+/// 脱敏规则试运行请求
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRedactionRequest {
+    /// 样例文本
+    pub text: String,
+    /// 待测试的规则列表（不提供时使用当前配置的 redaction.rules）
+    #[serde(default)]
+    pub rules: Option<Vec<RedactionRuleConfig>>,
+    /// 是否按严格模式判定 blocking 规则（不提供时使用当前配置的 redaction.strict）
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
+/// 单条规则的命中次数
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionCount {
+    /// 规则名
+    pub rule: String,
+    /// 命中次数
+    pub count: usize,
+}
+
+/// 脱敏规则试运行响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRedactionResponse {
+    /// 脱敏后的文本
+    pub output: String,
+    /// 输出是否与输入不同
+    pub changed: bool,
+    /// 各规则的命中次数（按配置顺序，仅包含命中过的规则）
+    pub redactions: Vec<RedactionCount>,
+    /// 严格模式下命中的 blocking 规则（非空时实际请求会被拒绝）
+    pub blocked_by: Vec<String>,
+}
+
+// ============ 进行中的请求 ============
+
+/// 进行中的消息请求
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveRequestItem {
+    /// 请求 ID（与 `request-id` 响应头一致）
+    pub id: String,
+    pub model: String,
+    /// 处理请求的凭据 ID（上游响应前未知）
+    pub credential_id: Option<u64>,
+    /// 开始时间（RFC3339）
+    pub started_at: String,
+    /// 已运行时长（毫秒）
+    pub elapsed_ms: i64,
+    /// 请求通道（`interactive` / `longRunning`）
+    pub lane: RequestLane,
+    pub stream: bool,
+    /// 单请求上游超时（秒，未携带 `x-kiro-timeout-secs` 时为 null）
+    pub timeout_secs: Option<u64>,
+    /// 是否已被取消（等待处理器结束）
+    pub cancelled: bool,
+}
+
+/// 进行中的消息请求列表响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveRequestsResponse {
+    pub total: usize,
+    /// 按开始时间排序
+    pub requests: Vec<ActiveRequestItem>,
+}
+
+// ============ 运行时状态迁移 ============
+
+/// 运行时状态导出格式版本
+pub const RUNTIME_STATE_VERSION: u32 = 1;
+
+/// 可迁移的运行时状态（不含密钥，用于蓝绿部署时预热新实例）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeState {
+    /// 导出格式版本
+    pub version: u32,
+    /// 导出时间（RFC3339 格式）
+    pub exported_at: String,
+    /// 凭据选择、失败计数与刷新退避等状态
+    pub token_manager: TokenManagerState,
+    /// 消息请求速率限制器状态（未配置 rateLimitCapacity 时为 None）
+    #[serde(default)]
+    pub rate_limiter: Option<RateLimiterState>,
+}
+
 // ============ 通用响应 ============
 
 /// 操作成功响应
@@ -236,4 +752,8 @@ impl AdminErrorResponse {
     pub fn internal_error(message: impl Into<String>) -> Self {
         Self::new("internal_error", message)
     }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new("conflict", message)
+    }
 }
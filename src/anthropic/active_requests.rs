@@ -0,0 +1,300 @@
+//! 进行中的消息请求登记
+//!
+//! 消息请求在调用上游前登记到 [`ActiveRequests`]，Admin API 据此列出进行中的请求
+//! （`GET /api/admin/requests/active`）并按 ID 取消（`POST /api/admin/requests/{id}/cancel`）。
+//!
+//! 登记项由 [`ActiveRequestGuard`] 持有，guard 被 drop 时移除：非流式请求在响应生成后移除，
+//! 流式请求随 SSE 流结束、被取消或客户端断开导致流被丢弃时移除。
+//! 取消只发出信号，由持有 guard 的处理器丢弃上游 future，流式响应以 SSE `error` 事件结束。
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, stream};
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::kiro::provider::UPSTREAM_TIMEOUT_SECS;
+
+use super::stream::error_sse_event;
+
+/// 请求被管理员取消时返回给客户端的错误信息
+pub const CANCELLED_MESSAGE: &str = "Request was cancelled by an administrator";
+
+/// 请求所在的通道
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RequestLane {
+    /// 使用默认上游超时（或更短的单请求超时）的普通请求
+    Interactive,
+    /// 通过 `x-kiro-timeout-secs` 放宽到默认上游超时以上的长耗时请求
+    LongRunning,
+}
+
+impl RequestLane {
+    /// 按单请求超时划分通道
+    pub fn for_timeout(timeout: Option<Duration>) -> Self {
+        match timeout {
+            Some(timeout) if timeout > Duration::from_secs(UPSTREAM_TIMEOUT_SECS) => {
+                Self::LongRunning
+            }
+            _ => Self::Interactive,
+        }
+    }
+}
+
+/// 进行中请求的快照
+#[derive(Debug, Clone)]
+pub struct ActiveRequest {
+    /// 请求 ID（与 `request-id` 响应头一致）
+    pub id: String,
+    /// 客户端请求的模型
+    pub model: String,
+    /// 处理请求的凭据 ID（上游响应前未知，A/B 路由时为指定的凭据）
+    pub credential_id: Option<u64>,
+    /// 登记时间
+    pub started_at: DateTime<Utc>,
+    /// 请求通道
+    pub lane: RequestLane,
+    /// 是否为流式请求
+    pub stream: bool,
+    /// 单请求上游超时（未携带 `x-kiro-timeout-secs` 时为 None）
+    pub timeout: Option<Duration>,
+    /// 是否已被取消（等待处理器结束）
+    pub cancelled: bool,
+}
+
+struct Entry {
+    /// 登记序号（区分同 ID 的先后登记）
+    seq: u64,
+    request: ActiveRequest,
+    cancel: watch::Sender<bool>,
+}
+
+/// 进行中请求的登记表（Anthropic API 与 Admin API 共享）
+#[derive(Default)]
+pub struct ActiveRequests {
+    entries: Mutex<HashMap<String, Entry>>,
+    next_seq: AtomicU64,
+}
+
+impl ActiveRequests {
+    /// 登记请求，返回持有登记项的 guard
+    ///
+    /// ID 已存在时（客户端复用请求 ID）替换旧登记项，旧 guard 的 drop 不会移除新登记项
+    pub fn register(self: &Arc<Self>, request: ActiveRequest) -> ActiveRequestGuard {
+        let (cancel, cancelled) = watch::channel(false);
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let id = request.id.clone();
+        self.entries.lock().insert(
+            id.clone(),
+            Entry {
+                seq,
+                request,
+                cancel,
+            },
+        );
+        ActiveRequestGuard {
+            registry: self.clone(),
+            id,
+            seq,
+            cancelled,
+        }
+    }
+
+    /// 按登记时间排序的进行中请求
+    pub fn list(&self) -> Vec<ActiveRequest> {
+        let mut requests: Vec<ActiveRequest> = self
+            .entries
+            .lock()
+            .values()
+            .map(|e| e.request.clone())
+            .collect();
+        requests.sort_by_key(|r| r.started_at);
+        requests
+    }
+
+    /// 取消指定请求，请求不存在（或已结束）时返回 false
+    pub fn cancel(&self, id: &str) -> bool {
+        let mut entries = self.entries.lock();
+        let Some(entry) = entries.get_mut(id) else {
+            return false;
+        };
+        entry.request.cancelled = true;
+        entry.cancel.send_replace(true);
+        true
+    }
+
+    /// 登记中的请求数
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+}
+
+/// 登记项守卫（drop 时移除登记项）
+pub struct ActiveRequestGuard {
+    registry: Arc<ActiveRequests>,
+    id: String,
+    seq: u64,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl ActiveRequestGuard {
+    /// 记录处理请求的凭据
+    pub fn set_credential(&self, credential_id: u64) {
+        let mut entries = self.registry.entries.lock();
+        if let Some(entry) = entries.get_mut(&self.id).filter(|e| e.seq == self.seq) {
+            entry.request.credential_id = Some(credential_id);
+        }
+    }
+
+    /// 等待请求被取消（未被取消时永不完成）
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.clone();
+        if cancelled.wait_for(|c| *c).await.is_err() {
+            // 登记项已被同 ID 的新请求替换，本请求不会再收到取消信号
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        let mut entries = self.registry.entries.lock();
+        // 仅移除自己的登记项（不移除同 ID 的新登记项）
+        if entries.get(&self.id).is_some_and(|e| e.seq == self.seq) {
+            entries.remove(&self.id);
+        }
+    }
+}
+
+/// 取消时发送给客户端的最终 SSE 事件
+fn cancelled_sse() -> Bytes {
+    Bytes::from(error_sse_event("api_error", CANCELLED_MESSAGE).to_sse_string())
+}
+
+/// 在流结束（或被丢弃）前持有登记项；请求被取消时丢弃原始流（连同上游响应）并以 SSE `error` 事件结束
+pub fn until_cancelled<S>(
+    stream: S,
+    guard: ActiveRequestGuard,
+) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    S: Stream<Item = Result<Bytes, Infallible>>,
+{
+    stream::unfold(
+        (Box::pin(stream), guard, false),
+        |(mut stream, guard, finished)| async move {
+            if finished {
+                return None;
+            }
+            tokio::select! {
+                item = stream.next() => item.map(|item| (item, (stream, guard, false))),
+                _ = guard.cancelled() => {
+                    tracing::info!("请求已被管理员取消");
+                    Some((Ok(cancelled_sse()), (stream, guard, true)))
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: &str) -> ActiveRequest {
+        ActiveRequest {
+            id: id.to_string(),
+            model: "claude-sonnet-4".to_string(),
+            credential_id: None,
+            started_at: Utc::now(),
+            lane: RequestLane::Interactive,
+            stream: true,
+            timeout: None,
+            cancelled: false,
+        }
+    }
+
+    #[test]
+    fn test_guard_drop_removes_entry() {
+        let registry = Arc::new(ActiveRequests::default());
+        let guard = registry.register(request("req_1"));
+        guard.set_credential(7);
+
+        let listed = registry.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, "req_1");
+        assert_eq!(listed[0].credential_id, Some(7));
+
+        drop(guard);
+        assert_eq!(registry.len(), 0);
+        assert!(!registry.cancel("req_1"));
+    }
+
+    #[test]
+    fn test_stale_guard_keeps_replacement_entry() {
+        let registry = Arc::new(ActiveRequests::default());
+        let stale = registry.register(request("req_1"));
+        let current = registry.register(request("req_1"));
+
+        drop(stale);
+        assert_eq!(registry.len(), 1);
+        drop(current);
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[test]
+    fn test_lane_for_timeout() {
+        assert_eq!(RequestLane::for_timeout(None), RequestLane::Interactive);
+        assert_eq!(
+            RequestLane::for_timeout(Some(Duration::from_secs(30))),
+            RequestLane::Interactive
+        );
+        assert_eq!(
+            RequestLane::for_timeout(Some(Duration::from_secs(UPSTREAM_TIMEOUT_SECS + 1))),
+            RequestLane::LongRunning
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_ends_stream_with_error_event() {
+        let registry = Arc::new(ActiveRequests::default());
+        let guard = registry.register(request("req_1"));
+        let upstream =
+            stream::iter([Ok(Bytes::from_static(b"event: ping\n\n"))]).chain(stream::pending());
+        let mut stream = Box::pin(until_cancelled(upstream, guard));
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            Bytes::from_static(b"event: ping\n\n")
+        );
+        assert!(registry.cancel("req_1"));
+        assert!(registry.list()[0].cancelled);
+
+        let last = stream.next().await.unwrap().unwrap();
+        let last = String::from_utf8(last.to_vec()).unwrap();
+        assert!(last.starts_with("event: error\n"), "{}", last);
+        assert!(last.contains(CANCELLED_MESSAGE), "{}", last);
+        assert!(stream.next().await.is_none());
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_stream_removes_entry() {
+        let registry = Arc::new(ActiveRequests::default());
+        let guard = registry.register(request("req_1"));
+        let stream = until_cancelled(stream::pending::<Result<Bytes, Infallible>>(), guard);
+
+        assert_eq!(registry.len(), 1);
+        // 客户端断开：axum 丢弃响应体流
+        drop(stream);
+        assert_eq!(registry.len(), 0);
+    }
+}
@@ -0,0 +1,204 @@
+//! anthropic-beta 请求头处理
+//!
+//! 客户端通过 `anthropic-beta` 请求头（逗号分隔，可出现多次）声明需要的 beta 特性：
+//! - 命中 `rejectBetas` 的 beta：返回 400 `invalid_request_error`，避免静默产生与预期不符的行为
+//! - 命中 `passthroughBetas` 的 beta：启用对应的等效行为，并通过响应头 `anthropic-beta` 回显
+//! - 其他 beta：仅记录日志后忽略
+//!
+//! 目前实现的等效行为：
+//! - `prompt-caching-2024-07-31`：响应 usage 中补充 `cache_creation_input_tokens`
+//!   与 `cache_read_input_tokens`（Kiro 不报告缓存命中，恒为 0）
+
+use axum::http::{HeaderMap, HeaderValue};
+
+use crate::model::config::Config;
+
+/// beta 特性请求头（请求与响应共用）
+pub const ANTHROPIC_BETA_HEADER: &str = "anthropic-beta";
+
+/// 已知的 anthropic-beta 特性
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnthropicBeta {
+    /// `prompt-caching-2024-07-31`
+    PromptCaching,
+    /// `token-efficient-tools-2025-02-19`
+    TokenEfficientTools,
+    /// 其他 beta（原样保留，统一为小写）
+    Other(String),
+}
+
+impl AnthropicBeta {
+    fn parse(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "prompt-caching-2024-07-31" => Self::PromptCaching,
+            "token-efficient-tools-2025-02-19" => Self::TokenEfficientTools,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::PromptCaching => "prompt-caching-2024-07-31",
+            Self::TokenEfficientTools => "token-efficient-tools-2025-02-19",
+            Self::Other(name) => name,
+        }
+    }
+
+    /// 是否在给定列表中（忽略大小写）
+    fn is_listed(&self, list: &[String]) -> bool {
+        list.iter()
+            .any(|name| name.trim().eq_ignore_ascii_case(self.as_str()))
+    }
+}
+
+/// 解析请求头中的 beta 列表（按出现顺序去重）
+pub fn parse_betas(headers: &HeaderMap) -> Vec<AnthropicBeta> {
+    let mut betas: Vec<AnthropicBeta> = Vec::new();
+    let values = headers
+        .get_all(ANTHROPIC_BETA_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+    for value in values {
+        let beta = AnthropicBeta::parse(value);
+        if !betas.contains(&beta) {
+            betas.push(beta);
+        }
+    }
+    betas
+}
+
+/// beta 特性的放行/拒绝策略（`passthroughBetas`、`rejectBetas`）
+#[derive(Debug, Clone, Default)]
+pub struct BetaPolicy {
+    passthrough: Vec<String>,
+    reject: Vec<String>,
+}
+
+impl BetaPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            passthrough: config.passthrough_betas.clone(),
+            reject: config.reject_betas.clone(),
+        }
+    }
+
+    /// 按策略处理请求的 beta 列表，命中 rejectBetas 时返回被拒绝的 beta
+    pub fn resolve(&self, requested: Vec<AnthropicBeta>) -> Result<BetaFeatures, Vec<String>> {
+        let rejected: Vec<String> = requested
+            .iter()
+            .filter(|b| b.is_listed(&self.reject))
+            .map(|b| b.as_str().to_string())
+            .collect();
+        if !rejected.is_empty() {
+            return Err(rejected);
+        }
+
+        let (enabled, ignored): (Vec<_>, Vec<_>) = requested
+            .into_iter()
+            .partition(|b| b.is_listed(&self.passthrough));
+        if !ignored.is_empty() {
+            tracing::debug!(
+                betas = ?ignored.iter().map(AnthropicBeta::as_str).collect::<Vec<_>>(),
+                "未在 passthroughBetas 中的 anthropic-beta 已忽略"
+            );
+        }
+        Ok(BetaFeatures { enabled })
+    }
+}
+
+/// 本次请求启用的 beta 特性
+#[derive(Debug, Clone, Default)]
+pub struct BetaFeatures {
+    enabled: Vec<AnthropicBeta>,
+}
+
+impl BetaFeatures {
+    /// 是否在 usage 中补充缓存相关字段
+    pub fn prompt_caching(&self) -> bool {
+        self.enabled.contains(&AnthropicBeta::PromptCaching)
+    }
+
+    /// 回显给客户端的响应头值（无启用的 beta 时为 None）
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        if self.enabled.is_empty() {
+            return None;
+        }
+        let joined = self
+            .enabled
+            .iter()
+            .map(AnthropicBeta::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+        HeaderValue::from_str(&joined).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ANTHROPIC_BETA_HEADER, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn policy(passthrough: &[&str], reject: &[&str]) -> BetaPolicy {
+        BetaPolicy {
+            passthrough: passthrough.iter().map(|s| s.to_string()).collect(),
+            reject: reject.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_comma_separated_and_repeated_headers() {
+        let betas = parse_betas(&headers(&[
+            "Prompt-Caching-2024-07-31, token-efficient-tools-2025-02-19,,",
+            "interleaved-thinking-2025-05-14,prompt-caching-2024-07-31",
+        ]));
+        assert_eq!(
+            betas,
+            vec![
+                AnthropicBeta::PromptCaching,
+                AnthropicBeta::TokenEfficientTools,
+                AnthropicBeta::Other("interleaved-thinking-2025-05-14".to_string()),
+            ]
+        );
+        assert!(parse_betas(&HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_passthrough_enables_only_listed_betas() {
+        let requested = parse_betas(&headers(&[
+            "prompt-caching-2024-07-31,token-efficient-tools-2025-02-19",
+        ]));
+        let features = policy(&["PROMPT-CACHING-2024-07-31"], &[])
+            .resolve(requested.clone())
+            .unwrap();
+        assert!(features.prompt_caching());
+        assert_eq!(
+            features.header_value().unwrap(),
+            "prompt-caching-2024-07-31"
+        );
+
+        let features = policy(&[], &[]).resolve(requested).unwrap();
+        assert!(!features.prompt_caching());
+        assert!(features.header_value().is_none());
+    }
+
+    #[test]
+    fn test_reject_takes_precedence() {
+        let requested = parse_betas(&headers(&[
+            "prompt-caching-2024-07-31,computer-use-2025-01-24",
+        ]));
+        let err = policy(&["prompt-caching-2024-07-31"], &["computer-use-2025-01-24"])
+            .resolve(requested)
+            .unwrap_err();
+        assert_eq!(err, vec!["computer-use-2025-01-24".to_string()]);
+    }
+}
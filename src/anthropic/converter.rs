@@ -6,7 +6,8 @@ use uuid::Uuid;
 
 use crate::kiro::model::requests::conversation::{
     AssistantMessage, ConversationState, CurrentMessage, HistoryAssistantMessage,
-    HistoryUserMessage, KiroImage, Message, UserInputMessage, UserInputMessageContext, UserMessage,
+    HistoryUserMessage, KiroImage, Message, ReasoningContent, UserInputMessage,
+    UserInputMessageContext, UserMessage,
 };
 use crate::kiro::model::requests::tool::{
     InputSchema, Tool, ToolResult, ToolSpecification, ToolUseEntry,
@@ -190,16 +191,28 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
     // 1. 映射模型
     let model_id = map_model(&req.model)
         .ok_or_else(|| ConversionError::UnsupportedModel(req.model.clone()))?;
+    convert_request_with_model_id(req, model_id)
+}
 
+/// 使用指定的模型 ID 将 Anthropic 请求转换为 Kiro 请求（不经 [`map_model`] 映射）
+///
+/// 路由到 OpenAI 协议后端的模型使用原始模型名
+pub fn convert_request_with_model_id(
+    req: &MessagesRequest,
+    model_id: String,
+) -> Result<ConversionResult, ConversionError> {
     // 2. 检查消息列表
     if req.messages.is_empty() {
         return Err(ConversionError::EmptyMessages);
     }
 
-    // 2.5. 预处理 prefill：如果末尾是 assistant，静默丢弃并截断到最后一条 user
-    // Claude 4.x 已弃用 assistant prefill，Kiro API 也不支持
+    // 2.5. 预处理 prefill：如果末尾是 assistant，截断到最后一条 user
+    // Kiro API 不支持 prefill，其中的文本改为附加到当前 user 消息中，由模型接着续写（见 extract_prefill）
+    let prefill = extract_prefill(&req.messages);
     let messages: &[_] = if req.messages.last().is_some_and(|m| m.role != "user") {
-        tracing::info!("检测到末尾 assistant 消息（prefill），静默丢弃");
+        if prefill.is_none() {
+            tracing::info!("检测到末尾 assistant 消息（prefill）但不含文本，静默丢弃");
+        }
         let last_user_idx = req
             .messages
             .iter()
@@ -268,7 +281,16 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
 
     // 12. 构建当前消息
     // 保留文本内容，即使有工具结果也不丢弃用户文本
-    let content = text_content;
+    let content = match &prefill {
+        Some(prefill) => {
+            tracing::info!(
+                prefill_len = prefill.len(),
+                "检测到 assistant prefill，改为续写指令"
+            );
+            format!("{}{}", text_content, prefill_instruction(prefill))
+        }
+        None => text_content,
+    };
 
     let mut user_input = UserInputMessage::new(content, &model_id)
         .with_context(context)
@@ -291,6 +313,33 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
     Ok(ConversionResult { conversation_state })
 }
 
+/// 提取末尾 assistant 消息（prefill）中的文本
+///
+/// 仅取最后一条 user 消息之后的 assistant 消息；不含非空白文本时返回 None
+pub fn extract_prefill(messages: &[super::types::Message]) -> Option<String> {
+    let last_user_idx = messages.iter().rposition(|m| m.role == "user")?;
+    let prefill: String = messages[last_user_idx + 1..]
+        .iter()
+        .filter_map(|m| process_message_content(&m.content).ok())
+        .map(|(text, _, _)| text)
+        .collect();
+    (!prefill.trim().is_empty()).then_some(prefill)
+}
+
+/// 附加到当前 user 消息末尾的续写指令
+///
+/// 要求模型从 prefill 结束处（可能是单词或 JSON 的中间）直接续写，不要重复 prefill；
+/// 模型仍回显 prefill 时由响应侧的 prefill 回显过滤移除
+fn prefill_instruction(prefill: &str) -> String {
+    format!(
+        "\n\n<assistant_prefill>{}</assistant_prefill>\n\
+         Your response has already begun with the exact text inside <assistant_prefill> above. \
+         Continue from exactly where it ends, even if that is in the middle of a word or a JSON value. \
+         Do not repeat any of that text and do not add any preamble.",
+        prefill
+    )
+}
+
 /// 确定聊天触发类型
 /// "AUTO" 模式可能会导致 400 Bad Request 错误
 fn determine_chat_trigger_type(_req: &MessagesRequest) -> String {
@@ -571,6 +620,37 @@ fn has_thinking_tags(content: &str) -> bool {
     content.contains("<thinking_mode>") || content.contains("<max_thinking_length>")
 }
 
+/// 构建注入到历史开头的系统消息（附加分块写入策略与 thinking 标签），无需注入时返回 None
+pub fn build_system_prompt(req: &MessagesRequest) -> Option<String> {
+    // 生成thinking前缀（如果需要）
+    let thinking_prefix = generate_thinking_prefix(req);
+
+    let Some(ref system) = req.system else {
+        // 没有系统消息但有thinking配置，插入新的系统消息
+        return thinking_prefix;
+    };
+
+    let system_content: String = system
+        .iter()
+        .map(|s| s.text.clone())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if system_content.is_empty() {
+        return None;
+    }
+
+    // 追加分块写入策略到系统消息
+    let system_content = format!("{}\n{}", system_content, SYSTEM_CHUNKED_POLICY);
+
+    // 注入thinking标签到系统消息最前面（如果需要且不存在）
+    match thinking_prefix {
+        Some(prefix) if !has_thinking_tags(&system_content) => {
+            Some(format!("{}\n{}", prefix, system_content))
+        }
+        _ => Some(system_content),
+    }
+}
+
 /// 构建历史消息
 ///
 /// # Arguments
@@ -582,42 +662,9 @@ fn has_thinking_tags(content: &str) -> bool {
 fn build_history(req: &MessagesRequest, messages: &[super::types::Message], model_id: &str) -> Result<Vec<Message>, ConversionError> {
     let mut history = Vec::new();
 
-    // 生成thinking前缀（如果需要）
-    let thinking_prefix = generate_thinking_prefix(req);
-
-    // 1. 处理系统消息
-    if let Some(ref system) = req.system {
-        let system_content: String = system
-            .iter()
-            .map(|s| s.text.clone())
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        if !system_content.is_empty() {
-            // 追加分块写入策略到系统消息
-            let system_content = format!("{}\n{}", system_content, SYSTEM_CHUNKED_POLICY);
-
-            // 注入thinking标签到系统消息最前面（如果需要且不存在）
-            let final_content = if let Some(ref prefix) = thinking_prefix {
-                if !has_thinking_tags(&system_content) {
-                    format!("{}\n{}", prefix, system_content)
-                } else {
-                    system_content
-                }
-            } else {
-                system_content
-            };
-
-            // 系统消息作为 user + assistant 配对
-            let user_msg = HistoryUserMessage::new(final_content, model_id);
-            history.push(Message::User(user_msg));
-
-            let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
-            history.push(Message::Assistant(assistant_msg));
-        }
-    } else if let Some(ref prefix) = thinking_prefix {
-        // 没有系统消息但有thinking配置，插入新的系统消息
-        let user_msg = HistoryUserMessage::new(prefix.clone(), model_id);
+    // 1. 处理系统消息：系统消息作为 user + assistant 配对
+    if let Some(system_prompt) = build_system_prompt(req) {
+        let user_msg = HistoryUserMessage::new(system_prompt, model_id);
         history.push(Message::User(user_msg));
 
         let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
@@ -719,6 +766,8 @@ fn convert_assistant_message(
     let mut thinking_content = String::new();
     let mut text_content = String::new();
     let mut tool_uses = Vec::new();
+    // 带签名的 thinking 与 redacted_thinking 块原样回传，签名丢失会导致上游拒绝或降级下一轮
+    let mut reasoning = Vec::new();
 
     match &msg.content {
         serde_json::Value::String(s) => {
@@ -729,8 +778,21 @@ fn convert_assistant_message(
                 if let Ok(block) = serde_json::from_value::<ContentBlock>(item.clone()) {
                     match block.block_type.as_str() {
                         "thinking" => {
+                            // 带签名的 thinking 只通过 reasoningContent 回传，未签名的内联到正文
                             if let Some(thinking) = block.thinking {
-                                thinking_content.push_str(&thinking);
+                                if block.signature.is_some() {
+                                    reasoning.push(ReasoningContent::ReasoningText {
+                                        text: thinking,
+                                        signature: block.signature,
+                                    });
+                                } else {
+                                    thinking_content.push_str(&thinking);
+                                }
+                            }
+                        }
+                        "redacted_thinking" => {
+                            if let Some(data) = block.data {
+                                reasoning.push(ReasoningContent::RedactedContent(data));
                             }
                         }
                         "text" => {
@@ -752,9 +814,9 @@ fn convert_assistant_message(
         _ => {}
     }
 
-    // 组合 thinking 和 text 内容
+    // 组合未签名的 thinking 和 text 内容
     // 格式: <thinking>思考内容</thinking>\n\ntext内容
-    // 注意: Kiro API 要求 content 字段不能为空，当只有 tool_use 时需要占位符
+    // 注意: Kiro API 要求 content 字段不能为空，只有 tool_use 或 reasoningContent 时需要占位符
     let final_content = if !thinking_content.is_empty() {
         if !text_content.is_empty() {
             format!(
@@ -764,7 +826,7 @@ fn convert_assistant_message(
         } else {
             format!("<thinking>{}</thinking>", thinking_content)
         }
-    } else if text_content.is_empty() && !tool_uses.is_empty() {
+    } else if text_content.is_empty() && (!tool_uses.is_empty() || !reasoning.is_empty()) {
         " ".to_string()
     } else {
         text_content
@@ -774,6 +836,9 @@ fn convert_assistant_message(
     if !tool_uses.is_empty() {
         assistant = assistant.with_tool_uses(tool_uses);
     }
+    if !reasoning.is_empty() {
+        assistant = assistant.with_reasoning_content(reasoning);
+    }
 
     Ok(HistoryAssistantMessage {
         assistant_response_message: assistant,
@@ -791,6 +856,7 @@ fn merge_assistant_messages(
     }
 
     let mut all_tool_uses: Vec<ToolUseEntry> = Vec::new();
+    let mut all_reasoning: Vec<ReasoningContent> = Vec::new();
     let mut content_parts: Vec<String> = Vec::new();
 
     for msg in messages {
@@ -802,6 +868,9 @@ fn merge_assistant_messages(
         if let Some(tus) = am.tool_uses {
             all_tool_uses.extend(tus);
         }
+        if let Some(reasoning) = am.reasoning_content {
+            all_reasoning.extend(reasoning);
+        }
     }
 
     let content = if content_parts.is_empty() && !all_tool_uses.is_empty() {
@@ -814,6 +883,9 @@ fn merge_assistant_messages(
     if !all_tool_uses.is_empty() {
         assistant = assistant.with_tool_uses(all_tool_uses);
     }
+    if !all_reasoning.is_empty() {
+        assistant = assistant.with_reasoning_content(all_reasoning);
+    }
     Ok(HistoryAssistantMessage {
         assistant_response_message: assistant,
     })
@@ -902,6 +974,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
         assert_eq!(determine_chat_trigger_type(&req), "MANUAL");
     }
@@ -981,6 +1057,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let result = convert_request(&req).unwrap();
@@ -1045,6 +1125,10 @@ mod tests {
             tool_choice: None,
             thinking: None,
             output_config: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
             metadata: Some(Metadata {
                 user_id: Some(
                     "user_0dede55c6dcc4a11a30bbb5e7f22e6fdf86cdeba3820019cc27612af4e1243cd_account__session_a0662283-7fd3-4399-a7eb-52b9a717ae88".to_string(),
@@ -1078,6 +1162,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let result = convert_request(&req).unwrap();
@@ -1466,6 +1554,71 @@ mod tests {
         assert_eq!(tool_uses[0].tool_use_id, "toolu_01ABC");
     }
 
+    fn prefill_request(prefill: serde_json::Value) -> MessagesRequest {
+        use super::super::types::Message as AnthropicMessage;
+
+        MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![
+                AnthropicMessage {
+                    role: "user".to_string(),
+                    content: serde_json::json!("Describe the user as JSON"),
+                },
+                AnthropicMessage {
+                    role: "assistant".to_string(),
+                    content: prefill,
+                },
+            ],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            output_config: None,
+            metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        }
+    }
+
+    #[test]
+    fn test_prefill_is_forwarded_as_continuation_instruction() {
+        let req = prefill_request(serde_json::json!([
+            {"type": "text", "text": "{\"name\": \"Al"}
+        ]));
+        assert_eq!(
+            extract_prefill(&req.messages).as_deref(),
+            Some("{\"name\": \"Al")
+        );
+
+        let state = convert_request(&req).unwrap().conversation_state;
+        let content = &state.current_message.user_input_message.content;
+        assert!(content.starts_with("Describe the user as JSON"));
+        assert!(content.contains("<assistant_prefill>{\"name\": \"Al</assistant_prefill>"));
+        // prefill 不进入历史（末尾 user 之后的 assistant 被截断）
+        assert!(state.history.is_empty());
+    }
+
+    #[test]
+    fn test_prefill_without_text_is_dropped() {
+        let req = prefill_request(serde_json::json!("   "));
+        assert_eq!(extract_prefill(&req.messages), None);
+
+        let state = convert_request(&req).unwrap().conversation_state;
+        assert_eq!(
+            state.current_message.user_input_message.content,
+            "Describe the user as JSON"
+        );
+
+        // 末尾是 user 时没有 prefill
+        let mut req = prefill_request(serde_json::json!("Sure"));
+        req.messages.pop();
+        assert_eq!(extract_prefill(&req.messages), None);
+    }
+
     #[test]
     fn test_consecutive_assistant_with_tool_use_result_pairing() {
         // 测试 Issue #79 的完整场景
@@ -1508,6 +1661,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let result = convert_request(&req);
@@ -1527,4 +1684,103 @@ mod tests {
         }
         assert!(found_tool_use, "合并后的 assistant 消息应包含 tool_use");
     }
+
+    #[test]
+    fn test_thinking_signature_survives_tool_use_round_trip() {
+        use super::super::types::Message as AnthropicMessage;
+
+        // 第一轮的 thinking + tool_use 作为历史回传，第二轮携带工具结果
+        let req = MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![
+                AnthropicMessage {
+                    role: "user".to_string(),
+                    content: serde_json::json!("Read the config file"),
+                },
+                AnthropicMessage {
+                    role: "assistant".to_string(),
+                    content: serde_json::json!([
+                        {"type": "thinking", "thinking": "I should read the file.", "signature": "sig-1"},
+                        {"type": "redacted_thinking", "data": "ZW5jcnlwdGVk"},
+                        {"type": "tool_use", "id": "toolu_01", "name": "read_file", "input": {"path": "/config.json"}}
+                    ]),
+                },
+                AnthropicMessage {
+                    role: "user".to_string(),
+                    content: serde_json::json!([
+                        {"type": "tool_result", "tool_use_id": "toolu_01", "content": "{\"key\": \"value\"}"}
+                    ]),
+                },
+            ],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            output_config: None,
+            metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+
+        let state = convert_request(&req).unwrap().conversation_state;
+        let assistant = state
+            .history
+            .iter()
+            .find_map(|msg| match msg {
+                Message::Assistant(a) => Some(&a.assistant_response_message),
+                _ => None,
+            })
+            .unwrap();
+        // 带签名的 thinking 只在 reasoningContent 中回传一次，正文使用占位符
+        assert_eq!(assistant.content, " ");
+        assert_eq!(
+            assistant.reasoning_content.as_deref(),
+            Some(
+                &[
+                    ReasoningContent::ReasoningText {
+                        text: "I should read the file.".to_string(),
+                        signature: Some("sig-1".to_string()),
+                    },
+                    ReasoningContent::RedactedContent("ZW5jcnlwdGVk".to_string()),
+                ][..]
+            )
+        );
+        assert_eq!(
+            assistant.tool_uses.as_ref().unwrap()[0].tool_use_id,
+            "toolu_01"
+        );
+        assert_eq!(
+            serde_json::to_value(assistant).unwrap()["reasoningContent"],
+            serde_json::json!([
+                {"reasoningText": {"text": "I should read the file.", "signature": "sig-1"}},
+                {"redactedContent": "ZW5jcnlwdGVk"}
+            ])
+        );
+
+        let context = &state
+            .current_message
+            .user_input_message
+            .user_input_message_context;
+        assert_eq!(context.tool_results[0].tool_use_id, "toolu_01");
+    }
+
+    #[test]
+    fn test_unsigned_thinking_has_no_reasoning_content() {
+        let msg = super::super::types::Message {
+            role: "assistant".to_string(),
+            content: serde_json::json!([{"type": "thinking", "thinking": "hmm"}]),
+        };
+        let converted = convert_assistant_message(&msg).unwrap();
+        assert_eq!(converted.assistant_response_message.reasoning_content, None);
+        let json = serde_json::to_value(&converted).unwrap();
+        assert!(
+            json["assistantResponseMessage"]
+                .get("reasoningContent")
+                .is_none()
+        );
+    }
 }
@@ -3,29 +3,855 @@
 use std::convert::Infallible;
 
 use anyhow::Error;
+use crate::kiro::model::credentials::subscription_supports_opus;
 use crate::kiro::model::events::Event;
 use crate::kiro::model::requests::kiro::KiroRequest;
 use crate::kiro::parser::decoder::EventStreamDecoder;
+use crate::kiro::provider::{KiroProvider, MessagesCall, Provider, ServedCredential};
+use crate::kiro::user_usage::{UserUsageRecorder, user_key};
+use crate::model::config::{
+    DEFAULT_MAX_RESPONSE_BYTES, KIRO_BACKEND, PromptTooLongPolicy, SystemPromptMode,
+    ToolInputValidationPolicy, UnsupportedParamsPolicy,
+};
 use crate::token;
 use axum::{
-    Json as JsonExtractor,
+    Extension, Json as JsonExtractor,
     body::Body,
-    extract::State,
-    http::{StatusCode, header},
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Json, Response},
 };
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{Stream, StreamExt, stream};
 use serde_json::json;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::time::interval;
 use uuid::Uuid;
 
-use super::converter::{ConversionError, convert_request};
-use super::middleware::AppState;
-use super::stream::{BufferedStreamContext, SseEvent, StreamContext};
-use super::types::{CountTokensRequest, CountTokensResponse, ErrorResponse, MessagesRequest, Model, ModelsResponse, OutputConfig, Thinking};
+use super::active_requests::{
+    ActiveRequest, ActiveRequestGuard, CANCELLED_MESSAGE, RequestLane, until_cancelled,
+};
+use super::beta::{ANTHROPIC_BETA_HEADER, BetaFeatures, parse_betas};
+use super::converter::{
+    ConversionError, build_system_prompt, convert_request, convert_request_with_model_id,
+    extract_prefill, map_model,
+};
+use super::middleware::{AppState, RequestId, passthrough_requested};
+use super::models::{CONTEXT_WINDOW_SIZE, context_window, registered_models};
+use super::post_processing::{TextFilterStream, TextFilters};
+use super::queue::{QUEUE_RETRY_AFTER_SECS, QueuePermit, QueueTimeout, hold_permit};
+use super::redaction::{REDACTIONS_HEADER, RedactionSummary, Redactor};
+use super::response_store::ResponseStore;
+use super::stream::{
+    BufferedStreamContext, SseEvent, StreamContext, add_cache_usage_fields, reaches_max_tokens,
+    upstream_error_kind,
+};
+use super::types::{ContentBlock, CountTokensRequest, CountTokensResponse, ErrorResponse, ImageSource, Message, MessagesRequest, Model, ModelsResponse, OutputConfig, SystemMessage, Thinking, Tool};
+use super::tool_validation::{
+    DEGRADED_RESPONSE_HEADER, TOOL_INPUT_VALIDATION_HEADER, ToolInputRecovery, ToolInputValidator,
+    degraded_tool_text, recover_tool_input,
+};
 use super::websearch;
+use crate::common::auth;
+use crate::common::model_pattern::find_by_model;
+
+/// count_tokens 回退到本地估算时附加的响应头
+const TOKEN_COUNT_FALLBACK_HEADER: &str = "x-token-count-fallback";
+
+/// JSON 模式请求头（值为 `json_object` 时等同于请求体中的 `response_format`）
+const RESPONSE_FORMAT_HEADER: &str = "x-response-format";
+
+/// JSON 模式追加到 system 的指令
+const JSON_MODE_INSTRUCTION: &str = "Respond with a single valid JSON value and nothing else. \
+Do not wrap it in Markdown code fences and do not add any explanation or text before or after it.";
+
+/// JSON 模式纠正重试时追加的 user 消息
+const JSON_MODE_CORRECTION: &str = "Your previous response was not valid JSON. \
+Reply again with only the corrected JSON value, without Markdown code fences or any other text.";
+
+/// max_tokens 被 maxTokensCap 下调时附加的响应头，值为 `<原值>-><下调后的值>`
+const MAX_TOKENS_CLAMPED_HEADER: &str = "x-kiro-max-tokens-clamped";
+
+/// warn 策略下列出被丢弃的采样参数的响应头
+const UNSUPPORTED_PARAMS_HEADER: &str = "x-kiro-unsupported-params";
+
+/// 单请求上游超时请求头（秒，受 maxRequestTimeoutSecs 限制）
+const TIMEOUT_HEADER: &str = "x-kiro-timeout-secs";
+
+/// A/B 路由请求头，格式为 `credential:<id>`
+const AB_VARIANT_HEADER: &str = "x-ab-variant";
+
+/// 解析 A/B 路由请求头，返回需要固定使用的凭据 ID
+///
+/// - 未携带 `X-AB-Variant` 或不是 `credential:<id>` 形式时返回 None（走正常负载均衡）
+/// - 必须同时携带正确的 `X-Admin-Key`，否则返回 403
+/// - 凭据不存在或已禁用时返回 400
+fn pinned_credential(state: &AppState, headers: &HeaderMap) -> Result<Option<u64>, Box<Response>> {
+    let Some(raw_id) = headers
+        .get(AB_VARIANT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("credential:"))
+    else {
+        return Ok(None);
+    };
+
+    if !state.has_valid_admin_key(headers) {
+        tracing::warn!("X-AB-Variant 凭据路由缺少有效的 X-Admin-Key，拒绝请求");
+        return Err(Box::new(
+            (
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse::new(
+                    "permission_error",
+                    "X-AB-Variant credential routing requires a valid X-Admin-Key header.",
+                )),
+            )
+                .into_response(),
+        ));
+    }
+
+    let available = raw_id.trim().parse::<u64>().ok().filter(|id| {
+        state.token_manager.as_ref().is_some_and(|m| {
+            m.snapshot()
+                .entries
+                .iter()
+                .any(|e| e.id == *id && !e.disabled)
+        })
+    });
+    match available {
+        Some(id) => {
+            tracing::info!(credential_id = id, "A/B 路由：固定使用指定凭据");
+            Ok(Some(id))
+        }
+        None => Err(Box::new(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(
+                    "invalid_request_error",
+                    format!(
+                        "Credential '{}' is not available for A/B routing.",
+                        raw_id.trim()
+                    ),
+                )),
+            )
+                .into_response(),
+        )),
+    }
+}
+
+/// 透传模式下，客户端上游 Token 的提取结果
+///
+/// - 开启 `passthroughMode`，或携带 `X-Passthrough: true` 且有有效的 `X-Admin-Key`（否则返回 403）时启用
+/// - 启用后 API Key 必须通过 `x-api-key` 提供，`Authorization: Bearer <token>` 中是转发给上游的 Token，否则返回 401
+/// - 只对 Kiro 后端生效，路由到其他后端的请求不透传
+fn passthrough_token(
+    state: &AppState,
+    headers: &HeaderMap,
+    backend: &str,
+) -> Result<Option<String>, Box<Response>> {
+    let requested = passthrough_requested(headers);
+    if requested && !state.has_valid_admin_key(headers) {
+        tracing::warn!("X-Passthrough 缺少有效的 X-Admin-Key，拒绝请求");
+        return Err(Box::new(
+            (
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse::new(
+                    "permission_error",
+                    "X-Passthrough requires a valid X-Admin-Key header.",
+                )),
+            )
+                .into_response(),
+        ));
+    }
+    let enabled = requested || state.passthrough_mode();
+    if !enabled || backend != KIRO_BACKEND {
+        return Ok(None);
+    }
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty());
+    match token {
+        Some(token) if headers.contains_key("x-api-key") => Ok(Some(token.to_string())),
+        _ => Err(Box::new(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new(
+                    "authentication_error",
+                    "Passthrough mode requires the API key in x-api-key and the upstream token in Authorization: Bearer.",
+                )),
+            )
+                .into_response(),
+        )),
+    }
+}
+
+/// 解析 `x-kiro-timeout-secs`，返回本次请求的上游超时（覆盖全局超时）
+///
+/// - 未携带时返回 None（使用全局超时）
+/// - 不是正整数时返回 400
+/// - 超过 maxRequestTimeoutSecs 时按上限处理
+fn request_timeout(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Option<Duration>, Box<Response>> {
+    let Some(value) = headers.get(TIMEOUT_HEADER) else {
+        return Ok(None);
+    };
+    let Some(secs) = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+    else {
+        return Err(invalid_request(format!(
+            "{} must be a positive integer number of seconds.",
+            TIMEOUT_HEADER
+        )));
+    };
+    let timeout = Duration::from_secs(secs);
+    if timeout > state.max_request_timeout {
+        tracing::info!(
+            "{} 为 {} 秒，超过上限，按 {} 秒处理",
+            TIMEOUT_HEADER,
+            secs,
+            state.max_request_timeout.as_secs()
+        );
+        return Ok(Some(state.max_request_timeout));
+    }
+    Ok(Some(timeout))
+}
+
+/// 登记进行中的请求（ID 与 `request-id` 响应头一致，便于按响应头取消）
+fn register_active_request(
+    state: &AppState,
+    request_id: Option<Extension<RequestId>>,
+    model: &str,
+    call: &MessagesCall<'_>,
+) -> ActiveRequestGuard {
+    let id = request_id
+        .map(|Extension(RequestId(id))| id)
+        .unwrap_or_else(|| format!("req_{}", Uuid::new_v4().simple()));
+    state.active_requests.register(ActiveRequest {
+        id,
+        model: model.to_string(),
+        credential_id: call.pinned,
+        started_at: chrono::Utc::now(),
+        lane: RequestLane::for_timeout(call.timeout),
+        stream: call.is_stream,
+        timeout: call.timeout,
+        cancelled: false,
+    })
+}
+
+/// 请求在开始返回响应前被管理员取消时的错误响应
+fn cancelled_response() -> Response {
+    tracing::info!("请求已被管理员取消");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse::new("api_error", CANCELLED_MESSAGE)),
+    )
+        .into_response()
+}
+
+/// 读取上游成功响应中记录的凭据信息
+fn served_credential(response: &reqwest::Response) -> Option<ServedCredential> {
+    response.extensions().get::<ServedCredential>().cloned()
+}
+
+/// 将实际处理请求的凭据写入响应扩展，由响应头中间件按配置透出
+fn with_served_credential(mut response: Response, served: Option<ServedCredential>) -> Response {
+    if let Some(served) = served {
+        response.extensions_mut().insert(served);
+    }
+    response
+}
+
+/// 响应输出的后处理器
+#[derive(Default)]
+struct OutputProcessors {
+    /// 工具输入校验器（未启用 validateToolInputs 或无工具时为 None）
+    tool_validator: Option<ToolInputValidator>,
+    /// 是否修复损坏的工具参数 JSON（repairToolInputs）
+    repair_tool_inputs: bool,
+    /// 文本后处理过滤器（未配置 postProcessing 时为 None）
+    text_filters: Option<Arc<TextFilters>>,
+    /// 用户用量记录器（响应结束时记录 token 用量）
+    usage_recorder: Option<UserUsageRecorder>,
+    /// 上游并发许可（响应结束后归还）
+    queue_permit: Option<QueuePermit>,
+    /// 本次请求启用的 anthropic-beta 特性
+    betas: BetaFeatures,
+    /// assistant prefill 文本（用于移除模型回显的 prefill）
+    prefill: Option<String>,
+    /// 请求的 max_tokens（用于判断输出是否被截断）
+    max_tokens: Option<i32>,
+    /// JSON 模式（移除输出中的代码块围栏并校验是否为合法 JSON）
+    json_output: bool,
+    /// JSON 模式纠正重试（仅非流式请求且启用 jsonModeRetry 时）
+    json_retry: Option<JsonRetry>,
+    /// 非流式响应体字节上限（maxResponseBytes）
+    max_response_bytes: usize,
+    /// 非流式响应暂存区（enableResponseRetrieval）
+    response_store: Option<Arc<ResponseStore>>,
+    /// 请求使用的 API Key（暂存响应的所有者）
+    api_key: Option<String>,
+}
+
+/// JSON 模式纠正重试所需的原始请求
+struct JsonRetry {
+    /// 原始请求（已追加 JSON 指令）
+    request: MessagesRequest,
+    profile_arn: Option<String>,
+    /// 处理该请求的上游后端
+    backend: String,
+    /// 出站脱敏规则（重试请求同样需要脱敏）
+    redactor: Option<Arc<Redactor>>,
+}
+
+impl OutputProcessors {
+    fn new(state: &AppState, tools: Option<&[Tool]>, betas: BetaFeatures) -> Self {
+        Self {
+            tool_validator: build_tool_validator(state, tools),
+            repair_tool_inputs: state.repair_tool_inputs,
+            text_filters: state.text_filters.clone(),
+            usage_recorder: None,
+            queue_permit: None,
+            betas,
+            prefill: None,
+            max_tokens: None,
+            json_output: false,
+            json_retry: None,
+            max_response_bytes: state
+                .token_manager
+                .as_ref()
+                .map_or(DEFAULT_MAX_RESPONSE_BYTES, |m| {
+                    m.config().max_response_bytes
+                }),
+            response_store: state.response_store.clone(),
+            api_key: None,
+        }
+    }
+
+    /// 记录请求使用的 API Key（只有同一 Key 才能取回暂存的响应）
+    fn with_api_key(mut self, headers: &HeaderMap) -> Self {
+        self.api_key = auth::api_key_from_headers(headers).map(str::to_string);
+        self
+    }
+
+    /// 设置上游调用准入结果（响应结束时记录用户用量并归还并发许可）
+    fn with_admission(mut self, (usage_recorder, queue_permit): Admission) -> Self {
+        self.usage_recorder = usage_recorder;
+        self.queue_permit = queue_permit;
+        self
+    }
+
+    /// 设置 assistant prefill（响应开头回显的 prefill 会被移除）
+    fn with_prefill(mut self, prefill: Option<String>) -> Self {
+        self.prefill = prefill;
+        self
+    }
+
+    /// 设置请求的 max_tokens
+    fn with_max_tokens(mut self, max_tokens: i32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// 启用 JSON 模式
+    ///
+    /// 非流式请求在启用 jsonModeRetry 时保留原始请求用于纠正重试；
+    /// 带 prefill 的请求输出是续写内容，不做纠正重试
+    fn with_json_mode(mut self, state: &AppState, payload: &MessagesRequest) -> Self {
+        self.json_output = true;
+        let retry_enabled = state
+            .token_manager
+            .as_ref()
+            .is_some_and(|m| m.config().json_mode_retry);
+        if retry_enabled && !payload.stream && self.prefill.is_none() {
+            self.json_retry = Some(JsonRetry {
+                request: payload.clone(),
+                profile_arn: state.profile_arn.clone(),
+                backend: state.route_model(&payload.model).0.to_string(),
+                redactor: state.redactor.clone(),
+            });
+        }
+        self
+    }
+
+    /// 为单个响应创建文本过滤状态
+    fn text_filter_stream(&self) -> Option<TextFilterStream> {
+        let stream = self.text_filters.as_ref().map(|f| f.stream());
+        let stream = match &self.prefill {
+            Some(prefill) => Some(stream.unwrap_or_default().with_prefill_echo(prefill)),
+            None => stream,
+        };
+        if !self.json_output {
+            return stream;
+        }
+        let stream = stream.unwrap_or_default();
+        Some(match &self.prefill {
+            Some(prefill) => stream.with_json_output_after(prefill),
+            None => stream.with_json_output(),
+        })
+    }
+}
+
+/// 按配置构建工具输入校验器（未启用 validateToolInputs 或无工具时返回 None）
+fn build_tool_validator(state: &AppState, tools: Option<&[Tool]>) -> Option<ToolInputValidator> {
+    let config = state.token_manager.as_ref()?.config();
+    if !config.validate_tool_inputs {
+        return None;
+    }
+    tools
+        .filter(|t| !t.is_empty())
+        .map(|t| ToolInputValidator::new(config.tool_input_validation_policy, t))
+}
+
+/// 检查模型是否被 allowedModels 白名单允许（先按别名映射到 Kiro 模型再比较）
+fn is_model_allowed(state: &AppState, model: &str) -> bool {
+    let Some(manager) = state.token_manager.as_ref() else {
+        return true;
+    };
+    let config = manager.config();
+    config.is_model_allowed(model)
+        || map_model(model).is_some_and(|mapped| config.is_model_allowed(&mapped))
+}
+
+/// 模型不在白名单中时返回 400
+fn reject_disallowed_model(state: &AppState, model: &str) -> Option<Response> {
+    if is_model_allowed(state, model) {
+        return None;
+    }
+    tracing::warn!(model = %model, "模型不在 allowedModels 白名单中，拒绝请求");
+    Some(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                "invalid_request_error",
+                format!("Model '{}' is not in the allowed models list.", model),
+            )),
+        )
+            .into_response(),
+    )
+}
+
+/// 当前凭据的订阅不支持请求的模型时返回 403 `subscription_required`
+///
+/// 当前凭据受限时，若仍有其他可用凭据支持该模型则放行（选择凭据时会自动切换）。
+/// 仅检查使用托管凭据的 Kiro 请求，透传模式与其他后端不做检查。
+fn reject_unsubscribed_model(
+    state: &AppState,
+    backend: &str,
+    passthrough: bool,
+    model: &str,
+) -> Option<Response> {
+    if backend != KIRO_BACKEND || passthrough {
+        return None;
+    }
+    let current = state.current_credential_snapshot()?;
+    if !requires_paid_subscription(model)
+        || subscription_supports_opus(current.subscription_title.as_deref())
+        || any_credential_supports_paid_models(state)
+    {
+        return None;
+    }
+    tracing::warn!(
+        credential_id = %current.id,
+        subscription = ?current.subscription_title,
+        model = %model,
+        "当前订阅不支持请求的模型"
+    );
+    Some(
+        (
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(
+                "subscription_required",
+                format!("当前订阅不支持模型 {}，需要 PRO 或更高等级订阅", model),
+            )),
+        )
+            .into_response(),
+    )
+}
+
+/// 判断模型是否需要付费订阅（目前仅 Opus 系列）
+fn requires_paid_subscription(model: &str) -> bool {
+    model.to_lowercase().contains("opus")
+}
+
+/// 是否存在其他未禁用且支持付费模型的凭据
+fn any_credential_supports_paid_models(state: &AppState) -> bool {
+    state.token_manager.as_ref().is_some_and(|manager| {
+        manager
+            .snapshot()
+            .entries
+            .iter()
+            .any(|e| !e.disabled && subscription_supports_opus(e.subscription_title.as_deref()))
+    })
+}
+
+/// Anthropic API 支持的图片 media_type
+pub const SUPPORTED_IMAGE_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// 检查图片数据源，返回面向客户端的错误消息
+///
+/// Base64 只检查字符集与填充位置，不解码整个数据
+fn image_source_error(source: &ImageSource) -> Option<String> {
+    if !SUPPORTED_IMAGE_TYPES.contains(&source.media_type.as_str()) {
+        return Some(format!(
+            "Unsupported image media type: '{}'. Supported types: {}",
+            source.media_type,
+            SUPPORTED_IMAGE_TYPES.join(", ")
+        ));
+    }
+    let data = source.data.as_bytes();
+    if data.is_empty() {
+        return Some("Image data must not be empty.".to_string());
+    }
+    let body_len = data.len() - data.iter().rev().take_while(|&&b| b == b'=').count();
+    let valid = data.len() - body_len <= 2
+        && data[..body_len]
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/'));
+    (!valid).then(|| "Image data is not valid base64.".to_string())
+}
+
+/// 校验所有消息中的图片内容块，media_type 不受支持或数据无效时返回 400（在任何上游调用之前）
+fn reject_invalid_images(messages: &[Message]) -> Option<Response> {
+    let error = messages
+        .iter()
+        .filter_map(|m| m.content.as_array())
+        .flatten()
+        .filter(|item| item.get("type").and_then(|t| t.as_str()) == Some("image"))
+        .filter_map(|item| serde_json::from_value::<ContentBlock>(item.clone()).ok())
+        .find_map(|block| block.source.as_ref().and_then(image_source_error))?;
+    tracing::warn!("图片内容块校验失败，拒绝请求: {}", error);
+    Some(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("invalid_request_error", error)),
+        )
+            .into_response(),
+    )
+}
+
+/// 返回 400 invalid_request_error
+fn invalid_request(message: String) -> Box<Response> {
+    tracing::warn!("请求参数校验失败，拒绝请求: {}", message);
+    Box::new(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("invalid_request_error", message)),
+        )
+            .into_response(),
+    )
+}
+
+/// 校验采样参数的取值范围，并按 unsupportedParamsPolicy 处理（在任何上游调用之前）
+///
+/// Kiro 协议没有对应的采样参数，无法转发；返回需要在响应头中列出的被丢弃参数（仅 warn 策略）
+fn check_sampling_params(
+    state: &AppState,
+    payload: &MessagesRequest,
+) -> Result<Vec<&'static str>, Box<Response>> {
+    if let Some(temperature) = payload.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+        return Err(invalid_request(format!(
+            "temperature: must be between 0 and 2, got {}",
+            temperature
+        )));
+    }
+    if let Some(top_p) = payload.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+        return Err(invalid_request(format!(
+            "top_p: must be between 0 and 1, got {}",
+            top_p
+        )));
+    }
+    if let Some(top_k) = payload.top_k.filter(|k| *k < 0) {
+        return Err(invalid_request(format!(
+            "top_k: must be greater than or equal to 0, got {}",
+            top_k
+        )));
+    }
+
+    let dropped: Vec<&'static str> = [
+        ("temperature", payload.temperature.is_some()),
+        ("top_p", payload.top_p.is_some()),
+        ("top_k", payload.top_k.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, present)| present.then_some(name))
+    .collect();
+    if dropped.is_empty() {
+        return Ok(dropped);
+    }
+
+    let policy = state
+        .token_manager
+        .as_ref()
+        .map(|manager| manager.config().unsupported_params_policy)
+        .unwrap_or_default();
+    match policy {
+        UnsupportedParamsPolicy::Ignore => {
+            tracing::debug!(params = ?dropped, "上游不支持采样参数，已忽略");
+            Ok(Vec::new())
+        }
+        UnsupportedParamsPolicy::Warn => {
+            tracing::warn!(params = ?dropped, "上游不支持采样参数，已忽略");
+            Ok(dropped)
+        }
+        UnsupportedParamsPolicy::Reject => Err(invalid_request(format!(
+            "Unsupported parameter(s): {}. The upstream does not support sampling parameters.",
+            dropped.join(", ")
+        ))),
+    }
+}
+
+/// 在响应头中列出被丢弃的采样参数
+fn with_unsupported_params_header(mut response: Response, dropped: &[&str]) -> Response {
+    if dropped.is_empty() {
+        return response;
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&dropped.join(", ")) {
+        response
+            .headers_mut()
+            .insert(UNSUPPORTED_PARAMS_HEADER, value);
+    }
+    response
+}
+
+/// 可转发给外部 count_tokens API 的客户端信息（排除与 Admin API Key 相同的密钥）
+fn count_context(state: &AppState, headers: &HeaderMap) -> token::ClientCountContext {
+    let admin_api_key = state
+        .token_manager
+        .as_ref()
+        .and_then(|m| m.config().admin_api_key.clone());
+    token::ClientCountContext::from_headers(headers, admin_api_key.as_deref())
+}
+
+/// 解析 anthropic-beta 请求头，请求了 rejectBetas 中的 beta 时返回 400
+fn resolve_betas(state: &AppState, headers: &HeaderMap) -> Result<BetaFeatures, Box<Response>> {
+    let requested = parse_betas(headers);
+    if requested.is_empty() {
+        return Ok(BetaFeatures::default());
+    }
+    tracing::info!(
+        betas = ?requested.iter().map(|b| b.as_str()).collect::<Vec<_>>(),
+        "客户端请求了 anthropic-beta 特性"
+    );
+    state.beta_policy.resolve(requested).map_err(|rejected| {
+        tracing::warn!(betas = ?rejected, "请求了 rejectBetas 中的 anthropic-beta，拒绝请求");
+        Box::new(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(
+                    "invalid_request_error",
+                    format!(
+                        "anthropic-beta feature(s) not supported by this proxy: {}",
+                        rejected.join(", ")
+                    ),
+                )),
+            )
+                .into_response(),
+        )
+    })
+}
+
+/// 在响应头中回显启用的 anthropic-beta 特性
+fn with_beta_header(mut response: Response, value: Option<header::HeaderValue>) -> Response {
+    if let Some(value) = value {
+        response.headers_mut().insert(ANTHROPIC_BETA_HEADER, value);
+    }
+    response
+}
+
+/// 预检对话长度：预估输入 tokens 超过上下文窗口减去 max_tokens 时按 promptTooLongPolicy 处理
+///
+/// 上下文窗口取自模型注册表（见 [`context_window`]）；
+/// reject 策略返回 400，warn 策略仅记录日志并返回 None
+fn check_prompt_length(
+    state: &AppState,
+    model: &str,
+    input_tokens: i32,
+    max_tokens: i32,
+) -> Option<Response> {
+    let window = context_window(model);
+    let limit = i64::from(window) - i64::from(max_tokens.max(0));
+    if i64::from(input_tokens) <= limit {
+        return None;
+    }
+
+    let policy = state
+        .token_manager
+        .as_ref()
+        .map(|tm| tm.config().prompt_too_long_policy)
+        .unwrap_or_default();
+    tracing::warn!(
+        model = %model,
+        input_tokens,
+        max_tokens,
+        context_window = window,
+        ?policy,
+        "预估输入 tokens 超出上下文窗口"
+    );
+    if policy == PromptTooLongPolicy::Warn {
+        return None;
+    }
+
+    Some(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                "invalid_request_error",
+                format!(
+                    "prompt is too long: {} tokens > {} maximum",
+                    input_tokens,
+                    limit.max(0)
+                ),
+            )),
+        )
+            .into_response(),
+    )
+}
+
+/// 按 maxTokensCap 下调请求的 max_tokens（只降不升），返回 (原值, 下调后的值)
+///
+/// 按映射后的 Kiro 模型 ID 匹配（无法映射时使用原始模型名）
+fn clamp_max_tokens(state: &AppState, payload: &mut MessagesRequest) -> Option<(i32, i32)> {
+    let config = state.token_manager.as_ref()?.config();
+    let model = map_model(&payload.model).unwrap_or_else(|| payload.model.clone());
+    let cap = i32::try_from(*find_by_model(&config.max_tokens_cap, &model)?).unwrap_or(i32::MAX);
+    if payload.max_tokens <= cap {
+        return None;
+    }
+
+    let original = payload.max_tokens;
+    payload.max_tokens = cap;
+    tracing::info!(
+        model = %payload.model,
+        mapped_model = %model,
+        original_max_tokens = original,
+        clamped_max_tokens = cap,
+        "max_tokens 超过 maxTokensCap，已下调"
+    );
+    Some((original, cap))
+}
+
+/// 在响应头中注明 max_tokens 的下调
+fn with_max_tokens_clamped_header(mut response: Response, clamped: Option<(i32, i32)>) -> Response {
+    if let Some((original, cap)) = clamped {
+        let value = format!("{}->{}", original, cap);
+        if let Ok(value) = header::HeaderValue::from_str(&value) {
+            response
+                .headers_mut()
+                .insert(MAX_TOKENS_CLAMPED_HEADER, value);
+        }
+    }
+    response
+}
+
+/// 按 metadata.user_id 记录用户请求，超过 userLimits 当日上限时返回 429
+fn admit_user_request(
+    state: &AppState,
+    user_id: Option<&str>,
+    model: &str,
+) -> Result<Option<UserUsageRecorder>, Box<Response>> {
+    let Some(manager) = state.token_manager.as_ref() else {
+        return Ok(None);
+    };
+    let user = user_key(user_id);
+    match manager.admit_user_request(&user) {
+        Ok(()) => Ok(Some(UserUsageRecorder::new(
+            manager.clone(),
+            user,
+            model.to_string(),
+        ))),
+        Err(e) => {
+            tracing::warn!(user = %e.user, limit = e.limit, "用户已达每日请求上限，拒绝请求");
+            Err(Box::new(
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(ErrorResponse::new("rate_limit_error", e.to_string())),
+                )
+                    .into_response(),
+            ))
+        }
+    }
+}
+
+/// 上游调用准入结果：用户用量记录器与上游并发许可
+type Admission = (Option<UserUsageRecorder>, Option<QueuePermit>);
+
+/// 上游调用前的准入检查（在本地校验之后执行，被拒绝的请求不计入用户用量、不占用排队名额）
+///
+/// 依次检查全局速率限制（被限流的请求不计入用户用量）、用户每日请求上限，并等待上游并发许可
+async fn admit_upstream_call(
+    state: &AppState,
+    user_id: Option<&str>,
+    model: &str,
+) -> Result<Admission, Box<Response>> {
+    if let Some(response) = reject_rate_limited(state) {
+        return Err(Box::new(response));
+    }
+    let usage_recorder = admit_user_request(state, user_id, model)?;
+    let queue_permit = acquire_queue_permit(state).await?;
+    Ok((usage_recorder, queue_permit))
+}
+
+/// 在响应体发送完毕（或被丢弃）前持有并发许可
+fn with_queue_permit(response: Response, permit: Option<QueuePermit>) -> Response {
+    if permit.is_none() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(hold_permit(body.into_data_stream(), permit));
+    Response::from_parts(parts, body)
+}
+
+/// 等待上游并发许可，排队超时返回 503
+async fn acquire_queue_permit(state: &AppState) -> Result<Option<QueuePermit>, Box<Response>> {
+    let Some(queue) = state.request_queue.as_ref() else {
+        return Ok(None);
+    };
+    match queue.acquire().await {
+        Ok(permit) => Ok(Some(permit)),
+        Err(QueueTimeout) => {
+            tracing::warn!("等待上游并发许可超时，拒绝请求");
+            let mut response = (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    "overloaded_error",
+                    "Server is at capacity, please retry later",
+                )),
+            )
+                .into_response();
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                header::HeaderValue::from(QUEUE_RETRY_AFTER_SECS),
+            );
+            Err(Box::new(response))
+        }
+    }
+}
+
+/// 超出速率限制时返回 429，Retry-After 为可重试前需要等待的秒数
+fn reject_rate_limited(state: &AppState) -> Option<Response> {
+    let limited = state.rate_limiter.as_ref()?.check(Instant::now()).err()?;
+    let retry_after = limited.retry_after_secs();
+    tracing::warn!(retry_after, "超出消息请求速率限制，拒绝请求");
+    let message = format!("Rate limit exceeded, please retry after {retry_after} seconds");
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ErrorResponse::new("rate_limit_error", message)),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
+    Some(response)
+}
 
 /// 将 KiroProvider 错误映射为 HTTP 响应
 fn map_provider_error(err: Error) -> Response {
@@ -67,116 +893,197 @@ fn map_provider_error(err: Error) -> Response {
         .into_response()
 }
 
-/// GET /v1/models
-///
-/// 返回可用的模型列表
-pub async fn get_models() -> impl IntoResponse {
-    tracing::info!("Received GET /v1/models request");
+/// 上游在响应体中返回异常 / 错误帧时的错误响应
+fn upstream_error_response(code: &str, message: String) -> Response {
+    let (status, error_type) = upstream_error_kind(code);
+    (status, Json(ErrorResponse::new(error_type, message))).into_response()
+}
 
-    let models = vec![
-        Model {
-            id: "claude-sonnet-4-5-20250929".to_string(),
-            object: "model".to_string(),
-            created: 1727568000,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Sonnet 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-sonnet-4-5-20250929-thinking".to_string(),
-            object: "model".to_string(),
-            created: 1727568000,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Sonnet 4.5 (Thinking)".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-opus-4-5-20251101".to_string(),
-            object: "model".to_string(),
-            created: 1730419200,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Opus 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-opus-4-5-20251101-thinking".to_string(),
-            object: "model".to_string(),
-            created: 1730419200,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Opus 4.5 (Thinking)".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-sonnet-4-6".to_string(),
-            object: "model".to_string(),
-            created: 1770314400,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Sonnet 4.6".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-sonnet-4-6-thinking".to_string(),
-            object: "model".to_string(),
-            created: 1770314400,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Sonnet 4.6 (Thinking)".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-opus-4-6".to_string(),
-            object: "model".to_string(),
-            created: 1770314400,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Opus 4.6".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-opus-4-6-thinking".to_string(),
-            object: "model".to_string(),
-            created: 1770314400,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Opus 4.6 (Thinking)".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-haiku-4-5-20251001".to_string(),
-            object: "model".to_string(),
-            created: 1727740800,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Haiku 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-haiku-4-5-20251001-thinking".to_string(),
-            object: "model".to_string(),
-            created: 1727740800,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Haiku 4.5 (Thinking)".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-    ];
+/// 请求是否启用 JSON 模式（请求体 `response_format` 或 `x-response-format` 请求头）
+fn json_mode_requested(payload: &MessagesRequest, headers: &HeaderMap) -> bool {
+    payload
+        .response_format
+        .as_ref()
+        .is_some_and(|f| f.is_json_object())
+        || headers
+            .get(RESPONSE_FORMAT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("json_object"))
+}
 
-    Json(ModelsResponse {
-        object: "list".to_string(),
-        data: models,
-    })
+/// 启用 JSON 模式时在 system 末尾追加只输出 JSON 的指令，返回是否启用
+fn apply_json_mode(payload: &mut MessagesRequest, headers: &HeaderMap) -> bool {
+    if !json_mode_requested(payload, headers) {
+        return false;
+    }
+    payload
+        .system
+        .get_or_insert_with(Vec::new)
+        .push(SystemMessage {
+            text: JSON_MODE_INSTRUCTION.to_string(),
+        });
+    true
 }
 
-/// POST /v1/messages
+/// 按 systemPromptMode 合并运营方系统提示词与客户端的 `system`
 ///
-/// 创建消息（对话）
-pub async fn post_messages(
+/// prepend / append 模式下合并为一条系统消息，两部分之间以空行分隔
+fn apply_operator_system_prompt(state: &AppState, payload: &mut MessagesRequest) {
+    let Some(manager) = state.token_manager.as_ref() else {
+        return;
+    };
+    let config = manager.config();
+    let Some(operator) = config
+        .system_prompt
+        .as_deref()
+        .filter(|p| !p.trim().is_empty())
+    else {
+        return;
+    };
+
+    let client = payload
+        .system
+        .take()
+        .map(|system| {
+            system
+                .into_iter()
+                .map(|s| s.text)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|text| !text.is_empty());
+    let text = match (config.system_prompt_mode, client) {
+        (SystemPromptMode::Prepend, Some(client)) => format!("{}\n\n{}", operator, client),
+        (SystemPromptMode::Append, Some(client)) => format!("{}\n\n{}", client, operator),
+        _ => operator.to_string(),
+    };
+    payload.system = Some(vec![SystemMessage { text }]);
+}
+
+/// 将 Anthropic 请求转换为发往上游的 JSON 请求体
+///
+/// 纯函数（不发起网络调用），实际请求与 dry-run 共用，保证两者发送的内容一致。
+/// 路由到 OpenAI 协议后端（`backend` 不为 `kiro`）的模型不做 Kiro 模型映射，保留原始模型名。
+/// 配置了出站脱敏规则时对转换后的对话脱敏，返回各规则的命中次数；严格模式下命中 blocking 规则时返回 400
+fn build_upstream_body(
+    payload: &MessagesRequest,
+    profile_arn: Option<String>,
+    backend: &str,
+    redactor: Option<&Redactor>,
+) -> Result<(String, RedactionSummary), Box<Response>> {
+    let conversion_result = if backend == KIRO_BACKEND {
+        convert_request(payload)
+    } else {
+        convert_request_with_model_id(payload, payload.model.clone())
+    };
+    let conversion_result = conversion_result.map_err(|e| {
+        let (error_type, message) = match &e {
+            ConversionError::UnsupportedModel(model) => {
+                ("invalid_request_error", format!("模型不支持: {}", model))
+            }
+            ConversionError::EmptyMessages => ("invalid_request_error", "消息列表为空".to_string()),
+        };
+        tracing::warn!("请求转换失败: {}", e);
+        Box::new(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(error_type, message)),
+            )
+                .into_response(),
+        )
+    })?;
+
+    let mut conversation_state = conversion_result.conversation_state;
+    let redactions = match redactor {
+        Some(redactor) => redactor
+            .redact_conversation(&mut conversation_state)
+            .map_err(|blocked| invalid_request(blocked.to_string()))?,
+        None => RedactionSummary::default(),
+    };
+    if !redactions.is_empty() {
+        tracing::debug!(redactions = ?redactions.counts(), "出站请求已按规则脱敏");
+    }
+
+    // 构建 Kiro 请求
+    let kiro_request = KiroRequest {
+        conversation_state,
+        profile_arn,
+    };
+
+    let body = serde_json::to_string(&kiro_request).map_err(|e| {
+        tracing::error!("序列化请求失败: {}", e);
+        Box::new(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(
+                    "internal_error",
+                    format!("序列化请求失败: {}", e),
+                )),
+            )
+                .into_response(),
+        )
+    })?;
+    Ok((body, redactions))
+}
+
+/// 在响应头中列出各脱敏规则的命中次数
+fn with_redactions_header(mut response: Response, redactions: &RedactionSummary) -> Response {
+    if let Some(value) = redactions.header_value() {
+        response.headers_mut().insert(REDACTIONS_HEADER, value);
+    }
+    response
+}
+
+/// GET /v1/models
+///
+/// 返回可用的模型列表（配置了 allowedModels 时仅返回白名单内的模型）
+pub async fn get_models(State(state): State<AppState>) -> impl IntoResponse {
+    tracing::info!("Received GET /v1/models request");
+
+    let models: Vec<Model> = registered_models()
+        .into_iter()
+        .filter(|m| is_model_allowed(&state, &m.id))
+        .collect();
+
+    Json(ModelsResponse {
+        object: "list".to_string(),
+        data: models,
+    })
+}
+
+/// GET /v1/messages/{id}
+///
+/// 返回当前 API Key 暂存的非流式响应（需开启 enableResponseRetrieval），不存在或已过期时返回 404
+pub async fn get_message(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(message_id): Path<String>,
+) -> Response {
+    let stored = state
+        .response_store
+        .as_ref()
+        .zip(auth::api_key_from_headers(&headers))
+        .and_then(|(s, api_key)| s.get(api_key, &message_id));
+    match stored {
+        Some(body) => (StatusCode::OK, Json(body)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("message {} not found or expired", message_id),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// POST /v1/messages
+///
+/// 创建消息（对话）
+pub async fn post_messages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_id: Option<Extension<RequestId>>,
     JsonExtractor(mut payload): JsonExtractor<MessagesRequest>,
 ) -> Response {
     tracing::info!(
@@ -186,11 +1093,13 @@ pub async fn post_messages(
         message_count = %payload.messages.len(),
         "Received POST /v1/messages request"
     );
-    // 检查 KiroProvider 是否可用
-    let provider = match &state.kiro_provider {
-        Some(p) => p.clone(),
+    // 按模型选择上游后端并检查其 Provider 是否可用
+    let (backend, provider) = state.route_model(&payload.model);
+    let backend = backend.to_string();
+    let provider = match provider {
+        Some(p) => p,
         None => {
-            tracing::error!("KiroProvider 未配置");
+            tracing::error!("上游后端 {} 的 Provider 未配置", backend);
             return (
                 StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
@@ -202,76 +1111,145 @@ pub async fn post_messages(
         }
     };
 
+    // anthropic-beta 特性（rejectBetas 检查在任何上游调用之前）
+    let betas = match resolve_betas(&state, &headers) {
+        Ok(betas) => betas,
+        Err(response) => return *response,
+    };
+    let beta_header = betas.header_value();
+
+    // 模型白名单检查（在任何上游调用之前）
+    if let Some(response) = reject_disallowed_model(&state, &payload.model) {
+        return response;
+    }
+
+    // 图片内容块校验（在任何上游调用之前）
+    if let Some(response) = reject_invalid_images(&payload.messages) {
+        return response;
+    }
+
+    // 采样参数校验（在任何上游调用之前）
+    let dropped_params = match check_sampling_params(&state, &payload) {
+        Ok(dropped) => dropped,
+        Err(response) => return *response,
+    };
+
+    // A/B 路由：X-AB-Variant 指定凭据（需 X-Admin-Key）
+    let pinned = match pinned_credential(&state, &headers) {
+        Ok(pinned) => pinned,
+        Err(response) => return *response,
+    };
+
+    // 透传模式：使用客户端提供的上游 Token（X-Passthrough 需 X-Admin-Key）
+    let passthrough = match passthrough_token(&state, &headers, &backend) {
+        Ok(token) => token,
+        Err(response) => return *response,
+    };
+
+    // 订阅等级检查（仅托管凭据的 Kiro 请求）
+    if let Some(response) =
+        reject_unsubscribed_model(&state, &backend, passthrough.is_some(), &payload.model)
+    {
+        return response;
+    }
+
+    // 单请求上游超时（x-kiro-timeout-secs，受 maxRequestTimeoutSecs 限制）
+    let timeout = match request_timeout(&state, &headers) {
+        Ok(timeout) => timeout,
+        Err(response) => return *response,
+    };
+
     // 检测模型名是否包含 "thinking" 后缀，若包含则覆写 thinking 配置
     override_thinking_from_model_name(&mut payload);
 
-    // 检查是否为 WebSearch 请求
-    if websearch::has_web_search_tool(&payload) {
+    // 按模型限制 max_tokens 上限
+    let max_tokens_clamped = clamp_max_tokens(&state, &mut payload);
+
+    // 检查是否为 WebSearch 请求（依赖 Kiro MCP 接口与托管凭据，模拟上游模式、透传模式或路由到其他后端时按普通请求处理）
+    let websearch_provider = state.kiro_provider.clone().filter(|_| {
+        backend == KIRO_BACKEND && passthrough.is_none() && websearch::has_web_search_tool(&payload)
+    });
+    if let Some(kiro_provider) = websearch_provider {
         tracing::info!("检测到 WebSearch 工具，路由到 WebSearch 处理");
 
+        let user_id = payload.metadata.as_ref().and_then(|m| m.user_id.as_deref());
+        let (usage_recorder, queue_permit) =
+            match admit_upstream_call(&state, user_id, &payload.model).await {
+                Ok(admission) => admission,
+                Err(response) => return *response,
+            };
+
         // 估算输入 tokens
         let input_tokens = token::count_all_tokens(
             payload.model.clone(),
             payload.system.clone(),
             payload.messages.clone(),
             payload.tools.clone(),
+            &count_context(&state, &headers),
         ) as i32;
+        if let Some(recorder) = usage_recorder {
+            recorder.record(input_tokens, 0);
+        }
 
-        return websearch::handle_websearch_request(provider, &payload, input_tokens).await;
+        let response =
+            websearch::handle_websearch_request(kiro_provider, &payload, input_tokens).await;
+        let response = with_queue_permit(response, queue_permit);
+        let response = with_max_tokens_clamped_header(response, max_tokens_clamped);
+        let response = with_unsupported_params_header(response, &dropped_params);
+        return with_beta_header(response, beta_header);
     }
 
-    // 转换请求
-    let conversion_result = match convert_request(&payload) {
-        Ok(result) => result,
-        Err(e) => {
-            let (error_type, message) = match &e {
-                ConversionError::UnsupportedModel(model) => {
-                    ("invalid_request_error", format!("模型不支持: {}", model))
-                }
-                ConversionError::EmptyMessages => {
-                    ("invalid_request_error", "消息列表为空".to_string())
-                }
-            };
-            tracing::warn!("请求转换失败: {}", e);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse::new(error_type, message)),
-            )
-                .into_response();
-        }
-    };
+    // 合并运营方系统提示词
+    apply_operator_system_prompt(&state, &mut payload);
 
-    // 构建 Kiro 请求
-    let kiro_request = KiroRequest {
-        conversation_state: conversion_result.conversation_state,
-        profile_arn: state.profile_arn.clone(),
-    };
+    // JSON 模式：追加只输出 JSON 的指令
+    let json_mode = apply_json_mode(&mut payload, &headers);
 
-    let request_body = match serde_json::to_string(&kiro_request) {
+    // 转换请求并构建 Kiro 请求体
+    let (request_body, redactions) = match build_upstream_body(
+        &payload,
+        state.profile_arn.clone(),
+        &backend,
+        state.redactor.as_deref(),
+    ) {
         Ok(body) => body,
-        Err(e) => {
-            tracing::error!("序列化请求失败: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new(
-                    "internal_error",
-                    format!("序列化请求失败: {}", e),
-                )),
-            )
-                .into_response();
-        }
+        Err(response) => return *response,
     };
 
     tracing::debug!("Kiro request body: {}", request_body);
 
+    // 工具输入校验器与文本过滤器（需在 tools 被移动前构建）
+    let mut processors = OutputProcessors::new(&state, payload.tools.as_deref(), betas)
+        .with_prefill(extract_prefill(&payload.messages))
+        .with_max_tokens(payload.max_tokens)
+        .with_api_key(&headers);
+    if json_mode {
+        processors = processors.with_json_mode(&state, &payload);
+    }
+
     // 估算输入 tokens
     let input_tokens = token::count_all_tokens(
         payload.model.clone(),
         payload.system,
         payload.messages,
         payload.tools,
+        &count_context(&state, &headers),
     ) as i32;
 
+    // 预检对话长度（超出上下文窗口时不再调用上游）
+    if let Some(response) =
+        check_prompt_length(&state, &payload.model, input_tokens, payload.max_tokens)
+    {
+        return response;
+    }
+
+    // 本地校验全部通过后才计入用户用量、占用排队名额
+    let user_id = payload.metadata.as_ref().and_then(|m| m.user_id.as_deref());
+    processors = match admit_upstream_call(&state, user_id, &payload.model).await {
+        Ok(admission) => processors.with_admission(admission),
+        Err(response) => return *response,
+    };
+
     // 检查是否启用了thinking
     let thinking_enabled = payload
         .thinking
@@ -279,53 +1257,108 @@ pub async fn post_messages(
         .map(|t| t.is_enabled())
         .unwrap_or(false);
 
-    if payload.stream {
+    let call = MessagesCall {
+        request_body: &request_body,
+        is_stream: payload.stream,
+        pinned,
+        max_tokens: payload.max_tokens,
+        passthrough_token: passthrough.as_deref(),
+        timeout,
+    };
+
+    // 登记进行中的请求（guard 随响应结束或被丢弃而移除登记项）
+    let guard = register_active_request(&state, request_id, &payload.model, &call);
+
+    let response = if payload.stream {
         // 流式响应
         handle_stream_request(
             provider,
-            &request_body,
+            call,
             &payload.model,
             input_tokens,
             thinking_enabled,
+            processors,
+            guard,
         )
         .await
     } else {
-        // 非流式响应
-        handle_non_stream_request(provider, &request_body, &payload.model, input_tokens).await
-    }
+        // 非流式响应（被取消时丢弃上游调用）
+        tokio::select! {
+            response = handle_non_stream_request(
+                provider,
+                call,
+                &payload.model,
+                input_tokens,
+                processors,
+                &guard,
+            ) => response,
+            _ = guard.cancelled() => cancelled_response(),
+        }
+    };
+    let response = with_max_tokens_clamped_header(response, max_tokens_clamped);
+    let response = with_unsupported_params_header(response, &dropped_params);
+    let response = with_redactions_header(response, &redactions);
+    with_beta_header(response, beta_header)
 }
 
 /// 处理流式请求
 async fn handle_stream_request(
-    provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
-    request_body: &str,
+    provider: Arc<dyn Provider>,
+    call: MessagesCall<'_>,
     model: &str,
     input_tokens: i32,
     thinking_enabled: bool,
+    processors: OutputProcessors,
+    guard: ActiveRequestGuard,
 ) -> Response {
-    // 调用 Kiro API（支持多凭据故障转移）
-    let response = match provider.call_api_stream(request_body).await {
-        Ok(resp) => resp,
-        Err(e) => return map_provider_error(e),
+    // 调用上游 API（支持多凭据故障转移；被取消时丢弃上游调用）
+    let response = tokio::select! {
+        result = provider.call_messages(call) => match result {
+            Ok(resp) => resp,
+            Err(e) => return map_provider_error(e),
+        },
+        _ = guard.cancelled() => return cancelled_response(),
     };
 
+    let served = served_credential(&response);
+    if let Some(served) = &served {
+        guard.set_credential(served.id);
+    }
+
     // 创建流处理上下文
-    let mut ctx = StreamContext::new_with_thinking(model, input_tokens, thinking_enabled);
+    let text_filter = processors.text_filter_stream();
+    let usage_recorder = processors
+        .usage_recorder
+        .map(|r| r.with_credential(served.as_ref().map(|s| s.id)));
+    let mut ctx = StreamContext::new_with_thinking(model, input_tokens, thinking_enabled)
+        .with_tool_validator(processors.tool_validator)
+        .with_tool_input_recovery(processors.repair_tool_inputs)
+        .with_text_filter(text_filter)
+        .with_usage_recorder(usage_recorder)
+        .with_cache_usage(processors.betas.prompt_caching())
+        .with_max_tokens(processors.max_tokens);
 
     // 生成初始事件
     let initial_events = ctx.generate_initial_events();
 
-    // 创建 SSE 流
-    let stream = create_sse_stream(response, ctx, initial_events);
+    // 创建 SSE 流（流结束后归还并发许可并移除登记项，被取消时以 error 事件结束）
+    let stream = until_cancelled(
+        hold_permit(
+            create_sse_stream(response, ctx, initial_events),
+            processors.queue_permit,
+        ),
+        guard,
+    );
 
     // 返回 SSE 响应
-    Response::builder()
+    let response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "text/event-stream")
         .header(header::CACHE_CONTROL, "no-cache")
         .header(header::CONNECTION, "keep-alive")
         .body(Body::from_stream(stream))
-        .unwrap()
+        .unwrap();
+    with_served_credential(response, served)
 }
 
 /// Ping 事件间隔（25秒）
@@ -391,7 +1424,9 @@ fn create_sse_stream(
                                 .map(|e| Ok(Bytes::from(e.to_sse_string())))
                                 .collect();
 
-                            Some((stream::iter(bytes), (body_stream, ctx, decoder, false, ping_interval)))
+                            // 上游中途返回异常时流以 error 事件结束
+                            let finished = ctx.upstream_failed();
+                            Some((stream::iter(bytes), (body_stream, ctx, decoder, finished, ping_interval)))
                         }
                         Some(Err(e)) => {
                             tracing::error!("读取响应流失败: {}", e);
@@ -428,25 +1463,57 @@ fn create_sse_stream(
     initial_stream.chain(processing_stream)
 }
 
-/// 上下文窗口大小（200k tokens）
-const CONTEXT_WINDOW_SIZE: i32 = 200_000;
+/// 非流式响应：将累积的文本经后处理（跳过开头的 thinking 内容）后作为一个文本块追加
+fn finish_text_block(
+    content: &mut Vec<serde_json::Value>,
+    text: &mut String,
+    filter: &mut Option<TextFilterStream>,
+) {
+    if text.is_empty() {
+        return;
+    }
+    let text = std::mem::take(text);
+    let text = match filter {
+        Some(filter) => filter.apply_outside_thinking(&text),
+        None => text,
+    };
+    if !text.is_empty() {
+        content.push(json!({"type": "text", "text": text}));
+    }
+}
+
+/// 非流式响应：将尚未收到签名的 thinking 文本作为一个 thinking 块追加
+fn finish_reasoning_block(content: &mut Vec<serde_json::Value>, reasoning_text: &mut String) {
+    if !reasoning_text.is_empty() {
+        content.push(json!({"type": "thinking", "thinking": std::mem::take(reasoning_text)}));
+    }
+}
 
 /// 处理非流式请求
 async fn handle_non_stream_request(
-    provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
-    request_body: &str,
+    provider: Arc<dyn Provider>,
+    call: MessagesCall<'_>,
     model: &str,
     input_tokens: i32,
+    mut processors: OutputProcessors,
+    guard: &ActiveRequestGuard,
 ) -> Response {
-    // 调用 Kiro API（支持多凭据故障转移）
-    let response = match provider.call_api(request_body).await {
+    // 调用上游 API（支持多凭据故障转移）
+    let response = match provider.call_messages(call).await {
         Ok(resp) => resp,
         Err(e) => return map_provider_error(e),
     };
 
-    // 读取响应体
-    let body_bytes = match response.bytes().await {
-        Ok(bytes) => bytes,
+    let mut served = served_credential(&response);
+    if let Some(served) = &served {
+        guard.set_credential(served.id);
+    }
+
+    // 读取响应体（超过 maxResponseBytes 时截断）
+    let max_response_bytes = processors.max_response_bytes;
+    let body = read_body_capped(response, max_response_bytes).await;
+    let (mut body_bytes, mut truncated) = match body {
+        Ok(body) => body,
         Err(e) => {
             tracing::error!("读取响应体失败: {}", e);
             return (
@@ -460,19 +1527,52 @@ async fn handle_non_stream_request(
         }
     };
 
+    // JSON 模式：输出不是合法 JSON 时追加纠正对话重试一次（已截断的响应不重试）
+    let retried = match processors.json_retry.take() {
+        Some(retry) if !truncated => {
+            retry_invalid_json(&provider, call, retry, &processors, &body_bytes).await
+        }
+        _ => None,
+    };
+    if let Some((retry_served, (retry_body, retry_truncated))) = retried {
+        served = retry_served;
+        body_bytes = retry_body;
+        truncated = retry_truncated;
+    }
+
+    let text_filter = processors.text_filter_stream();
+    let tool_validator = processors.tool_validator;
+    let repair_tool_inputs = processors.repair_tool_inputs;
+    let usage_recorder = processors
+        .usage_recorder
+        .map(|r| r.with_credential(served.as_ref().map(|s| s.id)));
+    let _queue_permit = processors.queue_permit;
+    let cache_usage = processors.betas.prompt_caching();
+    let max_tokens = processors.max_tokens;
+    let response_store = processors.response_store;
+    let api_key = processors.api_key;
+
     // 解析事件流
     let mut decoder = EventStreamDecoder::new();
     if let Err(e) = decoder.feed(&body_bytes) {
         tracing::warn!("缓冲区溢出: {}", e);
     }
 
+    // 按事件顺序排列的内容块；连续的文本与未签名的 thinking 在切换到其他块时收尾
+    let mut content: Vec<serde_json::Value> = Vec::new();
     let mut text_content = String::new();
-    let mut tool_uses: Vec<serde_json::Value> = Vec::new();
+    let mut reasoning_text = String::new();
+    let mut text_filter = text_filter;
     let mut has_tool_use = false;
     let mut stop_reason = "end_turn".to_string();
     // 从 contextUsageEvent 计算的实际输入 tokens
     let mut context_input_tokens: Option<i32> = None;
 
+    // 是否存在未通过 schema 校验的工具输入
+    let mut tool_validation_failed = false;
+    // 是否有参数无法修复、降级为文本输出的工具调用
+    let mut degraded_tool_input = false;
+
     // 收集工具调用的增量 JSON
     let mut tool_json_buffers: std::collections::HashMap<String, String> =
         std::collections::HashMap::new();
@@ -483,11 +1583,24 @@ async fn handle_non_stream_request(
                 if let Ok(event) = Event::from_frame(frame) {
                     match event {
                         Event::AssistantResponse(resp) => {
+                            finish_reasoning_block(&mut content, &mut reasoning_text);
                             text_content.push_str(&resp.content);
                         }
+                        Event::ReasoningContent(reasoning) => {
+                            finish_text_block(&mut content, &mut text_content, &mut text_filter);
+                            if let Some(data) = reasoning.redacted_content {
+                                content.push(json!({"type": "redacted_thinking", "data": data}));
+                            }
+                            reasoning_text.push_str(&reasoning.text);
+                            if let Some(signature) = reasoning.signature {
+                                content.push(json!({
+                                    "type": "thinking",
+                                    "thinking": std::mem::take(&mut reasoning_text),
+                                    "signature": signature
+                                }));
+                            }
+                        }
                         Event::ToolUse(tool_use) => {
-                            has_tool_use = true;
-
                             // 累积工具的 JSON 输入
                             let buffer = tool_json_buffers
                                 .entry(tool_use.tool_use_id.clone())
@@ -496,6 +1609,42 @@ async fn handle_non_stream_request(
 
                             // 如果是完整的工具调用，添加到列表
                             if tool_use.stop {
+                                // 参数 JSON 损坏时先尝试修复，无法修复则降级为文本
+                                let recovery = repair_tool_inputs.then(|| {
+                                    recover_tool_input(
+                                        &tool_use.tool_use_id,
+                                        &tool_use.name,
+                                        buffer,
+                                    )
+                                });
+                                finish_reasoning_block(&mut content, &mut reasoning_text);
+                                finish_text_block(
+                                    &mut content,
+                                    &mut text_content,
+                                    &mut text_filter,
+                                );
+                                let buffer = match recovery {
+                                    Some(ToolInputRecovery::Unrecoverable) => {
+                                        // 降级文本不经过后处理，避免说明与原始参数被过滤器改写
+                                        let text = degraded_tool_text(
+                                            &tool_use.tool_use_id,
+                                            &tool_use.name,
+                                            buffer,
+                                        );
+                                        content.push(json!({"type": "text", "text": text}));
+                                        degraded_tool_input = true;
+                                        continue;
+                                    }
+                                    Some(ToolInputRecovery::Valid(input))
+                                    | Some(ToolInputRecovery::Repaired(input)) => input,
+                                    None => buffer.clone(),
+                                };
+                                has_tool_use = true;
+
+                                let check = tool_validator.as_ref().map(|v| {
+                                    v.check(&tool_use.tool_use_id, &tool_use.name, &buffer)
+                                });
+                                let buffer = check.as_ref().map_or(buffer.as_str(), |c| c.input.as_str());
                                 let input: serde_json::Value = if buffer.is_empty() {
                                     serde_json::json!({})
                                 } else {
@@ -509,12 +1658,20 @@ async fn handle_non_stream_request(
                                         })
                                 };
 
-                                tool_uses.push(json!({
+                                let mut block = json!({
                                     "type": "tool_use",
                                     "id": tool_use.tool_use_id,
                                     "name": tool_use.name,
                                     "input": input
-                                }));
+                                });
+                                if let Some((check, validator)) = check.zip(tool_validator.as_ref()) {
+                                    if let Some(errors) = check.annotation(validator.policy()) {
+                                        block["is_error"] = json!(true);
+                                        block["validation_errors"] = errors;
+                                    }
+                                    tool_validation_failed |= !check.errors.is_empty();
+                                }
+                                content.push(block);
                             }
                         }
                         Event::ContextUsage(context_usage) => {
@@ -535,10 +1692,24 @@ async fn handle_non_stream_request(
                                 actual_input_tokens
                             );
                         }
-                        Event::Exception { exception_type, .. } => {
-                            if exception_type == "ContentLengthExceededException" {
+                        Event::Exception(exception) => {
+                            if exception.is_content_length_exceeded() {
+                                tracing::info!(
+                                    "上游返回 {}，stop_reason 设为 max_tokens",
+                                    exception.exception_type
+                                );
                                 stop_reason = "max_tokens".to_string();
+                                continue;
                             }
+                            tracing::warn!("收到异常事件: {}", exception);
+                            return upstream_error_response(
+                                &exception.exception_type,
+                                exception.to_string(),
+                            );
+                        }
+                        Event::Error(error) => {
+                            tracing::error!("收到错误事件: {}", error);
+                            return upstream_error_response(&error.error_code, error.to_string());
                         }
                         _ => {}
                     }
@@ -554,28 +1725,46 @@ async fn handle_non_stream_request(
     if has_tool_use && stop_reason == "end_turn" {
         stop_reason = "tool_use".to_string();
     }
-
-    // 构建响应内容
-    let mut content: Vec<serde_json::Value> = Vec::new();
-
-    if !text_content.is_empty() {
-        content.push(json!({
-            "type": "text",
-            "text": text_content
-        }));
+    // 响应体被截断：未完成的工具调用已丢弃，按输出耗尽处理
+    if truncated {
+        tracing::warn!(
+            max_response_bytes,
+            "上游响应体超过 maxResponseBytes，已截断，stop_reason 设为 max_tokens"
+        );
+        stop_reason = "max_tokens".to_string();
     }
 
-    content.extend(tool_uses);
+    // 收尾最后的文本与 thinking 块（thinking 签名原样返回供客户端下一轮回传）
+    finish_reasoning_block(&mut content, &mut reasoning_text);
+    finish_text_block(&mut content, &mut text_content, &mut text_filter);
+    let json_output_invalid = text_filter
+        .as_ref()
+        .is_some_and(|filter| filter.json_output_valid() == Some(false));
 
     // 估算输出 tokens
     let output_tokens = token::estimate_output_tokens(&content);
 
+    // 上游未给出截断信号时，按输出 token 估算判断是否耗尽了预算
+    let budget_reached = max_tokens.is_some_and(|max| reaches_max_tokens(output_tokens, max));
+    if budget_reached && stop_reason == "end_turn" {
+        tracing::info!(
+            output_tokens,
+            max_tokens = ?max_tokens,
+            "输出 tokens 估算值达到 max_tokens 预算，stop_reason 设为 max_tokens"
+        );
+        stop_reason = "max_tokens".to_string();
+    }
+
     // 使用从 contextUsageEvent 计算的 input_tokens，如果没有则使用估算值
     let final_input_tokens = context_input_tokens.unwrap_or(input_tokens);
+    if let Some(recorder) = usage_recorder {
+        recorder.record(final_input_tokens, output_tokens);
+    }
 
     // 构建 Anthropic 响应
-    let response_body = json!({
-        "id": format!("msg_{}", Uuid::new_v4().to_string().replace('-', "")),
+    let message_id = format!("msg_{}", Uuid::new_v4().to_string().replace('-', ""));
+    let mut response_body = json!({
+        "id": message_id,
         "type": "message",
         "role": "assistant",
         "content": content,
@@ -587,8 +1776,140 @@ async fn handle_non_stream_request(
             "output_tokens": output_tokens
         }
     });
+    if cache_usage {
+        add_cache_usage_fields(&mut response_body["usage"]);
+    }
+
+    // 暂存响应，客户端可通过 Location 指向的地址重新获取
+    let location = response_store
+        .zip(api_key)
+        .filter(|(store, api_key)| store.insert(api_key, message_id.clone(), response_body.clone()))
+        .map(|_| format!("/v1/messages/{}", message_id));
+
+    let mut response = (StatusCode::OK, Json(response_body)).into_response();
+    if let Some(location) = location.and_then(|l| header::HeaderValue::try_from(l).ok()) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    if tool_validation_failed
+        && tool_validator
+            .as_ref()
+            .is_some_and(|v| v.policy() == ToolInputValidationPolicy::Warn)
+    {
+        response.headers_mut().insert(
+            TOOL_INPUT_VALIDATION_HEADER,
+            header::HeaderValue::from_static("failed"),
+        );
+    }
+    if degraded_tool_input {
+        response.headers_mut().append(
+            DEGRADED_RESPONSE_HEADER,
+            header::HeaderValue::from_static("tool-input"),
+        );
+    }
+    if json_output_invalid {
+        tracing::warn!("JSON 模式下输出不是合法 JSON");
+        response.headers_mut().append(
+            DEGRADED_RESPONSE_HEADER,
+            header::HeaderValue::from_static("json-output"),
+        );
+    }
+    if truncated {
+        response.headers_mut().append(
+            DEGRADED_RESPONSE_HEADER,
+            header::HeaderValue::from_static("response-size"),
+        );
+    }
+    with_served_credential(response, served)
+}
+
+/// 读取非流式响应体，累计超过 `max_bytes` 时截断并停止读取（丢弃剩余内容）
+///
+/// 返回 (响应体, 是否被截断)
+async fn read_body_capped(
+    mut response: reqwest::Response,
+    max_bytes: usize,
+) -> reqwest::Result<(Bytes, bool)> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        let room = max_bytes - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            return Ok((body.freeze(), true));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((body.freeze(), false))
+}
+
+/// 提取非流式响应中的全部助手文本
+fn assistant_text(body: &[u8]) -> String {
+    let mut decoder = EventStreamDecoder::new();
+    if let Err(e) = decoder.feed(body) {
+        tracing::warn!("缓冲区溢出: {}", e);
+    }
+    decoder
+        .decode_iter()
+        .filter_map(|frame| Event::from_frame(frame.ok()?).ok())
+        .filter_map(|event| match event {
+            Event::AssistantResponse(resp) => Some(resp.content),
+            _ => None,
+        })
+        .collect()
+}
+
+/// JSON 模式纠正重试：响应文本不是合法 JSON 时，追加上一轮输出与纠正提示后重新请求一次
+///
+/// 重试经过同一 Provider（同样的凭据故障转移与重试上限），且每个请求最多一次。
+/// 输出合法、无文本输出或重试失败时返回 None，保留原响应
+async fn retry_invalid_json(
+    provider: &Arc<dyn Provider>,
+    call: MessagesCall<'_>,
+    retry: JsonRetry,
+    processors: &OutputProcessors,
+    body: &[u8],
+) -> Option<(Option<ServedCredential>, (Bytes, bool))> {
+    let mut filter = processors.text_filter_stream()?;
+    let text = filter.apply_outside_thinking(&assistant_text(body));
+    if filter.json_output_valid() != Some(false) {
+        return None;
+    }
+    tracing::info!("JSON 模式下输出不是合法 JSON，追加纠正对话重试一次");
 
-    (StatusCode::OK, Json(response_body)).into_response()
+    let mut request = retry.request;
+    request.messages.push(Message {
+        role: "assistant".to_string(),
+        content: json!(text),
+    });
+    request.messages.push(Message {
+        role: "user".to_string(),
+        content: json!(JSON_MODE_CORRECTION),
+    });
+    let (request_body, _) = build_upstream_body(
+        &request,
+        retry.profile_arn,
+        &retry.backend,
+        retry.redactor.as_deref(),
+    )
+    .ok()?;
+    let retry_call = MessagesCall {
+        request_body: &request_body,
+        ..call
+    };
+    let response = match provider.call_messages(retry_call).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("JSON 模式纠正重试失败: {}", e);
+            return None;
+        }
+    };
+    let served = served_credential(&response);
+    match read_body_capped(response, processors.max_response_bytes).await {
+        Ok(body) => Some((served, body)),
+        Err(e) => {
+            tracing::warn!("读取 JSON 模式纠正重试响应失败: {}", e);
+            None
+        }
+    }
 }
 
 /// 检测模型名是否包含 "thinking" 后缀，若包含则覆写 thinking 配置
@@ -629,52 +1950,18 @@ fn override_thinking_from_model_name(payload: &mut MessagesRequest) {
     }
 }
 
-/// POST /v1/messages/count_tokens
-///
-/// 计算消息的 token 数量
-pub async fn count_tokens(
-    JsonExtractor(payload): JsonExtractor<CountTokensRequest>,
-) -> impl IntoResponse {
-    tracing::info!(
-        model = %payload.model,
-        message_count = %payload.messages.len(),
-        "Received POST /v1/messages/count_tokens request"
-    );
-
-    let total_tokens = token::count_all_tokens(
-        payload.model,
-        payload.system,
-        payload.messages,
-        payload.tools,
-    ) as i32;
-
-    Json(CountTokensResponse {
-        input_tokens: total_tokens.max(1) as i32,
-    })
-}
-
-/// POST /cc/v1/messages
+/// POST /v1/messages/dry-run
 ///
-/// Claude Code 兼容端点，与 /v1/messages 的区别在于：
-/// - 流式响应会等待 kiro 端返回 contextUsageEvent 后再发送 message_start
-/// - message_start 中的 input_tokens 是从 contextUsageEvent 计算的准确值
-pub async fn post_messages_cc(
+/// 返回请求转换后将发往上游的完整内容（请求体、模型映射、选中的凭据、URL 与请求头），
+/// 不发起任何网络调用，也不会刷新 Token 或切换当前凭据。Authorization 请求头已脱敏。
+pub async fn post_messages_dry_run(
     State(state): State<AppState>,
+    headers: HeaderMap,
     JsonExtractor(mut payload): JsonExtractor<MessagesRequest>,
 ) -> Response {
-    tracing::info!(
-        model = %payload.model,
-        max_tokens = %payload.max_tokens,
-        stream = %payload.stream,
-        message_count = %payload.messages.len(),
-        "Received POST /cc/v1/messages request"
-    );
-
-    // 检查 KiroProvider 是否可用
     let provider = match &state.kiro_provider {
         Some(p) => p.clone(),
         None => {
-            tracing::error!("KiroProvider 未配置");
             return (
                 StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
@@ -686,76 +1973,390 @@ pub async fn post_messages_cc(
         }
     };
 
-    // 检测模型名是否包含 "thinking" 后缀，若包含则覆写 thinking 配置
-    override_thinking_from_model_name(&mut payload);
+    if let Some(response) = reject_disallowed_model(&state, &payload.model) {
+        return response;
+    }
 
-    // 检查是否为 WebSearch 请求
-    if websearch::has_web_search_tool(&payload) {
-        tracing::info!("检测到 WebSearch 工具，路由到 WebSearch 处理");
+    // 仅能预览发往 Kiro 的请求
+    let (backend, _) = state.route_model(&payload.model);
+    if backend != KIRO_BACKEND {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                "invalid_request_error",
+                format!(
+                    "Dry-run only supports models routed to Kiro (model is routed to backend '{}').",
+                    backend
+                ),
+            )),
+        )
+            .into_response();
+    }
 
-        // 估算输入 tokens
-        let input_tokens = token::count_all_tokens(
-            payload.model.clone(),
-            payload.system.clone(),
-            payload.messages.clone(),
-            payload.tools.clone(),
-        ) as i32;
+    // 图片内容块校验（在任何上游调用之前）
+    if let Some(response) = reject_invalid_images(&payload.messages) {
+        return response;
+    }
 
-        return websearch::handle_websearch_request(provider, &payload, input_tokens).await;
+    if let Err(response) = check_sampling_params(&state, &payload) {
+        return *response;
     }
 
-    // 转换请求
-    let conversion_result = match convert_request(&payload) {
-        Ok(result) => result,
-        Err(e) => {
-            let (error_type, message) = match &e {
-                ConversionError::UnsupportedModel(model) => {
-                    ("invalid_request_error", format!("模型不支持: {}", model))
-                }
-                ConversionError::EmptyMessages => {
-                    ("invalid_request_error", "消息列表为空".to_string())
-                }
-            };
-            tracing::warn!("请求转换失败: {}", e);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse::new(error_type, message)),
-            )
+    let pinned = match pinned_credential(&state, &headers) {
+        Ok(pinned) => pinned,
+        Err(response) => return *response,
+    };
+
+    override_thinking_from_model_name(&mut payload);
+
+    // WebSearch 请求走 MCP 接口，请求结构与对话接口不同
+    if websearch::has_web_search_tool(&payload) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                "invalid_request_error",
+                "Dry-run does not support web_search requests.",
+            )),
+        )
+            .into_response();
+    }
+
+    apply_operator_system_prompt(&state, &mut payload);
+    apply_json_mode(&mut payload, &headers);
+
+    let profile_arn = state.profile_arn.clone();
+    let (request_body, redactions) = match build_upstream_body(
+        &payload,
+        profile_arn,
+        KIRO_BACKEND,
+        state.redactor.as_deref(),
+    ) {
+        Ok(body) => body,
+        Err(response) => return *response,
+    };
+
+    let upstream = match provider.preview_request(&request_body, pinned) {
+        Ok(upstream) => upstream,
+        Err(e) => return map_provider_error(e),
+    };
+
+    let upstream_headers: serde_json::Map<String, serde_json::Value> = upstream
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == header::AUTHORIZATION {
+                "Bearer [REDACTED]".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), json!(value))
+        })
+        .collect();
+    let upstream_payload: serde_json::Value =
+        serde_json::from_str(&upstream.body).unwrap_or(serde_json::Value::Null);
+
+    let response = Json(json!({
+        "model": payload.model,
+        "modelId": map_model(&payload.model),
+        "credentialId": upstream.credential_id,
+        "profileArn": state.profile_arn,
+        "machineId": upstream.machine_id,
+        "url": upstream.url,
+        "headers": upstream_headers,
+        "systemPrompt": build_system_prompt(&payload),
+        "payload": upstream_payload,
+    }))
+    .into_response();
+    with_redactions_header(response, &redactions)
+}
+
+/// POST /v1/messages/count_tokens
+///
+/// 计算消息的 token 数量
+pub async fn count_tokens(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    JsonExtractor(payload): JsonExtractor<CountTokensRequest>,
+) -> Response {
+    tracing::info!(
+        model = %payload.model,
+        message_count = %payload.messages.len(),
+        "Received POST /v1/messages/count_tokens request"
+    );
+
+    // 未配置外部 count_tokens API 时可经 Kiro 上游计数，失败时回退到本地估算
+    let mut upstream_failed = false;
+    if let Some(provider) = upstream_count_provider(&state) {
+        match count_tokens_upstream(
+            provider,
+            &payload,
+            state.profile_arn.clone(),
+            state.redactor.as_deref(),
+        )
+        .await
+        {
+            Ok((total_tokens, served)) => {
+                let response = Json(CountTokensResponse {
+                    input_tokens: (total_tokens as i32).max(1),
+                })
                 .into_response();
+                return with_served_credential(response, Some(served));
+            }
+            Err(e) => {
+                tracing::warn!("经 Kiro 上游计数失败，回退到本地计算: {}", e);
+                upstream_failed = true;
+            }
         }
-    };
+    }
 
-    // 构建 Kiro 请求
-    let kiro_request = KiroRequest {
-        conversation_state: conversion_result.conversation_state,
-        profile_arn: state.profile_arn.clone(),
+    let (total_tokens, source) = token::count_all_tokens_with_source(
+        payload.model,
+        payload.system,
+        payload.messages,
+        payload.tools,
+        &count_context(&state, &headers),
+    );
+
+    let mut response = Json(CountTokensResponse {
+        input_tokens: (total_tokens as i32).max(1),
+    })
+    .into_response();
+
+    // 远程 API 或 Kiro 上游计数失败时标注已回退到本地估算
+    if upstream_failed || source == token::TokenCountSource::LocalFallback {
+        response.headers_mut().insert(
+            TOKEN_COUNT_FALLBACK_HEADER,
+            header::HeaderValue::from_static("local"),
+        );
+    }
+
+    // 本地计数不调用上游，以当前活跃凭据作为服务凭据
+    let served = state
+        .current_credential_snapshot()
+        .map(|c| ServedCredential {
+            id: c.id,
+            auth_method: c.auth_method,
+        });
+    with_served_credential(response, served)
+}
+
+/// 开启 `countTokensFallback.useUpstream` 且未配置外部 count_tokens API 时返回用于计数的 Provider
+fn upstream_count_provider(state: &AppState) -> Option<&Arc<KiroProvider>> {
+    if token::remote_api_configured() {
+        return None;
+    }
+    state.kiro_provider.as_ref().filter(|p| {
+        p.token_manager()
+            .config()
+            .count_tokens_fallback
+            .use_upstream
+    })
+}
+
+/// 将 count_tokens 请求转换为 Kiro 请求体并经上游计数（同样经过出站脱敏，blocking 规则命中时回退到本地估算）
+async fn count_tokens_upstream(
+    provider: &KiroProvider,
+    payload: &CountTokensRequest,
+    profile_arn: Option<String>,
+    redactor: Option<&Redactor>,
+) -> anyhow::Result<(u64, ServedCredential)> {
+    let request = MessagesRequest {
+        model: payload.model.clone(),
+        max_tokens: 1,
+        messages: payload.messages.clone(),
+        stream: false,
+        system: payload.system.clone(),
+        tools: payload.tools.clone(),
+        tool_choice: None,
+        thinking: None,
+        output_config: None,
+        metadata: None,
+        response_format: None,
+        temperature: None,
+        top_p: None,
+        top_k: None,
     };
+    let (request_body, _) = build_upstream_body(&request, profile_arn, KIRO_BACKEND, redactor)
+        .map_err(|_| anyhow::anyhow!("请求转换失败"))?;
+    provider.count_tokens(&request_body).await
+}
 
-    let request_body = match serde_json::to_string(&kiro_request) {
-        Ok(body) => body,
-        Err(e) => {
-            tracing::error!("序列化请求失败: {}", e);
+/// POST /cc/v1/messages
+///
+/// Claude Code 兼容端点，与 /v1/messages 的区别在于：
+/// - 流式响应会等待 kiro 端返回 contextUsageEvent 后再发送 message_start
+/// - message_start 中的 input_tokens 是从 contextUsageEvent 计算的准确值
+pub async fn post_messages_cc(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_id: Option<Extension<RequestId>>,
+    JsonExtractor(mut payload): JsonExtractor<MessagesRequest>,
+) -> Response {
+    tracing::info!(
+        model = %payload.model,
+        max_tokens = %payload.max_tokens,
+        stream = %payload.stream,
+        message_count = %payload.messages.len(),
+        "Received POST /cc/v1/messages request"
+    );
+
+    // 按模型选择上游后端并检查其 Provider 是否可用
+    let (backend, provider) = state.route_model(&payload.model);
+    let backend = backend.to_string();
+    let provider = match provider {
+        Some(p) => p,
+        None => {
+            tracing::error!("上游后端 {} 的 Provider 未配置", backend);
             return (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
-                    "internal_error",
-                    format!("序列化请求失败: {}", e),
+                    "service_unavailable",
+                    "Kiro API provider not configured",
                 )),
             )
                 .into_response();
         }
     };
 
+    // anthropic-beta 特性（rejectBetas 检查在任何上游调用之前）
+    let betas = match resolve_betas(&state, &headers) {
+        Ok(betas) => betas,
+        Err(response) => return *response,
+    };
+    let beta_header = betas.header_value();
+
+    // 模型白名单检查（在任何上游调用之前）
+    if let Some(response) = reject_disallowed_model(&state, &payload.model) {
+        return response;
+    }
+
+    // 图片内容块校验（在任何上游调用之前）
+    if let Some(response) = reject_invalid_images(&payload.messages) {
+        return response;
+    }
+
+    // 采样参数校验（在任何上游调用之前）
+    let dropped_params = match check_sampling_params(&state, &payload) {
+        Ok(dropped) => dropped,
+        Err(response) => return *response,
+    };
+
+    // A/B 路由：X-AB-Variant 指定凭据（需 X-Admin-Key）
+    let pinned = match pinned_credential(&state, &headers) {
+        Ok(pinned) => pinned,
+        Err(response) => return *response,
+    };
+
+    // 透传模式：使用客户端提供的上游 Token（X-Passthrough 需 X-Admin-Key）
+    let passthrough = match passthrough_token(&state, &headers, &backend) {
+        Ok(token) => token,
+        Err(response) => return *response,
+    };
+
+    // 订阅等级检查（仅托管凭据的 Kiro 请求）
+    if let Some(response) =
+        reject_unsubscribed_model(&state, &backend, passthrough.is_some(), &payload.model)
+    {
+        return response;
+    }
+
+    // 单请求上游超时（x-kiro-timeout-secs，受 maxRequestTimeoutSecs 限制）
+    let timeout = match request_timeout(&state, &headers) {
+        Ok(timeout) => timeout,
+        Err(response) => return *response,
+    };
+
+    // 检测模型名是否包含 "thinking" 后缀，若包含则覆写 thinking 配置
+    override_thinking_from_model_name(&mut payload);
+
+    // 按模型限制 max_tokens 上限
+    let max_tokens_clamped = clamp_max_tokens(&state, &mut payload);
+
+    // 检查是否为 WebSearch 请求（依赖 Kiro MCP 接口与托管凭据，模拟上游模式、透传模式或路由到其他后端时按普通请求处理）
+    let websearch_provider = state.kiro_provider.clone().filter(|_| {
+        backend == KIRO_BACKEND && passthrough.is_none() && websearch::has_web_search_tool(&payload)
+    });
+    if let Some(kiro_provider) = websearch_provider {
+        tracing::info!("检测到 WebSearch 工具，路由到 WebSearch 处理");
+
+        let user_id = payload.metadata.as_ref().and_then(|m| m.user_id.as_deref());
+        let (usage_recorder, queue_permit) =
+            match admit_upstream_call(&state, user_id, &payload.model).await {
+                Ok(admission) => admission,
+                Err(response) => return *response,
+            };
+
+        // 估算输入 tokens
+        let input_tokens = token::count_all_tokens(
+            payload.model.clone(),
+            payload.system.clone(),
+            payload.messages.clone(),
+            payload.tools.clone(),
+            &count_context(&state, &headers),
+        ) as i32;
+        if let Some(recorder) = usage_recorder {
+            recorder.record(input_tokens, 0);
+        }
+
+        let response =
+            websearch::handle_websearch_request(kiro_provider, &payload, input_tokens).await;
+        let response = with_queue_permit(response, queue_permit);
+        let response = with_max_tokens_clamped_header(response, max_tokens_clamped);
+        let response = with_unsupported_params_header(response, &dropped_params);
+        return with_beta_header(response, beta_header);
+    }
+
+    // 合并运营方系统提示词
+    apply_operator_system_prompt(&state, &mut payload);
+
+    // JSON 模式：追加只输出 JSON 的指令
+    let json_mode = apply_json_mode(&mut payload, &headers);
+
+    // 转换请求并构建 Kiro 请求体
+    let (request_body, redactions) = match build_upstream_body(
+        &payload,
+        state.profile_arn.clone(),
+        &backend,
+        state.redactor.as_deref(),
+    ) {
+        Ok(body) => body,
+        Err(response) => return *response,
+    };
+
     tracing::debug!("Kiro request body: {}", request_body);
 
+    // 工具输入校验器与文本过滤器（需在 tools 被移动前构建）
+    let mut processors = OutputProcessors::new(&state, payload.tools.as_deref(), betas)
+        .with_prefill(extract_prefill(&payload.messages))
+        .with_max_tokens(payload.max_tokens)
+        .with_api_key(&headers);
+    if json_mode {
+        processors = processors.with_json_mode(&state, &payload);
+    }
+
     // 估算输入 tokens
     let input_tokens = token::count_all_tokens(
         payload.model.clone(),
         payload.system,
         payload.messages,
         payload.tools,
+        &count_context(&state, &headers),
     ) as i32;
 
+    // 预检对话长度（超出上下文窗口时不再调用上游）
+    if let Some(response) =
+        check_prompt_length(&state, &payload.model, input_tokens, payload.max_tokens)
+    {
+        return response;
+    }
+
+    // 本地校验全部通过后才计入用户用量、占用排队名额
+    let user_id = payload.metadata.as_ref().and_then(|m| m.user_id.as_deref());
+    processors = match admit_upstream_call(&state, user_id, &payload.model).await {
+        Ok(admission) => processors.with_admission(admission),
+        Err(response) => return *response,
+    };
+
     // 检查是否启用了thinking
     let thinking_enabled = payload
         .thinking
@@ -763,20 +2364,48 @@ pub async fn post_messages_cc(
         .map(|t| t.is_enabled())
         .unwrap_or(false);
 
-    if payload.stream {
+    let call = MessagesCall {
+        request_body: &request_body,
+        is_stream: payload.stream,
+        pinned,
+        max_tokens: payload.max_tokens,
+        passthrough_token: passthrough.as_deref(),
+        timeout,
+    };
+
+    // 登记进行中的请求（guard 随响应结束或被丢弃而移除登记项）
+    let guard = register_active_request(&state, request_id, &payload.model, &call);
+
+    let response = if payload.stream {
         // 流式响应（缓冲模式）
         handle_stream_request_buffered(
             provider,
-            &request_body,
+            call,
             &payload.model,
             input_tokens,
             thinking_enabled,
+            processors,
+            guard,
         )
         .await
     } else {
-        // 非流式响应（复用现有逻辑，已经使用正确的 input_tokens）
-        handle_non_stream_request(provider, &request_body, &payload.model, input_tokens).await
-    }
+        // 非流式响应（复用现有逻辑，已经使用正确的 input_tokens；被取消时丢弃上游调用）
+        tokio::select! {
+            response = handle_non_stream_request(
+                provider,
+                call,
+                &payload.model,
+                input_tokens,
+                processors,
+                &guard,
+            ) => response,
+            _ = guard.cancelled() => cancelled_response(),
+        }
+    };
+    let response = with_max_tokens_clamped_header(response, max_tokens_clamped);
+    let response = with_unsupported_params_header(response, &dropped_params);
+    let response = with_redactions_header(response, &redactions);
+    with_beta_header(response, beta_header)
 }
 
 /// 处理流式请求（缓冲版本）
@@ -784,32 +2413,59 @@ pub async fn post_messages_cc(
 /// 与 `handle_stream_request` 不同，此函数会缓冲所有事件直到流结束，
 /// 然后用从 contextUsageEvent 计算的正确 input_tokens 生成 message_start 事件。
 async fn handle_stream_request_buffered(
-    provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
-    request_body: &str,
+    provider: Arc<dyn Provider>,
+    call: MessagesCall<'_>,
     model: &str,
     estimated_input_tokens: i32,
     thinking_enabled: bool,
+    processors: OutputProcessors,
+    guard: ActiveRequestGuard,
 ) -> Response {
-    // 调用 Kiro API（支持多凭据故障转移）
-    let response = match provider.call_api_stream(request_body).await {
-        Ok(resp) => resp,
-        Err(e) => return map_provider_error(e),
+    // 调用上游 API（支持多凭据故障转移；被取消时丢弃上游调用）
+    let response = tokio::select! {
+        result = provider.call_messages(call) => match result {
+            Ok(resp) => resp,
+            Err(e) => return map_provider_error(e),
+        },
+        _ = guard.cancelled() => return cancelled_response(),
     };
 
+    let served = served_credential(&response);
+    if let Some(served) = &served {
+        guard.set_credential(served.id);
+    }
+
     // 创建缓冲流处理上下文
-    let ctx = BufferedStreamContext::new(model, estimated_input_tokens, thinking_enabled);
+    let text_filter = processors.text_filter_stream();
+    let usage_recorder = processors
+        .usage_recorder
+        .map(|r| r.with_credential(served.as_ref().map(|s| s.id)));
+    let ctx = BufferedStreamContext::new(model, estimated_input_tokens, thinking_enabled)
+        .with_tool_validator(processors.tool_validator)
+        .with_tool_input_recovery(processors.repair_tool_inputs)
+        .with_text_filter(text_filter)
+        .with_usage_recorder(usage_recorder)
+        .with_cache_usage(processors.betas.prompt_caching())
+        .with_max_tokens(processors.max_tokens);
 
-    // 创建缓冲 SSE 流
-    let stream = create_buffered_sse_stream(response, ctx);
+    // 创建缓冲 SSE 流（流结束后归还并发许可并移除登记项，被取消时以 error 事件结束）
+    let stream = until_cancelled(
+        hold_permit(
+            create_buffered_sse_stream(response, ctx),
+            processors.queue_permit,
+        ),
+        guard,
+    );
 
     // 返回 SSE 响应
-    Response::builder()
+    let response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "text/event-stream")
         .header(header::CACHE_CONTROL, "no-cache")
         .header(header::CONNECTION, "keep-alive")
         .body(Body::from_stream(stream))
-        .unwrap()
+        .unwrap();
+    with_served_credential(response, served)
 }
 
 /// 创建缓冲 SSE 事件流
@@ -873,6 +2529,15 @@ fn create_buffered_sse_stream(
                                         }
                                     }
                                 }
+                                // 上游中途返回异常：返回已缓冲的事件（以 error 事件结尾）并结束
+                                if ctx.upstream_failed() {
+                                    let all_events = ctx.finish_and_get_all_events();
+                                    let bytes: Vec<Result<Bytes, Infallible>> = all_events
+                                        .into_iter()
+                                        .map(|e| Ok(Bytes::from(e.to_sse_string())))
+                                        .collect();
+                                    return Some((stream::iter(bytes), (body_stream, ctx, decoder, true, ping_interval)));
+                                }
                                 // 继续读取下一个 chunk，不发送任何数据
                             }
                             Some(Err(e)) => {
@@ -902,3 +2567,2431 @@ fn create_buffered_sse_stream(
     )
     .flatten()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::credentials::KiroCredentials;
+    use crate::kiro::provider::KiroProvider;
+    use crate::kiro::token_manager::MultiTokenManager;
+    use crate::model::config::Config;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// 启动模拟上游，统计请求次数（返回空的事件流）
+    async fn spawn_upstream() -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::OK
+                }
+            }),
+        );
+        (spawn(router).await, hits)
+    }
+
+    async fn spawn(router: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    /// 拥有有效 Token 的测试凭据（无需刷新）
+    fn valid_credentials(seed: &str) -> KiroCredentials {
+        KiroCredentials {
+            access_token: Some("test_token".to_string()),
+            refresh_token: Some(seed.repeat(150)),
+            expires_at: Some((chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        }
+    }
+
+    /// 启动指向模拟上游的代理服务器
+    async fn spawn_proxy_with(
+        config: Config,
+        credentials: Vec<KiroCredentials>,
+        upstream: &str,
+    ) -> String {
+        let manager = MultiTokenManager::new(config, credentials, None, None, false).unwrap();
+        let provider = KiroProvider::new(Arc::new(manager)).with_endpoint_override(upstream);
+        spawn(crate::anthropic::router::create_router_with_provider(
+            "test-key",
+            Some(provider),
+            None,
+        ))
+        .await
+    }
+
+    /// 启动配置了 allowedModels 的代理服务器
+    async fn spawn_proxy(allowed_models: Vec<&str>, upstream: &str) -> String {
+        let mut config = Config::default();
+        config.allowed_models = Some(allowed_models.into_iter().map(String::from).collect());
+        spawn_proxy_with(config, vec![valid_credentials("a")], upstream).await
+    }
+
+    async fn post_model(base: &str, model: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&json!({
+                "model": model,
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_model_rejected_before_upstream() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_proxy(vec!["claude-sonnet-4-5"], &upstream).await;
+
+        let resp = post_model(&base, "claude-opus-4-5").await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(
+            body["error"]["message"],
+            "Model 'claude-opus-4-5' is not in the allowed models list."
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    fn subscribed_credentials(seed: &str, subscription_title: &str) -> KiroCredentials {
+        KiroCredentials {
+            subscription_title: Some(subscription_title.to_string()),
+            ..valid_credentials(seed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_free_credential_rejected_for_pro_model() {
+        let (upstream, hits) = spawn_upstream().await;
+        let credentials = vec![subscribed_credentials("a", "KIRO FREE")];
+        let base = spawn_proxy_with(Config::default(), credentials, &upstream).await;
+
+        let resp = post_model(&base, "claude-opus-4-6").await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "subscription_required");
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            post_model(&base, "claude-sonnet-4-5").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pro_model_allowed_when_other_credential_supports_it() {
+        let (upstream, hits) = spawn_upstream().await;
+        let mut pro = subscribed_credentials("b", "KIRO PRO+");
+        pro.priority = 1;
+        let credentials = vec![subscribed_credentials("a", "KIRO FREE"), pro];
+        let base = spawn_proxy_with(Config::default(), credentials, &upstream).await;
+
+        assert_eq!(
+            post_model(&base, "claude-opus-4-6").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_skips_subscription_check_only_with_admin_key() {
+        let (upstream, hits) = spawn_upstream().await;
+        let mut config = Config::default();
+        config.admin_api_key = Some("admin-key".to_string());
+        let credentials = vec![subscribed_credentials("a", "KIRO FREE")];
+        let base = spawn_proxy_with(config, credentials, &upstream).await;
+
+        let post = |headers: &'static [(&'static str, &'static str)]| {
+            let mut request = reqwest::Client::new()
+                .post(format!("{}/v1/messages", base))
+                .header("x-api-key", "test-key")
+                .header("authorization", "Bearer client-aws-token")
+                .json(&json!({
+                    "model": "claude-opus-4-6",
+                    "max_tokens": 16,
+                    "messages": [{ "role": "user", "content": "hi" }]
+                }));
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            request.send()
+        };
+
+        // 非 true 的值仍然检查订阅，缺少有效 X-Admin-Key 时拒绝透传
+        for headers in [
+            &[("x-passthrough", "false")][..],
+            &[("x-passthrough", "true")][..],
+            &[("x-passthrough", "true"), ("x-admin-key", "wrong")][..],
+        ] {
+            let resp = post(headers).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{:?}", headers);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        let resp = post(&[("x-passthrough", "true"), ("x-admin-key", "admin-key")])
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// 启动模拟 Token 计数上游：返回 `status`，成功时 tokenCount 为 1234
+    async fn spawn_count_upstream(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = axum::Router::new().route(
+            "/getTokenCount",
+            axum::routing::post(move |body: String| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    assert!(request["conversationState"].is_object(), "{}", body);
+                    (status, Json(json!({ "tokenCount": 1234 })))
+                }
+            }),
+        );
+        (spawn(router).await, hits)
+    }
+
+    async fn post_count_tokens(base: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages/count_tokens", base))
+            .header("x-api-key", "test-key")
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{ "role": "user", "content": "hello" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_uses_upstream_when_enabled() {
+        let (upstream, hits) = spawn_count_upstream(StatusCode::OK).await;
+        let mut config = Config::default();
+        config.count_tokens_fallback.use_upstream = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_count_tokens(&base).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(TOKEN_COUNT_FALLBACK_HEADER).is_none());
+        let body: CountTokensResponse = resp.json().await.unwrap();
+        assert_eq!(body.input_tokens, 1234);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // 未开启时使用本地估算，不请求上游
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+        let body: CountTokensResponse = post_count_tokens(&base).await.json().await.unwrap();
+        assert_ne!(body.input_tokens, 1234);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_upstream_failure_falls_back_to_local() {
+        let (upstream, hits) = spawn_count_upstream(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let mut config = Config::default();
+        config.count_tokens_fallback.use_upstream = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_count_tokens(&base).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[TOKEN_COUNT_FALLBACK_HEADER], "local");
+        let body: CountTokensResponse = resp.json().await.unwrap();
+        assert!(body.input_tokens >= 1);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    fn image_request(media_type: &str, data: &str) -> serde_json::Value {
+        json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 16,
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "what is this?" },
+                    {
+                        "type": "image",
+                        "source": { "type": "base64", "media_type": media_type, "data": data }
+                    }
+                ]
+            }]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_invalid_image_rejected_before_upstream() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_proxy(vec!["claude-sonnet-4-5"], &upstream).await;
+
+        let cases = [
+            ("image/bmp", "iVBORw0KGgo=", "image/bmp"),
+            ("", "iVBORw0KGgo=", "Unsupported image media type"),
+            ("image/png", "", "must not be empty"),
+            ("image/png", "iVBOR*w0KGgo", "not valid base64"),
+            ("image/png", "iVBORw0K=Ggo", "not valid base64"),
+        ];
+        for (media_type, data, expected) in cases {
+            let resp =
+                post_messages_json(&base, "/v1/messages", image_request(media_type, data)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", media_type);
+            let body: serde_json::Value = resp.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+            let message = body["error"]["message"].as_str().unwrap();
+            assert!(message.contains(expected), "{}", message);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        let valid = image_request("image/png", "iVBORw0KGgo=");
+        let resp = post_messages_json(&base, "/v1/messages", valid).await;
+        assert_ne!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_user_daily_limit_and_usage_accounting() {
+        let (upstream, hits) = spawn_upstream().await;
+        let mut config = Config::default();
+        config.user_limits = std::collections::HashMap::from([("user_abc_account".to_string(), 2)]);
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![valid_credentials("a")], None, None, false)
+                .unwrap(),
+        );
+        let provider = KiroProvider::new(manager.clone()).with_endpoint_override(&upstream);
+        let base = spawn(crate::anthropic::router::create_router_with_provider(
+            "test-key",
+            Some(provider),
+            None,
+        ))
+        .await;
+
+        let post = |metadata: serde_json::Value| {
+            let base = base.clone();
+            async move {
+                reqwest::Client::new()
+                    .post(format!("{}/v1/messages", base))
+                    .header("x-api-key", "test-key")
+                    .json(&json!({
+                        "model": "claude-sonnet-4-5",
+                        "max_tokens": 16,
+                        "metadata": metadata,
+                        "messages": [{ "role": "user", "content": "hi" }]
+                    }))
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+        let user =
+            json!({ "user_id": "user_abc_account__session_0b4445e1-f5be-49e1-87ce-62bbc28ad705" });
+
+        for _ in 0..2 {
+            assert_eq!(post(user.clone()).await.status(), StatusCode::OK);
+        }
+        let resp = post(user.clone()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // 未携带 user_id 的请求归入 anonymous，不受其他用户上限影响
+        assert_eq!(post(json!({})).await.status(), StatusCode::OK);
+
+        let users = manager.user_usage();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].user_id, "anonymous");
+        assert_eq!(users[0].request_count, 1);
+        assert_eq!(users[1].user_id, "user_abc_account");
+        assert_eq!(users[1].request_count, 2);
+        assert_eq!(users[1].daily_requests, 2);
+        assert_eq!(users[1].daily_limit, Some(2));
+        assert!(users[1].input_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_request_does_not_consume_user_quota() {
+        let (upstream, hits) = spawn_upstream().await;
+        let mut config = Config::default();
+        config.user_limits = std::collections::HashMap::from([("user_abc_account".to_string(), 1)]);
+        config.prompt_too_long_policy = PromptTooLongPolicy::Reject;
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![valid_credentials("a")], None, None, false)
+                .unwrap(),
+        );
+        let provider = KiroProvider::new(manager.clone()).with_endpoint_override(&upstream);
+        let base = spawn(crate::anthropic::router::create_router_with_provider(
+            "test-key",
+            Some(provider),
+            None,
+        ))
+        .await;
+
+        let post = |max_tokens: i64| {
+            let base = base.clone();
+            async move {
+                post_json(
+                    &base,
+                    &json!({
+                        "model": "claude-sonnet-4-5",
+                        "max_tokens": max_tokens,
+                        "metadata": {
+                            "user_id": "user_abc_account__session_0b4445e1-f5be-49e1-87ce-62bbc28ad705"
+                        },
+                        "messages": [{ "role": "user", "content": "hi" }]
+                    }),
+                )
+                .await
+            }
+        };
+
+        // 本地校验失败（prompt too long）的请求不占用当日额度
+        let resp = post(i64::from(CONTEXT_WINDOW_SIZE) + 1).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+        assert!(manager.user_usage().is_empty());
+
+        assert_eq!(post(16).await.status(), StatusCode::OK);
+        assert_eq!(post(16).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        let users = manager.user_usage();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].daily_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_non_stream_repairs_or_degrades_corrupted_tool_input() {
+        use crate::kiro::parser::frame::encode_frame;
+
+        let tool_frame = |id: &str, input: &str| {
+            encode_frame(
+                &[
+                    (":message-type", "event"),
+                    (":event-type", "toolUseEvent"),
+                    (":content-type", "application/json"),
+                ],
+                json!({ "name": "read", "toolUseId": id, "input": input, "stop": true })
+                    .to_string()
+                    .as_bytes(),
+            )
+        };
+        let mut body = tool_frame("tool_a", r#"{"path":"/tmp/a"#);
+        body.extend(tool_frame("tool_b", r#"{"path":"/tmp/b"]"#));
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = body.clone();
+                async move { body }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let mut config = Config::default();
+        config.repair_tool_inputs = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[DEGRADED_RESPONSE_HEADER], "tool-input");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        let content = body["content"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[0]["type"], "tool_use");
+        assert_eq!(content[0]["id"], "tool_a");
+        assert_eq!(content[0]["input"], json!({ "path": "/tmp/a" }));
+        assert_eq!(content[1]["type"], "text");
+        assert!(
+            content[1]["text"]
+                .as_str()
+                .unwrap()
+                .contains(r#"{"path":"/tmp/b"]"#)
+        );
+        assert_eq!(body["stop_reason"], "tool_use");
+    }
+
+    #[tokio::test]
+    async fn test_output_reaching_max_tokens_sets_max_tokens_stop_reason() {
+        use crate::kiro::parser::frame::encode_frame;
+
+        // 上游在输出约 100 tokens 后停止，没有任何截断信号
+        let frame = encode_frame(
+            &[
+                (":message-type", "event"),
+                (":event-type", "assistantResponseEvent"),
+                (":content-type", "application/json"),
+            ],
+            json!({ "content": "word ".repeat(80) })
+                .to_string()
+                .as_bytes(),
+        );
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = frame.clone();
+                async move { body }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+
+        let request = |max_tokens: i32, stream: bool| {
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": max_tokens,
+                "stream": stream,
+                "messages": [{ "role": "user", "content": "hi" }]
+            })
+        };
+        for (max_tokens, expected) in [(16, "max_tokens"), (4096, "end_turn")] {
+            let resp = post_messages_json(&base, "/v1/messages", request(max_tokens, false)).await;
+            let body: serde_json::Value = resp.json().await.unwrap();
+            assert_eq!(body["stop_reason"], expected, "max_tokens: {}", max_tokens);
+
+            for path in ["/v1/messages", "/cc/v1/messages"] {
+                let resp = post_messages_json(&base, path, request(max_tokens, true)).await;
+                let events = parse_sse_events(&resp.text().await.unwrap());
+                let stop_reason = events
+                    .iter()
+                    .find(|e| e["type"] == "message_delta")
+                    .map(|e| e["delta"]["stop_reason"].clone());
+                assert_eq!(stop_reason, Some(json!(expected)), "path: {}", path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_exception_mid_response_is_reported() {
+        use crate::kiro::parser::frame::{encode_event_frame, encode_frame};
+
+        // 上游输出部分文本后返回 ThrottlingException
+        let mut body =
+            encode_event_frame("assistantResponseEvent", &json!({ "content": "partial" }));
+        body.extend(encode_frame(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", "ThrottlingException"),
+            ],
+            json!({ "message": "Too many requests" })
+                .to_string()
+                .as_bytes(),
+        ));
+        body.extend(encode_event_frame(
+            "assistantResponseEvent",
+            &json!({ "content": "ignored" }),
+        ));
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = body.clone();
+                async move { body }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+
+        let request = |stream: bool| {
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 1024,
+                "stream": stream,
+                "messages": [{ "role": "user", "content": "hi" }]
+            })
+        };
+        let resp = post_messages_json(&base, "/v1/messages", request(false)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("ThrottlingException")
+        );
+
+        for path in ["/v1/messages", "/cc/v1/messages"] {
+            let resp = post_messages_json(&base, path, request(true)).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let text = resp.text().await.unwrap();
+            let events = parse_sse_events(&text);
+            let last = events.last().unwrap();
+            assert_eq!(last["type"], "error", "path: {}", path);
+            assert_eq!(last["error"]["type"], "rate_limit_error", "path: {}", path);
+            assert!(
+                !events.iter().any(|e| e["type"] == "message_stop"),
+                "path: {}",
+                path
+            );
+            assert!(!text.contains("ignored"), "path: {}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gzip_upstream_response_is_decompressed() {
+        use crate::kiro::parser::frame::encode_frame;
+        use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+        use std::io::{Read, Write};
+
+        let frame = encode_frame(
+            &[
+                (":message-type", "event"),
+                (":event-type", "assistantResponseEvent"),
+                (":content-type", "application/json"),
+            ],
+            json!({ "content": "hello from gzip" })
+                .to_string()
+                .as_bytes(),
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&frame).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = compressed.clone();
+                async move { ([(header::CONTENT_ENCODING, "gzip")], body) }
+            }),
+        );
+        let upstream = spawn(router).await;
+
+        // 不自动解压的客户端，直接检查原始响应体
+        let client = reqwest::Client::builder().no_gzip().build().unwrap();
+        for response_compression in [false, true] {
+            let mut config = Config::default();
+            config.response_compression = response_compression;
+            let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+            for accept_gzip in [false, true] {
+                let mut request = client
+                    .post(format!("{}/v1/messages", base))
+                    .header("x-api-key", "test-key")
+                    .json(&json!({
+                        "model": "claude-sonnet-4-5",
+                        "max_tokens": 16,
+                        "messages": [{ "role": "user", "content": "hi" }]
+                    }));
+                if accept_gzip {
+                    request = request.header(header::ACCEPT_ENCODING, "gzip");
+                }
+                let resp = request.send().await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+
+                let compressed = response_compression && accept_gzip;
+                assert_eq!(
+                    resp.headers().get(header::CONTENT_ENCODING).is_some(),
+                    compressed
+                );
+                let raw = resp.bytes().await.unwrap();
+                let body = if compressed {
+                    let mut decoded = Vec::new();
+                    GzDecoder::new(raw.as_ref())
+                        .read_to_end(&mut decoded)
+                        .unwrap();
+                    decoded
+                } else {
+                    raw.to_vec()
+                };
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["content"][0]["text"], "hello from gzip");
+            }
+        }
+    }
+
+    /// 启动较慢的模拟上游，记录同时处理中的最大请求数
+    async fn spawn_slow_upstream(delay: std::time::Duration) -> (String, Arc<AtomicUsize>) {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let max = max_in_flight.clone();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let in_flight = in_flight.clone();
+                let max = max.clone();
+                async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    StatusCode::OK
+                }
+            }),
+        );
+        (spawn(router).await, max_in_flight)
+    }
+
+    #[tokio::test]
+    async fn test_queue_limits_concurrent_upstream_requests() {
+        let (upstream, max_in_flight) =
+            spawn_slow_upstream(std::time::Duration::from_millis(200)).await;
+        let mut config = Config::default();
+        config.max_concurrent_upstream_requests = 1;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let (first, second) = tokio::join!(
+            post_model(&base, "claude-sonnet-4-5"),
+            post_model(&base, "claude-sonnet-4-5")
+        );
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_returns_overloaded() {
+        let (upstream, _) = spawn_slow_upstream(std::time::Duration::from_millis(500)).await;
+        let mut config = Config::default();
+        config.max_concurrent_upstream_requests = 1;
+        config.queue_wait_timeout_secs = 0;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let busy = tokio::spawn({
+            let base = base.clone();
+            async move { post_model(&base, "claude-sonnet-4-5").await.status() }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "5");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "overloaded_error");
+        assert_eq!(
+            body["error"]["message"],
+            "Server is at capacity, please retry later"
+        );
+        assert_eq!(busy.await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_queue_permit_held_until_response_body_consumed() {
+        let queue = super::super::queue::RequestQueue::new(1, std::time::Duration::from_millis(50));
+        let permit = queue.acquire().await.unwrap();
+        let response = with_queue_permit(
+            (StatusCode::OK, "websearch body").into_response(),
+            Some(permit),
+        );
+        assert_eq!(queue.available_permits(), 0);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"websearch body");
+        assert_eq!(queue.available_permits(), 1);
+    }
+
+    /// 启动使用模拟上游的代理服务器
+    async fn spawn_mock_proxy(config: Config) -> String {
+        let provider = crate::kiro::mock::MockProvider::from_config(&config);
+        spawn(crate::anthropic::router::create_router_with_mock_provider(
+            "test-key", provider, &config,
+        ))
+        .await
+    }
+
+    /// 记录收到的请求后委托给模拟上游的 Provider
+    #[derive(Default)]
+    struct RecordingProvider {
+        inner: crate::kiro::mock::MockProvider,
+        model_ids: parking_lot::Mutex<Vec<String>>,
+    }
+
+    impl Provider for RecordingProvider {
+        fn call_messages<'a>(
+            &'a self,
+            call: MessagesCall<'a>,
+        ) -> futures::future::BoxFuture<'a, anyhow::Result<reqwest::Response>> {
+            let request: KiroRequest = serde_json::from_str(call.request_body).unwrap();
+            self.model_ids.lock().push(
+                request
+                    .conversation_state
+                    .current_message
+                    .user_input_message
+                    .model_id,
+            );
+            self.inner.call_messages(call)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_model_backends_route_to_named_provider() {
+        let mut config = Config::default();
+        config.model_backends = std::collections::HashMap::from([
+            ("qwen-*".to_string(), "vllm".to_string()),
+            ("llama-*".to_string(), "gpu".to_string()),
+        ]);
+        let kiro = Arc::new(RecordingProvider::default());
+        let vllm = Arc::new(RecordingProvider::default());
+        let state = AppState::new("test-key")
+            .with_provider(kiro.clone(), &config)
+            .with_backend("vllm", vllm.clone());
+        let base = spawn(crate::anthropic::router::create_router(state)).await;
+
+        for (model, stream) in [
+            ("qwen-72b", false),
+            ("claude-sonnet-4-5", false),
+            ("qwen-7b", true),
+        ] {
+            let resp = post_messages_json(
+                &base,
+                "/v1/messages",
+                json!({
+                    "model": model,
+                    "max_tokens": 16,
+                    "stream": stream,
+                    "messages": [{"role": "user", "content": "hello"}]
+                }),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", model);
+        }
+
+        // 路由到其他后端的模型不做 Kiro 模型映射
+        assert_eq!(*vllm.model_ids.lock(), ["qwen-72b", "qwen-7b"]);
+        assert_eq!(*kiro.model_ids.lock(), ["claude-sonnet-4.5"]);
+
+        // 后端未注册（如创建失败）时返回 503
+        let resp = post_messages_json(
+            &base,
+            "/v1/messages",
+            json!({
+                "model": "llama-3",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hello"}]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(kiro.model_ids.lock().len(), 1);
+    }
+
+    async fn post_messages_json(
+        base: &str,
+        path: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}{}", base, path))
+            .header("x-api-key", "test-key")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn get_with_key(url: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(url)
+            .header("x-api-key", "test-key")
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_non_stream_response_can_be_retrieved_by_location() {
+        let mut config = Config::default();
+        config.enable_response_retrieval = true;
+        let base = spawn_mock_proxy(config).await;
+
+        let resp = post_messages_json(
+            &base,
+            "/v1/messages",
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hello"}]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let location = resp.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let original: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            location,
+            format!("/v1/messages/{}", original["id"].as_str().unwrap())
+        );
+
+        let resp = get_with_key(format!("{}{}", base, location)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<serde_json::Value>().await.unwrap(), original);
+
+        let resp = get_with_key(format!("{}/v1/messages/msg_missing", base)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "not_found_error");
+    }
+
+    #[tokio::test]
+    async fn test_response_retrieval_disabled_by_default() {
+        let base = spawn_mock_proxy(Config::default()).await;
+
+        let resp = post_messages_json(
+            &base,
+            "/v1/messages",
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hello"}]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::LOCATION).is_none());
+        let body: serde_json::Value = resp.json().await.unwrap();
+
+        let url = format!("{}/v1/messages/{}", base, body["id"].as_str().unwrap());
+        assert_eq!(get_with_key(url).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 解析 SSE 响应体中的 data 事件（忽略 ping）
+    fn parse_sse_events(body: &str) -> Vec<serde_json::Value> {
+        body.lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+            .filter(|event| event["type"] != "ping")
+            .collect()
+    }
+
+    fn weather_tool() -> serde_json::Value {
+        json!({
+            "name": "get_weather",
+            "description": "Get the weather for a city",
+            "input_schema": {
+                "type": "object",
+                "properties": { "city": { "type": "string" } },
+                "required": ["city"]
+            }
+        })
+    }
+
+    async fn post_with_betas(base: &str, betas: &str, stream: bool) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .header("anthropic-beta", betas)
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "stream": stream,
+                "messages": [{ "role": "user", "content": "hello" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_beta_is_echoed_and_enables_cache_usage() {
+        let mut config = Config::default();
+        config.passthrough_betas = vec!["prompt-caching-2024-07-31".to_string()];
+        let base = spawn_mock_proxy(config).await;
+        let betas = "prompt-caching-2024-07-31, token-efficient-tools-2025-02-19";
+
+        let resp = post_with_betas(&base, betas, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()["anthropic-beta"],
+            "prompt-caching-2024-07-31"
+        );
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["usage"]["cache_read_input_tokens"], 0);
+        assert_eq!(body["usage"]["cache_creation_input_tokens"], 0);
+
+        let resp = post_with_betas(&base, betas, true).await;
+        assert_eq!(
+            resp.headers()["anthropic-beta"],
+            "prompt-caching-2024-07-31"
+        );
+        let events = parse_sse_events(&resp.text().await.unwrap());
+        assert_eq!(events[0]["message"]["usage"]["cache_read_input_tokens"], 0);
+        let delta = events
+            .iter()
+            .find(|e| e["type"] == "message_delta")
+            .unwrap();
+        assert_eq!(delta["usage"]["cache_creation_input_tokens"], 0);
+
+        // 未放行的 beta 不回显、不改变响应
+        let resp = post_with_betas(&base, "token-efficient-tools-2025-02-19", false).await;
+        assert!(resp.headers().get("anthropic-beta").is_none());
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert!(body["usage"].get("cache_read_input_tokens").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_beta_returns_invalid_request() {
+        let mut config = Config::default();
+        config.reject_betas = vec!["computer-use-2025-01-24".to_string()];
+        let base = spawn_mock_proxy(config).await;
+
+        let resp = post_with_betas(
+            &base,
+            "prompt-caching-2024-07-31,computer-use-2025-01-24",
+            false,
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("computer-use-2025-01-24")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixed_window_rate_limit_returns_429() {
+        let mut config = Config::default();
+        config.rate_limit_capacity = Some(5);
+        config.rate_limit_window_secs = Some(10);
+        let base = spawn_mock_proxy(config).await;
+
+        let mut statuses = Vec::new();
+        let mut retry_after = None;
+        for _ in 0..7 {
+            let resp = post_model(&base, "claude-sonnet-4-5").await;
+            statuses.push(resp.status());
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                retry_after = resp.headers().get(header::RETRY_AFTER).cloned();
+                let body: serde_json::Value = resp.json().await.unwrap();
+                assert_eq!(body["error"]["type"], "rate_limit_error");
+            }
+        }
+        assert_eq!(statuses[..5], [StatusCode::OK; 5]);
+        assert_eq!(statuses[5..], [StatusCode::TOO_MANY_REQUESTS; 2]);
+        let retry_after: u64 = retry_after.unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=10).contains(&retry_after), "{}", retry_after);
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_non_stream_echo() {
+        let base = spawn_mock_proxy(Config::default()).await;
+        let resp = post_messages_json(
+            &base,
+            "/v1/messages",
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 64,
+                "messages": [{ "role": "user", "content": "hello mock world" }]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["type"], "message");
+        assert_eq!(body["role"], "assistant");
+        assert_eq!(body["content"][0]["type"], "text");
+        assert_eq!(body["content"][0]["text"], "hello mock world");
+        assert_eq!(body["stop_reason"], "end_turn");
+        assert!(body["usage"]["output_tokens"].as_i64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_stream_event_sequence() {
+        let base = spawn_mock_proxy(Config::default()).await;
+        let resp = post_messages_json(
+            &base,
+            "/v1/messages",
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 3,
+                "stream": true,
+                "messages": [{ "role": "user", "content": "one two three four five" }]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        let events = parse_sse_events(&resp.text().await.unwrap());
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(types.first(), Some(&"message_start"));
+        assert_eq!(types.last(), Some(&"message_stop"));
+        assert!(types.contains(&"content_block_start"));
+        assert!(types.contains(&"content_block_stop"));
+        assert!(types.contains(&"message_delta"));
+
+        // max_tokens 为 3 时最多回显 3 个词
+        let text: String = events
+            .iter()
+            .filter(|e| e["type"] == "content_block_delta")
+            .filter_map(|e| e["delta"]["text"].as_str())
+            .collect();
+        assert_eq!(text, "one two three ");
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_tool_use() {
+        let base = spawn_mock_proxy(Config::default()).await;
+        let request = |content: &str| {
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 64,
+                "tools": [weather_tool()],
+                "messages": [{ "role": "user", "content": content }]
+            })
+        };
+
+        let resp = post_messages_json(&base, "/v1/messages", request("please use tool")).await;
+        let body: serde_json::Value = resp.json().await.unwrap();
+        let tool_use = body["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|block| block["type"] == "tool_use")
+            .expect("应包含 tool_use 块");
+        assert_eq!(tool_use["name"], "get_weather");
+        assert_eq!(tool_use["input"], json!({ "city": "mock" }));
+        assert_eq!(body["stop_reason"], "tool_use");
+
+        // 未包含触发短语时只回显文本
+        let resp = post_messages_json(&base, "/v1/messages", request("what is the weather")).await;
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["content"].as_array().unwrap().len(), 1);
+        assert_eq!(body["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_buffered_stream_tool_use() {
+        let base = spawn_mock_proxy(Config::default()).await;
+        let resp = post_messages_json(
+            &base,
+            "/cc/v1/messages",
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 64,
+                "stream": true,
+                "tools": [weather_tool()],
+                "messages": [{ "role": "user", "content": "use tool please" }]
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let events = parse_sse_events(&resp.text().await.unwrap());
+        assert_eq!(events[0]["type"], "message_start");
+        let tool_start = events
+            .iter()
+            .find(|e| {
+                e["type"] == "content_block_start" && e["content_block"]["type"] == "tool_use"
+            })
+            .expect("应包含 tool_use 块");
+        assert_eq!(tool_start["content_block"]["name"], "get_weather");
+        let stop_reason = events
+            .iter()
+            .find(|e| e["type"] == "message_delta")
+            .map(|e| e["delta"]["stop_reason"].clone());
+        assert_eq!(stop_reason, Some(json!("tool_use")));
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_simulated_error() {
+        let mut config = Config::default();
+        config.mock_error_rate = 1.0;
+        let base = spawn_mock_proxy(config).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "api_error");
+    }
+
+    #[tokio::test]
+    async fn test_allowed_model_reaches_upstream() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_proxy(vec!["claude-sonnet-4-5"], &upstream).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // 带日期后缀的别名映射到同一 Kiro 模型，同样允许
+        let resp = post_model(&base, "claude-sonnet-4-5-20250929").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_models_endpoint_filtered_by_allowlist() {
+        let (upstream, _) = spawn_upstream().await;
+        let base = spawn_proxy(vec!["claude-sonnet-4-5"], &upstream).await;
+
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!("{}/v1/models", base))
+            .header("x-api-key", "test-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let ids: Vec<&str> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "claude-sonnet-4-5-20250929",
+                "claude-sonnet-4-5-20250929-thinking"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_is_model_allowed() {
+        let mut config = Config::default();
+        assert!(config.is_model_allowed("claude-opus-4-6"));
+        config.allowed_models = Some(Vec::new());
+        assert!(config.is_model_allowed("claude-opus-4-6"));
+        config.allowed_models = Some(vec!["claude-sonnet-4-5".to_string()]);
+        assert!(config.is_model_allowed("claude-sonnet-4.5"));
+        assert!(config.is_model_allowed("Claude-Sonnet-4-5"));
+        assert!(!config.is_model_allowed("claude-opus-4-5"));
+    }
+
+    /// 启动两个凭据、配置了 Admin Key 并透出凭据 ID 响应头的代理服务器
+    async fn spawn_ab_proxy(upstream: &str) -> String {
+        let mut config = Config::default();
+        config.admin_api_key = Some("admin-key".to_string());
+        config.expose_credential_id_header = true;
+        spawn_proxy_with(
+            config,
+            vec![valid_credentials("a"), valid_credentials("b")],
+            upstream,
+        )
+        .await
+    }
+
+    async fn post_ab(base: &str, variant: &str, admin_key: Option<&str>) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .header("x-ab-variant", variant);
+        if let Some(key) = admin_key {
+            request = request.header("x-admin-key", key);
+        }
+        request
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ab_variant_routes_to_requested_credential() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        // 默认按优先级使用凭据 #1
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.headers()["x-credential-id"], "1");
+
+        for _ in 0..2 {
+            let resp = post_ab(&base, "credential:2", Some("admin-key")).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["x-credential-id"], "2");
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_ab_variant_requires_admin_key() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        for admin_key in [None, Some("wrong-key")] {
+            let resp = post_ab(&base, "credential:2", admin_key).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        // 非 credential: 形式的变体不影响正常路由
+        let resp = post_ab(&base, "control", None).await;
+        assert_eq!(resp.headers()["x-credential-id"], "1");
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// 启动按顺序返回文本响应的模拟上游（最后一条重复返回），记录每次的请求体
+    async fn spawn_text_upstream(
+        texts: Vec<&str>,
+    ) -> (String, Arc<parking_lot::Mutex<Vec<String>>>) {
+        use crate::kiro::parser::frame::encode_frame;
+
+        let bodies: Arc<parking_lot::Mutex<Vec<String>>> = Arc::default();
+        let seen = bodies.clone();
+        let texts: Vec<String> = texts.into_iter().map(String::from).collect();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move |body: String| {
+                let seen = seen.clone();
+                let texts = texts.clone();
+                async move {
+                    let mut seen = seen.lock();
+                    let text = &texts[seen.len().min(texts.len() - 1)];
+                    seen.push(body);
+                    encode_frame(
+                        &[
+                            (":message-type", "event"),
+                            (":event-type", "assistantResponseEvent"),
+                            (":content-type", "application/json"),
+                        ],
+                        json!({ "content": text }).to_string().as_bytes(),
+                    )
+                }
+            }),
+        );
+        (spawn(router).await, bodies)
+    }
+
+    #[tokio::test]
+    async fn test_non_stream_response_includes_thinking_signatures() {
+        use crate::kiro::parser::frame::encode_event_frame;
+
+        let body: Vec<u8> = [
+            ("reasoningContentEvent", json!({"text": "I should "})),
+            (
+                "reasoningContentEvent",
+                json!({"text": "read it.", "signature": "sig-1"}),
+            ),
+            ("reasoningContentEvent", json!({"redactedContent": "ZW5j"})),
+            ("assistantResponseEvent", json!({"content": "Done"})),
+        ]
+        .iter()
+        .flat_map(|(event_type, payload)| encode_event_frame(event_type, payload))
+        .collect();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = body.clone();
+                async move { body }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            body["content"],
+            json!([
+                {"type": "thinking", "thinking": "I should read it.", "signature": "sig-1"},
+                {"type": "redacted_thinking", "data": "ZW5j"},
+                {"type": "text", "text": "Done"}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_stream_content_blocks_follow_event_order() {
+        use crate::kiro::parser::frame::encode_event_frame;
+
+        let body: Vec<u8> = [
+            ("assistantResponseEvent", json!({"content": "Let me "})),
+            ("assistantResponseEvent", json!({"content": "check."})),
+            ("reasoningContentEvent", json!({"text": "Need the file."})),
+            (
+                "toolUseEvent",
+                json!({"name": "read", "toolUseId": "tool_a", "input": "{}", "stop": true}),
+            ),
+            ("assistantResponseEvent", json!({"content": "Reading."})),
+        ]
+        .iter()
+        .flat_map(|(event_type, payload)| encode_event_frame(event_type, payload))
+        .collect();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let body = body.clone();
+                async move { body }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            body["content"],
+            json!([
+                {"type": "text", "text": "Let me check."},
+                {"type": "thinking", "thinking": "Need the file."},
+                {"type": "tool_use", "id": "tool_a", "name": "read", "input": {}},
+                {"type": "text", "text": "Reading."}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_stream_oversized_response_is_truncated() {
+        use crate::kiro::parser::frame::encode_frame;
+
+        const UPSTREAM_TOTAL: usize = 50 * 1024 * 1024;
+        const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+        // 按需生成事件帧，统计上游实际产生的字节数
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(move || {
+                let counter = counter.clone();
+                async move {
+                    let frame = Bytes::from(encode_frame(
+                        &[
+                            (":message-type", "event"),
+                            (":event-type", "assistantResponseEvent"),
+                            (":content-type", "application/json"),
+                        ],
+                        json!({ "content": "x".repeat(60_000) })
+                            .to_string()
+                            .as_bytes(),
+                    ));
+                    let frames = stream::unfold(0usize, move |sent| {
+                        let frame = frame.clone();
+                        let counter = counter.clone();
+                        async move {
+                            if sent >= UPSTREAM_TOTAL {
+                                return None;
+                            }
+                            counter.fetch_add(frame.len(), Ordering::SeqCst);
+                            Some((Ok::<_, Infallible>(frame.clone()), sent + frame.len()))
+                        }
+                    });
+                    axum::body::Body::from_stream(frames)
+                }
+            }),
+        );
+        let upstream = spawn(router).await;
+        let mut config = Config::default();
+        config.max_response_bytes = MAX_RESPONSE_BYTES;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_model(&base, "claude-sonnet-4-5").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[DEGRADED_RESPONSE_HEADER], "response-size");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["stop_reason"], "max_tokens");
+        let text = body["content"][0]["text"].as_str().unwrap();
+        assert!(!text.is_empty());
+        assert!(text.len() <= MAX_RESPONSE_BYTES);
+        // 超出上限后不再读取，上游只产生了一小部分响应
+        assert!(produced.load(Ordering::SeqCst) < UPSTREAM_TOTAL / 2);
+    }
+
+    /// 发送 JSON 模式请求（`via_header` 时使用 x-response-format 请求头，否则使用 response_format 字段）
+    async fn post_json_mode(base: &str, via_header: bool) -> reqwest::Response {
+        let mut body = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key");
+        if via_header {
+            request = request.header(RESPONSE_FORMAT_HEADER, "json_object");
+        } else {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+        request.json(&body).send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_mode_invalid_output_sets_degraded_header() {
+        let (upstream, bodies) = spawn_text_upstream(vec!["Sure! {\"a\": 1}"]).await;
+        let base =
+            spawn_proxy_with(Config::default(), vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_json_mode(&base, false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[DEGRADED_RESPONSE_HEADER], "json-output");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["content"][0]["text"], "Sure! {\"a\": 1}");
+
+        // 未启用 jsonModeRetry 时不重试
+        let bodies = bodies.lock();
+        assert_eq!(bodies.len(), 1);
+        assert!(bodies[0].contains("Respond with a single valid JSON value"));
+    }
+
+    #[tokio::test]
+    async fn test_json_mode_retry_corrects_invalid_output() {
+        let (upstream, bodies) =
+            spawn_text_upstream(vec!["not json", "```json\n{\"a\": 1}\n```"]).await;
+        let mut config = Config::default();
+        config.json_mode_retry = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_json_mode(&base, true).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(DEGRADED_RESPONSE_HEADER).is_none());
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["content"][0]["text"], "{\"a\": 1}");
+
+        let bodies = bodies.lock();
+        assert_eq!(bodies.len(), 2);
+        assert!(!bodies[0].contains("was not valid JSON"));
+        assert!(bodies[1].contains("not json"));
+        assert!(bodies[1].contains("Your previous response was not valid JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_json_mode_retry_skipped_for_valid_output() {
+        let (upstream, bodies) = spawn_text_upstream(vec!["{\"a\": 1}"]).await;
+        let mut config = Config::default();
+        config.json_mode_retry = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_json_mode(&base, false).await;
+        assert!(resp.headers().get(DEGRADED_RESPONSE_HEADER).is_none());
+        assert_eq!(bodies.lock().len(), 1);
+    }
+
+    /// 配置了 maxTokensCap 的应用状态
+    fn max_tokens_cap_state(caps: &[(&str, u32)]) -> AppState {
+        let mut config = Config::default();
+        config.max_tokens_cap = caps
+            .iter()
+            .map(|(pattern, cap)| (pattern.to_string(), *cap))
+            .collect();
+        let manager =
+            MultiTokenManager::new(config, vec![valid_credentials("a")], None, None, false)
+                .unwrap();
+        AppState::new("test-key").with_kiro_provider(KiroProvider::new(Arc::new(manager)))
+    }
+
+    fn clamp(state: &AppState, model: &str, max_tokens: i32) -> (i32, Option<(i32, i32)>) {
+        let mut payload: MessagesRequest = serde_json::from_value(json!({
+            "model": model,
+            "max_tokens": max_tokens,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+        let clamped = clamp_max_tokens(state, &mut payload);
+        (payload.max_tokens, clamped)
+    }
+
+    #[test]
+    fn test_max_tokens_cap_pattern_precedence() {
+        let state = max_tokens_cap_state(&[
+            ("claude-sonnet-4.6", 4096),
+            ("claude-sonnet-*", 8192),
+            ("default", 16384),
+        ]);
+
+        // 按映射后的模型 ID 匹配：精确 > 前缀 > 默认
+        assert_eq!(
+            clamp(&state, "claude-sonnet-4-6", 128000),
+            (4096, Some((128000, 4096)))
+        );
+        assert_eq!(
+            clamp(&state, "claude-sonnet-4-5-20250929", 128000),
+            (8192, Some((128000, 8192)))
+        );
+        assert_eq!(
+            clamp(&state, "claude-opus-4-6", 128000),
+            (16384, Some((128000, 16384)))
+        );
+        // 只降不升
+        assert_eq!(clamp(&state, "claude-sonnet-4-5", 1024), (1024, None));
+        assert_eq!(clamp(&state, "claude-sonnet-4-5", 8192), (8192, None));
+    }
+
+    #[test]
+    fn test_max_tokens_cap_unmatched_model_passes_through() {
+        let state = max_tokens_cap_state(&[("claude-sonnet-*", 8192)]);
+        assert_eq!(clamp(&state, "claude-opus-4-6", 128000), (128000, None));
+        assert_eq!(clamp(&state, "gpt-4o", 128000), (128000, None));
+
+        // 未配置 maxTokensCap 时不限制
+        let state = max_tokens_cap_state(&[]);
+        assert_eq!(clamp(&state, "claude-sonnet-4-5", 128000), (128000, None));
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_cap_sets_response_header() {
+        let (upstream, _) = spawn_text_upstream(vec!["hello"]).await;
+        let mut config = Config::default();
+        config.max_tokens_cap =
+            std::collections::HashMap::from([("claude-sonnet-*".to_string(), 8192)]);
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let post = |model: &'static str| {
+            reqwest::Client::new()
+                .post(format!("{}/v1/messages", base))
+                .header("x-api-key", "test-key")
+                .json(&json!({
+                    "model": model,
+                    "max_tokens": 128000,
+                    "messages": [{ "role": "user", "content": "hi" }]
+                }))
+                .send()
+        };
+
+        let resp = post("claude-sonnet-4-5").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[MAX_TOKENS_CLAMPED_HEADER], "128000->8192");
+
+        let resp = post("claude-opus-4-6").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(MAX_TOKENS_CLAMPED_HEADER).is_none());
+    }
+
+    /// `max_tokens_delta` 为 0 时预估输入 tokens 恰好等于上下文窗口减去 max_tokens
+    async fn prompt_length_proxy(
+        policy: PromptTooLongPolicy,
+        max_tokens_delta: i64,
+    ) -> (
+        String,
+        Arc<parking_lot::Mutex<Vec<String>>>,
+        serde_json::Value,
+    ) {
+        let mut request = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                { "role": "user", "content": "word ".repeat(400) },
+                { "role": "assistant", "content": "ok" },
+                { "role": "user", "content": "word ".repeat(400) }
+            ]
+        });
+        let messages = serde_json::from_value(request["messages"].clone()).unwrap();
+        let input_tokens = token::count_all_tokens(
+            "claude-sonnet-4-5".to_string(),
+            None,
+            messages,
+            None,
+            &token::ClientCountContext::default(),
+        ) as i64;
+        request["max_tokens"] =
+            json!(i64::from(CONTEXT_WINDOW_SIZE) - input_tokens + max_tokens_delta);
+
+        let (upstream, bodies) = spawn_text_upstream(vec!["hello"]).await;
+        let mut config = Config::default();
+        config.prompt_too_long_policy = policy;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+        (base, bodies, request)
+    }
+
+    async fn post_json(base: &str, request: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(request)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prompt_too_long_is_rejected_before_upstream() {
+        let (base, bodies, request) = prompt_length_proxy(PromptTooLongPolicy::Reject, 1).await;
+
+        let resp = post_json(&base, &request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.starts_with("prompt is too long: "), "{}", message);
+        assert!(message.ends_with(" maximum"), "{}", message);
+        assert!(bodies.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_just_under_context_window_passes() {
+        let (base, bodies, request) = prompt_length_proxy(PromptTooLongPolicy::Reject, 0).await;
+
+        let resp = post_json(&base, &request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(bodies.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_too_long_warn_policy_passes_through() {
+        let (base, bodies, request) = prompt_length_proxy(PromptTooLongPolicy::Warn, 1).await;
+
+        let resp = post_json(&base, &request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(bodies.lock().len(), 1);
+    }
+
+    fn system_prompt_state(mode: SystemPromptMode) -> AppState {
+        let mut config = Config::default();
+        config.system_prompt = Some("OPERATOR".to_string());
+        config.system_prompt_mode = mode;
+        let manager =
+            MultiTokenManager::new(config, vec![valid_credentials("a")], None, None, false)
+                .unwrap();
+        AppState::new("test-key").with_kiro_provider(KiroProvider::new(Arc::new(manager)))
+    }
+
+    fn merged_system(state: &AppState, system: serde_json::Value) -> Option<String> {
+        let mut request = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+        if !system.is_null() {
+            request["system"] = system;
+        }
+        let mut payload: MessagesRequest = serde_json::from_value(request).unwrap();
+        apply_operator_system_prompt(state, &mut payload);
+        let system = payload.system?;
+        assert_eq!(system.len(), 1);
+        Some(system[0].text.clone())
+    }
+
+    #[test]
+    fn test_operator_system_prompt_modes() {
+        let client = json!([{ "text": "CLIENT-1" }, { "text": "CLIENT-2" }]);
+
+        let state = system_prompt_state(SystemPromptMode::Replace);
+        assert_eq!(merged_system(&state, client.clone()).unwrap(), "OPERATOR");
+
+        let state = system_prompt_state(SystemPromptMode::Prepend);
+        assert_eq!(
+            merged_system(&state, client.clone()).unwrap(),
+            "OPERATOR\n\nCLIENT-1\nCLIENT-2"
+        );
+        // 客户端未提供 system 时只使用运营方提示词
+        assert_eq!(
+            merged_system(&state, serde_json::Value::Null).unwrap(),
+            "OPERATOR"
+        );
+
+        let state = system_prompt_state(SystemPromptMode::Append);
+        assert_eq!(
+            merged_system(&state, json!("CLIENT")).unwrap(),
+            "CLIENT\n\nOPERATOR"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operator_system_prompt_prepended_upstream() {
+        let (upstream, bodies) = spawn_text_upstream(vec!["hello"]).await;
+        let mut config = Config::default();
+        config.system_prompt = Some("Follow the safety guidelines.".to_string());
+        config.system_prompt_mode = SystemPromptMode::Prepend;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "system": "You are a pirate.",
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body: serde_json::Value = serde_json::from_str(&bodies.lock()[0]).unwrap();
+        let system = body["conversationState"]["history"][0]["userInputMessage"]["content"]
+            .as_str()
+            .unwrap();
+        assert!(
+            system.starts_with("Follow the safety guidelines.\n\nYou are a pirate."),
+            "{}",
+            system
+        );
+    }
+
+    /// 按 unsupportedParamsPolicy 启动代理，发送带采样参数的请求，返回 (响应, 上游收到的请求数)
+    async fn post_sampling(
+        policy: UnsupportedParamsPolicy,
+        params: serde_json::Value,
+    ) -> (reqwest::Response, usize) {
+        let (upstream, bodies) = spawn_text_upstream(vec!["hello"]).await;
+        let mut config = Config::default();
+        config.unsupported_params_policy = policy;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let mut body = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(params.as_object().unwrap().clone());
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        let hits = bodies.lock().len();
+        (resp, hits)
+    }
+
+    #[tokio::test]
+    async fn test_sampling_params_ignore_policy() {
+        let params = json!({ "temperature": 0.7, "top_p": 0.9, "top_k": 40 });
+        let (resp, hits) = post_sampling(UnsupportedParamsPolicy::Ignore, params).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(UNSUPPORTED_PARAMS_HEADER).is_none());
+        assert_eq!(hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sampling_params_warn_policy() {
+        let params = json!({ "temperature": 0.7, "top_k": 40 });
+        let (resp, hits) = post_sampling(UnsupportedParamsPolicy::Warn, params).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[UNSUPPORTED_PARAMS_HEADER],
+            "temperature, top_k"
+        );
+        assert_eq!(hits, 1);
+
+        // 未携带采样参数时不附加响应头
+        let (resp, _) = post_sampling(UnsupportedParamsPolicy::Warn, json!({})).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(UNSUPPORTED_PARAMS_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sampling_params_reject_policy() {
+        let params = json!({ "top_p": 0.9 });
+        let (resp, hits) = post_sampling(UnsupportedParamsPolicy::Reject, params).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.contains("top_p"), "{}", message);
+        assert!(!message.contains("temperature"), "{}", message);
+        assert_eq!(hits, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sampling_params_out_of_range_rejected() {
+        for (params, name) in [
+            (json!({ "temperature": 2.5 }), "temperature"),
+            (json!({ "temperature": -0.1 }), "temperature"),
+            (json!({ "top_p": 1.5 }), "top_p"),
+            (json!({ "top_k": -1 }), "top_k"),
+        ] {
+            let (resp, hits) = post_sampling(UnsupportedParamsPolicy::Ignore, params).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = resp.json().await.unwrap();
+            let message = body["error"]["message"].as_str().unwrap();
+            assert!(message.starts_with(name), "{}", message);
+            assert_eq!(hits, 0);
+        }
+
+        // 边界值合法
+        let params = json!({ "temperature": 2.0, "top_p": 0.0, "top_k": 0 });
+        let (resp, _) = post_sampling(UnsupportedParamsPolicy::Ignore, params).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    fn redaction_config(strict: bool) -> Config {
+        let mut config = Config::default();
+        config.redaction = serde_json::from_value(json!({
+            "strict": strict,
+            "rules": [
+                { "name": "internal-host", "pattern": r"[a-z0-9-]+\.corp\.internal", "replacement": "[HOST]" },
+                { "name": "aws-account", "pattern": r"\b\d{12}\b", "blocking": true }
+            ]
+        }))
+        .unwrap();
+        config
+    }
+
+    async fn post_redaction(base: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "system": "Deploy to build-01.corp.internal",
+                "messages": [{ "role": "user", "content": "db.corp.internal in 123456789012" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_redaction_applied_to_upstream_payload() {
+        let (upstream, bodies) = spawn_text_upstream(vec!["hello"]).await;
+        let base = spawn_proxy_with(
+            redaction_config(false),
+            vec![valid_credentials("a")],
+            &upstream,
+        )
+        .await;
+
+        let resp = post_redaction(&base).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[REDACTIONS_HEADER],
+            "internal-host=2, aws-account=1"
+        );
+
+        let body = bodies.lock()[0].clone();
+        assert!(!body.contains("corp.internal"), "{}", body);
+        assert!(!body.contains("123456789012"), "{}", body);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let state = &body["conversationState"];
+        let system = state["history"][0]["userInputMessage"]["content"]
+            .as_str()
+            .unwrap();
+        assert!(system.starts_with("Deploy to [HOST]"), "{}", system);
+        assert_eq!(
+            state["currentMessage"]["userInputMessage"]["content"],
+            "[HOST] in [REDACTED]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redaction_strict_mode_rejects_blocking_rule() {
+        let (upstream, bodies) = spawn_text_upstream(vec!["hello"]).await;
+        let base = spawn_proxy_with(
+            redaction_config(true),
+            vec![valid_credentials("a")],
+            &upstream,
+        )
+        .await;
+
+        let resp = post_redaction(&base).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.contains("aws-account"), "{}", message);
+        assert!(!message.contains("123456789012"), "{}", message);
+        assert!(bodies.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ab_variant_unknown_credential_rejected() {
+        let (upstream, hits) = spawn_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        for variant in ["credential:9", "credential:abc"] {
+            let resp = post_ab(&base, variant, Some("admin-key")).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    /// 模拟上游捕获到的请求（路径、请求头、请求体）
+    type Captured = Arc<parking_lot::Mutex<Option<(String, HeaderMap, serde_json::Value)>>>;
+
+    /// 启动记录最近一次请求的模拟上游
+    async fn spawn_capturing_upstream() -> (String, Captured) {
+        let captured: Captured = Arc::default();
+        let slot = captured.clone();
+        let router = axum::Router::new().route(
+            "/generateAssistantResponse",
+            axum::routing::post(
+                move |uri: axum::http::Uri, headers: HeaderMap, body: Bytes| {
+                    let slot = slot.clone();
+                    async move {
+                        let body = serde_json::from_slice(&body).unwrap();
+                        *slot.lock() = Some((uri.path().to_string(), headers, body));
+                        StatusCode::OK
+                    }
+                },
+            ),
+        );
+        (spawn(router).await, captured)
+    }
+
+    fn dry_run_request_body() -> serde_json::Value {
+        json!({
+            "model": "claude-sonnet-4-5-20250929",
+            "max_tokens": 1024,
+            "system": "You are a helpful assistant.",
+            "metadata": {
+                "user_id": "user_abc_account__session_0b4445e1-f5be-49e1-87ce-62bbc28ad705"
+            },
+            "tools": [{
+                "name": "read_file",
+                "description": "Read a file",
+                "input_schema": {
+                    "type": "object",
+                    "properties": { "path": { "type": "string" } }
+                }
+            }],
+            "messages": [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": "hello" },
+                { "role": "user", "content": "read a.txt" }
+            ]
+        })
+    }
+
+    async fn post_dry_run(
+        base: &str,
+        api_key: Option<&str>,
+        admin_key: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new().post(format!("{}/v1/messages/dry-run", base));
+        if let Some(key) = api_key {
+            request = request.header("x-api-key", key);
+        }
+        if let Some(key) = admin_key {
+            request = request.header("x-admin-key", key);
+        }
+        request.json(&dry_run_request_body()).send().await.unwrap()
+    }
+
+    /// 移除每次请求随机生成的字段
+    fn strip_random_fields(value: &mut serde_json::Value) {
+        if let Some(state) = value
+            .get_mut("conversationState")
+            .and_then(|s| s.as_object_mut())
+        {
+            state.remove("agentContinuationId");
+        }
+    }
+
+    /// 以透传方式发送消息请求（`Authorization` 携带客户端自己的上游 Token）
+    async fn post_passthrough(
+        base: &str,
+        api_key: Option<&str>,
+        extra_headers: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("authorization", "Bearer client-aws-token");
+        if let Some(key) = api_key {
+            request = request.header("x-api-key", key);
+        }
+        for (name, value) in extra_headers {
+            request = request.header(*name, *value);
+        }
+        request
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    fn captured_authorization(captured: &Captured) -> Option<String> {
+        captured
+            .lock()
+            .as_ref()
+            .map(|(_, headers, _)| headers[header::AUTHORIZATION].to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_mode_forwards_client_token() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let mut config = Config::default();
+        config.passthrough_mode = true;
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![valid_credentials("a")], None, None, false)
+                .unwrap(),
+        );
+        let provider = KiroProvider::new(manager.clone()).with_endpoint_override(&upstream);
+        let base = spawn(crate::anthropic::router::create_router_with_provider(
+            "test-key",
+            Some(provider),
+            None,
+        ))
+        .await;
+
+        // API Key 仍需通过 x-api-key 提供
+        let resp = post_passthrough(&base, None, &[]).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("authorization", "Bearer test-key")
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(captured.lock().is_none());
+
+        let resp = post_passthrough(&base, Some("test-key"), &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            captured_authorization(&captured).as_deref(),
+            Some("Bearer client-aws-token")
+        );
+        // 不经过凭据管理，也不上报调用结果
+        assert_eq!(manager.snapshot().entries[0].success_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_header_requires_admin_key() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let mut config = Config::default();
+        config.admin_api_key = Some("admin-key".to_string());
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_passthrough(&base, Some("test-key"), &[("x-passthrough", "true")]).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(captured.lock().is_none());
+
+        let resp = post_passthrough(
+            &base,
+            Some("test-key"),
+            &[("x-passthrough", "true"), ("x-admin-key", "admin-key")],
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            captured_authorization(&captured).as_deref(),
+            Some("Bearer client-aws-token")
+        );
+
+        // 未启用透传时使用托管凭据的 Token
+        let resp = post_passthrough(&base, Some("test-key"), &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            captured_authorization(&captured).as_deref(),
+            Some("Bearer test_token")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_matches_real_upstream_request() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&dry_run_request_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let (path, real_headers, mut real_body) = captured.lock().take().unwrap();
+
+        let resp = post_dry_run(&base, None, Some("admin-key")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut dry_run: serde_json::Value = resp.json().await.unwrap();
+        // dry-run 不发起网络调用
+        assert!(captured.lock().is_none());
+
+        strip_random_fields(&mut real_body);
+        strip_random_fields(&mut dry_run["payload"]);
+        assert_eq!(dry_run["payload"], real_body);
+        assert_eq!(dry_run["url"], format!("{}{}", upstream, path));
+        assert_eq!(dry_run["credentialId"], 1);
+        assert_eq!(dry_run["modelId"], "claude-sonnet-4.5");
+        // 系统提示词为注入后的最终内容，且与发送的历史首条消息一致
+        let system_prompt = dry_run["systemPrompt"].as_str().unwrap();
+        assert!(system_prompt.starts_with("You are a helpful assistant."));
+        assert!(
+            real_body
+                .to_string()
+                .contains(&serde_json::to_string(system_prompt).unwrap())
+        );
+
+        let dry_headers = dry_run["headers"].as_object().unwrap();
+        assert_eq!(dry_headers["authorization"], "Bearer [REDACTED]");
+        for (name, value) in real_headers.iter() {
+            // 每次请求随机生成，或由 HTTP 客户端自动添加
+            if matches!(
+                name.as_str(),
+                "amz-sdk-invocation-id"
+                    | "authorization"
+                    | "content-length"
+                    | "accept"
+                    | "accept-encoding"
+            ) {
+                continue;
+            }
+            assert_eq!(
+                dry_headers.get(name.as_str()).and_then(|v| v.as_str()),
+                Some(value.to_str().unwrap()),
+                "header {}",
+                name
+            );
+        }
+        let machine_id = dry_run["machineId"].as_str().unwrap();
+        assert!(
+            real_headers["x-amz-user-agent"]
+                .to_str()
+                .unwrap()
+                .ends_with(machine_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_respects_pinned_credential() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages/dry-run", base))
+            .header("x-admin-key", "admin-key")
+            .header("x-ab-variant", "credential:2")
+            .json(&dry_run_request_body())
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["credentialId"], 2);
+        assert!(captured.lock().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_requires_admin_key_by_default() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let base = spawn_ab_proxy(&upstream).await;
+
+        let resp = post_dry_run(&base, Some("test-key"), None).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        for (api_key, admin_key) in [
+            (None, None),
+            (None, Some("wrong-key")),
+            (Some("wrong"), None),
+        ] {
+            let resp = post_dry_run(&base, api_key, admin_key).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(captured.lock().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_enabled_accepts_api_key() {
+        let (upstream, captured) = spawn_capturing_upstream().await;
+        let mut config = Config::default();
+        config.dry_run_enabled = true;
+        let base = spawn_proxy_with(config, vec![valid_credentials("a")], &upstream).await;
+
+        let resp = post_dry_run(&base, Some("test-key"), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = post_dry_run(&base, None, None).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(captured.lock().is_none());
+    }
+
+    // ============ 单请求超时与进行中请求登记 ============
+
+    /// 启动使用指定上游的代理服务器，返回地址与共享的进行中请求登记表
+    async fn spawn_registry_proxy(
+        config: Config,
+        provider: Arc<dyn Provider>,
+    ) -> (
+        String,
+        Arc<crate::anthropic::active_requests::ActiveRequests>,
+    ) {
+        let state = AppState::new("test-key").with_provider(provider, &config);
+        let registry = state.active_requests.clone();
+        let base = spawn(crate::anthropic::router::create_router(state)).await;
+        (base, registry)
+    }
+
+    /// 等待登记表中出现指定数量的请求
+    async fn wait_for_active(
+        registry: &crate::anthropic::active_requests::ActiveRequests,
+        count: usize,
+    ) -> Vec<ActiveRequest> {
+        for _ in 0..300 {
+            let requests = registry.list();
+            if requests.len() == count {
+                return requests;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("登记表中的请求数未达到 {}: {:?}", count, registry.list());
+    }
+
+    async fn post_with_timeout(
+        base: &str,
+        stream: bool,
+        timeout: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", "test-key")
+            .json(&json!({
+                "model": "claude-sonnet-4",
+                "max_tokens": 16,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "hello there"}]
+            }));
+        if let Some(timeout) = timeout {
+            request = request.header(TIMEOUT_HEADER, timeout);
+        }
+        request.send().await.unwrap()
+    }
+
+    /// 先返回一段文本、随后永不结束的流式上游
+    struct HangingStreamProvider;
+
+    impl Provider for HangingStreamProvider {
+        fn call_messages<'a>(
+            &'a self,
+            _call: MessagesCall<'a>,
+        ) -> futures::future::BoxFuture<'a, anyhow::Result<reqwest::Response>> {
+            Box::pin(async move {
+                let first = crate::kiro::parser::frame::encode_event_frame(
+                    "assistantResponseEvent",
+                    &json!({ "content": "partial" }),
+                );
+                let body = stream::iter([Ok::<_, std::io::Error>(Bytes::from(first))])
+                    .chain(stream::pending());
+                let response = http::Response::builder()
+                    .status(http::StatusCode::OK)
+                    .body(reqwest::Body::wrap_stream(body))?;
+                Ok(reqwest::Response::from(response))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_is_registered_until_response_completes() {
+        let mut config = Config::default();
+        config.mock_latency_ms = 300;
+        config.max_request_timeout_secs = 1200;
+        let provider = Arc::new(crate::kiro::mock::MockProvider::from_config(&config));
+        let (base, registry) = spawn_registry_proxy(config, provider).await;
+
+        let pending =
+            tokio::spawn(async move { post_with_timeout(&base, false, Some("5000")).await });
+        let active = wait_for_active(&registry, 1).await;
+        assert_eq!(active[0].model, "claude-sonnet-4");
+        assert!(!active[0].stream);
+        // 超过 maxRequestTimeoutSecs 时按上限处理
+        assert_eq!(active[0].timeout, Some(Duration::from_secs(1200)));
+        assert_eq!(active[0].lane, RequestLane::LongRunning);
+
+        let resp = pending.await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["request-id"], active[0].id.as_str());
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_timeout_header_is_rejected() {
+        let provider = Arc::new(crate::kiro::mock::MockProvider::default());
+        let (base, registry) = spawn_registry_proxy(Config::default(), provider).await;
+
+        for value in ["0", "-1", "soon"] {
+            let resp = post_with_timeout(&base, false, Some(value)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", value);
+            let body: serde_json::Value = resp.json().await.unwrap();
+            assert!(
+                body["error"]["message"]
+                    .as_str()
+                    .unwrap()
+                    .contains(TIMEOUT_HEADER)
+            );
+        }
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_header_overrides_upstream_timeout() {
+        let mut config = Config::default();
+        config.mock_latency_ms = 5_000;
+        let provider = Arc::new(crate::kiro::mock::MockProvider::from_config(&config));
+        let (base, registry) = spawn_registry_proxy(config, provider).await;
+
+        let started = Instant::now();
+        let resp = post_with_timeout(&base, false, Some("1")).await;
+        assert!(!resp.status().is_success());
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_cancel_drops_pending_upstream_call() {
+        let mut config = Config::default();
+        config.mock_latency_ms = 10_000;
+        let provider = Arc::new(crate::kiro::mock::MockProvider::from_config(&config));
+        let (base, registry) = spawn_registry_proxy(config, provider).await;
+
+        let started = Instant::now();
+        let pending = tokio::spawn(async move { post_with_timeout(&base, true, None).await });
+        let active = wait_for_active(&registry, 1).await;
+        assert_eq!(active[0].lane, RequestLane::Interactive);
+        assert!(registry.cancel(&active[0].id));
+
+        let resp = pending.await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["message"], CANCELLED_MESSAGE);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_cancel_ends_stream_with_error_event() {
+        let (base, registry) =
+            spawn_registry_proxy(Config::default(), Arc::new(HangingStreamProvider)).await;
+
+        let mut resp = post_with_timeout(&base, true, None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut received = String::new();
+        while !received.contains("partial") {
+            let chunk = resp.chunk().await.unwrap().unwrap();
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+
+        let id = resp.headers()["request-id"].to_str().unwrap().to_string();
+        assert!(registry.cancel(&id));
+        while let Some(chunk) = resp.chunk().await.unwrap() {
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        let last = received.trim_end().rsplit("\n\n").next().unwrap();
+        assert!(last.starts_with("event: error"), "{}", received);
+        assert!(last.contains(CANCELLED_MESSAGE), "{}", received);
+        assert!(registry.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_removes_registered_stream() {
+        let (base, registry) =
+            spawn_registry_proxy(Config::default(), Arc::new(HangingStreamProvider)).await;
+
+        let mut resp = post_with_timeout(&base, true, None).await;
+        resp.chunk().await.unwrap().unwrap();
+        assert_eq!(registry.list().len(), 1);
+
+        drop(resp);
+        wait_for_active(&registry, 0).await;
+    }
+}
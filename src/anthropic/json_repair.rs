@@ -0,0 +1,313 @@
+//! 损坏 JSON 修复
+//!
+//! 上游偶尔返回不完整的工具参数 JSON（流被截断、括号不配对等）。
+//! [`repair_json`] 尝试做最小修复使其可解析：
+//! - 移除容器结束前的多余逗号
+//! - 补全未闭合的字符串（丢弃末尾不完整的转义序列）
+//! - 丢弃未写完的对象键，为缺失的值补 `null`
+//! - 补全被截断的字面量（`tru` → `true`）与数字（`1.` → `1.0`）
+//! - 按嵌套顺序补全缺失的 `}` / `]`
+//!
+//! 修复后仍无法解析（如括号类型不匹配、根值之后有多余内容）时返回 None。
+
+/// 容器类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// 扫描状态
+#[derive(Default)]
+struct Scanner {
+    out: String,
+    stack: Vec<Container>,
+    /// 对象中下一个字符串是否为键
+    expect_key: bool,
+    in_string: bool,
+    /// 当前字符串是否为对象键
+    string_is_key: bool,
+    /// 当前字符串（或已结束但尚未遇到 `:` 的键）在 out 中的起始位置
+    key_start: Option<usize>,
+    /// 当前字符串中最后一个转义序列的起始位置（转义未完成时有效）
+    escape_start: Option<usize>,
+    /// `\u` 转义剩余的十六进制位数
+    unicode_remaining: u8,
+}
+
+impl Scanner {
+    /// 最后一个非空白字符
+    fn last_significant(&self) -> Option<char> {
+        self.out.trim_end().chars().last()
+    }
+
+    /// 移除末尾多余的逗号（连同其后的空白）
+    fn trim_trailing_comma(&mut self) {
+        let trimmed = self.out.trim_end();
+        if trimmed.ends_with(',') {
+            let len = trimmed.len() - 1;
+            self.out.truncate(len);
+        }
+    }
+
+    fn push_string_char(&mut self, c: char) {
+        let pos = self.out.len();
+        self.out.push(c);
+        if self.unicode_remaining > 0 {
+            if !c.is_ascii_hexdigit() {
+                // 非法转义，交给 serde_json 报错
+                self.unicode_remaining = 0;
+                self.escape_start = None;
+                return;
+            }
+            self.unicode_remaining -= 1;
+            if self.unicode_remaining == 0 {
+                self.escape_start = None;
+            }
+        } else if self.escape_start.is_some() {
+            if c == 'u' {
+                self.unicode_remaining = 4;
+            } else {
+                self.escape_start = None;
+            }
+        } else if c == '\\' {
+            self.escape_start = Some(pos);
+        } else if c == '"' {
+            self.in_string = false;
+            if !self.string_is_key {
+                self.key_start = None;
+            }
+        }
+    }
+
+    /// 处理字符串外的一个字符，结构错误时返回 None
+    fn push_structural_char(&mut self, c: char) -> Option<()> {
+        match c {
+            '"' => {
+                self.string_is_key =
+                    self.stack.last() == Some(&Container::Object) && self.expect_key;
+                self.key_start = Some(self.out.len());
+                self.in_string = true;
+                self.out.push(c);
+            }
+            '{' => {
+                self.stack.push(Container::Object);
+                self.expect_key = true;
+                self.out.push(c);
+            }
+            '[' => {
+                self.stack.push(Container::Array);
+                self.expect_key = false;
+                self.out.push(c);
+            }
+            '}' | ']' => {
+                let expected = if c == '}' {
+                    Container::Object
+                } else {
+                    Container::Array
+                };
+                if self.stack.pop() != Some(expected) {
+                    return None;
+                }
+                self.trim_trailing_comma();
+                self.expect_key = false;
+                self.out.push(c);
+            }
+            ':' => {
+                self.expect_key = false;
+                self.key_start = None;
+                self.out.push(c);
+            }
+            ',' => {
+                self.expect_key = self.stack.last() == Some(&Container::Object);
+                self.out.push(c);
+            }
+            _ => self.out.push(c),
+        }
+        Some(())
+    }
+
+    /// 输入结束后补全未完成的结构
+    fn finish(mut self) -> String {
+        if self.in_string {
+            if let Some(start) = self.escape_start {
+                self.out.truncate(start);
+            }
+            self.out.push('"');
+            if !self.string_is_key {
+                self.key_start = None;
+            }
+        }
+
+        // 未写完的键（无论字符串是否闭合）整体丢弃
+        if let Some(start) = self.key_start {
+            self.out.truncate(start);
+            self.trim_trailing_comma();
+        }
+
+        self.complete_trailing_token();
+
+        match self.last_significant() {
+            Some(',') => self.trim_trailing_comma(),
+            Some(':') => self.out.push_str("null"),
+            _ => {}
+        }
+
+        while let Some(container) = self.stack.pop() {
+            self.out.push(match container {
+                Container::Object => '}',
+                Container::Array => ']',
+            });
+        }
+        self.out
+    }
+
+    /// 补全末尾被截断的字面量或数字
+    fn complete_trailing_token(&mut self) {
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        let token_start = self
+            .out
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')))
+            .map_or(0, |i| i + 1);
+        let token = &self.out[token_start..];
+        if token.is_empty() {
+            return;
+        }
+
+        let completion = if token.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            ["true", "false", "null"]
+                .into_iter()
+                .find(|literal| literal.starts_with(token))
+                .map(|literal| literal[token.len()..].to_string())
+        } else if token.ends_with(['.', 'e', 'E', '+', '-']) {
+            Some("0".to_string())
+        } else {
+            None
+        };
+        if let Some(completion) = completion {
+            self.out.push_str(&completion);
+        }
+    }
+}
+
+/// 尝试修复损坏的 JSON，返回可解析的修复结果（无法修复时返回 None）
+pub fn repair_json(input: &str) -> Option<String> {
+    let mut scanner = Scanner::default();
+    for c in input.chars() {
+        if scanner.in_string {
+            scanner.push_string_char(c);
+        } else {
+            scanner.push_structural_char(c)?;
+        }
+    }
+
+    let repaired = scanner.finish();
+    serde_json::from_str::<serde_json::Value>(&repaired)
+        .ok()
+        .map(|_| repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn test_repair_common_corruptions() {
+        let cases: &[(&str, Value)] = &[
+            // 值被截断
+            (
+                r#"{"path":"/tmp/a.txt","limit":"#,
+                json!({"path": "/tmp/a.txt", "limit": null}),
+            ),
+            (r#"{"ok":tr"#, json!({"ok": true})),
+            (r#"{"n":1."#, json!({"n": 1.0})),
+            (r#"{"n":2e"#, json!({"n": 2.0})),
+            (r#"{"items":[1,2,"#, json!({"items": [1, 2]})),
+            // 多余逗号
+            (r#"{"a":1,}"#, json!({"a": 1})),
+            (r#"{"a":[1,2,],}"#, json!({"a": [1, 2]})),
+            // 字符串未闭合
+            (r#"{"content":"hello wor"#, json!({"content": "hello wor"})),
+            (r#"{"content":"line\"#, json!({"content": "line"})),
+            (r#"{"content":"\u00"#, json!({"content": ""})),
+            (r#"{"content":"a\"b"#, json!({"content": "a\"b"})),
+            // 键未写完
+            (r#"{"a":1,"b"#, json!({"a": 1})),
+            (r#"{"a":1,"b""#, json!({"a": 1})),
+            (r#"{"a":{"#, json!({"a": {}})),
+            // 括号未闭合
+            (r#"{"a":{"b":[{"c":1}"#, json!({"a": {"b": [{"c": 1}]}})),
+            // 已经合法
+            (r#"{"a":"}"}"#, json!({"a": "}"})),
+        ];
+
+        for (input, expected) in cases {
+            let repaired = repair_json(input).unwrap_or_else(|| panic!("无法修复: {}", input));
+            let value: Value = serde_json::from_str(&repaired).unwrap();
+            assert_eq!(&value, expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_unrepairable_inputs() {
+        for input in [r#"{"a":[1}"#, r#"{"a":1}}"#, r#"{"a":1} x"#, r#"{"a":xyz}"#] {
+            assert_eq!(repair_json(input), None, "input: {}", input);
+        }
+    }
+
+    /// 随机生成 JSON 值
+    fn random_value(rng: &mut fastrand::Rng, depth: u32) -> Value {
+        let kind = if depth == 0 { rng.u8(..4) } else { rng.u8(..6) };
+        match kind {
+            0 => Value::Null,
+            1 => Value::Bool(rng.bool()),
+            2 => json!(rng.i64(-1000..1000) as f64 / 8.0),
+            3 => {
+                let chars = [
+                    'a', 'Z', ' ', '"', '\\', '\n', '{', ']', ',', ':', '中', 'é',
+                ];
+                Value::String(
+                    (0..rng.usize(..8))
+                        .map(|_| chars[rng.usize(..chars.len())])
+                        .collect(),
+                )
+            }
+            4 => Value::Array(
+                (0..rng.usize(..4))
+                    .map(|_| random_value(rng, depth - 1))
+                    .collect(),
+            ),
+            _ => Value::Object(
+                (0..rng.usize(..4))
+                    .map(|i| (format!("k{}", i), random_value(rng, depth - 1)))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_repaired_truncations_always_parse() {
+        let mut rng = fastrand::Rng::with_seed(0x5eed);
+        for _ in 0..200 {
+            let mut value = random_value(&mut rng, 3);
+            if !value.is_object() {
+                value = json!({ "v": value });
+            }
+            let text = if rng.bool() {
+                serde_json::to_string(&value).unwrap()
+            } else {
+                serde_json::to_string_pretty(&value).unwrap()
+            };
+
+            for (end, _) in text.char_indices().skip(1) {
+                let truncated = &text[..end];
+                let repaired = repair_json(truncated)
+                    .unwrap_or_else(|| panic!("截断后无法修复: {:?}", truncated));
+                assert!(serde_json::from_str::<Value>(&repaired).is_ok());
+            }
+            assert_eq!(repair_json(&text).as_deref(), Some(text.as_str()));
+        }
+    }
+}
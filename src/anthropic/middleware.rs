@@ -1,45 +1,212 @@
 //! Anthropic API 中间件
 
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
     body::Body,
     extract::State,
-    http::{Request, StatusCode},
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Json, Response},
 };
+use tracing::Instrument;
+use uuid::Uuid;
 
-use crate::common::auth;
-use crate::kiro::provider::KiroProvider;
+use crate::common::auth::{self, PrecomputedApiKey};
+use crate::common::ip_allowlist::IpAllowlist;
+use crate::common::model_pattern::find_by_model;
+use crate::kiro::openai::OpenAiProvider;
+use crate::kiro::provider::{KiroProvider, Provider, ServedCredential};
+use crate::kiro::token_manager::{CredentialEntrySnapshot, MultiTokenManager};
+use crate::model::config::{Config, DEFAULT_MAX_REQUEST_TIMEOUT_SECS, KIRO_BACKEND};
 
+use super::active_requests::ActiveRequests;
+use super::beta::BetaPolicy;
+use super::post_processing::TextFilters;
+use super::queue::RequestQueue;
+use super::rate_limit::RateLimiter;
+use super::redaction::Redactor;
+use super::response_store::ResponseStore;
 use super::types::ErrorResponse;
 
+/// 需要管理员权限的 /v1 功能（A/B 路由、dry-run）携带 Admin API Key 的请求头
+pub const ADMIN_KEY_HEADER: &str = "x-admin-key";
+
+/// 按请求启用透传模式的请求头（值为 `true`，需同时携带 X-Admin-Key）
+pub const PASSTHROUGH_HEADER: &str = "x-passthrough";
+
+/// 请求是否携带 `X-Passthrough: true`（不校验 X-Admin-Key）
+pub fn passthrough_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(PASSTHROUGH_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
 /// 应用共享状态
 #[derive(Clone)]
 pub struct AppState {
-    /// API 密钥
-    pub api_key: String,
+    /// API 密钥（启动时预计算，认证时常量时间比较且不分配）
+    pub api_key: PrecomputedApiKey,
+    /// 按名称注册的消息上游（`kiro` 为 KiroProvider 或模拟上游，其余来自 openaiBackends）
+    pub providers: Arc<HashMap<String, Arc<dyn Provider>>>,
+    /// 按模型选择上游后端（modelBackends）
+    pub model_backends: Arc<HashMap<String, String>>,
     /// Kiro Provider（可选，用于实际 API 调用）
     /// 内部使用 MultiTokenManager，已支持线程安全的多凭据管理
     pub kiro_provider: Option<Arc<KiroProvider>>,
+    /// 凭据管理器（与 KiroProvider 共享，用于请求级凭据状态检查）
+    pub token_manager: Option<Arc<MultiTokenManager>>,
     /// Profile ARN（可选，用于请求）
     pub profile_arn: Option<String>,
+    /// 响应文本后处理过滤器（配置了 postProcessing 时存在）
+    pub text_filters: Option<Arc<TextFilters>>,
+    /// 出站提示词脱敏规则（配置了 redaction 时存在）
+    pub redactor: Option<Arc<Redactor>>,
+    /// 上游请求队列（maxConcurrentUpstreamRequests 为 0 时不限制）
+    pub request_queue: Option<Arc<RequestQueue>>,
+    /// 消息请求速率限制器（未配置 rateLimitCapacity 时不限流）
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// anthropic-beta 放行/拒绝策略
+    pub beta_policy: Arc<BetaPolicy>,
+    /// 是否按客户端 Accept-Encoding 压缩响应体
+    pub response_compression: bool,
+    /// 非流式响应暂存区（未开启 enableResponseRetrieval 时为 None）
+    pub response_store: Option<Arc<ResponseStore>>,
+    /// 进行中的消息请求登记表（与 Admin API 共享以列出、取消请求）
+    pub active_requests: Arc<ActiveRequests>,
+    /// `x-kiro-timeout-secs` 允许的单请求上游超时上限（maxRequestTimeoutSecs）
+    pub max_request_timeout: Duration,
+    /// 允许跨域访问的来源（corsAllowedOrigins，None 表示任何来源）
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// 是否修复上游返回的损坏工具参数 JSON（repairToolInputs）
+    pub repair_tool_inputs: bool,
 }
 
 impl AppState {
     /// 创建新的应用状态
-    pub fn new(api_key: impl Into<String>) -> Self {
+    pub fn new(api_key: impl AsRef<str>) -> Self {
+        let Ok(api_key) = PrecomputedApiKey::from_str(api_key.as_ref());
         Self {
-            api_key: api_key.into(),
+            api_key,
+            providers: Arc::new(HashMap::new()),
+            model_backends: Arc::new(HashMap::new()),
             kiro_provider: None,
+            token_manager: None,
             profile_arn: None,
+            text_filters: None,
+            redactor: None,
+            request_queue: None,
+            rate_limiter: None,
+            beta_policy: Arc::new(BetaPolicy::default()),
+            response_compression: false,
+            response_store: None,
+            active_requests: Arc::default(),
+            max_request_timeout: Duration::from_secs(DEFAULT_MAX_REQUEST_TIMEOUT_SECS),
+            cors_allowed_origins: None,
+            repair_tool_inputs: false,
+        }
+    }
+
+    /// 设置 Kiro 消息上游，并按配置创建 OpenAI 协议后端、初始化模型路由、文本过滤器、脱敏规则、请求队列、beta 策略与响应暂存区
+    pub fn with_provider(mut self, provider: Arc<dyn Provider>, config: &Config) -> Self {
+        // 正则已在加载配置时校验，这里的编译失败仅记录日志并禁用过滤
+        self.text_filters = match TextFilters::from_config(&config.post_processing) {
+            Ok(filters) => filters.map(Arc::new),
+            Err(e) => {
+                tracing::error!("编译响应文本过滤器失败，已禁用后处理: {:#}", e);
+                None
+            }
+        };
+        // 脱敏规则同样已在加载配置时编译校验（启动时即报错），这里的失败仅记录日志
+        self.redactor = match Redactor::from_config(&config.redaction) {
+            Ok(redactor) => redactor.map(Arc::new),
+            Err(e) => {
+                tracing::error!("编译出站脱敏规则失败: {:#}", e);
+                None
+            }
+        };
+        self.request_queue = (config.max_concurrent_upstream_requests > 0).then(|| {
+            Arc::new(RequestQueue::new(
+                config.max_concurrent_upstream_requests,
+                Duration::from_secs(config.queue_wait_timeout_secs),
+            ))
+        });
+        self.rate_limiter = RateLimiter::from_config(config).map(Arc::new);
+        self.beta_policy = Arc::new(BetaPolicy::from_config(config));
+        self.response_compression = config.response_compression;
+        self.max_request_timeout = Duration::from_secs(config.max_request_timeout_secs);
+        self.cors_allowed_origins = config.cors_allowed_origins.clone();
+        self.repair_tool_inputs = config.repair_tool_inputs;
+        if config.is_production() && cors_allows_any_origin(&self.cors_allowed_origins) {
+            tracing::warn!(
+                "生产环境下 CORS 允许任何来源的浏览器请求，建议通过 corsAllowedOrigins 限制允许的来源"
+            );
+        }
+        self.response_store = config.enable_response_retrieval.then(|| {
+            let store = Arc::new(ResponseStore::new(
+                Duration::from_secs(config.response_retention_secs),
+                config.response_store_max_entries,
+                config.response_store_max_bytes,
+            ));
+            store.start_eviction();
+            store
+        });
+
+        let mut providers = HashMap::from([(KIRO_BACKEND.to_string(), provider)]);
+        for (name, backend) in &config.openai_backends {
+            match OpenAiProvider::from_config(name, backend, config) {
+                Ok(openai) => {
+                    providers.insert(name.clone(), Arc::new(openai) as Arc<dyn Provider>);
+                }
+                Err(e) => tracing::error!(
+                    "创建 OpenAI 后端 {} 失败，路由到该后端的请求将返回 503: {:#}",
+                    name,
+                    e
+                ),
+            }
         }
+        self.providers = Arc::new(providers);
+        self.model_backends = Arc::new(config.model_backends.clone());
+        self
+    }
+
+    /// 注册（或替换）指定名称的消息上游
+    #[cfg(test)]
+    pub fn with_backend(mut self, name: impl Into<String>, provider: Arc<dyn Provider>) -> Self {
+        Arc::make_mut(&mut self.providers).insert(name.into(), provider);
+        self
+    }
+
+    /// 按 modelBackends 选择处理该模型的上游，返回后端名称与对应的 Provider（未注册时为 None）
+    pub fn route_model(&self, model: &str) -> (&str, Option<Arc<dyn Provider>>) {
+        let backend = find_by_model(&self.model_backends, model)
+            .map(String::as_str)
+            .unwrap_or(KIRO_BACKEND);
+        (backend, self.providers.get(backend).cloned())
     }
 
-    /// 设置 KiroProvider
+    /// 设置 KiroProvider（同时共享其凭据管理器）
     pub fn with_kiro_provider(mut self, provider: KiroProvider) -> Self {
-        self.kiro_provider = Some(Arc::new(provider));
+        let provider = Arc::new(provider);
+        self = self.with_provider(provider.clone(), provider.token_manager().config());
+        self.token_manager = Some(provider.shared_token_manager());
+        self.kiro_provider = Some(provider);
+        self
+    }
+
+    /// 使用外部共享的速率限制器（与 Admin API 共享以导出/导入限流状态）
+    pub fn with_rate_limiter(mut self, rate_limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// 使用外部共享的进行中请求登记表（与 Admin API 共享以列出、取消请求）
+    pub fn with_active_requests(mut self, active_requests: Arc<ActiveRequests>) -> Self {
+        self.active_requests = active_requests;
         self
     }
 
@@ -48,6 +215,52 @@ impl AppState {
         self.profile_arn = Some(arn.into());
         self
     }
+
+    /// 客户端未携带 anthropic-version 时回显的默认版本
+    fn default_anthropic_version(&self) -> String {
+        self.token_manager
+            .as_ref()
+            .map(|m| m.config().anthropic_version.clone())
+            .unwrap_or_else(|| DEFAULT_ANTHROPIC_VERSION.to_string())
+    }
+
+    /// 是否在响应头中透出处理请求的凭据信息
+    fn expose_credential_id_header(&self) -> bool {
+        self.token_manager
+            .as_ref()
+            .is_some_and(|m| m.config().expose_credential_id_header)
+    }
+
+    /// 是否全局开启透传模式（passthroughMode）
+    pub fn passthrough_mode(&self) -> bool {
+        self.token_manager
+            .as_ref()
+            .is_some_and(|m| m.config().passthrough_mode)
+    }
+
+    /// 请求头中是否携带了与 adminApiKey 一致的 `X-Admin-Key`（未配置 adminApiKey 时始终为 false）
+    pub fn has_valid_admin_key(&self, headers: &HeaderMap) -> bool {
+        let admin_key = self
+            .token_manager
+            .as_ref()
+            .and_then(|m| m.config().admin_api_key.clone())
+            .filter(|k| !k.is_empty());
+        match (admin_key, headers.get(ADMIN_KEY_HEADER)) {
+            (Some(expected), Some(provided)) => provided
+                .to_str()
+                .is_ok_and(|provided| auth::constant_time_eq(provided, &expected)),
+            _ => false,
+        }
+    }
+
+    /// 获取当前活跃凭据的状态快照
+    pub fn current_credential_snapshot(&self) -> Option<CredentialEntrySnapshot> {
+        let snapshot = self.token_manager.as_ref()?.snapshot();
+        snapshot
+            .entries
+            .into_iter()
+            .find(|e| e.id == snapshot.current_id)
+    }
 }
 
 /// API Key 认证中间件
@@ -56,8 +269,8 @@ pub async fn auth_middleware(
     request: Request<Body>,
     next: Next,
 ) -> Response {
-    match auth::extract_api_key(&request) {
-        Some(key) if auth::constant_time_eq(&key, &state.api_key) => next.run(request).await,
+    match auth::api_key_from_headers(request.headers()) {
+        Some(key) if state.api_key.matches(key) => next.run(request).await,
         _ => {
             let error = ErrorResponse::authentication_error();
             (StatusCode::UNAUTHORIZED, Json(error)).into_response()
@@ -65,20 +278,451 @@ pub async fn auth_middleware(
     }
 }
 
+/// `X-Admin-Key` 来源 IP 限制中间件
+///
+/// 配置 `adminAllowedIps` 后，`/v1` 下携带 `X-Admin-Key` 的请求（A/B 凭据路由、按请求透传、dry-run）
+/// 必须来自允许的地址，否则返回 403，避免泄露的 Admin Key 在任意来源生效
+pub async fn admin_key_allowlist_middleware(
+    State(allowlist): State<Arc<IpAllowlist>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    if !request.headers().contains_key(ADMIN_KEY_HEADER) || allowlist.allows(&request) {
+        return next.run(request).await;
+    }
+    tracing::warn!(
+        "拒绝来源 IP 不在 adminAllowedIps 中的 X-Admin-Key 请求: {}",
+        request.uri().path()
+    );
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse::new(
+            "permission_error",
+            "X-Admin-Key is not allowed from this address.",
+        )),
+    )
+        .into_response()
+}
+
+/// dry-run 端点认证中间件
+///
+/// 普通 API Key 默认无权访问：需携带有效的 `X-Admin-Key`，
+/// 或在配置中开启 `dryRunEnabled` 后使用普通 API Key
+pub async fn dry_run_auth_middleware(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    if state.has_valid_admin_key(request.headers()) {
+        return next.run(request).await;
+    }
+
+    let api_key_valid =
+        auth::api_key_from_headers(request.headers()).is_some_and(|key| state.api_key.matches(key));
+    let dry_run_enabled = state
+        .token_manager
+        .as_ref()
+        .is_some_and(|m| m.config().dry_run_enabled);
+    match (api_key_valid, dry_run_enabled) {
+        (true, true) => next.run(request).await,
+        (true, false) => (
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(
+                "permission_error",
+                "Dry-run requires a valid X-Admin-Key header.",
+            )),
+        )
+            .into_response(),
+        (false, _) => {
+            let error = ErrorResponse::authentication_error();
+            (StatusCode::UNAUTHORIZED, Json(error)).into_response()
+        }
+    }
+}
+
+/// 请求 ID
+///
+/// 由响应头中间件生成并写入请求扩展，同时作为日志 span 字段和 `request-id` 响应头，
+/// 便于将客户端错误报告与服务端日志关联
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// 未配置时使用的默认 anthropic-version
+const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// 处理请求的凭据 ID 响应头（需开启 exposeCredentialIdHeader）
+const CREDENTIAL_ID_HEADER: &str = "x-credential-id";
+
+/// 处理请求的凭据认证方式响应头（需开启 exposeCredentialIdHeader）
+const CREDENTIAL_AUTH_METHOD_HEADER: &str = "x-credential-auth-method";
+
+/// Anthropic 兼容响应头中间件
+///
+/// 为所有响应（包括 401/400/500 等错误响应和流式响应）附加：
+/// - `request-id`：复用请求扩展中已有的 [`RequestId`]，否则生成新的
+/// - `anthropic-version`：回显请求头中的版本，未携带时使用配置的默认值
+///
+/// 开启 `exposeCredentialIdHeader` 时，成功响应还会附加 `X-Credential-ID` 与
+/// `X-Credential-Auth-Method`（取自处理器写入响应扩展的 [`ServedCredential`]）
+pub async fn response_headers_middleware(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let request_id = match request.extensions().get::<RequestId>() {
+        Some(id) => id.0.clone(),
+        None => {
+            let id = format!("req_{}", Uuid::new_v4().simple());
+            request.extensions_mut().insert(RequestId(id.clone()));
+            id
+        }
+    };
+
+    let anthropic_version = request
+        .headers()
+        .get("anthropic-version")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| state.default_anthropic_version());
+
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let mut response = next.run(request).instrument(span).await;
+
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        headers.insert("request-id", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&anthropic_version) {
+        headers.insert("anthropic-version", value);
+    }
+
+    let served = response
+        .extensions()
+        .get::<ServedCredential>()
+        .cloned()
+        .filter(|_| state.expose_credential_id_header() && response.status().is_success());
+    if let Some(served) = served {
+        let headers = response.headers_mut();
+        headers.insert(CREDENTIAL_ID_HEADER, HeaderValue::from(served.id));
+        if let Some(value) = served
+            .auth_method
+            .and_then(|m| HeaderValue::from_str(&m).ok())
+        {
+            headers.insert(CREDENTIAL_AUTH_METHOD_HEADER, value);
+        }
+    }
+    response
+}
+
 /// CORS 中间件层
 ///
-/// **安全说明**：当前配置允许所有来源（Any），这是为了支持公开 API 服务。
-/// 如果需要更严格的安全控制，请根据实际需求配置具体的允许来源、方法和头信息。
+/// `origins` 为 `corsAllowedOrigins` 配置：
+/// - 未配置（None）或包含 `"*"`：允许任何来源（Any），用于公开 API 服务
+/// - 其他情况：仅允许列表中的来源，`"null"` 匹配 file:// 页面发出的请求
 ///
-/// # 配置说明
-/// - `allow_origin(Any)`: 允许任何来源的请求
-/// - `allow_methods(Any)`: 允许任何 HTTP 方法
-/// - `allow_headers(Any)`: 允许任何请求头
-pub fn cors_layer() -> tower_http::cors::CorsLayer {
-    use tower_http::cors::{Any, CorsLayer};
+/// 方法与请求头始终允许任意值（Any），认证由 API Key 负责
+pub fn cors_layer(origins: &Option<Vec<String>>) -> tower_http::cors::CorsLayer {
+    use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+    let allow_origin = match origins {
+        Some(origins) if !origins.iter().any(|o| o.trim() == "*") => {
+            // 非法来源已在加载配置时校验，这里仅跳过并记录日志
+            let origins = origins.iter().filter_map(|origin| {
+                HeaderValue::from_str(origin.trim())
+                    .inspect_err(|_| tracing::warn!("忽略无效的 CORS 来源: {:?}", origin))
+                    .ok()
+            });
+            AllowOrigin::list(origins)
+        }
+        _ => AllowOrigin::from(Any),
+    };
 
     CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(allow_origin)
         .allow_methods(Any)
         .allow_headers(Any)
 }
+
+/// `corsAllowedOrigins` 是否允许任何来源
+pub fn cors_allows_any_origin(origins: &Option<Vec<String>>) -> bool {
+    origins
+        .as_ref()
+        .is_none_or(|origins| origins.iter().any(|o| o.trim() == "*"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::credentials::KiroCredentials;
+    use crate::model::config::Config;
+    use axum::{Router, routing::post};
+
+    fn credential(subscription_title: &str, priority: u32) -> KiroCredentials {
+        KiroCredentials {
+            refresh_token: Some(format!("{}-{}", subscription_title, priority).repeat(20)),
+            subscription_title: Some(subscription_title.to_string()),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_key_rejected_from_disallowed_address() {
+        // 对端地址为 127.0.0.1
+        let spawn = |allowed: &str| {
+            let mut config = Config::default();
+            config.admin_allowed_ips = vec![allowed.to_string()];
+            let allowlist = Arc::new(IpAllowlist::from_config(&config).unwrap().unwrap());
+            let router = Router::new()
+                .route("/v1/messages", post(|| async { StatusCode::OK }))
+                .layer(axum::middleware::from_fn_with_state(
+                    allowlist,
+                    admin_key_allowlist_middleware,
+                ));
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                tokio::spawn(async move {
+                    let app = router.into_make_service_with_connect_info::<std::net::SocketAddr>();
+                    axum::serve(listener, app).await.unwrap();
+                });
+                format!("http://{}/v1/messages", addr)
+            }
+        };
+        let post_with = |url: String, admin_key: bool| async move {
+            let mut request = reqwest::Client::new().post(url);
+            if admin_key {
+                request = request.header(ADMIN_KEY_HEADER, "admin");
+            }
+            request.send().await.unwrap()
+        };
+
+        let denied = spawn("10.0.0.0/8").await;
+        let resp = post_with(denied.clone(), true).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "permission_error");
+        // 未携带 X-Admin-Key 的普通请求不受限制
+        assert_eq!(post_with(denied, false).await.status(), StatusCode::OK);
+
+        let allowed = spawn("127.0.0.0/8").await;
+        assert_eq!(post_with(allowed, true).await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_current_credential_snapshot() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![credential("KIRO PRO", 0)],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let mut state = AppState::new("test-key");
+        assert!(state.current_credential_snapshot().is_none());
+
+        state.token_manager = Some(Arc::new(manager));
+        let snapshot = state.current_credential_snapshot().unwrap();
+        assert_eq!(snapshot.subscription_title.as_deref(), Some("KIRO PRO"));
+    }
+
+    async fn spawn_router(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    fn assert_anthropic_headers(resp: &reqwest::Response, expected_version: &str) {
+        let request_id = resp.headers().get("request-id").unwrap().to_str().unwrap();
+        assert!(request_id.starts_with("req_"), "{}", request_id);
+        assert_eq!(
+            resp.headers().get("anthropic-version").unwrap(),
+            expected_version
+        );
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_on_success() {
+        let base = spawn_router(crate::anthropic::router::create_router_with_provider(
+            "test-key", None, None,
+        ))
+        .await;
+
+        let resp = reqwest::Client::new()
+            .get(format!("{}/v1/models", base))
+            .header("x-api-key", "test-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_anthropic_headers(&resp, DEFAULT_ANTHROPIC_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_on_auth_failure() {
+        let base = spawn_router(crate::anthropic::router::create_router_with_provider(
+            "test-key", None, None,
+        ))
+        .await;
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/cc/v1/messages", base))
+            .header("anthropic-version", "2024-10-22")
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_anthropic_headers(&resp, "2024-10-22");
+    }
+
+    async fn count_tokens_with_credential_header(expose: bool) -> (reqwest::Response, u64) {
+        let mut config = Config::default();
+        config.expose_credential_id_header = expose;
+        let mut cred = credential("KIRO PRO", 0);
+        cred.id = Some(7);
+        cred.auth_method = Some("social".to_string());
+        let manager =
+            Arc::new(MultiTokenManager::new(config, vec![cred], None, None, false).unwrap());
+        let current_id = manager.snapshot().current_id;
+
+        let provider = KiroProvider::new(manager);
+        let base = spawn_router(crate::anthropic::router::create_router_with_provider(
+            "test-key",
+            Some(provider),
+            None,
+        ))
+        .await;
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/v1/messages/count_tokens", base))
+            .header("x-api-key", "test-key")
+            .json(&serde_json::json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{ "role": "user", "content": "hello" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        (resp, current_id)
+    }
+
+    #[tokio::test]
+    async fn test_credential_id_header_when_enabled() {
+        let (resp, current_id) = count_tokens_with_credential_header(true).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(CREDENTIAL_ID_HEADER).unwrap(),
+            current_id.to_string().as_str()
+        );
+        assert_eq!(
+            resp.headers().get(CREDENTIAL_AUTH_METHOD_HEADER).unwrap(),
+            "social"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_credential_id_header_absent_by_default() {
+        let (resp, _) = count_tokens_with_credential_header(false).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(CREDENTIAL_ID_HEADER).is_none());
+        assert!(resp.headers().get(CREDENTIAL_AUTH_METHOD_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_on_streaming_response() {
+        use axum::routing::get;
+
+        let router = Router::new()
+            .route(
+                "/v1/stream",
+                get(|| async {
+                    let chunks = futures::stream::iter(vec![
+                        Ok::<_, std::convert::Infallible>("event: ping\n\n"),
+                        Ok("event: message_stop\n\n"),
+                    ]);
+                    Response::builder()
+                        .header("content-type", "text/event-stream")
+                        .body(Body::from_stream(chunks))
+                        .unwrap()
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                AppState::new("test-key"),
+                response_headers_middleware,
+            ));
+        let base = spawn_router(router).await;
+
+        let resp = reqwest::get(format!("{}/v1/stream", base)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_anthropic_headers(&resp, DEFAULT_ANTHROPIC_VERSION);
+        assert!(resp.text().await.unwrap().contains("message_stop"));
+    }
+
+    async fn get_models_from(base: &str, origin: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/v1/models", base))
+            .header("x-api-key", "test-key")
+            .header("origin", origin)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_allowed_origins() {
+        let mut state = AppState::new("test-key");
+        state.cors_allowed_origins = Some(vec![
+            "https://allowed.example.com".to_string(),
+            "null".to_string(),
+        ]);
+        let base = spawn_router(crate::anthropic::router::create_router(state)).await;
+
+        let resp = get_models_from(&base, "https://allowed.example.com").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("access-control-allow-origin").unwrap(),
+            "https://allowed.example.com"
+        );
+
+        let resp = get_models_from(&base, "null").await;
+        assert_eq!(
+            resp.headers().get("access-control-allow-origin").unwrap(),
+            "null"
+        );
+
+        let resp = get_models_from(&base, "https://other.example.com").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_any_origin_by_default_and_with_wildcard() {
+        let base = spawn_router(crate::anthropic::router::create_router_with_provider(
+            "test-key", None, None,
+        ))
+        .await;
+        let resp = get_models_from(&base, "https://other.example.com").await;
+        assert_eq!(
+            resp.headers().get("access-control-allow-origin").unwrap(),
+            "*"
+        );
+
+        let mut state = AppState::new("test-key");
+        state.cors_allowed_origins = Some(vec![
+            "https://allowed.example.com".to_string(),
+            "*".to_string(),
+        ]);
+        assert!(cors_allows_any_origin(&state.cors_allowed_origins));
+        let base = spawn_router(crate::anthropic::router::create_router(state)).await;
+        let resp = get_models_from(&base, "https://other.example.com").await;
+        assert_eq!(
+            resp.headers().get("access-control-allow-origin").unwrap(),
+            "*"
+        );
+    }
+}
@@ -8,6 +8,7 @@
 //! - `GET /v1/models` - 获取可用模型列表
 //! - `POST /v1/messages` - 创建消息（对话）
 //! - `POST /v1/messages/count_tokens` - 计算 token 数量
+//! - `GET /v1/messages/{id}` - 获取暂存的非流式响应（需开启 enableResponseRetrieval）
 //!
 //! ## Claude Code 兼容端点 (/cc/v1)
 //! - `POST /cc/v1/messages` - 创建消息（流式响应会等待 contextUsageEvent 后再发送 message_start，确保 input_tokens 准确）
@@ -22,12 +23,24 @@
 //! axum::serve(listener, app).await?;
 //! ```
 
-mod converter;
+pub mod active_requests;
+mod beta;
+pub mod converter;
 mod handlers;
+mod json_repair;
 mod middleware;
+mod models;
+pub mod post_processing;
+mod queue;
+pub mod rate_limit;
+pub mod redaction;
+mod response_store;
 mod router;
+mod schema_validator;
 mod stream;
+mod tool_validation;
 pub mod types;
 mod websearch;
 
-pub use router::create_router_with_provider;
+pub use middleware::admin_key_allowlist_middleware;
+pub use router::{create_router_with_mock_provider, create_router_with_shared_rate_limiter};
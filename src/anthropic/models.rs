@@ -0,0 +1,140 @@
+//! 模型注册表
+//!
+//! `GET /v1/models` 返回的模型列表与对话长度预检使用的上下文窗口均来自这里
+
+use super::converter::map_model;
+use super::types::Model;
+
+/// 上下文窗口大小（200k tokens，当前所有模型相同）
+pub const CONTEXT_WINDOW_SIZE: i32 = 200_000;
+
+/// 注册的模型
+struct ModelSpec {
+    id: &'static str,
+    display_name: &'static str,
+    created: i64,
+    max_tokens: i32,
+    /// 上下文窗口（tokens）
+    context_window: i32,
+}
+
+const MODELS: &[ModelSpec] = &[
+    ModelSpec {
+        id: "claude-sonnet-4-5-20250929",
+        display_name: "Claude Sonnet 4.5",
+        created: 1727568000,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-sonnet-4-5-20250929-thinking",
+        display_name: "Claude Sonnet 4.5 (Thinking)",
+        created: 1727568000,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-opus-4-5-20251101",
+        display_name: "Claude Opus 4.5",
+        created: 1730419200,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-opus-4-5-20251101-thinking",
+        display_name: "Claude Opus 4.5 (Thinking)",
+        created: 1730419200,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-sonnet-4-6",
+        display_name: "Claude Sonnet 4.6",
+        created: 1770314400,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-sonnet-4-6-thinking",
+        display_name: "Claude Sonnet 4.6 (Thinking)",
+        created: 1770314400,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-opus-4-6",
+        display_name: "Claude Opus 4.6",
+        created: 1770314400,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-opus-4-6-thinking",
+        display_name: "Claude Opus 4.6 (Thinking)",
+        created: 1770314400,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-haiku-4-5-20251001",
+        display_name: "Claude Haiku 4.5",
+        created: 1727740800,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+    ModelSpec {
+        id: "claude-haiku-4-5-20251001-thinking",
+        display_name: "Claude Haiku 4.5 (Thinking)",
+        created: 1727740800,
+        max_tokens: 32000,
+        context_window: CONTEXT_WINDOW_SIZE,
+    },
+];
+
+/// 注册的全部模型（`GET /v1/models` 的响应条目）
+pub fn registered_models() -> Vec<Model> {
+    MODELS
+        .iter()
+        .map(|m| Model {
+            id: m.id.to_string(),
+            object: "model".to_string(),
+            created: m.created,
+            owned_by: "anthropic".to_string(),
+            display_name: m.display_name.to_string(),
+            model_type: "chat".to_string(),
+            max_tokens: m.max_tokens,
+        })
+        .collect()
+}
+
+/// 模型的上下文窗口
+///
+/// 先按模型 ID 精确匹配，再按映射后的 Kiro 模型匹配（如 `claude-sonnet-4-5` 对应
+/// `claude-sonnet-4-5-20250929`）；未注册的模型为 [`CONTEXT_WINDOW_SIZE`]
+pub fn context_window(model: &str) -> i32 {
+    let mapped = map_model(model);
+    MODELS
+        .iter()
+        .find(|m| m.id == model)
+        .or_else(|| {
+            mapped.as_ref().and_then(|mapped| {
+                MODELS
+                    .iter()
+                    .find(|m| map_model(m.id).as_ref() == Some(mapped))
+            })
+        })
+        .map_or(CONTEXT_WINDOW_SIZE, |m| m.context_window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_window_matches_registered_and_mapped_models() {
+        assert_eq!(context_window("claude-opus-4-6"), CONTEXT_WINDOW_SIZE);
+        assert_eq!(context_window("claude-sonnet-4-5"), CONTEXT_WINDOW_SIZE);
+        assert_eq!(context_window("unknown-model"), CONTEXT_WINDOW_SIZE);
+        assert_eq!(registered_models().len(), MODELS.len());
+    }
+}
@@ -0,0 +1,956 @@
+//! 响应文本后处理
+//!
+//! 按配置顺序对文本内容应用过滤器（正则替换、移除开头的固定前缀），
+//! 流式响应的 text_delta 与非流式响应的文本块共用同一套逻辑：
+//! - 每个过滤器都是一个流式阶段，上一阶段的输出作为下一阶段的输入
+//! - 正则阶段保留末尾 [`FILTER_LOOKBEHIND_BYTES`] 字节不输出，确保跨 chunk 的匹配能被替换
+//! - 前缀阶段只作用于整个响应的首个文本块，判定完成后直接透传
+//! - 各阶段暂存的文本不超过 [`FILTER_MAX_PENDING_BYTES`]，超出后按已收到的文本直接判定，
+//!   超大响应不会在过滤器中无限累积
+//!
+//! 请求包含 assistant prefill 时，过滤链最前面额外加入 prefill 回显阶段
+//! （[`TextFilterStream::with_prefill_echo`]），移除模型在开头重复输出的 prefill 文本。
+//!
+//! JSON 模式下过滤链末尾额外加入 JSON 输出阶段（[`TextFilterStream::with_json_output`]），
+//! 移除包裹整个响应的 Markdown 代码块围栏，并在结束后校验输出是否为合法 JSON。
+//!
+//! 调用方只应把文本内容送入过滤器，thinking 与 tool_use 内容不经过此模块。
+
+use anyhow::Context;
+use regex::Regex;
+
+use crate::model::config::{PostProcessingConfig, TextFilterConfig};
+
+/// 正则阶段为跨 chunk 匹配保留的最大字节数（更长的跨 chunk 匹配无法保证被替换）
+pub const FILTER_LOOKBEHIND_BYTES: usize = 128;
+
+/// 各阶段暂存待判定文本的上限（超出后放弃等待，按已收到的文本判定并输出）
+pub const FILTER_MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// JSON 输出阶段为校验累积的最大字节数，超出后不再校验
+const JSON_VALIDATION_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// prefill 回显阶段中，部分回显（prefill 的后缀）至少需要的字符数，过短的重叠视为正常续写
+const MIN_PARTIAL_ECHO_CHARS: usize = 3;
+
+/// 查找不超过 target 的最近 UTF-8 字符边界
+fn floor_char_boundary(s: &str, target: usize) -> usize {
+    let mut pos = target.min(s.len());
+    while pos > 0 && !s.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+/// 编译后的过滤器列表（可在请求间共享，每个响应通过 [`TextFilters::stream`] 创建独立状态）
+#[derive(Debug, Clone)]
+pub struct TextFilters {
+    filters: Vec<CompiledFilter>,
+}
+
+#[derive(Debug, Clone)]
+enum CompiledFilter {
+    Regex {
+        regex: Regex,
+        replacement: String,
+        first_match_only: bool,
+    },
+    StripPrefix {
+        prefixes: Vec<String>,
+    },
+}
+
+impl TextFilters {
+    /// 编译过滤器配置（正则无效时返回错误）
+    pub fn compile(filters: &[TextFilterConfig]) -> anyhow::Result<Self> {
+        let filters = filters
+            .iter()
+            .enumerate()
+            .map(|(i, filter)| match filter {
+                TextFilterConfig::Regex {
+                    pattern,
+                    replacement,
+                    first_match_only,
+                } => Ok(CompiledFilter::Regex {
+                    regex: Regex::new(pattern)
+                        .with_context(|| format!("filters[{}] 正则表达式无效: {}", i, pattern))?,
+                    replacement: replacement.clone(),
+                    first_match_only: *first_match_only,
+                }),
+                TextFilterConfig::StripPrefix { prefixes } => Ok(CompiledFilter::StripPrefix {
+                    prefixes: prefixes.iter().filter(|p| !p.is_empty()).cloned().collect(),
+                }),
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { filters })
+    }
+
+    /// 从配置构建过滤器，未配置任何过滤器时返回 None
+    pub fn from_config(config: &PostProcessingConfig) -> anyhow::Result<Option<Self>> {
+        if config.is_empty() {
+            return Ok(None);
+        }
+        Self::compile(&config.filters).map(Some)
+    }
+
+    /// 为单个响应创建流式过滤状态
+    pub fn stream(&self) -> TextFilterStream {
+        TextFilterStream {
+            stages: self
+                .filters
+                .iter()
+                .map(|filter| match filter {
+                    CompiledFilter::Regex {
+                        regex,
+                        replacement,
+                        first_match_only,
+                    } => Stage::Regex(RegexStage {
+                        regex: regex.clone(),
+                        replacement: replacement.clone(),
+                        first_match_only: *first_match_only,
+                        done: false,
+                        pending: String::new(),
+                    }),
+                    CompiledFilter::StripPrefix { prefixes } => Stage::StripPrefix(PrefixStage {
+                        prefixes: prefixes.clone(),
+                        decided: prefixes.is_empty(),
+                        trim_following: false,
+                        pending: String::new(),
+                    }),
+                })
+                .collect(),
+        }
+    }
+
+    /// 对一段完整文本应用过滤器（非流式响应、Admin 试运行）
+    pub fn apply(&self, text: &str) -> String {
+        let mut stream = self.stream();
+        let mut output = stream.push(text);
+        output.push_str(&stream.flush());
+        output
+    }
+
+    /// 按固定大小切分后逐段送入流式过滤（Admin 试运行，用于模拟流式响应的分片）
+    pub fn apply_in_chunks(&self, text: &str, chunk_size: usize) -> String {
+        let mut stream = self.stream();
+        let mut output = String::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            // 至少切出一个完整字符，避免 chunk_size 小于字符宽度时死循环
+            let mut cut = floor_char_boundary(rest, chunk_size.max(1));
+            if cut == 0 {
+                cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+            }
+            output.push_str(&stream.push(&rest[..cut]));
+            rest = &rest[cut..];
+        }
+        output.push_str(&stream.flush());
+        output
+    }
+}
+
+/// 单个响应的流式过滤状态
+#[derive(Debug, Default)]
+pub struct TextFilterStream {
+    stages: Vec<Stage>,
+}
+
+impl TextFilterStream {
+    /// 送入一段文本，返回当前可以安全输出的部分（可能为空）
+    pub fn push(&mut self, text: &str) -> String {
+        let mut output = text.to_string();
+        for stage in &mut self.stages {
+            output = stage.push(&output);
+        }
+        output
+    }
+
+    /// 在过滤链最前面加入 prefill 回显阶段
+    pub fn with_prefill_echo(mut self, prefill: &str) -> Self {
+        self.stages
+            .insert(0, Stage::PrefillEcho(PrefillEchoStage::new(prefill)));
+        self
+    }
+
+    /// 在过滤链末尾加入 JSON 输出阶段
+    pub fn with_json_output(mut self) -> Self {
+        self.stages
+            .push(Stage::JsonOutput(JsonOutputStage::default()));
+        self
+    }
+
+    /// 在过滤链末尾加入续写 prefill 的 JSON 输出阶段
+    ///
+    /// 输出是 prefill 的续写，不移除围栏；校验 prefill 与输出拼接后的文本
+    pub fn with_json_output_after(mut self, prefill: &str) -> Self {
+        self.stages.push(Stage::JsonOutput(JsonOutputStage {
+            state: FenceState::Plain,
+            pending: String::new(),
+            emitted: prefill.to_string(),
+            emitted_overflow: false,
+        }));
+        self
+    }
+
+    /// JSON 模式下已输出的文本是否为合法 JSON
+    ///
+    /// 未启用 JSON 模式或尚未输出任何非空白文本（如只有工具调用）时返回 None，
+    /// 应在 [`flush`](Self::flush) 之后调用
+    pub fn json_output_valid(&self) -> Option<bool> {
+        self.stages.iter().find_map(|stage| match stage {
+            Stage::JsonOutput(stage) => stage.is_valid(),
+            _ => None,
+        })
+    }
+
+    /// 对一段完整文本应用过滤
+    fn apply(&mut self, text: &str) -> String {
+        let mut output = self.push(text);
+        output.push_str(&self.flush());
+        output
+    }
+
+    /// 对非流式响应的完整文本应用过滤器，跳过开头的 `<thinking>...</thinking>` 部分
+    pub fn apply_outside_thinking(&mut self, text: &str) -> String {
+        const START: &str = "<thinking>";
+        const END: &str = "</thinking>";
+        if text.trim_start().starts_with(START) {
+            if let Some(end) = text.find(END) {
+                let split = end + END.len();
+                return format!("{}{}", &text[..split], self.apply(&text[split..]));
+            }
+            // thinking 未闭合，整段均视为 thinking 内容
+            return text.to_string();
+        }
+        self.apply(text)
+    }
+
+    /// 输出所有暂存内容（文本块结束、切换到 thinking / tool_use 或流结束时调用）
+    ///
+    /// 首个文本块的前缀判定在此处结束，之后的文本块不再移除前缀。
+    pub fn flush(&mut self) -> String {
+        let mut output = String::new();
+        for stage in &mut self.stages {
+            let mut chunk = stage.push(&output);
+            chunk.push_str(&stage.flush());
+            output = chunk;
+        }
+        output
+    }
+}
+
+#[derive(Debug)]
+enum Stage {
+    Regex(RegexStage),
+    StripPrefix(PrefixStage),
+    PrefillEcho(PrefillEchoStage),
+    JsonOutput(JsonOutputStage),
+}
+
+impl Stage {
+    fn push(&mut self, text: &str) -> String {
+        match self {
+            Stage::Regex(stage) => stage.push(text),
+            Stage::StripPrefix(stage) => stage.push(text),
+            Stage::PrefillEcho(stage) => stage.push(text),
+            Stage::JsonOutput(stage) => stage.push(text),
+        }
+    }
+
+    fn flush(&mut self) -> String {
+        match self {
+            Stage::Regex(stage) => stage.flush(),
+            Stage::PrefillEcho(stage) => stage.flush(),
+            Stage::JsonOutput(stage) => stage.flush(),
+            Stage::StripPrefix(stage) => {
+                // 尚未收到任何文本时（如响应以 thinking 开头）保持待判定
+                if !stage.pending.is_empty() {
+                    stage.decided = true;
+                }
+                stage.trim_following = false;
+                std::mem::take(&mut stage.pending)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RegexStage {
+    regex: Regex,
+    replacement: String,
+    first_match_only: bool,
+    /// first_match_only 且已替换过一次
+    done: bool,
+    /// 尚未输出的原始文本
+    pending: String,
+}
+
+impl RegexStage {
+    fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        if self.done {
+            return std::mem::take(&mut self.pending);
+        }
+
+        // 保留末尾一段文本等待后续 chunk，且不能从某个匹配的中间截断；
+        // 跨越截断点的匹配过长时仍按 lookbehind 截断，避免暂存文本无限增长
+        let mut cut = floor_char_boundary(
+            &self.pending,
+            self.pending.len().saturating_sub(FILTER_LOOKBEHIND_BYTES),
+        );
+        if let Some(m) = self
+            .regex
+            .find_iter(&self.pending)
+            .find(|m| m.start() < cut && m.end() > cut)
+            .filter(|m| self.pending.len() - m.start() <= FILTER_MAX_PENDING_BYTES)
+        {
+            cut = m.start();
+        }
+        if cut == 0 {
+            return String::new();
+        }
+
+        let ready: String = self.pending.drain(..cut).collect();
+        self.replace(&ready)
+    }
+
+    fn flush(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        if self.done {
+            return rest;
+        }
+        self.replace(&rest)
+    }
+
+    fn replace(&mut self, text: &str) -> String {
+        if self.first_match_only {
+            if !self.regex.is_match(text) {
+                return text.to_string();
+            }
+            self.done = true;
+            return self
+                .regex
+                .replacen(text, 1, self.replacement.as_str())
+                .into_owned();
+        }
+        self.regex
+            .replace_all(text, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+#[derive(Debug)]
+struct PrefixStage {
+    prefixes: Vec<String>,
+    /// 是否已完成首个文本块开头的前缀判定
+    decided: bool,
+    /// 已移除前缀但尚未收到非空白内容，继续丢弃前缀之后的空白
+    trim_following: bool,
+    /// 判定完成前暂存的文本
+    pending: String,
+}
+
+impl PrefixStage {
+    fn push(&mut self, text: &str) -> String {
+        if self.trim_following {
+            let rest = text.trim_start();
+            self.trim_following = rest.is_empty();
+            return rest.to_string();
+        }
+        if self.decided {
+            return text.to_string();
+        }
+        self.pending.push_str(text);
+
+        // 允许前缀之前存在空白字符
+        let head = self.pending.trim_start();
+        if let Some(prefix) = self.prefixes.iter().find(|p| head.starts_with(p.as_str())) {
+            let rest = head[prefix.len()..].trim_start().to_string();
+            tracing::debug!(prefix = %prefix, "已移除响应开头的固定前缀");
+            self.pending.clear();
+            self.decided = true;
+            self.trim_following = rest.is_empty();
+            return rest;
+        }
+
+        // 仍可能是某个前缀的一部分（或尚未收到非空白内容），继续等待
+        let waiting = head.is_empty() || self.prefixes.iter().any(|p| p.starts_with(head));
+        if waiting && self.pending.len() <= FILTER_MAX_PENDING_BYTES {
+            return String::new();
+        }
+
+        self.decided = true;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// 移除模型在响应开头回显的 prefill 文本
+///
+/// 按边界匹配，只移除以下两种回显：
+/// - 完整的 prefill（允许前导空白）
+/// - prefill 从词边界开始的后缀，且至少 [`MIN_PARTIAL_ECHO_CHARS`] 个字符
+///   （如 prefill 以 `is Par` 结尾、模型输出 `Paris.` 时移除 `Par`）
+///
+/// 正常续写通常以空白、标点或单词剩余部分开头，不会被误判为回显。
+#[derive(Debug)]
+struct PrefillEchoStage {
+    prefill: String,
+    /// 可能被部分回显的后缀，按长度降序
+    suffixes: Vec<String>,
+    /// 是否已完成判定
+    decided: bool,
+    /// 判定完成前暂存的文本
+    pending: String,
+}
+
+impl PrefillEchoStage {
+    fn new(prefill: &str) -> Self {
+        let boundary = |c: char| c.is_whitespace() || c.is_ascii_punctuation();
+        // 后缀从词边界开始：前一个字符是空白或标点，且自身不以空白开头
+        let suffixes = prefill
+            .char_indices()
+            .skip(1)
+            .filter(|&(i, c)| {
+                !c.is_whitespace() && prefill[..i].chars().next_back().is_some_and(boundary)
+            })
+            .map(|(i, _)| prefill[i..].to_string())
+            .filter(|s| s.chars().count() >= MIN_PARTIAL_ECHO_CHARS)
+            .collect();
+        Self {
+            prefill: prefill.to_string(),
+            suffixes,
+            decided: prefill.is_empty(),
+            pending: String::new(),
+        }
+    }
+
+    fn push(&mut self, text: &str) -> String {
+        if self.decided {
+            return text.to_string();
+        }
+        self.pending.push_str(text);
+
+        // 仍可能是完整 prefill 或某个后缀的一部分，继续等待
+        let head = self.pending.trim_start();
+        let waiting = self.prefill.starts_with(head) && head.len() < self.prefill.len()
+            || self
+                .suffixes
+                .iter()
+                .any(|s| s.starts_with(self.pending.as_str()) && self.pending.len() < s.len());
+        if waiting && self.pending.len() <= FILTER_MAX_PENDING_BYTES {
+            return String::new();
+        }
+        self.decide()
+    }
+
+    fn flush(&mut self) -> String {
+        // 尚未收到任何文本时（如响应以 thinking 开头）保持待判定
+        if self.decided || self.pending.is_empty() {
+            return std::mem::take(&mut self.pending);
+        }
+        self.decide()
+    }
+
+    /// 移除开头的回显（如有）并输出暂存文本
+    fn decide(&mut self) -> String {
+        self.decided = true;
+        let pending = std::mem::take(&mut self.pending);
+        let head = pending.trim_start();
+        if let Some(rest) = head.strip_prefix(self.prefill.as_str()) {
+            tracing::debug!("已移除响应开头回显的完整 prefill");
+            return rest.to_string();
+        }
+        match self
+            .suffixes
+            .iter()
+            .find(|s| pending.starts_with(s.as_str()))
+        {
+            Some(suffix) => {
+                tracing::debug!(echo = %suffix, "已移除响应开头回显的 prefill 后缀");
+                pending[suffix.len()..].to_string()
+            }
+            None => pending,
+        }
+    }
+}
+
+/// Markdown 代码块围栏
+const CODE_FENCE: &str = "```";
+
+/// JSON 输出阶段的围栏判定状态
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum FenceState {
+    /// 尚未收到足以判定开头是否为围栏的文本
+    #[default]
+    Undecided,
+    /// 开头的围栏行已移除，结尾的围栏待移除
+    Fenced,
+    /// 开头不是围栏（或围栏已闭合），直接透传
+    Plain,
+}
+
+/// 移除包裹整个响应的 Markdown 代码块围栏（```` ```json ... ``` ````），并累积输出用于 JSON 校验
+///
+/// 开头的围栏行需等到换行才能确定；围栏内的文本保留末尾由空白与反引号组成的部分，
+/// 直到后续出现其他字符（是 JSON 内容）或结束（是结尾围栏）。围栏可以被拆分在任意 chunk 之间。
+#[derive(Debug, Default)]
+struct JsonOutputStage {
+    state: FenceState,
+    /// 尚未输出的文本
+    pending: String,
+    /// 已输出的全部文本（续写时包含 prefill），用于 JSON 校验
+    emitted: String,
+    /// 已输出的文本超过 [`JSON_VALIDATION_MAX_BYTES`]，不再累积与校验
+    emitted_overflow: bool,
+}
+
+impl JsonOutputStage {
+    fn push(&mut self, text: &str) -> String {
+        let output = match self.state {
+            FenceState::Plain => text.to_string(),
+            FenceState::Undecided => {
+                self.pending.push_str(text);
+                self.decide_opening()
+            }
+            FenceState::Fenced => {
+                self.pending.push_str(text);
+                self.release_fenced()
+            }
+        };
+        self.record(&output);
+        output
+    }
+
+    fn flush(&mut self) -> String {
+        let pending = std::mem::take(&mut self.pending);
+        let output = match self.state {
+            FenceState::Fenced => {
+                self.state = FenceState::Plain;
+                let rest = pending.trim_end();
+                rest.strip_suffix(CODE_FENCE)
+                    .unwrap_or(rest)
+                    .trim_end()
+                    .to_string()
+            }
+            // 尚未收到任何文本时（如响应以 thinking 开头）保持待判定
+            FenceState::Undecided if pending.is_empty() => String::new(),
+            FenceState::Undecided | FenceState::Plain => {
+                self.state = FenceState::Plain;
+                pending
+            }
+        };
+        self.record(&output);
+        output
+    }
+
+    /// 累积已输出的文本用于校验，超出上限后放弃
+    fn record(&mut self, output: &str) {
+        if self.emitted_overflow {
+            return;
+        }
+        if self.emitted.len() + output.len() > JSON_VALIDATION_MAX_BYTES {
+            tracing::warn!("JSON 输出超过校验上限，跳过 JSON 校验");
+            self.emitted_overflow = true;
+            self.emitted = String::new();
+            return;
+        }
+        self.emitted.push_str(output);
+    }
+
+    /// 判定开头是否为围栏行，确定前不输出
+    ///
+    /// 暂存文本超过 [`FILTER_MAX_PENDING_BYTES`] 仍无法判定时按普通文本透传
+    fn decide_opening(&mut self) -> String {
+        let overflow = self.pending.len() > FILTER_MAX_PENDING_BYTES;
+        let head = self.pending.trim_start();
+        let partial_fence = CODE_FENCE.starts_with(head) && head.len() < CODE_FENCE.len();
+        if (head.is_empty() || partial_fence) && !overflow {
+            return String::new();
+        }
+        if !head.starts_with(CODE_FENCE) {
+            self.state = FenceState::Plain;
+            return std::mem::take(&mut self.pending);
+        }
+        // 围栏行（含语言标记）需要完整收到
+        let Some(line_end) = head.find('\n') else {
+            if overflow {
+                self.state = FenceState::Plain;
+                return std::mem::take(&mut self.pending);
+            }
+            return String::new();
+        };
+        tracing::debug!("已移除 JSON 输出开头的代码块围栏");
+        self.pending = head[line_end + 1..].to_string();
+        self.state = FenceState::Fenced;
+        self.release_fenced()
+    }
+
+    /// 输出围栏内的文本，保留末尾可能属于结尾围栏的部分（最多 [`FILTER_MAX_PENDING_BYTES`] 字节）
+    fn release_fenced(&mut self) -> String {
+        let keep_from = self
+            .pending
+            .trim_end_matches(|c: char| c.is_whitespace() || c == '`')
+            .len()
+            .max(floor_char_boundary(
+                &self.pending,
+                self.pending.len().saturating_sub(FILTER_MAX_PENDING_BYTES),
+            ));
+        let rest = self.pending.split_off(keep_from);
+        std::mem::replace(&mut self.pending, rest)
+    }
+
+    fn is_valid(&self) -> Option<bool> {
+        if self.emitted_overflow {
+            return None;
+        }
+        let text = self.emitted.trim();
+        if text.is_empty() {
+            return None;
+        }
+        Some(serde_json::from_str::<serde_json::Value>(text).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(pattern: &str, replacement: &str, first_match_only: bool) -> TextFilterConfig {
+        TextFilterConfig::Regex {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            first_match_only,
+        }
+    }
+
+    fn strip(prefixes: &[&str]) -> TextFilterConfig {
+        TextFilterConfig::StripPrefix {
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// 按给定 chunk 切分送入，返回完整输出
+    fn run_chunks(filters: &TextFilters, chunks: &[&str]) -> String {
+        let mut stream = filters.stream();
+        let mut output: String = chunks.iter().map(|c| stream.push(c)).collect();
+        output.push_str(&stream.flush());
+        output
+    }
+
+    #[test]
+    fn test_regex_replacement_across_chunks() {
+        let filters = TextFilters::compile(&[regex("Kiro( AI)?", "Claude", false)]).unwrap();
+        let output = run_chunks(&filters, &["Hello, I am Ki", "ro AI", ", and Kiro again"]);
+        assert_eq!(output, "Hello, I am Claude, and Claude again");
+    }
+
+    #[test]
+    fn test_regex_match_spanning_lookbehind_boundary() {
+        let filters = TextFilters::compile(&[regex("secret-[0-9]+", "[redacted]", false)]).unwrap();
+        let long = "x".repeat(FILTER_LOOKBEHIND_BYTES * 2);
+        let output = run_chunks(&filters, &[&long, "secret-", "12", "34 done"]);
+        assert_eq!(output, format!("{}[redacted] done", long));
+    }
+
+    #[test]
+    fn test_first_match_only() {
+        let filters = TextFilters::compile(&[regex("foo", "bar", true)]).unwrap();
+        let output = run_chunks(&filters, &["fo", "o foo ", "foo"]);
+        assert_eq!(output, "bar foo foo");
+    }
+
+    #[test]
+    fn test_strip_prefix_across_chunks() {
+        let filters = TextFilters::compile(&[strip(&["I am Kiro, an AI assistant."])]).unwrap();
+        let output = run_chunks(
+            &filters,
+            &["\nI am Ki", "ro, an AI assist", "ant. Sure, here"],
+        );
+        assert_eq!(output, "Sure, here");
+    }
+
+    #[test]
+    fn test_strip_prefix_only_at_very_start() {
+        let filters = TextFilters::compile(&[strip(&["I am Kiro."])]).unwrap();
+        let output = run_chunks(&filters, &["Sure. I am Kiro.", " Done"]);
+        assert_eq!(output, "Sure. I am Kiro. Done");
+    }
+
+    #[test]
+    fn test_strip_prefix_first_block_only() {
+        let filters = TextFilters::compile(&[strip(&["I am Kiro."])]).unwrap();
+        let mut stream = filters.stream();
+
+        // 首个文本块：前缀被移除
+        let mut first = stream.push("I am Kiro. Let me check.");
+        first.push_str(&stream.flush());
+        assert_eq!(first, "Let me check.");
+
+        // tool_use 之后的文本块：不再移除
+        let mut second = stream.push("I am Kiro. Done.");
+        second.push_str(&stream.flush());
+        assert_eq!(second, "I am Kiro. Done.");
+    }
+
+    #[test]
+    fn test_first_block_without_prefix_disables_stripping() {
+        let filters = TextFilters::compile(&[strip(&["I am Kiro."])]).unwrap();
+        let mut stream = filters.stream();
+        // 首个文本块只是前缀的一部分即结束（如紧接 tool_use）
+        let mut first = stream.push("I am");
+        first.push_str(&stream.flush());
+        assert_eq!(first, "I am");
+        assert_eq!(stream.push("I am Kiro. x"), "I am Kiro. x");
+    }
+
+    #[test]
+    fn test_flush_before_any_text_keeps_prefix_pending() {
+        let filters = TextFilters::compile(&[strip(&["I am Kiro."])]).unwrap();
+        let mut stream = filters.stream();
+        // 响应以 thinking 开头：切换块时 flush，但首个文本块尚未出现
+        assert_eq!(stream.flush(), "");
+        let mut text = stream.push("I am Kiro. Hi");
+        text.push_str(&stream.flush());
+        assert_eq!(text, "Hi");
+    }
+
+    #[test]
+    fn test_filters_apply_in_order() {
+        let filters =
+            TextFilters::compile(&[strip(&["Kiro here."]), regex("Kiro", "Claude", false)])
+                .unwrap();
+        assert_eq!(filters.apply("Kiro here. Kiro says hi"), "Claude says hi");
+    }
+
+    #[test]
+    fn test_multibyte_text_is_not_split() {
+        let filters = TextFilters::compile(&[regex("你好", "您好", false)]).unwrap();
+        let text = "中".repeat(100) + "你好世界";
+        let chunks: Vec<String> = text.chars().map(|c| c.to_string()).collect();
+        let refs: Vec<&str> = chunks.iter().map(String::as_str).collect();
+        assert_eq!(run_chunks(&filters, &refs), "中".repeat(100) + "您好世界");
+    }
+
+    #[test]
+    fn test_apply_outside_thinking() {
+        let filters = TextFilters::compile(&[regex("Kiro", "Claude", false)]).unwrap();
+        assert_eq!(
+            filters
+                .stream()
+                .apply_outside_thinking("<thinking>Kiro</thinking>\n\nKiro"),
+            "<thinking>Kiro</thinking>\n\nClaude"
+        );
+        assert_eq!(
+            filters.stream().apply_outside_thinking("<thinking>Kiro"),
+            "<thinking>Kiro"
+        );
+    }
+
+    #[test]
+    fn test_apply_in_chunks_matches_apply() {
+        let filters =
+            TextFilters::compile(&[strip(&["Sure!"]), regex("Kiro", "Claude", false)]).unwrap();
+        let text = "Sure! 我是 Kiro，Kiro 很高兴见到你";
+        for chunk_size in [0, 1, 2, 5, 1024] {
+            assert_eq!(
+                filters.apply_in_chunks(text, chunk_size),
+                filters.apply(text),
+                "chunk_size = {}",
+                chunk_size
+            );
+        }
+    }
+
+    /// 按给定 chunk 切分送入 prefill 回显阶段（可叠加配置的过滤器），返回完整输出
+    fn run_prefill(prefill: &str, filters: &[TextFilterConfig], chunks: &[&str]) -> String {
+        let mut stream = TextFilters::compile(filters)
+            .unwrap()
+            .stream()
+            .with_prefill_echo(prefill);
+        let mut output: String = chunks.iter().map(|c| stream.push(c)).collect();
+        output.push_str(&stream.flush());
+        output
+    }
+
+    #[test]
+    fn test_prefill_echo_mid_word() {
+        let prefill = "The capital of France is Par";
+        // 完整回显（含前导空白），跨 chunk
+        assert_eq!(
+            run_prefill(prefill, &[], &["\nThe capital of ", "France is Paris."]),
+            "is."
+        );
+        // 从词边界开始的部分回显
+        assert_eq!(run_prefill(prefill, &[], &["Pa", "ris."]), "is.");
+        // 正常续写原样输出
+        assert_eq!(run_prefill(prefill, &[], &["is", ". It is"]), "is. It is");
+        // 重叠过短时视为正常续写
+        assert_eq!(run_prefill("I said no", &[], &["no"]), "no");
+    }
+
+    #[test]
+    fn test_prefill_echo_mid_json() {
+        let prefill = "{\"name\": \"Al";
+        assert_eq!(
+            run_prefill(prefill, &[], &["{\"na", "me\": \"Alice\"}"]),
+            "ice\"}"
+        );
+        assert_eq!(run_prefill(prefill, &[], &["name\": \"Alice\"}"]), "ice\"}");
+        assert_eq!(
+            run_prefill(prefill, &[], &["ice\", ", "\"age\": 3}"]),
+            "ice\", \"age\": 3}"
+        );
+        // 流在判定前结束时按已收到的内容判定
+        assert_eq!(run_prefill(prefill, &[], &["{\"name"]), "{\"name");
+    }
+
+    #[test]
+    fn test_prefill_echo_runs_before_configured_filters() {
+        assert_eq!(
+            run_prefill(
+                "Sure! Kiro",
+                &[regex("Kiro", "Claude", false)],
+                &["Sure! Kiro is here"]
+            ),
+            " is here"
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex_is_rejected() {
+        assert!(TextFilters::compile(&[regex("(", "", false)]).is_err());
+    }
+
+    /// JSON 模式下按给定 chunk 切分送入，返回完整输出与校验结果
+    fn run_json_chunks(chunks: &[&str]) -> (String, Option<bool>) {
+        let mut stream = TextFilterStream::default().with_json_output();
+        let mut output: String = chunks.iter().map(|c| stream.push(c)).collect();
+        output.push_str(&stream.flush());
+        (output, stream.json_output_valid())
+    }
+
+    #[test]
+    fn test_json_output_strips_fences_split_across_chunks() {
+        let expected = "{\"a\": [1, 2]}";
+        let cases: &[&[&str]] = &[
+            &["```json\n{\"a\": [1, 2]}\n```"],
+            &[
+                "`",
+                "``js",
+                "on",
+                "\n{\"a\": ",
+                "[1, 2]}",
+                "\n`",
+                "``",
+                "\n",
+            ],
+            &["\n  ``", "`\n{\"a\": [1, 2]", "}\n``", "`"],
+            // 未闭合的围栏
+            &["```json\n{\"a\": [1, 2]}"],
+            // 没有围栏时原样透传
+            &["{\"a\": ", "[1, 2]}"],
+        ];
+        for chunks in cases {
+            let (output, valid) = run_json_chunks(chunks);
+            assert_eq!(output.trim(), expected, "{:?}", chunks);
+            assert_eq!(valid, Some(true), "{:?}", chunks);
+        }
+    }
+
+    #[test]
+    fn test_json_output_keeps_backticks_inside_content() {
+        let (output, valid) = run_json_chunks(&["```\n{\"code\": \"`", "``x``", "`\"}\n", "```"]);
+        assert_eq!(output, "{\"code\": \"```x```\"}");
+        assert_eq!(valid, Some(true));
+    }
+
+    #[test]
+    fn test_json_output_outputs_fenced_content_before_end() {
+        let mut stream = TextFilterStream::default().with_json_output();
+        assert_eq!(stream.push("```json\n{\"a\""), "{\"a\"");
+        // 末尾的空白与反引号可能属于结尾围栏，暂不输出
+        assert_eq!(stream.push(": 1}\n`"), ": 1}");
+        assert_eq!(stream.push("``"), "");
+        assert_eq!(stream.flush(), "");
+    }
+
+    #[test]
+    fn test_json_output_validation() {
+        assert_eq!(
+            run_json_chunks(&["Here is the JSON: ", "{\"a\": 1}"]).1,
+            Some(false)
+        );
+        assert_eq!(
+            run_json_chunks(&["```json\n{\"a\": ", "1\n```"]).1,
+            Some(false)
+        );
+        // 没有文本输出（如只有工具调用）时不做校验
+        assert_eq!(run_json_chunks(&[]).1, None);
+        assert_eq!(run_json_chunks(&["  \n"]).1, None);
+        // 未启用 JSON 模式
+        assert_eq!(TextFilterStream::default().json_output_valid(), None);
+    }
+
+    #[test]
+    fn test_json_output_after_prefill_validates_continuation() {
+        let mut stream = TextFilterStream::default()
+            .with_prefill_echo("{\"a\":")
+            .with_json_output_after("{\"a\":");
+        let mut output = stream.push(" 1,");
+        output.push_str(&stream.push(" \"b\": 2}"));
+        output.push_str(&stream.flush());
+        assert_eq!(output, " 1, \"b\": 2}");
+        assert_eq!(stream.json_output_valid(), Some(true));
+    }
+
+    #[test]
+    fn test_json_output_runs_after_configured_filters() {
+        let filters = TextFilters::compile(&[strip(&["Sure!"])]).unwrap();
+        let mut stream = filters.stream().with_json_output();
+        let mut output = stream.push("Sure! ```json\n{\"ok\"");
+        output.push_str(&stream.push(": true}\n```"));
+        output.push_str(&stream.flush());
+        assert_eq!(output, "{\"ok\": true}");
+        assert_eq!(stream.json_output_valid(), Some(true));
+    }
+
+    #[test]
+    fn test_pending_text_is_bounded() {
+        // 跨越截断点的超长匹配不再阻止输出
+        let filters = TextFilters::compile(&[regex("<[^>]*>", "", false)]).unwrap();
+        let mut stream = filters.stream();
+        let chunk = "x".repeat(FILTER_MAX_PENDING_BYTES / 4);
+        let mut output = stream.push("<");
+        for _ in 0..8 {
+            output.push_str(&stream.push(&chunk));
+        }
+        assert!(output.len() >= FILTER_MAX_PENDING_BYTES);
+
+        // 只有空白的开头不会被前缀、prefill 回显与 JSON 阶段无限暂存
+        let blank = " ".repeat(FILTER_MAX_PENDING_BYTES + 1);
+        let mut stream = TextFilters::compile(&[strip(&["Sure!"])])
+            .unwrap()
+            .stream()
+            .with_prefill_echo("Hello")
+            .with_json_output();
+        assert_eq!(stream.push(&blank).len(), blank.len());
+
+        // 围栏行迟迟不换行时按普通文本透传
+        let mut stream = TextFilterStream::default().with_json_output();
+        let fence = format!("```{}", "j".repeat(FILTER_MAX_PENDING_BYTES));
+        assert_eq!(stream.push(&fence), fence);
+    }
+
+    #[test]
+    fn test_json_validation_skipped_beyond_limit() {
+        let mut stream = TextFilterStream::default().with_json_output();
+        let chunk = "1".repeat(1024 * 1024);
+        stream.push("[");
+        for _ in 0..(JSON_VALIDATION_MAX_BYTES / chunk.len() + 1) {
+            stream.push(&chunk);
+        }
+        stream.push("]");
+        stream.flush();
+        assert_eq!(stream.json_output_valid(), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! 上游请求排队
+//!
+//! 突发流量下同时向上游发起过多请求容易触发 429 限流。消息请求在获取凭据、调用上游前
+//! 需先取得并发许可（`maxConcurrentUpstreamRequests`），超过 `queueWaitTimeoutSecs`
+//! 仍未取得许可时返回 503 `overloaded_error`。
+//!
+//! 许可在 [`QueuePermit`] 被 drop 时归还：非流式请求在响应生成后归还，
+//! 流式请求随 SSE 流结束（或客户端断开导致流被丢弃）归还。
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics;
+
+/// 排队超时后建议客户端重试的间隔（秒）
+pub const QUEUE_RETRY_AFTER_SECS: u64 = 5;
+
+/// 上游并发许可（drop 时归还）
+#[derive(Debug)]
+pub struct QueuePermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// 等待并发许可超时
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueTimeout;
+
+/// 上游请求队列
+#[derive(Debug)]
+pub struct RequestQueue {
+    semaphore: Arc<Semaphore>,
+    wait_timeout: Duration,
+    /// 正在等待许可的请求数
+    waiting: AtomicUsize,
+}
+
+impl RequestQueue {
+    pub fn new(max_concurrent: usize, wait_timeout: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            wait_timeout,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// 等待并发许可，超过等待时间返回 [`QueueTimeout`]
+    pub async fn acquire(&self) -> Result<QueuePermit, QueueTimeout> {
+        let started = Instant::now();
+        metrics::set_queue_depth(self.waiting.fetch_add(1, Ordering::SeqCst) + 1);
+        // 请求被取消时同样需要扣减排队数
+        let _waiting = WaitingGuard(&self.waiting);
+
+        let result =
+            tokio::time::timeout(self.wait_timeout, self.semaphore.clone().acquire_owned()).await;
+        let wait_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        match result {
+            Ok(Ok(permit)) => {
+                metrics::observe_queue_wait(wait_ms, "acquired");
+                Ok(QueuePermit { _permit: permit })
+            }
+            // 信号量不会被关闭，按超时处理
+            Ok(Err(_)) | Err(_) => {
+                metrics::observe_queue_wait(wait_ms, "timeout");
+                Err(QueueTimeout)
+            }
+        }
+    }
+
+    /// 当前可用的许可数
+    #[cfg(test)]
+    pub fn available_permits(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+}
+
+/// 排队数计数守卫（drop 时扣减并更新指标）
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        metrics::set_queue_depth(self.0.fetch_sub(1, Ordering::SeqCst) - 1);
+    }
+}
+
+/// 在流结束（或被丢弃）前持有许可
+pub fn hold_permit<S: Stream>(
+    stream: S,
+    permit: Option<QueuePermit>,
+) -> impl Stream<Item = S::Item> {
+    stream.map(move |item| {
+        let _permit = &permit;
+        item
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire_times_out_when_full() {
+        let queue = RequestQueue::new(1, Duration::from_millis(50));
+        let permit = queue.acquire().await.unwrap();
+        assert_eq!(queue.acquire().await.unwrap_err(), QueueTimeout);
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+        assert!(metrics::queue_wait_count("timeout") >= 1);
+
+        drop(permit);
+        assert!(queue.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_permit_held_until_stream_dropped() {
+        let queue = RequestQueue::new(1, Duration::from_millis(50));
+        let permit = queue.acquire().await.unwrap();
+        let mut stream = Box::pin(hold_permit(futures::stream::iter([1, 2]), Some(permit)));
+
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(queue.available_permits(), 0);
+        assert_eq!(stream.next().await, Some(2));
+        assert_eq!(stream.next().await, None);
+        drop(stream);
+        assert_eq!(queue.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_is_not_counted() {
+        let queue = Arc::new(RequestQueue::new(1, Duration::from_secs(30)));
+        let _permit = queue.acquire().await.unwrap();
+
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.acquire().await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 1);
+
+        waiter.abort();
+        let _ = waiter.await;
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,366 @@
+//! 消息请求速率限制
+//!
+//! 配置了 `rateLimitCapacity` 时，所有消息请求在调用上游前共享同一个限流器，
+//! 算法由配置决定：
+//! - 令牌桶（默认）：桶容量为 `rateLimitCapacity`，每秒补充 `rateLimitRefillPerSec` 个令牌，允许短时突发
+//! - 固定窗口（配置了 `rateLimitWindowSecs`）：每个窗口内最多 `rateLimitCapacity` 个请求，
+//!   新窗口开始时计数清零，对应 "每 5 分钟不超过 100 次" 这类严格配额
+//!
+//! 超出限制时返回 429 `rate_limit_error`，`Retry-After` 为可以重试前需要等待的秒数
+//! （固定窗口为当前窗口剩余时间）。
+
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::model::config::Config;
+
+/// 限流算法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitAlgorithm {
+    /// 令牌桶：每秒补充的令牌数
+    TokenBucket { refill_per_sec: f64 },
+    /// 固定窗口：窗口长度
+    FixedWindow { window: Duration },
+}
+
+/// 请求被限流，`retry_after` 后可重试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Retry-After 响应头的秒数（向上取整，至少 1 秒）
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+/// 限流状态
+#[derive(Debug)]
+enum LimiterState {
+    TokenBucket { tokens: f64, last_refill: Instant },
+    FixedWindow { window_start: Instant, count: u32 },
+}
+
+/// 可迁移的限流状态（用于蓝绿部署时导出/导入运行时状态）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "algorithm",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum RateLimiterState {
+    /// 令牌桶：导出时刻桶内剩余的令牌数
+    TokenBucket { tokens: f64 },
+    /// 固定窗口：当前窗口剩余毫秒数与已放行的请求数
+    FixedWindow {
+        window_remaining_ms: u64,
+        count: u32,
+    },
+}
+
+/// 请求速率限制器
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: u32,
+    algorithm: RateLimitAlgorithm,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, algorithm: RateLimitAlgorithm, now: Instant) -> Self {
+        let state = match algorithm {
+            RateLimitAlgorithm::TokenBucket { .. } => LimiterState::TokenBucket {
+                tokens: f64::from(capacity),
+                last_refill: now,
+            },
+            RateLimitAlgorithm::FixedWindow { .. } => LimiterState::FixedWindow {
+                window_start: now,
+                count: 0,
+            },
+        };
+        Self {
+            capacity,
+            algorithm,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// 按配置创建限流器（未配置 rateLimitCapacity 或为 0 时不限流）
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let capacity = config.rate_limit_capacity.filter(|&c| c > 0)?;
+        let algorithm = match config.rate_limit_window_secs.filter(|&w| w > 0) {
+            Some(window_secs) => RateLimitAlgorithm::FixedWindow {
+                window: Duration::from_secs(window_secs),
+            },
+            None => RateLimitAlgorithm::TokenBucket {
+                refill_per_sec: config.rate_limit_refill_per_sec,
+            },
+        };
+        Some(Self::new(capacity, algorithm, Instant::now()))
+    }
+
+    /// 尝试放行一个请求
+    pub fn check(&self, now: Instant) -> Result<(), RateLimited> {
+        let mut state = self.state.lock();
+        match (&mut *state, self.algorithm) {
+            (
+                LimiterState::TokenBucket {
+                    tokens,
+                    last_refill,
+                },
+                RateLimitAlgorithm::TokenBucket { refill_per_sec },
+            ) => {
+                let elapsed = now.saturating_duration_since(*last_refill).as_secs_f64();
+                *tokens = (*tokens + elapsed * refill_per_sec).min(f64::from(self.capacity));
+                *last_refill = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return Ok(());
+                }
+                // 补充速率极小时等待时间可能超出 Duration 的表示范围
+                let retry_after = Duration::try_from_secs_f64((1.0 - *tokens) / refill_per_sec)
+                    .unwrap_or(Duration::MAX);
+                Err(RateLimited { retry_after })
+            }
+            (
+                LimiterState::FixedWindow {
+                    window_start,
+                    count,
+                },
+                RateLimitAlgorithm::FixedWindow { window },
+            ) => {
+                // 跳过已经结束的窗口（窗口边界保持对齐）
+                let elapsed = now.saturating_duration_since(*window_start);
+                if elapsed >= window {
+                    let passed = (elapsed.as_nanos() / window.as_nanos()) as u32;
+                    *window_start += window * passed;
+                    *count = 0;
+                }
+                if *count < self.capacity {
+                    *count += 1;
+                    return Ok(());
+                }
+                Err(RateLimited {
+                    retry_after: (*window_start + window).saturating_duration_since(now),
+                })
+            }
+            _ => unreachable!("限流状态与算法在创建时一一对应"),
+        }
+    }
+
+    /// 导出当前限流状态（按 `now` 结算令牌补充与窗口切换，不消耗配额）
+    pub fn export_state(&self, now: Instant) -> RateLimiterState {
+        let state = self.state.lock();
+        match (&*state, self.algorithm) {
+            (
+                LimiterState::TokenBucket {
+                    tokens,
+                    last_refill,
+                },
+                RateLimitAlgorithm::TokenBucket { refill_per_sec },
+            ) => {
+                let elapsed = now.saturating_duration_since(*last_refill).as_secs_f64();
+                RateLimiterState::TokenBucket {
+                    tokens: (tokens + elapsed * refill_per_sec).min(f64::from(self.capacity)),
+                }
+            }
+            (
+                LimiterState::FixedWindow {
+                    window_start,
+                    count,
+                },
+                RateLimitAlgorithm::FixedWindow { window },
+            ) => {
+                let elapsed = now.saturating_duration_since(*window_start);
+                let (remaining, count) = if elapsed >= window {
+                    let into_window = elapsed.as_nanos() % window.as_nanos();
+                    (window - Duration::from_nanos(into_window as u64), 0)
+                } else {
+                    (window - elapsed, *count)
+                };
+                RateLimiterState::FixedWindow {
+                    window_remaining_ms: remaining.as_millis() as u64,
+                    count,
+                }
+            }
+            _ => unreachable!("限流状态与算法在创建时一一对应"),
+        }
+    }
+
+    /// 导入限流状态，返回是否已应用
+    ///
+    /// 导出方使用的限流算法与当前配置不同时忽略；令牌数与计数不超过当前容量
+    pub fn import_state(&self, imported: &RateLimiterState, now: Instant) -> bool {
+        let mut state = self.state.lock();
+        match (imported, self.algorithm) {
+            (RateLimiterState::TokenBucket { tokens }, RateLimitAlgorithm::TokenBucket { .. }) => {
+                *state = LimiterState::TokenBucket {
+                    tokens: tokens.clamp(0.0, f64::from(self.capacity)),
+                    last_refill: now,
+                };
+                true
+            }
+            (
+                RateLimiterState::FixedWindow {
+                    window_remaining_ms,
+                    count,
+                },
+                RateLimitAlgorithm::FixedWindow { window },
+            ) => {
+                let remaining = Duration::from_millis(*window_remaining_ms).min(window);
+                *state = LimiterState::FixedWindow {
+                    window_start: (now + remaining).checked_sub(window).unwrap_or(now),
+                    count: (*count).min(self.capacity),
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_window_allows_capacity_per_window() {
+        let start = Instant::now();
+        let window = Duration::from_secs(10);
+        let limiter = RateLimiter::new(5, RateLimitAlgorithm::FixedWindow { window }, start);
+
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let results: Vec<bool> = (0..7)
+            .map(|i| limiter.check(at(i as f64)).is_ok())
+            .collect();
+        assert_eq!(results, vec![true, true, true, true, true, false, false]);
+
+        // Retry-After 为当前窗口剩余时间
+        let limited = limiter.check(at(7.5)).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_millis(2500));
+        assert_eq!(limited.retry_after_secs(), 3);
+
+        // 新窗口开始时计数清零
+        assert!(limiter.check(at(10.0)).is_ok());
+        for _ in 0..4 {
+            assert!(limiter.check(at(11.0)).is_ok());
+        }
+        assert_eq!(
+            limiter.check(at(19.0)).unwrap_err().retry_after,
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_refills_continuously() {
+        let start = Instant::now();
+        let algorithm = RateLimitAlgorithm::TokenBucket {
+            refill_per_sec: 2.0,
+        };
+        let limiter = RateLimiter::new(3, algorithm, start);
+
+        for _ in 0..3 {
+            assert!(limiter.check(start).is_ok());
+        }
+        let limited = limiter.check(start).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+        assert_eq!(limited.retry_after_secs(), 1);
+
+        // 0.5 秒补充 1 个令牌，且不超过桶容量
+        assert!(limiter.check(start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check(start + Duration::from_millis(500)).is_err());
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check(later).is_ok());
+        }
+        assert!(limiter.check(later).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_tiny_refill_does_not_panic() {
+        let start = Instant::now();
+        let algorithm = RateLimitAlgorithm::TokenBucket {
+            refill_per_sec: f64::MIN_POSITIVE,
+        };
+        let limiter = RateLimiter::new(1, algorithm, start);
+
+        assert!(limiter.check(start).is_ok());
+        let limited = limiter.check(start).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::MAX);
+    }
+
+    #[test]
+    fn test_export_import_state_round_trip() {
+        let start = Instant::now();
+        let window = Duration::from_secs(10);
+        let source = RateLimiter::new(5, RateLimitAlgorithm::FixedWindow { window }, start);
+        for _ in 0..4 {
+            assert!(source.check(start + Duration::from_secs(12)).is_ok());
+        }
+        let exported = source.export_state(start + Duration::from_secs(13));
+        assert_eq!(
+            exported,
+            RateLimiterState::FixedWindow {
+                window_remaining_ms: 7000,
+                count: 4
+            }
+        );
+
+        // 新实例继承窗口剩余时间与计数
+        let later = start + Duration::from_secs(100);
+        let target = RateLimiter::new(5, RateLimitAlgorithm::FixedWindow { window }, later);
+        assert!(target.import_state(&exported, later));
+        assert_eq!(target.export_state(later), exported);
+        assert!(target.check(later).is_ok());
+        assert_eq!(
+            target.check(later).unwrap_err().retry_after,
+            Duration::from_secs(7)
+        );
+        assert!(target.check(later + Duration::from_secs(7)).is_ok());
+
+        // 令牌桶
+        let algorithm = RateLimitAlgorithm::TokenBucket {
+            refill_per_sec: 1.0,
+        };
+        let source = RateLimiter::new(3, algorithm, start);
+        for _ in 0..3 {
+            assert!(source.check(start).is_ok());
+        }
+        let exported = source.export_state(start + Duration::from_millis(500));
+        assert_eq!(exported, RateLimiterState::TokenBucket { tokens: 0.5 });
+        let target = RateLimiter::new(3, algorithm, later);
+        assert!(target.import_state(&exported, later));
+        assert!(target.check(later).is_err());
+
+        // 算法不同时忽略
+        let fixed = RateLimiter::new(5, RateLimitAlgorithm::FixedWindow { window }, later);
+        assert!(!fixed.import_state(&exported, later));
+        assert!(fixed.check(later).is_ok());
+    }
+
+    #[test]
+    fn test_from_config_selects_algorithm() {
+        let mut config = Config::default();
+        assert!(RateLimiter::from_config(&config).is_none());
+
+        config.rate_limit_capacity = Some(100);
+        let limiter = RateLimiter::from_config(&config).unwrap();
+        assert!(matches!(
+            limiter.algorithm,
+            RateLimitAlgorithm::TokenBucket { .. }
+        ));
+
+        config.rate_limit_window_secs = Some(300);
+        let limiter = RateLimiter::from_config(&config).unwrap();
+        assert_eq!(
+            limiter.algorithm,
+            RateLimitAlgorithm::FixedWindow {
+                window: Duration::from_secs(300)
+            }
+        );
+    }
+}
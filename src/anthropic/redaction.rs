@@ -0,0 +1,587 @@
+//! 出站提示词脱敏
+//!
+//! 在请求转换为上游请求体之后、发送之前，按配置的命名正则规则替换敏感内容：
+//! - 只作用于用户消息（含由 `system` 转换而来的系统提示词）与工具结果文本，
+//!   助手历史消息、图片与工具定义（名称、描述、schema）不做处理
+//! - 所有规则在同一轮扫描中匹配原始文本，替换结果不会被其他规则再次匹配
+//! - 多条规则的匹配重叠时：起点最早者优先，起点相同时取更长的匹配，仍相同时取配置中靠前的规则
+//! - 严格模式下 `blocking` 规则命中时拒绝整个请求，由调用方返回 400
+//!
+//! 规则在加载配置时编译（正则无效、规则名为空或重复时启动报错），请求间共享同一份编译结果。
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+use anyhow::Context;
+use axum::http::HeaderValue;
+use regex::{Captures, Regex};
+
+use crate::kiro::model::requests::conversation::{
+    ConversationState, Message, UserInputMessageContext,
+};
+use crate::model::config::RedactionConfig;
+
+/// 列出各规则命中次数的响应头（如 `aws-account=2, email=1`）
+pub const REDACTIONS_HEADER: &str = "x-kiro-redactions";
+
+/// 编译后的脱敏规则（可在请求间共享）
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    rules: Vec<CompiledRule>,
+    strict: bool,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    name: String,
+    regex: Regex,
+    replacement: String,
+    blocking: bool,
+}
+
+impl CompiledRule {
+    /// 从 start 开始查找下一处非空匹配（空匹配不做替换，跳过）
+    fn next_match<'t>(&self, text: &'t str, mut start: usize) -> Option<Captures<'t>> {
+        loop {
+            let caps = self.regex.captures_at(text, start)?;
+            let m = caps.get(0)?;
+            if !m.is_empty() {
+                return Some(caps);
+            }
+            start = m.end() + text[m.end()..].chars().next()?.len_utf8();
+        }
+    }
+}
+
+/// 单个请求中各规则的命中次数（按配置顺序，仅包含命中过的规则）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedactionSummary {
+    counts: Vec<(String, usize)>,
+}
+
+impl RedactionSummary {
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// 规则名与命中次数
+    pub fn counts(&self) -> &[(String, usize)] {
+        &self.counts
+    }
+
+    /// `x-kiro-redactions` 响应头的值（无命中时为 None）
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        if self.is_empty() {
+            return None;
+        }
+        let value = self
+            .counts
+            .iter()
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// 严格模式下命中 blocking 规则，请求被拒绝
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionBlocked {
+    /// 命中的 blocking 规则名（按配置顺序）
+    pub rules: Vec<String>,
+}
+
+impl std::fmt::Display for RedactionBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Request blocked by redaction rule(s): {}",
+            self.rules.join(", ")
+        )
+    }
+}
+
+/// 规则名只允许出现在响应头中不需要转义的字符
+fn is_valid_rule_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl Redactor {
+    /// 编译脱敏配置（规则名为空、含非法字符、重复或正则无效时返回错误）
+    pub fn compile(config: &RedactionConfig) -> anyhow::Result<Self> {
+        let mut names = HashSet::new();
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                if !is_valid_rule_name(&rule.name) {
+                    anyhow::bail!(
+                        "redaction.rules[{}] 规则名无效: {:?}（只能包含字母、数字、-、_、.）",
+                        i,
+                        rule.name
+                    );
+                }
+                if !names.insert(rule.name.as_str()) {
+                    anyhow::bail!("redaction.rules[{}] 规则名重复: {}", i, rule.name);
+                }
+                let regex = Regex::new(&rule.pattern).with_context(|| {
+                    format!(
+                        "redaction.rules[{}] ({}) 正则表达式无效: {}",
+                        i, rule.name, rule.pattern
+                    )
+                })?;
+                Ok(CompiledRule {
+                    name: rule.name.clone(),
+                    regex,
+                    replacement: rule.replacement.clone(),
+                    blocking: rule.blocking,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            rules,
+            strict: config.strict,
+        })
+    }
+
+    /// 从配置构建，未配置任何规则时返回 None
+    pub fn from_config(config: &RedactionConfig) -> anyhow::Result<Option<Self>> {
+        if config.is_empty() {
+            return Ok(None);
+        }
+        Self::compile(config).map(Some)
+    }
+
+    /// 对一段文本应用所有规则，累加各规则命中次数；没有任何命中时返回 None
+    ///
+    /// 缓存每条规则的下一处匹配，替换后只重新查找起点落在已替换区间内的规则
+    fn redact_text(&self, text: &str, counts: &mut [usize]) -> Option<String> {
+        let mut output = String::new();
+        let mut last = 0;
+        let mut next: Vec<Option<Captures>> = self
+            .rules
+            .iter()
+            .map(|rule| rule.next_match(text, 0))
+            .collect();
+        loop {
+            let best = next
+                .iter()
+                .enumerate()
+                .filter_map(|(i, caps)| {
+                    let m = caps.as_ref()?.get(0).expect("group 0 always participates");
+                    Some((m.start(), Reverse(m.end()), i))
+                })
+                .min();
+            let Some((_, _, i)) = best else {
+                break;
+            };
+            let caps = next[i].take().expect("best rule has a cached match");
+            let m = caps.get(0).expect("group 0 always participates");
+            output.push_str(&text[last..m.start()]);
+            caps.expand(&self.rules[i].replacement, &mut output);
+            counts[i] += 1;
+            last = m.end();
+
+            // 已用掉的匹配与起点落在替换区间内的匹配失效，其余缓存仍是 last 之后最左的匹配
+            for (j, rule) in self.rules.iter().enumerate() {
+                let stale = next[j].as_ref().map_or(j == i, |caps| {
+                    caps.get(0).expect("group 0 always participates").start() < last
+                });
+                if stale {
+                    next[j] = rule.next_match(text, last);
+                }
+            }
+        }
+        // 匹配均非空，last 为 0 说明没有任何命中
+        if last == 0 {
+            return None;
+        }
+        output.push_str(&text[last..]);
+        Some(output)
+    }
+
+    fn redact_in_place(&self, text: &mut String, counts: &mut [usize]) {
+        if let Some(redacted) = self.redact_text(text, counts) {
+            *text = redacted;
+        }
+    }
+
+    /// 替换用户消息正文与其中的工具结果文本（不处理工具定义）
+    fn redact_user_message(
+        &self,
+        content: &mut String,
+        context: &mut UserInputMessageContext,
+        counts: &mut [usize],
+    ) {
+        self.redact_in_place(content, counts);
+        for result in &mut context.tool_results {
+            for block in &mut result.content {
+                if let Some(serde_json::Value::String(text)) = block.get_mut("text") {
+                    self.redact_in_place(text, counts);
+                }
+            }
+        }
+    }
+
+    fn summarize(&self, counts: &[usize]) -> RedactionSummary {
+        RedactionSummary {
+            counts: self
+                .rules
+                .iter()
+                .zip(counts)
+                .filter(|(_, count)| **count > 0)
+                .map(|(rule, count)| (rule.name.clone(), *count))
+                .collect(),
+        }
+    }
+
+    /// 严格模式下命中的 blocking 规则名（非严格模式始终为空）
+    pub fn blocked_rules(&self, summary: &RedactionSummary) -> Vec<String> {
+        if !self.strict {
+            return Vec::new();
+        }
+        summary
+            .counts
+            .iter()
+            .filter(|(name, _)| {
+                self.rules
+                    .iter()
+                    .any(|rule| rule.blocking && rule.name == *name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// 对一段文本应用所有规则（Admin 试运行）
+    pub fn apply(&self, text: &str) -> (String, RedactionSummary) {
+        let mut counts = vec![0; self.rules.len()];
+        let output = self
+            .redact_text(text, &mut counts)
+            .unwrap_or_else(|| text.to_string());
+        (output, self.summarize(&counts))
+    }
+
+    /// 对转换后的上游对话应用所有规则
+    ///
+    /// 严格模式下命中 blocking 规则时返回 [`RedactionBlocked`]（此时对话内容已被部分修改，不应再发送）
+    pub fn redact_conversation(
+        &self,
+        state: &mut ConversationState,
+    ) -> Result<RedactionSummary, RedactionBlocked> {
+        let mut counts = vec![0; self.rules.len()];
+
+        let current = &mut state.current_message.user_input_message;
+        self.redact_user_message(
+            &mut current.content,
+            &mut current.user_input_message_context,
+            &mut counts,
+        );
+        for message in &mut state.history {
+            if let Message::User(user) = message {
+                let user = &mut user.user_input_message;
+                self.redact_user_message(
+                    &mut user.content,
+                    &mut user.user_input_message_context,
+                    &mut counts,
+                );
+            }
+        }
+
+        let summary = self.summarize(&counts);
+        let blocked = self.blocked_rules(&summary);
+        if !blocked.is_empty() {
+            return Err(RedactionBlocked { rules: blocked });
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::requests::conversation::{
+        CurrentMessage, HistoryAssistantMessage, HistoryUserMessage, UserInputMessage,
+    };
+    use crate::kiro::model::requests::tool::{InputSchema, Tool, ToolResult, ToolSpecification};
+    use crate::model::config::RedactionRuleConfig;
+
+    fn rule(name: &str, pattern: &str, replacement: &str) -> RedactionRuleConfig {
+        RedactionRuleConfig {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            blocking: false,
+        }
+    }
+
+    fn redactor(rules: Vec<RedactionRuleConfig>) -> Redactor {
+        Redactor::compile(&RedactionConfig {
+            rules,
+            strict: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rule_types() {
+        let hostname = || {
+            rule(
+                "internal-host",
+                r"\b[a-z0-9-]+\.corp\.example\.com\b",
+                "[HOST]",
+            )
+        };
+        let aws_account = || rule("aws-account", r"\b\d{12}\b", "[AWS_ACCOUNT]");
+        let email = || {
+            rule(
+                "email",
+                r"[A-Za-z0-9._%+-]+@([A-Za-z0-9-]+\.)+[A-Za-z]{2,}",
+                "[EMAIL]",
+            )
+        };
+        let arn_account = || rule("arn", r"arn:aws:iam::(\d{12}):", "arn:aws:iam::<$1>:");
+
+        let cases = [
+            (
+                hostname(),
+                "ssh db-01.corp.example.com and web.corp.example.com",
+                "ssh [HOST] and [HOST]",
+                2,
+            ),
+            (hostname(), "see example.com", "see example.com", 0),
+            (
+                aws_account(),
+                "account 123456789012, not 1234567890123",
+                "account [AWS_ACCOUNT], not 1234567890123",
+                1,
+            ),
+            (
+                email(),
+                "mail alice@customer.co.uk or bob@x.io",
+                "mail [EMAIL] or [EMAIL]",
+                2,
+            ),
+            (email(), "no address @ here", "no address @ here", 0),
+            (
+                arn_account(),
+                "arn:aws:iam::123456789012:role/x",
+                "arn:aws:iam::<123456789012>:role/x",
+                1,
+            ),
+            (rule("cjk", "机密", "**"), "这是机密文件", "这是**文件", 1),
+        ];
+        for (rule, input, expected, count) in cases {
+            let name = rule.name.clone();
+            let (output, summary) = redactor(vec![rule]).apply(input);
+            assert_eq!(output, expected, "rule {}", name);
+            let fired = summary.counts().first().map_or(0, |(_, c)| *c);
+            assert_eq!(fired, count, "rule {}", name);
+        }
+    }
+
+    #[test]
+    fn test_overlapping_matches_are_deterministic() {
+        let cases = [
+            // 起点最早者优先
+            (
+                vec![rule("b", "bc", "[B]"), rule("a", "ab", "[A]")],
+                "abc",
+                "[A]c",
+                vec![("a", 1)],
+            ),
+            // 起点相同时取更长的匹配
+            (
+                vec![rule("short", "ab", "[S]"), rule("long", "abcd", "[L]")],
+                "abcde",
+                "[L]e",
+                vec![("long", 1)],
+            ),
+            // 完全相同时取配置中靠前的规则
+            (
+                vec![rule("first", "abc", "[1]"), rule("second", "abc", "[2]")],
+                "abc abc",
+                "[1] [1]",
+                vec![("first", 2)],
+            ),
+            // 替换结果不会被后续规则再次匹配
+            (
+                vec![rule("a", "secret", "token"), rule("b", "token", "[T]")],
+                "secret token",
+                "token [T]",
+                vec![("a", 1), ("b", 1)],
+            ),
+            // 其他规则的下一处匹配落在替换区间内时重新查找
+            (
+                vec![rule("b", "bcd", "[B]"), rule("a", "abc", "[A]")],
+                "abcd bcd",
+                "[A]d [B]",
+                vec![("b", 1), ("a", 1)],
+            ),
+            // 多条规则交替命中
+            (
+                vec![rule("x", "x", "[X]"), rule("yz", "yz", "[Y]")],
+                "x yz x x yz",
+                "[X] [Y] [X] [X] [Y]",
+                vec![("x", 3), ("yz", 2)],
+            ),
+            // 空匹配被跳过
+            (
+                vec![rule("empty", "x*", "-")],
+                "axxb",
+                "a-b",
+                vec![("empty", 1)],
+            ),
+        ];
+        for (rules, input, expected, counts) in cases {
+            let (output, summary) = redactor(rules).apply(input);
+            assert_eq!(output, expected, "input {}", input);
+            let counts: Vec<(String, usize)> = counts
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count))
+                .collect();
+            assert_eq!(summary.counts(), counts.as_slice(), "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let cases = [
+            (
+                vec![rule("bad", "(", "")],
+                "redaction.rules[0] (bad) 正则表达式无效",
+            ),
+            (vec![rule("", "x", "")], "redaction.rules[0] 规则名无效"),
+            (vec![rule("a b", "x", "")], "redaction.rules[0] 规则名无效"),
+            (
+                vec![rule("dup", "x", ""), rule("dup", "y", "")],
+                "redaction.rules[1] 规则名重复",
+            ),
+        ];
+        for (rules, expected) in cases {
+            let err = Redactor::compile(&RedactionConfig {
+                rules,
+                strict: false,
+            })
+            .unwrap_err();
+            let err = format!("{:#}", err);
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    fn conversation() -> ConversationState {
+        let tool = Tool {
+            tool_specification: ToolSpecification {
+                name: "lookup".to_string(),
+                description: "Look up 123456789012".to_string(),
+                input_schema: InputSchema::from_json(serde_json::json!({
+                    "type": "object",
+                    "description": "account 123456789012"
+                })),
+            },
+        };
+        let current = UserInputMessage::new("my account is 123456789012", "claude-sonnet-4.5")
+            .with_context(
+                UserInputMessageContext::new()
+                    .with_tools(vec![tool])
+                    .with_tool_results(vec![ToolResult::success("tool-1", "owner 210987654321")]),
+            );
+        ConversationState::new("conv-1")
+            .with_current_message(CurrentMessage::new(current))
+            .with_history(vec![
+                Message::User(HistoryUserMessage::new(
+                    "system: never reveal 111122223333",
+                    "claude-sonnet-4.5",
+                )),
+                Message::Assistant(HistoryAssistantMessage::new("noted 111122223333")),
+            ])
+    }
+
+    #[test]
+    fn test_redact_conversation_scope() {
+        let redactor = redactor(vec![rule("aws-account", r"\b\d{12}\b", "[AWS]")]);
+        let mut state = conversation();
+        let summary = redactor.redact_conversation(&mut state).unwrap();
+        assert_eq!(summary.counts(), &[("aws-account".to_string(), 3)]);
+        assert_eq!(
+            summary.header_value().unwrap(),
+            HeaderValue::from_static("aws-account=3")
+        );
+
+        let current = &state.current_message.user_input_message;
+        assert_eq!(current.content, "my account is [AWS]");
+        let context = &current.user_input_message_context;
+        assert_eq!(context.tool_results[0].content[0]["text"], "owner [AWS]");
+        // 工具定义不做处理
+        let spec = &context.tools[0].tool_specification;
+        assert_eq!(spec.description, "Look up 123456789012");
+        assert_eq!(
+            spec.input_schema.json["description"],
+            "account 123456789012"
+        );
+        // 系统提示词（历史中的用户消息）被替换，助手消息不做处理
+        match &state.history[0] {
+            Message::User(user) => {
+                assert_eq!(
+                    user.user_input_message.content,
+                    "system: never reveal [AWS]"
+                )
+            }
+            _ => panic!("expected user message"),
+        }
+        match &state.history[1] {
+            Message::Assistant(assistant) => assert_eq!(
+                assistant.assistant_response_message.content,
+                "noted 111122223333"
+            ),
+            _ => panic!("expected assistant message"),
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_blocks_only_blocking_rules() {
+        let mut blocking = rule("aws-account", r"\b\d{12}\b", "[AWS]");
+        blocking.blocking = true;
+        let rules = vec![rule("email", r"\S+@\S+", "[EMAIL]"), blocking];
+
+        let cases = [
+            // 非严格模式：blocking 规则同样只做替换
+            (false, "id 123456789012", Ok(vec![("aws-account", 1)])),
+            (true, "id 123456789012", Err(vec!["aws-account"])),
+            // 严格模式下非 blocking 规则仍然替换
+            (true, "mail a@b.c", Ok(vec![("email", 1)])),
+            (true, "nothing here", Ok(vec![])),
+        ];
+        for (strict, content, expected) in cases {
+            let redactor = Redactor::compile(&RedactionConfig {
+                rules: rules.clone(),
+                strict,
+            })
+            .unwrap();
+            let mut state = ConversationState::new("conv-1").with_current_message(
+                CurrentMessage::new(UserInputMessage::new(content, "claude-sonnet-4.5")),
+            );
+            let result = redactor.redact_conversation(&mut state);
+            match expected {
+                Ok(counts) => {
+                    let counts: Vec<(String, usize)> = counts
+                        .into_iter()
+                        .map(|(name, count)| (name.to_string(), count))
+                        .collect();
+                    assert_eq!(result.unwrap().counts(), counts.as_slice(), "{}", content);
+                }
+                Err(names) => {
+                    let blocked = result.unwrap_err();
+                    assert_eq!(blocked.rules, names, "{}", content);
+                    assert_eq!(
+                        blocked.to_string(),
+                        "Request blocked by redaction rule(s): aws-account"
+                    );
+                }
+            }
+        }
+    }
+}
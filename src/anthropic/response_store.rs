@@ -0,0 +1,229 @@
+//! 非流式响应暂存
+//!
+//! 开启 `enableResponseRetrieval` 后，成功的非流式消息响应按其 `id` 暂存在内存中，
+//! 客户端可在 `responseRetentionSecs` 内通过 `GET /v1/messages/{id}` 重新获取（轮询模式）。
+//! 过期条目在读取时视为不存在，并由后台任务定期清理。
+//!
+//! 暂存区有条数与总字节上限（`responseStoreMaxEntries` / `responseStoreMaxBytes`），
+//! 超出时淘汰最早暂存的响应。每条响应记录创建它的 API Key 的哈希，只有同一 Key 才能取回。
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::kiro::token_manager::sha256_hex;
+
+/// 暂存的响应
+struct StoredResponse {
+    stored_at: Instant,
+    /// 创建该响应的 API Key 的 SHA-256
+    owner: String,
+    /// 序列化后的字节数（计入总字节上限）
+    size: usize,
+    body: Value,
+}
+
+/// 暂存区内容（`order` 按暂存先后记录 ID，用于淘汰最早的响应）
+#[derive(Default)]
+struct Entries {
+    by_id: HashMap<String, StoredResponse>,
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, message_id: &str) -> Option<StoredResponse> {
+        let stored = self.by_id.remove(message_id)?;
+        self.order.retain(|id| id != message_id);
+        self.bytes -= stored.size;
+        Some(stored)
+    }
+
+    fn pop_oldest(&mut self) -> Option<StoredResponse> {
+        let message_id = self.order.pop_front()?;
+        let stored = self.by_id.remove(&message_id)?;
+        self.bytes -= stored.size;
+        Some(stored)
+    }
+}
+
+/// 非流式响应暂存区
+pub struct ResponseStore {
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl ResponseStore {
+    pub fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            ttl,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    /// 暂存 `api_key` 创建的响应（同一 ID 覆盖旧值），超出上限时淘汰最早暂存的响应
+    ///
+    /// 单个响应超过总字节上限时不暂存，返回 false
+    pub fn insert(&self, api_key: &str, message_id: impl Into<String>, body: Value) -> bool {
+        let message_id = message_id.into();
+        let size = body.to_string().len();
+        if size > self.max_bytes {
+            tracing::debug!(
+                "响应 {} 大小 {} 字节超过暂存上限 {} 字节，不暂存",
+                message_id,
+                size,
+                self.max_bytes
+            );
+            return false;
+        }
+
+        let mut entries = self.entries.lock();
+        entries.remove(&message_id);
+        while entries.by_id.len() >= self.max_entries || entries.bytes + size > self.max_bytes {
+            if entries.pop_oldest().is_none() {
+                break;
+            }
+        }
+        entries.bytes += size;
+        entries.order.push_back(message_id.clone());
+        entries.by_id.insert(
+            message_id,
+            StoredResponse {
+                stored_at: Instant::now(),
+                owner: sha256_hex(api_key),
+                size,
+                body,
+            },
+        );
+        true
+    }
+
+    /// 获取 `api_key` 创建的未过期响应（其他 Key 创建的响应视为不存在）
+    pub fn get(&self, api_key: &str, message_id: &str) -> Option<Value> {
+        let owner = sha256_hex(api_key);
+        self.entries
+            .lock()
+            .by_id
+            .get(message_id)
+            .filter(|stored| stored.owner == owner && stored.stored_at.elapsed() < self.ttl)
+            .map(|stored| stored.body.clone())
+    }
+
+    /// 清理过期条目，返回清理数量
+    pub fn evict_expired(&self) -> usize {
+        let mut entries = self.entries.lock();
+        let expired: Vec<String> = entries
+            .by_id
+            .iter()
+            .filter(|(_, stored)| stored.stored_at.elapsed() >= self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for message_id in &expired {
+            entries.remove(message_id);
+        }
+        expired.len()
+    }
+
+    /// 启动后台清理任务（每个 TTL 周期清理一次，暂存区被释放后退出）
+    pub fn start_eviction(self: &Arc<Self>) -> JoinHandle<()> {
+        let store = Arc::downgrade(self);
+        let period = self.ttl;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // 首次 tick 立即完成，此时没有可清理的条目
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                let evicted = store.evict_expired();
+                if evicted > 0 {
+                    tracing::debug!("已清理 {} 条过期的暂存响应", evicted);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_get_returns_stored_response_until_expired() {
+        let store = ResponseStore::new(Duration::from_millis(50), 10, 1024);
+        assert!(store.insert("key", "msg_1", json!({"id": "msg_1"})));
+        assert_eq!(store.get("key", "msg_1"), Some(json!({"id": "msg_1"})));
+        assert_eq!(store.get("key", "msg_2"), None);
+        // 其他 API Key 无法取回
+        assert_eq!(store.get("other-key", "msg_1"), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(store.get("key", "msg_1"), None);
+        assert_eq!(store.evict_expired(), 1);
+        assert_eq!(store.evict_expired(), 0);
+        assert_eq!(store.entries.lock().bytes, 0);
+    }
+
+    #[test]
+    fn test_insert_evicts_oldest_when_over_limits() {
+        let body = |n: usize| json!({ "text": "x".repeat(n) });
+        let size = |n: usize| body(n).to_string().len();
+
+        // 条数上限
+        let store = ResponseStore::new(Duration::from_secs(60), 2, 1024);
+        store.insert("key", "msg_1", body(1));
+        store.insert("key", "msg_2", body(1));
+        // 覆盖同一 ID 不淘汰其他响应
+        store.insert("key", "msg_2", body(2));
+        assert!(store.get("key", "msg_1").is_some());
+        store.insert("key", "msg_3", body(1));
+        assert_eq!(store.get("key", "msg_1"), None);
+        assert_eq!(store.get("key", "msg_2"), Some(body(2)));
+        assert!(store.get("key", "msg_3").is_some());
+
+        // 字节上限
+        let store = ResponseStore::new(Duration::from_secs(60), 10, size(40) * 2);
+        store.insert("key", "msg_1", body(40));
+        store.insert("key", "msg_2", body(40));
+        store.insert("key", "msg_3", body(10));
+        assert_eq!(store.get("key", "msg_1"), None);
+        assert!(store.get("key", "msg_2").is_some());
+        assert!(store.get("key", "msg_3").is_some());
+        assert_eq!(store.entries.lock().bytes, size(40) + size(10));
+
+        // 超过总字节上限的单个响应不暂存
+        assert!(!store.insert("key", "msg_4", body(200)));
+        assert_eq!(store.get("key", "msg_4"), None);
+        assert!(store.get("key", "msg_2").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_eviction_task_removes_expired_entries() {
+        let store = Arc::new(ResponseStore::new(Duration::from_millis(20), 10, 1024));
+        store.insert("key", "msg_1", json!({}));
+        let task = store.start_eviction();
+
+        tokio::time::sleep(Duration::from_millis(70)).await;
+        assert!(store.entries.lock().by_id.is_empty());
+
+        // 暂存区释放后任务自行退出
+        drop(store);
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
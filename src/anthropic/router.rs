@@ -1,17 +1,30 @@
 //! Anthropic API 路由配置
 
+use std::sync::Arc;
+
 use axum::{
     Router,
     extract::DefaultBodyLimit,
     middleware,
     routing::{get, post},
 };
+use tower_http::compression::CompressionLayer;
 
+use crate::kiro::mock::MockProvider;
 use crate::kiro::provider::KiroProvider;
+use crate::metrics::metrics_handler;
+use crate::model::config::Config;
 
 use super::{
-    handlers::{count_tokens, get_models, post_messages, post_messages_cc},
-    middleware::{AppState, auth_middleware, cors_layer},
+    active_requests::ActiveRequests,
+    handlers::{
+        count_tokens, get_message, get_models, post_messages, post_messages_cc,
+        post_messages_dry_run,
+    },
+    middleware::{
+        AppState, auth_middleware, cors_layer, dry_run_auth_middleware, response_headers_middleware,
+    },
+    rate_limit::RateLimiter,
 };
 
 /// 请求体最大大小限制 (50MB)
@@ -23,21 +36,36 @@ const MAX_BODY_SIZE: usize = 50 * 1024 * 1024;
 /// - `GET /v1/models` - 获取可用模型列表
 /// - `POST /v1/messages` - 创建消息（对话）
 /// - `POST /v1/messages/count_tokens` - 计算 token 数量
+/// - `GET /v1/messages/{id}` - 获取暂存的非流式响应（需开启 `enableResponseRetrieval`）
+/// - `POST /v1/messages/dry-run` - 预览转换后的上游请求（不发起网络调用）
+/// - `GET /metrics` - Prometheus 指标
+///
+/// # 响应头
+/// 所有 `/v1`、`/cc/v1` 响应携带 `request-id` 和 `anthropic-version`
+///
+/// 上游压缩的响应由 HTTP 客户端解压后再处理；开启 `responseCompression` 时，
+/// 仅在客户端声明 `Accept-Encoding: gzip` 时压缩响应体（SSE 流不压缩）
 ///
 /// # 认证
 /// 所有 `/v1` 路径需要 API Key 认证，支持：
 /// - `x-api-key` header
 /// - `Authorization: Bearer <token>` header
 ///
+/// `/v1/messages/dry-run` 默认需要 `x-admin-key`，开启 `dryRunEnabled` 后也接受 API Key
+///
 /// # 参数
 /// - `api_key`: API 密钥，用于验证客户端请求
 /// - `kiro_provider`: 可选的 KiroProvider，用于调用上游 API
+/// - `rate_limiter`: 消息请求速率限制器（由调用方持有，与 Admin API 共享以导出/导入其状态）
+/// - `active_requests`: 进行中的消息请求登记表（由调用方持有，与 Admin API 共享以列出、取消请求）
 
 /// 创建带有 KiroProvider 的 Anthropic API 路由
-pub fn create_router_with_provider(
-    api_key: impl Into<String>,
+pub fn create_router_with_shared_rate_limiter(
+    api_key: impl AsRef<str>,
     kiro_provider: Option<KiroProvider>,
     profile_arn: Option<String>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    active_requests: Arc<ActiveRequests>,
 ) -> Router {
     let mut state = AppState::new(api_key);
     if let Some(provider) = kiro_provider {
@@ -46,15 +74,66 @@ pub fn create_router_with_provider(
     if let Some(arn) = profile_arn {
         state = state.with_profile_arn(arn);
     }
+    create_router(
+        state
+            .with_rate_limiter(rate_limiter)
+            .with_active_requests(active_requests),
+    )
+}
+
+/// 创建带有 KiroProvider 的 Anthropic API 路由（速率限制器按凭据管理器的配置创建）
+#[cfg(test)]
+pub fn create_router_with_provider(
+    api_key: impl AsRef<str>,
+    kiro_provider: Option<KiroProvider>,
+    profile_arn: Option<String>,
+) -> Router {
+    let rate_limiter = kiro_provider
+        .as_ref()
+        .and_then(|p| RateLimiter::from_config(p.token_manager().config()))
+        .map(Arc::new);
+    create_router_with_shared_rate_limiter(
+        api_key,
+        kiro_provider,
+        profile_arn,
+        rate_limiter,
+        Arc::default(),
+    )
+}
+
+/// 创建使用模拟上游的 Anthropic API 路由（不创建凭据管理器）
+pub fn create_router_with_mock_provider(
+    api_key: impl AsRef<str>,
+    provider: MockProvider,
+    config: &Config,
+) -> Router {
+    create_router(AppState::new(api_key).with_provider(Arc::new(provider), config))
+}
+
+pub(super) fn create_router(state: AppState) -> Router {
+    // dry-run 路由（默认仅 Admin API Key 可访问）
+    let dry_run_routes = Router::new()
+        .route("/messages/dry-run", post(post_messages_dry_run))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            dry_run_auth_middleware,
+        ));
 
     // 需要认证的 /v1 路由
     let v1_routes = Router::new()
         .route("/models", get(get_models))
         .route("/messages", post(post_messages))
         .route("/messages/count_tokens", post(count_tokens))
+        .route("/messages/{id}", get(get_message))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
+        ))
+        .merge(dry_run_routes)
+        // 最外层：认证失败等错误响应同样携带 request-id / anthropic-version
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_headers_middleware,
         ));
 
     // 需要认证的 /cc/v1 路由（Claude Code 兼容端点）
@@ -65,12 +144,32 @@ pub fn create_router_with_provider(
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
+        ))
+        // 最外层：认证失败等错误响应同样携带 request-id / anthropic-version
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_headers_middleware,
         ));
 
-    Router::new()
+    // Prometheus 指标（同样需要 API Key 认证）
+    let metrics_routes = Router::new().route("/metrics", get(metrics_handler)).layer(
+        middleware::from_fn_with_state(state.clone(), auth_middleware),
+    );
+
+    let response_compression = state.response_compression;
+    let cors = cors_layer(&state.cors_allowed_origins);
+    let router = Router::new()
         .nest("/v1", v1_routes)
         .nest("/cc/v1", cc_v1_routes)
-        .layer(cors_layer())
+        .merge(metrics_routes)
+        .layer(cors)
         .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
-        .with_state(state)
+        .with_state(state);
+
+    // 默认谓词不压缩 text/event-stream，流式响应不受影响
+    if response_compression {
+        router.layer(CompressionLayer::new())
+    } else {
+        router
+    }
 }
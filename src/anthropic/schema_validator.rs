@@ -0,0 +1,365 @@
+//! 轻量级 JSON Schema 校验器
+//!
+//! 仅实现工具输入校验所需的子集：
+//! - `type`（string / number / integer / boolean / object / array / null，支持数组形式）
+//! - `required`
+//! - `properties`
+//! - `enum`
+//! - `items`
+//!
+//! 未识别的关键字会被忽略，不会导致校验失败。
+
+use std::fmt;
+
+use serde_json::Value;
+
+/// 单条校验错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// 出错位置（JSON Pointer 格式，根为空字符串）
+    pub path: String,
+    /// 错误描述
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "/: {}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// 按 schema 校验 JSON 值，返回所有错误（空表示通过）
+pub fn validate(schema: &Value, value: &Value) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types = expected_types(expected);
+        if !types.is_empty() && !types.iter().any(|t| matches_type(t, value)) {
+            errors.push(ValidationError {
+                path: path.to_string(),
+                message: format!(
+                    "类型不匹配：期望 {}，实际为 {}",
+                    types.join(" | "),
+                    type_name(value)
+                ),
+            });
+            // 类型不符时不再深入校验子结构
+            return;
+        }
+    }
+
+    if schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .is_some_and(|allowed| !allowed.contains(value))
+    {
+        errors.push(ValidationError {
+            path: path.to_string(),
+            message: format!("值 {} 不在枚举范围内", value),
+        });
+    }
+
+    if let Some(object) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    errors.push(ValidationError {
+                        path: path.to_string(),
+                        message: format!("缺少必填字段 \"{}\"", key),
+                    });
+                }
+            }
+        }
+
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, prop_schema) in properties {
+                if let Some(prop_value) = object.get(key) {
+                    validate_at(prop_schema, prop_value, &child_path(path, key), errors);
+                }
+            }
+        }
+    }
+
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate_at(items, item, &child_path(path, &i.to_string()), errors);
+        }
+    }
+}
+
+/// 按 schema 修正明显的类型问题（如数字/布尔值被输出为字符串），返回修正后的值
+///
+/// 仅做无损转换：无法转换的值保持原样，由后续校验报告错误
+pub fn coerce(schema: &Value, value: &Value) -> Value {
+    let Some(schema_obj) = schema.as_object() else {
+        return value.clone();
+    };
+
+    let types = schema_obj
+        .get("type")
+        .map(expected_types)
+        .unwrap_or_default();
+
+    if let Value::String(s) = value {
+        // schema 允许字符串时保持原样
+        if types.contains(&"string") {
+            return value.clone();
+        }
+        return types
+            .iter()
+            .find_map(|t| coerce_string(t, s))
+            .unwrap_or_else(|| value.clone());
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema_obj.get("properties").and_then(Value::as_object);
+            let coerced = object
+                .iter()
+                .map(|(key, v)| {
+                    let v = match properties.and_then(|p| p.get(key)) {
+                        Some(prop_schema) => coerce(prop_schema, v),
+                        None => v.clone(),
+                    };
+                    (key.clone(), v)
+                })
+                .collect();
+            Value::Object(coerced)
+        }
+        Value::Array(array) => match schema_obj.get("items") {
+            Some(items) => Value::Array(array.iter().map(|v| coerce(items, v)).collect()),
+            None => value.clone(),
+        },
+        _ => value.clone(),
+    }
+}
+
+/// 尝试将字符串转换为目标类型
+fn coerce_string(expected: &str, s: &str) -> Option<Value> {
+    let s = s.trim();
+    match expected {
+        "integer" => s.parse::<i64>().ok().map(Value::from),
+        "number" => s.parse::<i64>().ok().map(Value::from).or_else(|| {
+            s.parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite())
+                .map(Value::from)
+        }),
+        "boolean" => match s {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        "null" if s == "null" => Some(Value::Null),
+        _ => None,
+    }
+}
+
+fn expected_types(expected: &Value) -> Vec<&str> {
+    match expected {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(list) => list.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn matches_type(expected: &str, value: &Value) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        // 未知类型不做约束
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// 拼接 JSON Pointer 路径（按 RFC 6901 转义 `~` 与 `/`）
+fn child_path(parent: &str, key: &str) -> String {
+    format!("{}/{}", parent, key.replace('~', "~0").replace('/', "~1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read_file_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "offset": { "type": "integer" },
+                "limit": { "type": "number" },
+                "verbose": { "type": "boolean" },
+                "mode": { "type": "string", "enum": ["text", "binary"] },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "options": {
+                    "type": "object",
+                    "properties": { "depth": { "type": "integer" } },
+                    "required": ["depth"]
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn messages(errors: &[ValidationError]) -> Vec<String> {
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let schema = read_file_schema();
+        // (payload, 期望的错误路径列表)
+        let fixtures: Vec<(Value, Vec<&str>)> = vec![
+            (json!({ "path": "/tmp/a" }), vec![]),
+            (
+                json!({
+                    "path": "/tmp/a", "offset": 10, "limit": 1.5, "verbose": true,
+                    "mode": "text", "tags": ["x", "y"], "options": { "depth": 2 }
+                }),
+                vec![],
+            ),
+            (json!({}), vec![""]),
+            (json!({ "path": 1 }), vec!["/path"]),
+            (json!({ "path": "a", "offset": 1.5 }), vec!["/offset"]),
+            (json!({ "path": "a", "offset": 2.0 }), vec![]),
+            (json!({ "path": "a", "limit": "10" }), vec!["/limit"]),
+            (json!({ "path": "a", "mode": "hex" }), vec!["/mode"]),
+            (
+                json!({ "path": "a", "tags": ["x", 1, "z", false] }),
+                vec!["/tags/1", "/tags/3"],
+            ),
+            (json!({ "path": "a", "options": {} }), vec!["/options"]),
+            (
+                json!({ "path": "a", "options": { "depth": "deep" } }),
+                vec!["/options/depth"],
+            ),
+            (json!([1, 2]), vec![""]),
+            // 未声明的字段不做约束
+            (
+                json!({ "path": "a", "extra": { "anything": true } }),
+                vec![],
+            ),
+        ];
+
+        for (payload, expected_paths) in fixtures {
+            let errors = validate(&schema, &payload);
+            let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                expected_paths,
+                "payload: {} -> {:?}",
+                payload,
+                messages(&errors)
+            );
+        }
+    }
+
+    #[test]
+    fn test_type_union_and_null() {
+        let schema = json!({ "type": ["string", "null"] });
+        assert!(validate(&schema, &json!("a")).is_empty());
+        assert!(validate(&schema, &Value::Null).is_empty());
+        assert_eq!(validate(&schema, &json!(1)).len(), 1);
+    }
+
+    #[test]
+    fn test_enum_without_type() {
+        let schema = json!({ "enum": [1, "two", null] });
+        assert!(validate(&schema, &json!("two")).is_empty());
+        assert!(validate(&schema, &Value::Null).is_empty());
+        assert_eq!(validate(&schema, &json!(2)).len(), 1);
+    }
+
+    #[test]
+    fn test_empty_and_unknown_schema_accepts_anything() {
+        for schema in [json!({}), json!(true), json!({ "type": "custom" })] {
+            assert!(validate(&schema, &json!({ "a": [1, 2] })).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_missing_required_message() {
+        let errors = validate(&read_file_schema(), &json!({}));
+        assert_eq!(messages(&errors), vec!["/: 缺少必填字段 \"path\""]);
+    }
+
+    #[test]
+    fn test_child_path_escaping() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "a/b~c": { "type": "string" } }
+        });
+        let errors = validate(&schema, &json!({ "a/b~c": 1 }));
+        assert_eq!(errors[0].path, "/a~1b~0c");
+    }
+
+    #[test]
+    fn test_coerce_numbers_and_booleans_from_strings() {
+        let schema = read_file_schema();
+        let payload = json!({
+            "path": "123",
+            "offset": " 42 ",
+            "limit": "2.5",
+            "verbose": "true",
+            "options": { "depth": "3" }
+        });
+        let coerced = coerce(&schema, &payload);
+        assert_eq!(
+            coerced,
+            json!({
+                "path": "123",
+                "offset": 42,
+                "limit": 2.5,
+                "verbose": true,
+                "options": { "depth": 3 }
+            })
+        );
+        assert!(validate(&schema, &coerced).is_empty());
+    }
+
+    #[test]
+    fn test_coerce_leaves_unconvertible_values() {
+        let schema = read_file_schema();
+        let payload = json!({ "path": "a", "offset": "ten", "tags": ["1", 2] });
+        let coerced = coerce(&schema, &payload);
+        assert_eq!(coerced, payload);
+        assert_eq!(validate(&schema, &coerced).len(), 2);
+    }
+
+    #[test]
+    fn test_coerce_array_items() {
+        let schema = json!({ "type": "array", "items": { "type": "integer" } });
+        assert_eq!(coerce(&schema, &json!(["1", "2", "x"])), json!([1, 2, "x"]));
+    }
+}
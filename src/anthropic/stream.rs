@@ -2,12 +2,20 @@
 //!
 //! 实现 Kiro → Anthropic 流式响应转换和 SSE 状态管理
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use axum::http::StatusCode;
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::kiro::model::events::Event;
+use crate::kiro::model::events::{Event, ReasoningContentEvent};
+use crate::kiro::user_usage::UserUsageRecorder;
+
+use super::models::CONTEXT_WINDOW_SIZE;
+use super::post_processing::TextFilterStream;
+use super::tool_validation::{
+    ToolInputRecovery, ToolInputValidator, degraded_tool_text, recover_tool_input,
+};
 
 /// 找到小于等于目标位置的最近有效UTF-8字符边界
 ///
@@ -196,6 +204,39 @@ impl SseEvent {
     }
 }
 
+/// 创建 SSE `error` 事件（流已开始后无法再改变状态码，错误以该事件结束流）
+pub fn error_sse_event(error_type: &str, message: impl Into<String>) -> SseEvent {
+    SseEvent::new(
+        "error",
+        json!({
+            "type": "error",
+            "error": {
+                "type": error_type,
+                "message": message.into()
+            }
+        }),
+    )
+}
+
+/// 上游异常类型（exception 帧的 `:exception-type` 或 error 帧的 `:error-code`）对应的状态码与 Anthropic 错误类型
+pub fn upstream_error_kind(code: &str) -> (StatusCode, &'static str) {
+    match code {
+        "ThrottlingException" | "ServiceQuotaExceededException" | "TooManyRequestsException" => {
+            (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error")
+        }
+        "ValidationException" | "BadRequestException" => {
+            (StatusCode::BAD_REQUEST, "invalid_request_error")
+        }
+        "AccessDeniedException" | "UnauthorizedException" => {
+            (StatusCode::FORBIDDEN, "permission_error")
+        }
+        "ServiceUnavailableException" | "ModelNotReadyException" => {
+            (StatusCode::SERVICE_UNAVAILABLE, "overloaded_error")
+        }
+        _ => (StatusCode::BAD_GATEWAY, "api_error"),
+    }
+}
+
 /// 内容块状态
 #[derive(Debug, Clone)]
 struct BlockState {
@@ -227,8 +268,8 @@ pub struct SseStateManager {
     message_started: bool,
     /// message_delta 是否已发送
     message_delta_sent: bool,
-    /// 活跃的内容块状态
-    active_blocks: HashMap<i32, BlockState>,
+    /// 活跃的内容块状态（按索引有序，自动关闭时按块顺序输出 content_block_stop）
+    active_blocks: BTreeMap<i32, BlockState>,
     /// 消息是否已结束
     message_ended: bool,
     /// 下一个块索引
@@ -250,7 +291,7 @@ impl SseStateManager {
         Self {
             message_started: false,
             message_delta_sent: false,
-            active_blocks: HashMap::new(),
+            active_blocks: BTreeMap::new(),
             message_ended: false,
             next_block_index: 0,
             stop_reason: None,
@@ -453,8 +494,42 @@ impl SseStateManager {
     }
 }
 
-/// 上下文窗口大小（200k tokens）
-const CONTEXT_WINDOW_SIZE: i32 = 200_000;
+/// 校验或修复工具输入时，单个工具调用缓冲参数的最大字节数
+///
+/// 超出部分被丢弃，不完整的参数按校验失败或无法修复处理，避免超大工具调用无限占用内存
+const MAX_BUFFERED_TOOL_INPUT_BYTES: usize = 4 * 1024 * 1024;
+
+/// 追加工具输入片段，总长度不超过 [`MAX_BUFFERED_TOOL_INPUT_BYTES`]
+fn push_tool_input(buffer: &mut String, fragment: &str, tool_use_id: &str) {
+    let room = MAX_BUFFERED_TOOL_INPUT_BYTES.saturating_sub(buffer.len());
+    if fragment.len() <= room {
+        buffer.push_str(fragment);
+        return;
+    }
+    if room > 0 {
+        tracing::warn!(
+            tool_use_id = %tool_use_id,
+            limit = MAX_BUFFERED_TOOL_INPUT_BYTES,
+            "工具输入超过缓冲上限，丢弃超出部分"
+        );
+    }
+    let mut end = room;
+    while !fragment.is_char_boundary(end) {
+        end -= 1;
+    }
+    buffer.push_str(&fragment[..end]);
+}
+
+/// 按输出 token 估算判定截断时的容差比例（估算值与上游实际计数存在偏差）
+const MAX_TOKENS_TOLERANCE_RATIO: f64 = 0.05;
+
+/// 输出 tokens 估算值是否已达到 max_tokens 预算（允许 [`MAX_TOKENS_TOLERANCE_RATIO`] 的误差）
+///
+/// 上游未发送 ContentLengthExceededException 时，这是判断输出被截断的唯一依据
+pub fn reaches_max_tokens(output_tokens: i32, max_tokens: i32) -> bool {
+    max_tokens > 0
+        && f64::from(output_tokens) >= f64::from(max_tokens) * (1.0 - MAX_TOKENS_TOLERANCE_RATIO)
+}
 
 /// 流处理上下文
 pub struct StreamContext {
@@ -484,9 +559,37 @@ pub struct StreamContext {
     pub thinking_block_index: Option<i32>,
     /// 文本块索引（thinking 启用时动态分配）
     pub text_block_index: Option<i32>,
+    /// 上游 reasoningContentEvent 对应的 thinking 块索引（收到签名后关闭）
+    reasoning_block_index: Option<i32>,
     /// 是否需要剥离 thinking 内容开头的换行符
     /// 模型输出 `<thinking>\n` 时，`\n` 可能与标签在同一 chunk 或下一 chunk
     strip_thinking_leading_newline: bool,
+    /// 工具输入校验器（启用 validateToolInputs 时存在）
+    tool_validator: Option<ToolInputValidator>,
+    /// 启用校验时缓冲的工具输入 (tool_id -> 已累积的 JSON)
+    tool_input_buffers: HashMap<String, String>,
+    /// 是否在工具调用完成时检查并修复参数 JSON（启用 repairToolInputs 时）
+    tool_input_recovery: bool,
+    /// 启用修复时缓冲的未完成工具调用（按首次出现顺序）
+    pending_tool_uses: Vec<PendingToolUse>,
+    /// 文本后处理过滤器（配置了 postProcessing 时存在）
+    text_filter: Option<TextFilterStream>,
+    /// 流结束时回写用户 token 用量
+    usage_recorder: Option<UserUsageRecorder>,
+    /// usage 中是否补充缓存相关字段（启用 prompt-caching beta 时）
+    cache_usage: bool,
+    /// 请求的 max_tokens（用于判断输出是否被截断）
+    max_tokens: Option<i32>,
+    /// 上游中途返回了异常 / 错误（已发送 SSE `error` 事件，不再处理后续事件）
+    upstream_failed: bool,
+}
+
+/// 启用参数修复时缓冲的工具调用
+#[derive(Debug)]
+struct PendingToolUse {
+    tool_use_id: String,
+    name: String,
+    input: String,
 }
 
 impl StreamContext {
@@ -510,13 +613,63 @@ impl StreamContext {
             thinking_extracted: false,
             thinking_block_index: None,
             text_block_index: None,
+            reasoning_block_index: None,
             strip_thinking_leading_newline: false,
+            tool_validator: None,
+            tool_input_buffers: HashMap::new(),
+            tool_input_recovery: false,
+            pending_tool_uses: Vec::new(),
+            text_filter: None,
+            usage_recorder: None,
+            cache_usage: false,
+            max_tokens: None,
+            upstream_failed: false,
         }
     }
 
+    /// 设置工具输入校验器
+    ///
+    /// 启用后工具输入不再逐段转发，而是在工具调用完成时校验后一次性输出
+    pub fn with_tool_validator(mut self, validator: Option<ToolInputValidator>) -> Self {
+        self.tool_validator = validator;
+        self
+    }
+
+    /// 设置是否修复损坏的工具参数 JSON
+    ///
+    /// 启用后整个 tool_use 块在工具调用完成时才输出：参数无法修复时改为输出说明文本
+    pub fn with_tool_input_recovery(mut self, enabled: bool) -> Self {
+        self.tool_input_recovery = enabled;
+        self
+    }
+
+    /// 设置文本后处理过滤器（仅作用于 text_delta）
+    pub fn with_text_filter(mut self, filter: Option<TextFilterStream>) -> Self {
+        self.text_filter = filter;
+        self
+    }
+
+    /// 设置用户用量记录器（生成最终事件时记录一次 token 用量）
+    pub fn with_usage_recorder(mut self, recorder: Option<UserUsageRecorder>) -> Self {
+        self.usage_recorder = recorder;
+        self
+    }
+
+    /// 设置是否在 usage 中补充缓存相关字段
+    pub fn with_cache_usage(mut self, enabled: bool) -> Self {
+        self.cache_usage = enabled;
+        self
+    }
+
+    /// 设置请求的 max_tokens（输出达到预算时 stop_reason 改为 max_tokens）
+    pub fn with_max_tokens(mut self, max_tokens: Option<i32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
     /// 生成 message_start 事件
     pub fn create_message_start_event(&self) -> serde_json::Value {
-        json!({
+        let mut event = json!({
             "type": "message_start",
             "message": {
                 "id": self.message_id,
@@ -531,7 +684,11 @@ impl StreamContext {
                     "output_tokens": 1
                 }
             }
-        })
+        });
+        if self.cache_usage {
+            add_cache_usage_fields(&mut event["message"]["usage"]);
+        }
+        event
     }
 
     /// 生成初始事件序列 (message_start + 文本块 start)
@@ -573,11 +730,28 @@ impl StreamContext {
         events
     }
 
+    /// 上游是否中途返回了异常 / 错误（流应在发送 `error` 事件后结束）
+    pub fn upstream_failed(&self) -> bool {
+        self.upstream_failed
+    }
+
     /// 处理 Kiro 事件并转换为 Anthropic SSE 事件
     pub fn process_kiro_event(&mut self, event: &Event) -> Vec<SseEvent> {
+        if self.upstream_failed {
+            return Vec::new();
+        }
         match event {
-            Event::AssistantResponse(resp) => self.process_assistant_response(&resp.content),
-            Event::ToolUse(tool_use) => self.process_tool_use(tool_use),
+            Event::AssistantResponse(resp) => {
+                let mut events = self.close_reasoning_block();
+                events.extend(self.process_assistant_response(&resp.content));
+                events
+            }
+            Event::ToolUse(tool_use) => {
+                let mut events = self.close_reasoning_block();
+                events.extend(self.process_tool_use(tool_use));
+                events
+            }
+            Event::ReasoningContent(reasoning) => self.process_reasoning_content(reasoning),
             Event::ContextUsage(context_usage) => {
                 // 从上下文使用百分比计算实际的 input_tokens
                 // 公式: percentage * 200000 / 100 = percentage * 2000
@@ -597,28 +771,38 @@ impl StreamContext {
                 );
                 Vec::new()
             }
-            Event::Error {
-                error_code,
-                error_message,
-            } => {
-                tracing::error!("收到错误事件: {} - {}", error_code, error_message);
-                Vec::new()
+            Event::Error(error) => {
+                tracing::error!("收到错误事件: {}", error);
+                self.fail_upstream(&error.error_code, error.to_string())
             }
-            Event::Exception {
-                exception_type,
-                message,
-            } => {
+            Event::Exception(exception) => {
                 // 处理 ContentLengthExceededException
-                if exception_type == "ContentLengthExceededException" {
+                if exception.is_content_length_exceeded() {
+                    tracing::info!(
+                        "上游返回 {}，stop_reason 设为 max_tokens",
+                        exception.exception_type
+                    );
                     self.state_manager.set_stop_reason("max_tokens");
+                    return Vec::new();
                 }
-                tracing::warn!("收到异常事件: {} - {}", exception_type, message);
-                Vec::new()
+                tracing::warn!("收到异常事件: {}", exception);
+                self.fail_upstream(&exception.exception_type, exception.to_string())
             }
             _ => Vec::new(),
         }
     }
 
+    /// 上游中途失败：记录已产生的用量并以 SSE `error` 事件结束流
+    fn fail_upstream(&mut self, code: &str, message: String) -> Vec<SseEvent> {
+        self.upstream_failed = true;
+        let input_tokens = self.context_input_tokens.unwrap_or(self.input_tokens);
+        if let Some(recorder) = self.usage_recorder.take() {
+            recorder.record(input_tokens, self.output_tokens);
+        }
+        let (_, error_type) = upstream_error_kind(code);
+        vec![error_sse_event(error_type, message)]
+    }
+
     /// 处理助手响应事件
     fn process_assistant_response(&mut self, content: &str) -> Vec<SseEvent> {
         if content.is_empty() {
@@ -657,6 +841,9 @@ impl StreamContext {
                         events.extend(self.create_text_delta_events(&before_thinking));
                     }
 
+                    // thinking 块开始前输出过滤器暂存的文本，保证顺序
+                    events.extend(self.flush_text_filter());
+
                     // 进入 thinking 块
                     self.in_thinking_block = true;
                     self.strip_thinking_leading_newline = true;
@@ -784,13 +971,41 @@ impl StreamContext {
         events
     }
 
+    /// 经过后处理过滤器后创建 text_delta 事件
+    ///
+    /// 过滤器可能暂存部分文本（用于跨 chunk 匹配），暂存内容在块切换或流结束时由
+    /// `flush_text_filter` 输出。
+    fn create_text_delta_events(&mut self, text: &str) -> Vec<SseEvent> {
+        let text = match self.text_filter.as_mut() {
+            Some(filter) => filter.push(text),
+            None => text.to_string(),
+        };
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.emit_text_delta_events(&text)
+    }
+
+    /// 输出过滤器暂存的文本
+    fn flush_text_filter(&mut self) -> Vec<SseEvent> {
+        let text = self
+            .text_filter
+            .as_mut()
+            .map(|filter| filter.flush())
+            .unwrap_or_default();
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.emit_text_delta_events(&text)
+    }
+
     /// 创建 text_delta 事件
     ///
     /// 如果文本块尚未创建，会先创建文本块。
     /// 当发生 tool_use 时，状态机会自动关闭当前文本块；后续文本会自动创建新的文本块继续输出。
     ///
     /// 返回值包含可能的 content_block_start 事件和 content_block_delta 事件。
-    fn create_text_delta_events(&mut self, text: &str) -> Vec<SseEvent> {
+    fn emit_text_delta_events(&mut self, text: &str) -> Vec<SseEvent> {
         let mut events = Vec::new();
 
         // 如果当前 text_block_index 指向的块已经被关闭（例如 tool_use 开始时自动 stop），
@@ -844,6 +1059,92 @@ impl StreamContext {
         events
     }
 
+    /// 处理推理内容事件
+    ///
+    /// 思考文本以 thinking 块输出，收到签名时发送 signature_delta 并关闭该块；
+    /// 加密的思考内容以独立的 redacted_thinking 块输出
+    fn process_reasoning_content(&mut self, reasoning: &ReasoningContentEvent) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+
+        if let Some(data) = &reasoning.redacted_content {
+            events.extend(self.close_reasoning_block());
+            let index = self.state_manager.next_block_index();
+            events.extend(self.state_manager.handle_content_block_start(
+                index,
+                "redacted_thinking",
+                json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {
+                        "type": "redacted_thinking",
+                        "data": data
+                    }
+                }),
+            ));
+            events.extend(self.state_manager.handle_content_block_stop(index));
+        }
+
+        if reasoning.text.is_empty() && reasoning.signature.is_none() {
+            return events;
+        }
+        let index = match self.reasoning_block_index {
+            Some(index) => index,
+            None => self.start_reasoning_block(&mut events),
+        };
+        if !reasoning.text.is_empty() {
+            self.output_tokens += estimate_tokens(&reasoning.text);
+            events.push(self.create_thinking_delta_event(index, &reasoning.text));
+        }
+        if let Some(signature) = &reasoning.signature {
+            events.extend(self.state_manager.handle_content_block_delta(
+                index,
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {
+                        "type": "signature_delta",
+                        "signature": signature
+                    }
+                }),
+            ));
+            events.extend(self.state_manager.handle_content_block_stop(index));
+            self.reasoning_block_index = None;
+        }
+        events
+    }
+
+    /// 开始新的推理 thinking 块（先关闭打开的文本块，保证块顺序）
+    fn start_reasoning_block(&mut self, events: &mut Vec<SseEvent>) -> i32 {
+        events.extend(self.flush_text_filter());
+        if let Some(text_index) = self.text_block_index.take() {
+            events.extend(self.state_manager.handle_content_block_stop(text_index));
+        }
+        let index = self.state_manager.next_block_index();
+        self.reasoning_block_index = Some(index);
+        events.extend(self.state_manager.handle_content_block_start(
+            index,
+            "thinking",
+            json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": {
+                    "type": "thinking",
+                    "thinking": ""
+                }
+            }),
+        ));
+        index
+    }
+
+    /// 关闭未收到签名的推理 thinking 块（后续输出文本或工具调用时）
+    fn close_reasoning_block(&mut self) -> Vec<SseEvent> {
+        self.reasoning_block_index
+            .take()
+            .and_then(|index| self.state_manager.handle_content_block_stop(index))
+            .into_iter()
+            .collect()
+    }
+
     /// 创建 thinking_delta 事件
     fn create_thinking_delta_event(&self, index: i32, thinking: &str) -> SseEvent {
         SseEvent::new(
@@ -866,8 +1167,6 @@ impl StreamContext {
     ) -> Vec<SseEvent> {
         let mut events = Vec::new();
 
-        self.state_manager.set_has_tool_use(true);
-
         // tool_use 必须发生在 thinking 结束之后。
         // 但当 `</thinking>` 后面没有 `\n\n`（例如紧跟 tool_use 或流结束）时，
         // thinking 结束标签会滞留在 thinking_buffer，导致后续 flush 时把 `</thinking>` 当作内容输出。
@@ -920,6 +1219,17 @@ impl StreamContext {
             events.extend(self.create_text_delta_events(&buffered));
         }
 
+        // 启用修复时缓冲整个工具调用，完成后再决定输出 tool_use 块还是降级文本
+        if self.tool_input_recovery {
+            self.buffer_tool_use(tool_use, &mut events);
+            return events;
+        }
+
+        self.state_manager.set_has_tool_use(true);
+
+        // tool_use 开始会关闭当前文本块，先输出过滤器暂存的文本
+        events.extend(self.flush_text_filter());
+
         // 获取或分配块索引
         let block_index = if let Some(&idx) = self.tool_block_indices.get(&tool_use.tool_use_id) {
             idx
@@ -951,24 +1261,32 @@ impl StreamContext {
         if !tool_use.input.is_empty() {
             self.output_tokens += (tool_use.input.len() as i32 + 3) / 4; // 估算 token
 
-            if let Some(delta_event) = self.state_manager.handle_content_block_delta(
-                block_index,
-                json!({
-                    "type": "content_block_delta",
-                    "index": block_index,
-                    "delta": {
-                        "type": "input_json_delta",
-                        "partial_json": tool_use.input
-                    }
-                }),
-            ) {
-                events.push(delta_event);
+            if self.tool_validator.is_some() {
+                // 启用校验时先缓冲，待工具调用完成后统一校验再输出
+                let buffer = self
+                    .tool_input_buffers
+                    .entry(tool_use.tool_use_id.clone())
+                    .or_default();
+                push_tool_input(buffer, &tool_use.input, &tool_use.tool_use_id);
+            } else {
+                events.extend(self.create_input_json_delta_event(block_index, &tool_use.input));
             }
         }
 
         // 如果是完整的工具调用（stop=true），发送 content_block_stop
         if tool_use.stop {
-            if let Some(stop_event) = self.state_manager.handle_content_block_stop(block_index) {
+            let annotation = self.flush_validated_tool_input(
+                block_index,
+                &tool_use.tool_use_id,
+                &tool_use.name,
+                &mut events,
+            );
+            if let Some(mut stop_event) = self.state_manager.handle_content_block_stop(block_index)
+            {
+                if let Some(errors) = annotation {
+                    stop_event.data["is_error"] = json!(true);
+                    stop_event.data["validation_errors"] = errors;
+                }
                 events.push(stop_event);
             }
         }
@@ -976,9 +1294,160 @@ impl StreamContext {
         events
     }
 
+    /// 缓冲工具调用片段，调用完成时输出
+    fn buffer_tool_use(
+        &mut self,
+        tool_use: &crate::kiro::model::events::ToolUseEvent,
+        events: &mut Vec<SseEvent>,
+    ) {
+        self.output_tokens += (tool_use.input.len() as i32 + 3) / 4; // 估算 token
+
+        let position = self
+            .pending_tool_uses
+            .iter()
+            .position(|p| p.tool_use_id == tool_use.tool_use_id);
+        let position = position.unwrap_or_else(|| {
+            self.pending_tool_uses.push(PendingToolUse {
+                tool_use_id: tool_use.tool_use_id.clone(),
+                name: tool_use.name.clone(),
+                input: String::new(),
+            });
+            self.pending_tool_uses.len() - 1
+        });
+        push_tool_input(
+            &mut self.pending_tool_uses[position].input,
+            &tool_use.input,
+            &tool_use.tool_use_id,
+        );
+
+        if tool_use.stop {
+            let pending = self.pending_tool_uses.remove(position);
+            self.emit_recovered_tool_use(pending, events);
+        }
+    }
+
+    /// 检查（必要时修复）完整的工具参数并输出 tool_use 块，无法修复时输出说明文本
+    fn emit_recovered_tool_use(&mut self, pending: PendingToolUse, events: &mut Vec<SseEvent>) {
+        let PendingToolUse {
+            tool_use_id,
+            name,
+            input,
+        } = pending;
+
+        events.extend(self.flush_text_filter());
+        let input = match recover_tool_input(&tool_use_id, &name, &input) {
+            ToolInputRecovery::Valid(input) | ToolInputRecovery::Repaired(input) => input,
+            ToolInputRecovery::Unrecoverable => {
+                let text = degraded_tool_text(&tool_use_id, &name, &input);
+                events.extend(self.emit_text_delta_events(&text));
+                return;
+            }
+        };
+
+        self.state_manager.set_has_tool_use(true);
+        let block_index = self.state_manager.next_block_index();
+        self.tool_block_indices
+            .insert(tool_use_id.clone(), block_index);
+        events.extend(self.state_manager.handle_content_block_start(
+            block_index,
+            "tool_use",
+            json!({
+                "type": "content_block_start",
+                "index": block_index,
+                "content_block": {
+                    "type": "tool_use",
+                    "id": tool_use_id,
+                    "name": name,
+                    "input": {}
+                }
+            }),
+        ));
+
+        let annotation = if self.tool_validator.is_some() {
+            self.tool_input_buffers.insert(tool_use_id.clone(), input);
+            self.flush_validated_tool_input(block_index, &tool_use_id, &name, events)
+        } else {
+            if !input.is_empty() {
+                events.extend(self.create_input_json_delta_event(block_index, &input));
+            }
+            None
+        };
+        if let Some(mut stop_event) = self.state_manager.handle_content_block_stop(block_index) {
+            if let Some(errors) = annotation {
+                stop_event.data["is_error"] = json!(true);
+                stop_event.data["validation_errors"] = errors;
+            }
+            events.push(stop_event);
+        }
+    }
+
+    /// 创建 input_json_delta 事件
+    fn create_input_json_delta_event(
+        &mut self,
+        index: i32,
+        partial_json: &str,
+    ) -> Option<SseEvent> {
+        self.state_manager.handle_content_block_delta(
+            index,
+            json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {
+                    "type": "input_json_delta",
+                    "partial_json": partial_json
+                }
+            }),
+        )
+    }
+
+    /// 校验并输出缓冲的工具输入，返回需要标注在 content_block_stop 上的错误（annotate 策略）
+    fn flush_validated_tool_input(
+        &mut self,
+        index: i32,
+        tool_use_id: &str,
+        name: &str,
+        events: &mut Vec<SseEvent>,
+    ) -> Option<serde_json::Value> {
+        let validator = self.tool_validator.as_ref()?;
+        let raw = self
+            .tool_input_buffers
+            .remove(tool_use_id)
+            .unwrap_or_default();
+        let check = validator.check(tool_use_id, name, &raw);
+        let annotation = check.annotation(validator.policy());
+        if !check.input.is_empty() {
+            events.extend(self.create_input_json_delta_event(index, &check.input));
+        }
+        annotation
+    }
+
     /// 生成最终事件序列
+    ///
+    /// 上游中途失败时流已以 `error` 事件结束，不再生成 message_delta / message_stop
     pub fn generate_final_events(&mut self) -> Vec<SseEvent> {
         let mut events = Vec::new();
+        if self.upstream_failed {
+            return events;
+        }
+
+        // 流异常结束时缓冲的工具调用未完成，尝试修复后输出
+        for pending in std::mem::take(&mut self.pending_tool_uses) {
+            self.emit_recovered_tool_use(pending, &mut events);
+        }
+
+        // 流异常结束时工具调用可能未完成，原样输出缓冲的输入（不做校验，按块索引顺序）
+        let mut pending_inputs: Vec<(i32, String)> = std::mem::take(&mut self.tool_input_buffers)
+            .into_iter()
+            .filter_map(|(tool_use_id, input)| {
+                self.tool_block_indices
+                    .get(&tool_use_id)
+                    .map(|&index| (index, input))
+            })
+            .collect();
+        pending_inputs.sort_by_key(|(index, _)| *index);
+        for (index, input) in pending_inputs {
+            events.extend(self.create_input_json_delta_event(index, &input));
+        }
 
         // Flush thinking_buffer 中的剩余内容
         if self.thinking_enabled && !self.thinking_buffer.is_empty() {
@@ -1042,6 +1511,8 @@ impl StreamContext {
             self.thinking_buffer.clear();
         }
 
+        events.extend(self.flush_text_filter());
+
         // 如果整个流中只产生了 thinking 块，没有 text 也没有 tool_use，
         // 则设置 stop_reason 为 max_tokens（表示模型耗尽了 token 预算在思考上），
         // 并补发一套完整的 text 事件（内容为一个空格），确保 content 数组中有 text 块
@@ -1050,21 +1521,61 @@ impl StreamContext {
             && !self.state_manager.has_non_thinking_blocks()
         {
             self.state_manager.set_stop_reason("max_tokens");
-            events.extend(self.create_text_delta_events(" "));
+            events.extend(self.emit_text_delta_events(" "));
+        }
+
+        // 上游未给出截断信号时，按输出 token 估算判断是否耗尽了预算
+        let budget_reached = self
+            .max_tokens
+            .is_some_and(|max_tokens| reaches_max_tokens(self.output_tokens, max_tokens));
+        if budget_reached && self.state_manager.get_stop_reason() == "end_turn" {
+            tracing::info!(
+                output_tokens = self.output_tokens,
+                max_tokens = ?self.max_tokens,
+                "输出 tokens 估算值达到 max_tokens 预算，stop_reason 设为 max_tokens"
+            );
+            self.state_manager.set_stop_reason("max_tokens");
         }
 
         // 使用从 contextUsageEvent 计算的 input_tokens，如果没有则使用估算值
         let final_input_tokens = self.context_input_tokens.unwrap_or(self.input_tokens);
+        if let Some(recorder) = self.usage_recorder.take() {
+            recorder.record(final_input_tokens, self.output_tokens);
+        }
 
         // 生成最终事件
-        events.extend(
-            self.state_manager
-                .generate_final_events(final_input_tokens, self.output_tokens),
-        );
+        let mut final_events = self
+            .state_manager
+            .generate_final_events(final_input_tokens, self.output_tokens);
+        // JSON 模式下输出未能解析为 JSON：流已发出无法重试，在 message_delta 中标注降级
+        let json_output_invalid = self
+            .text_filter
+            .as_ref()
+            .is_some_and(|f| f.json_output_valid() == Some(false));
+        for event in final_events
+            .iter_mut()
+            .filter(|e| e.event == "message_delta")
+        {
+            if self.cache_usage {
+                add_cache_usage_fields(&mut event.data["usage"]);
+            }
+            if json_output_invalid {
+                event.data["degraded"] = json!("json-output");
+            }
+        }
+        events.extend(final_events);
         events
     }
 }
 
+/// 在 usage 中补充缓存相关字段（Kiro 不报告缓存命中，恒为 0）
+pub fn add_cache_usage_fields(usage: &mut serde_json::Value) {
+    if let Some(usage) = usage.as_object_mut() {
+        usage.insert("cache_creation_input_tokens".to_string(), json!(0));
+        usage.insert("cache_read_input_tokens".to_string(), json!(0));
+    }
+}
+
 /// 缓冲流处理上下文 - 用于 /cc/v1/messages 流式请求
 ///
 /// 与 `StreamContext` 不同，此上下文会缓冲所有事件直到流结束，
@@ -1103,6 +1614,42 @@ impl BufferedStreamContext {
         }
     }
 
+    /// 设置工具输入校验器
+    pub fn with_tool_validator(mut self, validator: Option<ToolInputValidator>) -> Self {
+        self.inner = self.inner.with_tool_validator(validator);
+        self
+    }
+
+    /// 设置是否修复损坏的工具参数 JSON
+    pub fn with_tool_input_recovery(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_tool_input_recovery(enabled);
+        self
+    }
+
+    /// 设置文本后处理过滤器
+    pub fn with_text_filter(mut self, filter: Option<TextFilterStream>) -> Self {
+        self.inner = self.inner.with_text_filter(filter);
+        self
+    }
+
+    /// 设置用户用量记录器
+    pub fn with_usage_recorder(mut self, recorder: Option<UserUsageRecorder>) -> Self {
+        self.inner = self.inner.with_usage_recorder(recorder);
+        self
+    }
+
+    /// 设置是否在 usage 中补充缓存相关字段
+    pub fn with_cache_usage(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_cache_usage(enabled);
+        self
+    }
+
+    /// 设置请求的 max_tokens
+    pub fn with_max_tokens(mut self, max_tokens: Option<i32>) -> Self {
+        self.inner = self.inner.with_max_tokens(max_tokens);
+        self
+    }
+
     /// 处理 Kiro 事件并缓冲结果
     ///
     /// 复用 StreamContext 的事件处理逻辑，但把结果缓存而不是立即发送。
@@ -1119,6 +1666,11 @@ impl BufferedStreamContext {
         self.event_buffer.extend(events);
     }
 
+    /// 上游是否中途返回了异常 / 错误
+    pub fn upstream_failed(&self) -> bool {
+        self.inner.upstream_failed()
+    }
+
     /// 完成流处理并返回所有事件
     ///
     /// 此方法会：
@@ -1193,6 +1745,55 @@ mod tests {
         assert!(sse_str.ends_with("\n\n"));
     }
 
+    #[test]
+    fn test_upstream_exception_ends_stream_with_error_event() {
+        use crate::kiro::parser::exception::KiroException;
+
+        let mut ctx = StreamContext::new_with_thinking("claude-sonnet-4", 1, false);
+        ctx.generate_initial_events();
+        ctx.process_assistant_response("partial");
+
+        let events = ctx.process_kiro_event(&Event::Exception(KiroException {
+            exception_type: "ThrottlingException".to_string(),
+            message: "Too many requests".to_string(),
+            request_id: None,
+        }));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "error");
+        assert_eq!(events[0].data["error"]["type"], "rate_limit_error");
+        assert!(ctx.upstream_failed());
+
+        // 后续事件被忽略，也不再生成 message_stop
+        let more: crate::kiro::model::events::AssistantResponseEvent =
+            serde_json::from_value(json!({ "content": "more" })).unwrap();
+        assert!(
+            ctx.process_kiro_event(&Event::AssistantResponse(more))
+                .is_empty()
+        );
+        assert!(ctx.generate_final_events().is_empty());
+    }
+
+    #[test]
+    fn test_content_length_exception_is_not_an_error() {
+        use crate::kiro::parser::exception::{CONTENT_LENGTH_EXCEEDED_EXCEPTION, KiroException};
+
+        let mut ctx = StreamContext::new_with_thinking("claude-sonnet-4", 1, false);
+        ctx.generate_initial_events();
+        let events = ctx.process_kiro_event(&Event::Exception(KiroException {
+            exception_type: CONTENT_LENGTH_EXCEEDED_EXCEPTION.to_string(),
+            message: String::new(),
+            request_id: None,
+        }));
+        assert!(events.is_empty());
+        assert!(!ctx.upstream_failed());
+        let final_events = ctx.generate_final_events();
+        let delta = final_events
+            .iter()
+            .find(|e| e.event == "message_delta")
+            .unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "max_tokens");
+    }
+
     #[test]
     fn test_sse_state_manager_message_start() {
         let mut manager = SseStateManager::new();
@@ -1227,6 +1828,507 @@ mod tests {
         assert!(event.is_none());
     }
 
+    fn validated_ctx(policy: crate::model::config::ToolInputValidationPolicy) -> StreamContext {
+        let tool: crate::anthropic::types::Tool = serde_json::from_value(serde_json::json!({
+            "name": "counter",
+            "description": "",
+            "input_schema": {
+                "type": "object",
+                "properties": { "count": { "type": "integer" } },
+                "required": ["count"]
+            }
+        }))
+        .unwrap();
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false)
+            .with_tool_validator(Some(ToolInputValidator::new(policy, &[tool])));
+        ctx.generate_initial_events();
+        ctx
+    }
+
+    fn tool_chunk(input: &str, stop: bool) -> crate::kiro::model::events::ToolUseEvent {
+        crate::kiro::model::events::ToolUseEvent {
+            name: "counter".to_string(),
+            tool_use_id: "tool_1".to_string(),
+            input: input.to_string(),
+            stop,
+        }
+    }
+
+    fn input_deltas(events: &[SseEvent]) -> Vec<String> {
+        events
+            .iter()
+            .filter(|e| e.data["delta"]["type"] == "input_json_delta")
+            .map(|e| {
+                e.data["delta"]["partial_json"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tool_input_validation_coerces_on_stop() {
+        let mut ctx = validated_ctx(crate::model::config::ToolInputValidationPolicy::Coerce);
+
+        // 未完成前不输出增量
+        let first = ctx.process_tool_use(&tool_chunk("{\"count\":", false));
+        assert!(input_deltas(&first).is_empty());
+
+        let last = ctx.process_tool_use(&tool_chunk("\"5\"}", true));
+        assert_eq!(input_deltas(&last), vec!["{\"count\":5}"]);
+        let tool_index = ctx.tool_block_indices["tool_1"];
+        let stop = last
+            .iter()
+            .find(|e| e.event == "content_block_stop" && e.data["index"] == tool_index)
+            .unwrap();
+        assert!(stop.data.get("is_error").is_none());
+    }
+
+    #[test]
+    fn test_tool_input_validation_annotates_stop_event() {
+        let mut ctx = validated_ctx(crate::model::config::ToolInputValidationPolicy::Annotate);
+
+        let events = ctx.process_tool_use(&tool_chunk("{\"count\":\"many\"}", true));
+        assert_eq!(input_deltas(&events), vec!["{\"count\":\"many\"}"]);
+        let tool_index = ctx.tool_block_indices["tool_1"];
+        let stop = events
+            .iter()
+            .find(|e| e.event == "content_block_stop" && e.data["index"] == tool_index)
+            .unwrap();
+        assert_eq!(stop.data["is_error"], true);
+        assert_eq!(stop.data["validation_errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_tool_input_validation_flushes_unfinished_input() {
+        let mut ctx = validated_ctx(crate::model::config::ToolInputValidationPolicy::Warn);
+
+        ctx.process_tool_use(&tool_chunk("{\"count\":1", false));
+        let final_events = ctx.generate_final_events();
+        assert_eq!(input_deltas(&final_events), vec!["{\"count\":1"]);
+    }
+
+    fn filtered_ctx(filters: &[crate::model::config::TextFilterConfig]) -> StreamContext {
+        let filters = super::super::post_processing::TextFilters::compile(filters).unwrap();
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false)
+            .with_text_filter(Some(filters.stream()));
+        ctx.generate_initial_events();
+        ctx
+    }
+
+    fn text_deltas(events: &[SseEvent]) -> String {
+        events
+            .iter()
+            .filter(|e| e.data["delta"]["type"] == "text_delta")
+            .map(|e| e.data["delta"]["text"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_text_filter_replaces_across_chunks() {
+        let mut ctx = filtered_ctx(&[crate::model::config::TextFilterConfig::Regex {
+            pattern: "Kiro".to_string(),
+            replacement: "Claude".to_string(),
+            first_match_only: false,
+        }]);
+
+        let mut events = ctx.process_assistant_response("I am Ki");
+        events.extend(ctx.process_assistant_response("ro."));
+        events.extend(ctx.generate_final_events());
+        assert_eq!(text_deltas(&events), "I am Claude.");
+    }
+
+    #[test]
+    fn test_prefill_echo_is_not_re_emitted() {
+        use super::super::post_processing::TextFilterStream;
+
+        let filter = TextFilterStream::default().with_prefill_echo("The capital of France is Par");
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_text_filter(Some(filter));
+        ctx.generate_initial_events();
+
+        let mut events = ctx.process_assistant_response("Pa");
+        events.extend(ctx.process_assistant_response("ris."));
+        events.extend(ctx.generate_final_events());
+        assert_eq!(text_deltas(&events), "is.");
+
+        let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "end_turn");
+    }
+
+    #[test]
+    fn test_json_output_strips_fences_split_across_deltas() {
+        use super::super::post_processing::TextFilterStream;
+
+        let filter = TextFilterStream::default().with_json_output();
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_text_filter(Some(filter));
+        ctx.generate_initial_events();
+
+        let mut events = Vec::new();
+        for chunk in ["``", "`json\n{\"a\"", ": 1}\n`", "``"] {
+            events.extend(ctx.process_assistant_response(chunk));
+        }
+        events.extend(ctx.generate_final_events());
+        assert_eq!(text_deltas(&events), "{\"a\": 1}");
+
+        let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+        assert!(delta.data.get("degraded").is_none());
+    }
+
+    #[test]
+    fn test_json_output_invalid_marks_message_delta_degraded() {
+        use super::super::post_processing::TextFilterStream;
+
+        let filter = TextFilterStream::default().with_json_output();
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_text_filter(Some(filter));
+        ctx.generate_initial_events();
+
+        let mut events = ctx.process_assistant_response("Sure, here it is: ");
+        events.extend(ctx.process_assistant_response("{\"a\": 1}"));
+        events.extend(ctx.generate_final_events());
+
+        let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+        assert_eq!(delta.data["degraded"], "json-output");
+    }
+
+    #[test]
+    fn test_text_filter_strips_prefix_in_first_text_block_only() {
+        let mut ctx = filtered_ctx(&[
+            crate::model::config::TextFilterConfig::StripPrefix {
+                prefixes: vec!["Sure!".to_string()],
+            },
+            crate::model::config::TextFilterConfig::Regex {
+                pattern: "Kiro".to_string(),
+                replacement: "Claude".to_string(),
+                first_match_only: false,
+            },
+        ]);
+
+        let mut events = ctx.process_assistant_response("Sure! Let me check.");
+        let tool_events = ctx.process_tool_use(&tool_chunk("{}", true));
+        // 正则阶段暂存的文本必须在 tool_use 块开始前输出
+        let tool_start = tool_events
+            .iter()
+            .position(|e| e.event == "content_block_start")
+            .unwrap();
+        assert!(
+            tool_events[..tool_start]
+                .iter()
+                .any(|e| e.data["delta"]["type"] == "text_delta")
+        );
+        events.extend(tool_events);
+        events.extend(ctx.process_assistant_response("Sure! Done."));
+        events.extend(ctx.generate_final_events());
+        assert_eq!(text_deltas(&events), "Let me check.Sure! Done.");
+    }
+
+    /// 将 SSE 事件序列压缩为便于比对的文本记录
+    fn transcript(events: &[SseEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| {
+                let d = &e.data;
+                match e.event.as_str() {
+                    "content_block_start" => format!(
+                        "start {} {}",
+                        d["index"],
+                        d["content_block"]["type"].as_str().unwrap()
+                    ),
+                    "content_block_delta" => {
+                        let delta = &d["delta"];
+                        let body = delta
+                            .get("text")
+                            .or_else(|| delta.get("thinking"))
+                            .or_else(|| delta.get("partial_json"))
+                            .and_then(|v| v.as_str());
+                        match delta.get("signature") {
+                            Some(signature) => format!("signature {} {}", d["index"], signature),
+                            None => format!("delta {} {:?}", d["index"], body.unwrap()),
+                        }
+                    }
+                    "content_block_stop" => format!("stop {}", d["index"]),
+                    "message_delta" => format!(
+                        "message_delta {}",
+                        d["delta"]["stop_reason"].as_str().unwrap()
+                    ),
+                    other => other.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    fn tool(
+        id: &str,
+        name: &str,
+        input: &str,
+        stop: bool,
+    ) -> crate::kiro::model::events::ToolUseEvent {
+        crate::kiro::model::events::ToolUseEvent {
+            name: name.to_string(),
+            tool_use_id: id.to_string(),
+            input: input.to_string(),
+            stop,
+        }
+    }
+
+    #[test]
+    fn test_golden_text_then_two_tool_uses() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_assistant_response("Let me check."));
+        events.extend(ctx.process_tool_use(&tool("a", "read", "{\"path\":", false)));
+        events.extend(ctx.process_tool_use(&tool("a", "read", "\"x\"}", true)));
+        events.extend(ctx.process_tool_use(&tool("b", "grep", "{}", true)));
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 text",
+                "delta 0 \"Let me check.\"",
+                "stop 0",
+                "start 1 tool_use",
+                "delta 1 \"{\\\"path\\\":\"",
+                "delta 1 \"\\\"x\\\"}\"",
+                "stop 1",
+                "start 2 tool_use",
+                "delta 2 \"{}\"",
+                "stop 2",
+                "message_delta tool_use",
+                "message_stop",
+            ]
+        );
+    }
+
+    fn reasoning(text: &str, signature: Option<&str>, redacted: Option<&str>) -> Event {
+        Event::ReasoningContent(ReasoningContentEvent {
+            text: text.to_string(),
+            signature: signature.map(str::to_string),
+            redacted_content: redacted.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn test_golden_interleaved_reasoning_with_signatures() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
+        let mut events = ctx.generate_initial_events();
+        for event in [
+            reasoning("I should ", None, None),
+            reasoning("read it.", Some("sig-1"), None),
+            reasoning("", None, Some("ZW5j")),
+            Event::ToolUse(tool("a", "read", "{}", true)),
+            reasoning("Now answer.", Some("sig-2"), None),
+            Event::AssistantResponse(serde_json::from_value(json!({"content": "Done"})).unwrap()),
+        ] {
+            events.extend(ctx.process_kiro_event(&event));
+        }
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 thinking",
+                "delta 0 \"I should \"",
+                "delta 0 \"read it.\"",
+                "signature 0 \"sig-1\"",
+                "stop 0",
+                "start 1 redacted_thinking",
+                "stop 1",
+                "start 2 tool_use",
+                "delta 2 \"{}\"",
+                "stop 2",
+                "start 3 thinking",
+                "delta 3 \"Now answer.\"",
+                "signature 3 \"sig-2\"",
+                "stop 3",
+                "start 4 text",
+                "delta 4 \"Done\"",
+                "stop 4",
+                "message_delta tool_use",
+                "message_stop",
+            ]
+        );
+        let redacted = events
+            .iter()
+            .find(|e| e.data["content_block"]["type"] == "redacted_thinking")
+            .unwrap();
+        assert_eq!(redacted.data["content_block"]["data"], "ZW5j");
+    }
+
+    #[test]
+    fn test_reasoning_block_without_signature_closes_before_text() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_kiro_event(&reasoning("plan", None, None)));
+        let text = serde_json::from_value(json!({"content": "Hi"})).unwrap();
+        events.extend(ctx.process_kiro_event(&Event::AssistantResponse(text)));
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 thinking",
+                "delta 0 \"plan\"",
+                "stop 0",
+                "start 1 text",
+                "delta 1 \"Hi\"",
+                "stop 1",
+                "message_delta end_turn",
+                "message_stop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_golden_thinking_then_tool_use() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_assistant_response("<thinking>\nplan</thinking>\n\n"));
+        events.extend(ctx.process_tool_use(&tool("a", "read", "{}", true)));
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 thinking",
+                "delta 0 \"plan\"",
+                "delta 0 \"\"",
+                "stop 0",
+                "start 1 tool_use",
+                "delta 1 \"{}\"",
+                "stop 1",
+                "message_delta tool_use",
+                "message_stop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_golden_three_tool_uses_back_to_back() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
+        let mut events = ctx.generate_initial_events();
+        for id in ["a", "b", "c"] {
+            events.extend(ctx.process_tool_use(&tool(id, "read", "{}", true)));
+        }
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 text",
+                "stop 0",
+                "start 1 tool_use",
+                "delta 1 \"{}\"",
+                "stop 1",
+                "start 2 tool_use",
+                "delta 2 \"{}\"",
+                "stop 2",
+                "start 3 tool_use",
+                "delta 3 \"{}\"",
+                "stop 3",
+                "message_delta tool_use",
+                "message_stop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_input_recovery_repairs_and_degrades() {
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_tool_input_recovery(true);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_tool_use(&tool("a", "read", "{\"path\":", false)));
+        events.extend(ctx.process_tool_use(&tool("a", "read", "\"x", true)));
+        events.extend(ctx.process_tool_use(&tool("b", "grep", "{\"q\":1]", true)));
+        // 流结束时未完成的工具调用同样尝试修复
+        events.extend(ctx.process_tool_use(&tool("c", "list", "{\"dir\":[", false)));
+        events.extend(ctx.generate_final_events());
+
+        let degraded = degraded_tool_text("b", "grep", "{\"q\":1]");
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start".to_string(),
+                "start 0 text".to_string(),
+                "stop 0".to_string(),
+                "start 1 tool_use".to_string(),
+                "delta 1 \"{\\\"path\\\":\\\"x\\\"}\"".to_string(),
+                "stop 1".to_string(),
+                "start 2 text".to_string(),
+                format!("delta 2 {:?}", degraded),
+                "stop 2".to_string(),
+                "start 3 tool_use".to_string(),
+                "delta 3 \"{\\\"dir\\\":[]}\"".to_string(),
+                "stop 3".to_string(),
+                "message_delta tool_use".to_string(),
+                "message_stop".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_input_recovery_only_degraded_is_not_tool_use() {
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_tool_input_recovery(true);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_tool_use(&tool("a", "read", "}", true)));
+        events.extend(ctx.generate_final_events());
+
+        let stop_reason = events
+            .iter()
+            .find(|e| e.event == "message_delta")
+            .map(|e| e.data["delta"]["stop_reason"].clone());
+        assert_eq!(stop_reason, Some(json!("end_turn")));
+        assert!(
+            !events
+                .iter()
+                .any(|e| e.data["content_block"]["type"] == "tool_use")
+        );
+    }
+
+    #[test]
+    fn test_interleaved_tool_use_deltas_route_to_own_block() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_tool_use(&tool("a", "read", "{\"p\":", false)));
+        events.extend(ctx.process_tool_use(&tool("b", "grep", "{\"q\":", false)));
+        events.extend(ctx.process_tool_use(&tool("a", "read", "1}", true)));
+        events.extend(ctx.process_tool_use(&tool("b", "grep", "2}", false)));
+        events.extend(ctx.process_tool_use(&tool("c", "list", "{", false)));
+        // 流结束时未完成的块按索引顺序关闭
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            transcript(&events),
+            vec![
+                "message_start",
+                "start 0 text",
+                "stop 0",
+                "start 1 tool_use",
+                "delta 1 \"{\\\"p\\\":\"",
+                "start 2 tool_use",
+                "delta 2 \"{\\\"q\\\":\"",
+                "delta 1 \"1}\"",
+                "stop 1",
+                "delta 2 \"2}\"",
+                "start 3 tool_use",
+                "delta 3 \"{\"",
+                "stop 2",
+                "stop 3",
+                "message_delta tool_use",
+                "message_stop",
+            ]
+        );
+    }
+
     #[test]
     fn test_text_delta_after_tool_use_restarts_text_block() {
         let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
@@ -1892,4 +2994,74 @@ mod tests {
             "stop_reason should be tool_use when tool_use is present"
         );
     }
+
+    /// 处理一段文本后返回最终 message_delta 中的 stop_reason
+    fn final_stop_reason(ctx: &mut StreamContext, text: &str) -> serde_json::Value {
+        let _initial_events = ctx.generate_initial_events();
+        let mut events = ctx.process_assistant_response(text);
+        events.extend(ctx.generate_final_events());
+        events
+            .iter()
+            .find(|e| e.event == "message_delta")
+            .expect("should have message_delta event")
+            .data["delta"]["stop_reason"]
+            .clone()
+    }
+
+    #[test]
+    fn test_push_tool_input_is_bounded() {
+        let mut buffer = "x".repeat(MAX_BUFFERED_TOOL_INPUT_BYTES - 1);
+        push_tool_input(&mut buffer, "é{}", "tool-1");
+        assert_eq!(buffer.len(), MAX_BUFFERED_TOOL_INPUT_BYTES - 1);
+        buffer.pop();
+        push_tool_input(&mut buffer, "é", "tool-1");
+        assert_eq!(buffer.len(), MAX_BUFFERED_TOOL_INPUT_BYTES);
+        push_tool_input(&mut buffer, "{}", "tool-1");
+        assert_eq!(buffer.len(), MAX_BUFFERED_TOOL_INPUT_BYTES);
+    }
+
+    #[test]
+    fn test_reaches_max_tokens_tolerance() {
+        assert!(reaches_max_tokens(100, 100));
+        assert!(reaches_max_tokens(95, 100));
+        assert!(!reaches_max_tokens(94, 100));
+        assert!(reaches_max_tokens(120, 100));
+        assert!(!reaches_max_tokens(10, 0));
+    }
+
+    #[test]
+    fn test_stream_ending_at_budget_sets_max_tokens_stop_reason() {
+        // 40 个 ASCII 字符估算为 10 tokens，恰好达到预算
+        let text = "abcd".repeat(10);
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_max_tokens(Some(10));
+        assert_eq!(final_stop_reason(&mut ctx, &text), "max_tokens");
+
+        // 远低于预算时保持 end_turn
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_max_tokens(Some(1000));
+        assert_eq!(final_stop_reason(&mut ctx, &text), "end_turn");
+
+        // 未设置 max_tokens 时不做判断
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
+        assert_eq!(final_stop_reason(&mut ctx, &text), "end_turn");
+    }
+
+    #[test]
+    fn test_buffered_stream_ending_at_budget_sets_max_tokens_stop_reason() {
+        use crate::kiro::model::events::Event;
+
+        for (max_tokens, expected) in [(10, "max_tokens"), (1000, "end_turn")] {
+            let mut ctx = BufferedStreamContext::new("test-model", 1, false)
+                .with_max_tokens(Some(max_tokens));
+            let event = serde_json::from_value(json!({ "content": "abcd".repeat(10) })).unwrap();
+            ctx.process_and_buffer(&Event::AssistantResponse(event));
+            let events = ctx.finish_and_get_all_events();
+            let message_delta = events
+                .iter()
+                .find(|e| e.event == "message_delta")
+                .expect("should have message_delta event");
+            assert_eq!(message_delta.data["delta"]["stop_reason"], expected);
+        }
+    }
 }
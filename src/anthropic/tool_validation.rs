@@ -0,0 +1,274 @@
+//! 工具输入校验
+//!
+//! 按请求中声明的 `input_schema` 校验上游返回的 tool_use 输入，
+//! 并根据配置的 [`ToolInputValidationPolicy`] 决定如何处理不符合 schema 的输入。
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::model::config::ToolInputValidationPolicy;
+
+use super::json_repair::repair_json;
+use super::schema_validator::{self, ValidationError};
+use super::types::Tool;
+
+/// 非流式响应中存在未通过校验的工具输入时附加的响应头（warn 策略）
+pub const TOOL_INPUT_VALIDATION_HEADER: &str = "x-tool-input-validation";
+
+/// 非流式响应被降级时附加的响应头，值为降级原因：
+/// `tool-input`（工具调用无法修复、已降级为文本）、`json-output`（JSON 模式输出不合法）、
+/// `response-size`（上游响应超过 maxResponseBytes 被截断）
+pub const DEGRADED_RESPONSE_HEADER: &str = "x-kiro-degraded";
+
+/// 工具输入 JSON 的恢复结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum ToolInputRecovery {
+    /// 原样可解析（空输入视为 `{}`，原样保留）
+    Valid(String),
+    /// 修复后可解析
+    Repaired(String),
+    /// 无法修复，应降级为文本块输出
+    Unrecoverable,
+}
+
+/// 检查完整的工具输入是否为合法 JSON，不合法时尝试修复
+pub fn recover_tool_input(tool_use_id: &str, name: &str, raw_input: &str) -> ToolInputRecovery {
+    if raw_input.trim().is_empty() || serde_json::from_str::<Value>(raw_input).is_ok() {
+        return ToolInputRecovery::Valid(raw_input.to_string());
+    }
+    match repair_json(raw_input) {
+        Some(repaired) => {
+            tracing::warn!(
+                tool_use_id = %tool_use_id,
+                tool = %name,
+                "工具输入 JSON 损坏，已自动修复"
+            );
+            ToolInputRecovery::Repaired(repaired)
+        }
+        None => {
+            tracing::warn!(
+                tool_use_id = %tool_use_id,
+                tool = %name,
+                raw_input = %raw_input,
+                "工具输入 JSON 损坏且无法修复，降级为文本输出"
+            );
+            ToolInputRecovery::Unrecoverable
+        }
+    }
+}
+
+/// 无法修复的工具调用降级后输出的文本（附带原始参数）
+pub fn degraded_tool_text(tool_use_id: &str, name: &str, raw_input: &str) -> String {
+    format!(
+        "\n[Tool call `{}` ({}) was dropped: its arguments were not valid JSON and could not be repaired. Raw arguments follow.]\n{}\n",
+        name, tool_use_id, raw_input
+    )
+}
+
+/// 单次工具输入校验结果
+#[derive(Debug)]
+pub struct ToolInputCheck {
+    /// 最终输出的输入 JSON 字符串（coerce 策略下可能已被修正）
+    pub input: String,
+    /// 处理后仍然存在的校验错误
+    pub errors: Vec<ValidationError>,
+}
+
+impl ToolInputCheck {
+    /// 是否需要在 tool_use 块上标注错误（仅 annotate 策略）
+    pub fn annotation(&self, policy: ToolInputValidationPolicy) -> Option<Value> {
+        if policy != ToolInputValidationPolicy::Annotate || self.errors.is_empty() {
+            return None;
+        }
+        Some(Value::Array(
+            self.errors
+                .iter()
+                .map(|e| Value::String(e.to_string()))
+                .collect(),
+        ))
+    }
+}
+
+/// 工具输入校验器（按请求构建）
+#[derive(Debug)]
+pub struct ToolInputValidator {
+    policy: ToolInputValidationPolicy,
+    /// 工具名称 -> input_schema
+    schemas: HashMap<String, Value>,
+}
+
+impl ToolInputValidator {
+    /// 从请求的工具列表构建校验器（没有 input_schema 的工具不做校验）
+    pub fn new(policy: ToolInputValidationPolicy, tools: &[Tool]) -> Self {
+        let schemas = tools
+            .iter()
+            .filter(|t| !t.input_schema.is_empty())
+            .map(|t| {
+                let schema = Value::Object(
+                    t.input_schema
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                );
+                (t.name.clone(), schema)
+            })
+            .collect();
+        Self { policy, schemas }
+    }
+
+    pub fn policy(&self) -> ToolInputValidationPolicy {
+        self.policy
+    }
+
+    /// 校验一次完整的工具输入，并按策略返回最终输出
+    pub fn check(&self, tool_use_id: &str, name: &str, raw_input: &str) -> ToolInputCheck {
+        let Some(schema) = self.schemas.get(name) else {
+            return ToolInputCheck {
+                input: raw_input.to_string(),
+                errors: Vec::new(),
+            };
+        };
+
+        let parsed = if raw_input.trim().is_empty() {
+            Ok(Value::Object(Default::default()))
+        } else {
+            serde_json::from_str::<Value>(raw_input)
+        };
+
+        let (input, errors, coerced) = match parsed {
+            Ok(value) => {
+                let errors = schema_validator::validate(schema, &value);
+                if errors.is_empty() || self.policy != ToolInputValidationPolicy::Coerce {
+                    (raw_input.to_string(), errors, false)
+                } else {
+                    let fixed = schema_validator::coerce(schema, &value);
+                    let coerced = fixed != value;
+                    (
+                        fixed.to_string(),
+                        schema_validator::validate(schema, &fixed),
+                        coerced,
+                    )
+                }
+            }
+            Err(e) => (
+                raw_input.to_string(),
+                vec![ValidationError {
+                    path: String::new(),
+                    message: format!("输入不是合法的 JSON: {}", e),
+                }],
+                false,
+            ),
+        };
+
+        if errors.is_empty() {
+            tracing::debug!(
+                tool_use_id = %tool_use_id,
+                tool = %name,
+                coerced,
+                validation = "passed",
+                "工具输入通过 schema 校验"
+            );
+        } else {
+            let joined = errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            tracing::warn!(
+                tool_use_id = %tool_use_id,
+                tool = %name,
+                policy = ?self.policy,
+                coerced,
+                validation = "failed",
+                errors = %joined,
+                "工具输入未通过 schema 校验"
+            );
+        }
+
+        ToolInputCheck { input, errors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_recover_tool_input() {
+        assert_eq!(
+            recover_tool_input("t1", "read", r#"{"path":"a"}"#),
+            ToolInputRecovery::Valid(r#"{"path":"a"}"#.to_string())
+        );
+        assert_eq!(
+            recover_tool_input("t1", "read", ""),
+            ToolInputRecovery::Valid(String::new())
+        );
+        assert_eq!(
+            recover_tool_input("t1", "read", r#"{"path":"a"#),
+            ToolInputRecovery::Repaired(r#"{"path":"a"}"#.to_string())
+        );
+        assert_eq!(
+            recover_tool_input("t1", "read", r#"{"path":"a"]"#),
+            ToolInputRecovery::Unrecoverable
+        );
+    }
+
+    fn tools() -> Vec<Tool> {
+        let schema = json!({
+            "type": "object",
+            "properties": { "count": { "type": "integer" } },
+            "required": ["count"]
+        });
+        vec![Tool {
+            tool_type: None,
+            name: "counter".to_string(),
+            description: String::new(),
+            input_schema: serde_json::from_value(schema).unwrap(),
+            max_uses: None,
+        }]
+    }
+
+    #[test]
+    fn test_unknown_tool_passes_through() {
+        let validator = ToolInputValidator::new(ToolInputValidationPolicy::Coerce, &tools());
+        let check = validator.check("t1", "other", "{\"x\":1}");
+        assert_eq!(check.input, "{\"x\":1}");
+        assert!(check.errors.is_empty());
+    }
+
+    #[test]
+    fn test_warn_keeps_input() {
+        let validator = ToolInputValidator::new(ToolInputValidationPolicy::Warn, &tools());
+        let check = validator.check("t1", "counter", "{\"count\":\"3\"}");
+        assert_eq!(check.input, "{\"count\":\"3\"}");
+        assert_eq!(check.errors.len(), 1);
+        assert!(check.annotation(ToolInputValidationPolicy::Warn).is_none());
+    }
+
+    #[test]
+    fn test_coerce_fixes_input() {
+        let validator = ToolInputValidator::new(ToolInputValidationPolicy::Coerce, &tools());
+        let check = validator.check("t1", "counter", "{\"count\":\"3\"}");
+        assert_eq!(check.input, "{\"count\":3}");
+        assert!(check.errors.is_empty());
+    }
+
+    #[test]
+    fn test_annotate_reports_errors() {
+        let validator = ToolInputValidator::new(ToolInputValidationPolicy::Annotate, &tools());
+        let check = validator.check("t1", "counter", "");
+        assert_eq!(check.input, "");
+        let annotation = check.annotation(validator.policy()).unwrap();
+        assert_eq!(annotation, json!(["/: 缺少必填字段 \"count\""]));
+    }
+
+    #[test]
+    fn test_invalid_json_is_reported() {
+        let validator = ToolInputValidator::new(ToolInputValidationPolicy::Coerce, &tools());
+        let check = validator.check("t1", "counter", "{\"count\":");
+        assert_eq!(check.input, "{\"count\":");
+        assert_eq!(check.errors.len(), 1);
+    }
+}
@@ -111,8 +111,22 @@ pub struct Metadata {
     pub user_id: Option<String>,
 }
 
+/// 输出格式约束（非 Anthropic 标准字段，兼容 OpenAI 风格的 `response_format`）
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+}
+
+impl ResponseFormat {
+    /// 是否要求输出 JSON（`json_object`）
+    pub fn is_json_object(&self) -> bool {
+        self.format_type == "json_object"
+    }
+}
+
 /// Messages 请求体
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MessagesRequest {
     pub model: String,
     pub max_tokens: i32,
@@ -127,6 +141,15 @@ pub struct MessagesRequest {
     pub output_config: Option<OutputConfig>,
     /// Claude Code 请求中的 metadata，包含 session 信息
     pub metadata: Option<Metadata>,
+    /// 输出格式约束（`{"type": "json_object"}` 时启用 JSON 模式）
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// 采样温度（0 ~ 2），上游不支持，按 `unsupportedParamsPolicy` 处理
+    pub temperature: Option<f64>,
+    /// 核采样概率（0 ~ 1），上游不支持，按 `unsupportedParamsPolicy` 处理
+    pub top_p: Option<f64>,
+    /// Top-K 采样（不小于 0），上游不支持，按 `unsupportedParamsPolicy` 处理
+    pub top_k: Option<i64>,
 }
 
 /// 反序列化 system 字段，支持字符串或数组格式
@@ -244,6 +267,12 @@ pub struct ContentBlock {
     pub text: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking: Option<String>,
+    /// thinking 块签名（多轮工具调用时客户端需原样回传）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// redacted_thinking 块的加密内容
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_use_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -291,3 +320,35 @@ pub struct CountTokensRequest {
 pub struct CountTokensResponse {
     pub input_tokens: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_thinking_block_signature_round_trip() {
+        let value = json!({
+            "type": "thinking",
+            "thinking": "I should read the file.",
+            "signature": "EqQBCgIYAhIM1gbcDa9GJwZA2b3h"
+        });
+        let block: ContentBlock = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(
+            block.signature.as_deref(),
+            Some("EqQBCgIYAhIM1gbcDa9GJwZA2b3h")
+        );
+        assert_eq!(serde_json::to_value(&block).unwrap(), value);
+    }
+
+    #[test]
+    fn test_redacted_thinking_block_round_trip() {
+        let value = json!({
+            "type": "redacted_thinking",
+            "data": "EmwKAhgBEgy3va3pzix/LafPsn4a"
+        });
+        let block: ContentBlock = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(block.data.as_deref(), Some("EmwKAhgBEgy3va3pzix/LafPsn4a"));
+        assert_eq!(serde_json::to_value(&block).unwrap(), value);
+    }
+}
@@ -570,6 +570,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         assert!(has_web_search_tool(&req));
@@ -608,6 +612,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         // 多个工具时不应该被识别为纯 websearch 请求
@@ -635,6 +643,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let query = extract_search_query(&req);
@@ -660,6 +672,10 @@ mod tests {
             thinking: None,
             output_config: None,
             metadata: None,
+            response_format: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let query = extract_search_query(&req);
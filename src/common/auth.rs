@@ -1,9 +1,13 @@
 //! 公共认证工具函数
 
+use std::convert::Infallible;
+use std::str::FromStr;
+
 use axum::{
     body::Body,
-    http::{Request, header},
+    http::{HeaderMap, Request, header},
 };
+use sha2::{Digest, Sha256};
 use subtle::ConstantTimeEq;
 
 /// 从请求中提取 API Key
@@ -12,22 +16,21 @@ use subtle::ConstantTimeEq;
 /// - `x-api-key` header
 /// - `Authorization: Bearer <token>` header
 pub fn extract_api_key(request: &Request<Body>) -> Option<String> {
+    api_key_from_headers(request.headers()).map(str::to_string)
+}
+
+/// 从请求头中借用 API Key（不分配，供认证热路径使用）
+pub fn api_key_from_headers(headers: &HeaderMap) -> Option<&str> {
     // 优先检查 x-api-key
-    if let Some(key) = request
-        .headers()
-        .get("x-api-key")
-        .and_then(|v| v.to_str().ok())
-    {
-        return Some(key.to_string());
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key);
     }
 
     // 其次检查 Authorization: Bearer
-    request
-        .headers()
+    headers
         .get(header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
-        .map(|s| s.to_string())
 }
 
 /// 常量时间字符串比较，防止时序攻击
@@ -39,3 +42,167 @@ pub fn extract_api_key(request: &Request<Body>) -> Option<String> {
 pub fn constant_time_eq(a: &str, b: &str) -> bool {
     a.as_bytes().ct_eq(b.as_bytes()).into()
 }
+
+/// 预计算 API Key 的定长表示长度
+const PRECOMPUTED_KEY_LEN: usize = 64;
+
+/// 超过定长表示长度的 Key 改为比较 SHA-256 摘要，长度字段记为该标记
+const HASHED_KEY_MARKER: u8 = u8::MAX;
+
+/// Key 的定长表示：不超过 64 字节时零填充，更长时为 SHA-256 摘要
+#[derive(Clone, Copy)]
+struct KeyRepr {
+    bytes: [u8; PRECOMPUTED_KEY_LEN],
+    len: u8,
+}
+
+impl KeyRepr {
+    fn new(key: &[u8]) -> Self {
+        let mut bytes = [0u8; PRECOMPUTED_KEY_LEN];
+        if key.len() <= PRECOMPUTED_KEY_LEN {
+            bytes[..key.len()].copy_from_slice(key);
+            return Self {
+                bytes,
+                len: key.len() as u8,
+            };
+        }
+        let digest = Sha256::digest(key);
+        bytes[..digest.len()].copy_from_slice(&digest);
+        Self {
+            bytes,
+            len: HASHED_KEY_MARKER,
+        }
+    }
+
+    fn ct_eq(&self, other: &Self) -> bool {
+        (self.bytes.ct_eq(&other.bytes) & self.len.ct_eq(&other.len)).into()
+    }
+}
+
+/// 启动时预先计算的 API Key
+///
+/// 传入的 Key 在栈上转换为同样的定长表示后做常量时间比较，认证热路径不产生堆分配
+#[derive(Clone)]
+pub struct PrecomputedApiKey(KeyRepr);
+
+impl PrecomputedApiKey {
+    /// 常量时间校验传入的 Key
+    pub fn matches(&self, key: &str) -> bool {
+        self.0.ct_eq(&KeyRepr::new(key.as_bytes()))
+    }
+}
+
+impl FromStr for PrecomputedApiKey {
+    type Err = Infallible;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        Ok(Self(KeyRepr::new(key.as_bytes())))
+    }
+}
+
+impl std::fmt::Debug for PrecomputedApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrecomputedApiKey(***)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// 统计当前线程在计数窗口内的堆分配次数
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNTING.with(Cell::get) {
+                ALLOCATIONS.with(|n| n.set(n.get() + 1));
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// 返回执行 `f` 期间当前线程的堆分配次数
+    fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        ALLOCATIONS.with(|n| n.set(0));
+        COUNTING.with(|c| c.set(true));
+        let result = f();
+        COUNTING.with(|c| c.set(false));
+        (result, ALLOCATIONS.with(Cell::get))
+    }
+
+    fn key(value: &str) -> PrecomputedApiKey {
+        let Ok(key) = PrecomputedApiKey::from_str(value);
+        key
+    }
+
+    #[test]
+    fn test_precomputed_key_accepts_and_rejects() {
+        let short = key("sk-test-key");
+        assert!(short.matches("sk-test-key"));
+        assert!(!short.matches("sk-test-kez"));
+        assert!(!short.matches("sk-test-ke"));
+        // 零填充不能让带尾部 NUL 的 Key 通过
+        assert!(!short.matches("sk-test-key\0"));
+        assert!(!short.matches(""));
+
+        let boundary = "k".repeat(PRECOMPUTED_KEY_LEN);
+        assert!(key(&boundary).matches(&boundary));
+        assert!(!key(&boundary).matches(&boundary[1..]));
+
+        let long = "x".repeat(200);
+        assert!(key(&long).matches(&long));
+        assert!(!key(&long).matches(&"x".repeat(199)));
+        assert!(!key(&long).matches(&boundary));
+        assert!(!key(&boundary).matches(&long));
+
+        let empty = key("");
+        assert!(empty.matches(""));
+        assert!(!empty.matches("a"));
+    }
+
+    #[test]
+    fn test_auth_hot_path_is_allocation_free() {
+        let short = key("sk-test-key");
+        let long = key(&"x".repeat(200));
+        let long_provided = "x".repeat(200);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-test-key".parse().unwrap());
+        let mut bearer = HeaderMap::new();
+        bearer.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", long_provided).parse().unwrap(),
+        );
+
+        let (results, allocations) = count_allocations(|| {
+            [
+                api_key_from_headers(&headers).is_some_and(|k| short.matches(k)),
+                api_key_from_headers(&bearer).is_some_and(|k| long.matches(k)),
+                api_key_from_headers(&headers).is_some_and(|k| long.matches(k)),
+            ]
+        });
+        assert_eq!(results, [true, true, false]);
+        assert_eq!(allocations, 0);
+
+        // 计数器本身能观测到分配
+        let (_, allocations) =
+            count_allocations(|| api_key_from_headers(&headers).map(str::to_string));
+        assert!(allocations > 0);
+    }
+}
@@ -0,0 +1,231 @@
+//! 多实例协调锁
+//!
+//! 多个进程共用同一个凭据文件时会互相覆盖刷新后的 Token（refreshToken 单次轮换，
+//! 被覆盖后上游即失效）。启动时在凭据文件旁创建 `kiro.lock`（O_EXCL 方式写入 PID）：
+//! - 锁文件不存在：创建成功即持有锁
+//! - 锁文件存在且持有者进程仍存活：返回持有者 PID，由调用方决定退出或进入从实例模式
+//! - 锁文件存在但持有者进程已退出（崩溃残留）或内容无法解析：视为过期锁，清理后重新获取
+//! - 锁文件中的 PID 与当前进程相同：同样视为过期锁（容器重启后进程 PID 通常仍为 1，
+//!   残留的锁文件记录的正是当前 PID）
+//!
+//! 持有的锁在 [`InstanceLock`] 被 drop 时释放（删除锁文件）。
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 锁文件名（位于凭据文件所在目录）
+pub const LOCK_FILE_NAME: &str = "kiro.lock";
+
+/// 获取锁的结果
+#[derive(Debug)]
+pub enum LockAcquisition {
+    /// 成功持有锁
+    Acquired(InstanceLock),
+    /// 锁已被其他存活实例持有
+    HeldBy(u32),
+}
+
+/// 已持有的实例锁（drop 时删除锁文件）
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// 凭据文件对应的锁文件路径
+    pub fn path_for(credentials_path: &Path) -> PathBuf {
+        credentials_path
+            .parent()
+            .map(|dir| dir.join(LOCK_FILE_NAME))
+            .unwrap_or_else(|| PathBuf::from(LOCK_FILE_NAME))
+    }
+
+    /// 尝试获取锁，持有者进程已退出的过期锁会被清理
+    pub fn try_acquire(path: &Path) -> io::Result<LockAcquisition> {
+        Self::try_acquire_with(path, is_process_alive)
+    }
+
+    fn try_acquire_with(
+        path: &Path,
+        is_alive: impl Fn(u32) -> bool,
+    ) -> io::Result<LockAcquisition> {
+        // 清理过期锁后最多重试一次，避免与其他实例竞争时无限循环
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(std::process::id().to_string().as_bytes())?;
+                    return Ok(LockAcquisition::Acquired(InstanceLock {
+                        path: path.to_path_buf(),
+                    }));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+
+            let holder = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok());
+            match holder {
+                // 当前进程尚未持有锁，记录当前 PID 的锁只能是上次运行（如容器重启前）的残留
+                Some(pid) if pid != std::process::id() && is_alive(pid) => {
+                    return Ok(LockAcquisition::HeldBy(pid));
+                }
+                _ => {
+                    tracing::warn!(
+                        "检测到过期的实例锁 {:?}（持有者 PID: {:?} 已退出），将重新获取",
+                        path,
+                        holder
+                    );
+                    match std::fs::remove_file(path) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("无法获取实例锁 {:?}", path),
+        ))
+    }
+
+    /// 锁文件路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => tracing::info!("已释放实例锁: {:?}", self.path),
+            Err(e) => tracing::warn!("释放实例锁失败: {:?}: {}", self.path, e),
+        }
+    }
+}
+
+/// 检查进程是否存活
+#[cfg(target_os = "linux")]
+fn is_process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// 检查进程是否存活
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// 检查进程是否存活（tasklist 输出中包含该 PID 即存活；命令执行失败时保守地视为存活）
+#[cfg(windows)]
+fn is_process_alive(pid: u32) -> bool {
+    let output = std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .stderr(std::process::Stdio::null())
+        .output();
+    // CSV 行格式: "映像名称","PID","会话名","会话#","内存使用"
+    let quoted_pid = format!("\"{}\"", pid);
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split(',').nth(1) == Some(quoted_pid.as_str())),
+        _ => true,
+    }
+}
+
+/// 检查进程是否存活（无法检测的平台保守地视为存活）
+#[cfg(not(any(unix, windows)))]
+fn is_process_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_lock_path() -> PathBuf {
+        std::env::temp_dir().join(format!("kiro-lock-{}.lock", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let path = temp_lock_path();
+        let lock = match InstanceLock::try_acquire(&path).unwrap() {
+            LockAcquisition::Acquired(lock) => lock,
+            LockAcquisition::HeldBy(pid) => panic!("锁不应被 {} 持有", pid),
+        };
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, std::process::id().to_string());
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_lock_held_by_live_process() {
+        let path = temp_lock_path();
+        std::fs::write(&path, "4242").unwrap();
+
+        let acquired = InstanceLock::try_acquire_with(&path, |pid| pid == 4242).unwrap();
+        assert!(matches!(acquired, LockAcquisition::HeldBy(4242)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lock_with_own_pid_is_reclaimed() {
+        // 容器重启后当前进程与残留锁记录的 PID 相同（通常为 1），即使该 PID 存活也视为过期锁
+        let path = temp_lock_path();
+        std::fs::write(&path, std::process::id().to_string()).unwrap();
+
+        let acquired = InstanceLock::try_acquire_with(&path, |_| true).unwrap();
+        assert!(matches!(acquired, LockAcquisition::Acquired(_)));
+    }
+
+    #[test]
+    fn test_stale_lock_from_dead_pid_is_reclaimed() {
+        let path = temp_lock_path();
+        std::fs::write(&path, "999999999").unwrap();
+
+        let acquired = InstanceLock::try_acquire_with(&path, |_| false).unwrap();
+        assert!(matches!(acquired, LockAcquisition::Acquired(_)));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+
+    #[test]
+    fn test_unparseable_lock_is_reclaimed() {
+        let path = temp_lock_path();
+        std::fs::write(&path, "garbage").unwrap();
+
+        let acquired = InstanceLock::try_acquire_with(&path, |_| true).unwrap();
+        assert!(matches!(acquired, LockAcquisition::Acquired(_)));
+    }
+
+    #[test]
+    fn test_is_process_alive() {
+        assert!(is_process_alive(std::process::id()));
+        #[cfg(any(unix, windows))]
+        assert!(!is_process_alive(999_999_999));
+    }
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            InstanceLock::path_for(Path::new("/data/credentials.json")),
+            PathBuf::from("/data/kiro.lock")
+        );
+        assert_eq!(
+            InstanceLock::path_for(Path::new("credentials.json")),
+            PathBuf::from("kiro.lock")
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! 按来源 IP 限制访问
+//!
+//! `adminAllowedIps` 配置的条目可以是单个 IP 或 CIDR 网段（IPv4 / IPv6），
+//! 配置后 `/api/admin` 与 `/admin` 只允许来自这些地址的请求，其余返回不带详情的 403。
+//! 来源地址默认取 TCP 对端地址；部署在可信反向代理之后时可开启 `trustForwardedFor`，
+//! 改用 `X-Forwarded-For` 的第一跳（缺失或无法解析时回退到对端地址）。
+//! `/v1` 下携带 `X-Admin-Key` 的请求同样受此限制（见 `anthropic::admin_key_allowlist_middleware`）。
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::model::config::Config;
+
+/// 转发链请求头
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// IP 网段（单个 IP 视为 /32 或 /128）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// 解析 `1.2.3.4`、`10.0.0.0/8`、`::1`、`2001:db8::/32` 等格式
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow::anyhow!("无效的 IP 地址: {}", value))?;
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| anyhow::anyhow!("无效的网段前缀长度: {}", value))?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// 地址是否在网段内（IPv4 映射的 IPv6 地址按 IPv4 匹配）
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(net).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 比较两个地址（按 `bits` 位宽）的前 `prefix_len` 位
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix_len);
+    (net >> shift) == (ip >> shift)
+}
+
+/// 来源 IP 允许列表
+#[derive(Debug, Clone)]
+pub struct IpAllowlist {
+    nets: Vec<IpNet>,
+    trust_forwarded_for: bool,
+}
+
+impl IpAllowlist {
+    /// 按配置创建允许列表（`adminAllowedIps` 为空时返回 None，即不限制）
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        if config.admin_allowed_ips.is_empty() {
+            return Ok(None);
+        }
+        let nets = config
+            .admin_allowed_ips
+            .iter()
+            .map(|entry| IpNet::parse(entry))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Some(Self {
+            nets,
+            trust_forwarded_for: config.trust_forwarded_for,
+        }))
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+
+    /// 请求的来源 IP 是否在允许列表中（无法确定来源 IP 时视为不允许）
+    pub fn allows(&self, request: &Request<Body>) -> bool {
+        self.client_ip(request)
+            .is_some_and(|ip| self.is_allowed(ip))
+    }
+
+    /// 确定请求的来源 IP
+    fn client_ip(&self, request: &Request<Body>) -> Option<IpAddr> {
+        if self.trust_forwarded_for {
+            let forwarded = request
+                .headers()
+                .get(FORWARDED_FOR_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .and_then(|hop| hop.trim().parse().ok());
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+}
+
+/// 来源 IP 限制中间件：不在允许列表中的请求返回 403（不附带详情）
+pub async fn ip_allowlist_middleware(
+    State(allowlist): State<Arc<IpAllowlist>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    match allowlist.client_ip(&request) {
+        Some(ip) if allowlist.is_allowed(ip) => next.run(request).await,
+        ip => {
+            tracing::warn!("拒绝来自 {:?} 的管理请求: {}", ip, request.uri().path());
+            StatusCode::FORBIDDEN.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware, routing::get};
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_cidr_boundaries() {
+        let net = IpNet::parse("192.168.1.0/24").unwrap();
+        assert!(net.contains(ip("192.168.1.0")));
+        assert!(net.contains(ip("192.168.1.255")));
+        assert!(!net.contains(ip("192.168.0.255")));
+        assert!(!net.contains(ip("192.168.2.0")));
+        // IPv4 映射的 IPv6 地址按 IPv4 匹配
+        assert!(net.contains(ip("::ffff:192.168.1.10")));
+
+        let odd = IpNet::parse("10.0.0.8/29").unwrap();
+        assert!(odd.contains(ip("10.0.0.8")));
+        assert!(odd.contains(ip("10.0.0.15")));
+        assert!(!odd.contains(ip("10.0.0.7")));
+        assert!(!odd.contains(ip("10.0.0.16")));
+
+        let exact = IpNet::parse("203.0.113.7").unwrap();
+        assert!(exact.contains(ip("203.0.113.7")));
+        assert!(!exact.contains(ip("203.0.113.6")));
+        assert!(!exact.contains(ip("203.0.113.8")));
+
+        let any = IpNet::parse("0.0.0.0/0").unwrap();
+        assert!(any.contains(ip("0.0.0.0")));
+        assert!(any.contains(ip("255.255.255.255")));
+        assert!(!any.contains(ip("::1")));
+    }
+
+    #[test]
+    fn test_ipv6_cidr_boundaries() {
+        let net = IpNet::parse("2001:db8::/32").unwrap();
+        assert!(net.contains(ip("2001:db8::")));
+        assert!(net.contains(ip("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!net.contains(ip("2001:db7:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!net.contains(ip("2001:db9::")));
+        assert!(!net.contains(ip("32.1.13.184")));
+
+        let exact = IpNet::parse("::1").unwrap();
+        assert!(exact.contains(ip("::1")));
+        assert!(!exact.contains(ip("::2")));
+
+        let any = IpNet::parse("::/0").unwrap();
+        assert!(any.contains(ip("ffff::")));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_entries() {
+        for value in [
+            "",
+            "localhost",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/-1",
+            "1.2.3.4/",
+        ] {
+            assert!(IpNet::parse(value).is_err(), "value: {}", value);
+        }
+        assert_eq!(
+            IpNet::parse(" 10.0.0.0/8 ").unwrap(),
+            IpNet::parse("10.0.0.0/8").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_empty_list_disables_allowlist() {
+        let mut config = Config::default();
+        assert!(IpAllowlist::from_config(&config).unwrap().is_none());
+
+        config.admin_allowed_ips = vec!["10.0.0.0/8".to_string(), "bad".to_string()];
+        assert!(IpAllowlist::from_config(&config).is_err());
+    }
+
+    /// 启动带来源 IP 限制的测试服务（对端地址为 127.0.0.1）
+    async fn spawn_restricted(allowed: &[&str], trust_forwarded_for: bool) -> String {
+        let mut config = Config::default();
+        config.admin_allowed_ips = allowed.iter().map(|s| s.to_string()).collect();
+        config.trust_forwarded_for = trust_forwarded_for;
+        let allowlist = Arc::new(IpAllowlist::from_config(&config).unwrap().unwrap());
+
+        let router = Router::new()
+            .route("/credentials", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(
+                allowlist,
+                ip_allowlist_middleware,
+            ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let app = router.into_make_service_with_connect_info::<SocketAddr>();
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}/credentials", addr)
+    }
+
+    async fn status(url: &str, forwarded_for: Option<&str>) -> u16 {
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(value) = forwarded_for {
+            request = request.header(FORWARDED_FOR_HEADER, value);
+        }
+        request.send().await.unwrap().status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn test_middleware_direct_mode_uses_peer_address() {
+        let url = spawn_restricted(&["127.0.0.0/8"], false).await;
+        assert_eq!(status(&url, None).await, 200);
+
+        let url = spawn_restricted(&["203.0.113.0/24"], false).await;
+        assert_eq!(status(&url, None).await, 403);
+        // 未开启 trustForwardedFor 时忽略伪造的 X-Forwarded-For
+        assert_eq!(status(&url, Some("203.0.113.7")).await, 403);
+
+        let resp = reqwest::get(&url).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 403);
+        assert!(resp.text().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_middleware_forwarded_mode_uses_first_hop() {
+        let url = spawn_restricted(&["203.0.113.0/24", "2001:db8::/32"], true).await;
+        assert_eq!(status(&url, Some("203.0.113.7, 10.0.0.1")).await, 200);
+        assert_eq!(status(&url, Some("2001:db8::1")).await, 200);
+        assert_eq!(status(&url, Some("10.0.0.1, 203.0.113.7")).await, 403);
+        // 缺失或无法解析时回退到对端地址（127.0.0.1，不在列表中）
+        assert_eq!(status(&url, None).await, 403);
+        assert_eq!(status(&url, Some("unknown")).await, 403);
+    }
+}
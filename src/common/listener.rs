@@ -0,0 +1,204 @@
+//! 服务监听目标（TCP 地址或 Unix domain socket）
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::model::config::Config;
+
+/// 服务监听目标
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindTarget {
+    /// `host:port`
+    Tcp(String),
+    /// Unix domain socket 文件路径
+    Unix(PathBuf),
+}
+
+impl BindTarget {
+    /// 按配置确定监听目标：设置了 `unixSocketPath` 时优先使用 Unix socket
+    pub fn from_config(config: &Config) -> Self {
+        match &config.unix_socket_path {
+            Some(path) => {
+                let defaults = Config::default();
+                if config.host != defaults.host || config.port != defaults.port {
+                    tracing::warn!(
+                        "同时配置了 unixSocketPath 与 host/port，将监听 Unix socket {}，忽略 {}:{}",
+                        path,
+                        config.host,
+                        config.port
+                    );
+                }
+                Self::Unix(PathBuf::from(path))
+            }
+            None => Self::Tcp(format!("{}:{}", config.host, config.port)),
+        }
+    }
+}
+
+impl fmt::Display for BindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Unix socket 文件守卫：drop 时删除 socket 文件
+#[derive(Debug)]
+pub struct UnixSocketGuard {
+    path: PathBuf,
+}
+
+impl Drop for UnixSocketGuard {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!("删除 Unix socket 文件 {:?} 失败: {}", self.path, e);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 监听 Unix socket
+///
+/// 路径上已存在的 socket 文件（如上次异常退出的残留）会先删除；
+/// 路径被普通文件或目录占用时返回错误，避免误删
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> anyhow::Result<(tokio::net::UnixListener, UnixSocketGuard)> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .map_err(|e| anyhow::anyhow!("删除已存在的 Unix socket {:?} 失败: {}", path, e))?,
+        Ok(_) => anyhow::bail!("{:?} 已存在且不是 Unix socket", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => anyhow::bail!("无法访问 {:?}: {}", path, e),
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| anyhow::anyhow!("监听 Unix socket {:?} 失败: {}", path, e))?;
+    Ok((
+        listener,
+        UnixSocketGuard {
+            path: path.to_path_buf(),
+        },
+    ))
+}
+
+/// 非 Unix 平台不支持 Unix socket
+#[cfg(not(unix))]
+pub fn bind_unix(path: &Path) -> anyhow::Result<(std::convert::Infallible, UnixSocketGuard)> {
+    anyhow::bail!(
+        "当前平台不支持 Unix socket（unixSocketPath: {:?}），请移除该配置并使用 host/port",
+        path
+    )
+}
+
+/// 检查 Unix socket 路径是否可用（不实际监听，供部署自检使用）
+pub fn check_unix_socket_path(path: &Path) -> anyhow::Result<()> {
+    if !cfg!(unix) {
+        anyhow::bail!("当前平台不支持 Unix socket，请移除 unixSocketPath 并使用 host/port");
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        anyhow::bail!("Unix socket 所在目录 {:?} 不存在", parent);
+    }
+    if path.exists() && !is_socket(path) {
+        anyhow::bail!("{:?} 已存在且不是 Unix socket", path);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_socket(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+fn is_socket(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kiro-uds-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_bind_target_prefers_unix_socket() {
+        let mut config = Config::default();
+        assert_eq!(
+            BindTarget::from_config(&config),
+            BindTarget::Tcp("127.0.0.1:8080".to_string())
+        );
+
+        config.port = 9000;
+        config.unix_socket_path = Some("/run/kiro.sock".to_string());
+        let target = BindTarget::from_config(&config);
+        assert_eq!(target, BindTarget::Unix(PathBuf::from("/run/kiro.sock")));
+        assert_eq!(target.to_string(), "unix:/run/kiro.sock");
+    }
+
+    #[tokio::test]
+    async fn test_serve_http_over_unix_socket() {
+        let dir = temp_dir();
+        let path = dir.join("kiro.sock");
+        // 残留的 socket 文件会被替换
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let (listener, guard) = bind_unix(&path).unwrap();
+        let app = axum::Router::new().route("/ping", axum::routing::get(|| async { "pong" }));
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("pong"), "{}", response);
+
+        server.abort();
+        drop(guard);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_path_rejects_regular_file() {
+        let dir = temp_dir();
+        let path = dir.join("kiro.sock");
+        std::fs::write(&path, "not a socket").unwrap();
+
+        assert!(check_unix_socket_path(&path).is_err());
+        let err = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async { bind_unix(&path).map(|_| ()) })
+            .unwrap_err();
+        assert!(err.to_string().contains("不是 Unix socket"), "{}", err);
+        // 普通文件不会被删除
+        assert!(path.exists());
+
+        assert!(check_unix_socket_path(&dir.join("missing/kiro.sock")).is_err());
+        assert!(check_unix_socket_path(&dir.join("new.sock")).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! 日志限流
+//!
+//! 凭据被禁用后客户端仍持续请求时，失败路径上的同一条日志会被反复输出（单日可达数 GB）。
+//! [`log_throttled!`] 按 key 限流：同一 key 在时间窗口内只输出第一条，
+//! 窗口结束后的下一条会附带 `(suppressed N similar messages)` 后缀，报告期间被抑制的条数。
+//!
+//! 限流状态保存在全局表中，过期条目在写入时定期清理。
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// 默认限流窗口
+pub const DEFAULT_LOG_THROTTLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 过期条目的清理间隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// 全局限流状态
+static GLOBAL: LazyLock<LogThrottle> = LazyLock::new(LogThrottle::default);
+
+/// 单个 key 的限流窗口
+struct ThrottleEntry {
+    window_start: Instant,
+    interval: Duration,
+    suppressed: u64,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    entries: HashMap<String, ThrottleEntry>,
+    last_cleanup: Option<Instant>,
+}
+
+/// 按 key 限流的日志状态表
+#[derive(Default)]
+pub struct LogThrottle {
+    state: Mutex<ThrottleState>,
+}
+
+impl LogThrottle {
+    /// 判断本条日志是否应输出
+    ///
+    /// 返回 `Some(n)` 表示应输出（`n` 为上一窗口内被抑制的条数），`None` 表示应抑制
+    pub fn check(&self, key: &str, interval: Duration, now: Instant) -> Option<u64> {
+        let mut state = self.state.lock();
+        let cleanup_due = state
+            .last_cleanup
+            .is_none_or(|last| now.duration_since(last) >= CLEANUP_INTERVAL);
+        if cleanup_due {
+            // 窗口结束后再过一个窗口仍无新日志的条目视为过期（其抑制计数随之丢弃）
+            state
+                .entries
+                .retain(|_, e| now.duration_since(e.window_start) < e.interval * 2);
+            state.last_cleanup = Some(now);
+        }
+
+        match state.entries.get_mut(key) {
+            Some(entry) if now.duration_since(entry.window_start) < entry.interval => {
+                entry.suppressed += 1;
+                None
+            }
+            Some(entry) => {
+                let suppressed = entry.suppressed;
+                entry.window_start = now;
+                entry.interval = interval;
+                entry.suppressed = 0;
+                Some(suppressed)
+            }
+            None => {
+                state.entries.insert(
+                    key.to_string(),
+                    ThrottleEntry {
+                        window_start: now,
+                        interval,
+                        suppressed: 0,
+                    },
+                );
+                Some(0)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+}
+
+/// 使用全局状态判断本条日志是否应输出（见 [`LogThrottle::check`]）
+pub fn check(key: &str, interval: Duration) -> Option<u64> {
+    GLOBAL.check(key, interval, Instant::now())
+}
+
+/// 被抑制条数的日志后缀（无抑制时为空）
+pub fn suppressed_suffix(suppressed: u64) -> String {
+    if suppressed == 0 {
+        String::new()
+    } else {
+        format!(" (suppressed {} similar messages)", suppressed)
+    }
+}
+
+/// 按 key 限流输出日志：`log_throttled!(warn, key, interval, "格式", 参数...)`
+macro_rules! log_throttled {
+    ($level:ident, $key:expr, $interval:expr, $($arg:tt)+) => {
+        if let Some(suppressed) = $crate::common::log_throttle::check(&$key, $interval) {
+            tracing::$level!(
+                "{}{}",
+                format_args!($($arg)+),
+                $crate::common::log_throttle::suppressed_suffix(suppressed)
+            );
+        }
+    };
+}
+
+pub(crate) use log_throttled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_first_message_passes_and_repeats_are_suppressed() {
+        let throttle = LogThrottle::default();
+        let start = Instant::now();
+
+        assert_eq!(throttle.check("a", INTERVAL, start), Some(0));
+        for i in 1..=5 {
+            assert_eq!(
+                throttle.check("a", INTERVAL, start + Duration::from_secs(i)),
+                None
+            );
+        }
+        // 不同 key 互不影响
+        assert_eq!(throttle.check("b", INTERVAL, start), Some(0));
+    }
+
+    #[test]
+    fn test_suppressed_count_reported_when_window_rolls_over() {
+        let throttle = LogThrottle::default();
+        let start = Instant::now();
+
+        assert_eq!(throttle.check("a", INTERVAL, start), Some(0));
+        for _ in 0..1423 {
+            assert_eq!(throttle.check("a", INTERVAL, start), None);
+        }
+        assert_eq!(throttle.check("a", INTERVAL, start + INTERVAL), Some(1423));
+        // 新窗口重新计数
+        assert_eq!(throttle.check("a", INTERVAL, start + INTERVAL), None);
+        assert_eq!(throttle.check("a", INTERVAL, start + INTERVAL * 2), Some(1));
+
+        assert_eq!(suppressed_suffix(0), "");
+        assert_eq!(
+            suppressed_suffix(1423),
+            " (suppressed 1423 similar messages)"
+        );
+    }
+
+    #[test]
+    fn test_stale_entries_are_cleaned_up() {
+        let throttle = LogThrottle::default();
+        let start = Instant::now();
+
+        throttle.check("old", INTERVAL, start);
+        throttle.check("recent", INTERVAL, start + CLEANUP_INTERVAL - INTERVAL);
+        assert_eq!(throttle.len(), 2);
+
+        // 到达清理间隔：仅移除窗口早已结束的条目
+        throttle.check("new", INTERVAL, start + CLEANUP_INTERVAL);
+        assert_eq!(throttle.len(), 2);
+        assert_eq!(
+            throttle.check("old", INTERVAL, start + CLEANUP_INTERVAL),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_macro_uses_global_state() {
+        let key = format!("macro-{}", uuid::Uuid::new_v4());
+        log_throttled!(warn, key, INTERVAL, "first {}", 1);
+        assert_eq!(check(&key, INTERVAL), None);
+    }
+}
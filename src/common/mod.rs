@@ -1,3 +1,9 @@
 //! 公共工具模块
 
 pub mod auth;
+pub mod instance_lock;
+pub mod ip_allowlist;
+pub mod listener;
+pub mod log_throttle;
+pub mod model_pattern;
+pub mod rotating_file;
@@ -0,0 +1,58 @@
+//! 以模型名为键的配置项匹配
+//!
+//! `pricing`、`maxTokensCap` 等配置的键按以下优先级匹配模型名：
+//! 1. 精确模型名（如 `claude-opus-4-5-20251101`）
+//! 2. 以 `*` 结尾的前缀（如 `claude-opus-*`），多个前缀匹配时取最长的
+//! 3. 默认键 `*` 或 `default`
+
+use std::collections::HashMap;
+
+/// 默认配置项的键
+const DEFAULT_PATTERNS: [&str; 2] = ["*", "default"];
+
+/// 查找模型对应的配置项（均未匹配时返回 None）
+pub fn find_by_model<'a, T>(patterns: &'a HashMap<String, T>, model: &str) -> Option<&'a T> {
+    if let Some(value) = patterns.get(model) {
+        return Some(value);
+    }
+
+    let prefix_match = patterns
+        .iter()
+        .filter_map(|(pattern, value)| {
+            let prefix = pattern.strip_suffix('*').filter(|p| !p.is_empty())?;
+            model.starts_with(prefix).then_some((prefix.len(), value))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, value)| value);
+
+    prefix_match.or_else(|| DEFAULT_PATTERNS.iter().find_map(|key| patterns.get(*key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_precedence() {
+        let caps = HashMap::from([
+            ("claude-sonnet-4.5".to_string(), 1),
+            ("claude-sonnet-*".to_string(), 2),
+            ("claude-*".to_string(), 3),
+            ("default".to_string(), 4),
+        ]);
+
+        assert_eq!(find_by_model(&caps, "claude-sonnet-4.5"), Some(&1));
+        assert_eq!(find_by_model(&caps, "claude-sonnet-4.6"), Some(&2));
+        assert_eq!(find_by_model(&caps, "claude-opus-4.6"), Some(&3));
+        assert_eq!(find_by_model(&caps, "gpt-4o"), Some(&4));
+
+        // `*` 与 `default` 同为默认键
+        let caps = HashMap::from([("*".to_string(), 5), ("claude-*".to_string(), 6)]);
+        assert_eq!(find_by_model(&caps, "gpt-4o"), Some(&5));
+        assert_eq!(find_by_model(&caps, "claude-haiku-4.5"), Some(&6));
+
+        // 单独的 `*` 前缀为空，不作为前缀参与匹配
+        let caps = HashMap::from([("claude-*".to_string(), 7)]);
+        assert_eq!(find_by_model(&caps, "gpt-4o"), None);
+    }
+}
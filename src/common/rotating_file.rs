@@ -0,0 +1,156 @@
+//! 按大小轮转的日志文件
+//!
+//! 以服务方式运行时日志写入文件而非标准输出。当前文件写满 `max_bytes` 后依次重命名为
+//! `<file>.1`、`<file>.2`……（数字越大越旧），超出 `keep` 个的最旧文件被删除。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 按大小轮转的文件写入器
+///
+/// 以追加方式打开，重启后继续写入已有文件；单次写入不会被拆分到两个文件中
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFileWriter {
+    /// 打开（必要时创建）日志文件及其所在目录
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes: max_bytes.max(1),
+            keep,
+            file,
+            written,
+        })
+    }
+
+    /// 第 `index` 个历史文件的路径（`<file>.<index>`）
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    /// 轮转：历史文件序号依次后移，当前文件成为 `<file>.1`，再重新打开空文件
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            remove_if_exists(&self.rotated_path(self.keep))?;
+            for index in (1..self.keep).rev() {
+                rename_if_exists(&self.rotated_path(index), &self.rotated_path(index + 1))?;
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("kiro-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("logs").join("kiro.log");
+        (dir, path)
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_rotates_by_size_and_keeps_limited_history() {
+        let (dir, path) = temp_log();
+        let mut writer = RotatingFileWriter::open(&path, 10, 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path), "dddddddd\n");
+        assert_eq!(read(&writer.rotated_path(1)), "cccccccc\n");
+        assert_eq!(read(&writer.rotated_path(2)), "bbbbbbbb\n");
+        assert!(!writer.rotated_path(3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_appends_and_counts_existing_size() {
+        let (dir, path) = temp_log();
+        RotatingFileWriter::open(&path, 10, 1)
+            .unwrap()
+            .write_all(b"12345678\n")
+            .unwrap();
+
+        // 重启后继续追加，已有内容计入大小
+        let mut writer = RotatingFileWriter::open(&path, 10, 1).unwrap();
+        writer.write_all(b"x\n").unwrap();
+        assert_eq!(read(&path), "x\n");
+        assert_eq!(read(&writer.rotated_path(1)), "12345678\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keep_zero_truncates_in_place() {
+        let (dir, path) = temp_log();
+        let mut writer = RotatingFileWriter::open(&path, 4, 0).unwrap();
+        writer.write_all(b"abc\n").unwrap();
+        writer.write_all(b"def\n").unwrap();
+        assert_eq!(read(&path), "def\n");
+        assert!(!writer.rotated_path(1).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -151,21 +151,17 @@ pub fn print_event_verbose(event: &Event) {
             println!("  payload ({} bytes):", payload.len());
             print_hex(payload);
         }
-        Event::Error {
-            error_code,
-            error_message,
-        } => {
+        Event::Error(e) => {
             println!("\n[事件] Error");
-            println!("  error_code: {:?}", error_code);
-            println!("  error_message: {:?}", error_message);
+            println!("  error_code: {:?}", e.error_code);
+            println!("  message: {:?}", e.message);
+            println!("  request_id: {:?}", e.request_id);
         }
-        Event::Exception {
-            exception_type,
-            message,
-        } => {
+        Event::Exception(e) => {
             println!("\n[事件] Exception");
-            println!("  exception_type: {:?}", exception_type);
-            println!("  message: {:?}", message);
+            println!("  exception_type: {:?}", e.exception_type);
+            println!("  message: {:?}", e.message);
+            println!("  request_id: {:?}", e.request_id);
         }
     }
 }
@@ -194,17 +190,11 @@ pub fn print_event(event: &Event) {
         Event::Unknown { event_type, .. } => {
             println!("\n[未知事件] {}", event_type);
         }
-        Event::Error {
-            error_code,
-            error_message,
-        } => {
-            println!("\n[错误] {}: {}", error_code, error_message);
-        }
-        Event::Exception {
-            exception_type,
-            message,
-        } => {
-            println!("\n[异常] {}: {}", exception_type, message);
+        Event::Error(e) => {
+            println!("\n[错误] {}", e);
+        }
+        Event::Exception(e) => {
+            println!("\n[异常] {}", e);
         }
     }
 }
@@ -0,0 +1,367 @@
+//! 部署自检（`--dry-run` / `--dry-run-full`）
+//!
+//! 依次校验配置、凭据、Token 管理器构建与监听地址，汇总全部问题后退出，不启动服务。
+//! `--dry-run` 不获取实例锁、不发起网络请求，也不回写任何文件。
+
+use std::path::Path;
+
+use crate::admin::report::ReportSchedule;
+use crate::common::ip_allowlist::IpAllowlist;
+use crate::common::listener::{BindTarget, check_unix_socket_path};
+use crate::kiro::model::credentials::CredentialsConfig;
+use crate::kiro::token_manager::MultiTokenManager;
+use crate::model::config::Config;
+
+/// 自检结果
+#[derive(Debug, Default)]
+pub struct DryRunReport {
+    /// 已加载内容的摘要
+    summary: Vec<String>,
+    /// 发现的问题
+    errors: Vec<String>,
+}
+
+impl DryRunReport {
+    /// 进程退出码：全部通过为 0，否则为 1
+    pub fn exit_code(&self) -> i32 {
+        if self.errors.is_empty() { 0 } else { 1 }
+    }
+
+    /// 渲染为可读文本（摘要在前，问题列表在后）
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for line in &self.summary {
+            output.push_str(line);
+            output.push('\n');
+        }
+        if self.errors.is_empty() {
+            output.push_str("自检通过\n");
+        } else {
+            output.push_str(&format!("自检失败，发现 {} 个问题:\n", self.errors.len()));
+            for error in &self.errors {
+                output.push_str(&format!("  - {}\n", error));
+            }
+        }
+        output
+    }
+}
+
+/// 执行自检
+///
+/// `full` 为 true 时额外使用优先级最高的凭据查询使用额度，测试到上游的网络连通性。
+/// 此时可能刷新 Token，为避免轮换后的 refreshToken 丢失，刷新结果会像正常启动一样回写凭据文件
+pub async fn run(config_path: &Path, credentials_path: &Path, full: bool) -> DryRunReport {
+    let mut report = DryRunReport::default();
+
+    let config = match Config::load(config_path) {
+        Ok(config) => {
+            report.summary.push(format!("配置文件: {:?}", config_path));
+            check_config(&config, &mut report);
+            Some(config)
+        }
+        Err(e) => {
+            report
+                .errors
+                .push(format!("加载配置文件 {:?} 失败: {:#}", config_path, e));
+            None
+        }
+    };
+
+    // 设置了凭据环境变量时从环境变量加载（与正常启动一致）
+    let credentials_env = CredentialsConfig::env_var_name();
+    let credentials_source = match credentials_env {
+        Some(var_name) => format!("凭据环境变量 {}", var_name),
+        None => format!("凭据文件 {:?}", credentials_path),
+    };
+    let credentials = match CredentialsConfig::load(credentials_path) {
+        Ok(credentials) => {
+            report.summary.push(format!(
+                "{}（{} 个凭据，{}格式）",
+                credentials_source,
+                credentials.len(),
+                if credentials.is_multiple() {
+                    "数组"
+                } else {
+                    "单对象"
+                }
+            ));
+            let errors = credentials.validate();
+            let valid = errors.is_empty();
+            report.errors.extend(errors);
+            valid.then_some(credentials)
+        }
+        Err(e) => {
+            report
+                .errors
+                .push(format!("加载{} 失败: {:#}", credentials_source, e));
+            None
+        }
+    };
+
+    // 配置或凭据无效时无法继续构建 Token 管理器与检查监听地址
+    let (Some(config), Some(credentials)) = (config, credentials) else {
+        return report;
+    };
+
+    match BindTarget::from_config(&config) {
+        BindTarget::Tcp(addr) => match tokio::net::TcpListener::bind(&addr).await {
+            Ok(listener) => {
+                drop(listener);
+                report.summary.push(format!("监听地址: {}", addr));
+            }
+            Err(e) => report
+                .errors
+                .push(format!("监听地址 {} 不可用: {}", addr, e)),
+        },
+        // 不实际监听：已存在的 socket 可能属于正在运行的实例，监听前会被删除
+        BindTarget::Unix(path) => match check_unix_socket_path(&path) {
+            Ok(()) => report
+                .summary
+                .push(format!("监听地址: unix:{}", path.display())),
+            Err(e) => report
+                .errors
+                .push(format!("unixSocketPath 不可用: {:#}", e)),
+        },
+    }
+
+    // 仅 --dry-run-full 回写凭据文件（见函数文档），来自环境变量的凭据不回写
+    let persist_path = (full && credentials_env.is_none()).then(|| credentials_path.to_path_buf());
+    let is_multiple_format = credentials.is_multiple();
+    let manager = match MultiTokenManager::new(
+        config.clone(),
+        credentials.into_sorted_credentials(),
+        crate::http_client::build_proxy_config(&config),
+        persist_path.clone(),
+        persist_path.is_some() && is_multiple_format,
+    ) {
+        Ok(manager) => manager,
+        Err(e) => {
+            report.errors.push(format!("创建 Token 管理器失败: {}", e));
+            return report;
+        }
+    };
+
+    let snapshot = manager.snapshot();
+    if snapshot.entries.is_empty() {
+        report
+            .summary
+            .push("未配置任何凭据，服务启动后需通过 Admin API 添加".to_string());
+    }
+    for entry in &snapshot.entries {
+        report.summary.push(format!(
+            "  #{} priority={} authMethod={}{}",
+            entry.id,
+            entry.priority,
+            entry.auth_method.as_deref().unwrap_or("social"),
+            if entry.disabled {
+                "（已禁用）"
+            } else {
+                ""
+            }
+        ));
+    }
+
+    if full {
+        if snapshot.available == 0 {
+            report
+                .errors
+                .push("没有可用凭据，无法测试网络连通性".to_string());
+        } else {
+            match manager.get_usage_limits_for(snapshot.current_id).await {
+                Ok(usage) => report.summary.push(format!(
+                    "连通性测试: 凭据 #{} 查询使用额度成功（订阅: {}）",
+                    snapshot.current_id,
+                    usage.subscription_title().unwrap_or("未知")
+                )),
+                Err(e) => report.errors.push(format!(
+                    "连通性测试: 凭据 #{} 查询使用额度失败: {:#}",
+                    snapshot.current_id, e
+                )),
+            }
+        }
+    }
+
+    report
+}
+
+/// 校验启动时才会检查的配置项
+fn check_config(config: &Config, report: &mut DryRunReport) {
+    if config
+        .api_key
+        .as_deref()
+        .is_none_or(|k| k.trim().is_empty())
+    {
+        report.errors.push("配置文件中未设置 apiKey".to_string());
+    }
+    if let Err(e) = IpAllowlist::from_config(config) {
+        report
+            .errors
+            .push(format!("adminAllowedIps 配置无效: {:#}", e));
+    }
+    if let Some(Err(e)) = config.report_schedule.as_deref().map(ReportSchedule::parse) {
+        report
+            .errors
+            .push(format!("reportSchedule 配置无效: {:#}", e));
+    }
+    let admin_enabled = config
+        .admin_api_key
+        .as_deref()
+        .is_some_and(|k| !k.trim().is_empty());
+    report.summary.push(format!(
+        "Admin API: {}",
+        if admin_enabled {
+            "已启用"
+        } else {
+            "未启用"
+        }
+    ));
+    if let Some(proxy_url) = &config.proxy_url {
+        report.summary.push(format!("HTTP 代理: {}", proxy_url));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在临时目录中写入配置与凭据文件，返回 (目录, 配置路径, 凭据路径)
+    fn write_files(
+        config: &str,
+        credentials: &str,
+    ) -> (std::path::PathBuf, std::path::PathBuf, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("kiro-dry-run-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.json");
+        let credentials_path = dir.join("credentials.json");
+        std::fs::write(&config_path, config).unwrap();
+        std::fs::write(&credentials_path, credentials).unwrap();
+        (dir, config_path, credentials_path)
+    }
+
+    const CONFIG: &str = r#"{"host": "127.0.0.1", "port": 0, "apiKey": "sk-test"}"#;
+
+    #[tokio::test]
+    async fn test_dry_run_reports_malformed_credentials() {
+        let (dir, config_path, credentials_path) = write_files(
+            CONFIG,
+            r#"[
+                {"id": 1, "refreshToken": "a"},
+                {"id": 1, "authMethod": "idc"}
+            ]"#,
+        );
+
+        let _env = crate::kiro::model::credentials::CREDENTIALS_ENV_LOCK
+            .lock()
+            .await;
+        let report = run(&config_path, &credentials_path, false).await;
+        assert_eq!(report.exit_code(), 1);
+        let output = report.render();
+        assert!(output.contains("自检失败，发现 3 个问题"), "{}", output);
+        assert!(
+            output.contains("第 2 个凭据缺少 refreshToken"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("第 2 个凭据的 id 1 与其他凭据重复"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("缺少 clientId 或 clientSecret"),
+            "{}",
+            output
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_unparseable_files() {
+        let (dir, config_path, credentials_path) =
+            write_files(r#"{"port": "#, r#"{"refreshToken": "#);
+
+        let _env = crate::kiro::model::credentials::CREDENTIALS_ENV_LOCK
+            .lock()
+            .await;
+        let report = run(&config_path, &credentials_path, false).await;
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("加载配置文件"));
+        assert!(report.errors[1].starts_with("加载凭据文件"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_passes_without_writing_files() {
+        let credentials = r#"[{"refreshToken": "a"}, {"refreshToken": "b", "priority": 1}]"#;
+        let (dir, config_path, credentials_path) = write_files(CONFIG, credentials);
+
+        let _env = crate::kiro::model::credentials::CREDENTIALS_ENV_LOCK
+            .lock()
+            .await;
+        let report = run(&config_path, &credentials_path, false).await;
+        assert_eq!(report.exit_code(), 0, "{:?}", report.errors);
+        let output = report.render();
+        assert!(output.contains("2 个凭据，数组格式"), "{}", output);
+        assert!(
+            output.contains("#1 priority=0 authMethod=social"),
+            "{}",
+            output
+        );
+        assert!(output.ends_with("自检通过\n"));
+
+        // 缺失的 ID 与 machineId 不回写
+        assert_eq!(
+            std::fs::read_to_string(&credentials_path).unwrap(),
+            credentials
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_unbindable_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = format!(
+            r#"{{"host": "127.0.0.1", "port": {}, "apiKey": "k"}}"#,
+            port
+        );
+        let (dir, config_path, credentials_path) = write_files(&config, "[]");
+
+        let _env = crate::kiro::model::credentials::CREDENTIALS_ENV_LOCK
+            .lock()
+            .await;
+        let report = run(&config_path, &credentials_path, false).await;
+        assert_eq!(report.exit_code(), 1);
+        assert!(report.errors[0].starts_with(&format!("监听地址 127.0.0.1:{} 不可用", port)));
+
+        drop(listener);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_checks_unix_socket_directory() {
+        let (dir, config_path, credentials_path) = write_files(CONFIG, "[]");
+        let socket = dir.join("missing").join("kiro.sock");
+        let config = format!(
+            r#"{{"apiKey": "k", "unixSocketPath": {:?}}}"#,
+            socket.to_str().unwrap()
+        );
+        std::fs::write(&config_path, config).unwrap();
+
+        let _env = crate::kiro::model::credentials::CREDENTIALS_ENV_LOCK
+            .lock()
+            .await;
+        let report = run(&config_path, &credentials_path, false).await;
+        assert_eq!(report.exit_code(), 1);
+        assert!(
+            report.errors[0].starts_with("unixSocketPath 不可用"),
+            "{:?}",
+            report.errors
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
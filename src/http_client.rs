@@ -1,11 +1,18 @@
 //! HTTP Client 构建模块
 //!
-//! 提供统一的 HTTP Client 构建功能，支持代理配置
+//! 提供统一的 HTTP Client 构建功能，支持代理配置，
+//! 以及按代理配置缓存上游 API Client 的 [`ClientPool`]
 
-use reqwest::{Client, Proxy};
+use chrono::Utc;
+use parking_lot::Mutex;
+use reqwest::{Client, ClientBuilder, Proxy};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use crate::model::config::TlsBackend;
+use crate::model::config::{Config, TlsBackend};
 
 /// 代理配置
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
@@ -36,6 +43,17 @@ impl ProxyConfig {
     }
 }
 
+/// 按配置构建全局代理（未配置 proxyUrl 时为 None）
+pub fn build_proxy_config(config: &Config) -> Option<ProxyConfig> {
+    config.proxy_url.as_ref().map(|url| {
+        let mut proxy = ProxyConfig::new(url);
+        if let (Some(username), Some(password)) = (&config.proxy_username, &config.proxy_password) {
+            proxy = proxy.with_auth(username, password);
+        }
+        proxy
+    })
+}
+
 /// 构建 HTTP Client
 ///
 /// # Arguments
@@ -49,11 +67,44 @@ pub fn build_client(
     timeout_secs: u64,
     tls_backend: TlsBackend,
 ) -> anyhow::Result<Client> {
-    let mut builder = Client::builder().timeout(Duration::from_secs(timeout_secs));
+    Ok(client_builder(proxy, timeout_secs, tls_backend)?.build()?)
+}
 
-    if tls_backend == TlsBackend::Rustls {
-        builder = builder.use_rustls_tls();
+/// 构建指定空闲连接保留时间的 HTTP Client（0 表示不保留空闲连接）
+pub fn build_client_with_idle_timeout(
+    proxy: Option<&ProxyConfig>,
+    timeout_secs: u64,
+    tls_backend: TlsBackend,
+    pool_idle_timeout_secs: u64,
+) -> anyhow::Result<Client> {
+    let mut builder = client_builder(proxy, timeout_secs, tls_backend)?
+        .pool_idle_timeout(Duration::from_secs(pool_idle_timeout_secs));
+    if pool_idle_timeout_secs == 0 {
+        builder = builder.pool_max_idle_per_host(0);
     }
+    Ok(builder.build()?)
+}
+
+fn client_builder(
+    proxy: Option<&ProxyConfig>,
+    timeout_secs: u64,
+    tls_backend: TlsBackend,
+) -> anyhow::Result<ClientBuilder> {
+    // 上游可能返回 gzip / brotli 压缩的响应，由 reqwest 透明解压
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(timeout_secs))
+        .gzip(true)
+        .brotli(true);
+
+    builder = match tls_backend {
+        TlsBackend::Rustls => builder.use_rustls_tls(),
+        #[cfg(feature = "native-tls")]
+        TlsBackend::NativeTls => builder.use_native_tls(),
+        #[cfg(not(feature = "native-tls"))]
+        TlsBackend::NativeTls => {
+            anyhow::bail!("当前构建未启用 native-tls 特性，请使用 `--features native-tls` 重新编译")
+        }
+    };
 
     if let Some(proxy_config) = proxy {
         let mut proxy = Proxy::all(&proxy_config.url)?;
@@ -67,7 +118,124 @@ pub fn build_client(
         tracing::debug!("HTTP Client 使用代理: {}", proxy_config.url);
     }
 
-    Ok(builder.build()?)
+    Ok(builder)
+}
+
+/// 按代理配置缓存的上游 API Client
+///
+/// 不同代理配置使用不同的 Client，共享相同代理的请求复用 Client（及其连接池）。
+/// 重置后所有 Client 按相同的 key 重新构建，旧 Client 的连接在其最后一个引用释放后关闭。
+pub struct ClientPool {
+    clients: Mutex<HashMap<Option<ProxyConfig>, PooledClient>>,
+    timeout_secs: u64,
+    tls_backend: TlsBackend,
+    pool_idle_timeout_secs: u64,
+    /// 下一个 Client 实例编号（重置后递增，便于区分新旧实例）
+    next_instance_id: AtomicU64,
+}
+
+struct PooledClient {
+    client: Client,
+    instance_id: u64,
+    created_at: String,
+    requests_served: u64,
+}
+
+/// 单个缓存 Client 的统计信息
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PooledClientStats {
+    /// 代理地址（None 表示直连）
+    pub proxy_url: Option<String>,
+    /// Client 实例编号
+    pub instance_id: u64,
+    /// 创建时间（RFC3339 格式）
+    pub created_at: String,
+    /// 该实例创建以来获取的次数（每次上游请求获取一次）
+    pub requests_served: u64,
+    /// 请求超时（秒）
+    pub timeout_secs: u64,
+    /// 空闲连接保留时间（秒）
+    pub pool_idle_timeout_secs: u64,
+}
+
+impl ClientPool {
+    pub fn new(timeout_secs: u64, tls_backend: TlsBackend, pool_idle_timeout_secs: u64) -> Self {
+        Self {
+            clients: Mutex::new(HashMap::new()),
+            timeout_secs,
+            tls_backend,
+            pool_idle_timeout_secs,
+            next_instance_id: AtomicU64::new(1),
+        }
+    }
+
+    fn build(&self, proxy: Option<&ProxyConfig>) -> anyhow::Result<PooledClient> {
+        let client = build_client_with_idle_timeout(
+            proxy,
+            self.timeout_secs,
+            self.tls_backend,
+            self.pool_idle_timeout_secs,
+        )?;
+        Ok(PooledClient {
+            client,
+            instance_id: self.next_instance_id.fetch_add(1, Ordering::Relaxed),
+            created_at: Utc::now().to_rfc3339(),
+            requests_served: 0,
+        })
+    }
+
+    /// 预先构建指定代理配置的 Client（不计入请求次数）
+    pub fn warm_up(&self, proxy: Option<&ProxyConfig>) -> anyhow::Result<()> {
+        if let Entry::Vacant(slot) = self.clients.lock().entry(proxy.cloned()) {
+            slot.insert(self.build(proxy)?);
+        }
+        Ok(())
+    }
+
+    /// 获取（或创建并缓存）指定代理配置的 Client，并累加请求次数
+    pub fn get(&self, proxy: Option<&ProxyConfig>) -> anyhow::Result<Client> {
+        let mut clients = self.clients.lock();
+        let pooled = match clients.entry(proxy.cloned()) {
+            Entry::Occupied(slot) => slot.into_mut(),
+            Entry::Vacant(slot) => slot.insert(self.build(proxy)?),
+        };
+        pooled.requests_served += 1;
+        Ok(pooled.client.clone())
+    }
+
+    /// 各缓存 Client 的统计信息（直连在前，其余按代理地址排序）
+    pub fn stats(&self) -> Vec<PooledClientStats> {
+        let clients = self.clients.lock();
+        let mut stats: Vec<PooledClientStats> = clients
+            .iter()
+            .map(|(proxy, pooled)| PooledClientStats {
+                proxy_url: proxy.as_ref().map(|p| p.url.clone()),
+                instance_id: pooled.instance_id,
+                created_at: pooled.created_at.clone(),
+                requests_served: pooled.requests_served,
+                timeout_secs: self.timeout_secs,
+                pool_idle_timeout_secs: self.pool_idle_timeout_secs,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.proxy_url.cmp(&b.proxy_url));
+        stats
+    }
+
+    /// 丢弃所有缓存的 Client 并按相同的代理配置重新构建，返回重建的数量
+    ///
+    /// 任一 Client 构建失败时保留原有缓存不变
+    pub fn reset(&self) -> anyhow::Result<usize> {
+        let mut clients = self.clients.lock();
+        let rebuilt = clients
+            .keys()
+            .map(|proxy| Ok((proxy.clone(), self.build(proxy.as_ref())?)))
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        let count = rebuilt.len();
+        *clients = rebuilt;
+        tracing::info!("已重建 {} 个上游 HTTP Client", count);
+        Ok(count)
+    }
 }
 
 #[cfg(test)]
@@ -102,4 +270,71 @@ mod tests {
         let client = build_client(Some(&config), 30, TlsBackend::Rustls);
         assert!(client.is_ok());
     }
+
+    #[cfg(not(feature = "native-tls"))]
+    #[test]
+    fn test_build_client_native_tls_requires_feature() {
+        let err = build_client(None, 30, TlsBackend::NativeTls).unwrap_err();
+        assert!(err.to_string().contains("native-tls"), "{}", err);
+    }
+
+    async fn assert_get_succeeds(tls_backend: TlsBackend) {
+        let client = build_client(None, 30, tls_backend).unwrap();
+        let response = client.get("https://httpbin.org/get").send().await.unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    #[ignore = "需要访问外网"]
+    async fn test_rustls_client_performs_get() {
+        assert_get_succeeds(TlsBackend::Rustls).await;
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[tokio::test]
+    #[ignore = "需要访问外网"]
+    async fn test_native_tls_client_performs_get() {
+        assert_get_succeeds(TlsBackend::NativeTls).await;
+    }
+
+    #[test]
+    fn test_client_pool_counts_requests_per_client() {
+        let pool = ClientPool::new(30, TlsBackend::Rustls, 15);
+        let proxy = ProxyConfig::new("http://127.0.0.1:7890");
+        pool.warm_up(None).unwrap();
+        assert_eq!(pool.stats()[0].requests_served, 0);
+
+        for _ in 0..3 {
+            pool.get(None).unwrap();
+        }
+        pool.get(Some(&proxy)).unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].proxy_url, None);
+        assert_eq!(stats[0].requests_served, 3);
+        assert_eq!(stats[1].proxy_url.as_deref(), Some("http://127.0.0.1:7890"));
+        assert_eq!(stats[1].requests_served, 1);
+        assert_eq!(stats[1].pool_idle_timeout_secs, 15);
+    }
+
+    #[test]
+    fn test_client_pool_reset_rebuilds_clients() {
+        let pool = ClientPool::new(30, TlsBackend::Rustls, 0);
+        pool.get(None).unwrap();
+        pool.get(Some(&ProxyConfig::new("http://127.0.0.1:7890")))
+            .unwrap();
+        let before: Vec<u64> = pool.stats().iter().map(|s| s.instance_id).collect();
+
+        assert_eq!(pool.reset().unwrap(), 2);
+
+        let after = pool.stats();
+        assert_eq!(after.len(), 2);
+        for (old_id, stats) in before.iter().zip(&after) {
+            assert_ne!(*old_id, stats.instance_id);
+            assert_eq!(stats.requests_served, 0);
+        }
+        pool.get(None).unwrap();
+        assert_eq!(pool.stats()[0].requests_served, 1);
+    }
 }
@@ -0,0 +1,316 @@
+//! 余额（使用额度）缓存
+//!
+//! 由 MultiTokenManager 持有，供 Admin API 与基于额度的路由决策共享：
+//! - 真实拉取上游使用额度后写入缓存（并持久化到 `kiro_balance_cache.json`）
+//! - 两次拉取之间，成功请求会在本地乐观累加 `current_usage`，额度用尽时直接标记为已满
+//! - 下一次真实拉取到达时以上游数据为准，覆盖本地估算
+//!
+//! 本地乐观更新不会单独触发落盘，随下一次真实拉取或删除时一并保存。
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::kiro::model::usage_limits::UsageLimitsResponse;
+
+/// 余额缓存过期时间（秒），5 分钟
+pub const BALANCE_CACHE_TTL_SECS: i64 = 300;
+
+/// 余额缓存文件名（位于凭据文件所在目录）
+const BALANCE_CACHE_FILE: &str = "kiro_balance_cache.json";
+
+/// 使用额度快照
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSnapshot {
+    /// 订阅类型
+    pub subscription_title: Option<String>,
+    /// 当前使用量
+    pub current_usage: f64,
+    /// 使用限额
+    pub usage_limit: f64,
+    /// 下次重置时间（Unix 时间戳）
+    pub next_reset_at: Option<f64>,
+}
+
+impl UsageSnapshot {
+    /// 从上游使用额度响应构建快照（`resource_type` 缺省为 AGENTIC_REQUEST）
+    pub fn from_usage_limits(usage: &UsageLimitsResponse, resource_type: Option<&str>) -> Self {
+        Self {
+            subscription_title: usage.subscription_title().map(|s| s.to_string()),
+            current_usage: usage.current_usage(resource_type),
+            usage_limit: usage.usage_limit(resource_type),
+            next_reset_at: usage.next_date_reset,
+        }
+    }
+
+    /// 剩余额度
+    pub fn remaining(&self) -> f64 {
+        (self.usage_limit - self.current_usage).max(0.0)
+    }
+}
+
+/// 缓存的余额条目（含时间戳）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedBalance {
+    /// 最近一次真实拉取的时间（Unix 秒，本地乐观更新不会刷新该时间）
+    pub cached_at: f64,
+    /// 缓存的使用额度
+    pub data: UsageSnapshot,
+    /// 自最近一次真实拉取以来本地乐观累加的请求数
+    #[serde(default, skip_serializing)]
+    pub local_increments: u64,
+}
+
+impl CachedBalance {
+    /// 距最近一次真实拉取是否未超过 `max_age_secs`
+    pub fn is_fresh(&self, max_age_secs: f64, now: f64) -> bool {
+        (now - self.cached_at) < max_age_secs
+    }
+}
+
+/// 按凭据 ID 索引的余额缓存
+pub struct BalanceCache {
+    entries: Mutex<HashMap<u64, CachedBalance>>,
+    path: Option<PathBuf>,
+}
+
+impl BalanceCache {
+    /// 从缓存目录加载（丢弃超过 TTL 的条目）；`cache_dir` 为 None 时不持久化
+    pub fn load(cache_dir: Option<PathBuf>, now: f64) -> Self {
+        let path = cache_dir.map(|d| d.join(BALANCE_CACHE_FILE));
+        let entries = path
+            .as_ref()
+            .map(|p| Self::load_from(p, now))
+            .unwrap_or_default();
+        Self {
+            entries: Mutex::new(entries),
+            path,
+        }
+    }
+
+    fn load_from(path: &PathBuf, now: f64) -> HashMap<u64, CachedBalance> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return HashMap::new(),
+        };
+
+        // 文件中使用字符串 key 以兼容 JSON 格式
+        let map: HashMap<String, CachedBalance> = match serde_json::from_str(&content) {
+            Ok(m) => m,
+            Err(e) => {
+                tracing::warn!("解析余额缓存失败，将忽略: {}", e);
+                return HashMap::new();
+            }
+        };
+
+        map.into_iter()
+            .filter_map(|(k, v)| {
+                let id = k.parse::<u64>().ok()?;
+                // 丢弃超过 TTL 的条目
+                v.is_fresh(BALANCE_CACHE_TTL_SECS as f64, now)
+                    .then_some((id, v))
+            })
+            .collect()
+    }
+
+    /// 获取指定凭据的缓存条目（不检查是否过期）
+    pub fn get_cached(&self, id: u64) -> Option<CachedBalance> {
+        self.entries.lock().get(&id).cloned()
+    }
+
+    /// 写入真实拉取到的使用额度，覆盖此前的本地估算并持久化
+    pub fn store(&self, id: u64, data: UsageSnapshot, now: f64) {
+        {
+            let mut entries = self.entries.lock();
+            if let Some(previous) = entries.get(&id).filter(|e| e.local_increments > 0) {
+                tracing::debug!(
+                    "凭据 #{} 余额已与上游对齐（本地估算 {}，上游 {}）",
+                    id,
+                    previous.data.current_usage,
+                    data.current_usage
+                );
+            }
+            entries.insert(
+                id,
+                CachedBalance {
+                    cached_at: now,
+                    data,
+                    local_increments: 0,
+                },
+            );
+        }
+        self.save();
+    }
+
+    /// 成功请求后乐观累加使用量（无缓存条目时忽略）
+    pub fn record_usage(&self, id: u64) {
+        if let Some(entry) = self.entries.lock().get_mut(&id) {
+            entry.data.current_usage += 1.0;
+            entry.local_increments += 1;
+        }
+    }
+
+    /// 额度用尽时将使用量标记为已满（无缓存条目时忽略）
+    pub fn mark_exhausted(&self, id: u64) {
+        if let Some(entry) = self.entries.lock().get_mut(&id) {
+            entry.data.current_usage = entry.data.current_usage.max(entry.data.usage_limit);
+        }
+    }
+
+    /// 移除指定凭据的缓存（凭据被删除时调用）
+    pub fn remove(&self, id: u64) {
+        let removed = self.entries.lock().remove(&id).is_some();
+        if removed {
+            self.save();
+        }
+    }
+
+    /// 从 `ids` 中筛选缓存缺失或距上次真实拉取超过 `max_age_secs` 的凭据
+    pub fn stale_ids(&self, ids: &[u64], max_age_secs: f64, now: f64) -> Vec<u64> {
+        let entries = self.entries.lock();
+        ids.iter()
+            .copied()
+            .filter(|id| {
+                !entries
+                    .get(id)
+                    .is_some_and(|e| e.is_fresh(max_age_secs, now))
+            })
+            .collect()
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // 持有锁期间完成序列化和写入，防止并发损坏
+        let entries = self.entries.lock();
+        let map: HashMap<String, &CachedBalance> =
+            entries.iter().map(|(k, v)| (k.to_string(), v)).collect();
+
+        match serde_json::to_string_pretty(&map) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(path, json) {
+                    tracing::warn!("保存余额缓存失败: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("序列化余额缓存失败: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+
+    fn snapshot(current_usage: f64) -> UsageSnapshot {
+        UsageSnapshot {
+            subscription_title: Some("KIRO PRO".to_string()),
+            current_usage,
+            usage_limit: 100.0,
+            next_reset_at: Some(NOW + 86_400.0),
+        }
+    }
+
+    #[test]
+    fn test_optimistic_increment() {
+        let cache = BalanceCache::load(None, NOW);
+        // 无缓存条目时不创建估算值
+        cache.record_usage(1);
+        assert!(cache.get_cached(1).is_none());
+
+        cache.store(1, snapshot(10.0), NOW);
+        cache.record_usage(1);
+        cache.record_usage(1);
+
+        let cached = cache.get_cached(1).unwrap();
+        assert_eq!(cached.data.current_usage, 12.0);
+        assert_eq!(cached.data.remaining(), 88.0);
+        assert_eq!(cached.local_increments, 2);
+        // 乐观更新不刷新缓存时间
+        assert_eq!(cached.cached_at, NOW);
+    }
+
+    #[test]
+    fn test_real_fetch_reconciles_local_estimate() {
+        let cache = BalanceCache::load(None, NOW);
+        cache.store(1, snapshot(10.0), NOW);
+        for _ in 0..5 {
+            cache.record_usage(1);
+        }
+
+        // 上游真实值以上游为准（可能与本地估算不一致）
+        cache.store(1, snapshot(13.0), NOW + 60.0);
+        let cached = cache.get_cached(1).unwrap();
+        assert_eq!(cached.data.current_usage, 13.0);
+        assert_eq!(cached.local_increments, 0);
+        assert_eq!(cached.cached_at, NOW + 60.0);
+    }
+
+    #[test]
+    fn test_mark_exhausted() {
+        let cache = BalanceCache::load(None, NOW);
+        cache.store(1, snapshot(10.0), NOW);
+        cache.mark_exhausted(1);
+        assert_eq!(cache.get_cached(1).unwrap().data.remaining(), 0.0);
+    }
+
+    #[test]
+    fn test_stale_ids() {
+        let cache = BalanceCache::load(None, NOW);
+        cache.store(1, snapshot(0.0), NOW - 600.0);
+        cache.store(2, snapshot(0.0), NOW - 10.0);
+        assert_eq!(cache.stale_ids(&[1, 2, 3], 300.0, NOW), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_persistence_round_trip() {
+        let dir = std::env::temp_dir().join(format!("kiro-balance-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cache = BalanceCache::load(Some(dir.clone()), NOW);
+        cache.store(2, snapshot(20.0), NOW - 1000.0);
+        cache.store(1, snapshot(10.0), NOW);
+        cache.record_usage(1);
+
+        // 重新加载：过期条目被丢弃，本地估算不会立即落盘
+        let reloaded = BalanceCache::load(Some(dir.clone()), NOW);
+        assert_eq!(reloaded.get_cached(1).unwrap().data.current_usage, 10.0);
+        assert!(reloaded.get_cached(2).is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_legacy_balance_response_format() {
+        let dir = std::env::temp_dir().join(format!("kiro-balance-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy = serde_json::json!({
+            "1": {
+                "cached_at": NOW,
+                "data": {
+                    "id": 1,
+                    "subscriptionTitle": "KIRO PRO",
+                    "currentUsage": 5.0,
+                    "usageLimit": 50.0,
+                    "remaining": 45.0,
+                    "usagePercentage": 10.0,
+                    "nextResetAt": null
+                }
+            }
+        });
+        std::fs::write(dir.join(BALANCE_CACHE_FILE), legacy.to_string()).unwrap();
+
+        let cache = BalanceCache::load(Some(dir.clone()), NOW);
+        let cached = cache.get_cached(1).unwrap();
+        assert_eq!(cached.data.current_usage, 5.0);
+        assert_eq!(cached.data.usage_limit, 50.0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
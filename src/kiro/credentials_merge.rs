@@ -0,0 +1,441 @@
+//! 凭据文件三方合并
+//!
+//! 凭据文件可能在服务运行期间被外部工具修改。回写前以上一份内存快照为基准（base），
+//! 将内存中的新快照（ours）与磁盘上的当前内容（external）按凭据 ID 逐字段合并：
+//! - 只有一方修改的字段采用修改方的值（外部修改覆盖内存中未变的字段）
+//! - 双方都修改的 Token 字段（accessToken / refreshToken / expiresAt）采用内存中刷新后的值
+//! - 双方都修改为不同值的其他字段、一方删除而另一方修改的凭据视为冲突，由调用方决定如何处理；
+//!   `resolved` 给出以外部文件为准解决冲突后的结果（Token 字段仍采用内存中的值）
+//!
+//! 合并只比较 JSON 值，不读写文件；变更摘要只包含凭据 ID 与字段名，不包含字段值。
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::{Map, Value};
+
+/// 双方都修改时以内存值为准的 Token 字段
+const TOKEN_FIELDS: &[&str] = &["accessToken", "refreshToken", "expiresAt"];
+
+/// 合并结果
+#[derive(Debug, Default, PartialEq)]
+pub struct CredentialsMerge {
+    /// 合并后的凭据列表（内存中的顺序在前，外部新增的凭据追加在末尾）
+    pub merged: Vec<Value>,
+    /// 采用外部修改或需要说明的变更
+    pub changes: Vec<String>,
+    /// 无法自动合并的冲突（非空时 `merged` 不应写入）
+    pub conflicts: Vec<String>,
+    /// 冲突以外部文件为准解决后的凭据列表（无冲突时与 `merged` 相同）
+    pub resolved: Vec<Value>,
+}
+
+impl CredentialsMerge {
+    /// 是否可以安全写入合并结果
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// 双方一致的凭据同时进入两种结果
+    fn push(&mut self, entry: Value) {
+        self.merged.push(entry.clone());
+        self.resolved.push(entry);
+    }
+}
+
+/// 三方合并凭据列表
+///
+/// 没有 `id` 的外部凭据无法与内存中的凭据对应，按外部新增处理
+pub fn merge_credentials(base: &[Value], ours: &[Value], external: &[Value]) -> CredentialsMerge {
+    let base_by_id = index_by_id(base);
+    let external_by_id = index_by_id(external);
+    let ours_by_id = index_by_id(ours);
+    let mut result = CredentialsMerge::default();
+
+    for entry in ours {
+        let Some(id) = credential_id(entry) else {
+            result.push(entry.clone());
+            continue;
+        };
+        match (base_by_id.get(&id), external_by_id.get(&id)) {
+            (Some(base_entry), Some(external_entry)) => {
+                let (merged, resolved) =
+                    merge_entry(id, base_entry, entry, external_entry, &mut result);
+                result.merged.push(merged);
+                result.resolved.push(resolved);
+            }
+            (Some(base_entry), None) => {
+                if entry == *base_entry {
+                    result.changes.push(format!("凭据 #{} 已在外部删除", id));
+                } else {
+                    result
+                        .conflicts
+                        .push(format!("凭据 #{} 已在外部删除，但内存中有修改", id));
+                    result.merged.push(entry.clone());
+                }
+            }
+            (None, Some(external_entry)) => {
+                if entry != *external_entry {
+                    result
+                        .conflicts
+                        .push(format!("凭据 #{} 在内存与外部同时新增，内容不同", id));
+                }
+                result.merged.push(entry.clone());
+                result.resolved.push((*external_entry).clone());
+            }
+            (None, None) => result.push(entry.clone()),
+        }
+    }
+
+    for entry in external {
+        let id = credential_id(entry);
+        if id.is_some_and(|id| ours_by_id.contains_key(&id)) {
+            continue;
+        }
+        match id.and_then(|id| base_by_id.get(&id).map(|base_entry| (id, base_entry))) {
+            // 内存中已删除
+            Some((id, base_entry)) => {
+                if entry != *base_entry {
+                    result
+                        .conflicts
+                        .push(format!("凭据 #{} 已在内存中删除，但外部有修改", id));
+                    result.resolved.push(entry.clone());
+                }
+            }
+            None => {
+                result.changes.push(match id {
+                    Some(id) => format!("外部新增凭据 #{}", id),
+                    None => "外部新增未分配 ID 的凭据".to_string(),
+                });
+                result.push(entry.clone());
+            }
+        }
+    }
+
+    result
+}
+
+/// 逐字段合并同一凭据，返回（合并结果，以外部为准解决冲突后的结果）
+fn merge_entry(
+    id: u64,
+    base: &Value,
+    ours: &Value,
+    external: &Value,
+    result: &mut CredentialsMerge,
+) -> (Value, Value) {
+    let fields = |value: &Value| value.as_object().cloned().unwrap_or_default();
+    let (base, ours, external) = (fields(base), fields(ours), fields(external));
+
+    // 保持内存快照中的字段顺序，其余字段按名称追加在末尾
+    let others: BTreeSet<&String> = base
+        .keys()
+        .chain(external.keys())
+        .filter(|key| !ours.contains_key(*key))
+        .collect();
+    let mut merged = Map::new();
+    let mut resolved = Map::new();
+    for key in ours.keys().chain(others) {
+        let (b, o, e) = (base.get(key), ours.get(key), external.get(key));
+        let value = if o == e || e == b {
+            o
+        } else if o == b {
+            result
+                .changes
+                .push(format!("凭据 #{} 的 {} 采用外部修改", id, key));
+            e
+        } else if TOKEN_FIELDS.contains(&key.as_str()) {
+            result.changes.push(format!(
+                "凭据 #{} 的 {} 两侧均有修改，保留内存中刷新后的值",
+                id, key
+            ));
+            o
+        } else {
+            result.conflicts.push(format!(
+                "凭据 #{} 的 {} 在内存与外部均被修改为不同的值",
+                id, key
+            ));
+            if let Some(value) = e {
+                resolved.insert(key.clone(), value.clone());
+            }
+            if let Some(value) = o {
+                merged.insert(key.clone(), value.clone());
+            }
+            continue;
+        };
+        if let Some(value) = value {
+            merged.insert(key.clone(), value.clone());
+            resolved.insert(key.clone(), value.clone());
+        }
+    }
+    (Value::Object(merged), Value::Object(resolved))
+}
+
+fn credential_id(entry: &Value) -> Option<u64> {
+    entry.get("id").and_then(Value::as_u64)
+}
+
+fn index_by_id(entries: &[Value]) -> HashMap<u64, &Value> {
+    entries
+        .iter()
+        .filter_map(|entry| credential_id(entry).map(|id| (id, entry)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn base() -> Vec<Value> {
+        vec![
+            json!({
+                "id": 1,
+                "refreshToken": "r1",
+                "accessToken": "a1",
+                "expiresAt": "2025-01-01T00:00:00Z",
+                "priority": 0
+            }),
+            json!({ "id": 2, "refreshToken": "r2", "priority": 1 }),
+        ]
+    }
+
+    #[test]
+    fn test_merge_without_external_changes_takes_ours() {
+        let mut ours = base();
+        ours[0]["accessToken"] = json!("a1-new");
+        ours[1]["priority"] = json!(5);
+
+        let result = merge_credentials(&base(), &ours, &base());
+        assert!(result.is_clean());
+        assert!(result.changes.is_empty());
+        assert_eq!(result.merged, ours);
+    }
+
+    #[test]
+    fn test_merge_external_edit_wins_for_untouched_fields() {
+        let ours = base();
+        let mut external = base();
+        external[1]["priority"] = json!(9);
+        external[1]["email"] = json!("ops@example.com");
+        external[0].as_object_mut().unwrap().remove("expiresAt");
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean(), "{:?}", result.conflicts);
+        assert_eq!(result.merged[1]["priority"], 9);
+        assert_eq!(result.merged[1]["email"], "ops@example.com");
+        // 外部删除的字段同样生效
+        assert!(result.merged[0].get("expiresAt").is_none());
+        assert_eq!(
+            result.changes,
+            vec![
+                "凭据 #1 的 expiresAt 采用外部修改",
+                "凭据 #2 的 priority 采用外部修改",
+                "凭据 #2 的 email 采用外部修改",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_combines_disjoint_edits_on_same_credential() {
+        let mut ours = base();
+        ours[0]["accessToken"] = json!("a1-new");
+        ours[0]["expiresAt"] = json!("2025-02-01T00:00:00Z");
+        let mut external = base();
+        external[0]["priority"] = json!(3);
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean());
+        assert_eq!(
+            result.merged[0],
+            json!({
+                "id": 1,
+                "refreshToken": "r1",
+                "accessToken": "a1-new",
+                "expiresAt": "2025-02-01T00:00:00Z",
+                "priority": 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_refreshed_tokens_win_over_external_token_edits() {
+        let mut ours = base();
+        ours[0]["refreshToken"] = json!("r1-rotated");
+        ours[0]["accessToken"] = json!("a1-new");
+        let mut external = base();
+        external[0]["refreshToken"] = json!("r1-pasted");
+        external[0]["accessToken"] = json!("a1-pasted");
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean());
+        assert_eq!(result.merged[0]["refreshToken"], "r1-rotated");
+        assert_eq!(result.merged[0]["accessToken"], "a1-new");
+        assert_eq!(result.changes.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_same_change_on_both_sides_is_not_a_conflict() {
+        let mut ours = base();
+        ours[1]["priority"] = json!(7);
+        let external = ours.clone();
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean());
+        assert!(result.changes.is_empty());
+        assert_eq!(result.merged, ours);
+        assert_eq!(result.resolved, ours);
+    }
+
+    #[test]
+    fn test_merge_conflicting_non_token_field() {
+        let mut ours = base();
+        ours[1]["priority"] = json!(4);
+        let mut external = base();
+        external[1]["priority"] = json!(8);
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(!result.is_clean());
+        assert_eq!(
+            result.conflicts,
+            vec!["凭据 #2 的 priority 在内存与外部均被修改为不同的值"]
+        );
+        assert_eq!(result.merged[1]["priority"], 4);
+        assert_eq!(result.resolved[1]["priority"], 8);
+    }
+
+    #[test]
+    fn test_resolved_prefers_external_but_keeps_refreshed_tokens() {
+        let mut ours = base();
+        ours[0]["refreshToken"] = json!("r1-rotated");
+        ours[0]["priority"] = json!(4);
+        ours[1]["accessToken"] = json!("a2-new");
+        ours.push(json!({ "id": 3, "refreshToken": "ours" }));
+        let mut external = vec![base()[0].clone()];
+        external[0]["priority"] = json!(8);
+        external.push(json!({ "id": 3, "refreshToken": "theirs" }));
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert_eq!(result.conflicts.len(), 3);
+        assert_eq!(
+            result.resolved,
+            vec![
+                json!({
+                    "id": 1,
+                    "refreshToken": "r1-rotated",
+                    "accessToken": "a1",
+                    "expiresAt": "2025-01-01T00:00:00Z",
+                    "priority": 8
+                }),
+                json!({ "id": 3, "refreshToken": "theirs" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_when_field_removed_externally_and_changed_in_memory() {
+        let mut ours = base();
+        ours[1]["priority"] = json!(4);
+        let mut external = base();
+        external[1].as_object_mut().unwrap().remove("priority");
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert_eq!(result.conflicts.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_external_addition_is_kept() {
+        let ours = base();
+        let mut external = base();
+        external.push(json!({ "id": 3, "refreshToken": "r3" }));
+        external.push(json!({ "refreshToken": "r4" }));
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean());
+        assert_eq!(result.merged.len(), 4);
+        assert_eq!(result.merged[2]["id"], 3);
+        assert_eq!(result.merged[3]["refreshToken"], "r4");
+        assert_eq!(
+            result.changes,
+            vec!["外部新增凭据 #3", "外部新增未分配 ID 的凭据"]
+        );
+    }
+
+    #[test]
+    fn test_merge_external_deletion_of_unchanged_credential() {
+        let ours = base();
+        let external = vec![base()[0].clone()];
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert!(result.is_clean());
+        assert_eq!(result.merged, external);
+        assert_eq!(result.changes, vec!["凭据 #2 已在外部删除"]);
+    }
+
+    #[test]
+    fn test_merge_external_deletion_of_changed_credential_conflicts() {
+        let mut ours = base();
+        ours[1]["accessToken"] = json!("a2-new");
+        let external = vec![base()[0].clone()];
+
+        let result = merge_credentials(&base(), &ours, &external);
+        assert_eq!(
+            result.conflicts,
+            vec!["凭据 #2 已在外部删除，但内存中有修改"]
+        );
+    }
+
+    #[test]
+    fn test_merge_our_deletion() {
+        let ours = vec![base()[0].clone()];
+
+        // 外部未修改被删除的凭据：删除生效
+        let result = merge_credentials(&base(), &ours, &base());
+        assert!(result.is_clean());
+        assert_eq!(result.merged, ours);
+
+        // 外部修改了被删除的凭据：冲突
+        let mut external = base();
+        external[1]["priority"] = json!(2);
+        let result = merge_credentials(&base(), &ours, &external);
+        assert_eq!(
+            result.conflicts,
+            vec!["凭据 #2 已在内存中删除，但外部有修改"]
+        );
+    }
+
+    #[test]
+    fn test_merge_our_addition_and_id_collision() {
+        let mut ours = base();
+        ours.push(json!({ "id": 3, "refreshToken": "ours" }));
+
+        let result = merge_credentials(&base(), &ours, &base());
+        assert!(result.is_clean());
+        assert_eq!(result.merged, ours);
+
+        // 外部以相同 ID 新增了不同的凭据
+        let mut external = base();
+        external.push(json!({ "id": 3, "refreshToken": "theirs" }));
+        let result = merge_credentials(&base(), &ours, &external);
+        assert_eq!(
+            result.conflicts,
+            vec!["凭据 #3 在内存与外部同时新增，内容不同"]
+        );
+
+        // 相同内容不算冲突
+        let result = merge_credentials(&base(), &ours, &ours);
+        assert!(result.is_clean());
+        assert_eq!(result.merged, ours);
+    }
+
+    #[test]
+    fn test_merge_changes_never_include_values() {
+        let mut ours = base();
+        ours[0]["refreshToken"] = json!("secret-ours");
+        let mut external = base();
+        external[0]["refreshToken"] = json!("secret-theirs");
+        external[1]["clientSecret"] = json!("secret-client");
+
+        let result = merge_credentials(&base(), &ours, &external);
+        let summary = format!("{:?}{:?}", result.changes, result.conflicts);
+        assert!(!summary.contains("secret"), "{}", summary);
+    }
+}
@@ -0,0 +1,500 @@
+//! 凭据文件单写者持久化
+//!
+//! Token 刷新、Admin 修改与统计路径都可能并发回写凭据文件，各自独立写整个文件时
+//! 内容可能交错导致文件损坏。[`CredentialsWriter`] 由唯一的 tokio 任务持有文件路径：
+//! - 调用方通过 mpsc 通道提交序列化好的快照，提交不阻塞
+//! - 写入期间到达的多个快照合并，只写最新的一份
+//! - 写入采用临时文件 + rename，文件在任何时刻都是完整的 JSON
+//! - [`CredentialsWriter::flush`] 等待此前提交的快照全部落盘（用于关闭与需要确认落盘的 Admin 操作）
+//! - 写入前经 [`ExternalEditGuard`] 检查文件是否在外部被修改，避免覆盖外部编辑
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::kiro::credentials_merge::{CredentialsMerge, merge_credentials};
+use crate::kiro::token_manager::sha256_hex;
+
+/// 外部修改保护
+///
+/// 记录上一份写入的内存快照与文件内容的哈希。回写前重新读取文件：
+/// - 文件内容与上一份快照一致时直接写入新快照
+/// - 否则说明文件在外部被修改过（或保留着此前合并进来的外部修改），三方合并后写入，
+///   外部修改首次出现时记录变更摘要
+/// - 合并出现冲突时以外部文件为准写入（Token 字段仍采用内存中刷新后的值），
+///   内存版本写入 `<文件名>.conflict`，冲突说明通过 Admin API 的凭据列表展示
+/// - 外部文件无法解析时保留外部文件，只写入 `.conflict` 并返回错误
+///
+/// 写入的内容与内存快照不同时记录 [`ExternalEdits`]，由调用方同步回内存
+#[derive(Debug, Default)]
+pub struct ExternalEditGuard {
+    /// 上一份写入的内存快照（三方合并的基准）
+    base: Option<String>,
+    /// 最近一次写入或读取到的文件内容的 SHA-256
+    last_hash: Option<String>,
+    /// 最近一次无法自动合并时的冲突说明
+    conflict: Option<String>,
+    /// 尚未同步回内存的外部修改
+    pending_edits: Option<ExternalEdits>,
+    /// 成功写入凭据文件的次数
+    writes: u64,
+}
+
+/// 写入文件的内容与内存快照之间的差异（外部修改或以外部为准解决的冲突）
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalEdits {
+    /// 回写时的内存快照
+    pub ours: Vec<Value>,
+    /// 实际写入文件的凭据
+    pub written: Vec<Value>,
+    /// 回写时的内存快照原文
+    snapshot: String,
+    /// 实际写入文件的内容
+    content: String,
+}
+
+impl ExternalEditGuard {
+    /// 以启动时加载的文件内容为基准
+    pub fn loaded(content: String) -> Self {
+        let mut guard = Self::default();
+        guard.rebase(content);
+        guard
+    }
+
+    /// 以当前文件内容为新的基准（本进程以其他方式改写文件后调用，如格式升级）
+    pub fn rebase(&mut self, content: String) {
+        self.last_hash = Some(sha256_hex(&content));
+        self.base = Some(content);
+    }
+
+    /// 最近一次合并冲突的说明（之后的外部修改被自动合并时清除）
+    pub fn conflict(&self) -> Option<&str> {
+        self.conflict.as_deref()
+    }
+
+    /// 成功写入凭据文件的次数（调用方据此判断某一时刻之后的内存状态是否已落盘）
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    /// 取出尚未同步回内存的外部修改（多次写入时只保留最新一次，其已包含此前的修改）
+    pub fn take_external_edits(&mut self) -> Option<ExternalEdits> {
+        self.pending_edits.take()
+    }
+
+    /// 内存已同步外部修改后调用：此后未再写入时以写入文件的内容为新的合并基准，
+    /// 避免之后对同步字段的修改被误判为冲突
+    pub fn mark_synced(&mut self, edits: &ExternalEdits) {
+        if self.base.as_deref() == Some(edits.snapshot.as_str()) {
+            self.base = Some(edits.content.clone());
+        }
+    }
+
+    /// 检查外部修改后写入快照
+    pub fn write(&mut self, path: &Path, snapshot: &str) -> std::io::Result<()> {
+        let current = match std::fs::read_to_string(path) {
+            Ok(current) => Some(current),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let content = match (current, self.base.as_deref()) {
+            (Some(current), Some(base)) if current != base => {
+                let current_hash = sha256_hex(&current);
+                let externally_changed = self.last_hash.as_deref() != Some(current_hash.as_str());
+                match merge_snapshots(base, snapshot, &current) {
+                    Ok(merge) => {
+                        if merge.is_clean() {
+                            if externally_changed {
+                                if !merge.changes.is_empty() {
+                                    tracing::warn!(
+                                        "凭据文件 {:?} 在外部被修改，已合并: {}",
+                                        path,
+                                        merge.changes.join("；")
+                                    );
+                                }
+                                self.conflict = None;
+                            }
+                        } else {
+                            let conflict_path = conflict_path(path);
+                            write_atomic(&conflict_path, snapshot)?;
+                            let message = format!(
+                                "凭据文件在外部被修改且无法自动合并（{}），已以外部文件为准，内存中的版本写入 {:?}",
+                                merge.conflicts.join("；"),
+                                conflict_path
+                            );
+                            tracing::warn!("{}", message);
+                            self.conflict = Some(message);
+                        }
+                        let written = if merge.is_clean() {
+                            merge.merged
+                        } else {
+                            merge.resolved
+                        };
+                        let content = serde_json::to_string_pretty(&written)
+                            .map_err(std::io::Error::other)?;
+                        if content != snapshot {
+                            self.pending_edits =
+                                serde_json::from_str(snapshot)
+                                    .ok()
+                                    .map(|ours| ExternalEdits {
+                                        ours,
+                                        written,
+                                        snapshot: snapshot.to_string(),
+                                        content: content.clone(),
+                                    });
+                        }
+                        content
+                    }
+                    Err(reason) => {
+                        let conflict_path = conflict_path(path);
+                        write_atomic(&conflict_path, snapshot)?;
+                        let message = format!(
+                            "凭据文件在外部被修改且无法自动合并（{}），已保留外部文件，内存中的版本写入 {:?}",
+                            reason, conflict_path
+                        );
+                        self.last_hash = Some(current_hash);
+                        self.conflict = Some(message.clone());
+                        return Err(std::io::Error::other(message));
+                    }
+                }
+            }
+            _ => snapshot.to_string(),
+        };
+
+        write_atomic(path, &content)?;
+        self.last_hash = Some(sha256_hex(&content));
+        self.base = Some(snapshot.to_string());
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+/// 三方合并序列化后的凭据列表，无法解析时返回原因
+fn merge_snapshots(base: &str, ours: &str, external: &str) -> Result<CredentialsMerge, String> {
+    let parse = |content: &str| serde_json::from_str::<Vec<Value>>(content).ok();
+    let (Some(base), Some(ours)) = (parse(base), parse(ours)) else {
+        return Err("无法解析上一次写入的凭据".to_string());
+    };
+    let Some(external) = parse(external) else {
+        return Err("外部修改后的文件不是凭据数组".to_string());
+    };
+    Ok(merge_credentials(&base, &ours, &external))
+}
+
+/// 冲突文件路径：`<文件名>.conflict`
+fn conflict_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.conflict", file_name))
+}
+
+/// 写者任务接收的命令
+enum WriterCommand {
+    /// 写入新的快照
+    Write(String),
+    /// 等待此前的快照落盘，返回最近一次写入的结果
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+/// 凭据文件写者句柄
+#[derive(Clone)]
+pub struct CredentialsWriter {
+    tx: mpsc::UnboundedSender<WriterCommand>,
+}
+
+impl CredentialsWriter {
+    /// 在当前 tokio runtime 中启动写者任务
+    pub fn spawn(path: PathBuf, guard: Arc<Mutex<ExternalEditGuard>>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_writer(path, guard, rx));
+        Self { tx }
+    }
+
+    /// 提交快照（不阻塞）；写者任务已退出时把快照原样返回，由调用方自行处理
+    pub fn submit(&self, snapshot: String) -> Result<(), String> {
+        self.tx
+            .send(WriterCommand::Write(snapshot))
+            .map_err(|e| match e.0 {
+                WriterCommand::Write(snapshot) => snapshot,
+                WriterCommand::Flush(_) => unreachable!(),
+            })
+    }
+
+    /// 等待此前提交的快照全部落盘
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(WriterCommand::Flush(tx))
+            .map_err(|_| anyhow::anyhow!("凭据写入任务已退出"))?;
+        rx.await
+            .map_err(|_| anyhow::anyhow!("凭据写入任务已退出"))?
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+/// 写者任务主循环
+async fn run_writer(
+    path: PathBuf,
+    guard: Arc<Mutex<ExternalEditGuard>>,
+    mut rx: mpsc::UnboundedReceiver<WriterCommand>,
+) {
+    let mut last_result: Result<(), String> = Ok(());
+
+    while let Some(command) = rx.recv().await {
+        // 取出当前已排队的全部命令：快照只保留最新一份，flush 在本轮写入后统一应答
+        let mut latest = None;
+        let mut waiters = Vec::new();
+        let mut next = Some(command);
+        while let Some(command) = next {
+            match command {
+                WriterCommand::Write(snapshot) => latest = Some(snapshot),
+                WriterCommand::Flush(waiter) => waiters.push(waiter),
+            }
+            next = rx.try_recv().ok();
+        }
+
+        if let Some(snapshot) = latest {
+            let target = path.clone();
+            let guard = guard.clone();
+            last_result =
+                match tokio::task::spawn_blocking(move || guard.lock().write(&target, &snapshot))
+                    .await
+                {
+                    Ok(Ok(())) => {
+                        tracing::debug!("已回写凭据到文件: {:?}", path);
+                        Ok(())
+                    }
+                    Ok(Err(e)) => Err(format!("回写凭据文件失败: {:?}: {}", path, e)),
+                    Err(e) => Err(format!("回写凭据文件失败: {:?}: {}", path, e)),
+                };
+            if let Err(e) = &last_result {
+                tracing::error!("{}", e);
+            }
+        }
+
+        for waiter in waiters {
+            let _ = waiter.send(last_result.clone());
+        }
+    }
+}
+
+/// 原子写入：先写同目录下的临时文件并 fsync，再 rename 覆盖目标文件（保留原文件权限）
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    write_atomic_with_mode(path, contents, false)
+}
+
+/// 原子写入仅所有者可读写（Unix 下为 0600）的文件，用于包含明文令牌的副本
+pub fn write_atomic_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    write_atomic_with_mode(path, contents, true)
+}
+
+fn write_atomic_with_mode(path: &Path, contents: &str, private: bool) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+
+    let result = (|| {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp_path)?;
+        if !private && let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("kiro-writer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        (dir, path)
+    }
+
+    fn snapshot(i: usize) -> String {
+        let credentials: Vec<serde_json::Value> = (0..=i % 7)
+            .map(|id| serde_json::json!({ "id": id, "seq": i, "refreshToken": "r".repeat(200 + i % 1000) }))
+            .collect();
+        serde_json::to_string_pretty(&credentials).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_snapshots_leave_last_snapshot_on_disk() {
+        let (dir, path) = temp_path("credentials.json");
+        let writer = CredentialsWriter::spawn(path.clone(), Arc::default());
+
+        for round in 0..5 {
+            let tasks: Vec<_> = (0..8)
+                .map(|task| {
+                    let writer = writer.clone();
+                    tokio::spawn(async move {
+                        for i in 0..50 {
+                            writer
+                                .submit(snapshot(round * 1000 + task * 50 + i))
+                                .unwrap();
+                            if i % 10 == 0 {
+                                tokio::task::yield_now().await;
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            // 写入进行中读取文件：任何时刻都是完整的 JSON
+            let reader_path = path.clone();
+            let reader = tokio::task::spawn_blocking(move || {
+                for _ in 0..50 {
+                    if let Ok(content) = std::fs::read_to_string(&reader_path) {
+                        serde_json::from_str::<serde_json::Value>(&content)
+                            .expect("凭据文件不应出现截断");
+                    }
+                }
+            });
+
+            for task in tasks {
+                task.await.unwrap();
+            }
+            reader.await.unwrap();
+
+            let last = snapshot(round * 1000 + 999_999);
+            writer.submit(last.clone()).unwrap();
+            writer.flush().await.unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), last);
+        }
+
+        // 临时文件均已清理
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(leftovers, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_guard_merges_external_edits() {
+        let (dir, path) = temp_path("credentials.json");
+        let loaded = r#"[{"id":1,"refreshToken":"r1","priority":0},{"id":2,"refreshToken":"r2","priority":1}]"#;
+        std::fs::write(&path, loaded).unwrap();
+        let mut guard = ExternalEditGuard::loaded(loaded.to_string());
+
+        // 外部工具修改了 #2 的优先级，内存中刷新了 #1 的 Token
+        std::fs::write(
+            &path,
+            r#"[{"id":1,"refreshToken":"r1","priority":0},{"id":2,"refreshToken":"r2","priority":5}]"#,
+        )
+        .unwrap();
+        let ours = r#"[{"id":1,"refreshToken":"r1-new","priority":0},{"id":2,"refreshToken":"r2","priority":1}]"#;
+        guard.write(&path, ours).unwrap();
+
+        let saved: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["refreshToken"], "r1-new");
+        assert_eq!(saved[1]["priority"], 5);
+        assert!(guard.conflict().is_none());
+
+        // 后续回写继续保留此前合并进来的外部修改
+        let ours = r#"[{"id":1,"refreshToken":"r1-newer","priority":0},{"id":2,"refreshToken":"r2","priority":1}]"#;
+        guard.write(&path, ours).unwrap();
+        let saved: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["refreshToken"], "r1-newer");
+        assert_eq!(saved[1]["priority"], 5);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_guard_resolves_conflict_with_external_edit() {
+        let (dir, path) = temp_path("credentials.json");
+        let loaded = r#"[{"id":1,"refreshToken":"r1","priority":0}]"#;
+        std::fs::write(&path, loaded).unwrap();
+        let mut guard = ExternalEditGuard::loaded(loaded.to_string());
+
+        let external = r#"[{"id":1,"refreshToken":"r1","priority":7}]"#;
+        std::fs::write(&path, external).unwrap();
+        let ours = r#"[{"id":1,"refreshToken":"r1-new","priority":3}]"#;
+        guard.write(&path, ours).unwrap();
+
+        // 冲突字段采用外部值，刷新后的 Token 仍然落盘
+        let saved: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["priority"], 7);
+        assert_eq!(saved[0]["refreshToken"], "r1-new");
+        let conflict_path = dir.join("credentials.json.conflict");
+        assert_eq!(std::fs::read_to_string(&conflict_path).unwrap(), ours);
+        assert!(guard.conflict().unwrap().contains("priority"));
+
+        let edits = guard.take_external_edits().unwrap();
+        assert_eq!(edits.ours[0]["priority"], 3);
+        assert_eq!(edits.written, saved);
+        assert!(guard.take_external_edits().is_none());
+
+        // 基准已前移：内存尚未同步时的后续回写也能继续合并
+        let ours = r#"[{"id":1,"refreshToken":"r1-newer","priority":3}]"#;
+        guard.write(&path, ours).unwrap();
+        let saved: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["priority"], 7);
+        assert_eq!(saved[0]["refreshToken"], "r1-newer");
+
+        // 之后的外部修改被自动合并时冲突警告消失
+        std::fs::write(
+            &path,
+            r#"[{"id":1,"refreshToken":"r1-newer","priority":7,"email":"ops@example.com"}]"#,
+        )
+        .unwrap();
+        guard.write(&path, ours).unwrap();
+        assert!(guard.conflict().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_guard_conflict_when_external_file_is_not_an_array() {
+        let (dir, path) = temp_path("credentials.json");
+        let loaded = r#"[{"id":1,"refreshToken":"r1"}]"#;
+        std::fs::write(&path, loaded).unwrap();
+        let mut guard = ExternalEditGuard::loaded(loaded.to_string());
+
+        std::fs::write(&path, "{ broken").unwrap();
+        assert!(guard.write(&path, loaded).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{ broken");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flush_reports_write_failure() {
+        let path = std::env::temp_dir()
+            .join(format!("kiro-writer-missing-{}", uuid::Uuid::new_v4()))
+            .join("credentials.json");
+        let writer = CredentialsWriter::spawn(path, Arc::default());
+
+        writer.flush().await.unwrap();
+        writer.submit(snapshot(1)).unwrap();
+        let err = writer.flush().await.unwrap_err();
+        assert!(err.to_string().contains("回写凭据文件失败"));
+    }
+}
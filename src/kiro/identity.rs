@@ -0,0 +1,97 @@
+//! 请求身份标识
+//!
+//! 上游会比对 machineId、Kiro 版本与 User-Agent：同一凭据在 Token 刷新、额度查询与
+//! 对话生成中必须携带相同的 `KiroIDE-{version}-{machineId}` 标识，否则可能触发异常检测。
+//! [`RequestIdentity`] 按凭据构建一次，各调用路径的 User-Agent 统一从这里派生，
+//! 不再各自拼接字符串。
+
+use uuid::Uuid;
+
+use crate::kiro::machine_id;
+use crate::kiro::model::credentials::KiroCredentials;
+use crate::model::config::Config;
+
+/// codewhispererstreaming（对话生成、MCP）使用的 SDK 版本
+const STREAMING_SDK_VERSION: &str = "1.0.27";
+
+/// getUsageLimits API 所需的 x-amz-user-agent header 前缀
+const USAGE_LIMITS_AMZ_USER_AGENT_PREFIX: &str = "aws-sdk-js/1.0.0";
+
+/// 凭据对应的请求身份（machineId、Kiro 版本及派生的 User-Agent）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestIdentity {
+    machine_id: String,
+    kiro_version: String,
+    system_version: String,
+    node_version: String,
+}
+
+impl RequestIdentity {
+    /// 按凭据与配置构建身份（无法生成 machineId 时返回错误）
+    pub fn from_credentials(
+        credentials: &KiroCredentials,
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        let machine_id = machine_id::generate_from_credentials(credentials, config)
+            .ok_or_else(|| anyhow::anyhow!("无法生成 machineId，请检查凭证配置"))?;
+        Ok(Self {
+            machine_id,
+            kiro_version: config.kiro_version.clone(),
+            system_version: config.system_version.clone(),
+            node_version: config.node_version.clone(),
+        })
+    }
+
+    pub fn machine_id(&self) -> &str {
+        &self.machine_id
+    }
+
+    /// 所有 User-Agent 共用的 `KiroIDE-{version}-{machineId}` 标识
+    pub fn ide_token(&self) -> String {
+        format!("KiroIDE-{}-{}", self.kiro_version, self.machine_id)
+    }
+
+    /// Social Token 刷新使用的 User-Agent
+    pub fn refresh_user_agent(&self) -> String {
+        self.ide_token()
+    }
+
+    /// 对话生成 / MCP 请求的 x-amz-user-agent
+    pub fn streaming_amz_user_agent(&self) -> String {
+        format!("aws-sdk-js/{} {}", STREAMING_SDK_VERSION, self.ide_token())
+    }
+
+    /// 对话生成 / MCP 请求的 User-Agent
+    pub fn streaming_user_agent(&self) -> String {
+        format!(
+            "aws-sdk-js/{sdk} ua/2.1 os/{} lang/js md/nodejs#{} api/codewhispererstreaming#{sdk} m/E {}",
+            self.system_version,
+            self.node_version,
+            self.ide_token(),
+            sdk = STREAMING_SDK_VERSION,
+        )
+    }
+
+    /// getUsageLimits 请求的 x-amz-user-agent
+    pub fn usage_amz_user_agent(&self) -> String {
+        format!(
+            "{} {}",
+            USAGE_LIMITS_AMZ_USER_AGENT_PREFIX,
+            self.ide_token()
+        )
+    }
+
+    /// getUsageLimits 请求的 User-Agent
+    pub fn usage_user_agent(&self) -> String {
+        format!(
+            "aws-sdk-js/1.0.0 ua/2.1 os/darwin#24.6.0 lang/js md/nodejs#22.21.1 \
+             api/codewhispererruntime#1.0.0 m/N,E {}",
+            self.ide_token()
+        )
+    }
+
+    /// 生成新的 amz-sdk-invocation-id（每次请求一个）
+    pub fn new_invocation_id(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
@@ -1,10 +1,18 @@
 //! 设备指纹生成器
 //!
 
+#[cfg(any(target_os = "linux", test))]
+use std::path::Path;
+use std::sync::OnceLock;
+
 use sha2::{Digest, Sha256};
 
 use crate::kiro::model::credentials::KiroCredentials;
-use crate::model::config::Config;
+use crate::model::config::{Config, MachineIdSource};
+
+/// Linux 机器标识文件（systemd 与 D-Bus 的位置，按顺序尝试）
+#[cfg(target_os = "linux")]
+const LINUX_MACHINE_ID_PATHS: [&str; 2] = ["/etc/machine-id", "/var/lib/dbus/machine-id"];
 
 /// 标准化 machineId 格式
 ///
@@ -32,9 +40,84 @@ fn normalize_machine_id(machine_id: &str) -> Option<String> {
     None
 }
 
+/// 由操作系统的机器标识派生 Machine ID（32 位十六进制）
+///
+/// - Linux: `/etc/machine-id`（或 `/var/lib/dbus/machine-id`）
+/// - macOS: `system_profiler SPHardwareDataType` 输出中的序列号（IOPlatformSerialNumber）
+/// - Windows: 注册表 `HKLM\SOFTWARE\Microsoft\Cryptography\MachineGuid`
+///
+/// 原始标识经 SHA-256 哈希后取前 32 个十六进制字符，不会直接外发。
+/// 读取结果在进程内缓存（macOS / Windows 需要启动子进程）
+pub fn from_filesystem_id() -> Option<String> {
+    static FILESYSTEM_ID: OnceLock<Option<String>> = OnceLock::new();
+    FILESYSTEM_ID
+        .get_or_init(|| platform_id().as_deref().and_then(derive_from_platform_id))
+        .clone()
+}
+
+/// 哈希原始机器标识，取前 32 个十六进制字符
+fn derive_from_platform_id(id: &str) -> Option<String> {
+    let id = id.trim();
+    if id.is_empty() {
+        return None;
+    }
+    Some(sha256_hex(id)[..32].to_string())
+}
+
+/// 读取机器标识文件（不存在或为空时返回 None）
+#[cfg(any(target_os = "linux", test))]
+fn read_id_file(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let id = content.trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn platform_id() -> Option<String> {
+    LINUX_MACHINE_ID_PATHS
+        .iter()
+        .find_map(|path| read_id_file(Path::new(path)))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_id() -> Option<String> {
+    let output = std::process::Command::new("system_profiler")
+        .arg("SPHardwareDataType")
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Serial Number (system):"))
+        .map(|serial| serial.trim().to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_id() -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args([
+            "query",
+            r"HKLM\SOFTWARE\Microsoft\Cryptography",
+            "/v",
+            "MachineGuid",
+        ])
+        .output()
+        .ok()?;
+    // 输出格式: `    MachineGuid    REG_SZ    xxxxxxxx-xxxx-...`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("MachineGuid"))
+        .and_then(|rest| rest.split_whitespace().nth(1).map(str::to_string))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn platform_id() -> Option<String> {
+    None
+}
+
 /// 根据凭证信息生成唯一的 Machine ID
 ///
-/// 优先使用凭据级 machineId，其次使用 config.machineId，然后使用 refreshToken 生成
+/// 优先使用凭据级 machineId，其次使用 config.machineId；
+/// `machineIdSource` 为 `system` 时使用操作系统机器标识，最后使用 refreshToken 生成
 pub fn generate_from_credentials(credentials: &KiroCredentials, config: &Config) -> Option<String> {
     // 如果配置了凭据级 machineId，优先使用
     if let Some(ref machine_id) = credentials.machine_id {
@@ -50,6 +133,17 @@ pub fn generate_from_credentials(credentials: &KiroCredentials, config: &Config)
         }
     }
 
+    // 使用操作系统机器标识生成（不随凭据轮换改变）
+    if config.machine_id_source == MachineIdSource::System {
+        if let Some(normalized) = from_filesystem_id()
+            .as_deref()
+            .and_then(normalize_machine_id)
+        {
+            return Some(normalized);
+        }
+        tracing::warn!("无法读取操作系统机器标识，回退到由 refreshToken 生成 machineId");
+    }
+
     // 使用 refreshToken 生成
     if let Some(ref refresh_token) = credentials.refresh_token {
         if !refresh_token.is_empty() {
@@ -125,6 +219,50 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_filesystem_id_is_derived_from_id_file() {
+        let path = std::env::temp_dir().join(format!("kiro-machine-id-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "4c4c4544004d3510804cb4c04f4d4e32\n").unwrap();
+
+        let id = read_id_file(&path)
+            .as_deref()
+            .and_then(derive_from_platform_id)
+            .unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        // 与文件内容（去除首尾空白）的 SHA-256 前 32 位一致
+        assert_eq!(id, sha256_hex("4c4c4544004d3510804cb4c04f4d4e32")[..32]);
+
+        std::fs::write(&path, "  \n").unwrap();
+        assert_eq!(read_id_file(&path), None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_id_file(&path), None);
+    }
+
+    #[test]
+    fn test_system_source_is_stable_across_credentials() {
+        let Some(filesystem_id) = from_filesystem_id() else {
+            // 当前环境没有可读的机器标识
+            return;
+        };
+        assert_eq!(filesystem_id.len(), 32);
+
+        let mut config = Config::default();
+        config.machine_id_source = MachineIdSource::System;
+        let first = KiroCredentials {
+            refresh_token: Some("first_refresh_token".to_string()),
+            ..Default::default()
+        };
+        let second = KiroCredentials {
+            refresh_token: Some("second_refresh_token".to_string()),
+            ..Default::default()
+        };
+
+        let id = generate_from_credentials(&first, &config).unwrap();
+        assert_eq!(id, format!("{}{}", filesystem_id, filesystem_id));
+        assert_eq!(generate_from_credentials(&second, &config), Some(id));
+    }
+
     #[test]
     fn test_normalize_uuid_format() {
         // UUID 格式应该被转换为 64 字符
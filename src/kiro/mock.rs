@@ -0,0 +1,250 @@
+//! 模拟上游（本地开发与集成测试）
+//!
+//! 开启 `--mock-upstream`（或配置 `mockMode`）后，消息请求不再发往 Kiro，
+//! 由 [`MockProvider`] 根据转换后的 Kiro 请求体生成确定性的 Event Stream 响应：
+//! - 回显最后一条用户消息，按空白分词后最多输出 `max_tokens` 个词
+//! - 请求携带工具且用户消息包含 [`MOCK_TOOL_TRIGGER`] 时，额外输出一次对第一个工具的调用
+//! - 按 `mockLatencyMs` 模拟响应延迟（请求超时短于该延迟时返回超时错误），按 `mockErrorRate` 随机返回上游错误
+//!
+//! 该模式下不加载凭据文件、不创建 Token 管理器。
+
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use crate::kiro::model::requests::kiro::KiroRequest;
+use crate::kiro::model::requests::tool::Tool;
+use crate::kiro::parser::frame::encode_event_frame;
+use crate::kiro::provider::{MessagesCall, Provider};
+use crate::model::config::Config;
+
+/// 触发模拟工具调用的短语（不区分大小写）
+pub const MOCK_TOOL_TRIGGER: &str = "use tool";
+
+/// 模拟工具调用的 ID
+const MOCK_TOOL_USE_ID: &str = "tooluse_mock_0";
+
+/// 用户消息为空时（如仅包含工具结果）的回复
+const EMPTY_MESSAGE_REPLY: &str = "Mock response.";
+
+/// 模拟上游 Provider
+#[derive(Debug, Clone, Default)]
+pub struct MockProvider {
+    /// 每次请求的模拟延迟
+    latency: Duration,
+    /// 模拟上游错误的概率（0.0 - 1.0）
+    error_rate: f64,
+}
+
+impl MockProvider {
+    /// 从配置创建（`mockLatencyMs`、`mockErrorRate`）
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            latency: Duration::from_millis(config.mock_latency_ms),
+            error_rate: config.mock_error_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    /// 生成 Event Stream 格式的响应体
+    fn render(&self, call: MessagesCall<'_>) -> anyhow::Result<Vec<u8>> {
+        let request: KiroRequest = serde_json::from_str(call.request_body)?;
+        let user_message = request
+            .conversation_state
+            .current_message
+            .user_input_message;
+
+        let mut body = Vec::new();
+        let text = if user_message.content.trim().is_empty() {
+            EMPTY_MESSAGE_REPLY.to_string()
+        } else {
+            user_message.content.clone()
+        };
+        let max_words = call.max_tokens.max(1) as usize;
+        for word in text.split_inclusive(char::is_whitespace).take(max_words) {
+            body.extend(encode_event_frame(
+                "assistantResponseEvent",
+                &serde_json::json!({ "content": word }),
+            ));
+        }
+
+        let triggered = user_message
+            .content
+            .to_lowercase()
+            .contains(MOCK_TOOL_TRIGGER);
+        let tool = user_message
+            .user_input_message_context
+            .tools
+            .first()
+            .filter(|_| triggered);
+        if let Some(tool) = tool {
+            body.extend(encode_event_frame(
+                "toolUseEvent",
+                &serde_json::json!({
+                    "name": tool.tool_specification.name,
+                    "toolUseId": MOCK_TOOL_USE_ID,
+                    "input": mock_tool_input(tool).to_string(),
+                    "stop": true
+                }),
+            ));
+        }
+
+        Ok(body)
+    }
+}
+
+impl Provider for MockProvider {
+    fn call_messages<'a>(
+        &'a self,
+        call: MessagesCall<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<reqwest::Response>> {
+        Box::pin(async move {
+            if !self.latency.is_zero() {
+                match call.timeout.filter(|timeout| *timeout < self.latency) {
+                    Some(timeout) => {
+                        tokio::time::sleep(timeout).await;
+                        anyhow::bail!("模拟上游请求超时（{}ms）", timeout.as_millis());
+                    }
+                    None => tokio::time::sleep(self.latency).await,
+                }
+            }
+            if self.error_rate > 0.0 && fastrand::f64() < self.error_rate {
+                anyhow::bail!("模拟上游错误（mockErrorRate）");
+            }
+
+            let body = self.render(call)?;
+            let response = http::Response::builder()
+                .status(http::StatusCode::OK)
+                .header(
+                    http::header::CONTENT_TYPE,
+                    "application/vnd.amazon.eventstream",
+                )
+                .body(body)?;
+            Ok(reqwest::Response::from(response))
+        })
+    }
+}
+
+/// 按工具 schema 的必填字段生成占位参数
+fn mock_tool_input(tool: &Tool) -> serde_json::Value {
+    let schema = &tool.tool_specification.input_schema.json;
+    let properties = schema.get("properties");
+    let required = schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|r| r.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let input = required
+        .into_iter()
+        .map(|name| {
+            let kind = properties
+                .and_then(|p| p.get(name))
+                .and_then(|p| p.get("type"))
+                .and_then(|t| t.as_str());
+            let value = match kind {
+                Some("integer") | Some("number") => serde_json::json!(0),
+                Some("boolean") => serde_json::json!(false),
+                Some("array") => serde_json::json!([]),
+                Some("object") => serde_json::json!({}),
+                _ => serde_json::json!("mock"),
+            };
+            (name.to_string(), value)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::Value::Object(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::events::Event;
+    use crate::kiro::parser::frame::frames;
+
+    fn kiro_body(content: &str, tools: serde_json::Value) -> String {
+        serde_json::json!({
+            "conversationState": {
+                "conversationId": "conv-1",
+                "currentMessage": {
+                    "userInputMessage": {
+                        "content": content,
+                        "modelId": "claude-sonnet-4.5",
+                        "userInputMessageContext": { "tools": tools }
+                    }
+                }
+            }
+        })
+        .to_string()
+    }
+
+    fn decode(body: &[u8]) -> Vec<Event> {
+        frames(body)
+            .map(|frame| Event::from_frame(frame.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn call(body: &str, max_tokens: i32) -> MessagesCall<'_> {
+        MessagesCall {
+            request_body: body,
+            is_stream: true,
+            pinned: None,
+            max_tokens,
+            passthrough_token: None,
+            timeout: None,
+        }
+    }
+
+    #[test]
+    fn test_echo_is_truncated_by_max_tokens() {
+        let body = kiro_body("one two three four", serde_json::json!([]));
+        let events = decode(&MockProvider::default().render(call(&body, 2)).unwrap());
+
+        let text: String = events
+            .iter()
+            .map(|e| match e {
+                Event::AssistantResponse(r) => r.content.clone(),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(text, "one two ");
+    }
+
+    #[test]
+    fn test_tool_use_requires_trigger_and_tools() {
+        let tools = serde_json::json!([{
+            "toolSpecification": {
+                "name": "get_weather",
+                "description": "Get weather",
+                "inputSchema": { "json": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" }, "days": { "type": "integer" } },
+                    "required": ["city", "days"]
+                }}
+            }
+        }]);
+        let provider = MockProvider::default();
+
+        let body = kiro_body("Please USE TOOL now", tools.clone());
+        let events = decode(&provider.render(call(&body, 100)).unwrap());
+        let Some(Event::ToolUse(tool_use)) = events.last() else {
+            panic!("expected tool use, got {:?}", events);
+        };
+        assert_eq!(tool_use.name, "get_weather");
+        assert_eq!(tool_use.tool_use_id, MOCK_TOOL_USE_ID);
+        assert!(tool_use.stop);
+        let input: serde_json::Value = serde_json::from_str(&tool_use.input).unwrap();
+        assert_eq!(input, serde_json::json!({ "city": "mock", "days": 0 }));
+
+        let body = kiro_body("no trigger here", tools);
+        let events = decode(&provider.render(call(&body, 100)).unwrap());
+        assert!(!events.iter().any(|e| matches!(e, Event::ToolUse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_error_rate_one_always_fails() {
+        let mut config = Config::default();
+        config.mock_error_rate = 1.0;
+        let provider = MockProvider::from_config(&config);
+        let body = kiro_body("hi", serde_json::json!([]));
+        assert!(provider.call_messages(call(&body, 16)).await.is_err());
+    }
+}
@@ -1,7 +1,16 @@
 //! Kiro API 客户端模块
 
+pub mod balance_cache;
+pub mod credentials_merge;
+pub mod credentials_writer;
+pub mod identity;
 pub mod machine_id;
+pub mod mock;
 pub mod model;
+pub mod openai;
 pub mod parser;
+pub mod pricing;
 pub mod provider;
+pub mod refresh_limiter;
 pub mod token_manager;
+pub mod user_usage;
@@ -3,13 +3,28 @@
 //! 支持从 Kiro IDE 的凭证文件加载，使用 Social 认证方式
 //! 支持单凭据和多凭据配置格式
 
+use chrono::FixedOffset;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::http_client::ProxyConfig;
 use crate::model::config::Config;
 
+/// 内联 JSON 凭据的环境变量名（设置后优先于凭据文件）
+pub const CREDENTIALS_JSON_ENV: &str = "KIRO_CREDENTIALS_JSON";
+
+/// Base64 编码凭据的环境变量名（未设置 `KIRO_CREDENTIALS_JSON` 时检查）
+pub const CREDENTIALS_BASE64_ENV: &str = "KIRO_CREDENTIALS_BASE64";
+
+/// 环境变量值中标记 Base64 编码的前缀
+const BASE64_PREFIX: &str = "base64:";
+
+/// 测试中读写凭据环境变量时持有，避免并行测试读到其他测试设置的凭据
+#[cfg(test)]
+pub(crate) static CREDENTIALS_ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
 /// Kiro OAuth 凭证
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -93,6 +108,16 @@ pub struct KiroCredentials {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy_password: Option<String>,
 
+    /// 凭据级上游基础 URL（可选，如经由中间服务转发）
+    /// 未配置时按 API Region 拼接官方地址
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_base_url: Option<String>,
+
+    /// 本地月度请求上限（可选，上游额度上报有延迟时的兜底）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub monthly_request_limit: Option<MonthlyRequestLimit>,
+
     /// 凭据是否被禁用（默认为 false）
     #[serde(default)]
     pub disabled: bool,
@@ -103,14 +128,243 @@ fn is_zero(value: &u32) -> bool {
     *value == 0
 }
 
-fn canonicalize_auth_method_value(value: &str) -> &str {
-    if value.eq_ignore_ascii_case("builder-id") || value.eq_ignore_ascii_case("iam") {
-        "idc"
-    } else {
-        value
+/// 日志与错误信息中需要脱敏的凭据字段
+pub const SENSITIVE_FIELDS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "client_secret",
+    "proxy_password",
+];
+
+/// 脱敏后的占位值
+const REDACTED: &str = "[REDACTED]";
+
+impl KiroCredentials {
+    /// 按 [`SENSITIVE_FIELDS`] 的顺序返回敏感字段
+    fn sensitive_fields_mut(&mut self) -> [&mut Option<String>; SENSITIVE_FIELDS.len()] {
+        [
+            &mut self.access_token,
+            &mut self.refresh_token,
+            &mut self.client_secret,
+            &mut self.proxy_password,
+        ]
+    }
+
+    /// 复制一份敏感字段已替换为 `[REDACTED]` 的凭据（未设置的字段保持为 None）
+    fn redacted(&self) -> KiroCredentials {
+        let mut credentials = self.clone();
+        for field in credentials.sensitive_fields_mut() {
+            if field.is_some() {
+                *field = Some(REDACTED.to_string());
+            }
+        }
+        credentials
     }
 }
 
+/// 始终脱敏敏感字段，用于面向用户的错误信息
+impl fmt::Display for KiroCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.redacted(), f)
+    }
+}
+
+/// 用于 DEBUG 日志的凭据包装：除非配置了 `logSensitiveFields`，敏感字段均输出为 `[REDACTED]`
+pub struct LogSafeCredentials<'a> {
+    credentials: &'a KiroCredentials,
+    reveal: bool,
+}
+
+impl<'a> LogSafeCredentials<'a> {
+    pub fn new(credentials: &'a KiroCredentials, config: &Config) -> Self {
+        Self {
+            credentials,
+            reveal: config.log_sensitive_fields,
+        }
+    }
+}
+
+impl fmt::Debug for LogSafeCredentials<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reveal {
+            fmt::Debug::fmt(self.credentials, f)
+        } else {
+            fmt::Debug::fmt(&self.credentials.redacted(), f)
+        }
+    }
+}
+
+/// 凭据级本地月度请求上限
+///
+/// 按 `timezone` 划分自然月统计成功请求数：达到软上限时记录日志并产生 Admin 事件，
+/// 达到硬上限时凭据不再参与选择，直到下个自然月自动恢复
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthlyRequestLimit {
+    /// 软上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_limit: Option<u64>,
+    /// 硬上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_limit: Option<u64>,
+    /// 划分自然月的时区（UTC 偏移，如 `+08:00`），未配置时为 UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl MonthlyRequestLimit {
+    /// 解析时区偏移（未配置时为 UTC）
+    pub fn offset(&self) -> anyhow::Result<FixedOffset> {
+        match self.timezone.as_deref() {
+            None => Ok(FixedOffset::east_opt(0).expect("UTC 偏移有效")),
+            Some(tz) => tz
+                .parse()
+                .map_err(|_| anyhow::anyhow!("timezone 应为 UTC 偏移（如 +08:00）: {}", tz)),
+        }
+    }
+
+    /// 校验时区格式与上限取值
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.offset()?;
+        if self.hard_limit == Some(0) {
+            anyhow::bail!("hardLimit 必须大于 0");
+        }
+        match (self.soft_limit, self.hard_limit) {
+            (Some(soft), Some(hard)) if soft > hard => {
+                anyhow::bail!("softLimit（{}）不能大于 hardLimit（{}）", soft, hard)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 校验凭据级上游基础 URL
+///
+/// 必须以 `https://` 开头（本地回环地址允许 `http://`，便于调试），且不能包含查询参数
+pub fn validate_upstream_base_url(url: &str) -> anyhow::Result<()> {
+    if url.contains('?') || url.contains('#') {
+        anyhow::bail!("upstreamBaseUrl 不能包含查询参数: {}", url);
+    }
+
+    let rest = match url.strip_prefix("https://") {
+        Some(rest) => rest,
+        None => match url.strip_prefix("http://") {
+            Some(rest) if is_loopback_host(upstream_host(rest)) => rest,
+            _ => anyhow::bail!("upstreamBaseUrl 必须以 https:// 开头: {}", url),
+        },
+    };
+    if upstream_host(rest).is_empty() {
+        anyhow::bail!("upstreamBaseUrl 缺少主机名: {}", url);
+    }
+    Ok(())
+}
+
+/// 提取上游基础 URL 中的主机部分（含端口）
+pub fn upstream_host(url: &str) -> &str {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    rest.split('/').next().unwrap_or_default()
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// 凭据字段中包含无法清理的非法字符
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizeError {
+    /// 出错字段（JSON 字段名）
+    pub field: &'static str,
+}
+
+impl fmt::Display for SanitizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "字段 {} 包含非法控制字符", self.field)
+    }
+}
+
+impl std::error::Error for SanitizeError {}
+
+/// 是否为 BOM 或零宽字符（常见于从网页复制的内容）
+fn is_invisible_junk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{feff}' | '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}'
+    )
+}
+
+/// 清理令牌类字段：去除 BOM/零宽字符与首尾空白，内部仍有控制字符时报错
+fn sanitize_token(
+    value: &Option<String>,
+    field: &'static str,
+) -> Result<Option<String>, SanitizeError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let cleaned: String = value.chars().filter(|c| !is_invisible_junk(*c)).collect();
+    let cleaned = cleaned.trim();
+    if cleaned.chars().any(char::is_control) {
+        return Err(SanitizeError { field });
+    }
+    Ok(Some(cleaned.to_string()))
+}
+
+/// 清理文本类字段：去除 BOM/零宽字符、控制字符与首尾空白，清理后为空时视为未配置
+fn sanitize_text(value: &Option<String>) -> Option<String> {
+    let cleaned: String = value
+        .as_deref()?
+        .chars()
+        .filter(|c| !is_invisible_junk(*c) && !c.is_control())
+        .collect();
+    let cleaned = cleaned.trim();
+    (!cleaned.is_empty()).then(|| cleaned.to_string())
+}
+
+/// 规范的认证方式
+const CANONICAL_AUTH_METHODS: &[&str] = &["social", "idc"];
+
+/// 认证方式别名（别名 → 规范名，比较时忽略大小写）
+pub const AUTH_METHOD_ALIASES: &[(&str, &str)] = &[
+    ("iam", "idc"),
+    ("builder-id", "idc"),
+    ("SSO", "idc"),
+    ("oauth", "social"),
+    ("cognito", "social"),
+];
+
+/// 将认证方式（规范名或别名）映射为规范名，无法识别时返回 None
+fn canonicalize_auth_method_value(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    CANONICAL_AUTH_METHODS
+        .iter()
+        .find(|m| m.eq_ignore_ascii_case(value))
+        .copied()
+        .or_else(|| {
+            AUTH_METHOD_ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(value))
+                .map(|(_, canonical)| *canonical)
+        })
+}
+
+/// 旧格式凭据文件迁移结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsMigration {
+    /// 已迁移为数组格式，原文件备份到该路径
+    Migrated { backup_path: PathBuf },
+    /// 文件已是数组格式（或不存在/为空），未做任何修改
+    AlreadyMultiple,
+}
+
 /// 凭据配置（支持单对象或数组格式）
 ///
 /// 自动识别配置文件格式：
@@ -126,12 +380,67 @@ pub enum CredentialsConfig {
 }
 
 impl CredentialsConfig {
-    /// 从文件加载凭据配置
+    /// 加载凭据配置
     ///
+    /// - 设置了 `KIRO_CREDENTIALS_JSON` 或 `KIRO_CREDENTIALS_BASE64` 时从环境变量加载，忽略文件
     /// - 如果文件不存在，返回空数组
     /// - 如果文件内容为空，返回空数组
     /// - 支持单对象或数组格式
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        match Self::env_var_name() {
+            Some(var_name) => Self::from_env_var(var_name),
+            None => Self::load_file(path),
+        }
+    }
+
+    /// 已设置的凭据环境变量名（`KIRO_CREDENTIALS_JSON` 优先），均未设置时返回 None
+    pub fn env_var_name() -> Option<&'static str> {
+        [CREDENTIALS_JSON_ENV, CREDENTIALS_BASE64_ENV]
+            .into_iter()
+            .find(|name| std::env::var_os(name).is_some())
+    }
+
+    /// 从环境变量加载凭据配置
+    ///
+    /// 变量值可以是单对象或数组格式的 JSON，也可以是带 `base64:` 前缀的 Base64 编码；
+    /// `KIRO_CREDENTIALS_BASE64` 的值省略前缀时同样按 Base64 解码
+    pub fn from_env_var(var_name: &str) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let value =
+            std::env::var(var_name).with_context(|| format!("读取环境变量 {} 失败", var_name))?;
+        Self::from_env_value(var_name, &value)
+    }
+
+    /// 解析凭据环境变量的值（见 [`from_env_var`](Self::from_env_var)）
+    pub fn from_env_value(var_name: &str, value: &str) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        use base64::Engine;
+
+        let value = value.trim();
+        let encoded = match value.strip_prefix(BASE64_PREFIX) {
+            Some(encoded) => Some(encoded),
+            None if var_name == CREDENTIALS_BASE64_ENV => Some(value),
+            None => None,
+        };
+        let json = match encoded {
+            Some(encoded) => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .with_context(|| format!("环境变量 {} 不是有效的 Base64", var_name))?;
+                String::from_utf8(bytes)
+                    .with_context(|| format!("环境变量 {} 解码后不是有效的 UTF-8", var_name))?
+            }
+            None => value.to_string(),
+        };
+
+        let config: CredentialsConfig = serde_json::from_str(&json)
+            .with_context(|| format!("环境变量 {} 不是有效的凭据 JSON", var_name))?;
+        config.sanitized()
+    }
+
+    /// 从文件加载凭据配置（见 [`load`](Self::load)，不检查环境变量）
+    fn load_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         let path = path.as_ref();
 
         // 文件不存在时返回空数组
@@ -146,8 +455,26 @@ impl CredentialsConfig {
             return Ok(CredentialsConfig::Multiple(vec![]));
         }
 
-        let config = serde_json::from_str(&content)?;
-        Ok(config)
+        let config: CredentialsConfig = serde_json::from_str(&content)?;
+        config.sanitized()
+    }
+
+    /// 清理所有凭据字段（见 [`KiroCredentials::sanitized`]），错误信息包含凭据序号
+    fn sanitized(self) -> anyhow::Result<Self> {
+        let sanitize = |index: usize, cred: KiroCredentials| {
+            cred.sanitized()
+                .map_err(|e| anyhow::anyhow!("第 {} 个凭据的{}", index + 1, e))
+        };
+        match self {
+            CredentialsConfig::Single(cred) => Ok(CredentialsConfig::Single(sanitize(0, cred)?)),
+            CredentialsConfig::Multiple(creds) => Ok(CredentialsConfig::Multiple(
+                creds
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, cred)| sanitize(i, cred))
+                    .collect::<anyhow::Result<_>>()?,
+            )),
+        }
     }
 
     /// 转换为按优先级排序的凭据列表
@@ -188,15 +515,107 @@ impl CredentialsConfig {
     pub fn is_multiple(&self) -> bool {
         matches!(self, CredentialsConfig::Multiple(_))
     }
+
+    /// 校验凭据内容，返回发现的全部问题（为空表示校验通过）
+    ///
+    /// 检查 refreshToken 缺失、IdC 凭据缺少 clientId/clientSecret、upstreamBaseUrl 无效、
+    /// 优先级为内部保留值以及凭据 ID 重复
+    pub fn validate(&self) -> Vec<String> {
+        let creds = match self {
+            CredentialsConfig::Single(cred) => std::slice::from_ref(cred),
+            CredentialsConfig::Multiple(creds) => creds.as_slice(),
+        };
+        let mut errors = Vec::new();
+        let mut seen_ids = std::collections::HashSet::new();
+        for (i, cred) in creds.iter().enumerate() {
+            let label = format!("第 {} 个凭据", i + 1);
+            if cred
+                .refresh_token
+                .as_deref()
+                .is_none_or(|t| t.trim().is_empty())
+            {
+                errors.push(format!("{}缺少 refreshToken", label));
+            }
+            if cred.canonical_auth_method() == "idc"
+                && (cred.client_id.is_none() || cred.client_secret.is_none())
+            {
+                errors.push(format!(
+                    "{}为 IdC 认证，但缺少 clientId 或 clientSecret",
+                    label
+                ));
+            }
+            if let Some(Err(e)) = cred
+                .upstream_base_url
+                .as_deref()
+                .map(validate_upstream_base_url)
+            {
+                errors.push(format!("{}的 upstreamBaseUrl 无效: {}", label, e));
+            }
+            if let Some(Err(e)) = cred
+                .monthly_request_limit
+                .as_ref()
+                .map(MonthlyRequestLimit::validate)
+            {
+                errors.push(format!("{}的 monthlyRequestLimit 无效: {}", label, e));
+            }
+            if cred.priority == u32::MAX {
+                errors.push(format!("{}的 priority {} 为内部保留值", label, u32::MAX));
+            }
+            if let Some(id) = cred.id.filter(|id| !seen_ids.insert(*id)) {
+                errors.push(format!("{}的 id {} 与其他凭据重复", label, id));
+            }
+        }
+        errors
+    }
+
+    /// 将旧版单对象凭据文件迁移为单元素数组格式
+    ///
+    /// 迁移时补全 id 与 machineId，原文件备份为 `<文件名>.bak`；
+    /// 文件已是数组格式时不做任何修改，因此可重复执行
+    pub fn migrate_to_multiple<P: AsRef<Path>>(
+        path: P,
+        config: &Config,
+    ) -> anyhow::Result<CredentialsMigration> {
+        use anyhow::Context;
+
+        let path = path.as_ref();
+        let mut cred = match Self::load_file(path)? {
+            CredentialsConfig::Multiple(_) => return Ok(CredentialsMigration::AlreadyMultiple),
+            CredentialsConfig::Single(cred) => cred,
+        };
+
+        cred.id.get_or_insert(1);
+        if cred.machine_id.is_none() {
+            cred.machine_id = crate::kiro::machine_id::generate_from_credentials(&cred, config);
+        }
+        let json = serde_json::to_string_pretty(&[cred]).context("序列化凭据失败")?;
+
+        let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+        backup_name.push(".bak");
+        let backup_path = path.with_file_name(backup_name);
+        fs::copy(path, &backup_path)
+            .with_context(|| format!("备份凭据文件失败: {:?}", backup_path))?;
+        fs::write(path, json).with_context(|| format!("写入凭据文件失败: {:?}", path))?;
+
+        Ok(CredentialsMigration::Migrated { backup_path })
+    }
 }
 
 impl KiroCredentials {
     /// 特殊值：显式不使用代理
     pub const PROXY_DIRECT: &'static str = "direct";
 
-    /// 获取默认凭证文件路径
-    pub fn default_credentials_path() -> &'static str {
-        "credentials.json"
+    /// 获取默认凭证文件路径（与默认配置文件位于同一目录）
+    ///
+    /// 设置了 `KIRO_CREDENTIALS_JSON` 或 `KIRO_CREDENTIALS_BASE64` 时凭据从环境变量加载，
+    /// 返回 None，见 [`CredentialsConfig::load`]
+    pub fn default_credentials_path() -> Option<PathBuf> {
+        match CredentialsConfig::env_var_name() {
+            Some(_) => None,
+            None => Some(crate::model::config::default_config_file(
+                "credentials.json",
+            )),
+        }
     }
 
     /// 获取有效的 Auth Region（用于 Token 刷新）
@@ -235,6 +654,17 @@ impl KiroCredentials {
         }
     }
 
+    /// 获取凭据级上游基础 URL（已去除末尾的 `/`），未配置时返回 None
+    pub fn upstream_base(&self) -> anyhow::Result<Option<&str>> {
+        match self.upstream_base_url.as_deref() {
+            Some(url) => {
+                validate_upstream_base_url(url)?;
+                Ok(Some(url.trim_end_matches('/')))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// 从 JSON 字符串解析凭证
     pub fn from_json(json_string: &str) -> Result<Self, serde_json::Error> {
         serde_json::from_str(json_string)
@@ -255,31 +685,98 @@ impl KiroCredentials {
         serde_json::to_string_pretty(self)
     }
 
-    pub fn canonicalize_auth_method(&mut self) {
-        let auth_method = match &self.auth_method {
-            Some(m) => m,
-            None => return,
-        };
+    /// 清理从外部粘贴的字段值
+    ///
+    /// - refreshToken / clientId / clientSecret：去除 BOM、零宽字符与首尾空白，
+    ///   内部仍有控制字符（如换行）时返回错误
+    /// - email / region / authRegion / apiRegion：去除 BOM、零宽字符、控制字符与首尾空白，
+    ///   清理后为空时视为未配置
+    /// - email 统一转为小写，便于展示与去重
+    pub fn sanitized(&self) -> Result<Self, SanitizeError> {
+        Ok(Self {
+            refresh_token: sanitize_token(&self.refresh_token, "refreshToken")?,
+            client_id: sanitize_token(&self.client_id, "clientId")?.filter(|v| !v.is_empty()),
+            client_secret: sanitize_token(&self.client_secret, "clientSecret")?
+                .filter(|v| !v.is_empty()),
+            email: sanitize_text(&self.email).map(|e| e.to_lowercase()),
+            region: sanitize_text(&self.region),
+            auth_region: sanitize_text(&self.auth_region),
+            api_region: sanitize_text(&self.api_region),
+            ..self.clone()
+        })
+    }
 
-        let canonical = canonicalize_auth_method_value(auth_method);
-        if canonical != auth_method {
+    /// 将 authMethod 别名原地替换为规范名（无法识别的值保持不变）
+    pub fn canonicalize_auth_method(&mut self) {
+        let canonical = self
+            .auth_method
+            .as_deref()
+            .and_then(canonicalize_auth_method_value);
+        if let Some(canonical) = canonical {
             self.auth_method = Some(canonical.to_string());
         }
     }
 
+    /// 规范的认证方式（`social` 或 `idc`）
+    ///
+    /// 未配置时按是否存在 clientId/clientSecret 推断；无法识别时按 `social` 处理
+    pub fn canonical_auth_method(&self) -> &'static str {
+        match self.auth_method.as_deref() {
+            Some(method) => canonicalize_auth_method_value(method).unwrap_or("social"),
+            None if self.client_id.is_some() && self.client_secret.is_some() => "idc",
+            None => "social",
+        }
+    }
+
+    /// authMethod 是否为可识别的规范名或别名（未配置视为可识别）
+    pub fn has_known_auth_method(&self) -> bool {
+        self.auth_method
+            .as_deref()
+            .is_none_or(|m| canonicalize_auth_method_value(m).is_some())
+    }
+
     /// 检查凭据是否支持 Opus 模型
     ///
     /// Free 账号不支持 Opus 模型，需要 PRO 或更高等级订阅
     pub fn supports_opus(&self) -> bool {
-        match &self.subscription_title {
-            Some(title) => {
-                let title_upper = title.to_uppercase();
-                // 如果包含 FREE，则不支持 Opus
-                !title_upper.contains("FREE")
-            }
-            // 如果还没有获取订阅信息，暂时允许（首次使用时会获取）
-            None => true,
-        }
+        subscription_supports_opus(self.subscription_title.as_deref())
+    }
+
+    /// 选择顺序的排序键：`(priority, id)`，优先级相同时 ID 小的优先，未分配 ID 的排在最后
+    pub fn sort_key(&self) -> (u32, u64) {
+        (self.priority, self.id.unwrap_or(u64::MAX))
+    }
+}
+
+/// 按 [`KiroCredentials::sort_key`] 比较，忽略 Token 等其他字段
+impl PartialEq for KiroCredentials {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for KiroCredentials {}
+
+impl PartialOrd for KiroCredentials {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KiroCredentials {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// 检查订阅类型是否支持 Opus 模型
+///
+/// 未获取订阅信息时暂时允许（首次使用时会获取）
+pub fn subscription_supports_opus(subscription_title: Option<&str>) -> bool {
+    match subscription_title {
+        // 如果包含 FREE，则不支持 Opus
+        Some(title) => !title.to_uppercase().contains("FREE"),
+        None => true,
     }
 }
 
@@ -288,6 +785,38 @@ mod tests {
     use super::*;
     use crate::model::config::Config;
 
+    #[test]
+    fn test_log_safe_credentials_redacts_tokens() {
+        let creds = KiroCredentials {
+            access_token: Some("secret-access-token".to_string()),
+            refresh_token: Some("secret-refresh-token".to_string()),
+            client_secret: Some("secret-client".to_string()),
+            proxy_password: Some("secret-proxy".to_string()),
+            client_id: Some("visible-client-id".to_string()),
+            ..Default::default()
+        };
+        let mut config = Config::default();
+
+        let output = format!("{:?}", LogSafeCredentials::new(&creds, &config));
+        assert!(
+            output.contains("access_token: Some(\"[REDACTED]\")"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("secret-"), "{}", output);
+        assert!(output.contains("visible-client-id"), "{}", output);
+        assert!(output.contains("profile_arn: None"), "{}", output);
+
+        config.log_sensitive_fields = true;
+        let output = format!("{:?}", LogSafeCredentials::new(&creds, &config));
+        assert!(output.contains("secret-access-token"), "{}", output);
+
+        // Display 始终脱敏
+        let output = creds.to_string();
+        assert_eq!(output.matches(REDACTED).count(), SENSITIVE_FIELDS.len());
+        assert!(!output.contains("secret-"), "{}", output);
+    }
+
     #[test]
     fn test_from_json() {
         let json = r#"{
@@ -338,6 +867,8 @@ mod tests {
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
+            upstream_base_url: None,
+            monthly_request_limit: None,
             disabled: false,
         };
 
@@ -351,10 +882,19 @@ mod tests {
 
     #[test]
     fn test_default_credentials_path() {
+        let _env = CREDENTIALS_ENV_LOCK.blocking_lock();
         assert_eq!(
-            KiroCredentials::default_credentials_path(),
-            "credentials.json"
+            KiroCredentials::default_credentials_path()
+                .unwrap()
+                .file_name(),
+            Some(std::ffi::OsStr::new("credentials.json"))
         );
+
+        // SAFETY: 持有 CREDENTIALS_ENV_LOCK，读取凭据环境变量的测试不会并行执行
+        unsafe { std::env::set_var(CREDENTIALS_BASE64_ENV, "e30=") };
+        let path = KiroCredentials::default_credentials_path();
+        unsafe { std::env::remove_var(CREDENTIALS_BASE64_ENV) };
+        assert_eq!(path, None);
     }
 
     #[test]
@@ -456,6 +996,8 @@ mod tests {
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
+            upstream_base_url: None,
+            monthly_request_limit: None,
             disabled: false,
         };
 
@@ -486,6 +1028,8 @@ mod tests {
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
+            upstream_base_url: None,
+            monthly_request_limit: None,
             disabled: false,
         };
 
@@ -598,6 +1142,8 @@ mod tests {
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
+            upstream_base_url: None,
+            monthly_request_limit: None,
             disabled: false,
         };
 
@@ -863,4 +1409,439 @@ mod tests {
         let result = creds.effective_proxy(None);
         assert_eq!(result, None);
     }
+
+    fn creds_with(field: &str, value: &str) -> KiroCredentials {
+        KiroCredentials::from_json(&serde_json::json!({ field: value }).to_string()).unwrap()
+    }
+
+    fn field_value(creds: &KiroCredentials, field: &str) -> Option<String> {
+        match field {
+            "refreshToken" => creds.refresh_token.clone(),
+            "clientId" => creds.client_id.clone(),
+            "clientSecret" => creds.client_secret.clone(),
+            "email" => creds.email.clone(),
+            "region" => creds.region.clone(),
+            "authRegion" => creds.auth_region.clone(),
+            "apiRegion" => creds.api_region.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    const SANITIZED_FIELDS: [&str; 7] = [
+        "refreshToken",
+        "clientId",
+        "clientSecret",
+        "email",
+        "region",
+        "authRegion",
+        "apiRegion",
+    ];
+
+    #[test]
+    fn test_sanitize_trims_whitespace() {
+        for field in SANITIZED_FIELDS {
+            let creds = creds_with(field, "  value\n").sanitized().unwrap();
+            assert_eq!(
+                field_value(&creds, field).as_deref(),
+                Some("value"),
+                "{}",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_sanitize_strips_bom_and_zero_width() {
+        for junk in ['\u{feff}', '\u{200b}', '\u{200c}', '\u{200d}', '\u{2060}'] {
+            for field in SANITIZED_FIELDS {
+                let value = format!("{}va{}lue{}", junk, junk, junk);
+                let creds = creds_with(field, &value).sanitized().unwrap();
+                assert_eq!(
+                    field_value(&creds, field).as_deref(),
+                    Some("value"),
+                    "{} {:?}",
+                    field,
+                    junk
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sanitize_rejects_control_chars_in_tokens() {
+        for field in ["refreshToken", "clientId", "clientSecret"] {
+            for junk in ['\n', '\r', '\t', '\u{0}', '\u{7f}'] {
+                let value = format!("abc{}def", junk);
+                let err = creds_with(field, &value).sanitized().unwrap_err();
+                assert_eq!(err.field, field);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sanitize_strips_control_chars_in_text_fields() {
+        for field in ["email", "region", "authRegion", "apiRegion"] {
+            let creds = creds_with(field, "ab\tc\u{7f}").sanitized().unwrap();
+            assert_eq!(
+                field_value(&creds, field).as_deref(),
+                Some("abc"),
+                "{}",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_sanitize_empty_optional_fields_become_none() {
+        for field in [
+            "clientId",
+            "clientSecret",
+            "email",
+            "region",
+            "authRegion",
+            "apiRegion",
+        ] {
+            let creds = creds_with(field, " \u{200b} ").sanitized().unwrap();
+            assert_eq!(field_value(&creds, field), None, "{}", field);
+        }
+        // refreshToken 保留空字符串，由 validate_refresh_token 报告“为空”
+        let creds = creds_with("refreshToken", " \u{feff}").sanitized().unwrap();
+        assert_eq!(creds.refresh_token.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_sanitize_lowercases_email() {
+        let creds = creds_with("email", " User@Example.COM\u{200b}")
+            .sanitized()
+            .unwrap();
+        assert_eq!(creds.email.as_deref(), Some("user@example.com"));
+    }
+
+    #[test]
+    fn test_credentials_config_load_reports_field_and_index() {
+        let _env = CREDENTIALS_ENV_LOCK.blocking_lock();
+        let path = std::env::temp_dir().join(format!("kiro-creds-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"[{"refreshToken": "ok\n"}, {"refreshToken": "bad\ntoken"}]"#,
+        )
+        .unwrap();
+        let err = CredentialsConfig::load(&path).unwrap_err().to_string();
+        assert!(err.contains("第 2 个凭据"), "{}", err);
+        assert!(err.contains("refreshToken"), "{}", err);
+
+        std::fs::write(
+            &path,
+            "[{\"refreshToken\": \"\u{feff}token \", \"email\": \"A@B.C\"}]",
+        )
+        .unwrap();
+        let creds = CredentialsConfig::load(&path)
+            .unwrap()
+            .into_sorted_credentials();
+        assert_eq!(creds[0].refresh_token.as_deref(), Some("token"));
+        assert_eq!(creds[0].email.as_deref(), Some("a@b.c"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_credentials_config_load_prefers_json_env_var() {
+        let _env = CREDENTIALS_ENV_LOCK.blocking_lock();
+        let path = std::env::temp_dir().join(format!("kiro-creds-{}.json", uuid::Uuid::new_v4()));
+        // SAFETY: 持有 CREDENTIALS_ENV_LOCK，读取凭据环境变量的测试不会并行执行
+        unsafe {
+            std::env::set_var(
+                CREDENTIALS_JSON_ENV,
+                r#"{"refreshToken": "env_refresh", "authMethod": "social"}"#,
+            )
+        };
+        let loaded = CredentialsConfig::load(&path);
+        unsafe { std::env::remove_var(CREDENTIALS_JSON_ENV) };
+
+        let config = loaded.unwrap();
+        assert!(!path.exists());
+        assert!(!config.is_multiple());
+        let creds = config.into_sorted_credentials();
+        assert_eq!(creds[0].refresh_token.as_deref(), Some("env_refresh"));
+        assert_eq!(CredentialsConfig::env_var_name(), None);
+    }
+
+    #[test]
+    fn test_credentials_from_env_value_decodes_base64() {
+        use base64::Engine;
+
+        let encoded = base64::engine::general_purpose::STANDARD
+            .encode(r#"[{"refreshToken": "a"}, {"refreshToken": "b", "priority": 1}]"#);
+        let config =
+            CredentialsConfig::from_env_value(CREDENTIALS_JSON_ENV, &format!("base64:{}", encoded))
+                .unwrap();
+        assert!(config.is_multiple());
+        assert_eq!(config.len(), 2);
+
+        // KIRO_CREDENTIALS_BASE64 可以省略前缀
+        let config = CredentialsConfig::from_env_value(CREDENTIALS_BASE64_ENV, &encoded).unwrap();
+        assert_eq!(config.len(), 2);
+
+        // 其他变量无前缀时按 JSON 解析
+        let err = format!(
+            "{:#}",
+            CredentialsConfig::from_env_value(CREDENTIALS_JSON_ENV, &encoded).unwrap_err()
+        );
+        assert!(err.contains("不是有效的凭据 JSON"), "{}", err);
+
+        let var_name = format!("KIRO_TEST_CREDENTIALS_{}", uuid::Uuid::new_v4().simple());
+        assert!(CredentialsConfig::from_env_var(&var_name).is_err());
+    }
+
+    fn legacy_credentials_file() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kiro-migrate-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiro_credentials.json");
+        fs::write(
+            &path,
+            r#"{"refreshToken": "legacy_refresh", "authMethod": "social"}"#,
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_migrate_single_credentials_to_array() {
+        let _env = CREDENTIALS_ENV_LOCK.blocking_lock();
+        let path = legacy_credentials_file();
+        let original = fs::read_to_string(&path).unwrap();
+
+        let outcome = CredentialsConfig::migrate_to_multiple(&path, &Config::default()).unwrap();
+        let backup_path = path.with_file_name("kiro_credentials.json.bak");
+        assert_eq!(
+            outcome,
+            CredentialsMigration::Migrated {
+                backup_path: backup_path.clone()
+            }
+        );
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), original);
+
+        let config = CredentialsConfig::load(&path).unwrap();
+        assert!(config.is_multiple());
+        let creds = config.into_sorted_credentials();
+        assert_eq!(creds.len(), 1);
+        assert_eq!(creds[0].id, Some(1));
+        assert_eq!(creds[0].refresh_token.as_deref(), Some("legacy_refresh"));
+        assert_eq!(creds[0].machine_id.as_ref().map(|m| m.len()), Some(64));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent_and_skips_arrays() {
+        let path = legacy_credentials_file();
+        CredentialsConfig::migrate_to_multiple(&path, &Config::default()).unwrap();
+        let migrated = fs::read_to_string(&path).unwrap();
+        let backup_path = path.with_file_name("kiro_credentials.json.bak");
+        let backup = fs::read_to_string(&backup_path).unwrap();
+
+        // 再次执行：文件与备份均保持不变
+        assert_eq!(
+            CredentialsConfig::migrate_to_multiple(&path, &Config::default()).unwrap(),
+            CredentialsMigration::AlreadyMultiple
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), migrated);
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), backup);
+
+        // 本身就是数组格式的文件不会被改写，也不会产生备份
+        let array_path = path.with_file_name("array.json");
+        fs::write(&array_path, r#"[{"refreshToken": "a"}]"#).unwrap();
+        assert_eq!(
+            CredentialsConfig::migrate_to_multiple(&array_path, &Config::default()).unwrap(),
+            CredentialsMigration::AlreadyMultiple
+        );
+        assert_eq!(
+            fs::read_to_string(&array_path).unwrap(),
+            r#"[{"refreshToken": "a"}]"#
+        );
+        assert!(!path.with_file_name("array.json.bak").exists());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_credentials_config_validate() {
+        let valid: CredentialsConfig = serde_json::from_str(
+            r#"[
+                {"id": 1, "refreshToken": "a"},
+                {"id": 2, "refreshToken": "b", "authMethod": "idc", "clientId": "c", "clientSecret": "s"}
+            ]"#,
+        )
+        .unwrap();
+        assert!(valid.validate().is_empty());
+
+        let invalid: CredentialsConfig = serde_json::from_str(
+            r#"[
+                {"id": 1, "refreshToken": "a", "upstreamBaseUrl": "http://relay.example.com"},
+                {"id": 1, "refreshToken": " "},
+                {"refreshToken": "c", "authMethod": "idc", "clientId": "c"},
+                {"refreshToken": "d", "priority": 4294967295},
+                {"refreshToken": "e", "monthlyRequestLimit": {"softLimit": 10, "hardLimit": 5}}
+            ]"#,
+        )
+        .unwrap();
+        let errors = invalid.validate();
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        assert!(errors[0].starts_with("第 1 个凭据的 upstreamBaseUrl 无效"));
+        assert_eq!(errors[1], "第 2 个凭据缺少 refreshToken");
+        assert_eq!(errors[2], "第 2 个凭据的 id 1 与其他凭据重复");
+        assert_eq!(
+            errors[3],
+            "第 3 个凭据为 IdC 认证，但缺少 clientId 或 clientSecret"
+        );
+        assert_eq!(errors[4], "第 4 个凭据的 priority 4294967295 为内部保留值");
+        assert_eq!(
+            errors[5],
+            "第 5 个凭据的 monthlyRequestLimit 无效: softLimit（10）不能大于 hardLimit（5）"
+        );
+
+        let single: CredentialsConfig = serde_json::from_str(r#"{"accessToken": "t"}"#).unwrap();
+        assert_eq!(single.validate(), vec!["第 1 个凭据缺少 refreshToken"]);
+    }
+
+    #[test]
+    fn test_credentials_ordering_by_priority_then_id() {
+        let cred = |id: Option<u64>, priority: u32| KiroCredentials {
+            id,
+            priority,
+            refresh_token: Some(format!("token-{:?}", id)),
+            ..Default::default()
+        };
+        let mut creds = [
+            cred(Some(4), 1),
+            cred(None, 0),
+            cred(Some(3), 0),
+            cred(Some(1), 2),
+            cred(Some(2), 0),
+        ];
+        creds.sort();
+        let order: Vec<(u32, Option<u64>)> = creds.iter().map(|c| (c.priority, c.id)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (0, Some(2)),
+                (0, Some(3)),
+                (0, None),
+                (1, Some(4)),
+                (2, Some(1))
+            ]
+        );
+        assert_eq!(creds.iter().min().unwrap().id, Some(2));
+
+        // 只比较优先级与 ID
+        let mut other = cred(Some(2), 0);
+        other.refresh_token = Some("other".to_string());
+        assert_eq!(creds[0], other);
+    }
+
+    #[test]
+    fn test_monthly_request_limit_validate() {
+        let limit: MonthlyRequestLimit =
+            serde_json::from_str(r#"{"softLimit": 800, "hardLimit": 1000, "timezone": "+08:00"}"#)
+                .unwrap();
+        assert!(limit.validate().is_ok());
+        assert_eq!(limit.offset().unwrap().local_minus_utc(), 8 * 3600);
+        assert_eq!(
+            MonthlyRequestLimit::default()
+                .offset()
+                .unwrap()
+                .local_minus_utc(),
+            0
+        );
+
+        for json in [
+            r#"{"hardLimit": 0}"#,
+            r#"{"softLimit": 2, "hardLimit": 1}"#,
+            r#"{"hardLimit": 10, "timezone": "Asia/Shanghai"}"#,
+        ] {
+            let limit: MonthlyRequestLimit = serde_json::from_str(json).unwrap();
+            assert!(limit.validate().is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_validate_upstream_base_url() {
+        for url in [
+            "https://relay.example.com",
+            "https://relay.example.com:8443/kiro/",
+            "http://127.0.0.1:9000",
+            "http://localhost/kiro",
+            "http://[::1]:9000",
+        ] {
+            assert!(validate_upstream_base_url(url).is_ok(), "{}", url);
+        }
+        for url in [
+            "http://relay.example.com",
+            "ftp://relay.example.com",
+            "relay.example.com",
+            "https://",
+            "https://relay.example.com/kiro?key=1",
+            "https://relay.example.com/#frag",
+        ] {
+            assert!(validate_upstream_base_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_upstream_base_trims_trailing_slash() {
+        let creds = KiroCredentials {
+            upstream_base_url: Some("https://relay.example.com/kiro/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            creds.upstream_base().unwrap(),
+            Some("https://relay.example.com/kiro")
+        );
+        assert_eq!(KiroCredentials::default().upstream_base().unwrap(), None);
+    }
+
+    #[test]
+    fn test_auth_method_aliases_are_canonicalized() {
+        for (alias, canonical) in AUTH_METHOD_ALIASES {
+            for value in [
+                alias.to_string(),
+                alias.to_uppercase(),
+                alias.to_lowercase(),
+            ] {
+                let mut creds = KiroCredentials {
+                    auth_method: Some(value.clone()),
+                    ..Default::default()
+                };
+                assert_eq!(creds.canonical_auth_method(), *canonical, "{}", value);
+                creds.canonicalize_auth_method();
+                assert_eq!(creds.auth_method.as_deref(), Some(*canonical), "{}", value);
+            }
+        }
+        let creds = KiroCredentials {
+            auth_method: Some("IdC".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(creds.canonical_auth_method(), "idc");
+    }
+
+    #[test]
+    fn test_unknown_auth_method_defaults_to_social() {
+        let mut creds = KiroCredentials {
+            auth_method: Some("saml".to_string()),
+            client_id: Some("client".to_string()),
+            client_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(creds.canonical_auth_method(), "social");
+        assert!(!creds.has_known_auth_method());
+        // 无法识别的值原样保留
+        creds.canonicalize_auth_method();
+        assert_eq!(creds.auth_method.as_deref(), Some("saml"));
+
+        // 未配置时按 clientId/clientSecret 推断
+        creds.auth_method = None;
+        assert_eq!(creds.canonical_auth_method(), "idc");
+        assert!(creds.has_known_auth_method());
+        assert_eq!(KiroCredentials::default().canonical_auth_method(), "social");
+    }
 }
@@ -3,6 +3,9 @@
 //! 定义事件类型枚举、trait 和统一事件结构
 
 use crate::kiro::parser::error::{ParseError, ParseResult};
+use crate::kiro::parser::exception::{
+    KiroError, KiroException, parse_error_frame, parse_exception,
+};
 use crate::kiro::parser::frame::Frame;
 
 /// 事件类型枚举
@@ -16,6 +19,8 @@ pub enum EventType {
     Metering,
     /// 上下文使用率事件
     ContextUsage,
+    /// 推理内容事件
+    ReasoningContent,
     /// 未知事件类型
     Unknown,
 }
@@ -28,6 +33,7 @@ impl EventType {
             "toolUseEvent" => Self::ToolUse,
             "meteringEvent" => Self::Metering,
             "contextUsageEvent" => Self::ContextUsage,
+            "reasoningContentEvent" => Self::ReasoningContent,
             _ => Self::Unknown,
         }
     }
@@ -39,6 +45,7 @@ impl EventType {
             Self::ToolUse => "toolUseEvent",
             Self::Metering => "meteringEvent",
             Self::ContextUsage => "contextUsageEvent",
+            Self::ReasoningContent => "reasoningContentEvent",
             Self::Unknown => "unknown",
         }
     }
@@ -71,22 +78,14 @@ pub enum Event {
     Metering(()),
     /// 上下文使用率
     ContextUsage(super::ContextUsageEvent),
+    /// 推理内容（thinking 文本、签名或加密内容）
+    ReasoningContent(super::ReasoningContentEvent),
     /// 未知事件 (保留原始帧数据)
     Unknown {},
     /// 服务端错误
-    Error {
-        /// 错误代码
-        error_code: String,
-        /// 错误消息
-        error_message: String,
-    },
+    Error(KiroError),
     /// 服务端异常
-    Exception {
-        /// 异常类型
-        exception_type: String,
-        /// 异常消息
-        message: String,
-    },
+    Exception(KiroException),
 }
 
 impl Event {
@@ -96,8 +95,8 @@ impl Event {
 
         match message_type {
             "event" => Self::parse_event(frame),
-            "error" => Self::parse_error(frame),
-            "exception" => Self::parse_exception(frame),
+            "error" => Ok(Self::Error(parse_error_frame(&frame)?)),
+            "exception" => Ok(Self::Exception(parse_exception(&frame)?)),
             other => Err(ParseError::InvalidMessageType(other.to_string())),
         }
     }
@@ -121,39 +120,13 @@ impl Event {
                 let payload = super::ContextUsageEvent::from_frame(&frame)?;
                 Ok(Self::ContextUsage(payload))
             }
+            EventType::ReasoningContent => {
+                let payload = super::ReasoningContentEvent::from_frame(&frame)?;
+                Ok(Self::ReasoningContent(payload))
+            }
             EventType::Unknown => Ok(Self::Unknown {}),
         }
     }
-
-    /// 解析错误类型消息
-    fn parse_error(frame: Frame) -> ParseResult<Self> {
-        let error_code = frame
-            .headers
-            .error_code()
-            .unwrap_or("UnknownError")
-            .to_string();
-        let error_message = frame.payload_as_str();
-
-        Ok(Self::Error {
-            error_code,
-            error_message,
-        })
-    }
-
-    /// 解析异常类型消息
-    fn parse_exception(frame: Frame) -> ParseResult<Self> {
-        let exception_type = frame
-            .headers
-            .exception_type()
-            .unwrap_or("UnknownException")
-            .to_string();
-        let message = frame.payload_as_str();
-
-        Ok(Self::Exception {
-            exception_type,
-            message,
-        })
-    }
 }
 
 #[cfg(test)]
@@ -172,6 +145,10 @@ mod tests {
             EventType::from_str("contextUsageEvent"),
             EventType::ContextUsage
         );
+        assert_eq!(
+            EventType::from_str("reasoningContentEvent"),
+            EventType::ReasoningContent
+        );
         assert_eq!(EventType::from_str("unknown_type"), EventType::Unknown);
     }
 
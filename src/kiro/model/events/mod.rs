@@ -5,9 +5,11 @@
 mod assistant;
 mod base;
 mod context_usage;
+mod reasoning;
 mod tool_use;
 
 pub use assistant::AssistantResponseEvent;
 pub use base::Event;
 pub use context_usage::ContextUsageEvent;
+pub use reasoning::ReasoningContentEvent;
 pub use tool_use::ToolUseEvent;
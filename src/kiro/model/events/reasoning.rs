@@ -0,0 +1,54 @@
+//! 推理内容事件
+//!
+//! 处理 reasoningContentEvent 类型的事件
+
+use serde::Deserialize;
+
+use crate::kiro::parser::error::ParseResult;
+use crate::kiro::parser::frame::Frame;
+
+use super::base::EventPayload;
+
+/// 推理内容事件
+///
+/// 上游以独立事件（而非 `<thinking>` 标签）返回 extended thinking 时使用：
+/// `text` 为思考内容片段，思考结束时携带 `signature`；
+/// 被上游加密屏蔽的思考内容以 `redactedContent` 返回
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReasoningContentEvent {
+    /// 思考内容片段
+    #[serde(default)]
+    pub text: String,
+    /// 思考块签名（多轮对话中需原样回传）
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// 加密的思考内容（对应 Anthropic 的 redacted_thinking 块）
+    #[serde(default)]
+    pub redacted_content: Option<String>,
+}
+
+impl EventPayload for ReasoningContentEvent {
+    fn from_frame(frame: &Frame) -> ParseResult<Self> {
+        frame.payload_as_json()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_text_and_signature() {
+        let event: ReasoningContentEvent =
+            serde_json::from_str(r#"{"text":"Let me think","signature":"sig-1"}"#).unwrap();
+        assert_eq!(event.text, "Let me think");
+        assert_eq!(event.signature.as_deref(), Some("sig-1"));
+        assert_eq!(event.redacted_content, None);
+
+        let event: ReasoningContentEvent =
+            serde_json::from_str(r#"{"redactedContent":"ZW5jcnlwdGVk"}"#).unwrap();
+        assert!(event.text.is_empty());
+        assert_eq!(event.redacted_content.as_deref(), Some("ZW5jcnlwdGVk"));
+    }
+}
@@ -7,10 +7,12 @@
 //! - `credentials`: OAuth 凭证
 //! - `token_refresh`: Token 刷新
 //! - `usage_limits`: 使用额度查询
+//! - `token_count`: Token 计数
 
 pub mod common;
 pub mod credentials;
 pub mod events;
 pub mod requests;
+pub mod token_count;
 pub mod token_refresh;
 pub mod usage_limits;
@@ -326,6 +326,9 @@ pub struct AssistantMessage {
     /// 工具使用列表
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tool_uses: Option<Vec<ToolUseEntry>>,
+    /// 带签名的 thinking 块与 redacted_thinking 块（按原始顺序回传给上游）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<Vec<ReasoningContent>>,
 }
 
 impl AssistantMessage {
@@ -334,9 +337,16 @@ impl AssistantMessage {
         Self {
             content: content.into(),
             tool_uses: None,
+            reasoning_content: None,
         }
     }
 
+    /// 设置推理内容
+    pub fn with_reasoning_content(mut self, reasoning_content: Vec<ReasoningContent>) -> Self {
+        self.reasoning_content = Some(reasoning_content);
+        self
+    }
+
     /// 设置工具使用
     pub fn with_tool_uses(mut self, tool_uses: Vec<ToolUseEntry>) -> Self {
         self.tool_uses = Some(tool_uses);
@@ -344,6 +354,20 @@ impl AssistantMessage {
     }
 }
 
+/// 历史助手消息中的推理内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReasoningContent {
+    /// thinking 块（文本与签名）
+    ReasoningText {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    /// redacted_thinking 块的加密内容
+    RedactedContent(String),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,32 @@
+//! Token 计数数据模型
+//!
+//! 包含 getTokenCount API 的响应类型定义
+
+use serde::Deserialize;
+
+/// Token 计数响应
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenCountResponse {
+    /// 输入 tokens 数（兼容 `inputTokens` / `input_tokens` 字段名）
+    #[serde(alias = "inputTokens", alias = "input_tokens")]
+    pub token_count: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_token_count_field_aliases() {
+        for body in [
+            r#"{"tokenCount": 42}"#,
+            r#"{"inputTokens": 42}"#,
+            r#"{"input_tokens": 42, "conversationId": "c-1"}"#,
+        ] {
+            let response: TokenCountResponse = serde_json::from_str(body).unwrap();
+            assert_eq!(response.token_count, 42, "{}", body);
+        }
+        assert!(serde_json::from_str::<TokenCountResponse>("{}").is_err());
+    }
+}
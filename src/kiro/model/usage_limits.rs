@@ -4,6 +4,9 @@
 
 use serde::Deserialize;
 
+/// 默认查询的资源类型
+pub const DEFAULT_USAGE_RESOURCE_TYPE: &str = "AGENTIC_REQUEST";
+
 /// 使用额度查询响应
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,6 +37,10 @@ pub struct SubscriptionInfo {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UsageBreakdown {
+    /// 资源类型（如 AGENTIC_REQUEST / INLINE_COMPLETION）
+    #[serde(default)]
+    pub resource_type: Option<String>,
+
     /// 当前使用量
     #[serde(default)]
     pub current_usage: i64,
@@ -139,16 +146,30 @@ impl UsageLimitsResponse {
             .and_then(|info| info.subscription_title.as_deref())
     }
 
-    /// 获取第一个使用量明细
-    fn primary_breakdown(&self) -> Option<&UsageBreakdown> {
-        self.usage_breakdown_list.first()
+    /// 获取指定资源类型的使用量明细
+    pub fn breakdown_for(&self, resource_type: &str) -> Option<&UsageBreakdown> {
+        self.usage_breakdown_list
+            .iter()
+            .find(|b| b.resource_type.as_deref() == Some(resource_type))
+    }
+
+    /// 获取用于计算额度的使用量明细（`resource_type` 缺省为 AGENTIC_REQUEST）
+    ///
+    /// 上游未标注资源类型时回退到第一个明细
+    fn select_breakdown(&self, resource_type: Option<&str>) -> Option<&UsageBreakdown> {
+        let resource_type = resource_type.unwrap_or(DEFAULT_USAGE_RESOURCE_TYPE);
+        self.breakdown_for(resource_type).or_else(|| {
+            self.usage_breakdown_list
+                .first()
+                .filter(|b| b.resource_type.is_none())
+        })
     }
 
     /// 获取总使用限额（精确值）
     ///
     /// 累加基础额度、激活的免费试用额度和激活的奖励额度
-    pub fn usage_limit(&self) -> f64 {
-        let Some(breakdown) = self.primary_breakdown() else {
+    pub fn usage_limit(&self, resource_type: Option<&str>) -> f64 {
+        let Some(breakdown) = self.select_breakdown(resource_type) else {
             return 0.0;
         };
 
@@ -174,8 +195,8 @@ impl UsageLimitsResponse {
     /// 获取总当前使用量（精确值）
     ///
     /// 累加基础使用量、激活的免费试用使用量和激活的奖励使用量
-    pub fn current_usage(&self) -> f64 {
-        let Some(breakdown) = self.primary_breakdown() else {
+    pub fn current_usage(&self, resource_type: Option<&str>) -> f64 {
+        let Some(breakdown) = self.select_breakdown(resource_type) else {
             return 0.0;
         };
 
@@ -197,4 +218,59 @@ impl UsageLimitsResponse {
 
         total
     }
+    /// 指定资源类型的额度是否已用尽（无对应明细时视为未用尽）
+    pub fn is_quota_exhausted(&self, resource_type: Option<&str>) -> bool {
+        let limit = self.usage_limit(resource_type);
+        limit > 0.0 && self.current_usage(resource_type) >= limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(breakdowns: serde_json::Value) -> UsageLimitsResponse {
+        serde_json::from_value(serde_json::json!({ "usageBreakdownList": breakdowns })).unwrap()
+    }
+
+    #[test]
+    fn test_breakdown_for_selects_matching_resource_type() {
+        let usage = response(serde_json::json!([
+            {
+                "resourceType": "INLINE_COMPLETION",
+                "currentUsageWithPrecision": 50.0,
+                "usageLimitWithPrecision": 50.0
+            },
+            {
+                "resourceType": "AGENTIC_REQUEST",
+                "currentUsageWithPrecision": 10.0,
+                "usageLimitWithPrecision": 100.0
+            }
+        ]));
+
+        let agentic = usage.breakdown_for("AGENTIC_REQUEST").unwrap();
+        assert_eq!(agentic.usage_limit_with_precision, 100.0);
+        let inline = usage.breakdown_for("INLINE_COMPLETION").unwrap();
+        assert_eq!(inline.usage_limit_with_precision, 50.0);
+        assert!(usage.breakdown_for("OTHER").is_none());
+
+        // 默认按 AGENTIC_REQUEST 计算，而不是第一个明细
+        assert_eq!(usage.current_usage(None), 10.0);
+        assert_eq!(usage.usage_limit(None), 100.0);
+        assert!(!usage.is_quota_exhausted(None));
+        assert!(usage.is_quota_exhausted(Some("INLINE_COMPLETION")));
+        // 标注了资源类型但没有匹配项时不回退
+        assert_eq!(usage.usage_limit(Some("OTHER")), 0.0);
+        assert!(!usage.is_quota_exhausted(Some("OTHER")));
+    }
+
+    #[test]
+    fn test_untyped_breakdown_falls_back_to_first_entry() {
+        let usage = response(serde_json::json!([{
+            "currentUsageWithPrecision": 5.0,
+            "usageLimitWithPrecision": 20.0
+        }]));
+        assert_eq!(usage.current_usage(None), 5.0);
+        assert_eq!(usage.usage_limit(Some("INLINE_COMPLETION")), 20.0);
+    }
 }
@@ -0,0 +1,459 @@
+//! OpenAI 协议上游后端
+//!
+//! 配置 `openaiBackends` 并通过 `modelBackends` 路由后，对应模型的消息请求不再发往 Kiro，
+//! 由 [`OpenAiProvider`] 将转换后的 Kiro 请求体改写为 Chat Completions 请求发往 OpenAI 兼容网关（如 vLLM），
+//! 再把响应重新编码为 Event Stream 事件帧，与 Kiro 上游共用同一套响应处理流程：
+//! - 历史与当前消息按顺序转换，工具结果转换为 `tool` 消息，工具调用转换为 `tool_calls`
+//! - 图片暂不转发
+//! - 流式请求同样以非流式方式调用上游，完整响应返回后再逐帧输出
+
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::http_client::build_client;
+use crate::kiro::model::requests::conversation::{
+    AssistantMessage, Message, UserInputMessageContext,
+};
+use crate::kiro::model::requests::kiro::KiroRequest;
+use crate::kiro::model::requests::tool::ToolResult;
+use crate::kiro::parser::frame::encode_event_frame;
+use crate::kiro::provider::{MessagesCall, Provider, UPSTREAM_TIMEOUT_SECS};
+use crate::model::config::{Config, OpenAiBackendConfig};
+
+/// OpenAI 协议上游 Provider
+pub struct OpenAiProvider {
+    /// 后端名称（openaiBackends 中的键，用于日志与错误信息）
+    name: String,
+    client: Client,
+    /// Chat Completions 地址
+    url: String,
+    api_key: Option<String>,
+    /// 覆盖上游模型名
+    model: Option<String>,
+}
+
+impl OpenAiProvider {
+    /// 按后端配置创建（HTTP Client 使用全局 tlsBackend，不经过全局代理）
+    pub fn from_config(
+        name: impl Into<String>,
+        backend: &OpenAiBackendConfig,
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        let client = build_client(None, UPSTREAM_TIMEOUT_SECS, config.tls_backend)?;
+        Ok(Self {
+            name: name.into(),
+            client,
+            url: format!(
+                "{}/chat/completions",
+                backend.base_url.trim_end_matches('/')
+            ),
+            api_key: backend.api_key.clone(),
+            model: backend.model.clone(),
+        })
+    }
+
+    async fn call(&self, call: MessagesCall<'_>) -> anyhow::Result<reqwest::Response> {
+        let request: KiroRequest = serde_json::from_str(call.request_body)?;
+        let model = self.model.as_deref().unwrap_or(
+            &request
+                .conversation_state
+                .current_message
+                .user_input_message
+                .model_id,
+        );
+        let body = chat_request(&request, model, call.max_tokens);
+
+        let mut builder = self.client.post(&self.url).json(&body);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        if let Some(timeout) = call.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("OpenAI 后端 {} 请求发送失败: {}", self.name, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("OpenAI 后端 {} 请求失败: {} {}", self.name, status, body);
+        }
+        let completion: ChatCompletion = response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("OpenAI 后端 {} 响应解析失败: {}", self.name, e))?;
+
+        let response = http::Response::builder()
+            .status(http::StatusCode::OK)
+            .header(
+                http::header::CONTENT_TYPE,
+                "application/vnd.amazon.eventstream",
+            )
+            .body(encode_completion(&completion))?;
+        Ok(reqwest::Response::from(response))
+    }
+}
+
+impl Provider for OpenAiProvider {
+    fn call_messages<'a>(
+        &'a self,
+        call: MessagesCall<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<reqwest::Response>> {
+        Box::pin(self.call(call))
+    }
+}
+
+/// Chat Completions 响应（仅解析用到的字段）
+#[derive(Debug, Deserialize)]
+struct ChatCompletion {
+    #[serde(default)]
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ChatToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatToolCall {
+    id: String,
+    function: ChatFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+/// 将 Kiro 请求转换为 Chat Completions 请求体
+fn chat_request(request: &KiroRequest, model: &str, max_tokens: i32) -> Value {
+    let state = &request.conversation_state;
+    let mut messages = Vec::new();
+    for message in &state.history {
+        match message {
+            Message::User(user) => {
+                let user = &user.user_input_message;
+                push_user_messages(
+                    &mut messages,
+                    &user.content,
+                    &user.user_input_message_context,
+                );
+            }
+            Message::Assistant(assistant) => {
+                messages.push(assistant_message(&assistant.assistant_response_message));
+            }
+        }
+    }
+    let current = &state.current_message.user_input_message;
+    push_user_messages(
+        &mut messages,
+        &current.content,
+        &current.user_input_message_context,
+    );
+
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+        "max_tokens": max_tokens,
+        "stream": false,
+    });
+    let tools = &current.user_input_message_context.tools;
+    if !tools.is_empty() {
+        body["tools"] = tools
+            .iter()
+            .map(|tool| {
+                let spec = &tool.tool_specification;
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": spec.name,
+                        "description": spec.description,
+                        "parameters": spec.input_schema.json,
+                    }
+                })
+            })
+            .collect();
+    }
+    body
+}
+
+/// 用户消息：工具结果在前（对应上一轮的工具调用），文本在后
+fn push_user_messages(messages: &mut Vec<Value>, content: &str, context: &UserInputMessageContext) {
+    for result in &context.tool_results {
+        messages.push(json!({
+            "role": "tool",
+            "tool_call_id": result.tool_use_id,
+            "content": tool_result_text(result),
+        }));
+    }
+    if !content.trim().is_empty() {
+        messages.push(json!({ "role": "user", "content": content }));
+    }
+}
+
+fn assistant_message(message: &AssistantMessage) -> Value {
+    let mut value = json!({ "role": "assistant", "content": message.content });
+    let tool_calls: Vec<Value> = message
+        .tool_uses
+        .iter()
+        .flatten()
+        .map(|tool_use| {
+            json!({
+                "id": tool_use.tool_use_id,
+                "type": "function",
+                "function": {
+                    "name": tool_use.name,
+                    "arguments": tool_use.input.to_string(),
+                }
+            })
+        })
+        .collect();
+    if !tool_calls.is_empty() {
+        value["tool_calls"] = Value::Array(tool_calls);
+    }
+    value
+}
+
+/// 拼接工具结果中的文本块（非文本块按 JSON 原样输出）
+fn tool_result_text(result: &ToolResult) -> String {
+    result
+        .content
+        .iter()
+        .map(|block| match block.get("text").and_then(Value::as_str) {
+            Some(text) => text.to_string(),
+            None => Value::Object(block.clone()).to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 将 Chat Completions 响应编码为 Kiro 事件帧
+fn encode_completion(completion: &ChatCompletion) -> Vec<u8> {
+    let mut body = Vec::new();
+    let Some(choice) = completion.choices.first() else {
+        return body;
+    };
+    let message = &choice.message;
+    if let Some(content) = message.content.as_deref().filter(|c| !c.is_empty()) {
+        body.extend(encode_event_frame(
+            "assistantResponseEvent",
+            &json!({ "content": content }),
+        ));
+    }
+    for tool_call in &message.tool_calls {
+        let arguments = match tool_call.function.arguments.trim() {
+            "" => "{}",
+            arguments => arguments,
+        };
+        body.extend(encode_event_frame(
+            "toolUseEvent",
+            &json!({
+                "name": tool_call.function.name,
+                "toolUseId": tool_call.id,
+                "input": arguments,
+                "stop": true
+            }),
+        ));
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::parser::frame::frames;
+    use axum::response::IntoResponse;
+
+    const KIRO_REQUEST: &str = r#"{
+        "conversationState": {
+            "conversationId": "conv-1",
+            "currentMessage": {
+                "userInputMessage": {
+                    "content": "",
+                    "modelId": "qwen-72b",
+                    "userInputMessageContext": {
+                        "toolResults": [{
+                            "toolUseId": "call_1",
+                            "content": [{"text": "Sunny"}],
+                            "status": "success"
+                        }],
+                        "tools": [{
+                            "toolSpecification": {
+                                "name": "get_weather",
+                                "description": "Get weather",
+                                "inputSchema": {"json": {"type": "object"}}
+                            }
+                        }]
+                    }
+                }
+            },
+            "history": [
+                {"userInputMessage": {"content": "Weather in Paris?", "modelId": "qwen-72b"}},
+                {"assistantResponseMessage": {
+                    "content": "Checking.",
+                    "toolUses": [{"toolUseId": "call_1", "name": "get_weather", "input": {"city": "Paris"}}]
+                }}
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_chat_request_from_kiro_request() {
+        let request: KiroRequest = serde_json::from_str(KIRO_REQUEST).unwrap();
+        let body = chat_request(&request, "qwen-72b", 256);
+
+        assert_eq!(body["model"], "qwen-72b");
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(
+            body["messages"],
+            json!([
+                {"role": "user", "content": "Weather in Paris?"},
+                {
+                    "role": "assistant",
+                    "content": "Checking.",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                    }]
+                },
+                {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}
+            ])
+        );
+        assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(
+            body["tools"][0]["function"]["parameters"],
+            json!({"type": "object"})
+        );
+    }
+
+    #[test]
+    fn test_encode_completion_as_kiro_events() {
+        let completion: ChatCompletion = serde_json::from_value(json!({
+            "choices": [{
+                "message": {
+                    "content": "Let me check.",
+                    "tool_calls": [{
+                        "id": "call_2",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": ""}
+                    }]
+                }
+            }]
+        }))
+        .unwrap();
+
+        let body = encode_completion(&completion);
+        let events: Vec<(String, Value)> = frames(&body)
+            .map(|frame| {
+                let frame = frame.unwrap();
+                (
+                    frame.event_type().unwrap().to_string(),
+                    frame.payload_as_json().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    "assistantResponseEvent".to_string(),
+                    json!({"content": "Let me check."})
+                ),
+                (
+                    "toolUseEvent".to_string(),
+                    json!({"name": "get_weather", "toolUseId": "call_2", "input": "{}", "stop": true})
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_messages_against_openai_upstream() {
+        let captured = std::sync::Arc::new(parking_lot::Mutex::new(None));
+        let sink = captured.clone();
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(
+                move |headers: axum::http::HeaderMap, axum::Json(body): axum::Json<Value>| {
+                    let sink = sink.clone();
+                    async move {
+                        let auth = headers
+                            .get("authorization")
+                            .map(|v| v.to_str().unwrap().to_string());
+                        let model = body["model"].as_str().unwrap().to_string();
+                        *sink.lock() = Some((auth, model.clone()));
+                        if model == "missing" {
+                            return (axum::http::StatusCode::NOT_FOUND, "model not found")
+                                .into_response();
+                        }
+                        axum::Json(json!({
+                            "choices": [{"message": {"role": "assistant", "content": "It is sunny."}}]
+                        }))
+                        .into_response()
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let backend = OpenAiBackendConfig {
+            base_url: format!("http://{}/v1/", addr),
+            api_key: Some("sk-vllm".to_string()),
+            model: Some("served-model".to_string()),
+        };
+        let provider = OpenAiProvider::from_config("vllm", &backend, &Config::default()).unwrap();
+        let call = MessagesCall {
+            request_body: KIRO_REQUEST,
+            is_stream: true,
+            pinned: None,
+            max_tokens: 64,
+            passthrough_token: None,
+            timeout: None,
+        };
+        let body = provider
+            .call_messages(call)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(
+            *captured.lock(),
+            Some((
+                Some("Bearer sk-vllm".to_string()),
+                "served-model".to_string()
+            ))
+        );
+        let frame = frames(&body).next().unwrap().unwrap();
+        assert_eq!(frame.event_type(), Some("assistantResponseEvent"));
+        assert_eq!(
+            frame.payload_as_json::<Value>().unwrap(),
+            json!({"content": "It is sunny."})
+        );
+
+        let backend = OpenAiBackendConfig {
+            model: Some("missing".to_string()),
+            ..backend
+        };
+        let provider = OpenAiProvider::from_config("vllm", &backend, &Config::default()).unwrap();
+        let err = provider.call_messages(call).await.unwrap_err().to_string();
+        assert!(err.contains("OpenAI 后端 vllm 请求失败: 404"), "{}", err);
+    }
+}
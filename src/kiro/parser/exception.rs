@@ -0,0 +1,244 @@
+//! 异常与错误帧解析
+//!
+//! 上游错误以 `:message-type` 为 `exception` 或 `error` 的帧返回：
+//! - exception 帧：`:exception-type` 头部给出异常类型，payload 通常为 `{"message": "..."}`
+//! - error 帧：`:error-code` 与 `:error-message` 头部给出错误代码与消息
+//!
+//! 两种帧的 payload 都可能附带 JSON 详情（message、requestId），解析时优先使用
+
+use std::fmt;
+
+use super::error::{ParseError, ParseResult};
+use super::frame::Frame;
+
+/// 输出超出长度限制的异常类型
+pub const CONTENT_LENGTH_EXCEEDED_EXCEPTION: &str = "ContentLengthExceededException";
+
+/// 上游返回的异常（`:message-type = exception`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KiroException {
+    /// 异常类型（如 `ThrottlingException`）
+    pub exception_type: String,
+    /// 异常消息
+    pub message: String,
+    /// 上游请求 ID
+    pub request_id: Option<String>,
+}
+
+impl KiroException {
+    /// 是否为输出超出长度限制（对应 stop_reason `max_tokens`）
+    pub fn is_content_length_exceeded(&self) -> bool {
+        self.exception_type == CONTENT_LENGTH_EXCEEDED_EXCEPTION
+    }
+}
+
+impl fmt::Display for KiroException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.exception_type, self.message)
+    }
+}
+
+impl std::error::Error for KiroException {}
+
+/// 上游返回的错误（`:message-type = error`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KiroError {
+    /// 错误代码
+    pub error_code: String,
+    /// 错误消息
+    pub message: String,
+    /// 上游请求 ID
+    pub request_id: Option<String>,
+}
+
+impl fmt::Display for KiroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.error_code, self.message)
+    }
+}
+
+impl std::error::Error for KiroError {}
+
+/// payload 中的 JSON 详情
+#[derive(Default)]
+struct PayloadDetail {
+    message: Option<String>,
+    request_id: Option<String>,
+    /// payload 不是 JSON 对象时的原始文本
+    raw: Option<String>,
+}
+
+impl PayloadDetail {
+    fn from_frame(frame: &Frame) -> Self {
+        let raw = frame.payload_as_str();
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Self::default();
+        }
+
+        let value: serde_json::Value = match serde_json::from_str(raw) {
+            Ok(value @ serde_json::Value::Object(_)) => value,
+            _ => {
+                return Self {
+                    raw: Some(raw.to_string()),
+                    ..Default::default()
+                };
+            }
+        };
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| value.get(*name).and_then(|v| v.as_str()))
+                .map(str::to_string)
+        };
+        Self {
+            message: field(&["message", "Message", "errorMessage"]),
+            request_id: field(&["requestId", "RequestId", "request_id"]),
+            raw: None,
+        }
+    }
+
+    /// 消息优先级：payload JSON > `:error-message` 头部 > payload 原文
+    fn message(self, header_message: Option<&str>) -> (String, Option<String>) {
+        let message = self
+            .message
+            .or_else(|| header_message.map(str::to_string))
+            .or(self.raw)
+            .unwrap_or_default();
+        (message, self.request_id)
+    }
+}
+
+/// 检查帧的消息类型
+fn expect_message_type(frame: &Frame, expected: &str) -> ParseResult<()> {
+    match frame.message_type() {
+        Some(t) if t == expected => Ok(()),
+        other => Err(ParseError::InvalidMessageType(
+            other.unwrap_or("<missing>").to_string(),
+        )),
+    }
+}
+
+/// 解析 exception 帧
+pub fn parse_exception(frame: &Frame) -> ParseResult<KiroException> {
+    expect_message_type(frame, "exception")?;
+
+    let exception_type = frame
+        .headers
+        .exception_type()
+        .unwrap_or("UnknownException")
+        .to_string();
+    let (message, request_id) =
+        PayloadDetail::from_frame(frame).message(frame.headers.error_message());
+
+    Ok(KiroException {
+        exception_type,
+        message,
+        request_id,
+    })
+}
+
+/// 解析 error 帧
+pub fn parse_error_frame(frame: &Frame) -> ParseResult<KiroError> {
+    expect_message_type(frame, "error")?;
+
+    let error_code = frame
+        .headers
+        .error_code()
+        .unwrap_or("UnknownError")
+        .to_string();
+    let (message, request_id) =
+        PayloadDetail::from_frame(frame).message(frame.headers.error_message());
+
+    Ok(KiroError {
+        error_code,
+        message,
+        request_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::parser::frame::{encode_frame, parse_frame};
+
+    fn frame(headers: &[(&str, &str)], payload: &str) -> Frame {
+        let (frame, _) = parse_frame(&encode_frame(headers, payload.as_bytes()))
+            .unwrap()
+            .unwrap();
+        frame
+    }
+
+    #[test]
+    fn test_parse_exception_with_json_payload() {
+        let frame = frame(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", "ThrottlingException"),
+                (":error-message", "header message"),
+            ],
+            r#"{"message":"Too many requests","requestId":"req-123"}"#,
+        );
+        let exception = parse_exception(&frame).unwrap();
+        assert_eq!(
+            exception,
+            KiroException {
+                exception_type: "ThrottlingException".to_string(),
+                message: "Too many requests".to_string(),
+                request_id: Some("req-123".to_string()),
+            }
+        );
+        assert!(!exception.is_content_length_exceeded());
+        assert_eq!(
+            exception.to_string(),
+            "ThrottlingException: Too many requests"
+        );
+    }
+
+    #[test]
+    fn test_parse_exception_falls_back_to_header_and_raw_payload() {
+        let from_header = parse_exception(&frame(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", CONTENT_LENGTH_EXCEEDED_EXCEPTION),
+                (":error-message", "output too long"),
+            ],
+            "",
+        ))
+        .unwrap();
+        assert!(from_header.is_content_length_exceeded());
+        assert_eq!(from_header.message, "output too long");
+        assert_eq!(from_header.request_id, None);
+
+        let from_raw =
+            parse_exception(&frame(&[(":message-type", "exception")], "plain text")).unwrap();
+        assert_eq!(from_raw.exception_type, "UnknownException");
+        assert_eq!(from_raw.message, "plain text");
+    }
+
+    #[test]
+    fn test_parse_error_frame() {
+        let error = parse_error_frame(&frame(
+            &[
+                (":message-type", "error"),
+                (":error-code", "InternalServerError"),
+                (":error-message", "boom"),
+            ],
+            "",
+        ))
+        .unwrap();
+        assert_eq!(error.error_code, "InternalServerError");
+        assert_eq!(error.message, "boom");
+    }
+
+    #[test]
+    fn test_message_type_mismatch_is_rejected() {
+        let event = frame(&[(":message-type", "event")], "{}");
+        assert!(matches!(
+            parse_exception(&event),
+            Err(ParseError::InvalidMessageType(t)) if t == "event"
+        ));
+        assert!(parse_error_frame(&event).is_err());
+        assert!(parse_exception(&frame(&[], "{}")).is_err());
+    }
+}
@@ -18,7 +18,7 @@
 
 use super::crc::crc32;
 use super::error::{ParseError, ParseResult};
-use super::header::{Headers, parse_headers};
+use super::header::{HeaderValueType, Headers, parse_headers};
 
 /// Prelude 固定大小 (12 字节)
 pub const PRELUDE_SIZE: usize = 12;
@@ -153,10 +153,99 @@ pub fn parse_frame(buffer: &[u8]) -> ParseResult<Option<(Frame, usize)>> {
     Ok(Some((Frame { headers, payload }, total_length)))
 }
 
+/// 将字符串头部和负载编码为一个完整的帧（`parse_frame` 的逆操作）
+///
+/// 供模拟上游与 OpenAI 协议后端生成 Event Stream 响应使用
+pub fn encode_frame(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+    let mut header_bytes = Vec::new();
+    for (name, value) in headers {
+        header_bytes.push(name.len() as u8);
+        header_bytes.extend_from_slice(name.as_bytes());
+        header_bytes.push(HeaderValueType::String as u8);
+        header_bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        header_bytes.extend_from_slice(value.as_bytes());
+    }
+
+    let total_length = PRELUDE_SIZE + header_bytes.len() + payload.len() + 4;
+    let mut buffer = Vec::with_capacity(total_length);
+    buffer.extend_from_slice(&(total_length as u32).to_be_bytes());
+    buffer.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+    let prelude_crc = crc32(&buffer);
+    buffer.extend_from_slice(&prelude_crc.to_be_bytes());
+    buffer.extend_from_slice(&header_bytes);
+    buffer.extend_from_slice(payload);
+    let message_crc = crc32(&buffer);
+    buffer.extend_from_slice(&message_crc.to_be_bytes());
+    buffer
+}
+
+/// 编码一个 JSON 负载的事件帧
+pub fn encode_event_frame(event_type: &str, payload: &serde_json::Value) -> Vec<u8> {
+    encode_frame(
+        &[
+            (":message-type", "event"),
+            (":event-type", event_type),
+            (":content-type", "application/json"),
+        ],
+        payload.to_string().as_bytes(),
+    )
+}
+
+/// 按顺序解析字节切片中的全部完整帧
+///
+/// 遇到不完整的尾部数据时结束迭代（不视为错误）；
+/// 其他解析错误返回一次 `Some(Err(..))` 后结束迭代。
+#[cfg(test)]
+pub struct FrameIterator<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+#[cfg(test)]
+impl Iterator for FrameIterator<'_> {
+    type Item = ParseResult<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match parse_frame(&self.data[self.offset..]) {
+            Ok(Some((frame, consumed))) => {
+                self.offset += consumed;
+                Some(Ok(frame))
+            }
+            Ok(None) | Err(ParseError::Incomplete { .. }) => None,
+            Err(e) => {
+                // 出错后无法定位下一帧的边界，直接结束
+                self.offset = self.data.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// 创建遍历 `data` 中所有帧的迭代器
+#[cfg(test)]
+pub fn frames(data: &[u8]) -> FrameIterator<'_> {
+    FrameIterator { data, offset: 0 }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encode_frame_round_trip() {
+        let headers = [
+            (":message-type", "event"),
+            (":event-type", "assistantResponseEvent"),
+        ];
+        let encoded = encode_frame(&headers, br#"{"content":"hi"}"#);
+
+        let (frame, consumed) = parse_frame(&encoded).unwrap().unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(frame.message_type(), Some("event"));
+        assert_eq!(frame.event_type(), Some("assistantResponseEvent"));
+        assert_eq!(frame.payload_as_str(), r#"{"content":"hi"}"#);
+    }
+
     #[test]
     fn test_frame_insufficient_data() {
         let buffer = [0u8; 10]; // 小于 PRELUDE_SIZE
@@ -175,4 +264,65 @@ mod tests {
         let result = parse_frame(&buffer);
         assert!(matches!(result, Err(ParseError::MessageTooSmall { .. })));
     }
+
+    fn event_frame(content: &str) -> Vec<u8> {
+        let payload = format!(r#"{{"content":"{}"}}"#, content);
+        encode_frame(
+            &[
+                (":message-type", "event"),
+                (":event-type", "assistantResponseEvent"),
+            ],
+            payload.as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_frames_iterates_complete_frames() {
+        let data: Vec<u8> = ["a", "b", "c"].into_iter().flat_map(event_frame).collect();
+
+        let results = frames(&data).collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        let payloads: Vec<String> = results
+            .into_iter()
+            .map(|r| r.unwrap().payload_as_str())
+            .collect();
+        assert_eq!(
+            payloads,
+            [
+                r#"{"content":"a"}"#,
+                r#"{"content":"b"}"#,
+                r#"{"content":"c"}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frames_stops_at_incomplete_tail() {
+        let third = event_frame("c");
+        let mut data: Vec<u8> = ["a", "b"].into_iter().flat_map(event_frame).collect();
+        data.extend_from_slice(&third[..third.len() / 2]);
+
+        let mut iter = frames(&data);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_frames_yields_error_once() {
+        let mut data = event_frame("a");
+        let mut corrupted = event_frame("b");
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        data.extend_from_slice(&corrupted);
+        data.extend_from_slice(&event_frame("c"));
+
+        let results = frames(&data).collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(ParseError::MessageCrcMismatch { .. })
+        ));
+    }
 }
@@ -117,6 +117,44 @@ impl Headers {
     pub fn error_code(&self) -> Option<&str> {
         self.get_string(":error-code")
     }
+
+    /// 获取错误消息 (:error-message)
+    pub fn error_message(&self) -> Option<&str> {
+        self.get_string(":error-message")
+    }
+
+    /// 合并另一组头部（如分开到达的 body 头部与 trailer 头部），同名时以 `other` 为准
+    pub fn merge(&mut self, other: Headers) {
+        self.extend(other);
+    }
+
+    /// 仅保留满足条件的头部
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &HeaderValue) -> bool) {
+        self.inner.retain(|name, value| f(name, value));
+    }
+}
+
+impl FromIterator<(String, HeaderValue)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (String, HeaderValue)>>(iter: I) -> Self {
+        Self {
+            inner: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(String, HeaderValue)> for Headers {
+    fn extend<I: IntoIterator<Item = (String, HeaderValue)>>(&mut self, iter: I) {
+        self.inner.extend(iter);
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, HeaderValue);
+    type IntoIter = std::collections::hash_map::IntoIter<String, HeaderValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
 }
 
 /// 从字节流解析头部
@@ -304,6 +342,63 @@ mod tests {
         assert_eq!(headers.message_type(), Some("event"));
     }
 
+    fn string(s: &str) -> HeaderValue {
+        HeaderValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_headers_merge_other_wins() {
+        let mut body = Headers::from_iter([
+            (":message-type".to_string(), string("event")),
+            (":event-type".to_string(), string("assistantResponseEvent")),
+        ]);
+        let trailer = Headers::from_iter([
+            (":event-type".to_string(), string("toolUseEvent")),
+            (":content-type".to_string(), string("application/json")),
+        ]);
+
+        body.merge(trailer);
+
+        assert_eq!(body.message_type(), Some("event"));
+        assert_eq!(body.event_type(), Some("toolUseEvent"));
+        assert_eq!(body.get_string(":content-type"), Some("application/json"));
+    }
+
+    #[test]
+    fn test_headers_into_iter_round_trip() {
+        let mut headers = Headers::new();
+        headers.insert(":message-type".to_string(), string("event"));
+        headers.insert("flag".to_string(), HeaderValue::Bool(true));
+
+        let mut pairs: Vec<(String, HeaderValue)> = headers.clone().into_iter().collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            pairs,
+            vec![
+                (":message-type".to_string(), string("event")),
+                ("flag".to_string(), HeaderValue::Bool(true)),
+            ]
+        );
+
+        let rebuilt = Headers::from_iter(pairs);
+        assert_eq!(rebuilt.message_type(), Some("event"));
+        assert_eq!(rebuilt.get("flag"), Some(&HeaderValue::Bool(true)));
+    }
+
+    #[test]
+    fn test_headers_retain_and_extend() {
+        let mut headers = Headers::from_iter([
+            (":message-type".to_string(), string("event")),
+            ("x-debug".to_string(), string("1")),
+        ]);
+        headers.retain(|name, _| name.starts_with(':'));
+        assert!(headers.get("x-debug").is_none());
+
+        headers.extend([(":error-code".to_string(), string("Throttled"))]);
+        assert_eq!(headers.error_code(), Some("Throttled"));
+        assert_eq!(headers.message_type(), Some("event"));
+    }
+
     #[test]
     fn test_parse_headers_string() {
         // 构造一个简单的头部: name_len(1) + name + type(7=string) + value_len(2) + value
@@ -6,5 +6,6 @@
 pub mod crc;
 pub mod decoder;
 pub mod error;
+pub mod exception;
 pub mod frame;
 pub mod header;
@@ -0,0 +1,96 @@
+//! 按模型估算请求费用
+//!
+//! `pricing` 配置的键按 [`find_by_model`] 的优先级匹配请求中的模型名
+//! （精确模型名 > 最长的 `前缀*` > 默认价格 `*`），均未匹配时不计费用。费用由凭据与用户统计分别累加，仅作粗略估算。
+
+use std::collections::HashMap;
+
+use crate::common::model_pattern::find_by_model;
+use crate::model::config::ModelPrice;
+
+/// 查找模型对应的价格
+pub fn find_price<'a>(
+    pricing: &'a HashMap<String, ModelPrice>,
+    model: &str,
+) -> Option<&'a ModelPrice> {
+    find_by_model(pricing, model)
+}
+
+/// 估算一次请求的费用（未匹配到价格时返回 None）
+pub fn estimate_cost(
+    pricing: &HashMap<String, ModelPrice>,
+    model: &str,
+    input_tokens: u64,
+    output_tokens: u64,
+) -> Option<f64> {
+    let price = find_price(pricing, model)?;
+    Some(
+        price.per_request
+            + input_tokens as f64 / 1000.0 * price.input_per_1k
+            + output_tokens as f64 / 1000.0 * price.output_per_1k,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(per_request: f64, input_per_1k: f64, output_per_1k: f64) -> ModelPrice {
+        ModelPrice {
+            per_request,
+            input_per_1k,
+            output_per_1k,
+        }
+    }
+
+    fn pricing() -> HashMap<String, ModelPrice> {
+        HashMap::from([
+            ("claude-opus-4-5".to_string(), price(0.0, 5.0, 25.0)),
+            ("claude-opus-*".to_string(), price(0.0, 15.0, 75.0)),
+            ("claude-*".to_string(), price(0.0, 3.0, 15.0)),
+            ("*".to_string(), price(0.01, 0.0, 0.0)),
+        ])
+    }
+
+    #[test]
+    fn test_pattern_precedence() {
+        let pricing = pricing();
+        let matched = |model: &str| find_price(&pricing, model).cloned();
+
+        // 精确匹配优先于前缀
+        assert_eq!(matched("claude-opus-4-5"), Some(price(0.0, 5.0, 25.0)));
+        // 最长前缀优先
+        assert_eq!(
+            matched("claude-opus-4-5-20251101"),
+            Some(price(0.0, 15.0, 75.0))
+        );
+        assert_eq!(matched("claude-sonnet-4-5"), Some(price(0.0, 3.0, 15.0)));
+        // 其余回退到默认价格
+        assert_eq!(matched("gpt-4o"), Some(price(0.01, 0.0, 0.0)));
+
+        let without_default: HashMap<String, ModelPrice> = pricing
+            .into_iter()
+            .filter(|(pattern, _)| pattern != "*")
+            .collect();
+        assert_eq!(find_price(&without_default, "gpt-4o"), None);
+        assert_eq!(estimate_cost(&without_default, "gpt-4o", 1000, 1000), None);
+    }
+
+    #[test]
+    fn test_accumulate_across_mixed_models() {
+        let pricing = pricing();
+        let requests = [
+            ("claude-opus-4-5", 2000, 1000),
+            ("claude-opus-4-1", 1000, 0),
+            ("claude-sonnet-4-5", 500, 2000),
+            ("unknown-model", 100_000, 100_000),
+        ];
+
+        let total: f64 = requests
+            .iter()
+            .filter_map(|(model, input, output)| estimate_cost(&pricing, model, *input, *output))
+            .sum();
+        // 10 + 25 + 15 + 1.5 + 30 + 0.01
+        assert!((total - 81.51).abs() < 1e-9, "total: {}", total);
+    }
+}
@@ -4,20 +4,22 @@
 //! 支持流式和非流式请求
 //! 支持多凭据故障转移和重试
 
+use futures::future::BoxFuture;
 use reqwest::Client;
 use reqwest::header::{AUTHORIZATION, CONNECTION, CONTENT_TYPE, HOST, HeaderMap, HeaderValue};
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
-use uuid::Uuid;
 
-use crate::http_client::{ProxyConfig, build_client};
-use crate::kiro::machine_id;
-use crate::kiro::model::credentials::KiroCredentials;
-use crate::kiro::token_manager::{CallContext, MultiTokenManager};
-use crate::model::config::TlsBackend;
-use parking_lot::Mutex;
+use crate::anthropic::converter::convert_request;
+use crate::anthropic::types::MessagesRequest;
+use crate::common::log_throttle::{DEFAULT_LOG_THROTTLE_INTERVAL, log_throttled};
+use crate::http_client::{ClientPool, ProxyConfig};
+use crate::kiro::model::credentials::{KiroCredentials, upstream_host};
+use crate::kiro::model::requests::kiro::KiroRequest;
+use crate::kiro::model::token_count::TokenCountResponse;
+use crate::kiro::token_manager::{CallContext, FailureKind, MultiTokenManager};
+use crate::metrics;
 
 /// 每个凭据的最大重试次数
 const MAX_RETRIES_PER_CREDENTIAL: usize = 3;
@@ -25,6 +27,99 @@ const MAX_RETRIES_PER_CREDENTIAL: usize = 3;
 /// 总重试次数硬上限（避免无限重试）
 const MAX_TOTAL_RETRIES: usize = 9;
 
+/// 上游请求的默认总超时（秒），可由单个请求的 `x-kiro-timeout-secs` 覆盖
+pub const UPSTREAM_TIMEOUT_SECS: u64 = 720;
+
+/// 实际处理请求的凭据信息
+///
+/// 成功响应时写入 `reqwest::Response` 的扩展中，供上层透出到响应头
+#[derive(Debug, Clone)]
+pub struct ServedCredential {
+    /// 凭据 ID
+    pub id: u64,
+    /// 认证方式
+    pub auth_method: Option<String>,
+}
+
+/// 发往上游的完整请求（仅构建，不发起网络调用）
+///
+/// 实际调用与 dry-run 共用同一构建逻辑，保证两者一致
+#[derive(Debug)]
+pub struct UpstreamRequest {
+    /// 使用的凭据 ID
+    pub credential_id: u64,
+    /// 请求头中使用的 machine_id
+    pub machine_id: String,
+    /// 目标 URL
+    pub url: String,
+    /// 请求头（含 Authorization）
+    pub headers: HeaderMap,
+    /// JSON 请求体
+    pub body: String,
+}
+
+/// 一次消息调用
+#[derive(Debug, Clone, Copy)]
+pub struct MessagesCall<'a> {
+    /// Kiro 格式的 JSON 请求体
+    pub request_body: &'a str,
+    /// 是否为流式请求
+    pub is_stream: bool,
+    /// A/B 路由固定使用的凭据 ID
+    pub pinned: Option<u64>,
+    /// 客户端请求的 max_tokens（Kiro 请求体中不携带，仅供模拟上游参考）
+    pub max_tokens: i32,
+    /// 透传模式下客户端提供的上游 Bearer Token（不经过凭据管理）
+    pub passthrough_token: Option<&'a str>,
+    /// 本次请求的上游超时（`x-kiro-timeout-secs`），覆盖 Client 的全局超时；None 时使用全局超时
+    pub timeout: Option<Duration>,
+}
+
+/// 单次上游 API 请求的失败（凭据状态已按失败类型上报）
+#[derive(Debug)]
+pub enum AttemptError {
+    /// 构建请求失败（如 machineId 无法生成、上游地址非法）
+    Build(anyhow::Error),
+    /// 请求发送失败（网络错误等）
+    Send(reqwest::Error),
+    /// 上游返回非成功状态码
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+        /// 上报失败后是否仍有可用凭据
+        has_more: bool,
+    },
+}
+
+impl std::fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Build(e) => write!(f, "构建 API 请求失败: {:#}", e),
+            Self::Send(e) => write!(f, "API 请求发送失败: {}", e),
+            Self::Status { status, body, .. } => write!(f, "API 请求失败: {} {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for AttemptError {}
+
+/// 自发出请求以来经过的毫秒数（上报给凭据的调用耗时，不含获取凭据与 Token 刷新）
+fn elapsed_ms(sent_at: Instant) -> u64 {
+    u64::try_from(sent_at.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// 消息上游
+///
+/// [`KiroProvider`] 调用真实的 Kiro API，[`MockProvider`](crate::kiro::mock::MockProvider)
+/// 在本地生成模拟响应；两者均返回 AWS Event Stream 格式的原始响应
+pub trait Provider: Send + Sync {
+    /// 发送消息请求
+    fn call_messages<'a>(
+        &'a self,
+        call: MessagesCall<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<reqwest::Response>>;
+}
+
 /// Kiro API Provider
 ///
 /// 核心组件，负责与 Kiro API 通信
@@ -33,11 +128,11 @@ pub struct KiroProvider {
     token_manager: Arc<MultiTokenManager>,
     /// 全局代理配置（用于凭据无自定义代理时的回退）
     global_proxy: Option<ProxyConfig>,
-    /// Client 缓存：key = effective proxy config, value = reqwest::Client
+    /// Client 缓存：key = effective proxy config
     /// 不同代理配置的凭据使用不同的 Client，共享相同代理的凭据复用 Client
-    client_cache: Mutex<HashMap<Option<ProxyConfig>, Client>>,
-    /// TLS 后端配置
-    tls_backend: TlsBackend,
+    clients: Arc<ClientPool>,
+    /// 测试用：覆盖上游 API 地址（如 `http://127.0.0.1:port`）
+    endpoint_override: Option<String>,
 }
 
 impl KiroProvider {
@@ -48,31 +143,41 @@ impl KiroProvider {
 
     /// 创建带代理配置的 KiroProvider 实例
     pub fn with_proxy(token_manager: Arc<MultiTokenManager>, proxy: Option<ProxyConfig>) -> Self {
-        let tls_backend = token_manager.config().tls_backend;
+        let config = token_manager.config();
+        let clients = ClientPool::new(
+            UPSTREAM_TIMEOUT_SECS,
+            config.tls_backend,
+            config.pool_idle_timeout_secs,
+        );
         // 预热：构建全局代理对应的 Client
-        let initial_client = build_client(proxy.as_ref(), 720, tls_backend)
+        clients
+            .warm_up(proxy.as_ref())
             .expect("创建 HTTP 客户端失败");
-        let mut cache = HashMap::new();
-        cache.insert(proxy.clone(), initial_client);
 
         Self {
             token_manager,
             global_proxy: proxy,
-            client_cache: Mutex::new(cache),
-            tls_backend,
+            clients: Arc::new(clients),
+            endpoint_override: None,
         }
     }
 
+    /// 测试用：将上游请求指向本地模拟服务器
+    #[cfg(test)]
+    pub(crate) fn with_endpoint_override(mut self, base: impl Into<String>) -> Self {
+        self.endpoint_override = Some(base.into());
+        self
+    }
+
     /// 根据凭据的代理配置获取（或创建并缓存）对应的 reqwest::Client
     fn client_for(&self, credentials: &KiroCredentials) -> anyhow::Result<Client> {
         let effective = credentials.effective_proxy(self.global_proxy.as_ref());
-        let mut cache = self.client_cache.lock();
-        if let Some(client) = cache.get(&effective) {
-            return Ok(client.clone());
-        }
-        let client = build_client(effective.as_ref(), 720, self.tls_backend)?;
-        cache.insert(effective, client.clone());
-        Ok(client)
+        self.clients.get(effective.as_ref())
+    }
+
+    /// 获取 Client 缓存的共享句柄（供 Admin API 查看连接统计与重建 Client）
+    pub fn client_pool(&self) -> Arc<ClientPool> {
+        self.clients.clone()
     }
 
     /// 获取 token_manager 的引用
@@ -80,6 +185,11 @@ impl KiroProvider {
         &self.token_manager
     }
 
+    /// 获取 token_manager 的共享句柄
+    pub fn shared_token_manager(&self) -> Arc<MultiTokenManager> {
+        self.token_manager.clone()
+    }
+
     /// 获取 API 基础 URL（使用 config 级 api_region）
     pub fn base_url(&self) -> String {
         format!(
@@ -101,28 +211,52 @@ impl KiroProvider {
         format!("q.{}.amazonaws.com", self.token_manager.config().effective_api_region())
     }
 
-    /// 获取凭据级 API 基础 URL
-    fn base_url_for(&self, credentials: &KiroCredentials) -> String {
-        format!(
-            "https://q.{}.amazonaws.com/generateAssistantResponse",
+    /// 获取凭据级上游根地址
+    ///
+    /// 优先级：测试覆盖地址 > 凭据.upstream_base_url > 按 API Region 拼接的官方地址
+    fn upstream_root_for(&self, credentials: &KiroCredentials) -> anyhow::Result<String> {
+        if let Some(base) = &self.endpoint_override {
+            return Ok(base.clone());
+        }
+        if let Some(base) = credentials.upstream_base()? {
+            return Ok(base.to_string());
+        }
+        Ok(format!(
+            "https://q.{}.amazonaws.com",
             credentials.effective_api_region(self.token_manager.config())
-        )
+        ))
+    }
+
+    /// 获取凭据级 API 基础 URL
+    fn base_url_for(&self, credentials: &KiroCredentials) -> anyhow::Result<String> {
+        Ok(format!(
+            "{}/generateAssistantResponse",
+            self.upstream_root_for(credentials)?
+        ))
     }
 
     /// 获取凭据级 MCP API URL
-    fn mcp_url_for(&self, credentials: &KiroCredentials) -> String {
-        format!(
-            "https://q.{}.amazonaws.com/mcp",
-            credentials.effective_api_region(self.token_manager.config())
-        )
+    fn mcp_url_for(&self, credentials: &KiroCredentials) -> anyhow::Result<String> {
+        Ok(format!("{}/mcp", self.upstream_root_for(credentials)?))
     }
 
-    /// 获取凭据级 API 基础域名
+    /// 获取凭据级 Token 计数 API URL
+    fn count_tokens_url_for(&self, credentials: &KiroCredentials) -> anyhow::Result<String> {
+        Ok(format!(
+            "{}/getTokenCount",
+            self.upstream_root_for(credentials)?
+        ))
+    }
+
+    /// 获取凭据级 API 基础域名（配置了 upstream_base_url 时使用其主机名）
     fn base_domain_for(&self, credentials: &KiroCredentials) -> String {
-        format!(
-            "q.{}.amazonaws.com",
-            credentials.effective_api_region(self.token_manager.config())
-        )
+        match credentials.upstream_base() {
+            Ok(Some(base)) => upstream_host(base).to_string(),
+            _ => format!(
+                "q.{}.amazonaws.com",
+                credentials.effective_api_region(self.token_manager.config())
+            ),
+        }
     }
 
     /// 从请求体中提取模型信息
@@ -142,26 +276,44 @@ impl KiroProvider {
             .map(|s| s.to_string())
     }
 
+    /// 按调用上下文构建发往上游的请求（纯构建，不发起网络调用）
+    pub fn build_request(
+        &self,
+        ctx: &CallContext,
+        request_body: &str,
+    ) -> anyhow::Result<UpstreamRequest> {
+        Ok(UpstreamRequest {
+            credential_id: ctx.id,
+            machine_id: ctx.identity()?.machine_id().to_string(),
+            url: self.base_url_for(&ctx.credentials)?,
+            headers: self.build_headers(ctx)?,
+            body: request_body.to_string(),
+        })
+    }
+
+    /// 预览请求将发往上游的内容（dry-run）
+    ///
+    /// 凭据选择规则与实际调用一致，但不刷新 Token、不切换当前凭据，也不发起网络调用
+    pub fn preview_request(
+        &self,
+        request_body: &str,
+        pinned_id: Option<u64>,
+    ) -> anyhow::Result<UpstreamRequest> {
+        let model = Self::extract_model_from_request(request_body);
+        let ctx = self
+            .token_manager
+            .preview_context(model.as_deref(), pinned_id)?;
+        self.build_request(&ctx, request_body)
+    }
+
     /// 构建请求头
     ///
     /// # Arguments
     /// * `ctx` - API 调用上下文，包含凭据和 token
     fn build_headers(&self, ctx: &CallContext) -> anyhow::Result<HeaderMap> {
-        let config = self.token_manager.config();
-
-        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, config)
-            .ok_or_else(|| anyhow::anyhow!("无法生成 machine_id，请检查凭证配置"))?;
-
-        let kiro_version = &config.kiro_version;
-        let os_name = &config.system_version;
-        let node_version = &config.node_version;
-
-        let x_amz_user_agent = format!("aws-sdk-js/1.0.27 KiroIDE-{}-{}", kiro_version, machine_id);
-
-        let user_agent = format!(
-            "aws-sdk-js/1.0.27 ua/2.1 os/{} lang/js md/nodejs#{} api/codewhispererstreaming#1.0.27 m/E KiroIDE-{}-{}",
-            os_name, node_version, kiro_version, machine_id
-        );
+        let identity = ctx.identity()?;
+        let x_amz_user_agent = identity.streaming_amz_user_agent();
+        let user_agent = identity.streaming_user_agent();
 
         let mut headers = HeaderMap::new();
 
@@ -182,7 +334,7 @@ impl KiroProvider {
         headers.insert(HOST, HeaderValue::from_str(&self.base_domain_for(&ctx.credentials)).unwrap());
         headers.insert(
             "amz-sdk-invocation-id",
-            HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap(),
+            HeaderValue::from_str(&identity.new_invocation_id()).unwrap(),
         );
         headers.insert(
             "amz-sdk-request",
@@ -199,21 +351,9 @@ impl KiroProvider {
 
     /// 构建 MCP 请求头
     fn build_mcp_headers(&self, ctx: &CallContext) -> anyhow::Result<HeaderMap> {
-        let config = self.token_manager.config();
-
-        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, config)
-            .ok_or_else(|| anyhow::anyhow!("无法生成 machine_id，请检查凭证配置"))?;
-
-        let kiro_version = &config.kiro_version;
-        let os_name = &config.system_version;
-        let node_version = &config.node_version;
-
-        let x_amz_user_agent = format!("aws-sdk-js/1.0.27 KiroIDE-{}-{}", kiro_version, machine_id);
-
-        let user_agent = format!(
-            "aws-sdk-js/1.0.27 ua/2.1 os/{} lang/js md/nodejs#{} api/codewhispererstreaming#1.0.27 m/E KiroIDE-{}-{}",
-            os_name, node_version, kiro_version, machine_id
-        );
+        let identity = ctx.identity()?;
+        let x_amz_user_agent = identity.streaming_amz_user_agent();
+        let user_agent = identity.streaming_user_agent();
 
         let mut headers = HeaderMap::new();
 
@@ -227,7 +367,7 @@ impl KiroProvider {
         headers.insert("host", HeaderValue::from_str(&self.base_domain_for(&ctx.credentials)).unwrap());
         headers.insert(
             "amz-sdk-invocation-id",
-            HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap(),
+            HeaderValue::from_str(&identity.new_invocation_id()).unwrap(),
         );
         headers.insert(
             "amz-sdk-request",
@@ -248,7 +388,8 @@ impl KiroProvider {
     /// - 400 Bad Request: 直接返回错误，不计入凭据失败
     /// - 401/403: 视为凭据/权限问题，计入失败次数并允许故障转移
     /// - 402 MONTHLY_REQUEST_COUNT: 视为额度用尽，禁用凭据并切换
-    /// - 429/5xx/网络等瞬态错误: 重试但不禁用或切换凭据（避免误把所有凭据锁死）
+    /// - 500/502/503/504: 不计入失败次数，换凭据重试（最多 `max_upstream_retries` 次）
+    /// - 429/其他 5xx/网络等瞬态错误: 重试但不禁用或切换凭据（避免误把所有凭据锁死）
     ///
     /// # Arguments
     /// * `request_body` - JSON 格式的请求体字符串
@@ -256,7 +397,8 @@ impl KiroProvider {
     /// # Returns
     /// 返回原始的 HTTP Response，不做解析
     pub async fn call_api(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
-        self.call_api_with_retry(request_body, false).await
+        self.call_api_with_retry(request_body, false, None, None)
+            .await
     }
 
     /// 发送流式 API 请求
@@ -265,7 +407,8 @@ impl KiroProvider {
     /// - 400 Bad Request: 直接返回错误，不计入凭据失败
     /// - 401/403: 视为凭据/权限问题，计入失败次数并允许故障转移
     /// - 402 MONTHLY_REQUEST_COUNT: 视为额度用尽，禁用凭据并切换
-    /// - 429/5xx/网络等瞬态错误: 重试但不禁用或切换凭据（避免误把所有凭据锁死）
+    /// - 500/502/503/504: 不计入失败次数，换凭据重试（最多 `max_upstream_retries` 次，仅在响应开始前）
+    /// - 429/其他 5xx/网络等瞬态错误: 重试但不禁用或切换凭据（避免误把所有凭据锁死）
     ///
     /// # Arguments
     /// * `request_body` - JSON 格式的请求体字符串
@@ -273,7 +416,73 @@ impl KiroProvider {
     /// # Returns
     /// 返回原始的 HTTP Response，调用方负责处理流式数据
     pub async fn call_api_stream(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
-        self.call_api_with_retry(request_body, true).await
+        self.call_api_with_retry(request_body, true, None, None)
+            .await
+    }
+
+    /// 使用调用方已获取的上下文发送非流式消息请求
+    ///
+    /// 请求转换为 Kiro 格式（profileArn 取自该上下文的凭据）后只尝试一次，不重试也不切换凭据：
+    /// 成功时上报成功并在响应扩展中写入 [`ServedCredential`]；失败时按失败类型上报
+    /// （402 额度用尽时禁用凭据，5xx 时切换当前凭据）后返回 [`AttemptError`]，
+    /// 由调用方决定是否重新获取上下文再次调用
+    pub async fn send_with_context(
+        &self,
+        ctx: CallContext,
+        request: &MessagesRequest,
+    ) -> Result<reqwest::Response, AttemptError> {
+        self.send_messages_with_context(ctx, request, "非流式")
+            .await
+    }
+
+    /// 使用调用方已获取的上下文发送流式消息请求（语义同 [`Self::send_with_context`]）
+    pub async fn send_streaming_with_context(
+        &self,
+        ctx: CallContext,
+        request: &MessagesRequest,
+    ) -> Result<reqwest::Response, AttemptError> {
+        self.send_messages_with_context(ctx, request, "流式").await
+    }
+
+    async fn send_messages_with_context(
+        &self,
+        ctx: CallContext,
+        request: &MessagesRequest,
+        api_type: &str,
+    ) -> Result<reqwest::Response, AttemptError> {
+        let conversation_state = convert_request(request)
+            .map_err(|e| AttemptError::Build(anyhow::anyhow!("{} 请求转换失败: {}", api_type, e)))?
+            .conversation_state;
+        let kiro_request = KiroRequest {
+            conversation_state,
+            profile_arn: ctx.credentials.profile_arn.clone(),
+        };
+        let request_body = serde_json::to_string(&kiro_request).map_err(|e| {
+            AttemptError::Build(anyhow::anyhow!("序列化{}请求失败: {}", api_type, e))
+        })?;
+        self.send_once(ctx, &request_body, None, true).await
+    }
+
+    /// 使用客户端提供的 Bearer Token 发送 API 请求（透传模式）
+    ///
+    /// 不经过凭据管理：不获取上下文、不上报成功或失败，也不重试；上游错误原样返回给调用方
+    pub async fn call_api_passthrough(
+        &self,
+        request_body: &str,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let ctx = CallContext::passthrough(token, self.token_manager.config());
+        let response = self
+            .prepare_request(&ctx, request_body, timeout)?
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("API 请求失败（透传模式）: {} {}", status, body);
+        }
+        Ok(response)
     }
 
     /// 发送 MCP API 请求
@@ -289,6 +498,128 @@ impl KiroProvider {
         self.call_mcp_with_retry(request_body).await
     }
 
+    /// 经 Kiro 上游计算请求的输入 tokens（`countTokensFallback.useUpstream`）
+    ///
+    /// 请求体与 generateAssistantResponse 相同，按正常路径选择凭据；
+    /// 计数只是估算，失败时不重试，由调用方回退到本地计算；
+    /// 失败也不计入凭据健康状态，避免辅助接口的异常禁用或切换凭据
+    pub async fn count_tokens(
+        &self,
+        request_body: &str,
+    ) -> anyhow::Result<(u64, ServedCredential)> {
+        let model = Self::extract_model_from_request(request_body);
+        let ctx = self.token_manager.acquire_context(model.as_deref()).await?;
+        let url = self.count_tokens_url_for(&ctx.credentials)?;
+        let headers = self.build_mcp_headers(&ctx)?;
+
+        let sent_at = Instant::now();
+        let response = self
+            .client_for(&ctx.credentials)?
+            .post(&url)
+            .headers(headers)
+            .body(request_body.to_string())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Token 计数请求失败: {} {}", status, body);
+        }
+
+        let data: TokenCountResponse = response.json().await?;
+        self.token_manager.report_success(&ctx, elapsed_ms(sent_at));
+        Ok((
+            data.token_count,
+            ServedCredential {
+                id: ctx.id,
+                auth_method: ctx.credentials.auth_method.clone(),
+            },
+        ))
+    }
+
+    /// 按调用上下文构建可直接发送的上游请求（选择凭据对应的 Client）
+    ///
+    /// 指定 `timeout` 时覆盖 Client 的全局超时（仅对本次请求生效）
+    fn prepare_request(
+        &self,
+        ctx: &CallContext,
+        request_body: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<reqwest::RequestBuilder> {
+        let request = self.build_request(ctx, request_body)?;
+        let builder = self
+            .client_for(&ctx.credentials)?
+            .post(&request.url)
+            .headers(request.headers)
+            .body(request.body);
+        Ok(match timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        })
+    }
+
+    /// 内部方法：使用给定上下文单次发送 API 请求并上报结果
+    ///
+    /// `switch_on_server_error` 为 false 时 500/502/503/504 不上报（不会切换当前凭据），
+    /// 用于重试次数已用尽、不再换凭据重试的最后一次请求
+    async fn send_once(
+        &self,
+        ctx: CallContext,
+        request_body: &str,
+        timeout: Option<Duration>,
+        switch_on_server_error: bool,
+    ) -> Result<reqwest::Response, AttemptError> {
+        let request = self
+            .prepare_request(&ctx, request_body, timeout)
+            .map_err(AttemptError::Build)?;
+        let sent_at = Instant::now();
+        let response = match request.send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.token_manager
+                    .report_failure(&ctx, Self::classify_send_error(&e));
+                return Err(AttemptError::Send(e));
+            }
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            let latency_ms = elapsed_ms(sent_at);
+            tracing::debug!(
+                "凭据 #{} 请求成功：上游耗时 {}ms，含获取凭据总耗时 {}ms",
+                ctx.id,
+                latency_ms,
+                ctx.elapsed_ms()
+            );
+            self.token_manager.report_success(&ctx, latency_ms);
+            let mut response = response;
+            response.extensions_mut().insert(ServedCredential {
+                id: ctx.id,
+                auth_method: ctx.credentials.auth_method,
+            });
+            return Ok(response);
+        }
+
+        // 失败响应：读取 body 用于日志/错误信息
+        let body = response.text().await.unwrap_or_default();
+        let has_more = if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
+            // 上游明确拒绝，按 100% 记录
+            self.token_manager.report_quota_exhausted(ctx.id, 1.0)
+        } else if !switch_on_server_error && matches!(status.as_u16(), 500 | 502 | 503 | 504) {
+            self.token_manager.available_count() > 0
+        } else {
+            self.token_manager
+                .report_failure(&ctx, Self::classify_status(status))
+                .has_more
+        };
+        Err(AttemptError::Status {
+            status,
+            body,
+            has_more,
+        })
+    }
+
     /// 内部方法：带重试逻辑的 MCP API 调用
     async fn call_mcp_with_retry(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
         let total_credentials = self.token_manager.total_count();
@@ -306,7 +637,13 @@ impl KiroProvider {
                 }
             };
 
-            let url = self.mcp_url_for(&ctx.credentials);
+            let url = match self.mcp_url_for(&ctx.credentials) {
+                Ok(u) => u,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
             let headers = match self.build_mcp_headers(&ctx) {
                 Ok(h) => h,
                 Err(e) => {
@@ -316,6 +653,7 @@ impl KiroProvider {
             };
 
             // 发送请求
+            let sent_at = Instant::now();
             let response = match self
                 .client_for(&ctx.credentials)?
                 .post(&url)
@@ -332,6 +670,8 @@ impl KiroProvider {
                         max_retries,
                         e
                     );
+                    self.token_manager
+                        .report_failure(&ctx, Self::classify_send_error(&e));
                     last_error = Some(e.into());
                     if attempt + 1 < max_retries {
                         sleep(Self::retry_delay(attempt)).await;
@@ -344,7 +684,7 @@ impl KiroProvider {
 
             // 成功响应
             if status.is_success() {
-                self.token_manager.report_success(ctx.id);
+                self.token_manager.report_success(&ctx, elapsed_ms(sent_at));
                 return Ok(response);
             }
 
@@ -353,7 +693,7 @@ impl KiroProvider {
 
             // 402 额度用尽
             if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
-                let has_available = self.token_manager.report_quota_exhausted(ctx.id);
+                let has_available = self.token_manager.report_quota_exhausted(ctx.id, 1.0);
                 if !has_available {
                     anyhow::bail!("MCP 请求失败（所有凭据已用尽）: {} {}", status, body);
                 }
@@ -368,7 +708,10 @@ impl KiroProvider {
 
             // 401/403 凭据问题
             if matches!(status.as_u16(), 401 | 403) {
-                let has_available = self.token_manager.report_failure(ctx.id);
+                let has_available = self
+                    .token_manager
+                    .report_failure(&ctx, FailureKind::UpstreamAuth)
+                    .has_more;
                 if !has_available {
                     anyhow::bail!("MCP 请求失败（所有凭据已用尽）: {} {}", status, body);
                 }
@@ -415,13 +758,24 @@ impl KiroProvider {
     /// - 每个凭据最多重试 MAX_RETRIES_PER_CREDENTIAL 次
     /// - 总重试次数 = min(凭据数量 × 每凭据重试次数, MAX_TOTAL_RETRIES)
     /// - 硬上限 9 次，避免无限重试
+    /// - 500/502/503/504 额外受 `max_upstream_retries` 限制，每次重试重新获取上下文（可能换凭据）
+    ///
+    /// 流式请求只在拿到响应状态码时判断是否重试，此时尚未向客户端发送任何数据；
+    /// 一旦返回 Response 开始转发流，后续错误由调用方原样透传。
+    ///
+    /// 指定 `pinned_id` 时始终使用该凭据（A/B 路由），无法获取其上下文（不存在、已禁用、刷新失败）时直接返回错误。
+    /// 指定 `timeout` 时每次尝试都以其覆盖 Client 的全局超时。
     async fn call_api_with_retry(
         &self,
         request_body: &str,
         is_stream: bool,
+        pinned_id: Option<u64>,
+        timeout: Option<Duration>,
     ) -> anyhow::Result<reqwest::Response> {
         let total_credentials = self.token_manager.total_count();
         let max_retries = (total_credentials * MAX_RETRIES_PER_CREDENTIAL).min(MAX_TOTAL_RETRIES);
+        let max_upstream_retries = self.token_manager.config().max_upstream_retries;
+        let mut upstream_retries = 0;
         let mut last_error: Option<anyhow::Error> = None;
         let api_type = if is_stream { "流式" } else { "非流式" };
 
@@ -430,64 +784,65 @@ impl KiroProvider {
 
         for attempt in 0..max_retries {
             // 获取调用上下文（绑定 index、credentials、token）
-            let ctx = match self.token_manager.acquire_context(model.as_deref()).await {
-                Ok(c) => c,
-                Err(e) => {
-                    last_error = Some(e);
-                    continue;
-                }
+            let ctx = match pinned_id {
+                Some(id) => self.token_manager.acquire_context_for_id(id).await?,
+                None => match self.token_manager.acquire_context(model.as_deref()).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        last_error = Some(e);
+                        continue;
+                    }
+                },
             };
-
-            let url = self.base_url_for(&ctx.credentials);
-            let headers = match self.build_headers(&ctx) {
-                Ok(h) => h,
-                Err(e) => {
+            let id = ctx.id;
+
+            // 发送请求（成功/失败已在单次调用中上报；5xx 重试次数已用尽时不再切换凭据）
+            let result = self
+                .send_once(
+                    ctx,
+                    request_body,
+                    timeout,
+                    upstream_retries < max_upstream_retries,
+                )
+                .await;
+            let (status, body, has_available) = match result {
+                Ok(response) => return Ok(response),
+                Err(AttemptError::Build(e)) => {
                     last_error = Some(e);
                     continue;
                 }
-            };
-
-            // 发送请求
-            let response = match self
-                .client_for(&ctx.credentials)?
-                .post(&url)
-                .headers(headers)
-                .body(request_body.to_string())
-                .send()
-                .await
-            {
-                Ok(resp) => resp,
-                Err(e) => {
-                    tracing::warn!(
+                Err(AttemptError::Send(e)) => {
+                    log_throttled!(
+                        warn,
+                        format!("api_send_failed:{}", id),
+                        DEFAULT_LOG_THROTTLE_INTERVAL,
                         "API 请求发送失败（尝试 {}/{}）: {}",
                         attempt + 1,
                         max_retries,
                         e
                     );
                     // 网络错误通常是上游/链路瞬态问题，不应导致"禁用凭据"或"切换凭据"
-                    // （否则一段时间网络抖动会把所有凭据都误禁用，需要重启才能恢复）
+                    // （否则一段时间网络抖动会把所有凭据都误禁用，需要重启才能恢复）；
+                    // 仅当其他凭据同时请求成功时才计入该凭据的失败次数
                     last_error = Some(e.into());
                     if attempt + 1 < max_retries {
                         sleep(Self::retry_delay(attempt)).await;
                     }
                     continue;
                 }
+                Err(AttemptError::Status {
+                    status,
+                    body,
+                    has_more,
+                }) => (status, body, has_more),
             };
 
-            let status = response.status();
-
-            // 成功响应
-            if status.is_success() {
-                self.token_manager.report_success(ctx.id);
-                return Ok(response);
-            }
-
-            // 失败响应：读取 body 用于日志/错误信息
-            let body = response.text().await.unwrap_or_default();
-
             // 402 Payment Required 且额度用尽：禁用凭据并故障转移
             if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
-                tracing::warn!(
+                log_throttled!(
+                    warn,
+                    format!("api_quota_exhausted:{}", id),
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
                     "API 请求失败（额度已用尽，禁用凭据并切换，尝试 {}/{}）: {} {}",
                     attempt + 1,
                     max_retries,
@@ -495,7 +850,6 @@ impl KiroProvider {
                     body
                 );
 
-                let has_available = self.token_manager.report_quota_exhausted(ctx.id);
                 if !has_available {
                     anyhow::bail!(
                         "{} API 请求失败（所有凭据已用尽）: {} {}",
@@ -519,9 +873,12 @@ impl KiroProvider {
                 anyhow::bail!("{} API 请求失败: {} {}", api_type, status, body);
             }
 
-            // 401/403 - 更可能是凭据/权限问题：计入失败并允许故障转移
+            // 401/403 - 更可能是凭据/权限问题：计入失败（达到 authFailureThreshold 后禁用）并允许故障转移
             if matches!(status.as_u16(), 401 | 403) {
-                tracing::warn!(
+                log_throttled!(
+                    warn,
+                    format!("api_credential_error:{}:{}", id, status.as_u16()),
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
                     "API 请求失败（可能为凭据错误，尝试 {}/{}）: {} {}",
                     attempt + 1,
                     max_retries,
@@ -529,7 +886,6 @@ impl KiroProvider {
                     body
                 );
 
-                let has_available = self.token_manager.report_failure(ctx.id);
                 if !has_available {
                     anyhow::bail!(
                         "{} API 请求失败（所有凭据已用尽）: {} {}",
@@ -548,10 +904,53 @@ impl KiroProvider {
                 continue;
             }
 
-            // 429/408/5xx - 瞬态上游错误：重试但不禁用或切换凭据
-            // （避免 429 high traffic / 502 high load 等瞬态错误把所有凭据锁死）
+            // 500/502/503/504 - 上游服务端错误：不计入失败次数，换凭据重试
+            if matches!(status.as_u16(), 500 | 502 | 503 | 504) {
+                if upstream_retries >= max_upstream_retries {
+                    anyhow::bail!("{} API 请求失败: {} {}", api_type, status, body);
+                }
+
+                if !has_available {
+                    anyhow::bail!(
+                        "{} API 请求失败（所有凭据已用尽）: {} {}",
+                        api_type,
+                        status,
+                        body
+                    );
+                }
+
+                upstream_retries += 1;
+                metrics::record_upstream_retry(id, status.as_u16());
+                log_throttled!(
+                    warn,
+                    format!("api_server_error:{}:{}", id, status.as_u16()),
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
+                    "API 请求失败（上游服务端错误，凭据 #{}，重试 {}/{}）: {} {}",
+                    id,
+                    upstream_retries,
+                    max_upstream_retries,
+                    status,
+                    body
+                );
+                last_error = Some(anyhow::anyhow!(
+                    "{} API 请求失败: {} {}",
+                    api_type,
+                    status,
+                    body
+                ));
+                if attempt + 1 < max_retries {
+                    sleep(Self::retry_delay(attempt)).await;
+                }
+                continue;
+            }
+
+            // 429/408/其他 5xx - 瞬态上游错误：重试但不禁用凭据
+            // （避免 429 high traffic 等瞬态错误把所有凭据锁死）
             if matches!(status.as_u16(), 408 | 429) || status.is_server_error() {
-                tracing::warn!(
+                log_throttled!(
+                    warn,
+                    format!("api_transient_error:{}", status.as_u16()),
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
                     "API 请求失败（上游瞬态错误，尝试 {}/{}）: {} {}",
                     attempt + 1,
                     max_retries,
@@ -576,7 +975,10 @@ impl KiroProvider {
             }
 
             // 兜底：当作可重试的瞬态错误处理（不切换凭据）
-            tracing::warn!(
+            log_throttled!(
+                warn,
+                format!("api_unknown_error:{}", status.as_u16()),
+                DEFAULT_LOG_THROTTLE_INTERVAL,
                 "API 请求失败（未知错误，尝试 {}/{}）: {} {}",
                 attempt + 1,
                 max_retries,
@@ -615,6 +1017,27 @@ impl KiroProvider {
         Duration::from_millis(backoff.saturating_add(jitter))
     }
 
+    /// 按响应状态码划分失败类型（402 额度用尽由调用方单独处理）
+    fn classify_status(status: reqwest::StatusCode) -> FailureKind {
+        match status.as_u16() {
+            401 | 403 => FailureKind::UpstreamAuth,
+            408 | 429 => FailureKind::UpstreamThrottle,
+            _ if status.is_server_error() => FailureKind::UpstreamServer,
+            _ => FailureKind::Client,
+        }
+    }
+
+    /// 按请求发送失败的错误划分失败类型
+    ///
+    /// 构建请求失败（URL/请求头非法）是本地转换的问题，其余视为网络错误
+    fn classify_send_error(error: &reqwest::Error) -> FailureKind {
+        if error.is_builder() {
+            FailureKind::Client
+        } else {
+            FailureKind::Network
+        }
+    }
+
     fn is_monthly_request_limit(body: &str) -> bool {
         if body.contains("MONTHLY_REQUEST_COUNT") {
             return true;
@@ -639,10 +1062,30 @@ impl KiroProvider {
     }
 }
 
+impl Provider for KiroProvider {
+    /// 透传模式直接使用客户端 Token；指定凭据时固定使用该凭据，
+    /// 否则按负载均衡选择（支持多凭据故障转移）
+    fn call_messages<'a>(
+        &'a self,
+        call: MessagesCall<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<reqwest::Response>> {
+        Box::pin(async move {
+            if let Some(token) = call.passthrough_token {
+                return self
+                    .call_api_passthrough(call.request_body, token, call.timeout)
+                    .await;
+            }
+            self.call_api_with_retry(call.request_body, call.is_stream, call.pinned, call.timeout)
+                .await
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::kiro::token_manager::CallContext;
+    use crate::kiro::identity::RequestIdentity;
+    use crate::kiro::token_manager::{CallContext, social_refresh_headers, usage_limits_headers};
     use crate::model::config::Config;
 
     fn create_test_provider(config: Config, credentials: KiroCredentials) -> KiroProvider {
@@ -678,11 +1121,13 @@ mod tests {
         credentials.profile_arn = Some("arn:aws:sso::123456789:profile/test".to_string());
         credentials.refresh_token = Some("a".repeat(150));
 
-        let provider = create_test_provider(config, credentials.clone());
+        let provider = create_test_provider(config.clone(), credentials.clone());
         let ctx = CallContext {
             id: 1,
+            identity: RequestIdentity::from_credentials(&credentials, &config).ok(),
             credentials,
             token: "test_token".to_string(),
+            created_at: std::time::Instant::now(),
         };
         let headers = provider.build_headers(&ctx).unwrap();
 
@@ -700,6 +1145,54 @@ mod tests {
         assert_eq!(headers.get(CONNECTION).unwrap(), "close");
     }
 
+    /// 提取 User-Agent 中的 `KiroIDE-{version}-{machineId}` 标识
+    fn ide_token(headers: &HeaderMap, name: &str) -> String {
+        headers
+            .get(name)
+            .unwrap_or_else(|| panic!("缺少请求头 {}", name))
+            .to_str()
+            .unwrap()
+            .split_whitespace()
+            .find(|part| part.starts_with("KiroIDE-"))
+            .unwrap_or_else(|| panic!("{} 中缺少 KiroIDE 标识", name))
+            .to_string()
+    }
+
+    #[test]
+    fn test_identity_token_consistent_across_call_paths() {
+        let mut config = Config::default();
+        config.kiro_version = "0.8.0".to_string();
+        let credentials = valid_credentials();
+        let machine_id =
+            crate::kiro::machine_id::generate_from_credentials(&credentials, &config).unwrap();
+        let expected = format!("KiroIDE-0.8.0-{}", machine_id);
+
+        let provider = create_test_provider(config, credentials);
+        let ctx = provider.token_manager.preview_context(None, None).unwrap();
+
+        let identity = ctx.identity().unwrap();
+        let refresh = social_refresh_headers(identity, "prod.auth.example").unwrap();
+        let usage = usage_limits_headers(identity, "q.example", &ctx.token).unwrap();
+        let generation = provider.build_headers(&ctx).unwrap();
+        let mcp = provider.build_mcp_headers(&ctx).unwrap();
+
+        let paths = [
+            ("refresh", &refresh, "user-agent"),
+            ("usage", &usage, "user-agent"),
+            ("usage", &usage, "x-amz-user-agent"),
+            ("generation", &generation, "user-agent"),
+            ("generation", &generation, "x-amz-user-agent"),
+            ("mcp", &mcp, "user-agent"),
+            ("mcp", &mcp, "x-amz-user-agent"),
+        ];
+        for (path, headers, name) in paths {
+            let token = ide_token(headers, name);
+            assert_eq!(token, expected, "{} {} 的 KiroIDE 标识不一致", path, name);
+        }
+        let request = provider.build_request(&ctx, "{}").unwrap();
+        assert_eq!(request.machine_id, machine_id);
+    }
+
     #[test]
     fn test_is_monthly_request_limit_detects_reason() {
         let body = r#"{"message":"You have reached the limit.","reason":"MONTHLY_REQUEST_COUNT"}"#;
@@ -717,4 +1210,353 @@ mod tests {
         let body = r#"{"message":"nope","reason":"DAILY_REQUEST_COUNT"}"#;
         assert!(!KiroProvider::is_monthly_request_limit(body));
     }
+
+    /// 拥有有效 Token 的测试凭据（无需刷新）
+    fn valid_credentials() -> KiroCredentials {
+        KiroCredentials {
+            access_token: Some("test_token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_status() {
+        let cases = [
+            (400, FailureKind::Client),
+            (404, FailureKind::Client),
+            (413, FailureKind::Client),
+            (401, FailureKind::UpstreamAuth),
+            (403, FailureKind::UpstreamAuth),
+            (408, FailureKind::UpstreamThrottle),
+            (429, FailureKind::UpstreamThrottle),
+            (500, FailureKind::UpstreamServer),
+            (503, FailureKind::UpstreamServer),
+            (505, FailureKind::UpstreamServer),
+        ];
+        for (status, expected) in cases {
+            let status = reqwest::StatusCode::from_u16(status).unwrap();
+            assert_eq!(
+                KiroProvider::classify_status(status),
+                expected,
+                "{}",
+                status
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_classify_send_error() {
+        let client = reqwest::Client::new();
+
+        let builder_error = client.get("not a url").send().await.unwrap_err();
+        assert_eq!(
+            KiroProvider::classify_send_error(&builder_error),
+            FailureKind::Client
+        );
+
+        // 端口 1 上没有服务，连接被拒绝
+        let connect_error = client.get("http://127.0.0.1:1").send().await.unwrap_err();
+        assert_eq!(
+            KiroProvider::classify_send_error(&connect_error),
+            FailureKind::Network
+        );
+    }
+
+    /// 启动模拟上游：按顺序返回 `statuses` 中的状态码（用尽后返回 200），并统计请求次数
+    async fn spawn_upstream(
+        statuses: Vec<u16>,
+    ) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{Router, http::StatusCode, routing::post};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let statuses = Arc::new(statuses);
+        let router = Router::new().route(
+            "/generateAssistantResponse",
+            post(move || {
+                let counter = counter.clone();
+                let statuses = statuses.clone();
+                async move {
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    let status = statuses.get(n).copied().unwrap_or(200);
+                    (StatusCode::from_u16(status).unwrap(), "upstream body")
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        (format!("http://{}", addr), hits)
+    }
+
+    fn provider_with_upstream(
+        max_upstream_retries: usize,
+        credentials: Vec<KiroCredentials>,
+        upstream: &str,
+    ) -> KiroProvider {
+        let mut config = Config::default();
+        config.max_upstream_retries = max_upstream_retries;
+        let tm = MultiTokenManager::new(config, credentials, None, None, false).unwrap();
+        KiroProvider::new(Arc::new(tm)).with_endpoint_override(upstream)
+    }
+
+    #[tokio::test]
+    async fn test_call_api_retries_on_503_then_succeeds() {
+        let (upstream, hits) = spawn_upstream(vec![503]).await;
+        let provider = provider_with_upstream(1, vec![valid_credentials()], &upstream);
+        let before = metrics::upstream_retries(1, 503);
+
+        let response = provider.call_api("{}").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), "upstream body");
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(metrics::upstream_retries(1, 503), before + 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_failure_does_not_affect_credential_health() {
+        use axum::{Router, http::StatusCode, routing::post};
+
+        let router = Router::new().route(
+            "/getTokenCount",
+            post(|| async { (StatusCode::FORBIDDEN, "forbidden") }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let upstream = format!("http://{}", addr);
+        let provider = provider_with_upstream(0, vec![valid_credentials()], &upstream);
+
+        assert!(provider.count_tokens("{}").await.is_err());
+        let entry = &provider.token_manager.snapshot().entries[0];
+        assert!(!entry.disabled);
+        assert_eq!(entry.failure_count, 0);
+        assert_eq!(entry.failure_counts.upstream_auth, 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_api_counts_requests_per_client() {
+        let (upstream, _) = spawn_upstream(vec![503]).await;
+        let provider = provider_with_upstream(1, vec![valid_credentials()], &upstream);
+        let pool = provider.client_pool();
+        assert_eq!(pool.stats()[0].requests_served, 0);
+
+        provider.call_api("{}").await.unwrap();
+        provider.call_api("{}").await.unwrap();
+
+        // 首次请求重试一次，共 3 次上游请求复用同一个直连 Client
+        let stats = pool.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].proxy_url, None);
+        assert_eq!(stats[0].requests_served, 3);
+    }
+
+    #[tokio::test]
+    async fn test_call_api_stream_switches_credential_on_5xx() {
+        let (upstream, hits) = spawn_upstream(vec![502]).await;
+        let provider = provider_with_upstream(
+            2,
+            vec![valid_credentials(), valid_credentials()],
+            &upstream,
+        );
+
+        let response = provider.call_api_stream("{}").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+        // 瞬态错误切换到第二个凭据，且不计入失败次数
+        let snapshot = provider.token_manager().snapshot();
+        assert_eq!(snapshot.current_id, 2);
+        assert!(snapshot.entries.iter().all(|e| e.failure_count == 0));
+    }
+
+    #[tokio::test]
+    async fn test_call_api_stops_after_max_upstream_retries() {
+        let (upstream, hits) = spawn_upstream(vec![503, 503, 503]).await;
+        let provider = provider_with_upstream(1, vec![valid_credentials()], &upstream);
+
+        let err = provider.call_api("{}").await.unwrap_err().to_string();
+        assert!(err.contains("503"), "{}", err);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_final_5xx_does_not_switch_credential() {
+        let (upstream, hits) = spawn_upstream(vec![503]).await;
+        let provider =
+            provider_with_upstream(0, vec![valid_credentials(), valid_credentials()], &upstream);
+
+        // 不再重试的 5xx 直接返回错误，不切换当前凭据
+        let err = provider.call_api("{}").await.unwrap_err().to_string();
+        assert!(err.contains("503"), "{}", err);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(provider.token_manager().snapshot().current_id, 1);
+    }
+
+    fn messages_request() -> MessagesRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hello" }]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_send_with_context_uses_given_credential() {
+        use axum::{Router, routing::post};
+
+        let captured: Arc<parking_lot::Mutex<Vec<(HeaderMap, String)>>> = Arc::default();
+        let sink = captured.clone();
+        let router = Router::new().route(
+            "/generateAssistantResponse",
+            post(move |headers: HeaderMap, body: String| {
+                sink.lock().push((headers, body));
+                async { "upstream body" }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let second = KiroCredentials {
+            refresh_token: Some("b".repeat(150)),
+            profile_arn: Some("arn:aws:codewhisperer:us-east-1:1:profile/second".to_string()),
+            ..valid_credentials()
+        };
+        let machine_id =
+            crate::kiro::machine_id::generate_from_credentials(&second, &Config::default())
+                .unwrap();
+        let provider = provider_with_upstream(
+            0,
+            vec![valid_credentials(), second],
+            &format!("http://{}", addr),
+        );
+
+        // 当前凭据为 #1，显式传入 #2 的上下文时不应重新选择凭据
+        let mut ctx = provider
+            .token_manager()
+            .preview_context(None, Some(2))
+            .unwrap();
+        ctx.token = "explicit_token".to_string();
+        let response = provider
+            .send_with_context(ctx, &messages_request())
+            .await
+            .unwrap();
+        assert_eq!(
+            response.extensions().get::<ServedCredential>().unwrap().id,
+            2
+        );
+
+        let (headers, body) = captured.lock().pop().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["profileArn"],
+            "arn:aws:codewhisperer:us-east-1:1:profile/second"
+        );
+        assert!(body["conversationState"].is_object());
+        assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer explicit_token");
+        assert!(ide_token(&headers, "user-agent").ends_with(&machine_id));
+        assert!(ide_token(&headers, "x-amz-user-agent").ends_with(&machine_id));
+        let snapshot = provider.token_manager().snapshot();
+        assert_eq!(snapshot.current_id, 1);
+        assert_eq!(snapshot.entries[1].success_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_streaming_with_context_does_not_retry() {
+        let (upstream, hits) = spawn_upstream(vec![503]).await;
+        let provider = provider_with_upstream(3, vec![valid_credentials()], &upstream);
+
+        let ctx = provider
+            .token_manager()
+            .preview_context(None, None)
+            .unwrap();
+        let err = provider
+            .send_streaming_with_context(ctx, &messages_request())
+            .await
+            .unwrap_err();
+        match err {
+            AttemptError::Status {
+                status, has_more, ..
+            } => {
+                assert_eq!(status.as_u16(), 503);
+                assert!(has_more);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_credential_upstream_base_url_override() {
+        use axum::{Router, routing::post};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = Router::new().route(
+            "/kiro/generateAssistantResponse",
+            post(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    "proxied body"
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let credentials = KiroCredentials {
+            upstream_base_url: Some(format!("http://{}/kiro/", addr)),
+            ..valid_credentials()
+        };
+        let provider = create_test_provider(Config::default(), credentials);
+
+        let response = provider.call_api("{}").await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "proxied body");
+        let response = provider.call_api_stream("{}").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_upstream_urls_for_credential() {
+        let provider = create_test_provider(Config::default(), KiroCredentials::default());
+        let credentials = KiroCredentials {
+            upstream_base_url: Some("https://relay.example.com:8443/kiro".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            provider.base_url_for(&credentials).unwrap(),
+            "https://relay.example.com:8443/kiro/generateAssistantResponse"
+        );
+        assert_eq!(
+            provider.mcp_url_for(&credentials).unwrap(),
+            "https://relay.example.com:8443/kiro/mcp"
+        );
+        assert_eq!(
+            provider.base_domain_for(&credentials),
+            "relay.example.com:8443"
+        );
+
+        let invalid = KiroCredentials {
+            upstream_base_url: Some("https://relay.example.com/kiro?key=1".to_string()),
+            ..Default::default()
+        };
+        assert!(provider.base_url_for(&invalid).is_err());
+    }
 }
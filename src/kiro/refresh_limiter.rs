@@ -0,0 +1,559 @@
+//! Token 刷新限流模块
+//!
+//! 过于频繁地调用刷新端点会触发上游 429，并可能导致账号被标记。
+//! 本模块按凭据维度限制刷新频率：
+//! - 两次刷新之间至少间隔 `min_interval`
+//! - 上游返回 429 后，在 Retry-After 窗口内不再尝试刷新
+//! - 统计最近一小时内的刷新次数（用于 Admin API 展示）
+//! - 刷新失败后在退避窗口内降低该凭据的选择优先级（窗口随连续失败次数指数增长）
+//!
+//! 所有方法都显式接收 `now`，便于在测试中模拟时钟。
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// 刷新次数统计窗口
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// 刷新被限流时返回的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshLimitError {
+    /// 距上次刷新不足最小间隔
+    TooFrequent { id: u64, retry_in: Duration },
+    /// 上游返回 429，仍处于 Retry-After 退避窗口内
+    Backoff { id: u64, retry_in: Duration },
+}
+
+impl fmt::Display for RefreshLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefreshLimitError::TooFrequent { id, retry_in } => write!(
+                f,
+                "凭据 #{} 刷新过于频繁，{} 秒后可再次刷新",
+                id,
+                retry_in.as_secs()
+            ),
+            RefreshLimitError::Backoff { id, retry_in } => write!(
+                f,
+                "凭据 #{} 刷新已被上游限流，{} 秒后可再次刷新",
+                id,
+                retry_in.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RefreshLimitError {}
+
+/// 上游刷新端点返回 429 时的错误（携带 Retry-After）
+#[derive(Debug)]
+pub struct RefreshRateLimited {
+    /// 上游 Retry-After 头解析出的等待时间
+    pub retry_after: Option<Duration>,
+    /// 原始错误信息
+    pub message: String,
+}
+
+impl fmt::Display for RefreshRateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RefreshRateLimited {}
+
+/// 刷新端点拒绝凭据（401）：刷新令牌已失效或被撤销，重试无意义
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshAuthenticationFailed {
+    /// 错误描述（包含上游状态码与响应体）
+    pub message: String,
+}
+
+impl fmt::Display for RefreshAuthenticationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RefreshAuthenticationFailed {}
+
+/// 等待刷新锁超时的错误（其他请求的刷新可能被上游挂起）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshLockTimeout {
+    /// 凭据 ID
+    pub id: u64,
+    /// 等待时长
+    pub timeout: Duration,
+}
+
+impl fmt::Display for RefreshLockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Token refresh lock timeout after {}s (credential #{})",
+            self.timeout.as_secs(),
+            self.id
+        )
+    }
+}
+
+impl std::error::Error for RefreshLockTimeout {}
+
+/// 解析 Retry-After 头（仅支持秒数格式）
+pub fn parse_retry_after(value: Option<&str>) -> Option<Duration> {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// 单个凭据的刷新状态
+#[derive(Debug, Default)]
+struct RefreshState {
+    /// 最近一次刷新尝试时间
+    last_attempt: Option<Instant>,
+    /// 429 退避截止时间
+    blocked_until: Option<Instant>,
+    /// 统计窗口内的刷新尝试时间
+    attempts: VecDeque<Instant>,
+    /// 最近一次刷新失败时间（刷新成功后清除）
+    last_failure: Option<Instant>,
+    /// 连续刷新失败次数（刷新成功后清零）
+    failure_streak: u32,
+}
+
+impl RefreshState {
+    fn prune(&mut self, now: Instant) {
+        while let Some(front) = self.attempts.front() {
+            if now.saturating_duration_since(*front) >= ATTEMPT_WINDOW {
+                self.attempts.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// 单个凭据可迁移的刷新限流状态（时间均为相对导出时刻的毫秒数，跨进程有效）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshLimitState {
+    /// 距最近一次刷新尝试的毫秒数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_ago_ms: Option<u64>,
+    /// 429 退避剩余毫秒数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_remaining_ms: Option<u64>,
+    /// 统计窗口内各次刷新尝试距今的毫秒数（按时间先后排列）
+    #[serde(default)]
+    pub attempts_ago_ms: Vec<u64>,
+    /// 距最近一次刷新失败的毫秒数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure_ago_ms: Option<u64>,
+    /// 连续刷新失败次数
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failure_streak: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// 按凭据维度的刷新限流器
+#[derive(Debug)]
+pub struct RefreshLimiter {
+    min_interval: Duration,
+    /// 上游 429 未携带 Retry-After 时使用的退避时间
+    default_backoff: Duration,
+    /// 首次刷新失败后的选择退避时间（为 0 时不退避）
+    failure_backoff: Duration,
+    /// 刷新失败选择退避时间上限
+    failure_backoff_max: Duration,
+    states: HashMap<u64, RefreshState>,
+}
+
+impl RefreshLimiter {
+    pub fn new(min_interval: Duration, default_backoff: Duration) -> Self {
+        Self {
+            min_interval,
+            default_backoff,
+            failure_backoff: Duration::ZERO,
+            failure_backoff_max: Duration::ZERO,
+            states: HashMap::new(),
+        }
+    }
+
+    /// 设置刷新失败后的选择退避：首次失败退避 `base`，之后每次连续失败翻倍，最多 `max`
+    pub fn with_failure_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.failure_backoff = base;
+        self.failure_backoff_max = max.max(base);
+        self
+    }
+
+    /// 检查指定凭据当前是否允许刷新
+    pub fn check(&self, id: u64, now: Instant) -> Result<(), RefreshLimitError> {
+        let Some(state) = self.states.get(&id) else {
+            return Ok(());
+        };
+
+        if let Some(until) = state.blocked_until.filter(|until| *until > now) {
+            return Err(RefreshLimitError::Backoff {
+                id,
+                retry_in: until - now,
+            });
+        }
+
+        if let Some(last) = state.last_attempt {
+            let elapsed = now.saturating_duration_since(last);
+            if elapsed < self.min_interval {
+                return Err(RefreshLimitError::TooFrequent {
+                    id,
+                    retry_in: self.min_interval - elapsed,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 记录一次刷新尝试
+    pub fn record_attempt(&mut self, id: u64, now: Instant) {
+        let state = self.states.entry(id).or_default();
+        state.last_attempt = Some(now);
+        state.attempts.push_back(now);
+        state.prune(now);
+    }
+
+    /// 记录一次上游 429，在退避窗口内阻止后续刷新
+    pub fn record_rate_limited(&mut self, id: u64, now: Instant, retry_after: Option<Duration>) {
+        let backoff = retry_after.unwrap_or(self.default_backoff);
+        self.states.entry(id).or_default().blocked_until = Some(now + backoff);
+    }
+
+    /// 记录一次刷新失败（连续失败次数加一）
+    pub fn record_failure(&mut self, id: u64, now: Instant) {
+        let state = self.states.entry(id).or_default();
+        state.last_failure = Some(now);
+        state.failure_streak = state.failure_streak.saturating_add(1);
+    }
+
+    /// 记录一次刷新成功，清除失败退避
+    pub fn record_success(&mut self, id: u64) {
+        if let Some(state) = self.states.get_mut(&id) {
+            state.last_failure = None;
+            state.failure_streak = 0;
+        }
+    }
+
+    /// 刷新失败退避剩余时间（未处于退避时返回 None）
+    ///
+    /// 退避期间选择凭据时应优先跳过该凭据（没有其他可用凭据时仍可使用）
+    pub fn failure_backoff_remaining(&self, id: u64, now: Instant) -> Option<Duration> {
+        let state = self.states.get(&id)?;
+        let last_failure = state.last_failure?;
+        let until = last_failure + self.failure_backoff_for(state.failure_streak);
+        (until > now).then(|| until - now)
+    }
+
+    /// 连续失败 `streak` 次后的退避时间
+    fn failure_backoff_for(&self, streak: u32) -> Duration {
+        let exponent = streak.saturating_sub(1).min(16);
+        self.failure_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.failure_backoff_max)
+    }
+
+    /// 最近一小时内的刷新尝试次数
+    pub fn attempts_last_hour(&self, id: u64, now: Instant) -> usize {
+        self.states
+            .get(&id)
+            .map(|s| {
+                s.attempts
+                    .iter()
+                    .filter(|t| now.saturating_duration_since(**t) < ATTEMPT_WINDOW)
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// 429 退避剩余时间（未处于退避时返回 None）
+    pub fn backoff_remaining(&self, id: u64, now: Instant) -> Option<Duration> {
+        self.states
+            .get(&id)
+            .and_then(|s| s.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// 移除凭据的刷新状态（凭据被删除时调用）
+    pub fn remove(&mut self, id: u64) {
+        self.states.remove(&id);
+    }
+
+    /// 导出指定凭据的限流状态（没有任何记录时返回 None）
+    pub fn export_state(&self, id: u64, now: Instant) -> Option<RefreshLimitState> {
+        let state = self.states.get(&id)?;
+        let ago = |t: &Instant| now.saturating_duration_since(*t).as_millis() as u64;
+        Some(RefreshLimitState {
+            last_attempt_ago_ms: state.last_attempt.as_ref().map(ago),
+            backoff_remaining_ms: state
+                .blocked_until
+                .filter(|until| *until > now)
+                .map(|until| (until - now).as_millis() as u64),
+            attempts_ago_ms: state
+                .attempts
+                .iter()
+                .filter(|t| now.saturating_duration_since(**t) < ATTEMPT_WINDOW)
+                .map(ago)
+                .collect(),
+            last_failure_ago_ms: state.last_failure.as_ref().map(ago),
+            failure_streak: state.failure_streak,
+        })
+    }
+
+    /// 导入指定凭据的限流状态，覆盖已有记录
+    ///
+    /// 早于本进程时钟起点、无法表示的时间点会被丢弃
+    pub fn import_state(&mut self, id: u64, imported: &RefreshLimitState, now: Instant) {
+        let before = |ms: u64| now.checked_sub(Duration::from_millis(ms));
+        let mut attempts: Vec<Instant> = imported
+            .attempts_ago_ms
+            .iter()
+            .filter_map(|ms| before(*ms))
+            .collect();
+        attempts.sort();
+        let mut state = RefreshState {
+            last_attempt: imported.last_attempt_ago_ms.and_then(before),
+            blocked_until: imported
+                .backoff_remaining_ms
+                .map(|ms| now + Duration::from_millis(ms)),
+            attempts: attempts.into(),
+            last_failure: imported.last_failure_ago_ms.and_then(before),
+            failure_streak: imported.failure_streak,
+        };
+        state.prune(now);
+        self.states.insert(id, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RefreshLimiter {
+        RefreshLimiter::new(Duration::from_secs(60), Duration::from_secs(120))
+    }
+
+    #[test]
+    fn test_first_refresh_is_allowed() {
+        let limiter = limiter();
+        assert!(limiter.check(1, Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_min_interval_enforced() {
+        let mut limiter = limiter();
+        let t0 = Instant::now();
+        limiter.record_attempt(1, t0);
+
+        let err = limiter.check(1, t0 + Duration::from_secs(10)).unwrap_err();
+        assert_eq!(
+            err,
+            RefreshLimitError::TooFrequent {
+                id: 1,
+                retry_in: Duration::from_secs(50)
+            }
+        );
+        // 其他凭据不受影响
+        assert!(limiter.check(2, t0 + Duration::from_secs(10)).is_ok());
+        assert!(limiter.check(1, t0 + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limited_blocks_until_retry_after() {
+        let mut limiter = limiter();
+        let t0 = Instant::now();
+        limiter.record_attempt(1, t0);
+        limiter.record_rate_limited(1, t0, Some(Duration::from_secs(300)));
+
+        // 超过最小间隔但仍在 Retry-After 窗口内
+        let at = t0 + Duration::from_secs(90);
+        assert!(matches!(
+            limiter.check(1, at),
+            Err(RefreshLimitError::Backoff { id: 1, .. })
+        ));
+        assert_eq!(
+            limiter.backoff_remaining(1, at),
+            Some(Duration::from_secs(210))
+        );
+
+        let after = t0 + Duration::from_secs(300);
+        assert!(limiter.check(1, after).is_ok());
+        assert_eq!(limiter.backoff_remaining(1, after), None);
+    }
+
+    #[test]
+    fn test_rate_limited_without_retry_after_uses_default() {
+        let mut limiter = limiter();
+        let t0 = Instant::now();
+        limiter.record_rate_limited(1, t0, None);
+        assert!(limiter.check(1, t0 + Duration::from_secs(119)).is_err());
+        assert!(limiter.check(1, t0 + Duration::from_secs(120)).is_ok());
+    }
+
+    #[test]
+    fn test_attempts_last_hour() {
+        let mut limiter = RefreshLimiter::new(Duration::ZERO, Duration::ZERO);
+        let t0 = Instant::now();
+        for i in 0..3 {
+            limiter.record_attempt(1, t0 + Duration::from_secs(i * 1200));
+        }
+        assert_eq!(
+            limiter.attempts_last_hour(1, t0 + Duration::from_secs(2400)),
+            3
+        );
+        assert_eq!(
+            limiter.attempts_last_hour(1, t0 + Duration::from_secs(3600)),
+            2
+        );
+        assert_eq!(limiter.attempts_last_hour(2, t0), 0);
+    }
+
+    #[test]
+    fn test_export_import_state_round_trip() {
+        let mut source = limiter();
+        let t0 = Instant::now();
+        source.record_attempt(1, t0);
+        source.record_attempt(1, t0 + Duration::from_secs(100));
+        source.record_rate_limited(
+            1,
+            t0 + Duration::from_secs(100),
+            Some(Duration::from_secs(300)),
+        );
+        let exported_at = t0 + Duration::from_secs(130);
+        let state = source.export_state(1, exported_at).unwrap();
+        assert_eq!(
+            state,
+            RefreshLimitState {
+                last_attempt_ago_ms: Some(30_000),
+                backoff_remaining_ms: Some(270_000),
+                attempts_ago_ms: vec![130_000, 30_000],
+                ..Default::default()
+            }
+        );
+        assert_eq!(source.export_state(2, exported_at), None);
+
+        // 在另一个进程中导入：时钟起点不同，但相对时间保持一致
+        let mut target = limiter();
+        let t1 = Instant::now() + Duration::from_secs(3600);
+        target.import_state(1, &state, t1);
+        assert_eq!(target.attempts_last_hour(1, t1), 2);
+        assert_eq!(
+            target.backoff_remaining(1, t1),
+            Some(Duration::from_secs(270))
+        );
+        assert_eq!(target.export_state(1, t1), Some(state));
+    }
+
+    fn failure_limiter() -> RefreshLimiter {
+        limiter().with_failure_backoff(Duration::from_secs(30), Duration::from_secs(100))
+    }
+
+    #[test]
+    fn test_failure_backoff_grows_until_cap() {
+        let mut limiter = failure_limiter();
+        let t0 = Instant::now();
+        assert_eq!(limiter.failure_backoff_remaining(1, t0), None);
+
+        limiter.record_failure(1, t0);
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t0 + Duration::from_secs(10)),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t0 + Duration::from_secs(30)),
+            None
+        );
+
+        // 连续失败：30s → 60s → 100s（上限）
+        let t1 = t0 + Duration::from_secs(30);
+        limiter.record_failure(1, t1);
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t1),
+            Some(Duration::from_secs(60))
+        );
+        let t2 = t1 + Duration::from_secs(60);
+        limiter.record_failure(1, t2);
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t2),
+            Some(Duration::from_secs(100))
+        );
+        limiter.record_failure(1, t2);
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t2),
+            Some(Duration::from_secs(100))
+        );
+        // 其他凭据不受影响
+        assert_eq!(limiter.failure_backoff_remaining(2, t2), None);
+    }
+
+    #[test]
+    fn test_failure_backoff_resets_on_success() {
+        let mut limiter = failure_limiter();
+        let t0 = Instant::now();
+        limiter.record_failure(1, t0);
+        limiter.record_failure(1, t0);
+        limiter.record_success(1);
+        assert_eq!(limiter.failure_backoff_remaining(1, t0), None);
+
+        // 成功后重新从初始退避开始
+        limiter.record_failure(1, t0);
+        assert_eq!(
+            limiter.failure_backoff_remaining(1, t0),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_failure_backoff_disabled_by_default() {
+        let mut limiter = limiter();
+        let t0 = Instant::now();
+        limiter.record_failure(1, t0);
+        assert_eq!(limiter.failure_backoff_remaining(1, t0), None);
+    }
+
+    #[test]
+    fn test_export_import_failure_backoff() {
+        let mut source = failure_limiter();
+        let t0 = Instant::now();
+        source.record_failure(1, t0);
+        source.record_failure(1, t0);
+        let exported_at = t0 + Duration::from_secs(20);
+        let state = source.export_state(1, exported_at).unwrap();
+        assert_eq!(state.last_failure_ago_ms, Some(20_000));
+        assert_eq!(state.failure_streak, 2);
+
+        let mut target = failure_limiter();
+        let t1 = Instant::now() + Duration::from_secs(3600);
+        target.import_state(1, &state, t1);
+        assert_eq!(
+            target.failure_backoff_remaining(1, t1),
+            Some(Duration::from_secs(40))
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(
+            parse_retry_after(Some(" 30 ")),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after(Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            None
+        );
+        assert_eq!(parse_retry_after(None), None);
+    }
+}
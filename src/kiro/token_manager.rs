@@ -4,25 +4,44 @@
 //! 支持单凭据 (TokenManager) 和多凭据 (MultiTokenManager) 管理
 
 use anyhow::bail;
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
 use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tokio::sync::Mutex as TokioMutex;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration as StdDuration, Instant};
 
+use crate::common::log_throttle::{DEFAULT_LOG_THROTTLE_INTERVAL, log_throttled};
 use crate::http_client::{ProxyConfig, build_client};
+use crate::kiro::balance_cache::{BalanceCache, CachedBalance, UsageSnapshot};
+use crate::kiro::credentials_writer::{
+    CredentialsWriter, ExternalEditGuard, write_atomic, write_atomic_private,
+};
+use crate::kiro::identity::RequestIdentity;
 use crate::kiro::machine_id;
-use crate::kiro::model::credentials::KiroCredentials;
+use crate::kiro::model::credentials::{
+    CredentialsConfig, CredentialsMigration, KiroCredentials, MonthlyRequestLimit,
+};
 use crate::kiro::model::token_refresh::{
     IdcRefreshRequest, IdcRefreshResponse, RefreshRequest, RefreshResponse,
 };
 use crate::kiro::model::usage_limits::UsageLimitsResponse;
-use crate::model::config::Config;
+use crate::kiro::pricing;
+use crate::kiro::refresh_limiter::{
+    RefreshAuthenticationFailed, RefreshLimitState, RefreshLimiter, RefreshLockTimeout,
+    RefreshRateLimited, parse_retry_after,
+};
+use crate::kiro::user_usage::{UserLimitExceeded, UserUsageSnapshot, UserUsageTracker};
+use crate::metrics;
+use crate::model::config::{AutoRecoverMode, Config};
 
 /// Token 管理器
 ///
@@ -78,7 +97,15 @@ impl TokenManager {
     /// 调用 getUsageLimits API 查询当前账户的使用额度
     pub async fn get_usage_limits(&mut self) -> anyhow::Result<UsageLimitsResponse> {
         let token = self.ensure_valid_token().await?;
-        get_usage_limits(&self.credentials, &self.config, &token, self.proxy.as_ref()).await
+        let identity = RequestIdentity::from_credentials(&self.credentials, &self.config)?;
+        get_usage_limits(
+            &self.credentials,
+            &self.config,
+            &identity,
+            &token,
+            self.proxy.as_ref(),
+        )
+        .await
     }
 }
 
@@ -104,13 +131,37 @@ pub(crate) fn is_token_expiring_soon(credentials: &KiroCredentials) -> bool {
     is_token_expiring_within(credentials, 10).unwrap_or(false)
 }
 
-fn sha256_hex(input: &str) -> String {
+pub(crate) fn sha256_hex(input: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(input.as_bytes());
     let result = hasher.finalize();
     format!("{:x}", result)
 }
 
+/// 当前 Unix 时间（秒，保留毫秒精度）
+fn unix_now() -> f64 {
+    Utc::now().timestamp_millis() as f64 / 1000.0
+}
+
+/// 取两个 RFC3339 时间中较晚的一个（无法解析的一方视为更早）
+fn later_rfc3339(a: Option<String>, b: Option<String>) -> Option<String> {
+    let parse = |s: &Option<String>| {
+        s.as_deref()
+            .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+    };
+    match (parse(&a), parse(&b)) {
+        (Some(ta), Some(tb)) => {
+            if tb > ta {
+                b
+            } else {
+                a
+            }
+        }
+        (None, Some(_)) => b,
+        _ => a.or(b),
+    }
+}
+
 /// 验证 refreshToken 的基本有效性
 pub(crate) fn validate_refresh_token(credentials: &KiroCredentials) -> anyhow::Result<()> {
     let refresh_token = credentials
@@ -144,28 +195,40 @@ pub(crate) async fn refresh_token(
 
     // 根据 auth_method 选择刷新方式
     // 如果未指定 auth_method，根据是否有 clientId/clientSecret 自动判断
-    let auth_method = credentials.auth_method.as_deref().unwrap_or_else(|| {
-        if credentials.client_id.is_some() && credentials.client_secret.is_some() {
-            "idc"
-        } else {
-            "social"
-        }
-    });
-
-    if auth_method.eq_ignore_ascii_case("idc")
-        || auth_method.eq_ignore_ascii_case("builder-id")
-        || auth_method.eq_ignore_ascii_case("iam")
-    {
+    if credentials.canonical_auth_method() == "idc" {
         refresh_idc_token(credentials, config, proxy).await
     } else {
-        refresh_social_token(credentials, config, proxy).await
+        let identity = RequestIdentity::from_credentials(credentials, config)?;
+        refresh_social_token(credentials, config, &identity, proxy).await
     }
 }
 
+/// Social Token 刷新请求头
+pub(crate) fn social_refresh_headers(
+    identity: &RequestIdentity,
+    refresh_domain: &str,
+) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Accept",
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+    headers.insert("User-Agent", identity.refresh_user_agent().parse()?);
+    headers.insert(
+        "Accept-Encoding",
+        HeaderValue::from_static("gzip, compress, deflate, br"),
+    );
+    headers.insert("host", refresh_domain.parse()?);
+    headers.insert("Connection", HeaderValue::from_static("close"));
+    Ok(headers)
+}
+
 /// 刷新 Social Token
 async fn refresh_social_token(
     credentials: &KiroCredentials,
     config: &Config,
+    identity: &RequestIdentity,
     proxy: Option<&ProxyConfig>,
 ) -> anyhow::Result<KiroCredentials> {
     tracing::info!("正在刷新 Social Token...");
@@ -176,9 +239,6 @@ async fn refresh_social_token(
 
     let refresh_url = format!("https://prod.{}.auth.desktop.kiro.dev/refreshToken", region);
     let refresh_domain = format!("prod.{}.auth.desktop.kiro.dev", region);
-    let machine_id = machine_id::generate_from_credentials(credentials, config)
-        .ok_or_else(|| anyhow::anyhow!("无法生成 machineId"))?;
-    let kiro_version = &config.kiro_version;
 
     let client = build_client(proxy, 60, config.tls_backend)?;
     let body = RefreshRequest {
@@ -187,21 +247,19 @@ async fn refresh_social_token(
 
     let response = client
         .post(&refresh_url)
-        .header("Accept", "application/json, text/plain, */*")
-        .header("Content-Type", "application/json")
-        .header(
-            "User-Agent",
-            format!("KiroIDE-{}-{}", kiro_version, machine_id),
-        )
-        .header("Accept-Encoding", "gzip, compress, deflate, br")
-        .header("host", &refresh_domain)
-        .header("Connection", "close")
+        .headers(social_refresh_headers(identity, &refresh_domain)?)
         .json(&body)
         .send()
         .await?;
 
     let status = response.status();
     if !status.is_success() {
+        let retry_after = parse_retry_after(
+            response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()),
+        );
         let body_text = response.text().await.unwrap_or_default();
         let error_msg = match status.as_u16() {
             401 => "OAuth 凭证已过期或无效，需要重新认证",
@@ -210,7 +268,18 @@ async fn refresh_social_token(
             500..=599 => "服务器错误，AWS OAuth 服务暂时不可用",
             _ => "Token 刷新失败",
         };
-        bail!("{}: {} {}", error_msg, status, body_text);
+        let message = format!("{}: {} {}", error_msg, status, body_text);
+        if status.as_u16() == 429 {
+            return Err(RefreshRateLimited {
+                retry_after,
+                message,
+            }
+            .into());
+        }
+        if status.as_u16() == 401 {
+            return Err(RefreshAuthenticationFailed { message }.into());
+        }
+        bail!(message);
     }
 
     let data: RefreshResponse = response.json().await?;
@@ -284,6 +353,12 @@ async fn refresh_idc_token(
 
     let status = response.status();
     if !status.is_success() {
+        let retry_after = parse_retry_after(
+            response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()),
+        );
         let body_text = response.text().await.unwrap_or_default();
         let error_msg = match status.as_u16() {
             401 => "IdC 凭证已过期或无效，需要重新认证",
@@ -292,7 +367,18 @@ async fn refresh_idc_token(
             500..=599 => "服务器错误，AWS OIDC 服务暂时不可用",
             _ => "IdC Token 刷新失败",
         };
-        bail!("{}: {} {}", error_msg, status, body_text);
+        let message = format!("{}: {} {}", error_msg, status, body_text);
+        if status.as_u16() == 429 {
+            return Err(RefreshRateLimited {
+                retry_after,
+                message,
+            }
+            .into());
+        }
+        if status.as_u16() == 401 {
+            return Err(RefreshAuthenticationFailed { message }.into());
+        }
+        bail!(message);
     }
 
     let data: IdcRefreshResponse = response.json().await?;
@@ -312,13 +398,32 @@ async fn refresh_idc_token(
     Ok(new_credentials)
 }
 
-/// getUsageLimits API 所需的 x-amz-user-agent header 前缀
-const USAGE_LIMITS_AMZ_USER_AGENT_PREFIX: &str = "aws-sdk-js/1.0.0";
+/// getUsageLimits 请求头
+pub(crate) fn usage_limits_headers(
+    identity: &RequestIdentity,
+    host: &str,
+    token: &str,
+) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-amz-user-agent", identity.usage_amz_user_agent().parse()?);
+    headers.insert("User-Agent", identity.usage_user_agent().parse()?);
+    headers.insert("host", host.parse()?);
+    let invocation_id = identity.new_invocation_id();
+    headers.insert("amz-sdk-invocation-id", invocation_id.parse()?);
+    headers.insert(
+        "amz-sdk-request",
+        HeaderValue::from_static("attempt=1; max=1"),
+    );
+    headers.insert("Authorization", format!("Bearer {}", token).parse()?);
+    headers.insert("Connection", HeaderValue::from_static("close"));
+    Ok(headers)
+}
 
 /// 获取使用额度信息
 pub(crate) async fn get_usage_limits(
     credentials: &KiroCredentials,
     config: &Config,
+    identity: &RequestIdentity,
     token: &str,
     proxy: Option<&ProxyConfig>,
 ) -> anyhow::Result<UsageLimitsResponse> {
@@ -327,14 +432,12 @@ pub(crate) async fn get_usage_limits(
     // 优先级：凭据.api_region > config.api_region > config.region
     let region = credentials.effective_api_region(config);
     let host = format!("q.{}.amazonaws.com", region);
-    let machine_id = machine_id::generate_from_credentials(credentials, config)
-        .ok_or_else(|| anyhow::anyhow!("无法生成 machineId"))?;
-    let kiro_version = &config.kiro_version;
 
     // 构建 URL
     let mut url = format!(
-        "https://{}/getUsageLimits?origin=AI_EDITOR&resourceType=AGENTIC_REQUEST",
-        host
+        "https://{}/getUsageLimits?origin=AI_EDITOR&resourceType={}",
+        host,
+        urlencoding::encode(&config.usage_resource_type)
     );
 
     // profileArn 是可选的
@@ -342,28 +445,11 @@ pub(crate) async fn get_usage_limits(
         url.push_str(&format!("&profileArn={}", urlencoding::encode(profile_arn)));
     }
 
-    // 构建 User-Agent headers
-    let user_agent = format!(
-        "aws-sdk-js/1.0.0 ua/2.1 os/darwin#24.6.0 lang/js md/nodejs#22.21.1 \
-         api/codewhispererruntime#1.0.0 m/N,E KiroIDE-{}-{}",
-        kiro_version, machine_id
-    );
-    let amz_user_agent = format!(
-        "{} KiroIDE-{}-{}",
-        USAGE_LIMITS_AMZ_USER_AGENT_PREFIX, kiro_version, machine_id
-    );
-
     let client = build_client(proxy, 60, config.tls_backend)?;
 
     let response = client
         .get(&url)
-        .header("x-amz-user-agent", &amz_user_agent)
-        .header("User-Agent", &user_agent)
-        .header("host", &host)
-        .header("amz-sdk-invocation-id", uuid::Uuid::new_v4().to_string())
-        .header("amz-sdk-request", "attempt=1; max=1")
-        .header("Authorization", format!("Bearer {}", token))
-        .header("Connection", "close")
+        .headers(usage_limits_headers(identity, &host, token)?)
         .send()
         .await?;
 
@@ -404,17 +490,476 @@ struct CredentialEntry {
     success_count: u64,
     /// 最后一次 API 调用时间（RFC3339 格式）
     last_used_at: Option<String>,
+    /// 按 pricing 配置累计的估算费用
+    estimated_cost: f64,
+    /// 最近的 Token 刷新记录（最多保留 REFRESH_HISTORY_CAPACITY 条，按时间先后排列）
+    refresh_history: VecDeque<RefreshAttempt>,
+    /// 最近的 API 调用结果（true 为成功，最多保留 RECENT_OUTCOMES_CAPACITY 条，不持久化）
+    recent_outcomes: VecDeque<bool>,
+    /// 最近一次 API 调用成功的时间（不持久化）
+    last_success_at: Option<Instant>,
+    /// 最近一次成功调用的耗时（毫秒，不持久化）
+    last_latency_ms: Option<u64>,
+    /// 各类失败的累计次数（不持久化）
+    failure_counts: FailureCounts,
+    /// 本地月度请求计数（随统计数据持久化）
+    monthly_requests: Option<MonthlyRequestCount>,
+    /// 最近一次 refreshToken 轮换后未能回写凭据文件（新令牌仅在内存中，不持久化）
+    ///
+    /// 记录标记时凭据文件的成功写入次数，之后任意一次回写成功即说明新令牌已落盘
+    rotation_unpersisted: Option<u64>,
+    /// 因额度用尽被禁用时的使用比例（1.0 表示 100%，不持久化）
+    disabled_at_quota: Option<f64>,
+}
+
+impl CredentialEntry {
+    /// 由凭据文件中的凭据创建条目（disabled 状态取自文件）
+    fn new(id: u64, credentials: KiroCredentials) -> Self {
+        let disabled = credentials.disabled;
+        Self {
+            id,
+            credentials,
+            failure_count: 0,
+            disabled,
+            disabled_reason: disabled.then_some(DisabledReason::Manual),
+            success_count: 0,
+            last_used_at: None,
+            estimated_cost: 0.0,
+            refresh_history: VecDeque::new(),
+            recent_outcomes: VecDeque::new(),
+            last_success_at: None,
+            last_latency_ms: None,
+            failure_counts: FailureCounts::default(),
+            monthly_requests: None,
+            rotation_unpersisted: None,
+            disabled_at_quota: None,
+        }
+    }
+
+    /// 写入凭据文件的凭据（同步 disabled 状态并规范化 authMethod）
+    fn persisted_credentials(&self) -> KiroCredentials {
+        let mut cred = self.credentials.clone();
+        cred.canonicalize_auth_method();
+        // 本地月度上限触发的暂停仅在内存中生效，跨月后自动恢复
+        cred.disabled =
+            self.disabled && self.disabled_reason != Some(DisabledReason::LocalLimitReached);
+        cred
+    }
+
+    /// 应用外部修改的字段（`ours` 为回写快照中的凭据，`written` 为写入文件的凭据）
+    ///
+    /// 内存中已再次变化的字段保持不变，返回实际更新的字段名；无更新或无法解析时返回 None
+    fn apply_external_fields(
+        &mut self,
+        ours: &serde_json::Value,
+        written: &serde_json::Value,
+    ) -> Option<Vec<String>> {
+        let current = serde_json::to_value(self.persisted_credentials()).ok()?;
+        let (Some(ours), Some(written), serde_json::Value::Object(mut fields)) =
+            (ours.as_object(), written.as_object(), current)
+        else {
+            return None;
+        };
+
+        let keys: BTreeSet<&String> = ours.keys().chain(written.keys()).collect();
+        let mut changed = Vec::new();
+        for key in keys {
+            let (o, w) = (ours.get(key), written.get(key));
+            if o == w || fields.get(key) != o {
+                continue;
+            }
+            match w {
+                Some(value) => fields.insert(key.clone(), value.clone()),
+                None => fields.remove(key),
+            };
+            changed.push(key.clone());
+        }
+        if changed.is_empty() {
+            return None;
+        }
+
+        let credentials =
+            match serde_json::from_value::<KiroCredentials>(serde_json::Value::Object(fields)) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    tracing::warn!("凭据 #{} 的外部修改无法解析，未同步到内存: {}", self.id, e);
+                    return None;
+                }
+            };
+        if changed.iter().any(|f| f == "disabled") && credentials.disabled != self.disabled {
+            self.disabled = credentials.disabled;
+            if self.disabled {
+                self.disabled_reason = Some(DisabledReason::Manual);
+            } else {
+                self.failure_count = 0;
+                self.disabled_reason = None;
+            }
+        }
+        self.credentials = credentials;
+        Some(changed)
+    }
+
+    /// 按凭据 monthlyRequestLimit 的时区计算当前月份（YYYY-MM，未配置时按 UTC）
+    fn current_month(&self, now: DateTime<Utc>) -> String {
+        let offset = self
+            .credentials
+            .monthly_request_limit
+            .as_ref()
+            .and_then(|limit| limit.offset().ok())
+            .unwrap_or_else(|| FixedOffset::east_opt(0).expect("UTC 偏移有效"));
+        now.with_timezone(&offset).format("%Y-%m").to_string()
+    }
+
+    /// 当月成功请求次数（计数所属月份已过去时为 0）
+    fn monthly_count(&self, now: DateTime<Utc>) -> u64 {
+        match &self.monthly_requests {
+            Some(m) if m.month == self.current_month(now) => m.count,
+            _ => 0,
+        }
+    }
+
+    /// 当月请求次数是否已达到本地硬上限
+    fn monthly_hard_limit_reached(&self, now: DateTime<Utc>) -> bool {
+        self.credentials
+            .monthly_request_limit
+            .as_ref()
+            .and_then(|limit| limit.hard_limit)
+            .is_some_and(|hard| self.monthly_count(now) >= hard)
+    }
+
+    /// 累加当月请求次数，达到本地软上限或硬上限时返回对应的管理事件
+    fn record_monthly_request(&mut self, now: DateTime<Utc>) -> Option<AdminEvent> {
+        let month = self.current_month(now);
+        let count = match self.monthly_requests.take() {
+            Some(m) if m.month == month => m.count + 1,
+            _ => 1,
+        };
+        self.monthly_requests = Some(MonthlyRequestCount {
+            month: month.clone(),
+            count,
+        });
+
+        let soft_limit = self
+            .credentials
+            .monthly_request_limit
+            .as_ref()
+            .and_then(|limit| limit.soft_limit);
+        if self.monthly_hard_limit_reached(now) {
+            return (!self.disabled).then(|| self.pause_for_monthly_limit(now));
+        }
+        (soft_limit == Some(count)).then(|| AdminEvent {
+            at: now.to_rfc3339(),
+            credential_id: self.id,
+            kind: AdminEventKind::SoftLimitReached,
+            message: format!(
+                "凭据 #{} 当月（{}）请求次数达到本地软上限 {}",
+                self.id, month, count
+            ),
+        })
+    }
+
+    /// 当月请求次数达到本地硬上限，暂停使用该凭据直到下个自然月
+    fn pause_for_monthly_limit(&mut self, now: DateTime<Utc>) -> AdminEvent {
+        self.disabled = true;
+        self.disabled_reason = Some(DisabledReason::LocalLimitReached);
+        AdminEvent {
+            at: now.to_rfc3339(),
+            credential_id: self.id,
+            kind: AdminEventKind::HardLimitReached,
+            message: format!(
+                "凭据 #{} 当月（{}）请求次数 {} 达到本地硬上限，暂停使用至下月",
+                self.id,
+                self.current_month(now),
+                self.monthly_count(now)
+            ),
+        }
+    }
+
+    /// 记录一次 API 调用结果（用于计算近期成功率）
+    fn record_outcome(&mut self, succeeded: bool) {
+        self.recent_outcomes.push_back(succeeded);
+        while self.recent_outcomes.len() > RECENT_OUTCOMES_CAPACITY {
+            self.recent_outcomes.pop_front();
+        }
+    }
+
+    /// 计算健康评分的各项因子
+    fn health_factors(&self, balance: Option<&CachedBalance>, now: DateTime<Utc>) -> HealthFactors {
+        let failures = self.failure_count.min(MAX_FAILURES_PER_CREDENTIAL) as f64;
+        let success_rate_factor = if self.recent_outcomes.is_empty() {
+            1.0
+        } else {
+            let succeeded = self.recent_outcomes.iter().filter(|ok| **ok).count();
+            succeeded as f64 / self.recent_outcomes.len() as f64
+        };
+        // 无余额缓存时视为额度充足
+        let quota_exhausted = balance.is_some_and(|b| {
+            b.data.usage_limit > 0.0
+                && b.data.current_usage / b.data.usage_limit >= HEALTH_QUOTA_THRESHOLD
+        });
+
+        HealthFactors {
+            failure_factor: 1.0 - failures / MAX_FAILURES_PER_CREDENTIAL as f64,
+            freshness_factor: self.token_freshness(now),
+            success_rate_factor,
+            quota_factor: if quota_exhausted { 0.0 } else { 1.0 },
+        }
+    }
+
+    /// Token 新鲜度：1 - 距上次刷新的时间 / Token 有效期（无 Token 或已过期为 0）
+    ///
+    /// 以最近一次成功刷新的时间作为签发时间，没有刷新记录时假定有效期为
+    /// [`ASSUMED_TOKEN_LIFETIME_SECS`]
+    fn token_freshness(&self, now: DateTime<Utc>) -> f64 {
+        let parse = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc))
+        };
+        let Some(expires_at) = self
+            .credentials
+            .expires_at
+            .as_deref()
+            .and_then(parse)
+            .filter(|_| self.credentials.access_token.is_some())
+        else {
+            return 0.0;
+        };
+
+        let issued_at = self
+            .refresh_history
+            .iter()
+            .rev()
+            .find(|a| a.succeeded)
+            .and_then(|a| parse(&a.attempted_at))
+            .filter(|t| *t < expires_at)
+            .unwrap_or(expires_at - Duration::seconds(ASSUMED_TOKEN_LIFETIME_SECS));
+        let lifetime = (expires_at - issued_at).num_milliseconds() as f64;
+        let elapsed = (now - issued_at).num_milliseconds() as f64;
+        (1.0 - elapsed / lifetime).clamp(0.0, 1.0)
+    }
+}
+
+/// 单次 Token 刷新记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshAttempt {
+    /// 刷新开始时间（RFC3339 格式）
+    pub attempted_at: String,
+    /// 是否刷新成功
+    pub succeeded: bool,
+    /// 刷新耗时（毫秒）
+    pub duration_ms: u64,
+    /// 失败原因
+    pub error: Option<String>,
+    /// 刷新响应是否返回了新的 refreshToken（旧令牌随即失效）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub rotated: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// 凭据健康评分的组成因子（均为 0.0 ~ 1.0，越大越健康）
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthFactors {
+    /// 连续失败次数因子：1 - failure_count / 最大失败次数
+    pub failure_factor: f64,
+    /// Token 新鲜度因子：1 - 距上次刷新的时间 / Token 有效期
+    pub freshness_factor: f64,
+    /// 近期成功率因子：最近若干次 API 调用的成功比例（无记录时为 1）
+    pub success_rate_factor: f64,
+    /// 额度因子：使用率低于 90% 时为 1，否则为 0（无余额缓存时为 1）
+    pub quota_factor: f64,
+}
+
+impl HealthFactors {
+    /// 加权后的综合健康评分（0.0 ~ 1.0）
+    pub fn score(&self) -> f64 {
+        self.failure_factor * HEALTH_WEIGHT_FAILURE
+            + self.freshness_factor * HEALTH_WEIGHT_FRESHNESS
+            + self.success_rate_factor * HEALTH_WEIGHT_SUCCESS_RATE
+            + self.quota_factor * HEALTH_WEIGHT_QUOTA
+    }
 }
 
 /// 禁用原因
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DisabledReason {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisabledReason {
     /// Admin API 手动禁用
     Manual,
     /// 连续失败达到阈值后自动禁用
     TooManyFailures,
     /// 额度已用尽（如 MONTHLY_REQUEST_COUNT）
     QuotaExceeded,
+    /// 当月请求次数达到本地硬上限（monthlyRequestLimit.hardLimit），跨月后自动恢复，不写回凭据文件
+    LocalLimitReached,
+}
+
+/// 本地月度请求计数
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthlyRequestCount {
+    /// 计数所属月份（YYYY-MM，按凭据 monthlyRequestLimit 的时区划分）
+    pub month: String,
+    /// 当月成功请求次数
+    pub count: u64,
+}
+
+/// 管理事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AdminEventKind {
+    /// 当月请求次数达到本地软上限
+    SoftLimitReached,
+    /// 当月请求次数达到本地硬上限，凭据已暂停使用
+    HardLimitReached,
+    /// 跨月（或上限调整）后恢复使用
+    LimitCleared,
+    /// refreshToken 已轮换但重试后仍未能回写凭据文件
+    RotationUnpersisted,
+}
+
+/// 管理事件（仅保留在内存中，最多 ADMIN_EVENTS_CAPACITY 条）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminEvent {
+    /// 发生时间（RFC3339 格式）
+    pub at: String,
+    /// 凭据 ID
+    pub credential_id: u64,
+    /// 事件类型
+    pub kind: AdminEventKind,
+    /// 说明
+    pub message: String,
+}
+
+/// API 调用失败类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// 连接/DNS/超时等网络错误：仅在其他凭据近期请求成功时计入失败次数
+    /// （此时问题更可能出在该凭据自身，如凭据级代理不可用）
+    Network,
+    /// 上游服务端错误（如 500/502/503/504）：不计入失败次数，仅切换到其他可用凭据
+    UpstreamServer,
+    /// 上游认证/权限错误（401/403）：计入失败次数，达到 `authFailureThreshold` 后禁用凭据
+    UpstreamAuth,
+    /// 上游限流（408/429）：不计入失败次数，也不切换凭据
+    UpstreamThrottle,
+    /// 请求本身的问题（400 等其他 4xx，如转换出的请求格式错误）：从不计入失败次数
+    Client,
+}
+
+/// 各类失败的累计次数（进程内统计，不持久化）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailureCounts {
+    pub network: u64,
+    pub upstream_server: u64,
+    pub upstream_auth: u64,
+    pub upstream_throttle: u64,
+    pub client: u64,
+}
+
+impl FailureCounts {
+    fn record(&mut self, kind: FailureKind) {
+        let counter = match kind {
+            FailureKind::Network => &mut self.network,
+            FailureKind::UpstreamServer => &mut self.upstream_server,
+            FailureKind::UpstreamAuth => &mut self.upstream_auth,
+            FailureKind::UpstreamThrottle => &mut self.upstream_throttle,
+            FailureKind::Client => &mut self.client,
+        };
+        *counter += 1;
+    }
+}
+
+/// 上报失败后对凭据的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureAction {
+    /// 不影响凭据状态
+    Ignore,
+    /// 不计入失败次数，切换到其他可用凭据
+    Switch,
+    /// 计入失败次数，连续失败达到阈值后禁用凭据
+    Count { threshold: u32 },
+}
+
+/// with-backoff 自愈策略下，已连续自愈 `streak` 次后距下次自愈的最小间隔
+fn self_heal_backoff(streak: u32) -> StdDuration {
+    let exponent = streak.saturating_sub(1).min(16);
+    SELF_HEAL_BACKOFF_BASE
+        .saturating_mul(1 << exponent)
+        .min(SELF_HEAL_BACKOFF_MAX)
+}
+
+/// 根据失败类型决定处理方式
+///
+/// `peers_succeeding` 表示其他可用凭据近期有成功请求，用于区分网络错误是全局的还是该凭据特有的
+fn failure_action(kind: FailureKind, auth_threshold: u32, peers_succeeding: bool) -> FailureAction {
+    match kind {
+        FailureKind::Network if peers_succeeding => FailureAction::Count {
+            threshold: MAX_FAILURES_PER_CREDENTIAL,
+        },
+        FailureKind::UpstreamServer => FailureAction::Switch,
+        FailureKind::UpstreamAuth => FailureAction::Count {
+            threshold: auth_threshold.max(1),
+        },
+        FailureKind::Network | FailureKind::UpstreamThrottle | FailureKind::Client => {
+            FailureAction::Ignore
+        }
+    }
+}
+
+/// 创建 MultiTokenManager 时的错误与警告
+#[derive(Debug)]
+pub enum MultiTokenManagerError {
+    /// 多个凭据使用了相同的 ID
+    DuplicateCredentialId(u64),
+    /// 未配置任何凭据（不阻止启动，仅记录日志）
+    EmptyCredentialsWarning,
+    /// 凭据优先级无效
+    InvalidPriority { id: u64, message: String },
+    /// 凭据回写失败
+    PersistenceError(anyhow::Error),
+}
+
+impl std::fmt::Display for MultiTokenManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultiTokenManagerError::DuplicateCredentialId(id) => {
+                write!(f, "检测到重复的凭据 ID: {}", id)
+            }
+            MultiTokenManagerError::EmptyCredentialsWarning => {
+                write!(f, "未配置任何凭据，可通过管理面板添加")
+            }
+            MultiTokenManagerError::InvalidPriority { id, message } => {
+                write!(f, "凭据 #{} 优先级无效: {}", id, message)
+            }
+            MultiTokenManagerError::PersistenceError(e) => {
+                write!(f, "补全凭据 ID/machineId 后持久化失败: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MultiTokenManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultiTokenManagerError::PersistenceError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// 失败上报结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureOutcome {
+    /// 是否还有可用凭据可以重试
+    pub has_more: bool,
 }
 
 /// 统计数据持久化条目
@@ -422,6 +967,146 @@ enum DisabledReason {
 struct StatsEntry {
     success_count: u64,
     last_used_at: Option<String>,
+    #[serde(default)]
+    estimated_cost: f64,
+    #[serde(default)]
+    monthly_requests: Option<MonthlyRequestCount>,
+}
+
+/// 待落盘的统计文件：(名称, (文件路径, JSON))
+type StatsFiles = [(&'static str, Option<(PathBuf, String)>); 2];
+
+/// 统计数据落盘器
+///
+/// 快照按序号排序，写入串行执行并以临时文件 + rename 原子替换；
+/// 较旧的快照晚于较新的快照到达时直接跳过，避免并发写入乱序或损坏文件
+#[derive(Default)]
+struct StatsWriter {
+    /// 最近分配的快照序号（持有期间生成快照，保证序号与快照先后一致）
+    snapshot_seq: Mutex<u64>,
+    /// 已写入的最新快照序号（持有期间独占写入）
+    written_seq: Mutex<u64>,
+}
+
+impl StatsWriter {
+    /// 分配快照序号并生成快照
+    fn snapshot(&self, take: impl FnOnce() -> StatsFiles) -> (u64, StatsFiles) {
+        let mut seq = self.snapshot_seq.lock();
+        *seq += 1;
+        (*seq, take())
+    }
+
+    /// 写入序号为 `seq` 的快照，已写入更新的快照时跳过；返回是否全部写入成功
+    fn write(&self, seq: u64, files: StatsFiles) -> bool {
+        let mut written = self.written_seq.lock();
+        if seq <= *written {
+            return true;
+        }
+        let mut ok = true;
+        for (name, file) in files {
+            let Some((path, json)) = file else {
+                continue;
+            };
+            if let Err(e) = write_atomic(&path, &json) {
+                tracing::warn!("保存{}失败: {}", name, e);
+                ok = false;
+            }
+        }
+        *written = seq;
+        ok
+    }
+}
+
+/// 统计数据导出格式版本
+pub const STATS_EXPORT_VERSION: u32 = 1;
+
+/// 统计数据导出（用于跨实例迁移）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsExport {
+    /// 导出格式版本
+    pub version: u32,
+    /// 各凭据的统计条目
+    pub entries: Vec<StatsExportEntry>,
+}
+
+/// 单个凭据的统计导出条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsExportEntry {
+    /// 导出实例中的凭据 ID（仅供参考，导入时不用于匹配）
+    pub id: u64,
+    /// refreshToken 的 SHA-256 哈希（导入时按此匹配凭据）
+    pub refresh_token_hash: String,
+    /// API 调用成功次数
+    pub success_count: u64,
+    /// 最后一次 API 调用时间（RFC3339 格式）
+    pub last_used_at: Option<String>,
+}
+
+/// 凭据管理器的可迁移运行时状态（蓝绿部署时由新实例导入以预热）
+///
+/// 不包含任何密钥（密钥保存在凭据文件中），时间相关的状态均以相对导出时刻的时长表示
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenManagerState {
+    /// 当前活跃凭据 ID
+    pub current_id: u64,
+    /// 负载均衡模式
+    pub load_balancing_mode: String,
+    /// 各凭据的运行时状态
+    pub credentials: Vec<CredentialRuntimeState>,
+}
+
+/// 单个凭据的运行时状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRuntimeState {
+    /// 凭据 ID（导入时按此匹配）
+    pub id: u64,
+    /// refreshToken 的 SHA-256 哈希（与导入方不一致时说明凭据已被替换，跳过该条目）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token_hash: Option<String>,
+    /// 连续失败次数
+    pub failure_count: u32,
+    /// 禁用原因（未禁用时为 None）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_reason: Option<DisabledReason>,
+    /// API 调用成功次数
+    pub success_count: u64,
+    /// 最后一次 API 调用时间（RFC3339 格式）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+    /// 累计估算费用
+    #[serde(default)]
+    pub estimated_cost: f64,
+    /// 最近的 Token 刷新记录
+    #[serde(default)]
+    pub refresh_history: Vec<RefreshAttempt>,
+    /// 最近的 API 调用结果（true 为成功）
+    #[serde(default)]
+    pub recent_outcomes: Vec<bool>,
+    /// 最近一次成功调用的耗时（毫秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_latency_ms: Option<u64>,
+    /// 各类失败的累计次数
+    #[serde(default)]
+    pub failure_counts: FailureCounts,
+    /// Token 刷新限流状态（没有刷新记录时为 None）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_limit: Option<RefreshLimitState>,
+    /// 本地月度请求计数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_requests: Option<MonthlyRequestCount>,
+}
+
+/// 导入运行时状态的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateImportOutcome {
+    /// 成功匹配并应用的凭据数
+    pub matched: usize,
+    /// 因 ID 不存在或 refreshToken 不一致而跳过的凭据数
+    pub skipped: usize,
 }
 
 // ============================================================================
@@ -438,6 +1123,8 @@ pub struct CredentialEntrySnapshot {
     pub priority: u32,
     /// 是否被禁用
     pub disabled: bool,
+    /// 禁用原因（未禁用时为 None）
+    pub disabled_reason: Option<DisabledReason>,
     /// 连续失败次数
     pub failure_count: u32,
     /// 认证方式
@@ -459,6 +1146,36 @@ pub struct CredentialEntrySnapshot {
     /// 代理 URL（用于前端展示）
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy_url: Option<String>,
+    /// 凭据级上游基础 URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_base_url: Option<String>,
+    /// 订阅类型（用于模型访问控制）
+    pub subscription_title: Option<String>,
+    /// 最近一小时内的 Token 刷新次数
+    pub refresh_attempts_last_hour: usize,
+    /// 刷新端点 429 退避剩余秒数（未处于退避时为 None）
+    pub refresh_backoff_secs: Option<u64>,
+    /// 刷新失败退避结束时间（RFC3339，之前选择凭据时会跳过该凭据；未处于退避时为 None）
+    pub next_refresh_attempt_at: Option<String>,
+    /// 综合健康评分（0.0 ~ 1.0）
+    pub health_score: f64,
+    /// 累计估算费用（未配置 pricing 时为 None）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+    /// 最近一次成功调用的耗时（毫秒，重启后为 None）
+    pub last_latency_ms: Option<u64>,
+    /// 各类失败的累计次数
+    pub failure_counts: FailureCounts,
+    /// 当月成功请求次数（本地计数）
+    pub monthly_requests: u64,
+    /// 本地月度请求上限
+    pub monthly_request_limit: Option<MonthlyRequestLimit>,
+    /// 保留的刷新记录中 refreshToken 轮换的次数
+    pub refresh_rotations: usize,
+    /// 最近一次 refreshToken 轮换后未能回写凭据文件（重启后将使用已失效的旧令牌）
+    pub rotation_unpersisted: bool,
+    /// 因额度用尽被禁用时的使用比例（1.0 表示 100%，未因额度用尽禁用时为 None）
+    pub quota_exhausted_at_percentage: Option<f64>,
 }
 
 /// 凭据管理器状态快照
@@ -473,6 +1190,27 @@ pub struct ManagerSnapshot {
     pub total: usize,
     /// 可用凭据数量
     pub available: usize,
+    /// 所有未禁用凭据健康评分的平均值（无可用凭据时为 0）
+    pub fleet_health_score: f64,
+    /// 所有凭据的累计估算费用（未配置 pricing 时为 None）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_estimated_cost: Option<f64>,
+    /// 凭据文件在外部被修改且无法自动合并时的警告
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_warning: Option<String>,
+    /// 所有凭据均被自动禁用时的自愈策略
+    pub auto_recover_all_disabled: AutoRecoverMode,
+    /// 最近一次自愈时间（RFC3339 格式，重启后为 None）
+    pub last_self_heal_at: Option<String>,
+}
+
+/// 所有凭据均被自动禁用时的自愈记录（仅保存在内存中）
+#[derive(Debug, Default)]
+struct SelfHealState {
+    /// 最近一次自愈时间
+    last_at: Option<DateTime<Utc>>,
+    /// 连续自愈次数（有请求成功后清零），决定 with-backoff 模式的最小间隔
+    streak: u32,
 }
 
 /// 多凭据 Token 管理器
@@ -488,22 +1226,95 @@ pub struct MultiTokenManager {
     current_id: Mutex<u64>,
     /// Token 刷新锁，确保同一时间只有一个刷新操作
     refresh_lock: TokioMutex<()>,
+    /// 按凭据的 Token 刷新限流器
+    refresh_limiter: Mutex<RefreshLimiter>,
+    /// 余额（使用额度）缓存
+    balance_cache: BalanceCache,
+    /// 使用额度查询结果缓存（凭据 ID → (查询时间, 结果)），TTL 见 `usage_limits_cache_ttl_secs`
+    usage_limits_cache: Mutex<HashMap<u64, (Instant, UsageLimitsResponse)>>,
+    /// 按 metadata.user_id 统计的用户用量（随统计数据一起持久化）
+    user_usage: UserUsageTracker,
     /// 凭据文件路径（用于回写）
     credentials_path: Option<PathBuf>,
+    /// 凭据文件单写者任务（首次在 Tokio runtime 内回写时启动）
+    credentials_writer: OnceLock<CredentialsWriter>,
+    /// 凭据文件外部修改保护（回写前检查并合并外部编辑）
+    persist_guard: Arc<Mutex<ExternalEditGuard>>,
     /// 是否为多凭据格式（数组格式才回写）
-    is_multiple_format: bool,
+    is_multiple_format: AtomicBool,
     /// 负载均衡模式（运行时可修改）
     load_balancing_mode: Mutex<String>,
     /// 最近一次统计持久化时间（用于 debounce）
     last_stats_save_at: Mutex<Option<Instant>>,
-    /// 统计数据是否有未落盘更新
-    stats_dirty: AtomicBool,
+    /// 统计数据是否有未落盘更新（与异步落盘任务共享）
+    stats_dirty: Arc<AtomicBool>,
+    /// 统计数据落盘器（串行化同步与异步写入）
+    stats_writer: Arc<StatsWriter>,
+    /// 从实例模式：其他实例持有凭据文件锁，Token 刷新结果与统计数据不落盘
+    secondary: AtomicBool,
+    /// 最近的管理事件（按时间先后排列）
+    events: Mutex<VecDeque<AdminEvent>>,
+    /// 所有凭据均被自动禁用时的自愈记录
+    self_heal: Mutex<SelfHealState>,
+    /// 测试用：按顺序返回的刷新结果（为空时请求真实刷新端点）
+    #[cfg(test)]
+    refresh_stub: Mutex<VecDeque<anyhow::Result<KiroCredentials>>>,
+    /// 测试用：按顺序返回的使用额度查询结果（为空时请求真实端点）
+    #[cfg(test)]
+    usage_stub: Mutex<VecDeque<anyhow::Result<UsageLimitsResponse>>>,
 }
 
+/// 支持的负载均衡模式
+///
+/// - `priority`：固定使用当前凭据，不可用时按优先级切换
+/// - `balanced`：每次请求选择成功次数最少的凭据
+/// - `reset-aware`：每次请求优先选择额度最早重置的凭据（用尽前先消耗即将重置的额度）
+pub const LOAD_BALANCING_MODES: &[&str] = &["priority", "balanced", "reset-aware"];
+
 /// 每个凭据最大 API 调用失败次数
 const MAX_FAILURES_PER_CREDENTIAL: u32 = 3;
+/// with-backoff 自愈策略的初始最小间隔（之后每次连续自愈翻倍）
+const SELF_HEAL_BACKOFF_BASE: StdDuration = StdDuration::from_secs(30);
+/// with-backoff 自愈策略的最大最小间隔
+const SELF_HEAL_BACKOFF_MAX: StdDuration = StdDuration::from_secs(30 * 60);
 /// 统计数据持久化防抖间隔
 const STATS_SAVE_DEBOUNCE: StdDuration = StdDuration::from_secs(30);
+/// 每个凭据保留的 Token 刷新记录条数
+const REFRESH_HISTORY_CAPACITY: usize = 20;
+/// 刷新端点返回 429 但未携带 Retry-After 时的默认退避时间
+const REFRESH_RATE_LIMIT_DEFAULT_BACKOFF: StdDuration = StdDuration::from_secs(300);
+/// 其他凭据在该时间内有成功请求时，网络错误视为凭据自身的问题
+const NETWORK_PEER_SUCCESS_WINDOW: StdDuration = StdDuration::from_secs(60);
+/// 存活检查中单次刷新的超时时间
+const LIVENESS_REFRESH_TIMEOUT: StdDuration = StdDuration::from_secs(15);
+/// 计算近期成功率时保留的 API 调用结果条数
+const RECENT_OUTCOMES_CAPACITY: usize = 10;
+/// 健康评分权重（合计为 1）
+const HEALTH_WEIGHT_FAILURE: f64 = 0.4;
+const HEALTH_WEIGHT_FRESHNESS: f64 = 0.3;
+const HEALTH_WEIGHT_SUCCESS_RATE: f64 = 0.2;
+const HEALTH_WEIGHT_QUOTA: f64 = 0.1;
+/// 额度使用率达到该比例后额度因子记为 0
+const HEALTH_QUOTA_THRESHOLD: f64 = 0.9;
+/// 没有刷新记录时假定的 Token 有效期（秒）
+const ASSUMED_TOKEN_LIFETIME_SECS: i64 = 3600;
+/// 保留的管理事件条数
+const ADMIN_EVENTS_CAPACITY: usize = 100;
+/// refreshToken 轮换后回写凭据文件的最大尝试次数
+const ROTATION_PERSIST_ATTEMPTS: u32 = 3;
+/// refreshToken 轮换后回写重试的初始退避时间（每次翻倍）
+const ROTATION_PERSIST_BACKOFF: StdDuration = StdDuration::from_millis(100);
+
+/// 将凭据条目序列化为凭据文件内容（同步 disabled 状态并规范化 authMethod）
+fn credentials_json(entries: &[CredentialEntry]) -> anyhow::Result<String> {
+    use anyhow::Context;
+
+    let credentials: Vec<KiroCredentials> = entries
+        .iter()
+        .map(CredentialEntry::persisted_credentials)
+        .collect();
+    serde_json::to_string_pretty(&credentials).context("序列化凭据失败")
+}
 
 /// API 调用上下文
 ///
@@ -511,12 +1322,60 @@ const STATS_SAVE_DEBOUNCE: StdDuration = StdDuration::from_secs(30);
 /// 用于解决并发调用时 current_id 竞态问题
 #[derive(Clone)]
 pub struct CallContext {
-    /// 凭据 ID（用于 report_success/report_failure）
+    /// 凭据 ID
     pub id: u64,
     /// 凭据信息（用于构建请求头）
     pub credentials: KiroCredentials,
     /// 访问 Token
     pub token: String,
+    /// 请求身份（machineId 与 User-Agent），按凭据构建一次；无法生成 machineId 时为 None
+    pub identity: Option<RequestIdentity>,
+    /// 上下文创建时间（用于计算调用耗时）
+    pub created_at: Instant,
+}
+
+impl CallContext {
+    /// 自上下文创建以来经过的毫秒数（含 Token 刷新等获取凭据的耗时）
+    pub fn elapsed_ms(&self) -> u64 {
+        u64::try_from(self.created_at.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// 测试用：仅包含凭据 ID 的调用上下文
+    #[cfg(test)]
+    pub fn for_test(id: u64) -> Self {
+        Self {
+            id,
+            credentials: KiroCredentials::default(),
+            token: String::new(),
+            identity: None,
+            created_at: Instant::now(),
+        }
+    }
+
+    /// 透传模式的调用上下文：使用客户端提供的 Token，不对应任何已管理的凭据（ID 为 0）
+    ///
+    /// 未配置 machineId 时按 Token 派生（与按 refreshToken 生成的规则相同）
+    pub fn passthrough(token: &str, config: &Config) -> Self {
+        let credentials = KiroCredentials {
+            access_token: Some(token.to_string()),
+            refresh_token: Some(token.to_string()),
+            ..Default::default()
+        };
+        Self {
+            id: 0,
+            identity: RequestIdentity::from_credentials(&credentials, config).ok(),
+            credentials,
+            token: token.to_string(),
+            created_at: Instant::now(),
+        }
+    }
+
+    /// 获取请求身份（无法生成 machineId 时返回错误）
+    pub fn identity(&self) -> anyhow::Result<&RequestIdentity> {
+        self.identity
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("无法生成 machine_id，请检查凭证配置"))
+    }
 }
 
 impl MultiTokenManager {
@@ -528,21 +1387,49 @@ impl MultiTokenManager {
     /// * `proxy` - 可选的代理配置
     /// * `credentials_path` - 凭据文件路径（用于回写）
     /// * `is_multiple_format` - 是否为多凭据格式（数组格式才回写）
+    ///
+    /// 凭据 ID 重复或优先级无效时返回错误；无凭据、回写失败等非致命问题仅记录日志
     pub fn new(
         config: Config,
         credentials: Vec<KiroCredentials>,
         proxy: Option<ProxyConfig>,
         credentials_path: Option<PathBuf>,
         is_multiple_format: bool,
-    ) -> anyhow::Result<Self> {
-        // 计算当前最大 ID，为没有 ID 的凭据分配新 ID
-        let max_existing_id = credentials.iter().filter_map(|c| c.id).max().unwrap_or(0);
-        let mut next_id = max_existing_id + 1;
-        let mut has_new_ids = false;
-        let mut has_new_machine_ids = false;
-        let config_ref = &config;
+    ) -> Result<Self, MultiTokenManagerError> {
+        let (manager, warnings) = Self::new_with_warnings(
+            config,
+            credentials,
+            proxy,
+            credentials_path,
+            is_multiple_format,
+        )?;
+        for warning in &warnings {
+            tracing::warn!("{}", warning);
+        }
+        Ok(manager)
+    }
 
-        let entries: Vec<CredentialEntry> = credentials
+    /// 创建管理器，并返回不影响启动的警告
+    fn new_with_warnings(
+        config: Config,
+        credentials: Vec<KiroCredentials>,
+        proxy: Option<ProxyConfig>,
+        credentials_path: Option<PathBuf>,
+        is_multiple_format: bool,
+    ) -> Result<(Self, Vec<MultiTokenManagerError>), MultiTokenManagerError> {
+        let mut warnings = Vec::new();
+        if credentials.is_empty() {
+            warnings.push(MultiTokenManagerError::EmptyCredentialsWarning);
+        }
+
+        // 计算当前最大 ID，为没有 ID 的凭据分配新 ID
+        let max_existing_id = credentials.iter().filter_map(|c| c.id).max().unwrap_or(0);
+        let mut next_id = max_existing_id + 1;
+        let mut has_new_ids = false;
+        let mut has_new_machine_ids = false;
+        let config_ref = &config;
+
+        let entries: Vec<CredentialEntry> = credentials
             .into_iter()
             .map(|mut cred| {
                 cred.canonicalize_auth_method();
@@ -561,68 +1448,98 @@ impl MultiTokenManager {
                         has_new_machine_ids = true;
                     }
                 }
-                CredentialEntry {
-                    id,
-                    credentials: cred.clone(),
-                    failure_count: 0,
-                    disabled: cred.disabled, // 从配置文件读取 disabled 状态
-                    disabled_reason: if cred.disabled {
-                        Some(DisabledReason::Manual)
-                    } else {
-                        None
-                    },
-                    success_count: 0,
-                    last_used_at: None,
-                }
+                CredentialEntry::new(id, cred)
             })
             .collect();
 
-        // 检测重复 ID
+        // 检测重复 ID 与无效优先级
         let mut seen_ids = std::collections::HashSet::new();
-        let mut duplicate_ids = Vec::new();
         for entry in &entries {
             if !seen_ids.insert(entry.id) {
-                duplicate_ids.push(entry.id);
+                return Err(MultiTokenManagerError::DuplicateCredentialId(entry.id));
+            }
+            if entry.credentials.priority == u32::MAX {
+                return Err(MultiTokenManagerError::InvalidPriority {
+                    id: entry.id,
+                    message: format!("{} 为内部保留值", u32::MAX),
+                });
+            }
+            if !entry.credentials.has_known_auth_method() {
+                tracing::warn!(
+                    "凭据 #{} 的 authMethod {:?} 无法识别，将按 social 方式刷新 Token",
+                    entry.id,
+                    entry.credentials.auth_method
+                );
             }
-        }
-        if !duplicate_ids.is_empty() {
-            anyhow::bail!("检测到重复的凭据 ID: {:?}", duplicate_ids);
         }
 
         // 选择初始凭据：优先级最高（priority 最小）的凭据，无凭据时为 0
         let initial_id = entries
             .iter()
-            .min_by_key(|e| e.credentials.priority)
+            .min_by_key(|e| e.credentials.sort_key())
             .map(|e| e.id)
             .unwrap_or(0);
 
         let load_balancing_mode = config.load_balancing_mode.clone();
+        let refresh_limiter = RefreshLimiter::new(
+            StdDuration::from_secs(config.min_refresh_interval_secs),
+            REFRESH_RATE_LIMIT_DEFAULT_BACKOFF,
+        )
+        .with_failure_backoff(
+            StdDuration::from_secs(config.refresh_failure_backoff_secs),
+            StdDuration::from_secs(config.refresh_failure_backoff_max_secs),
+        );
+        let cache_dir = credentials_path
+            .as_ref()
+            .and_then(|p| p.parent().map(|d| d.to_path_buf()));
+        let balance_cache = BalanceCache::load(cache_dir.clone(), unix_now());
+        let persist_guard = credentials_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(ExternalEditGuard::loaded)
+            .unwrap_or_default();
+        let user_usage = UserUsageTracker::load(cache_dir);
         let manager = Self {
             config,
             proxy,
             entries: Mutex::new(entries),
             current_id: Mutex::new(initial_id),
             refresh_lock: TokioMutex::new(()),
+            refresh_limiter: Mutex::new(refresh_limiter),
+            balance_cache,
+            usage_limits_cache: Mutex::new(HashMap::new()),
+            user_usage,
             credentials_path,
-            is_multiple_format,
+            credentials_writer: OnceLock::new(),
+            persist_guard: Arc::new(Mutex::new(persist_guard)),
+            is_multiple_format: AtomicBool::new(is_multiple_format),
             load_balancing_mode: Mutex::new(load_balancing_mode),
             last_stats_save_at: Mutex::new(None),
-            stats_dirty: AtomicBool::new(false),
+            stats_dirty: Arc::new(AtomicBool::new(false)),
+            stats_writer: Arc::default(),
+            secondary: AtomicBool::new(false),
+            events: Mutex::new(VecDeque::new()),
+            self_heal: Mutex::new(SelfHealState::default()),
+            #[cfg(test)]
+            refresh_stub: Mutex::new(VecDeque::new()),
+            #[cfg(test)]
+            usage_stub: Mutex::new(VecDeque::new()),
         };
 
         // 如果有新分配的 ID 或新生成的 machineId，立即持久化到配置文件
         if has_new_ids || has_new_machine_ids {
-            if let Err(e) = manager.persist_credentials() {
-                tracing::warn!("补全凭据 ID/machineId 后持久化失败: {}", e);
+            if let Err(e) = manager.write_credentials_now() {
+                warnings.push(MultiTokenManagerError::PersistenceError(e));
             } else {
                 tracing::info!("已补全凭据 ID/machineId 并写回配置文件");
             }
         }
 
-        // 加载持久化的统计数据（success_count, last_used_at）
+        // 加载持久化的统计数据（success_count, last_used_at）与刷新记录
         manager.load_stats();
+        manager.load_refresh_history();
 
-        Ok(manager)
+        Ok((manager, warnings))
     }
 
     /// 获取配置的引用
@@ -630,6 +1547,18 @@ impl MultiTokenManager {
         &self.config
     }
 
+    /// 进入从实例模式（其他实例持有凭据文件锁时调用）
+    ///
+    /// 从实例照常刷新 Token，但不回写凭据文件与统计数据，避免覆盖主实例的刷新结果
+    pub fn enter_secondary_mode(&self) {
+        self.secondary.store(true, Ordering::Relaxed);
+    }
+
+    /// 是否处于从实例模式
+    pub fn is_secondary(&self) -> bool {
+        self.secondary.load(Ordering::Relaxed)
+    }
+
     /// 获取当前活动凭据的克隆
     pub fn credentials(&self) -> KiroCredentials {
         let entries = self.entries.lock();
@@ -655,10 +1584,18 @@ impl MultiTokenManager {
     ///
     /// - priority 模式：选择优先级最高（priority 最小）的可用凭据
     /// - balanced 模式：轮询选择可用凭据
+    /// - reset-aware 模式：选择缓存余额中下次重置时间最早的凭据（见 [`Self::reset_aware_key`]）
+    ///
+    /// 处于刷新失败退避窗口内的凭据不参与选择，除非没有其他可用凭据
     ///
     /// # 参数
     /// - `model`: 可选的模型名称，用于过滤支持该模型的凭据（如 opus 模型需要付费订阅）
-    fn select_next_credential(&self, model: Option<&str>) -> Option<(u64, KiroCredentials)> {
+    /// - `now`: 当前时间，用于判断刷新失败退避
+    fn select_next_credential(
+        &self,
+        model: Option<&str>,
+        now: Instant,
+    ) -> Option<(u64, KiroCredentials)> {
         let entries = self.entries.lock();
 
         // 检查是否是 opus 模型
@@ -685,27 +1622,78 @@ impl MultiTokenManager {
             return None;
         }
 
+        let available = {
+            let limiter = self.refresh_limiter.lock();
+            let (ready, backing_off): (Vec<_>, Vec<_>) = available
+                .into_iter()
+                .partition(|e| limiter.failure_backoff_remaining(e.id, now).is_none());
+            if ready.is_empty() { backing_off } else { ready }
+        };
+
         let mode = self.load_balancing_mode.lock().clone();
         let mode = mode.as_str();
 
         match mode {
             "balanced" => {
                 // Least-Used 策略：选择成功次数最少的凭据
-                // 平局时按优先级排序（数字越小优先级越高）
+                // 平局时按优先级排序（数字越小优先级越高），再按 ID 排序保证确定性
                 let entry = available
                     .iter()
-                    .min_by_key(|e| (e.success_count, e.credentials.priority))?;
+                    .min_by_key(|e| (e.success_count, e.credentials.sort_key()))?;
 
                 Some((entry.id, entry.credentials.clone()))
             }
+            "reset-aware" => {
+                let now = unix_now();
+                let entry = available
+                    .iter()
+                    .filter_map(|e| {
+                        let reset_at = self.reset_aware_key(e.id, now)?;
+                        Some((e, reset_at))
+                    })
+                    .min_by(|(a, a_reset), (b, b_reset)| {
+                        // 有重置时间的凭据在前（越早越优先），缺少数据的按优先级排在其后
+                        let reset = match (a_reset, b_reset) {
+                            (Some(a), Some(b)) => a.total_cmp(b),
+                            (Some(_), None) => std::cmp::Ordering::Less,
+                            (None, Some(_)) => std::cmp::Ordering::Greater,
+                            (None, None) => std::cmp::Ordering::Equal,
+                        };
+                        reset.then_with(|| {
+                            (a.credentials.priority, a.id).cmp(&(b.credentials.priority, b.id))
+                        })
+                    })
+                    .map(|(e, _)| e)?;
+                Some((entry.id, entry.credentials.clone()))
+            }
             _ => {
                 // priority 模式（默认）：选择优先级最高的
-                let entry = available.iter().min_by_key(|e| e.credentials.priority)?;
+                let entry = available.iter().min_by_key(|e| e.credentials.sort_key())?;
                 Some((entry.id, entry.credentials.clone()))
             }
         }
     }
 
+    /// reset-aware 模式下凭据的排序依据
+    ///
+    /// - `None`：缓存显示额度已用尽，不参与选择
+    /// - `Some(None)`：无缓存余额或重置时间（按优先级排在有数据的凭据之后）
+    /// - `Some(Some(t))`：下次重置时间
+    ///
+    /// 重置时间已过的缓存视为无数据（额度可能已经恢复）
+    fn reset_aware_key(&self, id: u64, now: f64) -> Option<Option<f64>> {
+        let Some(cached) = self.balance_cache.get_cached(id) else {
+            return Some(None);
+        };
+        match cached.data.next_reset_at {
+            Some(reset_at) if reset_at > now => {
+                (cached.data.remaining() > 0.0).then_some(Some(reset_at))
+            }
+            Some(_) => Some(None),
+            None => (cached.data.remaining() > 0.0).then_some(None),
+        }
+    }
+
     /// 获取 API 调用上下文
     ///
     /// 返回绑定了 id、credentials 和 token 的调用上下文
@@ -717,31 +1705,43 @@ impl MultiTokenManager {
     /// # 参数
     /// - `model`: 可选的模型名称，用于过滤支持该模型的凭据（如 opus 模型需要付费订阅）
     pub async fn acquire_context(&self, model: Option<&str>) -> anyhow::Result<CallContext> {
+        self.apply_monthly_limits(Utc::now());
         let total = self.total_count();
         let mut tried_count = 0;
+        let mut last_error: Option<anyhow::Error> = None;
 
         loop {
             if tried_count >= total {
-                anyhow::bail!(
+                let message = format!(
                     "所有凭据均无法获取有效 Token（可用: {}/{}）",
                     self.available_count(),
                     total
                 );
+                // 保留最后一次失败原因，便于调用方识别（如刷新锁超时）
+                return Err(match last_error {
+                    Some(e) => e.context(message),
+                    None => anyhow::anyhow!(message),
+                });
             }
 
             let (id, credentials) = {
-                let is_balanced = self.load_balancing_mode.lock().as_str() == "balanced";
+                let is_priority = self.load_balancing_mode.lock().as_str() == "priority";
 
-                // balanced 模式：每次请求都轮询选择，不固定 current_id
-                // priority 模式：优先使用 current_id 指向的凭据
-                let current_hit = if is_balanced {
+                // balanced / reset-aware 模式：每次请求都重新选择，不固定 current_id
+                // priority 模式：优先使用 current_id 指向的凭据（处于刷新失败退避时重新选择）
+                let current_hit = if !is_priority {
                     None
                 } else {
                     let entries = self.entries.lock();
                     let current_id = *self.current_id.lock();
+                    let backing_off = self
+                        .refresh_limiter
+                        .lock()
+                        .failure_backoff_remaining(current_id, Instant::now())
+                        .is_some();
                     entries
                         .iter()
-                        .find(|e| e.id == current_id && !e.disabled)
+                        .find(|e| e.id == current_id && !e.disabled && !backing_off)
                         .map(|e| (e.id, e.credentials.clone()))
                 };
 
@@ -749,27 +1749,11 @@ impl MultiTokenManager {
                     hit
                 } else {
                     // 当前凭据不可用或 balanced 模式，根据负载均衡策略选择
-                    let mut best = self.select_next_credential(model);
-
-                    // 没有可用凭据：如果是"自动禁用导致全灭"，做一次类似重启的自愈
-                    if best.is_none() {
-                        let mut entries = self.entries.lock();
-                        if entries.iter().any(|e| {
-                            e.disabled && e.disabled_reason == Some(DisabledReason::TooManyFailures)
-                        }) {
-                            tracing::warn!(
-                                "所有凭据均已被自动禁用，执行自愈：重置失败计数并重新启用（等价于重启）"
-                            );
-                            for e in entries.iter_mut() {
-                                if e.disabled_reason == Some(DisabledReason::TooManyFailures) {
-                                    e.disabled = false;
-                                    e.disabled_reason = None;
-                                    e.failure_count = 0;
-                                }
-                            }
-                            drop(entries);
-                            best = self.select_next_credential(model);
-                        }
+                    let mut best = self.select_next_credential(model, Instant::now());
+
+                    // 没有可用凭据：如果是"自动禁用导致全灭"，按 autoRecoverAllDisabled 策略自愈
+                    if best.is_none() && self.try_self_heal(Utc::now()) {
+                        best = self.select_next_credential(model, Instant::now());
                     }
 
                     if let Some((new_id, new_creds)) = best {
@@ -783,27 +1767,113 @@ impl MultiTokenManager {
                         // 因为 available_count() 会尝试获取 entries 锁，
                         // 而此时我们已经持有该锁，会导致死锁
                         let available = entries.iter().filter(|e| !e.disabled).count();
+                        log_throttled!(
+                            warn,
+                            "acquire_context_exhausted",
+                            DEFAULT_LOG_THROTTLE_INTERVAL,
+                            "没有可用凭据，拒绝请求（可用 {}/{}）",
+                            available,
+                            total
+                        );
                         anyhow::bail!("所有凭据均已禁用（{}/{}）", available, total);
                     }
                 }
             };
 
             // 尝试获取/刷新 Token
-            match self.try_ensure_token(id, &credentials).await {
+            match self.try_ensure_token(id, credentials).await {
                 Ok(ctx) => {
                     return Ok(ctx);
                 }
                 Err(e) => {
-                    tracing::warn!("凭据 #{} Token 刷新失败，尝试下一个凭据: {}", id, e);
+                    log_throttled!(
+                        warn,
+                        format!("token_refresh_failure:{}", id),
+                        DEFAULT_LOG_THROTTLE_INTERVAL,
+                        "凭据 #{} Token 刷新失败，尝试下一个凭据: {}",
+                        id,
+                        e
+                    );
 
                     // Token 刷新失败，切换到下一个优先级的凭据（不计入失败次数）
                     self.switch_to_next_by_priority();
                     tried_count += 1;
+                    last_error = Some(e);
                 }
             }
         }
     }
 
+    /// 获取指定凭据的 API 调用上下文（跳过负载均衡选择，用于 A/B 路由）
+    ///
+    /// 凭据不存在或已禁用时返回错误，不会切换到其他凭据
+    pub async fn acquire_context_for_id(&self, id: u64) -> anyhow::Result<CallContext> {
+        self.apply_monthly_limits(Utc::now());
+        let credentials = {
+            let entries = self.entries.lock();
+            let entry = entries
+                .iter()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            if entry.disabled {
+                anyhow::bail!("凭据 #{} 已禁用", id);
+            }
+            entry.credentials.clone()
+        };
+
+        self.try_ensure_token(id, credentials).await
+    }
+
+    /// 预览下一次请求将使用的调用上下文（用于 dry-run）
+    ///
+    /// 与 `acquire_context` / `acquire_context_for_id` 的选择规则一致，但不刷新 Token、
+    /// 不修改当前凭据，也不触发自愈；Token 直接取自凭据中已有的 accessToken（可能已过期）
+    pub fn preview_context(
+        &self,
+        model: Option<&str>,
+        pinned_id: Option<u64>,
+    ) -> anyhow::Result<CallContext> {
+        let (id, credentials) = match pinned_id {
+            Some(id) => {
+                let entries = self.entries.lock();
+                let entry = entries
+                    .iter()
+                    .find(|e| e.id == id)
+                    .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+                if entry.disabled {
+                    anyhow::bail!("凭据 #{} 已禁用", id);
+                }
+                (entry.id, entry.credentials.clone())
+            }
+            None => {
+                let current_hit = if self.load_balancing_mode.lock().as_str() != "priority" {
+                    None
+                } else {
+                    let entries = self.entries.lock();
+                    let current_id = *self.current_id.lock();
+                    entries
+                        .iter()
+                        .find(|e| e.id == current_id && !e.disabled)
+                        .map(|e| (e.id, e.credentials.clone()))
+                };
+                match current_hit {
+                    Some(hit) => hit,
+                    None => self
+                        .select_next_credential(model, Instant::now())
+                        .ok_or_else(|| anyhow::anyhow!("没有可用的凭据"))?,
+                }
+            }
+        };
+
+        Ok(CallContext {
+            id,
+            token: credentials.access_token.clone().unwrap_or_default(),
+            identity: RequestIdentity::from_credentials(&credentials, &self.config).ok(),
+            credentials,
+            created_at: Instant::now(),
+        })
+    }
+
     /// 切换到下一个优先级最高的可用凭据（内部方法）
     fn switch_to_next_by_priority(&self) {
         let entries = self.entries.lock();
@@ -813,7 +1883,7 @@ impl MultiTokenManager {
         if let Some(entry) = entries
             .iter()
             .filter(|e| !e.disabled && e.id != *current_id)
-            .min_by_key(|e| e.credentials.priority)
+            .min_by_key(|e| e.credentials.sort_key())
         {
             *current_id = entry.id;
             tracing::info!(
@@ -836,7 +1906,7 @@ impl MultiTokenManager {
         if let Some(best) = entries
             .iter()
             .filter(|e| !e.disabled)
-            .min_by_key(|e| e.credentials.priority)
+            .min_by_key(|e| e.credentials.sort_key())
         {
             if best.id != *current_id {
                 tracing::info!(
@@ -852,1022 +1922,3838 @@ impl MultiTokenManager {
 
     /// 尝试使用指定凭据获取有效 Token
     ///
-    /// 使用双重检查锁定模式，确保同一时间只有一个刷新操作
+    /// Token 仍有效时直接使用调用方传入的凭据快照（不加锁、不复制）；
+    /// 需要刷新时进入 [`Self::refresh_for_context`]
     ///
     /// # Arguments
     /// * `id` - 凭据 ID，用于更新正确的条目
-    /// * `credentials` - 凭据信息
+    /// * `credentials` - 调用方已复制的凭据快照
     async fn try_ensure_token(
         &self,
         id: u64,
-        credentials: &KiroCredentials,
+        credentials: KiroCredentials,
     ) -> anyhow::Result<CallContext> {
-        // 第一次检查（无锁）：快速判断是否需要刷新
-        let needs_refresh = is_token_expired(credentials) || is_token_expiring_soon(credentials);
-
-        let creds = if needs_refresh {
-            // 获取刷新锁，确保同一时间只有一个刷新操作
-            let _guard = self.refresh_lock.lock().await;
-
-            // 第二次检查：获取锁后重新读取凭据，因为其他请求可能已经完成刷新
-            let current_creds = {
-                let entries = self.entries.lock();
-                entries
-                    .iter()
-                    .find(|e| e.id == id)
-                    .map(|e| e.credentials.clone())
-                    .ok_or_else(|| anyhow::anyhow!("凭据 #{} 不存在", id))?
-            };
-
-            if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
-                // 确实需要刷新
-                let effective_proxy = current_creds.effective_proxy(self.proxy.as_ref());
-                let new_creds =
-                    refresh_token(&current_creds, &self.config, effective_proxy.as_ref()).await?;
-
-                if is_token_expired(&new_creds) {
-                    anyhow::bail!("刷新后的 Token 仍然无效或已过期");
-                }
-
-                // 更新凭据
-                {
-                    let mut entries = self.entries.lock();
-                    if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
-                        entry.credentials = new_creds.clone();
-                    }
-                }
-
-                // 回写凭据到文件（仅多凭据格式），失败只记录警告
-                if let Err(e) = self.persist_credentials() {
-                    tracing::warn!("Token 刷新后持久化失败（不影响本次请求）: {}", e);
-                }
-
-                new_creds
-            } else {
-                // 其他请求已经完成刷新，直接使用新凭据
-                tracing::debug!("Token 已被其他请求刷新，跳过刷新");
-                current_creds
-            }
+        let needs_refresh = is_token_expired(&credentials) || is_token_expiring_soon(&credentials);
+        let credentials = if needs_refresh {
+            self.refresh_for_context(id).await?
         } else {
-            credentials.clone()
+            credentials
         };
 
-        let token = creds
+        let token = credentials
             .access_token
             .clone()
             .ok_or_else(|| anyhow::anyhow!("没有可用的 accessToken"))?;
 
         Ok(CallContext {
             id,
-            credentials: creds,
+            identity: RequestIdentity::from_credentials(&credentials, &self.config).ok(),
+            credentials,
             token,
+            created_at: Instant::now(),
         })
     }
 
-    /// 将凭据列表回写到源文件
-    ///
-    /// 仅在以下条件满足时回写：
-    /// - 源文件是多凭据格式（数组）
-    /// - credentials_path 已设置
+    /// Token 过期或即将过期时的刷新路径
     ///
-    /// # Returns
-    /// - `Ok(true)` - 成功写入文件
-    /// - `Ok(false)` - 跳过写入（非多凭据格式或无路径配置）
-    /// - `Err(_)` - 写入失败
-    fn persist_credentials(&self) -> anyhow::Result<bool> {
-        use anyhow::Context;
-
-        // 仅多凭据格式才回写
-        if !self.is_multiple_format {
-            return Ok(false);
-        }
+    /// 使用双重检查锁定模式：获取刷新锁后重新读取凭据，其他请求可能已经完成刷新
+    async fn refresh_for_context(&self, id: u64) -> anyhow::Result<KiroCredentials> {
+        let _guard = self.lock_refresh(id).await?;
 
-        let path = match &self.credentials_path {
-            Some(p) => p,
-            None => return Ok(false),
-        };
-
-        // 收集所有凭据
-        let credentials: Vec<KiroCredentials> = {
+        let current_creds = {
             let entries = self.entries.lock();
             entries
                 .iter()
-                .map(|e| {
-                    let mut cred = e.credentials.clone();
-                    cred.canonicalize_auth_method();
-                    // 同步 disabled 状态到凭据对象
-                    cred.disabled = e.disabled;
-                    cred
-                })
-                .collect()
+                .find(|e| e.id == id)
+                .map(|e| e.credentials.clone())
+                .ok_or_else(|| anyhow::anyhow!("凭据 #{} 不存在", id))?
         };
 
-        // 序列化为 pretty JSON
-        let json = serde_json::to_string_pretty(&credentials).context("序列化凭据失败")?;
-
-        // 写入文件（在 Tokio runtime 内使用 block_in_place 避免阻塞 worker）
-        if tokio::runtime::Handle::try_current().is_ok() {
-            tokio::task::block_in_place(|| std::fs::write(path, &json))
-                .with_context(|| format!("回写凭据文件失败: {:?}", path))?;
+        if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
+            // 确实需要刷新（受刷新限流约束）
+            self.refresh_credential_limited(id, current_creds).await
         } else {
-            std::fs::write(path, &json).with_context(|| format!("回写凭据文件失败: {:?}", path))?;
+            tracing::debug!("Token 已被其他请求刷新，跳过刷新");
+            Ok(current_creds)
         }
-
-        tracing::debug!("已回写凭据到文件: {:?}", path);
-        Ok(true)
-    }
-
-    /// 获取缓存目录（凭据文件所在目录）
-    pub fn cache_dir(&self) -> Option<PathBuf> {
-        self.credentials_path
-            .as_ref()
-            .and_then(|p| p.parent().map(|d| d.to_path_buf()))
     }
 
-    /// 统计数据文件路径
-    fn stats_path(&self) -> Option<PathBuf> {
-        self.cache_dir().map(|d| d.join("kiro_stats.json"))
+    /// 获取刷新锁
+    ///
+    /// 上游刷新端点挂起时持锁的请求可能长时间不释放，等待超过
+    /// `tokenRefreshLockTimeoutSecs` 后返回 [`RefreshLockTimeout`]（按瞬态失败处理，不计入失败次数）
+    async fn lock_refresh(&self, id: u64) -> anyhow::Result<tokio::sync::MutexGuard<'_, ()>> {
+        let timeout = StdDuration::from_secs(self.config.token_refresh_lock_timeout_secs);
+        match tokio::time::timeout(timeout, self.refresh_lock.lock()).await {
+            Ok(guard) => Ok(guard),
+            Err(_) => {
+                metrics::record_refresh_lock_timeout(id);
+                let error = RefreshLockTimeout { id, timeout };
+                tracing::warn!("{}", error);
+                Err(error.into())
+            }
+        }
     }
 
-    /// 从磁盘加载统计数据并应用到当前条目
-    fn load_stats(&self) {
-        let path = match self.stats_path() {
-            Some(p) => p,
-            None => return,
-        };
+    /// 在刷新限流约束下刷新指定凭据的 Token（调用方需持有 refresh_lock）
+    ///
+    /// - 距上次刷新不足最小间隔或处于 429 退避窗口时不会请求上游：
+    ///   凭据的 accessToken 尚未过期则直接复用，否则返回 `RefreshLimitError`
+    /// - 刷新端点返回 429 时记录 Retry-After，窗口内不再尝试刷新该凭据
+    /// - 刷新失败时记录失败退避（选择凭据时暂时跳过该凭据），刷新成功后清除
+    async fn refresh_credential_limited(
+        &self,
+        id: u64,
+        current_creds: KiroCredentials,
+    ) -> anyhow::Result<KiroCredentials> {
+        let now = Instant::now();
+        {
+            let mut limiter = self.refresh_limiter.lock();
+            if let Err(e) = limiter.check(id, now) {
+                // 仅复用尚未过期的 Token；已过期的 Token 会被上游以 401 拒绝并计入认证失败，
+                // 此时返回错误让调用方切换到其他凭据
+                if current_creds.access_token.is_some()
+                    && is_token_expiring_within(&current_creds, 0) == Some(false)
+                {
+                    tracing::warn!("{}，暂时复用现有 Token", e);
+                    return Ok(current_creds);
+                }
+                return Err(e.into());
+            }
+            limiter.record_attempt(id, now);
+        }
 
-        let content = match std::fs::read_to_string(&path) {
-            Ok(c) => c,
-            Err(_) => return, // 首次运行时文件不存在
-        };
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let result = self.call_refresh(&current_creds).await.and_then(|c| {
+            if is_token_expired(&c) {
+                anyhow::bail!("刷新后的 Token 仍然无效或已过期");
+            }
+            Ok(c)
+        });
+        let rotated = result.as_ref().is_ok_and(|c| {
+            c.refresh_token.is_some() && c.refresh_token != current_creds.refresh_token
+        });
+        self.record_refresh_attempt(
+            id,
+            RefreshAttempt {
+                attempted_at: started_at.to_rfc3339(),
+                succeeded: result.is_ok(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|e| e.to_string()),
+                rotated,
+            },
+        );
 
-        let stats: HashMap<String, StatsEntry> = match serde_json::from_str(&content) {
-            Ok(s) => s,
+        let new_creds = match result {
+            Ok(c) => {
+                self.refresh_limiter.lock().record_success(id);
+                c
+            }
             Err(e) => {
-                tracing::warn!("解析统计缓存失败，将忽略: {}", e);
-                return;
+                self.refresh_limiter
+                    .lock()
+                    .record_failure(id, Instant::now());
+                if let Some(limited) = e.downcast_ref::<RefreshRateLimited>() {
+                    tracing::warn!(
+                        "凭据 #{} 刷新被上游限流，Retry-After: {:?}",
+                        id,
+                        limited.retry_after
+                    );
+                    self.refresh_limiter.lock().record_rate_limited(
+                        id,
+                        Instant::now(),
+                        limited.retry_after,
+                    );
+                }
+                return Err(e);
             }
         };
 
-        let mut entries = self.entries.lock();
-        for entry in entries.iter_mut() {
-            if let Some(s) = stats.get(&entry.id.to_string()) {
-                entry.success_count = s.success_count;
-                entry.last_used_at = s.last_used_at.clone();
+        // 更新凭据
+        {
+            let mut entries = self.entries.lock();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.credentials = new_creds.clone();
             }
         }
-        *self.last_stats_save_at.lock() = Some(Instant::now());
-        self.stats_dirty.store(false, Ordering::Relaxed);
-        tracing::info!("已从缓存加载 {} 条统计数据", stats.len());
-    }
 
-    /// 将当前统计数据持久化到磁盘
-    fn save_stats(&self) {
-        let path = match self.stats_path() {
-            Some(p) => p,
-            None => return,
-        };
+        // 回写凭据到文件（仅多凭据格式）：refreshToken 轮换后必须确认落盘，否则失败只记录警告
+        if rotated {
+            self.persist_rotated_credentials(id, &new_creds).await;
+        } else if let Err(e) = self.persist_credentials() {
+            tracing::warn!("Token 刷新后持久化失败（不影响本次请求）: {}", e);
+        }
 
-        let stats: HashMap<String, StatsEntry> = {
-            let entries = self.entries.lock();
-            entries
-                .iter()
-                .map(|e| {
-                    (
-                        e.id.to_string(),
-                        StatsEntry {
-                            success_count: e.success_count,
-                            last_used_at: e.last_used_at.clone(),
-                        },
-                    )
-                })
-                .collect()
-        };
+        Ok(new_creds)
+    }
 
-        match serde_json::to_string_pretty(&stats) {
-            Ok(json) => {
-                if let Err(e) = std::fs::write(&path, json) {
-                    tracing::warn!("保存统计缓存失败: {}", e);
-                } else {
-                    *self.last_stats_save_at.lock() = Some(Instant::now());
-                    self.stats_dirty.store(false, Ordering::Relaxed);
+    /// refreshToken 轮换后回写凭据文件（旧令牌已失效，新令牌丢失会导致凭据在重启后不可用）
+    ///
+    /// 确认落盘失败时按退避重试；仍失败则保留内存中的新令牌，标记 rotationUnpersisted、
+    /// 产生管理事件，并在缓存目录写入应急副本以便手动恢复
+    async fn persist_rotated_credentials(&self, id: u64, credentials: &KiroCredentials) {
+        let mut backoff = ROTATION_PERSIST_BACKOFF;
+        let mut last_error = None;
+        for attempt in 1..=ROTATION_PERSIST_ATTEMPTS {
+            let result = match self.persist_credentials() {
+                Ok(true) => self.flush_credentials().await,
+                Ok(false) => {
+                    tracing::warn!(
+                        "凭据 #{} 的 refreshToken 已轮换，但凭据不回写文件，重启后将使用旧令牌",
+                        id
+                    );
+                    return;
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    self.set_rotation_unpersisted(id, false);
+                    return;
                 }
+                Err(e) => {
+                    tracing::warn!(
+                        "凭据 #{} 的 refreshToken 已轮换，第 {}/{} 次回写失败: {}",
+                        id,
+                        attempt,
+                        ROTATION_PERSIST_ATTEMPTS,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+            if attempt < ROTATION_PERSIST_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
             }
-            Err(e) => tracing::warn!("序列化统计数据失败: {}", e),
         }
-    }
-
-    /// 标记统计数据已更新，并按 debounce 策略决定是否立即落盘
-    fn save_stats_debounced(&self) {
-        self.stats_dirty.store(true, Ordering::Relaxed);
 
-        let should_flush = {
-            let last = *self.last_stats_save_at.lock();
-            match last {
-                Some(last_saved_at) => last_saved_at.elapsed() >= STATS_SAVE_DEBOUNCE,
-                None => true,
-            }
+        self.set_rotation_unpersisted(id, true);
+        let error = last_error.map(|e| e.to_string()).unwrap_or_default();
+        let recovery = match self.write_rotation_emergency_copy(id, credentials) {
+            Ok(path) => format!("应急副本已写入 {:?}", path),
+            Err(e) => format!("写入应急副本也失败: {}", e),
         };
+        self.push_event(AdminEvent {
+            at: Utc::now().to_rfc3339(),
+            credential_id: id,
+            kind: AdminEventKind::RotationUnpersisted,
+            message: format!(
+                "凭据 #{} 的 refreshToken 已轮换但回写凭据文件失败（{}），新令牌仅保存在内存中，{}",
+                id, error, recovery
+            ),
+        });
+    }
 
-        if should_flush {
-            self.save_stats();
+    fn set_rotation_unpersisted(&self, id: u64, unpersisted: bool) {
+        let since = unpersisted.then(|| self.persist_guard.lock().writes());
+        let mut entries = self.entries.lock();
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            entry.rotation_unpersisted = since;
         }
     }
 
-    /// 报告指定凭据 API 调用成功
-    ///
-    /// 重置该凭据的失败计数
-    ///
-    /// # Arguments
-    /// * `id` - 凭据 ID（来自 CallContext）
-    pub fn report_success(&self, id: u64) {
+    /// 标记 rotationUnpersisted 之后凭据文件又成功写入过时清除标记（写入的快照已包含新令牌）
+    fn clear_persisted_rotations(&self, writes: u64) {
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if entry
+                .rotation_unpersisted
+                .is_some_and(|since| writes > since)
+            {
+                tracing::info!("凭据 #{} 轮换后的 refreshToken 已回写凭据文件", entry.id);
+                entry.rotation_unpersisted = None;
+            }
+        }
+    }
+
+    /// 将轮换后的凭据写入缓存目录下的应急副本（`kiro_rotated_credential_<id>.json`，仅所有者可读写）
+    fn write_rotation_emergency_copy(
+        &self,
+        id: u64,
+        credentials: &KiroCredentials,
+    ) -> anyhow::Result<PathBuf> {
+        let dir = self
+            .cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("无法确定缓存目录"))?;
+        let path = dir.join(format!("kiro_rotated_credential_{}.json", id));
+        let json = serde_json::to_string_pretty(credentials)?;
+        write_atomic_private(&path, &json)?;
+        Ok(path)
+    }
+
+    /// 调用刷新端点（测试中优先返回预设结果）
+    async fn call_refresh(&self, credentials: &KiroCredentials) -> anyhow::Result<KiroCredentials> {
+        #[cfg(test)]
+        {
+            let stubbed = self.refresh_stub.lock().pop_front();
+            if let Some(stubbed) = stubbed {
+                // 模拟网络请求的挂起点，使并发测试能在刷新期间交错执行
+                tokio::task::yield_now().await;
+                return stubbed;
+            }
+        }
+
+        let effective_proxy = credentials.effective_proxy(self.proxy.as_ref());
+        refresh_token(credentials, &self.config, effective_proxy.as_ref()).await
+    }
+
+    /// 测试用：预设后续刷新调用的返回结果
+    #[cfg(test)]
+    pub(crate) fn stub_refresh_results(&self, results: Vec<anyhow::Result<KiroCredentials>>) {
+        self.refresh_stub.lock().extend(results);
+    }
+
+    /// 记录一次 Token 刷新尝试（超出容量时丢弃最早的记录）并持久化
+    fn record_refresh_attempt(&self, id: u64, attempt: RefreshAttempt) {
         {
             let mut entries = self.entries.lock();
             if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
-                entry.failure_count = 0;
-                entry.success_count += 1;
-                entry.last_used_at = Some(Utc::now().to_rfc3339());
-                tracing::debug!(
-                    "凭据 #{} API 调用成功（累计 {} 次）",
-                    id,
-                    entry.success_count
-                );
+                entry.refresh_history.push_back(attempt);
+                while entry.refresh_history.len() > REFRESH_HISTORY_CAPACITY {
+                    entry.refresh_history.pop_front();
+                }
             }
         }
-        self.save_stats_debounced();
+        self.save_refresh_history();
     }
 
-    /// 报告指定凭据 API 调用失败
-    ///
-    /// 增加失败计数，达到阈值时禁用凭据并切换到优先级最高的可用凭据
-    /// 返回是否还有可用凭据可以重试
+    /// 启动后台存活检查任务
     ///
-    /// # Arguments
-    /// * `id` - 凭据 ID（来自 CallContext）
-    pub fn report_failure(&self, id: u64) -> bool {
-        let result = {
-            let mut entries = self.entries.lock();
-            let mut current_id = self.current_id.lock();
-
-            let entry = match entries.iter_mut().find(|e| e.id == id) {
-                Some(e) => e,
-                None => return entries.iter().any(|e| !e.disabled),
-            };
-
-            entry.failure_count += 1;
-            entry.last_used_at = Some(Utc::now().to_rfc3339());
-            let failure_count = entry.failure_count;
+    /// 按 `interval` 周期检查长时间未被使用的凭据（见 `livenessCheckIdleThresholdSecs`），
+    /// 刷新令牌已失效的凭据会被禁用，避免故障切换时选中后才发现不可用。
+    /// 管理器被释放后任务自动退出。
+    pub fn start_liveness_checker(self: &Arc<Self>, interval: StdDuration) -> JoinHandle<()> {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // 首次 tick 立即完成，跳过以免启动时就发起刷新
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.check_liveness().await;
+            }
+        })
+    }
 
-            tracing::warn!(
-                "凭据 #{} API 调用失败（{}/{}）",
-                id,
-                failure_count,
-                MAX_FAILURES_PER_CREDENTIAL
-            );
+    /// 执行一轮存活检查
+    ///
+    /// 对闲置凭据校验刷新令牌，Token 已过期时尝试刷新（带超时）；
+    /// 仅令牌格式无效或刷新端点返回认证失败时禁用，网络错误等其他失败留给请求路径处理
+    async fn check_liveness(&self) {
+        let idle_threshold =
+            Duration::seconds(self.config.liveness_check_idle_threshold_secs as i64);
+        let now = Utc::now();
+        let candidates: Vec<(u64, KiroCredentials)> = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .filter(|e| !e.disabled)
+                .filter(|e| {
+                    e.last_used_at
+                        .as_deref()
+                        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                        .is_none_or(|t| now - t.with_timezone(&Utc) >= idle_threshold)
+                })
+                .map(|e| (e.id, e.credentials.clone()))
+                .collect()
+        };
 
-            if failure_count >= MAX_FAILURES_PER_CREDENTIAL {
-                entry.disabled = true;
-                entry.disabled_reason = Some(DisabledReason::TooManyFailures);
-                tracing::error!("凭据 #{} 已连续失败 {} 次，已被禁用", id, failure_count);
+        for (id, credentials) in candidates {
+            if let Err(e) = validate_refresh_token(&credentials) {
+                self.disable_dead_credential(id, &e.to_string());
+                continue;
+            }
+            if !is_token_expired(&credentials) {
+                continue;
+            }
 
-                // 切换到优先级最高的可用凭据
-                if let Some(next) = entries
-                    .iter()
-                    .filter(|e| !e.disabled)
-                    .min_by_key(|e| e.credentials.priority)
-                {
-                    *current_id = next.id;
-                    tracing::info!(
-                        "已切换到凭据 #{}（优先级 {}）",
-                        next.id,
-                        next.credentials.priority
-                    );
-                } else {
-                    tracing::error!("所有凭据均已禁用！");
+            match tokio::time::timeout(LIVENESS_REFRESH_TIMEOUT, self.refresh_for_context(id)).await
+            {
+                Ok(Ok(_)) => tracing::debug!("凭据 #{} 存活检查刷新成功", id),
+                Ok(Err(e)) if e.downcast_ref::<RefreshAuthenticationFailed>().is_some() => {
+                    self.disable_dead_credential(id, &e.to_string());
                 }
+                Ok(Err(e)) => tracing::debug!("凭据 #{} 存活检查刷新失败（不禁用）: {}", id, e),
+                Err(_) => tracing::debug!("凭据 #{} 存活检查刷新超时", id),
             }
+        }
+    }
 
-            entries.iter().any(|e| !e.disabled)
+    /// 禁用存活检查失败的凭据，必要时切换当前凭据
+    fn disable_dead_credential(&self, id: u64, reason: &str) {
+        let was_current = {
+            let mut entries = self.entries.lock();
+            let Some(entry) = entries.iter_mut().find(|e| e.id == id && !e.disabled) else {
+                return;
+            };
+            entry.disabled = true;
+            entry.disabled_reason = Some(DisabledReason::TooManyFailures);
+            *self.current_id.lock() == id
         };
+        tracing::warn!("凭据 #{} 存活检查失败，已禁用: {}", id, reason);
+
+        if was_current {
+            self.select_highest_priority();
+        }
         self.save_stats_debounced();
-        result
     }
 
-    /// 报告指定凭据额度已用尽
+    /// 获取指定凭据的完整凭证信息（Admin API，调用方负责脱敏）
+    pub fn credentials_of(&self, id: u64) -> anyhow::Result<KiroCredentials> {
+        let entries = self.entries.lock();
+        entries
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.credentials.clone())
+            .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))
+    }
+
+    /// 获取指定凭据的 Token 刷新记录（Admin API）
+    pub fn refresh_history(&self, id: u64) -> anyhow::Result<Vec<RefreshAttempt>> {
+        let entries = self.entries.lock();
+        let entry = entries
+            .iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+        Ok(entry.refresh_history.iter().cloned().collect())
+    }
+
+    /// 将凭据列表回写到源文件
     ///
-    /// 用于处理 402 Payment Required 且 reason 为 `MONTHLY_REQUEST_COUNT` 的场景：
-    /// - 立即禁用该凭据（不等待连续失败阈值）
-    /// - 切换到下一个可用凭据继续重试
-    /// - 返回是否还有可用凭据
-    pub fn report_quota_exhausted(&self, id: u64) -> bool {
-        let result = {
-            let mut entries = self.entries.lock();
-            let mut current_id = self.current_id.lock();
+    /// 仅在以下条件满足时回写：
+    /// - 源文件是多凭据格式（数组）
+    /// - credentials_path 已设置
+    ///
+    /// 在 Tokio runtime 内时快照交给单写者任务异步落盘（不阻塞，连续写入会合并），
+    /// 需要确认落盘时调用 [`Self::flush_credentials`]；否则直接原子写入
+    ///
+    /// # Returns
+    /// - `Ok(true)` - 已写入文件或已提交给写入任务
+    /// - `Ok(false)` - 跳过写入（非多凭据格式或无路径配置）
+    /// - `Err(_)` - 序列化或写入失败
+    fn persist_credentials(&self) -> anyhow::Result<bool> {
+        use anyhow::Context;
 
-            let entry = match entries.iter_mut().find(|e| e.id == id) {
-                Some(e) => e,
-                None => return entries.iter().any(|e| !e.disabled),
-            };
+        let Some(path) = self.persist_target() else {
+            return Ok(false);
+        };
+        self.sync_external_edits();
 
-            if entry.disabled {
-                return entries.iter().any(|e| !e.disabled);
+        // 在 entries 锁内生成并提交快照，保证提交顺序与修改顺序一致
+        let json = {
+            let entries = self.entries.lock();
+            let json = credentials_json(&entries)?;
+            if tokio::runtime::Handle::try_current().is_ok() {
+                let writer = self.credentials_writer.get_or_init(|| {
+                    CredentialsWriter::spawn(path.to_path_buf(), self.persist_guard.clone())
+                });
+                match writer.submit(json) {
+                    Ok(()) => return Ok(true),
+                    // 写入任务已随所在 runtime 退出，改为直接写入
+                    Err(json) => json,
+                }
+            } else {
+                json
             }
+        };
 
-            entry.disabled = true;
-            entry.disabled_reason = Some(DisabledReason::QuotaExceeded);
-            entry.last_used_at = Some(Utc::now().to_rfc3339());
-            // 设为阈值，便于在管理面板中直观看到该凭据已不可用
-            entry.failure_count = MAX_FAILURES_PER_CREDENTIAL;
+        self.persist_guard
+            .lock()
+            .write(path, &json)
+            .with_context(|| format!("回写凭据文件失败: {:?}", path))?;
+        tracing::debug!("已回写凭据到文件: {:?}", path);
+        self.sync_external_edits();
+        Ok(true)
+    }
 
-            tracing::error!("凭据 #{} 额度已用尽（MONTHLY_REQUEST_COUNT），已被禁用", id);
+    /// 立即同步回写凭据文件（用于启动阶段，写入失败需要直接报告）
+    fn write_credentials_now(&self) -> anyhow::Result<bool> {
+        use anyhow::Context;
 
-            // 切换到优先级最高的可用凭据
-            if let Some(next) = entries
-                .iter()
-                .filter(|e| !e.disabled)
-                .min_by_key(|e| e.credentials.priority)
-            {
-                *current_id = next.id;
-                tracing::info!(
-                    "已切换到凭据 #{}（优先级 {}）",
-                    next.id,
-                    next.credentials.priority
-                );
-                true
-            } else {
-                tracing::error!("所有凭据均已禁用！");
-                false
-            }
+        let Some(path) = self.persist_target() else {
+            return Ok(false);
         };
-        self.save_stats_debounced();
+        let json = credentials_json(&self.entries.lock())?;
+        self.persist_guard
+            .lock()
+            .write(path, &json)
+            .with_context(|| format!("回写凭据文件失败: {:?}", path))?;
+        tracing::debug!("已回写凭据到文件: {:?}", path);
+        Ok(true)
+    }
+
+    /// 等待已提交的凭据快照落盘（关闭前及需要确认落盘的 Admin 操作使用）
+    pub async fn flush_credentials(&self) -> anyhow::Result<()> {
+        let result = match self.credentials_writer.get() {
+            Some(writer) => writer.flush().await,
+            None => Ok(()),
+        };
+        self.sync_external_edits();
         result
     }
 
-    /// 切换到优先级最高的可用凭据
+    /// 将回写时合并进凭据文件的外部修改同步回内存
     ///
-    /// 返回是否成功切换
-    pub fn switch_to_next(&self) -> bool {
-        let entries = self.entries.lock();
-        let mut current_id = self.current_id.lock();
+    /// 只应用内存中自回写快照以来未再变化的字段，避免覆盖期间刷新的 Token；
+    /// 写入任务正持有保护锁时跳过，留待下一次同步，调用方不会等待文件 IO。
+    /// 外部新增但没有 `id` 的凭据留在文件中，重启加载时分配 ID。
+    /// 同时清除此后已成功回写的 rotationUnpersisted 标记
+    ///
+    /// 加锁顺序为 persist_guard → entries，持有 entries 时不得再获取 persist_guard
+    fn sync_external_edits(&self) {
+        let Some(mut guard) = self.persist_guard.try_lock() else {
+            return;
+        };
+        self.clear_persisted_rotations(guard.writes());
+        let Some(edits) = guard.take_external_edits() else {
+            return;
+        };
+        let by_id = |values: &[serde_json::Value]| -> HashMap<u64, serde_json::Value> {
+            values
+                .iter()
+                .filter_map(|v| Some((v.get("id")?.as_u64()?, v.clone())))
+                .collect()
+        };
+        let (ours, written) = (by_id(&edits.ours), by_id(&edits.written));
 
-        // 选择优先级最高的未禁用凭据（排除当前凭据）
-        if let Some(next) = entries
-            .iter()
-            .filter(|e| !e.disabled && e.id != *current_id)
-            .min_by_key(|e| e.credentials.priority)
+        let mut updated = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut reselect = false;
         {
-            *current_id = next.id;
-            tracing::info!(
-                "已切换到凭据 #{}（优先级 {}）",
-                next.id,
-                next.credentials.priority
-            );
-            true
-        } else {
-            // 没有其他可用凭据，检查当前凭据是否可用
-            entries.iter().any(|e| e.id == *current_id && !e.disabled)
-        }
-    }
+            let mut entries = self.entries.lock();
+            for entry in entries.iter_mut() {
+                let Some(ours) = ours.get(&entry.id) else {
+                    continue;
+                };
+                let Some(written) = written.get(&entry.id) else {
+                    removed.push(entry.id);
+                    continue;
+                };
+                let Some(fields) = entry.apply_external_fields(ours, written) else {
+                    continue;
+                };
+                reselect |= fields.iter().any(|f| f == "priority" || f == "disabled");
+                updated.push(entry.id);
+            }
+            entries.retain(|e| !removed.contains(&e.id));
 
-    /// 获取使用额度信息
-    pub async fn get_usage_limits(&self) -> anyhow::Result<UsageLimitsResponse> {
-        let ctx = self.acquire_context(None).await?;
-        let effective_proxy = ctx.credentials.effective_proxy(self.proxy.as_ref());
-        get_usage_limits(
-            &ctx.credentials,
-            &self.config,
-            &ctx.token,
-            effective_proxy.as_ref(),
-        )
-        .await
-    }
+            for value in &edits.written {
+                let Some(id) = value.get("id").and_then(serde_json::Value::as_u64) else {
+                    continue;
+                };
+                if ours.contains_key(&id) || entries.iter().any(|e| e.id == id) {
+                    continue;
+                }
+                match serde_json::from_value::<KiroCredentials>(value.clone()) {
+                    Ok(mut cred) => {
+                        cred.canonicalize_auth_method();
+                        entries.push(CredentialEntry::new(id, cred));
+                        added.push(id);
+                    }
+                    Err(e) => tracing::warn!("外部新增的凭据 #{} 无法解析，未加载: {}", id, e),
+                }
+            }
+        }
+        guard.mark_synced(&edits);
+        drop(guard);
 
-    // ========================================================================
-    // Admin API 方法
-    // ========================================================================
+        for id in updated.iter().chain(&removed) {
+            self.invalidate_usage_cache(*id);
+        }
+        for id in &removed {
+            self.refresh_limiter.lock().remove(*id);
+            self.balance_cache.remove(*id);
+        }
+        if updated.is_empty() && removed.is_empty() && added.is_empty() {
+            return;
+        }
+        tracing::info!(
+            "已同步凭据文件中的外部修改: 更新 {:?}，新增 {:?}，删除 {:?}",
+            updated,
+            added,
+            removed
+        );
 
-    /// 获取管理器状态快照（用于 Admin API）
-    pub fn snapshot(&self) -> ManagerSnapshot {
-        let entries = self.entries.lock();
         let current_id = *self.current_id.lock();
-        let available = entries.iter().filter(|e| !e.disabled).count();
+        if reselect || removed.contains(&current_id) {
+            self.select_highest_priority();
+        }
+        if self.entries.lock().is_empty() {
+            *self.current_id.lock() = 0;
+        }
+    }
 
-        ManagerSnapshot {
-            entries: entries
-                .iter()
-                .map(|e| CredentialEntrySnapshot {
-                    id: e.id,
-                    priority: e.credentials.priority,
-                    disabled: e.disabled,
-                    failure_count: e.failure_count,
-                    auth_method: e.credentials.auth_method.as_deref().map(|m| {
-                        if m.eq_ignore_ascii_case("builder-id") || m.eq_ignore_ascii_case("iam") {
-                            "idc".to_string()
-                        } else {
-                            m.to_string()
-                        }
-                    }),
-                    has_profile_arn: e.credentials.profile_arn.is_some(),
-                    expires_at: e.credentials.expires_at.clone(),
-                    refresh_token_hash: e.credentials.refresh_token.as_deref().map(sha256_hex),
-                    email: e.credentials.email.clone(),
-                    success_count: e.success_count,
-                    last_used_at: e.last_used_at.clone(),
-                    has_proxy: e.credentials.proxy_url.is_some(),
-                    proxy_url: e.credentials.proxy_url.clone(),
-                })
-                .collect(),
-            current_id,
-            total: entries.len(),
-            available,
+    /// 需要回写时返回凭据文件路径
+    fn persist_target(&self) -> Option<&Path> {
+        // 仅多凭据格式才回写
+        if !self.is_multiple_format.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        // 从实例不回写，凭据文件由主实例维护
+        if self.is_secondary() {
+            tracing::debug!("从实例模式，跳过凭据回写");
+            return None;
         }
+
+        self.credentials_path.as_deref()
     }
 
-    /// 设置凭据禁用状态（Admin API）
-    pub fn set_disabled(&self, id: u64, disabled: bool) -> anyhow::Result<()> {
-        {
-            let mut entries = self.entries.lock();
-            let entry = entries
-                .iter_mut()
-                .find(|e| e.id == id)
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
-            entry.disabled = disabled;
-            if !disabled {
-                // 启用时重置失败计数
-                entry.failure_count = 0;
-                entry.disabled_reason = None;
-            } else {
-                entry.disabled_reason = Some(DisabledReason::Manual);
-            }
-        }
-        // 持久化更改
-        self.persist_credentials()?;
-        Ok(())
+    /// 获取缓存目录（凭据文件所在目录）
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        self.credentials_path
+            .as_ref()
+            .and_then(|p| p.parent().map(|d| d.to_path_buf()))
     }
 
-    /// 设置凭据优先级（Admin API）
-    ///
-    /// 修改优先级后会立即按新优先级重新选择当前凭据。
-    /// 即使持久化失败，内存中的优先级和当前凭据选择也会生效。
-    pub fn set_priority(&self, id: u64, priority: u32) -> anyhow::Result<()> {
-        {
-            let mut entries = self.entries.lock();
-            let entry = entries
-                .iter_mut()
-                .find(|e| e.id == id)
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
-            entry.credentials.priority = priority;
-        }
-        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
-        self.select_highest_priority();
-        // 持久化更改
-        self.persist_credentials()?;
-        Ok(())
+    /// 统计数据文件路径
+    fn stats_path(&self) -> Option<PathBuf> {
+        self.cache_dir().map(|d| d.join("kiro_stats.json"))
     }
 
-    /// 重置凭据失败计数并重新启用（Admin API）
-    pub fn reset_and_enable(&self, id: u64) -> anyhow::Result<()> {
-        {
-            let mut entries = self.entries.lock();
-            let entry = entries
-                .iter_mut()
-                .find(|e| e.id == id)
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
-            entry.failure_count = 0;
-            entry.disabled = false;
-            entry.disabled_reason = None;
+    /// 从磁盘加载统计数据并应用到当前条目
+    fn load_stats(&self) {
+        let path = match self.stats_path() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return, // 首次运行时文件不存在
+        };
+
+        let stats: HashMap<String, StatsEntry> = match serde_json::from_str(&content) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("解析统计缓存失败，将忽略: {}", e);
+                return;
+            }
+        };
+
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if let Some(s) = stats.get(&entry.id.to_string()) {
+                entry.success_count = s.success_count;
+                entry.last_used_at = s.last_used_at.clone();
+                entry.estimated_cost = s.estimated_cost;
+                entry.monthly_requests = s.monthly_requests.clone();
+            }
         }
-        // 持久化更改
-        self.persist_credentials()?;
-        Ok(())
+        drop(entries);
+        self.apply_monthly_limits(Utc::now());
+        *self.last_stats_save_at.lock() = Some(Instant::now());
+        self.stats_dirty.store(false, Ordering::Relaxed);
+        tracing::info!("已从缓存加载 {} 条统计数据", stats.len());
     }
 
-    /// 获取指定凭据的使用额度（Admin API）
-    pub async fn get_usage_limits_for(&self, id: u64) -> anyhow::Result<UsageLimitsResponse> {
-        let credentials = {
-            let entries = self.entries.lock();
-            entries
-                .iter()
-                .find(|e| e.id == id)
-                .map(|e| e.credentials.clone())
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
-        };
+    /// 刷新记录文件路径
+    fn refresh_history_path(&self) -> Option<PathBuf> {
+        self.cache_dir()
+            .map(|d| d.join("kiro_refresh_history.json"))
+    }
 
-        // 检查是否需要刷新 token
-        let needs_refresh = is_token_expired(&credentials) || is_token_expiring_soon(&credentials);
+    /// 从磁盘加载 Token 刷新记录
+    fn load_refresh_history(&self) {
+        let Some(path) = self.refresh_history_path() else {
+            return;
+        };
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return;
+        };
 
-        let token = if needs_refresh {
-            let _guard = self.refresh_lock.lock().await;
-            let current_creds = {
-                let entries = self.entries.lock();
-                entries
-                    .iter()
-                    .find(|e| e.id == id)
-                    .map(|e| e.credentials.clone())
-                    .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+        let history: HashMap<String, VecDeque<RefreshAttempt>> =
+            match serde_json::from_str(&content) {
+                Ok(h) => h,
+                Err(e) => {
+                    tracing::warn!("解析刷新记录失败，将忽略: {}", e);
+                    return;
+                }
             };
 
-            if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
-                let effective_proxy = current_creds.effective_proxy(self.proxy.as_ref());
-                let new_creds =
-                    refresh_token(&current_creds, &self.config, effective_proxy.as_ref()).await?;
-                {
-                    let mut entries = self.entries.lock();
-                    if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
-                        entry.credentials = new_creds.clone();
-                    }
-                }
-                // 持久化失败只记录警告，不影响本次请求
-                if let Err(e) = self.persist_credentials() {
-                    tracing::warn!("Token 刷新后持久化失败（不影响本次请求）: {}", e);
-                }
-                new_creds
-                    .access_token
-                    .ok_or_else(|| anyhow::anyhow!("刷新后无 access_token"))?
-            } else {
-                current_creds
-                    .access_token
-                    .ok_or_else(|| anyhow::anyhow!("凭据无 access_token"))?
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if let Some(records) = history.get(&entry.id.to_string()) {
+                let skip = records.len().saturating_sub(REFRESH_HISTORY_CAPACITY);
+                entry.refresh_history = records.iter().skip(skip).cloned().collect();
             }
-        } else {
-            credentials
-                .access_token
-                .ok_or_else(|| anyhow::anyhow!("凭据无 access_token"))?
+        }
+    }
+
+    /// 将 Token 刷新记录持久化到磁盘
+    fn save_refresh_history(&self) {
+        let Some(path) = self.refresh_history_path() else {
+            return;
         };
 
-        let credentials = {
+        let history: HashMap<String, VecDeque<RefreshAttempt>> = {
             let entries = self.entries.lock();
             entries
                 .iter()
-                .find(|e| e.id == id)
-                .map(|e| e.credentials.clone())
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+                .filter(|e| !e.refresh_history.is_empty())
+                .map(|e| (e.id.to_string(), e.refresh_history.clone()))
+                .collect()
         };
 
-        let effective_proxy = credentials.effective_proxy(self.proxy.as_ref());
-        let usage_limits = get_usage_limits(&credentials, &self.config, &token, effective_proxy.as_ref()).await?;
-
-        // 更新订阅等级到凭据（仅在发生变化时持久化）
-        if let Some(subscription_title) = usage_limits.subscription_title() {
-            let changed = {
-                let mut entries = self.entries.lock();
-                if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
-                    let old_title = entry.credentials.subscription_title.clone();
-                    if old_title.as_deref() != Some(subscription_title) {
-                        entry.credentials.subscription_title =
-                            Some(subscription_title.to_string());
-                        tracing::info!(
-                            "凭据 #{} 订阅等级已更新: {:?} -> {}",
-                            id,
-                            old_title,
-                            subscription_title
-                        );
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            };
-
-            if changed {
-                if let Err(e) = self.persist_credentials() {
-                    tracing::warn!("订阅等级更新后持久化失败（不影响本次请求）: {}", e);
+        match serde_json::to_string_pretty(&history) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    tracing::warn!("保存刷新记录失败: {}", e);
                 }
             }
+            Err(e) => tracing::warn!("序列化刷新记录失败: {}", e),
         }
-
-        Ok(usage_limits)
     }
 
-    /// 添加新凭据（Admin API）
-    ///
-    /// # 流程
-    /// 1. 验证凭据基本字段（refresh_token 不为空）
-    /// 2. 基于 refreshToken 的 SHA-256 哈希检测重复
-    /// 3. 尝试刷新 Token 验证凭据有效性
-    /// 4. 分配新 ID（当前最大 ID + 1）
-    /// 5. 添加到 entries 列表
-    /// 6. 持久化到配置文件
-    ///
-    /// # 返回
-    /// - `Ok(u64)` - 新凭据 ID
-    /// - `Err(_)` - 验证失败或添加失败
-    pub async fn add_credential(&self, new_cred: KiroCredentials) -> anyhow::Result<u64> {
-        // 1. 基本验证
-        validate_refresh_token(&new_cred)?;
-
-        // 2. 基于 refreshToken 的 SHA-256 哈希检测重复
-        let new_refresh_token = new_cred
-            .refresh_token
-            .as_deref()
-            .ok_or_else(|| anyhow::anyhow!("缺少 refreshToken"))?;
-        let new_refresh_token_hash = sha256_hex(new_refresh_token);
-        let duplicate_exists = {
+    /// 序列化当前统计数据，返回 (文件路径, JSON)
+    fn stats_snapshot_json(&self) -> Option<(PathBuf, String)> {
+        let path = self.stats_path()?;
+        let stats: HashMap<String, StatsEntry> = {
             let entries = self.entries.lock();
-            entries.iter().any(|entry| {
-                entry
-                    .credentials
-                    .refresh_token
-                    .as_deref()
-                    .map(sha256_hex)
-                    .as_deref()
-                    == Some(new_refresh_token_hash.as_str())
-            })
+            entries
+                .iter()
+                .map(|e| {
+                    (
+                        e.id.to_string(),
+                        StatsEntry {
+                            success_count: e.success_count,
+                            last_used_at: e.last_used_at.clone(),
+                            estimated_cost: e.estimated_cost,
+                            monthly_requests: e.monthly_requests.clone(),
+                        },
+                    )
+                })
+                .collect()
         };
-        if duplicate_exists {
-            anyhow::bail!("凭据已存在（refreshToken 重复）");
-        }
 
-        // 3. 尝试刷新 Token 验证凭据有效性
-        let effective_proxy = new_cred.effective_proxy(self.proxy.as_ref());
-        let mut validated_cred =
-            refresh_token(&new_cred, &self.config, effective_proxy.as_ref()).await?;
+        match serde_json::to_string_pretty(&stats) {
+            Ok(json) => Some((path, json)),
+            Err(e) => {
+                tracing::warn!("序列化统计数据失败: {}", e);
+                None
+            }
+        }
+    }
 
-        // 4. 分配新 ID
-        let new_id = {
-            let entries = self.entries.lock();
-            entries.iter().map(|e| e.id).max().unwrap_or(0) + 1
+    /// 将当前统计数据持久化到磁盘
+    ///
+    /// 在 Tokio 运行时内以阻塞任务写入，避免阻塞工作线程；
+    /// 运行时外（如 Drop）回退到 [`Self::save_stats_sync`]
+    fn save_stats(&self) {
+        if self.is_secondary() {
+            return;
+        }
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            self.save_stats_sync();
+            return;
         };
 
-        // 5. 设置 ID 并保留用户输入的元数据
-        validated_cred.id = Some(new_id);
-        validated_cred.priority = new_cred.priority;
-        validated_cred.auth_method = new_cred.auth_method.map(|m| {
-            if m.eq_ignore_ascii_case("builder-id") || m.eq_ignore_ascii_case("iam") {
-                "idc".to_string()
-            } else {
-                m
+        let (seq, files) = self.snapshot_stats();
+        let writer = self.stats_writer.clone();
+        let dirty = self.stats_dirty.clone();
+        handle.spawn_blocking(move || {
+            if !writer.write(seq, files) {
+                dirty.store(true, Ordering::Relaxed);
             }
         });
-        validated_cred.client_id = new_cred.client_id;
-        validated_cred.client_secret = new_cred.client_secret;
-        validated_cred.region = new_cred.region;
-        validated_cred.auth_region = new_cred.auth_region;
-        validated_cred.api_region = new_cred.api_region;
-        validated_cred.machine_id = new_cred.machine_id;
-        validated_cred.email = new_cred.email;
-        validated_cred.proxy_url = new_cred.proxy_url;
-        validated_cred.proxy_username = new_cred.proxy_username;
-        validated_cred.proxy_password = new_cred.proxy_password;
+    }
 
-        {
-            let mut entries = self.entries.lock();
-            entries.push(CredentialEntry {
-                id: new_id,
-                credentials: validated_cred,
-                failure_count: 0,
-                disabled: false,
-                disabled_reason: None,
-                success_count: 0,
-                last_used_at: None,
-            });
-        }
+    /// 清除待写入标记并生成统计数据与用户用量的快照
+    ///
+    /// 先清除标记再生成快照：生成快照之后的更新会重新标记，不会因写入完成而丢失
+    fn snapshot_stats(&self) -> (u64, StatsFiles) {
+        self.stats_dirty.store(false, Ordering::Relaxed);
+        *self.last_stats_save_at.lock() = Some(Instant::now());
+        self.stats_writer.snapshot(|| {
+            [
+                ("统计缓存", self.stats_snapshot_json()),
+                ("用户用量统计", self.user_usage.snapshot_json()),
+            ]
+        })
+    }
 
-        // 6. 持久化
-        self.persist_credentials()?;
+    /// 同步将当前统计数据持久化到磁盘（用于 Drop 及需要立即落盘的管理操作）
+    fn save_stats_sync(&self) {
+        if self.is_secondary() {
+            return;
+        }
+        let (seq, files) = self.snapshot_stats();
+        if !self.stats_writer.write(seq, files) {
+            self.stats_dirty.store(true, Ordering::Relaxed);
+        }
+    }
 
-        tracing::info!("成功添加凭据 #{}", new_id);
-        Ok(new_id)
+    /// 记录一次来自指定用户的请求（超过 userLimits 配置的当日上限时拒绝）
+    pub fn admit_user_request(&self, user: &str) -> Result<(), UserLimitExceeded> {
+        let limit = self.config.user_limits.get(user).copied();
+        self.user_usage.try_admit(user, limit, Utc::now())?;
+        self.save_stats_debounced();
+        Ok(())
     }
 
-    /// 删除凭据（Admin API）
-    ///
-    /// # 前置条件
-    /// - 凭据必须已禁用（disabled = true）
+    /// 累加本次请求的 token 用量与估算费用（费用同时计入用户与处理请求的凭据）
+    pub fn record_usage(
+        &self,
+        user: &str,
+        credential_id: Option<u64>,
+        model: &str,
+        input_tokens: i32,
+        output_tokens: i32,
+    ) {
+        let input_tokens = input_tokens.max(0) as u64;
+        let output_tokens = output_tokens.max(0) as u64;
+        let cost = pricing::estimate_cost(&self.config.pricing, model, input_tokens, output_tokens);
+
+        self.user_usage
+            .record_tokens(user, input_tokens, output_tokens, cost.unwrap_or(0.0));
+        if let (Some(cost), Some(id)) = (cost, credential_id) {
+            let mut entries = self.entries.lock();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.estimated_cost += cost;
+            }
+        }
+        self.save_stats_debounced();
+    }
+
+    /// 是否配置了 pricing（未配置时不输出费用字段）
+    fn cost_tracking_enabled(&self) -> bool {
+        !self.config.pricing.is_empty()
+    }
+
+    /// 获取所有用户的用量统计
+    pub fn user_usage(&self) -> Vec<UserUsageSnapshot> {
+        self.user_usage.snapshot(
+            &self.config.user_limits,
+            self.cost_tracking_enabled(),
+            Utc::now(),
+        )
+    }
+
+    /// 标记统计数据已更新，并按 debounce 策略决定是否立即落盘
+    fn save_stats_debounced(&self) {
+        self.stats_dirty.store(true, Ordering::Relaxed);
+
+        let should_flush = {
+            let last = *self.last_stats_save_at.lock();
+            match last {
+                Some(last_saved_at) => last_saved_at.elapsed() >= STATS_SAVE_DEBOUNCE,
+                None => true,
+            }
+        };
+
+        if should_flush {
+            self.save_stats();
+        }
+    }
+
+    /// 导出统计数据（用于迁移到新实例）
     ///
-    /// # 行为
-    /// 1. 验证凭据存在
-    /// 2. 验证凭据已禁用
-    /// 3. 从 entries 移除
-    /// 4. 如果删除的是当前凭据，切换到优先级最高的可用凭据
-    /// 5. 如果删除后没有凭据，将 current_id 重置为 0
-    /// 6. 持久化到文件
+    /// 没有 refreshToken 的凭据无法在其他实例中匹配，不会被导出
+    pub fn export_stats(&self) -> StatsExport {
+        let entries = self.entries.lock();
+        StatsExport {
+            version: STATS_EXPORT_VERSION,
+            entries: entries
+                .iter()
+                .filter_map(|e| {
+                    let hash = e.credentials.refresh_token.as_deref().map(sha256_hex)?;
+                    Some(StatsExportEntry {
+                        id: e.id,
+                        refresh_token_hash: hash,
+                        success_count: e.success_count,
+                        last_used_at: e.last_used_at.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// 导入统计数据
     ///
-    /// # 返回
-    /// - `Ok(())` - 删除成功
-    /// - `Err(_)` - 凭据不存在、未禁用或持久化失败
-    pub fn delete_credential(&self, id: u64) -> anyhow::Result<()> {
-        let was_current = {
+    /// 按 refreshToken 哈希匹配凭据（不同实例间 ID 可能不同）。
+    /// 已有统计不会被回退：success_count 与 last_used_at 均取较大值。
+    ///
+    /// # Returns
+    /// 成功匹配并合并的条目数
+    pub fn import_stats(&self, export: StatsExport) -> anyhow::Result<usize> {
+        if export.version != STATS_EXPORT_VERSION {
+            bail!(
+                "不支持的统计导出版本: {}（当前支持 {}）",
+                export.version,
+                STATS_EXPORT_VERSION
+            );
+        }
+
+        let imported: HashMap<&str, &StatsExportEntry> = export
+            .entries
+            .iter()
+            .map(|e| (e.refresh_token_hash.as_str(), e))
+            .collect();
+
+        let matched = {
             let mut entries = self.entries.lock();
+            let mut matched = 0;
+            for entry in entries.iter_mut() {
+                let hash = match entry.credentials.refresh_token.as_deref() {
+                    Some(token) => sha256_hex(token),
+                    None => continue,
+                };
+                let Some(stats) = imported.get(hash.as_str()) else {
+                    continue;
+                };
 
-            // 查找凭据
-            let entry = entries
+                entry.success_count = entry.success_count.max(stats.success_count);
+                entry.last_used_at =
+                    later_rfc3339(entry.last_used_at.take(), stats.last_used_at.clone());
+                matched += 1;
+            }
+            matched
+        };
+
+        if matched > 0 {
+            self.save_stats_sync();
+        }
+        tracing::info!(
+            "已导入统计数据：{} 条中匹配 {} 条",
+            export.entries.len(),
+            matched
+        );
+        Ok(matched)
+    }
+
+    /// 导出可迁移的运行时状态（只读取内存，不发起任何上游调用）
+    ///
+    /// 加锁顺序与 [`snapshot`](Self::snapshot) 一致：entries → current_id → refresh_limiter
+    pub fn export_state(&self) -> TokenManagerState {
+        let entries = self.entries.lock();
+        let current_id = *self.current_id.lock();
+        let limiter = self.refresh_limiter.lock();
+        let now = Instant::now();
+        TokenManagerState {
+            current_id,
+            load_balancing_mode: self.get_load_balancing_mode(),
+            credentials: entries
                 .iter()
-                .find(|e| e.id == id)
-                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+                .map(|e| CredentialRuntimeState {
+                    id: e.id,
+                    refresh_token_hash: e.credentials.refresh_token.as_deref().map(sha256_hex),
+                    failure_count: e.failure_count,
+                    disabled_reason: e.disabled.then_some(e.disabled_reason).flatten(),
+                    success_count: e.success_count,
+                    last_used_at: e.last_used_at.clone(),
+                    estimated_cost: e.estimated_cost,
+                    refresh_history: e.refresh_history.iter().cloned().collect(),
+                    recent_outcomes: e.recent_outcomes.iter().copied().collect(),
+                    last_latency_ms: e.last_latency_ms,
+                    failure_counts: e.failure_counts,
+                    refresh_limit: limiter.export_state(e.id, now),
+                    monthly_requests: e.monthly_requests.clone(),
+                })
+                .collect(),
+        }
+    }
 
-            // 检查是否已禁用
-            if !entry.disabled {
-                anyhow::bail!("只能删除已禁用的凭据（请先禁用凭据 #{}）", id);
+    /// 导入其他实例导出的运行时状态
+    ///
+    /// 按凭据 ID 匹配，ID 不存在或 refreshToken 哈希不一致的条目被跳过。
+    /// 成功次数与最后使用时间取较大值，其余状态以导入数据为准；
+    /// 自动禁用（失败过多、额度用尽）随状态迁移，手动禁用以凭据文件为准。
+    /// 负载均衡模式只在内存中生效（配置文件由导出方维护）。
+    pub fn import_state(&self, state: TokenManagerState) -> StateImportOutcome {
+        let mut outcome = StateImportOutcome::default();
+        let now = Instant::now();
+        {
+            let mut entries = self.entries.lock();
+            let mut current_id = self.current_id.lock();
+            let mut limiter = self.refresh_limiter.lock();
+            for imported in &state.credentials {
+                let Some(entry) = entries.iter_mut().find(|e| e.id == imported.id) else {
+                    outcome.skipped += 1;
+                    continue;
+                };
+                let local_hash = entry.credentials.refresh_token.as_deref().map(sha256_hex);
+                if local_hash.is_some()
+                    && imported.refresh_token_hash.is_some()
+                    && local_hash != imported.refresh_token_hash
+                {
+                    tracing::warn!(
+                        "凭据 #{} 的 refreshToken 与导出方不一致，跳过其运行时状态",
+                        imported.id
+                    );
+                    outcome.skipped += 1;
+                    continue;
+                }
+
+                entry.failure_count = imported.failure_count;
+                match imported.disabled_reason {
+                    Some(DisabledReason::Manual) => {}
+                    Some(reason) => {
+                        entry.disabled = true;
+                        entry.disabled_reason = Some(reason);
+                    }
+                    None if entry.disabled_reason != Some(DisabledReason::Manual) => {
+                        entry.disabled = false;
+                        entry.disabled_reason = None;
+                    }
+                    None => {}
+                }
+                entry.success_count = entry.success_count.max(imported.success_count);
+                entry.last_used_at =
+                    later_rfc3339(entry.last_used_at.take(), imported.last_used_at.clone());
+                entry.estimated_cost = imported.estimated_cost;
+                entry.refresh_history = imported.refresh_history.iter().cloned().collect();
+                while entry.refresh_history.len() > REFRESH_HISTORY_CAPACITY {
+                    entry.refresh_history.pop_front();
+                }
+                entry.recent_outcomes = imported.recent_outcomes.iter().copied().collect();
+                while entry.recent_outcomes.len() > RECENT_OUTCOMES_CAPACITY {
+                    entry.recent_outcomes.pop_front();
+                }
+                entry.last_latency_ms = imported.last_latency_ms;
+                entry.failure_counts = imported.failure_counts;
+                entry.monthly_requests = imported.monthly_requests.clone();
+                match &imported.refresh_limit {
+                    Some(refresh_limit) => limiter.import_state(entry.id, refresh_limit, now),
+                    None => limiter.remove(entry.id),
+                }
+                outcome.matched += 1;
             }
 
-            // 记录是否是当前凭据
-            let current_id = *self.current_id.lock();
-            let was_current = current_id == id;
+            if entries
+                .iter()
+                .any(|e| e.id == state.current_id && !e.disabled)
+            {
+                *current_id = state.current_id;
+            }
+        }
 
-            // 删除凭据
-            entries.retain(|e| e.id != id);
+        if LOAD_BALANCING_MODES.contains(&state.load_balancing_mode.as_str()) {
+            *self.load_balancing_mode.lock() = state.load_balancing_mode;
+        }
+        if outcome.matched > 0 {
+            self.save_stats_debounced();
+        }
+        tracing::info!(
+            "已导入运行时状态：{} 个凭据中匹配 {} 个，跳过 {} 个",
+            state.credentials.len(),
+            outcome.matched,
+            outcome.skipped
+        );
+        outcome
+    }
 
-            was_current
+    /// 报告 API 调用成功
+    ///
+    /// 重置该凭据的失败计数，并在同一次加锁中记录本次调用耗时
+    ///
+    /// # Arguments
+    /// * `ctx` - 本次调用的上下文
+    /// * `latency_ms` - 调用耗时（毫秒，自发出请求到收到响应头，不含 Token 刷新）
+    pub fn report_success(&self, ctx: &CallContext, latency_ms: u64) {
+        self.record_success(ctx.id, Some(latency_ms), Utc::now());
+    }
+
+    /// 所有凭据均被自动禁用时，按 autoRecoverAllDisabled 策略做一次类似重启的自愈
+    ///
+    /// 重置自动禁用凭据的失败计数并重新启用，返回是否执行了自愈：
+    /// - `always`：每次都执行
+    /// - `with-backoff`：距上次自愈不足最小间隔时跳过，间隔随连续自愈次数翻倍
+    ///   （[`SELF_HEAL_BACKOFF_BASE`] 起，最多 [`SELF_HEAL_BACKOFF_MAX`]）
+    /// - `never`：从不执行
+    fn try_self_heal(&self, now: DateTime<Utc>) -> bool {
+        let mut entries = self.entries.lock();
+        let auto_disabled = |e: &CredentialEntry| {
+            e.disabled && e.disabled_reason == Some(DisabledReason::TooManyFailures)
         };
+        if !entries.iter().any(auto_disabled) {
+            return false;
+        }
 
-        // 如果删除的是当前凭据，切换到优先级最高的可用凭据
-        if was_current {
-            self.select_highest_priority();
+        let mut state = self.self_heal.lock();
+        match self.config.auto_recover_all_disabled {
+            AutoRecoverMode::Always => {}
+            AutoRecoverMode::WithBackoff => {
+                if let Some(last_at) = state.last_at {
+                    let interval = self_heal_backoff(state.streak);
+                    let elapsed = (now - last_at).to_std().unwrap_or_default();
+                    if elapsed < interval {
+                        log_throttled!(
+                            warn,
+                            "self_heal_backoff",
+                            DEFAULT_LOG_THROTTLE_INTERVAL,
+                            "所有凭据均已被自动禁用，距上次自愈不足 {} 秒，暂不自愈",
+                            interval.as_secs()
+                        );
+                        return false;
+                    }
+                }
+            }
+            AutoRecoverMode::Never => {
+                log_throttled!(
+                    warn,
+                    "self_heal_never",
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
+                    "所有凭据均已被自动禁用，autoRecoverAllDisabled 为 never，不执行自愈"
+                );
+                return false;
+            }
         }
 
-        // 如果删除后没有任何凭据，将 current_id 重置为 0（与初始化行为保持一致）
-        {
-            let entries = self.entries.lock();
-            if entries.is_empty() {
-                let mut current_id = self.current_id.lock();
-                *current_id = 0;
-                tracing::info!("所有凭据已删除，current_id 已重置为 0");
+        tracing::warn!("所有凭据均已被自动禁用，执行自愈：重置失败计数并重新启用（等价于重启）");
+        for e in entries.iter_mut().filter(|e| auto_disabled(e)) {
+            e.disabled = false;
+            e.disabled_reason = None;
+            e.failure_count = 0;
+        }
+        state.last_at = Some(now);
+        state.streak = state.streak.saturating_add(1);
+        true
+    }
+
+    /// 报告指定凭据 API 调用成功（不记录耗时）
+    #[deprecated(note = "使用 report_success(ctx, latency_ms)，在同一次加锁中记录耗时")]
+    pub fn report_success_by_id(&self, id: u64) {
+        self.record_success(id, None, Utc::now());
+    }
+
+    fn record_success(&self, id: u64, latency_ms: Option<u64>, now: DateTime<Utc>) {
+        let event = {
+            let mut entries = self.entries.lock();
+            match entries.iter_mut().find(|e| e.id == id) {
+                Some(entry) => {
+                    entry.failure_count = 0;
+                    entry.success_count += 1;
+                    entry.last_used_at = Some(now.to_rfc3339());
+                    entry.last_success_at = Some(Instant::now());
+                    if latency_ms.is_some() {
+                        entry.last_latency_ms = latency_ms;
+                    }
+                    entry.record_outcome(true);
+                    tracing::debug!(
+                        "凭据 #{} API 调用成功（累计 {} 次）",
+                        id,
+                        entry.success_count
+                    );
+                    entry.record_monthly_request(now)
+                }
+                None => None,
+            }
+        };
+        self.self_heal.lock().streak = 0;
+        if let Some(event) = event {
+            self.push_event(event);
+        }
+        self.balance_cache.record_usage(id);
+        self.save_stats_debounced();
+    }
+
+    /// 按当前时间重新评估本地月度硬上限
+    ///
+    /// 跨月（或上限调高）后恢复因 `LocalLimitReached` 暂停的凭据；
+    /// 仍可用但已达到硬上限的凭据（如从统计数据恢复计数后）暂停使用
+    fn apply_monthly_limits(&self, now: DateTime<Utc>) {
+        let events: Vec<AdminEvent> = {
+            let mut entries = self.entries.lock();
+            entries
+                .iter_mut()
+                .filter_map(|entry| {
+                    let reached = entry.monthly_hard_limit_reached(now);
+                    match entry.disabled_reason {
+                        Some(DisabledReason::LocalLimitReached) if entry.disabled && !reached => {
+                            entry.disabled = false;
+                            entry.disabled_reason = None;
+                            Some(AdminEvent {
+                                at: now.to_rfc3339(),
+                                credential_id: entry.id,
+                                kind: AdminEventKind::LimitCleared,
+                                message: format!(
+                                    "凭据 #{} 当月（{}）请求次数 {} 低于本地硬上限，已恢复使用",
+                                    entry.id,
+                                    entry.current_month(now),
+                                    entry.monthly_count(now)
+                                ),
+                            })
+                        }
+                        _ if reached && !entry.disabled => Some(entry.pause_for_monthly_limit(now)),
+                        _ => None,
+                    }
+                })
+                .collect()
+        };
+        for event in events {
+            self.push_event(event);
+        }
+    }
+
+    /// 记录一条管理事件（同时输出日志）
+    fn push_event(&self, event: AdminEvent) {
+        match event.kind {
+            AdminEventKind::LimitCleared => tracing::info!("{}", event.message),
+            _ => tracing::warn!("{}", event.message),
+        }
+        let mut events = self.events.lock();
+        events.push_back(event);
+        while events.len() > ADMIN_EVENTS_CAPACITY {
+            events.pop_front();
+        }
+    }
+
+    /// 获取最近的管理事件（按时间先后排列，Admin API）
+    pub fn events(&self) -> Vec<AdminEvent> {
+        self.events.lock().iter().cloned().collect()
+    }
+
+    /// 报告指定凭据 API 调用失败
+    ///
+    /// 按失败类型（见 [`FailureKind`]）累加分类计数并决定是否计入失败次数：
+    /// - 计入失败次数的类型连续达到阈值时禁用凭据，并切换到优先级最高的可用凭据
+    /// - `FailureKind::UpstreamServer`：不计入失败次数，切换到下一个可用凭据（若存在）
+    ///
+    /// 返回是否还有可用凭据可以重试
+    ///
+    /// # Arguments
+    /// * `ctx` - 本次调用的上下文
+    /// * `kind` - 失败类型
+    pub fn report_failure(&self, ctx: &CallContext, kind: FailureKind) -> FailureOutcome {
+        self.record_failure(ctx.id, kind)
+    }
+
+    /// 报告指定凭据 API 调用失败
+    #[deprecated(note = "使用 report_failure(ctx, kind)")]
+    pub fn report_failure_by_id(&self, id: u64, kind: FailureKind) -> FailureOutcome {
+        self.record_failure(id, kind)
+    }
+
+    fn record_failure(&self, id: u64, kind: FailureKind) -> FailureOutcome {
+        let action = {
+            let mut entries = self.entries.lock();
+            let now = Instant::now();
+            let peers_succeeding = entries.iter().any(|e| {
+                e.id != id
+                    && !e.disabled
+                    && e.last_success_at
+                        .is_some_and(|t| now.duration_since(t) <= NETWORK_PEER_SUCCESS_WINDOW)
+            });
+            let Some(entry) = entries.iter_mut().find(|e| e.id == id) else {
+                return FailureOutcome {
+                    has_more: entries.iter().any(|e| !e.disabled),
+                };
+            };
+            entry.failure_counts.record(kind);
+            failure_action(kind, self.config.auth_failure_threshold, peers_succeeding)
+        };
+
+        match action {
+            FailureAction::Ignore => FailureOutcome {
+                has_more: self.entries.lock().iter().any(|e| !e.disabled),
+            },
+            FailureAction::Switch => self.report_transient_failure(id),
+            FailureAction::Count { threshold } => self.count_failure(id, threshold),
+        }
+    }
+
+    /// 计入一次失败，连续失败达到 `threshold` 时禁用凭据（内部方法）
+    fn count_failure(&self, id: u64, threshold: u32) -> FailureOutcome {
+        let (result, disabled_now) = {
+            let mut entries = self.entries.lock();
+            let mut current_id = self.current_id.lock();
+
+            let entry = match entries.iter_mut().find(|e| e.id == id) {
+                Some(e) => e,
+                None => {
+                    return FailureOutcome {
+                        has_more: entries.iter().any(|e| !e.disabled),
+                    };
+                }
+            };
+
+            entry.failure_count += 1;
+            entry.last_used_at = Some(Utc::now().to_rfc3339());
+            entry.record_outcome(false);
+            let failure_count = entry.failure_count;
+
+            log_throttled!(
+                warn,
+                format!("credential_failure:{}", id),
+                DEFAULT_LOG_THROTTLE_INTERVAL,
+                "凭据 #{} API 调用失败（{}/{}）",
+                id,
+                failure_count,
+                threshold
+            );
+
+            let disabled_now = failure_count >= threshold;
+            if disabled_now {
+                entry.disabled = true;
+                entry.disabled_reason = Some(DisabledReason::TooManyFailures);
+                tracing::error!("凭据 #{} 已连续失败 {} 次，已被禁用", id, failure_count);
+
+                // 切换到优先级最高的可用凭据
+                if let Some(next) = entries
+                    .iter()
+                    .filter(|e| !e.disabled)
+                    .min_by_key(|e| e.credentials.sort_key())
+                {
+                    *current_id = next.id;
+                    tracing::info!(
+                        "已切换到凭据 #{}（优先级 {}）",
+                        next.id,
+                        next.credentials.priority
+                    );
+                } else {
+                    log_throttled!(
+                        error,
+                        "all_credentials_disabled",
+                        DEFAULT_LOG_THROTTLE_INTERVAL,
+                        "所有凭据均已禁用！"
+                    );
+                }
             }
+
+            (entries.iter().any(|e| !e.disabled), disabled_now)
+        };
+        if disabled_now {
+            self.invalidate_usage_cache(id);
+        }
+        self.save_stats_debounced();
+        FailureOutcome { has_more: result }
+    }
+
+    /// 报告上游瞬态错误（内部方法）
+    ///
+    /// 瞬态错误与凭据本身无关，不计入失败次数（避免上游抖动把所有凭据锁死），
+    /// 仅在存在其他可用凭据时切换过去，让下一次重试换一个凭据
+    fn report_transient_failure(&self, id: u64) -> FailureOutcome {
+        let entries = self.entries.lock();
+        let mut current_id = self.current_id.lock();
+
+        let next = entries
+            .iter()
+            .filter(|e| !e.disabled && e.id != id)
+            .min_by_key(|e| e.credentials.sort_key());
+        if let Some(next) = next.filter(|_| *current_id == id) {
+            *current_id = next.id;
+            tracing::info!(
+                "凭据 #{} 遇到上游瞬态错误，已切换到凭据 #{}（优先级 {}）",
+                id,
+                next.id,
+                next.credentials.priority
+            );
+        }
+
+        FailureOutcome {
+            has_more: entries.iter().any(|e| !e.disabled),
+        }
+    }
+
+    /// 报告指定凭据额度已用尽
+    ///
+    /// 用于处理 402 Payment Required 且 reason 为 `MONTHLY_REQUEST_COUNT` 的场景，
+    /// 以及查询使用额度时发现额度已用尽的场景：
+    /// - 立即禁用该凭据（不等待连续失败阈值），记录禁用时的使用比例（`usage_percentage`，1.0 表示 100%）
+    /// - 切换到下一个可用凭据继续重试
+    /// - 返回是否还有可用凭据
+    pub fn report_quota_exhausted(&self, id: u64, usage_percentage: f64) -> bool {
+        self.invalidate_usage_cache(id);
+        let result = {
+            let mut entries = self.entries.lock();
+            let mut current_id = self.current_id.lock();
+
+            let entry = match entries.iter_mut().find(|e| e.id == id) {
+                Some(e) => e,
+                None => return entries.iter().any(|e| !e.disabled),
+            };
+
+            if entry.disabled {
+                return entries.iter().any(|e| !e.disabled);
+            }
+
+            entry.disabled = true;
+            entry.disabled_reason = Some(DisabledReason::QuotaExceeded);
+            entry.disabled_at_quota = Some(usage_percentage);
+            entry.last_used_at = Some(Utc::now().to_rfc3339());
+            entry.record_outcome(false);
+            // 设为阈值，便于在管理面板中直观看到该凭据已不可用
+            entry.failure_count = MAX_FAILURES_PER_CREDENTIAL;
+
+            tracing::error!("凭据 #{} 额度已用尽（MONTHLY_REQUEST_COUNT），已被禁用", id);
+            self.balance_cache.mark_exhausted(id);
+
+            // 切换到优先级最高的可用凭据
+            if let Some(next) = entries
+                .iter()
+                .filter(|e| !e.disabled)
+                .min_by_key(|e| e.credentials.sort_key())
+            {
+                *current_id = next.id;
+                tracing::info!(
+                    "已切换到凭据 #{}（优先级 {}）",
+                    next.id,
+                    next.credentials.priority
+                );
+                true
+            } else {
+                log_throttled!(
+                    error,
+                    "all_credentials_disabled",
+                    DEFAULT_LOG_THROTTLE_INTERVAL,
+                    "所有凭据均已禁用！"
+                );
+                false
+            }
+        };
+        self.save_stats_debounced();
+        result
+    }
+
+    /// 切换到优先级最高的可用凭据
+    ///
+    /// 返回是否成功切换
+    pub fn switch_to_next(&self) -> bool {
+        let entries = self.entries.lock();
+        let mut current_id = self.current_id.lock();
+
+        // 选择优先级最高的未禁用凭据（排除当前凭据）
+        if let Some(next) = entries
+            .iter()
+            .filter(|e| !e.disabled && e.id != *current_id)
+            .min_by_key(|e| e.credentials.sort_key())
+        {
+            *current_id = next.id;
+            tracing::info!(
+                "已切换到凭据 #{}（优先级 {}）",
+                next.id,
+                next.credentials.priority
+            );
+            true
+        } else {
+            // 没有其他可用凭据，检查当前凭据是否可用
+            entries.iter().any(|e| e.id == *current_id && !e.disabled)
+        }
+    }
+
+    /// 获取使用额度信息（额度已用尽时自动禁用该凭据）
+    pub async fn get_usage_limits(&self) -> anyhow::Result<UsageLimitsResponse> {
+        let ctx = self.acquire_context(None).await?;
+        let effective_proxy = ctx.credentials.effective_proxy(self.proxy.as_ref());
+        let usage_limits = get_usage_limits(
+            &ctx.credentials,
+            &self.config,
+            ctx.identity()?,
+            &ctx.token,
+            effective_proxy.as_ref(),
+        )
+        .await?;
+        self.disable_if_quota_exhausted(ctx.id, &usage_limits);
+        Ok(usage_limits)
+    }
+
+    /// 使用额度查询结果显示额度已用尽时主动禁用凭据，无需等到 API 调用返回 402
+    fn disable_if_quota_exhausted(&self, id: u64, usage_limits: &UsageLimitsResponse) {
+        let resource_type = Some(self.config.usage_resource_type.as_str());
+        if !usage_limits.is_quota_exhausted(resource_type) {
+            return;
+        }
+        let already_disabled = self
+            .entries
+            .lock()
+            .iter()
+            .find(|e| e.id == id)
+            .is_none_or(|e| e.disabled);
+        if already_disabled {
+            return;
+        }
+        let usage_percentage =
+            usage_limits.current_usage(resource_type) / usage_limits.usage_limit(resource_type);
+        tracing::warn!(
+            "凭据 #{} 的 {} 额度已用尽（{:.0}%），主动禁用",
+            id,
+            self.config.usage_resource_type,
+            usage_percentage * 100.0
+        );
+        self.report_quota_exhausted(id, usage_percentage);
+    }
+
+    // ========================================================================
+    // Admin API 方法
+    // ========================================================================
+
+    /// 获取管理器状态快照（用于 Admin API）
+    pub fn snapshot(&self) -> ManagerSnapshot {
+        self.sync_external_edits();
+        self.apply_monthly_limits(Utc::now());
+        let persist_warning = self.persist_guard.lock().conflict().map(str::to_string);
+        let entries = self.entries.lock();
+        let current_id = *self.current_id.lock();
+        let limiter = self.refresh_limiter.lock();
+        let now = Instant::now();
+        let wall_now = Utc::now();
+        let available = entries.iter().filter(|e| !e.disabled).count();
+        let cost_enabled = self.cost_tracking_enabled();
+
+        let health_scores: Vec<f64> = entries
+            .iter()
+            .map(|e| {
+                e.health_factors(self.balance_cache.get_cached(e.id).as_ref(), wall_now)
+                    .score()
+            })
+            .collect();
+        let fleet_health_score = if available == 0 {
+            0.0
+        } else {
+            entries
+                .iter()
+                .zip(&health_scores)
+                .filter(|(e, _)| !e.disabled)
+                .map(|(_, score)| score)
+                .sum::<f64>()
+                / available as f64
+        };
+
+        ManagerSnapshot {
+            entries: entries
+                .iter()
+                .zip(health_scores)
+                .map(|(e, health_score)| CredentialEntrySnapshot {
+                    id: e.id,
+                    priority: e.credentials.priority,
+                    disabled: e.disabled,
+                    disabled_reason: e.disabled.then_some(e.disabled_reason).flatten(),
+                    failure_count: e.failure_count,
+                    auth_method: e
+                        .credentials
+                        .auth_method
+                        .as_ref()
+                        .map(|_| e.credentials.canonical_auth_method().to_string()),
+                    has_profile_arn: e.credentials.profile_arn.is_some(),
+                    expires_at: e.credentials.expires_at.clone(),
+                    refresh_token_hash: e.credentials.refresh_token.as_deref().map(sha256_hex),
+                    email: e.credentials.email.clone(),
+                    success_count: e.success_count,
+                    last_used_at: e.last_used_at.clone(),
+                    has_proxy: e.credentials.proxy_url.is_some(),
+                    proxy_url: e.credentials.proxy_url.clone(),
+                    upstream_base_url: e.credentials.upstream_base_url.clone(),
+                    subscription_title: e.credentials.subscription_title.clone(),
+                    refresh_attempts_last_hour: limiter.attempts_last_hour(e.id, now),
+                    refresh_backoff_secs: limiter.backoff_remaining(e.id, now).map(|d| d.as_secs()),
+                    next_refresh_attempt_at: limiter
+                        .failure_backoff_remaining(e.id, now)
+                        .and_then(|d| chrono::Duration::from_std(d).ok())
+                        .map(|d| (wall_now + d).to_rfc3339()),
+                    health_score,
+                    estimated_cost: cost_enabled.then_some(e.estimated_cost),
+                    last_latency_ms: e.last_latency_ms,
+                    failure_counts: e.failure_counts,
+                    monthly_requests: e.monthly_count(wall_now),
+                    monthly_request_limit: e.credentials.monthly_request_limit.clone(),
+                    refresh_rotations: e.refresh_history.iter().filter(|a| a.rotated).count(),
+                    rotation_unpersisted: e.rotation_unpersisted.is_some(),
+                    quota_exhausted_at_percentage: (e.disabled
+                        && e.disabled_reason == Some(DisabledReason::QuotaExceeded))
+                    .then_some(e.disabled_at_quota)
+                    .flatten(),
+                })
+                .collect(),
+            current_id,
+            total: entries.len(),
+            available,
+            fleet_health_score,
+            total_estimated_cost: cost_enabled
+                .then(|| entries.iter().map(|e| e.estimated_cost).sum()),
+            persist_warning,
+            auto_recover_all_disabled: self.config.auto_recover_all_disabled,
+            last_self_heal_at: self.self_heal.lock().last_at.map(|t| t.to_rfc3339()),
+        }
+    }
+
+    /// 获取指定凭据健康评分的各项因子（Admin API）
+    pub fn health_factors(&self, id: u64) -> anyhow::Result<HealthFactors> {
+        let entries = self.entries.lock();
+        let entry = entries
+            .iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+        Ok(entry.health_factors(self.balance_cache.get_cached(id).as_ref(), Utc::now()))
+    }
+
+    /// 设置凭据禁用状态（Admin API）
+    pub fn set_disabled(&self, id: u64, disabled: bool) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            entry.disabled = disabled;
+            if !disabled {
+                // 启用时重置失败计数
+                entry.failure_count = 0;
+                entry.disabled_reason = None;
+            } else {
+                entry.disabled_reason = Some(DisabledReason::Manual);
+            }
+        }
+        if disabled {
+            self.invalidate_usage_cache(id);
+        }
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 设置凭据优先级（Admin API）
+    ///
+    /// 修改优先级后会立即按新优先级重新选择当前凭据。
+    /// 即使持久化失败，内存中的优先级和当前凭据选择也会生效。
+    pub fn set_priority(&self, id: u64, priority: u32) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            entry.credentials.priority = priority;
+        }
+        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
+        self.select_highest_priority();
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 按给定顺序重排凭据优先级（Admin API）
+    ///
+    /// `ordered_ids` 必须与现有凭据 ID 集合完全一致（无缺失、无多余、无重复），
+    /// 校验通过后按顺序将优先级重写为 0..n，并只持久化一次。
+    pub fn reorder_credentials(&self, ordered_ids: &[u64]) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+
+            let mut seen = std::collections::HashSet::with_capacity(ordered_ids.len());
+            if let Some(dup) = ordered_ids.iter().find(|id| !seen.insert(**id)) {
+                bail!("凭据排序无效: ID #{} 重复", dup);
+            }
+            if let Some(unknown) = ordered_ids
+                .iter()
+                .find(|id| !entries.iter().any(|e| e.id == **id))
+            {
+                bail!("凭据排序无效: 未知的凭据 ID #{}", unknown);
+            }
+            let missing: Vec<u64> = entries
+                .iter()
+                .map(|e| e.id)
+                .filter(|id| !seen.contains(id))
+                .collect();
+            if !missing.is_empty() {
+                bail!("凭据排序无效: 缺少凭据 ID {:?}", missing);
+            }
+
+            for entry in entries.iter_mut() {
+                if let Some(pos) = ordered_ids.iter().position(|id| *id == entry.id) {
+                    entry.credentials.priority = pos as u32;
+                }
+            }
+        }
+        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
+        self.select_highest_priority();
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 将优先级压缩为连续整数（Admin API）
+    ///
+    /// 按 (priority, id) 排序后依次重写为 0..n，不改变凭据之间的相对顺序，可重复调用。
+    /// 返回每个凭据的 (id, 原优先级, 新优先级)，按新优先级排列。
+    pub fn rebalance_priorities(&self) -> anyhow::Result<Vec<(u64, u32, u32)>> {
+        let reassignments = {
+            let mut entries = self.entries.lock();
+            let mut order: Vec<(u32, u64)> =
+                entries.iter().map(|e| e.credentials.sort_key()).collect();
+            order.sort();
+
+            let reassignments: Vec<(u64, u32, u32)> = order
+                .into_iter()
+                .enumerate()
+                .map(|(pos, (old, id))| (id, old, pos as u32))
+                .collect();
+            for (id, _, new) in &reassignments {
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == *id) {
+                    entry.credentials.priority = *new;
+                }
+            }
+            reassignments
+        };
+        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
+        self.select_highest_priority();
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(reassignments)
+    }
+
+    /// 将凭据提升为最高优先级（Admin API）
+    ///
+    /// 目标凭据优先级设为 0；若其他凭据中已有优先级 0，则其他凭据优先级统一加 1，
+    /// 保持相互之间的相对顺序。所有修改在同一次加锁内完成。
+    pub fn promote_credential(&self, id: u64) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            if !entries.iter().any(|e| e.id == id) {
+                bail!("凭据不存在: {}", id);
+            }
+            let shift = entries
+                .iter()
+                .any(|e| e.id != id && e.credentials.priority == 0);
+            for entry in entries.iter_mut() {
+                if entry.id == id {
+                    entry.credentials.priority = 0;
+                } else if shift {
+                    entry.credentials.priority = entry.credentials.priority.saturating_add(1);
+                }
+            }
+        }
+        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
+        self.select_highest_priority();
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 将凭据降为最低优先级（Admin API）
+    ///
+    /// 目标凭据优先级设为其他凭据最大优先级加 1，其他凭据不变。
+    pub fn demote_credential(&self, id: u64) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            let lowest = entries
+                .iter()
+                .filter(|e| e.id != id)
+                .map(|e| e.credentials.priority)
+                .max();
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            if let Some(lowest) = lowest.filter(|p| *p >= entry.credentials.priority) {
+                entry.credentials.priority = lowest.saturating_add(1);
+            }
+        }
+        // 立即按新优先级重新选择当前凭据（无论持久化是否成功）
+        self.select_highest_priority();
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 重置凭据失败计数并重新启用（Admin API）
+    pub fn reset_and_enable(&self, id: u64) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            entry.failure_count = 0;
+            entry.disabled = false;
+            entry.disabled_reason = None;
+        }
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 获取指定凭据的使用额度（Admin API）
+    ///
+    /// `usage_limits_cache_ttl_secs` 内重复查询同一凭据时直接返回缓存结果，不请求上游
+    pub async fn get_usage_limits_for(&self, id: u64) -> anyhow::Result<UsageLimitsResponse> {
+        if let Some(cached) = self.cached_usage_limits(id) {
+            tracing::debug!("凭据 #{} 使用额度命中缓存", id);
+            return Ok(cached);
+        }
+
+        let credentials = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .find(|e| e.id == id)
+                .map(|e| e.credentials.clone())
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+        };
+
+        // 检查是否需要刷新 token
+        let needs_refresh = is_token_expired(&credentials) || is_token_expiring_soon(&credentials);
+
+        let token = if needs_refresh {
+            let _guard = self.lock_refresh(id).await?;
+            let current_creds = {
+                let entries = self.entries.lock();
+                entries
+                    .iter()
+                    .find(|e| e.id == id)
+                    .map(|e| e.credentials.clone())
+                    .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+            };
+
+            if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
+                self.refresh_credential_limited(id, current_creds)
+                    .await?
+                    .access_token
+                    .ok_or_else(|| anyhow::anyhow!("刷新后无 access_token"))?
+            } else {
+                current_creds
+                    .access_token
+                    .ok_or_else(|| anyhow::anyhow!("凭据无 access_token"))?
+            }
+        } else {
+            credentials
+                .access_token
+                .ok_or_else(|| anyhow::anyhow!("凭据无 access_token"))?
+        };
+
+        let credentials = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .find(|e| e.id == id)
+                .map(|e| e.credentials.clone())
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+        };
+
+        let usage_limits = self.call_usage_limits(&credentials, &token).await?;
+        // 先于写入缓存处理（禁用凭据会清除该凭据的使用额度缓存）
+        self.disable_if_quota_exhausted(id, &usage_limits);
+        if self.config.usage_limits_cache_ttl_secs > 0 {
+            self.usage_limits_cache
+                .lock()
+                .insert(id, (Instant::now(), usage_limits.clone()));
+        }
+        self.balance_cache.store(
+            id,
+            UsageSnapshot::from_usage_limits(&usage_limits, Some(&self.config.usage_resource_type)),
+            unix_now(),
+        );
+        // 更新订阅等级到凭据（仅在发生变化时持久化）
+        if let Some(subscription_title) = usage_limits.subscription_title() {
+            let changed = {
+                let mut entries = self.entries.lock();
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                    let old_title = entry.credentials.subscription_title.clone();
+                    if old_title.as_deref() != Some(subscription_title) {
+                        entry.credentials.subscription_title =
+                            Some(subscription_title.to_string());
+                        tracing::info!(
+                            "凭据 #{} 订阅等级已更新: {:?} -> {}",
+                            id,
+                            old_title,
+                            subscription_title
+                        );
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                }
+            };
+
+            if changed {
+                if let Err(e) = self.persist_credentials() {
+                    tracing::warn!("订阅等级更新后持久化失败（不影响本次请求）: {}", e);
+                }
+            }
+        }
+
+        Ok(usage_limits)
+    }
+
+    /// 获取指定凭据的缓存余额（不检查是否过期，也不触发上游请求）
+    pub fn get_cached_balance(&self, id: u64) -> Option<CachedBalance> {
+        self.balance_cache.get_cached(id)
+    }
+
+    /// 测试用：直接写入余额缓存
+    #[cfg(test)]
+    pub(crate) fn store_balance_for_test(&self, id: u64, current_usage: f64, usage_limit: f64) {
+        self.store_balance_with_reset_for_test(id, current_usage, usage_limit, None);
+    }
+
+    /// 读取未过期的使用额度缓存
+    fn cached_usage_limits(&self, id: u64) -> Option<UsageLimitsResponse> {
+        let ttl = StdDuration::from_secs(self.config.usage_limits_cache_ttl_secs);
+        self.usage_limits_cache
+            .lock()
+            .get(&id)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < ttl)
+            .map(|(_, usage_limits)| usage_limits.clone())
+    }
+
+    /// 丢弃指定凭据的使用额度缓存，下次查询直接请求上游
+    pub fn invalidate_usage_cache(&self, id: u64) {
+        self.usage_limits_cache.lock().remove(&id);
+    }
+
+    /// 查询使用额度（测试中优先返回预设结果）
+    async fn call_usage_limits(
+        &self,
+        credentials: &KiroCredentials,
+        token: &str,
+    ) -> anyhow::Result<UsageLimitsResponse> {
+        #[cfg(test)]
+        if let Some(stubbed) = self.usage_stub.lock().pop_front() {
+            return stubbed;
+        }
+
+        let effective_proxy = credentials.effective_proxy(self.proxy.as_ref());
+        let identity = RequestIdentity::from_credentials(credentials, &self.config)?;
+        get_usage_limits(
+            credentials,
+            &self.config,
+            &identity,
+            token,
+            effective_proxy.as_ref(),
+        )
+        .await
+    }
+
+    /// 测试用：预设后续使用额度查询的返回结果
+    #[cfg(test)]
+    pub(crate) fn stub_usage_limits(&self, results: Vec<anyhow::Result<UsageLimitsResponse>>) {
+        self.usage_stub.lock().extend(results);
+    }
+
+    /// 测试用：将余额缓存的拉取时间提前 `secs` 秒（模拟时间流逝）
+    #[cfg(test)]
+    pub(crate) fn age_balance_for_test(&self, id: u64, secs: f64) {
+        let cached = self.balance_cache.get_cached(id).expect("余额缓存不存在");
+        self.balance_cache
+            .store(id, cached.data, cached.cached_at - secs);
+        if let Some((fetched_at, _)) = self.usage_limits_cache.lock().get_mut(&id) {
+            *fetched_at -= StdDuration::from_secs_f64(secs);
+        }
+    }
+
+    /// 测试用：直接写入带重置时间的余额缓存
+    #[cfg(test)]
+    pub(crate) fn store_balance_with_reset_for_test(
+        &self,
+        id: u64,
+        current_usage: f64,
+        usage_limit: f64,
+        next_reset_at: Option<f64>,
+    ) {
+        let snapshot = UsageSnapshot {
+            subscription_title: None,
+            current_usage,
+            usage_limit,
+            next_reset_at,
+        };
+        self.balance_cache.store(id, snapshot, unix_now());
+    }
+
+    /// 从上游拉取指定凭据的使用额度并更新余额缓存
+    pub async fn refresh_balance(&self, id: u64) -> anyhow::Result<CachedBalance> {
+        self.get_usage_limits_for(id).await?;
+        self.balance_cache
+            .get_cached(id)
+            .ok_or_else(|| anyhow::anyhow!("凭据 #{} 余额缓存更新失败", id))
+    }
+
+    /// 刷新缓存缺失或超过 `max_age` 的启用凭据余额，返回成功刷新的数量
+    ///
+    /// 单个凭据刷新失败只记录日志，不影响其他凭据
+    pub async fn refresh_stale_balances(&self, max_age: StdDuration) -> usize {
+        let ids: Vec<u64> = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .filter(|e| !e.disabled)
+                .map(|e| e.id)
+                .collect()
+        };
+        let stale = self
+            .balance_cache
+            .stale_ids(&ids, max_age.as_secs_f64(), unix_now());
+
+        let mut refreshed = 0;
+        for id in stale {
+            match self.refresh_balance(id).await {
+                Ok(_) => refreshed += 1,
+                Err(e) => tracing::warn!("刷新凭据 #{} 余额失败: {}", id, e),
+            }
+        }
+        refreshed
+    }
+
+    /// 添加新凭据（Admin API）
+    ///
+    /// # 流程
+    /// 1. 清理字段（去除空白与不可见字符）并验证基本字段（refresh_token 不为空）
+    /// 2. 基于 refreshToken 的 SHA-256 哈希检测重复（快速失败，避免无谓的刷新请求）
+    /// 3. 尝试刷新 Token 验证凭据有效性
+    /// 4. 持有 entries 锁再次检测重复，并在同一临界区内分配新 ID（当前最大 ID + 1）、加入列表
+    /// 5. 持久化到配置文件
+    ///
+    /// # 原子性
+    /// 刷新验证期间会释放锁，并发添加同一 refreshToken 时两次调用都可能通过第 2 步；
+    /// 第 4 步的重复检测与插入在同一把锁内完成，保证最终只有一个调用成功，其余返回"凭据已存在"。
+    /// 刷新后 refreshToken 可能被轮换，因此同时比对提交的与刷新后的 refreshToken
+    ///
+    /// # 返回
+    /// - `Ok(u64)` - 新凭据 ID
+    /// - `Err(_)` - 验证失败或添加失败
+    pub async fn add_credential(&self, new_cred: KiroCredentials) -> anyhow::Result<u64> {
+        // 1. 清理粘贴带入的空白/不可见字符后再做基本验证
+        let new_cred = new_cred.sanitized()?;
+        validate_refresh_token(&new_cred)?;
+        new_cred.upstream_base()?;
+        if let Some(limit) = &new_cred.monthly_request_limit {
+            limit.validate()?;
+        }
+
+        // 2. 基于 refreshToken 的 SHA-256 哈希检测重复
+        let new_refresh_token = new_cred
+            .refresh_token
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("缺少 refreshToken"))?;
+        let new_refresh_token_hash = sha256_hex(new_refresh_token);
+        if Self::has_refresh_token_hash(&self.entries.lock(), &[&new_refresh_token_hash]) {
+            anyhow::bail!("凭据已存在（refreshToken 重复）");
+        }
+
+        // 3. 尝试刷新 Token 验证凭据有效性
+        let mut validated_cred = self.call_refresh(&new_cred).await?;
+
+        // 单对象格式无法容纳多个凭据，先升级为数组格式，避免新凭据重启后丢失
+        if !self.is_multiple_format.load(Ordering::SeqCst) {
+            self.upgrade_to_multiple_format()?;
+        }
+
+        // 保留用户输入的元数据
+        validated_cred.priority = new_cred.priority;
+        validated_cred.auth_method = new_cred.auth_method;
+        validated_cred.canonicalize_auth_method();
+        validated_cred.client_id = new_cred.client_id;
+        validated_cred.client_secret = new_cred.client_secret;
+        validated_cred.region = new_cred.region;
+        validated_cred.auth_region = new_cred.auth_region;
+        validated_cred.api_region = new_cred.api_region;
+        validated_cred.machine_id = new_cred.machine_id;
+        validated_cred.email = new_cred.email;
+        validated_cred.proxy_url = new_cred.proxy_url;
+        validated_cred.proxy_username = new_cred.proxy_username;
+        validated_cred.proxy_password = new_cred.proxy_password;
+        validated_cred.upstream_base_url = new_cred.upstream_base_url;
+        validated_cred.monthly_request_limit = new_cred.monthly_request_limit;
+
+        // 4. 在同一临界区内再次检测重复、分配新 ID 并插入
+        let rotated_hash = validated_cred.refresh_token.as_deref().map(sha256_hex);
+        let new_id = {
+            let mut entries = self.entries.lock();
+            let hashes: Vec<&str> = std::iter::once(new_refresh_token_hash.as_str())
+                .chain(rotated_hash.as_deref())
+                .collect();
+            if Self::has_refresh_token_hash(&entries, &hashes) {
+                anyhow::bail!("凭据已存在（refreshToken 重复）");
+            }
+
+            let new_id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+            validated_cred.id = Some(new_id);
+            entries.push(CredentialEntry {
+                id: new_id,
+                credentials: validated_cred,
+                failure_count: 0,
+                disabled: false,
+                disabled_reason: None,
+                success_count: 0,
+                last_used_at: None,
+                estimated_cost: 0.0,
+                refresh_history: VecDeque::new(),
+                recent_outcomes: VecDeque::new(),
+                last_success_at: None,
+                last_latency_ms: None,
+                failure_counts: FailureCounts::default(),
+                monthly_requests: None,
+                rotation_unpersisted: None,
+                disabled_at_quota: None,
+            });
+            new_id
+        };
+
+        // 5. 持久化（确认落盘后再返回）
+        self.persist_credentials()?;
+        self.flush_credentials().await?;
+
+        tracing::info!("成功添加凭据 #{}", new_id);
+        Ok(new_id)
+    }
+
+    /// 检查是否已有凭据的 refreshToken 哈希命中给定列表
+    fn has_refresh_token_hash(entries: &[CredentialEntry], hashes: &[&str]) -> bool {
+        entries.iter().any(|entry| {
+            entry
+                .credentials
+                .refresh_token
+                .as_deref()
+                .map(sha256_hex)
+                .is_some_and(|hash| hashes.contains(&hash.as_str()))
+        })
+    }
+
+    /// 将旧版单对象凭据文件升级为数组格式
+    ///
+    /// 原文件备份为 `<文件名>.bak`，随后按内存中的凭据重写文件；
+    /// 已是数组格式、从实例或未配置凭据文件路径时仅切换内存中的格式标记
+    pub fn upgrade_to_multiple_format(&self) -> anyhow::Result<()> {
+        if self.is_multiple_format.load(Ordering::SeqCst) || self.is_secondary() {
+            return Ok(());
+        }
+
+        if let Some(path) = &self.credentials_path {
+            let migration = CredentialsConfig::migrate_to_multiple(path, &self.config)?;
+            if let CredentialsMigration::Migrated { backup_path } = migration {
+                tracing::info!("凭据文件已升级为数组格式，原文件备份至 {:?}", backup_path);
+            }
+            // 升级后的文件由本进程写入，以其为新的外部修改检测基准
+            if let Ok(content) = std::fs::read_to_string(path) {
+                self.persist_guard.lock().rebase(content);
+            }
+        }
+
+        self.is_multiple_format.store(true, Ordering::SeqCst);
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 删除凭据（Admin API）
+    ///
+    /// # 前置条件
+    /// - 凭据必须已禁用（disabled = true）
+    ///
+    /// # 行为
+    /// 1. 验证凭据存在
+    /// 2. 验证凭据已禁用
+    /// 3. 从 entries 移除
+    /// 4. 如果删除的是当前凭据，切换到优先级最高的可用凭据
+    /// 5. 如果删除后没有凭据，将 current_id 重置为 0
+    /// 6. 持久化到文件
+    ///
+    /// # 返回
+    /// - `Ok(())` - 删除成功
+    /// - `Err(_)` - 凭据不存在、未禁用或持久化失败
+    pub fn delete_credential(&self, id: u64) -> anyhow::Result<()> {
+        let was_current = {
+            let mut entries = self.entries.lock();
+
+            // 查找凭据
+            let entry = entries
+                .iter()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+
+            // 检查是否已禁用
+            if !entry.disabled {
+                anyhow::bail!("只能删除已禁用的凭据（请先禁用凭据 #{}）", id);
+            }
+
+            // 记录是否是当前凭据
+            let current_id = *self.current_id.lock();
+            let was_current = current_id == id;
+
+            // 删除凭据
+            entries.retain(|e| e.id != id);
+
+            was_current
+        };
+        self.refresh_limiter.lock().remove(id);
+        self.balance_cache.remove(id);
+        self.invalidate_usage_cache(id);
+
+        // 如果删除的是当前凭据，切换到优先级最高的可用凭据
+        if was_current {
+            self.select_highest_priority();
+        }
+
+        // 如果删除后没有任何凭据，将 current_id 重置为 0（与初始化行为保持一致）
+        {
+            let entries = self.entries.lock();
+            if entries.is_empty() {
+                let mut current_id = self.current_id.lock();
+                *current_id = 0;
+                tracing::info!("所有凭据已删除，current_id 已重置为 0");
+            }
+        }
+
+        // 持久化更改
+        self.persist_credentials()?;
+
+        // 立即回写统计数据与刷新记录，清除已删除凭据的残留条目
+        self.save_stats_sync();
+        self.save_refresh_history();
+
+        tracing::info!("已删除凭据 #{}", id);
+        Ok(())
+    }
+
+    /// 获取负载均衡模式（Admin API）
+    pub fn get_load_balancing_mode(&self) -> String {
+        self.load_balancing_mode.lock().clone()
+    }
+
+    fn persist_load_balancing_mode(&self, mode: &str) -> anyhow::Result<()> {
+        use anyhow::Context;
+
+        let config_path = match self.config.config_path() {
+            Some(path) => path.to_path_buf(),
+            None => {
+                tracing::warn!("配置文件路径未知，负载均衡模式仅在当前进程生效: {}", mode);
+                return Ok(());
+            }
+        };
+
+        let mut config = Config::load(&config_path)
+            .with_context(|| format!("重新加载配置失败: {}", config_path.display()))?;
+        config.load_balancing_mode = mode.to_string();
+        config
+            .save()
+            .with_context(|| format!("持久化负载均衡模式失败: {}", config_path.display()))?;
+
+        Ok(())
+    }
+
+    /// 设置负载均衡模式（Admin API）
+    pub fn set_load_balancing_mode(&self, mode: String) -> anyhow::Result<()> {
+        // 验证模式值
+        if !LOAD_BALANCING_MODES.contains(&mode.as_str()) {
+            anyhow::bail!("无效的负载均衡模式: {}", mode);
+        }
+
+        let previous_mode = self.get_load_balancing_mode();
+        if previous_mode == mode {
+            return Ok(());
+        }
+
+        *self.load_balancing_mode.lock() = mode.clone();
+
+        if let Err(err) = self.persist_load_balancing_mode(&mode) {
+            *self.load_balancing_mode.lock() = previous_mode;
+            return Err(err);
+        }
+
+        tracing::info!("负载均衡模式已设置为: {}", mode);
+        Ok(())
+    }
+}
+
+impl Drop for MultiTokenManager {
+    fn drop(&mut self) {
+        // 无法在 drop 中等待异步任务，同步写入
+        if self.stats_dirty.load(Ordering::Relaxed) {
+            self.save_stats_sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_manager_new() {
+        let config = Config::default();
+        let credentials = KiroCredentials::default();
+        let tm = TokenManager::new(config, credentials, None);
+        assert!(tm.credentials().access_token.is_none());
+    }
+
+    #[test]
+    fn test_is_token_expired_with_expired_token() {
+        let mut credentials = KiroCredentials::default();
+        credentials.expires_at = Some("2020-01-01T00:00:00Z".to_string());
+        assert!(is_token_expired(&credentials));
+    }
+
+    #[test]
+    fn test_is_token_expired_with_valid_token() {
+        let mut credentials = KiroCredentials::default();
+        let future = Utc::now() + Duration::hours(1);
+        credentials.expires_at = Some(future.to_rfc3339());
+        assert!(!is_token_expired(&credentials));
+    }
+
+    #[test]
+    fn test_is_token_expired_within_5_minutes() {
+        let mut credentials = KiroCredentials::default();
+        let expires = Utc::now() + Duration::minutes(3);
+        credentials.expires_at = Some(expires.to_rfc3339());
+        assert!(is_token_expired(&credentials));
+    }
+
+    #[test]
+    fn test_is_token_expired_no_expires_at() {
+        let credentials = KiroCredentials::default();
+        assert!(is_token_expired(&credentials));
+    }
+
+    #[test]
+    fn test_is_token_expiring_soon_within_10_minutes() {
+        let mut credentials = KiroCredentials::default();
+        let expires = Utc::now() + Duration::minutes(8);
+        credentials.expires_at = Some(expires.to_rfc3339());
+        assert!(is_token_expiring_soon(&credentials));
+    }
+
+    #[test]
+    fn test_is_token_expiring_soon_beyond_10_minutes() {
+        let mut credentials = KiroCredentials::default();
+        let expires = Utc::now() + Duration::minutes(15);
+        credentials.expires_at = Some(expires.to_rfc3339());
+        assert!(!is_token_expiring_soon(&credentials));
+    }
+
+    #[test]
+    fn test_validate_refresh_token_missing() {
+        let credentials = KiroCredentials::default();
+        let result = validate_refresh_token(&credentials);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validate_refresh_token_valid() {
+        let mut credentials = KiroCredentials::default();
+        credentials.refresh_token = Some("a".repeat(150));
+        let result = validate_refresh_token(&credentials);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        let result = sha256_hex("test");
+        assert_eq!(
+            result,
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_credential_reject_duplicate_refresh_token() {
+        let config = Config::default();
+
+        let mut existing = KiroCredentials::default();
+        existing.refresh_token = Some("a".repeat(150));
+
+        let manager = MultiTokenManager::new(config, vec![existing], None, None, false).unwrap();
+
+        let mut duplicate = KiroCredentials::default();
+        duplicate.refresh_token = Some("a".repeat(150));
+
+        let result = manager.add_credential(duplicate).await;
+        assert!(result.is_err());
+        assert!(result.err().unwrap().to_string().contains("凭据已存在"));
+    }
+
+    #[tokio::test]
+    async fn test_add_credential_concurrent_duplicates_insert_once() {
+        let manager = MultiTokenManager::new(Config::default(), vec![], None, None, true).unwrap();
+        let refreshed = || {
+            Ok(KiroCredentials {
+                access_token: Some("new-access".to_string()),
+                refresh_token: Some("c".repeat(150)),
+                ..Default::default()
+            })
+        };
+        manager.stub_refresh_results(vec![refreshed(), refreshed()]);
+
+        let new_cred = || KiroCredentials {
+            refresh_token: Some("c".repeat(150)),
+            ..Default::default()
+        };
+        let (first, second) = tokio::join!(
+            manager.add_credential(new_cred()),
+            manager.add_credential(new_cred())
+        );
+
+        assert_eq!(first.is_ok() as u8 + second.is_ok() as u8, 1);
+        let error = first.err().or(second.err()).unwrap();
+        assert!(error.to_string().contains("凭据已存在"), "{}", error);
+        assert_eq!(manager.snapshot().entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_stale_balances_skips_fresh_entries() {
+        let credentials = |seed: &str| KiroCredentials {
+            access_token: Some("access".to_string()),
+            refresh_token: Some(seed.repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![credentials("a"), credentials("b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        manager.store_balance_with_reset_for_test(1, 10.0, 100.0, None);
+        manager.store_balance_with_reset_for_test(2, 10.0, 100.0, None);
+        manager.age_balance_for_test(1, 600.0);
+        manager.stub_usage_limits(vec![Ok(serde_json::from_value(serde_json::json!({
+            "usageBreakdownList": [{
+                "currentUsageWithPrecision": 40.0,
+                "usageLimitWithPrecision": 100.0
+            }]
+        }))
+        .unwrap())]);
+
+        let refreshed = manager
+            .refresh_stale_balances(StdDuration::from_secs(300))
+            .await;
+        assert_eq!(refreshed, 1);
+        // 仅过期的凭据 #1 请求上游，未过期的 #2 保持原缓存
+        assert_eq!(
+            manager.get_cached_balance(1).unwrap().data.current_usage,
+            40.0
+        );
+        assert_eq!(
+            manager.get_cached_balance(2).unwrap().data.current_usage,
+            10.0
+        );
+        assert!(manager.usage_stub.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_usage_limits_for_caches_within_ttl() {
+        let credentials = KiroCredentials {
+            access_token: Some("access".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![credentials], None, None, false)
+                .unwrap();
+        let usage = |current: f64| -> anyhow::Result<UsageLimitsResponse> {
+            Ok(serde_json::from_value(serde_json::json!({
+                "usageBreakdownList": [{
+                    "currentUsageWithPrecision": current,
+                    "usageLimitWithPrecision": 100.0
+                }]
+            }))
+            .unwrap())
+        };
+        // 每个预设结果对应一次上游请求
+        manager.stub_usage_limits(vec![usage(10.0), usage(20.0), usage(30.0)]);
+
+        for _ in 0..3 {
+            let usage_limits = manager.get_usage_limits_for(1).await.unwrap();
+            assert_eq!(usage_limits.current_usage(None), 10.0);
+        }
+
+        // 禁用凭据后缓存失效
+        manager.set_disabled(1, true).unwrap();
+        let usage_limits = manager.get_usage_limits_for(1).await.unwrap();
+        assert_eq!(usage_limits.current_usage(None), 20.0);
+
+        manager.invalidate_usage_cache(1);
+        let usage_limits = manager.get_usage_limits_for(1).await.unwrap();
+        assert_eq!(usage_limits.current_usage(None), 30.0);
+    }
+
+    #[test]
+    fn test_persist_merges_external_edits_and_reports_conflicts() {
+        let dir = std::env::temp_dir().join(format!("kiro-external-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials.json");
+        let credentials: Vec<KiroCredentials> = (1..=2)
+            .map(|id| KiroCredentials {
+                id: Some(id),
+                refresh_token: Some(format!("{}", id).repeat(150)),
+                machine_id: Some(format!("m{}", id)),
+                priority: id as u32,
+                ..Default::default()
+            })
+            .collect();
+        std::fs::write(&path, serde_json::to_string_pretty(&credentials).unwrap()).unwrap();
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            credentials,
+            None,
+            Some(path.clone()),
+            true,
+        )
+        .unwrap();
+
+        let read = || -> Vec<serde_json::Value> {
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+        };
+        let edit = |f: &dyn Fn(&mut Vec<serde_json::Value>)| {
+            let mut saved = read();
+            f(&mut saved);
+            std::fs::write(&path, serde_json::to_string_pretty(&saved).unwrap()).unwrap();
+        };
+
+        // 外部修改 #2 的邮箱，内存中禁用 #1：两者都保留
+        edit(&|saved| saved[1]["email"] = serde_json::json!("ops@example.com"));
+        manager.set_disabled(1, true).unwrap();
+        let saved = read();
+        assert_eq!(saved[0]["disabled"], true);
+        assert_eq!(saved[1]["email"], "ops@example.com");
+        assert!(manager.snapshot().persist_warning.is_none());
+
+        // 双方修改同一字段：以外部文件为准，内存版本写入 .conflict
+        edit(&|saved| saved[1]["priority"] = serde_json::json!(9));
+        manager.set_priority(2, 0).unwrap();
+        assert_eq!(read()[1]["priority"], 9);
+        assert!(dir.join("credentials.json.conflict").exists());
+        let snapshot = manager.snapshot();
+        let warning = snapshot.persist_warning.unwrap();
+        assert!(warning.contains("凭据 #2 的 priority"), "{}", warning);
+
+        // 合并进文件的外部修改同步回内存
+        let entry = |id: u64| snapshot.entries.iter().find(|e| e.id == id).unwrap();
+        assert_eq!(entry(2).priority, 9);
+        assert_eq!(entry(2).email.as_deref(), Some("ops@example.com"));
+
+        // 外部新增、删除凭据同样同步；之后的回写不再冲突
+        edit(&|saved| {
+            saved.remove(0);
+            saved.push(serde_json::json!({ "id": 3, "refreshToken": "3".repeat(150) }));
+        });
+        manager.set_priority(2, 4).unwrap();
+        let snapshot = manager.snapshot();
+        let ids: Vec<u64> = snapshot.entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(snapshot.current_id, 2);
+        assert!(snapshot.persist_warning.is_none());
+        let saved = read();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0]["priority"], 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_credential_upgrades_single_format_file() {
+        let dir = std::env::temp_dir().join(format!("kiro-upgrade-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials.json");
+        let existing_token = "a".repeat(150);
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"refreshToken":"{}","machineId":"m1"}}"#,
+                existing_token
+            ),
+        )
+        .unwrap();
+
+        let existing = KiroCredentials {
+            refresh_token: Some(existing_token),
+            machine_id: Some("m1".to_string()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![existing],
+            None,
+            Some(path.clone()),
+            false,
+        )
+        .unwrap();
+
+        let new_cred = KiroCredentials {
+            refresh_token: Some("b".repeat(150)),
+            ..Default::default()
+        };
+        manager.stub_refresh_results(vec![Ok(KiroCredentials {
+            access_token: Some("new-access".to_string()),
+            refresh_token: Some("b".repeat(150)),
+            ..Default::default()
+        })]);
+        let new_id = manager.add_credential(new_cred).await.unwrap();
+        assert_eq!(new_id, 2);
+
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let saved = saved.as_array().expect("凭据文件应升级为数组格式");
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[1]["id"], 2);
+        assert!(dir.join("credentials.json.bak").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // MultiTokenManager 测试
+
+    #[test]
+    fn test_multi_token_manager_new() {
+        let config = Config::default();
+        let mut cred1 = KiroCredentials::default();
+        cred1.priority = 0;
+        let mut cred2 = KiroCredentials::default();
+        cred2.priority = 1;
+
+        let manager =
+            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+        assert_eq!(manager.total_count(), 2);
+        assert_eq!(manager.available_count(), 2);
+    }
+
+    #[test]
+    fn test_multi_token_manager_empty_credentials() {
+        let config = Config::default();
+        let result = MultiTokenManager::new(config, vec![], None, None, false);
+        // 支持 0 个凭据启动（可通过管理面板添加）
+        assert!(result.is_ok());
+        let manager = result.unwrap();
+        assert_eq!(manager.total_count(), 0);
+        assert_eq!(manager.available_count(), 0);
+    }
+
+    #[test]
+    fn test_multi_token_manager_duplicate_ids() {
+        let config = Config::default();
+        let mut cred1 = KiroCredentials::default();
+        cred1.id = Some(1);
+        let mut cred2 = KiroCredentials::default();
+        cred2.id = Some(1); // 重复 ID
+
+        let result = MultiTokenManager::new(config, vec![cred1, cred2], None, None, false);
+        assert!(result.is_err());
+        let err_msg = result.err().unwrap().to_string();
+        assert!(
+            err_msg.contains("重复的凭据 ID"),
+            "错误消息应包含 '重复的凭据 ID'，实际: {}",
+            err_msg
+        );
+    }
+
+    #[test]
+    fn test_multi_token_manager_error_variants() {
+        let with_id = |id| KiroCredentials {
+            id: Some(id),
+            ..Default::default()
+        };
+        let result = MultiTokenManager::new(
+            Config::default(),
+            vec![with_id(7), with_id(7)],
+            None,
+            None,
+            false,
+        );
+        assert!(matches!(
+            result.err(),
+            Some(MultiTokenManagerError::DuplicateCredentialId(7))
+        ));
+
+        let reserved = KiroCredentials {
+            priority: u32::MAX,
+            ..Default::default()
+        };
+        let result = MultiTokenManager::new(Config::default(), vec![reserved], None, None, false);
+        assert!(matches!(
+            result.err(),
+            Some(MultiTokenManagerError::InvalidPriority { id: 1, .. })
+        ));
+
+        // 未知 authMethod 仅告警，不阻止创建
+        let unknown = KiroCredentials {
+            auth_method: Some("saml".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            MultiTokenManager::new(Config::default(), vec![unknown], None, None, false).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_multi_token_manager_non_fatal_warnings() {
+        let (manager, warnings) =
+            MultiTokenManager::new_with_warnings(Config::default(), vec![], None, None, false)
+                .unwrap();
+        assert_eq!(manager.total_count(), 0);
+        assert!(matches!(
+            warnings.as_slice(),
+            [MultiTokenManagerError::EmptyCredentialsWarning]
+        ));
+
+        // 凭据文件所在目录不存在，补全 ID 后回写失败
+        let path = std::env::temp_dir()
+            .join(format!("kiro-missing-{}", uuid::Uuid::new_v4()))
+            .join("credentials.json");
+        let (manager, warnings) = MultiTokenManager::new_with_warnings(
+            Config::default(),
+            vec![KiroCredentials::default()],
+            None,
+            Some(path),
+            true,
+        )
+        .unwrap();
+        assert_eq!(manager.total_count(), 1);
+        assert!(matches!(
+            warnings.as_slice(),
+            [MultiTokenManagerError::PersistenceError(_)]
+        ));
+    }
+
+    #[test]
+    fn test_multi_token_manager_error_converts_to_anyhow() {
+        let err: anyhow::Error = MultiTokenManagerError::DuplicateCredentialId(3).into();
+        assert_eq!(err.to_string(), "检测到重复的凭据 ID: 3");
+    }
+
+    #[test]
+    fn test_multi_token_manager_report_failure() {
+        let mut config = Config::default();
+        config.auth_failure_threshold = MAX_FAILURES_PER_CREDENTIAL;
+        let cred1 = KiroCredentials::default();
+        let cred2 = KiroCredentials::default();
+
+        let manager =
+            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+
+        // 凭据会自动分配 ID（从 1 开始）
+        // 前两次失败不会禁用（使用 ID 1）
+        assert!(
+            manager
+                .report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert!(
+            manager
+                .report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert_eq!(manager.available_count(), 2);
+
+        // 第三次失败会禁用第一个凭据
+        assert!(
+            manager
+                .report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert_eq!(manager.available_count(), 1);
+
+        // 继续失败第二个凭据（使用 ID 2）
+        assert!(
+            manager
+                .report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert!(
+            manager
+                .report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert!(
+            !manager
+                .report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth)
+                .has_more
+        ); // 所有凭据都禁用了
+        assert_eq!(manager.available_count(), 0);
+    }
+
+    #[test]
+    fn test_multi_token_manager_report_transient_failure() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![KiroCredentials::default(), KiroCredentials::default()],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+
+        // 瞬态错误不计入失败次数，但会切换到其他可用凭据
+        for _ in 0..MAX_FAILURES_PER_CREDENTIAL + 1 {
+            assert!(
+                manager
+                    .report_failure(&CallContext::for_test(1), FailureKind::UpstreamServer)
+                    .has_more
+            );
+        }
+        assert_eq!(manager.available_count(), 2);
+        assert_eq!(*manager.current_id.lock(), 2);
+        assert!(
+            manager
+                .snapshot()
+                .entries
+                .iter()
+                .all(|e| e.failure_count == 0)
+        );
+    }
+
+    #[test]
+    fn test_failure_action_by_kind() {
+        use FailureAction::*;
+        use FailureKind::*;
+
+        let count = |threshold| Count { threshold };
+        // (失败类型, 其他凭据近期是否成功, 预期处理方式)
+        let cases = [
+            (Network, false, Ignore),
+            (Network, true, count(MAX_FAILURES_PER_CREDENTIAL)),
+            (UpstreamServer, false, Switch),
+            (UpstreamServer, true, Switch),
+            (UpstreamAuth, false, count(1)),
+            (UpstreamAuth, true, count(1)),
+            (UpstreamThrottle, false, Ignore),
+            (UpstreamThrottle, true, Ignore),
+            (Client, false, Ignore),
+            (Client, true, Ignore),
+        ];
+        for (kind, peers_succeeding, expected) in cases {
+            assert_eq!(
+                failure_action(kind, 1, peers_succeeding),
+                expected,
+                "{:?} (peers_succeeding = {})",
+                kind,
+                peers_succeeding
+            );
+        }
+
+        assert_eq!(failure_action(UpstreamAuth, 5, false), count(5));
+        // 阈值配置为 0 时按 1 处理
+        assert_eq!(failure_action(UpstreamAuth, 0, false), count(1));
+    }
+
+    #[test]
+    fn test_report_failure_by_kind() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![KiroCredentials::default(), KiroCredentials::default()],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+
+        // 其他凭据没有成功请求时，网络错误视为全局问题，不计入失败次数
+        for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
+            manager.report_failure(&CallContext::for_test(1), FailureKind::Network);
+        }
+        // 限流与请求本身的问题从不计入
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamThrottle);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::Client);
+        let entry = manager.snapshot().entries[0].clone();
+        assert_eq!(entry.failure_count, 0);
+        assert!(!entry.disabled);
+        assert_eq!(
+            entry.failure_counts,
+            FailureCounts {
+                network: MAX_FAILURES_PER_CREDENTIAL as u64,
+                upstream_throttle: 1,
+                client: 1,
+                ..Default::default()
+            }
+        );
+
+        // 其他凭据请求成功时，网络错误计入失败次数，连续达到阈值后禁用
+        manager.report_success(&CallContext::for_test(2), 0);
+        for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
+            manager.report_failure(&CallContext::for_test(1), FailureKind::Network);
+        }
+        assert!(manager.snapshot().entries[0].disabled);
+        assert_eq!(*manager.current_id.lock(), 2);
+
+        // 认证错误默认一次即禁用
+        assert!(
+            !manager
+                .report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth)
+                .has_more
+        );
+        assert_eq!(manager.snapshot().entries[1].failure_counts.upstream_auth, 1);
+        assert_eq!(manager.available_count(), 0);
+    }
+
+    #[test]
+    fn test_multi_token_manager_report_success() {
+        let mut config = Config::default();
+        config.auth_failure_threshold = MAX_FAILURES_PER_CREDENTIAL;
+        let cred = KiroCredentials::default();
+
+        let manager = MultiTokenManager::new(config, vec![cred], None, None, false).unwrap();
+
+        // 失败两次（使用 ID 1）
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+
+        // 成功后重置计数（使用 ID 1）
+        manager.report_success(&CallContext::for_test(1), 0);
+
+        // 再失败两次不会禁用
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        assert_eq!(manager.available_count(), 1);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_report_success_records_latency() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![KiroCredentials::default()],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(manager.snapshot().entries[0].last_latency_ms, None);
+
+        let ctx = CallContext::for_test(1);
+        manager.report_success(&ctx, 1234);
+        let entry = &manager.snapshot().entries[0];
+        assert_eq!(entry.last_latency_ms, Some(1234));
+        assert_eq!(entry.success_count, 1);
+
+        // 仅凭 ID 上报时不覆盖已记录的耗时
+        manager.report_success_by_id(1);
+        let entry = &manager.snapshot().entries[0];
+        assert_eq!(entry.last_latency_ms, Some(1234));
+        assert_eq!(entry.success_count, 2);
+
+        manager.report_failure_by_id(1, FailureKind::Client);
+        assert_eq!(manager.snapshot().entries[0].failure_count, 0);
+
+        assert!(ctx.elapsed_ms() < 60_000);
+    }
+
+    #[test]
+    fn test_multi_token_manager_switch_to_next() {
+        let config = Config::default();
+        let mut cred1 = KiroCredentials::default();
+        cred1.refresh_token = Some("token1".to_string());
+        let mut cred2 = KiroCredentials::default();
+        cred2.refresh_token = Some("token2".to_string());
+
+        let manager =
+            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+
+        // 初始是第一个凭据
+        assert_eq!(
+            manager.credentials().refresh_token,
+            Some("token1".to_string())
+        );
+
+        // 切换到下一个
+        assert!(manager.switch_to_next());
+        assert_eq!(
+            manager.credentials().refresh_token,
+            Some("token2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_select_next_credential_tie_break_by_id() {
+        // 同优先级时按 ID 选择，与 Vec 顺序无关
+        let cred5 = KiroCredentials {
+            id: Some(5),
+            ..Default::default()
+        };
+        let cred2 = KiroCredentials {
+            id: Some(2),
+            ..Default::default()
+        };
+
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![cred5, cred2], None, None, false)
+                .unwrap();
+
+        assert_eq!(*manager.current_id.lock(), 2);
+        assert_eq!(
+            manager
+                .select_next_credential(None, Instant::now())
+                .map(|(id, _)| id),
+            Some(2)
+        );
+
+        *manager.load_balancing_mode.lock() = "balanced".to_string();
+        assert_eq!(
+            manager
+                .select_next_credential(None, Instant::now())
+                .map(|(id, _)| id),
+            Some(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_failure_backoff_skips_credential_during_window() {
+        let mut config = Config::default();
+        config.min_refresh_interval_secs = 0;
+        let expiring = KiroCredentials {
+            id: Some(1),
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::minutes(7)).to_rfc3339()),
+            ..Default::default()
+        };
+        let valid = KiroCredentials {
+            id: Some(2),
+            priority: 1,
+            access_token: Some("token-2".to_string()),
+            refresh_token: Some("b".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager =
+            MultiTokenManager::new(config, vec![expiring.clone(), valid], None, None, false)
+                .unwrap();
+        *manager.load_balancing_mode.lock() = "balanced".to_string();
+        manager.stub_refresh_results(vec![Err(anyhow::anyhow!("refresh token revoked"))]);
+
+        // #1 刷新失败后切换到 #2，失败不计入 failure_count
+        assert_eq!(manager.acquire_context(None).await.unwrap().id, 2);
+        let failed_at = Instant::now();
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.entries[0].failure_count, 0);
+        assert!(snapshot.entries[0].next_refresh_attempt_at.is_some());
+        assert!(snapshot.entries[1].next_refresh_attempt_at.is_none());
+
+        // 退避窗口（默认 30 秒）内选择时跳过 #1，窗口结束后重新参与选择
+        let select = |now| manager.select_next_credential(None, now).map(|(id, _)| id);
+        assert_eq!(select(failed_at), Some(2));
+        assert_eq!(select(failed_at + StdDuration::from_secs(20)), Some(2));
+        assert_eq!(select(failed_at + StdDuration::from_secs(31)), Some(1));
+
+        // 没有其他可用凭据时仍使用退避中的凭据
+        manager.set_disabled(2, true).unwrap();
+        assert_eq!(select(failed_at), Some(1));
+
+        // 刷新成功后清除退避
+        manager.stub_refresh_results(vec![refreshed_with("a".repeat(150))]);
+        manager
+            .refresh_credential_limited(1, expiring)
+            .await
+            .unwrap();
+        let snapshot = manager.snapshot();
+        assert!(snapshot.entries[0].next_refresh_attempt_at.is_none());
+    }
+
+    #[test]
+    fn test_refresh_failure_backoff_grows_with_consecutive_failures() {
+        let manager = manager_with_priorities(&[0, 1]);
+        let t0 = Instant::now();
+        {
+            let mut limiter = manager.refresh_limiter.lock();
+            limiter.record_failure(1, t0);
+            limiter.record_failure(1, t0);
         }
+        let select = |now| manager.select_next_credential(None, now).map(|(id, _)| id);
 
-        // 持久化更改
-        self.persist_credentials()?;
+        // 连续失败两次：退避 60 秒
+        assert_eq!(select(t0 + StdDuration::from_secs(59)), Some(2));
+        assert_eq!(select(t0 + StdDuration::from_secs(60)), Some(1));
+    }
 
-        // 立即回写统计数据，清除已删除凭据的残留条目
-        self.save_stats();
+    #[test]
+    fn test_reorder_credentials_rewrites_priorities() {
+        let creds = [3, 3, 7]
+            .into_iter()
+            .map(|priority| KiroCredentials {
+                priority,
+                ..Default::default()
+            })
+            .collect();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
 
-        tracing::info!("已删除凭据 #{}", id);
-        Ok(())
+        manager.reorder_credentials(&[3, 1, 2]).unwrap();
+
+        let snapshot = manager.snapshot();
+        let priority_of = |id: u64| {
+            snapshot
+                .entries
+                .iter()
+                .find(|e| e.id == id)
+                .map(|e| e.priority)
+                .unwrap()
+        };
+        assert_eq!(priority_of(3), 0);
+        assert_eq!(priority_of(1), 1);
+        assert_eq!(priority_of(2), 2);
+        // 重排后立即切换到优先级最高的凭据
+        assert_eq!(snapshot.current_id, 3);
     }
 
-    /// 获取负载均衡模式（Admin API）
-    pub fn get_load_balancing_mode(&self) -> String {
-        self.load_balancing_mode.lock().clone()
+    #[test]
+    fn test_reorder_credentials_rejects_id_mismatch() {
+        let creds = vec![KiroCredentials::default(), KiroCredentials::default()];
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+
+        for ids in [&[1][..], &[1, 2, 3][..], &[1, 1][..], &[2, 9][..]] {
+            let err = manager.reorder_credentials(ids).unwrap_err().to_string();
+            assert!(err.contains("凭据排序无效"), "{:?}: {}", ids, err);
+        }
+
+        // 校验失败时不修改任何优先级
+        assert!(manager.snapshot().entries.iter().all(|e| e.priority == 0));
     }
 
-    fn persist_load_balancing_mode(&self, mode: &str) -> anyhow::Result<()> {
-        use anyhow::Context;
+    fn manager_with_priorities(priorities: &[u32]) -> MultiTokenManager {
+        let creds = priorities
+            .iter()
+            .map(|&priority| KiroCredentials {
+                priority,
+                ..Default::default()
+            })
+            .collect();
+        MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap()
+    }
 
-        let config_path = match self.config.config_path() {
-            Some(path) => path.to_path_buf(),
-            None => {
-                tracing::warn!("配置文件路径未知，负载均衡模式仅在当前进程生效: {}", mode);
-                return Ok(());
-            }
+    #[test]
+    fn test_reset_aware_prefers_earliest_reset() {
+        let manager = manager_with_priorities(&[0, 1, 2, 3, 4]);
+        *manager.load_balancing_mode.lock() = "reset-aware".to_string();
+        let now = unix_now();
+
+        // #1 无缓存；#2 一天后重置；#3 一小时后重置；#4 额度用尽；#5 重置时间已过
+        manager.store_balance_with_reset_for_test(2, 10.0, 100.0, Some(now + 86_400.0));
+        manager.store_balance_with_reset_for_test(3, 90.0, 100.0, Some(now + 3_600.0));
+        manager.store_balance_with_reset_for_test(4, 100.0, 100.0, Some(now + 60.0));
+        manager.store_balance_with_reset_for_test(5, 100.0, 100.0, Some(now - 60.0));
+
+        let select = || {
+            manager
+                .select_next_credential(None, Instant::now())
+                .map(|(id, _)| id)
         };
+        assert_eq!(select(), Some(3));
 
-        let mut config = Config::load(&config_path)
-            .with_context(|| format!("重新加载配置失败: {}", config_path.display()))?;
-        config.load_balancing_mode = mode.to_string();
-        config
-            .save()
-            .with_context(|| format!("持久化负载均衡模式失败: {}", config_path.display()))?;
+        manager.set_disabled(3, true).unwrap();
+        assert_eq!(select(), Some(2));
 
-        Ok(())
+        // 有重置时间的凭据都不可用后，按优先级选择缺少数据的凭据（#4 被排除）
+        manager.set_disabled(2, true).unwrap();
+        assert_eq!(select(), Some(1));
+        manager.set_disabled(1, true).unwrap();
+        assert_eq!(select(), Some(5));
     }
 
-    /// 设置负载均衡模式（Admin API）
-    pub fn set_load_balancing_mode(&self, mode: String) -> anyhow::Result<()> {
-        // 验证模式值
-        if mode != "priority" && mode != "balanced" {
-            anyhow::bail!("无效的负载均衡模式: {}", mode);
-        }
+    #[test]
+    fn test_set_load_balancing_mode_rejects_unknown_mode() {
+        let manager = manager_with_priorities(&[0]);
+        let result = manager.set_load_balancing_mode("random".to_string());
+        assert!(result.is_err());
+        assert_eq!(manager.get_load_balancing_mode(), "priority");
+    }
 
-        let previous_mode = self.get_load_balancing_mode();
-        if previous_mode == mode {
-            return Ok(());
-        }
+    fn priorities(manager: &MultiTokenManager) -> Vec<(u64, u32)> {
+        let mut entries: Vec<(u64, u32)> = manager
+            .snapshot()
+            .entries
+            .iter()
+            .map(|e| (e.id, e.priority))
+            .collect();
+        entries.sort();
+        entries
+    }
 
-        *self.load_balancing_mode.lock() = mode.clone();
+    #[test]
+    fn test_rebalance_priorities_compresses_values() {
+        let manager = manager_with_priorities(&[10, 0, 100, 10]);
 
-        if let Err(err) = self.persist_load_balancing_mode(&mode) {
-            *self.load_balancing_mode.lock() = previous_mode;
-            return Err(err);
-        }
+        let reassignments = manager.rebalance_priorities().unwrap();
 
-        tracing::info!("负载均衡模式已设置为: {}", mode);
-        Ok(())
-    }
-}
+        // 相同优先级按 ID 排序
+        assert_eq!(
+            reassignments,
+            vec![(2, 0, 0), (1, 10, 1), (4, 10, 2), (3, 100, 3)]
+        );
+        assert_eq!(priorities(&manager), vec![(1, 1), (2, 0), (3, 3), (4, 2)]);
+        assert_eq!(manager.snapshot().current_id, 2);
 
-impl Drop for MultiTokenManager {
-    fn drop(&mut self) {
-        if self.stats_dirty.load(Ordering::Relaxed) {
-            self.save_stats();
-        }
+        // 幂等：再次调用不改变任何优先级
+        let again = manager.rebalance_priorities().unwrap();
+        assert!(again.iter().all(|(_, old, new)| old == new));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn test_token_manager_new() {
-        let config = Config::default();
-        let credentials = KiroCredentials::default();
-        let tm = TokenManager::new(config, credentials, None);
-        assert!(tm.credentials().access_token.is_none());
+    fn test_promote_credential_shifts_others_down() {
+        let manager = manager_with_priorities(&[0, 1, 2]);
+
+        manager.promote_credential(3).unwrap();
+
+        assert_eq!(priorities(&manager), vec![(1, 1), (2, 2), (3, 0)]);
+        // 提升后立即切换到被提升的凭据
+        assert_eq!(manager.snapshot().current_id, 3);
     }
 
     #[test]
-    fn test_is_token_expired_with_expired_token() {
-        let mut credentials = KiroCredentials::default();
-        credentials.expires_at = Some("2020-01-01T00:00:00Z".to_string());
-        assert!(is_token_expired(&credentials));
+    fn test_promote_credential_without_conflict_keeps_others() {
+        let manager = manager_with_priorities(&[5, 9, 7]);
+
+        manager.promote_credential(2).unwrap();
+
+        assert_eq!(priorities(&manager), vec![(1, 5), (2, 0), (3, 7)]);
+        assert_eq!(manager.snapshot().current_id, 2);
     }
 
     #[test]
-    fn test_is_token_expired_with_valid_token() {
-        let mut credentials = KiroCredentials::default();
-        let future = Utc::now() + Duration::hours(1);
-        credentials.expires_at = Some(future.to_rfc3339());
-        assert!(!is_token_expired(&credentials));
+    fn test_demote_credential_moves_to_lowest() {
+        let manager = manager_with_priorities(&[0, 1, 2]);
+
+        manager.demote_credential(1).unwrap();
+
+        assert_eq!(priorities(&manager), vec![(1, 3), (2, 1), (3, 2)]);
+        assert_eq!(manager.snapshot().current_id, 2);
+
+        // 已经是唯一的最低优先级时不变
+        manager.demote_credential(1).unwrap();
+        assert_eq!(priorities(&manager), vec![(1, 3), (2, 1), (3, 2)]);
     }
 
     #[test]
-    fn test_is_token_expired_within_5_minutes() {
-        let mut credentials = KiroCredentials::default();
-        let expires = Utc::now() + Duration::minutes(3);
-        credentials.expires_at = Some(expires.to_rfc3339());
-        assert!(is_token_expired(&credentials));
+    fn test_promote_and_demote_unknown_credential() {
+        let manager = manager_with_priorities(&[0, 1]);
+        assert!(manager.promote_credential(9).is_err());
+        assert!(manager.demote_credential(9).is_err());
+        assert_eq!(priorities(&manager), vec![(1, 0), (2, 1)]);
     }
 
-    #[test]
-    fn test_is_token_expired_no_expires_at() {
-        let credentials = KiroCredentials::default();
-        assert!(is_token_expired(&credentials));
+    /// Token 将在 `expires_in_mins` 分钟后过期的凭据
+    fn cred_expiring_in(expires_in_mins: i64) -> KiroCredentials {
+        KiroCredentials {
+            access_token: Some("token".to_string()),
+            expires_at: Some((Utc::now() + Duration::minutes(expires_in_mins)).to_rfc3339()),
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn test_is_token_expiring_soon_within_10_minutes() {
-        let mut credentials = KiroCredentials::default();
-        let expires = Utc::now() + Duration::minutes(8);
-        credentials.expires_at = Some(expires.to_rfc3339());
-        assert!(is_token_expiring_soon(&credentials));
+    fn test_health_freshness_factor() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![
+                cred_expiring_in(30),
+                cred_expiring_in(-5),
+                KiroCredentials::default(),
+            ],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let freshness = |id| manager.health_factors(id).unwrap().freshness_factor;
+
+        // 无刷新记录时按 1 小时有效期估算
+        assert!((freshness(1) - 0.5).abs() < 0.01);
+        // 已过期或没有 Token
+        assert_eq!(freshness(2), 0.0);
+        assert_eq!(freshness(3), 0.0);
+
+        // 10 分钟前刷新、30 分钟后过期：已过去 1/4
+        manager.entries.lock()[0]
+            .refresh_history
+            .push_back(RefreshAttempt {
+                attempted_at: (Utc::now() - Duration::minutes(10)).to_rfc3339(),
+                succeeded: true,
+                duration_ms: 100,
+                error: None,
+                rotated: false,
+            });
+        assert!((freshness(1) - 0.75).abs() < 0.01);
     }
 
     #[test]
-    fn test_is_token_expiring_soon_beyond_10_minutes() {
-        let mut credentials = KiroCredentials::default();
-        let expires = Utc::now() + Duration::minutes(15);
-        credentials.expires_at = Some(expires.to_rfc3339());
-        assert!(!is_token_expiring_soon(&credentials));
+    fn test_health_success_rate_uses_recent_outcomes() {
+        let mut config = Config::default();
+        config.auth_failure_threshold = MAX_FAILURES_PER_CREDENTIAL;
+        let manager =
+            MultiTokenManager::new(config, vec![cred_expiring_in(60)], None, None, false).unwrap();
+        assert_eq!(manager.health_factors(1).unwrap().success_rate_factor, 1.0);
+
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        // 瞬态错误不计入
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamServer);
+        for _ in 0..9 {
+            manager.report_success(&CallContext::for_test(1), 0);
+        }
+
+        // 仅保留最近 10 次：1 次失败 + 9 次成功
+        let factors = manager.health_factors(1).unwrap();
+        assert!((factors.success_rate_factor - 0.9).abs() < 1e-9);
+        assert_eq!(factors.failure_factor, 1.0);
+        assert!(manager.health_factors(9).is_err());
     }
 
     #[test]
-    fn test_validate_refresh_token_missing() {
-        let credentials = KiroCredentials::default();
-        let result = validate_refresh_token(&credentials);
-        assert!(result.is_err());
+    fn test_fleet_health_score_averages_enabled_credentials() {
+        let mut config = Config::default();
+        config.auth_failure_threshold = MAX_FAILURES_PER_CREDENTIAL;
+        let manager = MultiTokenManager::new(
+            config,
+            vec![
+                cred_expiring_in(60),
+                cred_expiring_in(60),
+                cred_expiring_in(-5),
+            ],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        manager.report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth);
+        manager.set_disabled(3, true).unwrap();
+
+        let snapshot = manager.snapshot();
+        let score = |id: u64| {
+            snapshot
+                .entries
+                .iter()
+                .find(|e| e.id == id)
+                .unwrap()
+                .health_score
+        };
+        assert!(score(2) < score(1));
+        let expected = (score(1) + score(2)) / 2.0;
+        assert!((snapshot.fleet_health_score - expected).abs() < 1e-9);
     }
 
-    #[test]
-    fn test_validate_refresh_token_valid() {
-        let mut credentials = KiroCredentials::default();
-        credentials.refresh_token = Some("a".repeat(150));
-        let result = validate_refresh_token(&credentials);
-        assert!(result.is_ok());
+    fn cred_with(id: u64, refresh_token: &str) -> KiroCredentials {
+        KiroCredentials {
+            id: Some(id),
+            refresh_token: Some(refresh_token.to_string()),
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn test_sha256_hex() {
-        let result = sha256_hex("test");
-        assert_eq!(
-            result,
-            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+    fn test_stats_export_import_matches_by_refresh_token_hash() {
+        let source = MultiTokenManager::new(
+            Config::default(),
+            vec![cred_with(1, "token-a"), cred_with(2, "token-b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        for _ in 0..3 {
+            source.report_success(&CallContext::for_test(1), 0);
+        }
+        source.report_success(&CallContext::for_test(2), 0);
+
+        // 目标实例中相同 refreshToken 的凭据 ID 不同
+        let target = MultiTokenManager::new(
+            Config::default(),
+            vec![
+                cred_with(10, "token-b"),
+                cred_with(20, "token-a"),
+                cred_with(30, "token-c"),
+            ],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        target.report_success(&CallContext::for_test(10), 0);
+        target.report_success(&CallContext::for_test(10), 0);
+
+        let export = source.export_stats();
+        assert_eq!(export.version, STATS_EXPORT_VERSION);
+        assert_eq!(export.entries.len(), 2);
+
+        let matched = target.import_stats(export).unwrap();
+        assert_eq!(matched, 2);
+
+        let counts: HashMap<u64, u64> = target
+            .snapshot()
+            .entries
+            .iter()
+            .map(|e| (e.id, e.success_count))
+            .collect();
+        assert_eq!(counts[&20], 3);
+        // 已有统计高于导入值时保留
+        assert_eq!(counts[&10], 2);
+        assert_eq!(counts[&30], 0);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_limited_reuses_existing_token_within_interval() {
+        let mut cred = cred_with(1, &"a".repeat(150));
+        cred.access_token = Some("old-token".to_string());
+        // 即将过期（触发刷新）但仍然有效
+        cred.expires_at = Some((Utc::now() + Duration::minutes(3)).to_rfc3339());
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![cred.clone()], None, None, false)
+                .unwrap();
+        manager
+            .refresh_limiter
+            .lock()
+            .record_attempt(1, Instant::now());
+
+        // 最小间隔内不请求上游，直接复用现有 Token
+        let creds = manager.refresh_credential_limited(1, cred).await.unwrap();
+        assert_eq!(creds.access_token.as_deref(), Some("old-token"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_limited_errors_with_expired_token_within_interval() {
+        let mut cred = cred_with(1, &"a".repeat(150));
+        cred.access_token = Some("old-token".to_string());
+        cred.expires_at = Some((Utc::now() - Duration::minutes(1)).to_rfc3339());
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![cred.clone()], None, None, false)
+                .unwrap();
+        manager
+            .refresh_limiter
+            .lock()
+            .record_attempt(1, Instant::now());
+
+        // 已过期的 Token 不再复用，返回错误以便切换凭据
+        let err = manager
+            .refresh_credential_limited(1, cred)
+            .await
+            .unwrap_err();
+        assert!(
+            err.downcast_ref::<crate::kiro::refresh_limiter::RefreshLimitError>()
+                .is_some()
         );
     }
 
     #[tokio::test]
-    async fn test_add_credential_reject_duplicate_refresh_token() {
-        let config = Config::default();
+    async fn test_refresh_limited_errors_without_token_during_backoff() {
+        let cred = cred_with(1, &"a".repeat(150));
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![cred.clone()], None, None, false)
+                .unwrap();
+        manager.refresh_limiter.lock().record_rate_limited(
+            1,
+            Instant::now(),
+            Some(StdDuration::from_secs(600)),
+        );
 
-        let mut existing = KiroCredentials::default();
-        existing.refresh_token = Some("a".repeat(150));
+        let err = manager
+            .refresh_credential_limited(1, cred)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::kiro::refresh_limiter::RefreshLimitError>(),
+            Some(crate::kiro::refresh_limiter::RefreshLimitError::Backoff { id: 1, .. })
+        ));
 
-        let manager = MultiTokenManager::new(config, vec![existing], None, None, false).unwrap();
+        let snapshot = manager.snapshot();
+        assert!(snapshot.entries[0].refresh_backoff_secs.unwrap() > 590);
+    }
 
-        let mut duplicate = KiroCredentials::default();
-        duplicate.refresh_token = Some("a".repeat(150));
+    #[test]
+    fn test_stats_import_rejects_unknown_version() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![cred_with(1, "token-a")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let export = StatsExport {
+            version: STATS_EXPORT_VERSION + 1,
+            entries: Vec::new(),
+        };
+        assert!(manager.import_stats(export).is_err());
+    }
 
-        let result = manager.add_credential(duplicate).await;
-        assert!(result.is_err());
-        assert!(result.err().unwrap().to_string().contains("凭据已存在"));
+    #[test]
+    fn test_runtime_state_export_import_round_trip() {
+        let credentials = vec![
+            cred_with(1, "token-a"),
+            cred_with(2, "token-b"),
+            cred_with(3, "token-c"),
+        ];
+        let mut config = Config::default();
+        config.auth_failure_threshold = 1;
+        let source =
+            MultiTokenManager::new(config.clone(), credentials.clone(), None, None, false).unwrap();
+        source.report_success(&CallContext::for_test(1), 0);
+        source.report_success(&CallContext::for_test(1), 0);
+        source.report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth);
+        source.refresh_limiter.lock().record_rate_limited(
+            3,
+            Instant::now(),
+            Some(StdDuration::from_secs(300)),
+        );
+        source.switch_to_next();
+
+        let target = MultiTokenManager::new(config, credentials, None, None, false).unwrap();
+        let state = source.export_state();
+        // 经过 JSON 序列化，与 HTTP 传输一致
+        let state: TokenManagerState =
+            serde_json::from_value(serde_json::to_value(&state).unwrap()).unwrap();
+        let outcome = target.import_state(state);
+        assert_eq!(
+            outcome,
+            StateImportOutcome {
+                matched: 3,
+                skipped: 0
+            }
+        );
+
+        // 退避剩余秒数随时间变化，单独比较
+        let normalize = |manager: &MultiTokenManager| {
+            let snapshot = manager.snapshot();
+            let backoff = snapshot.entries[2].refresh_backoff_secs;
+            let mut value = serde_json::to_value(snapshot).unwrap();
+            for entry in value["entries"].as_array_mut().unwrap() {
+                entry.as_object_mut().unwrap().remove("refreshBackoffSecs");
+            }
+            (value, backoff)
+        };
+        let (source_value, source_backoff) = normalize(&source);
+        let (target_value, target_backoff) = normalize(&target);
+        assert_eq!(source_value, target_value);
+        assert_eq!(target_value["entries"][1]["disabled"], true);
+        assert!(source_backoff.unwrap().abs_diff(target_backoff.unwrap()) <= 1);
+        assert!(target_backoff.unwrap() > 290);
     }
 
-    // MultiTokenManager 测试
+    #[test]
+    fn test_runtime_state_import_skips_unknown_and_mismatched_credentials() {
+        let source = MultiTokenManager::new(
+            Config::default(),
+            vec![cred_with(1, "token-a"), cred_with(2, "token-b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        source.report_success(&CallContext::for_test(1), 0);
+        source.report_success(&CallContext::for_test(2), 0);
+
+        // #1 的 refreshToken 已更换，#2 在目标实例中不存在
+        let target = MultiTokenManager::new(
+            Config::default(),
+            vec![cred_with(1, "token-rotated"), cred_with(3, "token-c")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let outcome = target.import_state(source.export_state());
+        assert_eq!(
+            outcome,
+            StateImportOutcome {
+                matched: 0,
+                skipped: 2
+            }
+        );
+        assert!(
+            target
+                .snapshot()
+                .entries
+                .iter()
+                .all(|e| e.success_count == 0)
+        );
+    }
 
     #[test]
-    fn test_multi_token_manager_new() {
-        let config = Config::default();
-        let mut cred1 = KiroCredentials::default();
-        cred1.priority = 0;
-        let mut cred2 = KiroCredentials::default();
-        cred2.priority = 1;
+    fn test_estimated_cost_per_credential_and_persistence() {
+        use crate::model::config::ModelPrice;
 
-        let manager =
-            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
-        assert_eq!(manager.total_count(), 2);
-        assert_eq!(manager.available_count(), 2);
+        let dir = std::env::temp_dir().join(format!("kiro-cost-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials.json");
+        let credentials = vec![cred_with(1, "token-a"), cred_with(2, "token-b")];
+        std::fs::write(&path, serde_json::to_string(&credentials).unwrap()).unwrap();
+
+        let mut config = Config::default();
+        config.pricing = HashMap::from([
+            (
+                "claude-opus-*".to_string(),
+                ModelPrice {
+                    input_per_1k: 15.0,
+                    output_per_1k: 75.0,
+                    ..Default::default()
+                },
+            ),
+            (
+                "*".to_string(),
+                ModelPrice {
+                    per_request: 0.5,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let load = |config: Config| {
+            MultiTokenManager::new(config, credentials.clone(), None, Some(path.clone()), true)
+                .unwrap()
+        };
+
+        let manager = load(config.clone());
+        manager.record_usage("user_a", Some(1), "claude-opus-4-5", 1000, 1000);
+        manager.record_usage("user_a", Some(2), "claude-sonnet-4-5", 1000, 1000);
+        manager.record_usage("user_b", Some(2), "claude-sonnet-4-5", 0, 0);
+        // 未经上游（如 WebSearch）的请求只计入用户
+        manager.record_usage("user_b", None, "claude-opus-4-5", 2000, 0);
+
+        let snapshot = manager.snapshot();
+        let costs: Vec<Option<f64>> = snapshot.entries.iter().map(|e| e.estimated_cost).collect();
+        assert_eq!(costs, vec![Some(90.0), Some(1.0)]);
+        assert_eq!(snapshot.total_estimated_cost, Some(91.0));
+        let users = manager.user_usage();
+        assert_eq!(users[0].estimated_cost, Some(90.5));
+        assert_eq!(users[1].estimated_cost, Some(30.5));
+
+        manager.save_stats();
+        let reloaded = load(config);
+        assert_eq!(reloaded.snapshot().total_estimated_cost, Some(91.0));
+
+        // 未配置 pricing 时不输出费用字段
+        let unpriced = load(Config::default());
+        let snapshot = unpriced.snapshot();
+        assert_eq!(snapshot.total_estimated_cost, None);
+        assert_eq!(snapshot.entries[0].estimated_cost, None);
+        assert_eq!(unpriced.user_usage()[0].estimated_cost, None);
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert!(json.get("totalEstimatedCost").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    #[test]
-    fn test_multi_token_manager_empty_credentials() {
-        let config = Config::default();
-        let result = MultiTokenManager::new(config, vec![], None, None, false);
-        // 支持 0 个凭据启动（可通过管理面板添加）
-        assert!(result.is_ok());
-        let manager = result.unwrap();
-        assert_eq!(manager.total_count(), 0);
-        assert_eq!(manager.available_count(), 0);
+    fn limited_cred(id: u64, refresh_token: &str, limit: MonthlyRequestLimit) -> KiroCredentials {
+        KiroCredentials {
+            monthly_request_limit: Some(limit),
+            ..cred_with(id, refresh_token)
+        }
     }
 
     #[test]
-    fn test_multi_token_manager_duplicate_ids() {
-        let config = Config::default();
-        let mut cred1 = KiroCredentials::default();
-        cred1.id = Some(1);
-        let mut cred2 = KiroCredentials::default();
-        cred2.id = Some(1); // 重复 ID
+    fn test_monthly_request_limit_thresholds_and_rollover() {
+        let limit = MonthlyRequestLimit {
+            soft_limit: Some(2),
+            hard_limit: Some(3),
+            timezone: Some("+08:00".to_string()),
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![limited_cred(1, "token-a", limit), cred_with(2, "token-b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let state = |now: DateTime<Utc>| {
+            let entries = manager.entries.lock();
+            (
+                entries[0].disabled,
+                entries[0].disabled_reason,
+                entries[0].monthly_count(now),
+            )
+        };
+        let kinds = || -> Vec<AdminEventKind> { manager.events().iter().map(|e| e.kind).collect() };
 
-        let result = MultiTokenManager::new(config, vec![cred1, cred2], None, None, false);
-        assert!(result.is_err());
-        let err_msg = result.err().unwrap().to_string();
-        assert!(
-            err_msg.contains("重复的凭据 ID"),
-            "错误消息应包含 '重复的凭据 ID'，实际: {}",
-            err_msg
+        // UTC 1 月 31 日 15:00，即 +08:00 时区的 1 月 31 日 23:00
+        let now: DateTime<Utc> = "2026-01-31T15:00:00Z".parse().unwrap();
+        manager.record_success(1, None, now);
+        assert_eq!(state(now), (false, None, 1));
+        assert!(manager.events().is_empty());
+
+        manager.record_success(1, None, now);
+        assert_eq!(state(now), (false, None, 2));
+        assert_eq!(kinds(), vec![AdminEventKind::SoftLimitReached]);
+
+        manager.record_success(1, None, now);
+        assert_eq!(
+            state(now),
+            (true, Some(DisabledReason::LocalLimitReached), 3)
+        );
+        assert_eq!(
+            kinds(),
+            vec![
+                AdminEventKind::SoftLimitReached,
+                AdminEventKind::HardLimitReached
+            ]
+        );
+        assert_eq!(manager.events()[1].credential_id, 1);
+        // 未配置上限的凭据只计数
+        manager.record_success(2, None, now);
+        assert_eq!(manager.entries.lock()[1].monthly_count(now), 1);
+        assert_eq!(manager.events().len(), 2);
+
+        // 暂停状态不写回凭据文件
+        let json: serde_json::Value =
+            serde_json::from_str(&credentials_json(&manager.entries.lock()).unwrap()).unwrap();
+        assert_eq!(json[0]["disabled"], false);
+
+        // 同一月份内不恢复
+        let same_month: DateTime<Utc> = "2026-01-31T15:59:59Z".parse().unwrap();
+        manager.apply_monthly_limits(same_month);
+        assert_eq!(
+            state(same_month),
+            (true, Some(DisabledReason::LocalLimitReached), 3)
+        );
+
+        // +08:00 时区已进入 2 月（UTC 仍为 1 月）：计数归零并恢复使用
+        let next_month: DateTime<Utc> = "2026-01-31T16:00:00Z".parse().unwrap();
+        manager.apply_monthly_limits(next_month);
+        assert_eq!(state(next_month), (false, None, 0));
+        assert_eq!(kinds().last(), Some(&AdminEventKind::LimitCleared));
+
+        manager.record_success(1, None, next_month);
+        assert_eq!(
+            manager.entries.lock()[0].monthly_requests,
+            Some(MonthlyRequestCount {
+                month: "2026-02".to_string(),
+                count: 1
+            })
         );
     }
 
     #[test]
-    fn test_multi_token_manager_report_failure() {
-        let config = Config::default();
-        let cred1 = KiroCredentials::default();
-        let cred2 = KiroCredentials::default();
+    fn test_monthly_request_count_persistence() {
+        let dir = std::env::temp_dir().join(format!("kiro-monthly-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials.json");
+        let limit = MonthlyRequestLimit {
+            hard_limit: Some(1),
+            ..Default::default()
+        };
+        let credentials = vec![limited_cred(1, "token-a", limit), cred_with(2, "token-b")];
+        std::fs::write(&path, serde_json::to_string(&credentials).unwrap()).unwrap();
+        let load = || {
+            MultiTokenManager::new(
+                Config::default(),
+                credentials.clone(),
+                None,
+                Some(path.clone()),
+                true,
+            )
+            .unwrap()
+        };
 
-        let manager =
-            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+        let manager = load();
+        manager.report_success(&CallContext::for_test(1), 10);
+        let snapshot = manager.snapshot();
+        let entry = &snapshot.entries[0];
+        assert!(entry.disabled);
+        assert_eq!(
+            entry.disabled_reason,
+            Some(DisabledReason::LocalLimitReached)
+        );
+        assert_eq!(entry.monthly_requests, 1);
+        assert_eq!(
+            entry.monthly_request_limit.as_ref().unwrap().hard_limit,
+            Some(1)
+        );
+        assert_eq!(snapshot.available, 1);
 
-        // 凭据会自动分配 ID（从 1 开始）
-        // 前两次失败不会禁用（使用 ID 1）
-        assert!(manager.report_failure(1));
-        assert!(manager.report_failure(1));
-        assert_eq!(manager.available_count(), 2);
+        manager.write_credentials_now().unwrap();
+        let on_disk: Vec<KiroCredentials> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(!on_disk[0].disabled);
 
-        // 第三次失败会禁用第一个凭据
-        assert!(manager.report_failure(1));
-        assert_eq!(manager.available_count(), 1);
+        // 重启后从统计数据恢复计数，当月仍处于暂停状态
+        manager.save_stats();
+        let reloaded = load();
+        let entry = &reloaded.snapshot().entries[0];
+        assert_eq!(
+            entry.disabled_reason,
+            Some(DisabledReason::LocalLimitReached)
+        );
+        assert_eq!(entry.monthly_requests, 1);
 
-        // 继续失败第二个凭据（使用 ID 2）
-        assert!(manager.report_failure(2));
-        assert!(manager.report_failure(2));
-        assert!(!manager.report_failure(2)); // 所有凭据都禁用了
-        assert_eq!(manager.available_count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn stats_test_manager(dir: &Path) -> MultiTokenManager {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("credentials.json");
+        let credentials = vec![cred_with(1, "token-a")];
+        std::fs::write(&path, serde_json::to_string(&credentials).unwrap()).unwrap();
+        MultiTokenManager::new(Config::default(), credentials, None, Some(path), true).unwrap()
+    }
+
+    fn saved_success_count(dir: &Path) -> Option<u64> {
+        let content = std::fs::read_to_string(dir.join("kiro_stats.json")).ok()?;
+        let stats: HashMap<String, StatsEntry> = serde_json::from_str(&content).ok()?;
+        Some(stats.get("1")?.success_count)
     }
 
     #[test]
-    fn test_multi_token_manager_report_success() {
-        let config = Config::default();
-        let cred = KiroCredentials::default();
+    fn test_save_stats_without_runtime_falls_back_to_sync_write() {
+        let dir = std::env::temp_dir().join(format!("kiro-stats-sync-{}", uuid::Uuid::new_v4()));
+        let manager = stats_test_manager(&dir);
 
-        let manager = MultiTokenManager::new(config, vec![cred], None, None, false).unwrap();
+        // 运行时外的 debounce 落盘同步写入
+        manager.report_success(&CallContext::for_test(1), 10);
+        assert_eq!(saved_success_count(&dir), Some(1));
 
-        // 失败两次（使用 ID 1）
-        manager.report_failure(1);
-        manager.report_failure(1);
+        // debounce 窗口内的更新由 Drop 同步写入
+        manager.report_success(&CallContext::for_test(1), 10);
+        assert!(manager.stats_dirty.load(Ordering::Relaxed));
+        drop(manager);
+        assert_eq!(saved_success_count(&dir), Some(2));
 
-        // 成功后重置计数（使用 ID 1）
-        manager.report_success(1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        // 再失败两次不会禁用
-        manager.report_failure(1);
-        manager.report_failure(1);
-        assert_eq!(manager.available_count(), 1);
+    #[tokio::test]
+    async fn test_save_stats_in_runtime_writes_asynchronously() {
+        let dir = std::env::temp_dir().join(format!("kiro-stats-async-{}", uuid::Uuid::new_v4()));
+        let manager = stats_test_manager(&dir);
+
+        manager.report_success(&CallContext::for_test(1), 10);
+        // 并发派发的第二个任务与第一个任务串行写入
+        manager.save_stats();
+        for _ in 0..100 {
+            if !manager.stats_dirty.load(Ordering::Relaxed) {
+                break;
+            }
+            tokio::time::sleep(StdDuration::from_millis(10)).await;
+        }
+        assert!(!manager.stats_dirty.load(Ordering::Relaxed));
+        for _ in 0..100 {
+            if saved_success_count(&dir) == Some(1) {
+                break;
+            }
+            tokio::time::sleep(StdDuration::from_millis(10)).await;
+        }
+        assert_eq!(saved_success_count(&dir), Some(1));
+
+        // 在运行时内 drop 也不会 panic
+        drop(manager);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_multi_token_manager_switch_to_next() {
-        let config = Config::default();
-        let mut cred1 = KiroCredentials::default();
-        cred1.refresh_token = Some("token1".to_string());
-        let mut cred2 = KiroCredentials::default();
-        cred2.refresh_token = Some("token2".to_string());
+    fn test_stats_writer_skips_stale_snapshot() {
+        let dir = std::env::temp_dir().join(format!("kiro-stats-order-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kiro_stats.json");
+        let writer = StatsWriter::default();
+        let file = |json: &str| -> StatsFiles {
+            [
+                ("统计缓存", Some((path.clone(), json.to_string()))),
+                ("用户用量统计", None),
+            ]
+        };
 
-        let manager =
-            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+        let (old_seq, old_files) = writer.snapshot(|| file("old"));
+        let (new_seq, new_files) = writer.snapshot(|| file("new"));
+        assert!(old_seq < new_seq);
 
-        // 初始是第一个凭据
-        assert_eq!(
-            manager.credentials().refresh_token,
-            Some("token1".to_string())
-        );
+        // 较新的快照先写入，较旧的快照随后到达时跳过
+        assert!(writer.write(new_seq, new_files));
+        assert!(writer.write(old_seq, old_files));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
 
-        // 切换到下一个
-        assert!(manager.switch_to_next());
-        assert_eq!(
-            manager.credentials().refresh_token,
-            Some("token2".to_string())
-        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_later_rfc3339() {
+        let early = Some("2025-01-01T00:00:00Z".to_string());
+        let late = Some("2025-06-01T00:00:00+08:00".to_string());
+        assert_eq!(later_rfc3339(early.clone(), late.clone()), late);
+        assert_eq!(later_rfc3339(late.clone(), early.clone()), late);
+        assert_eq!(later_rfc3339(None, early.clone()), early);
+        assert_eq!(later_rfc3339(early.clone(), None), early);
     }
 
     #[test]
@@ -1914,10 +5800,10 @@ mod tests {
 
         // 凭据会自动分配 ID（从 1 开始）
         for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
-            manager.report_failure(1);
+            manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
         }
         for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
-            manager.report_failure(2);
+            manager.report_failure(&CallContext::for_test(2), FailureKind::UpstreamAuth);
         }
 
         assert_eq!(manager.available_count(), 0);
@@ -1939,14 +5825,101 @@ mod tests {
 
         // 凭据会自动分配 ID（从 1 开始）
         assert_eq!(manager.available_count(), 2);
-        assert!(manager.report_quota_exhausted(1));
+        assert!(manager.report_quota_exhausted(1, 1.0));
         assert_eq!(manager.available_count(), 1);
 
         // 再禁用第二个后，无可用凭据
-        assert!(!manager.report_quota_exhausted(2));
+        assert!(!manager.report_quota_exhausted(2, 1.0));
         assert_eq!(manager.available_count(), 0);
     }
 
+    #[tokio::test]
+    async fn test_usage_limits_at_full_usage_disables_credential() {
+        let credentials = |seed: &str| KiroCredentials {
+            access_token: Some("access".to_string()),
+            refresh_token: Some(seed.repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![credentials("a"), credentials("b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let usage = |current: f64| -> anyhow::Result<UsageLimitsResponse> {
+            Ok(serde_json::from_value(serde_json::json!({
+                "usageBreakdownList": [{
+                    "currentUsageWithPrecision": current,
+                    "usageLimitWithPrecision": 100.0
+                }]
+            }))
+            .unwrap())
+        };
+        manager.stub_usage_limits(vec![usage(99.0), usage(100.0), usage(100.0)]);
+
+        // 未用尽时不禁用
+        manager.get_usage_limits_for(1).await.unwrap();
+        assert_eq!(manager.available_count(), 2);
+
+        manager.invalidate_usage_cache(1);
+        let usage_limits = manager.get_usage_limits_for(1).await.unwrap();
+        assert!(usage_limits.is_quota_exhausted(None));
+        let entry = manager.snapshot().entries[0].clone();
+        assert!(entry.disabled);
+        assert_eq!(entry.disabled_reason, Some(DisabledReason::QuotaExceeded));
+        assert_eq!(entry.quota_exhausted_at_percentage, Some(1.0));
+        assert_eq!(manager.available_count(), 1);
+        assert_eq!(manager.snapshot().current_id, 2);
+
+        // 已禁用的凭据再次查询不会重复处理
+        let event_count = manager.events().len();
+        manager.invalidate_usage_cache(1);
+        manager.get_usage_limits_for(1).await.unwrap();
+        assert_eq!(manager.available_count(), 1);
+        assert_eq!(manager.events().len(), event_count);
+
+        // 重新启用后不再展示额度用尽比例
+        manager.set_disabled(1, false).unwrap();
+        assert_eq!(
+            manager.snapshot().entries[0].quota_exhausted_at_percentage,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_disable_invalidates_usage_cache() {
+        let credentials = |seed: &str| KiroCredentials {
+            access_token: Some("access".to_string()),
+            refresh_token: Some(seed.repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![credentials("a"), credentials("b")],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        manager.stub_usage_limits(vec![Ok(serde_json::from_value(serde_json::json!({
+            "usageBreakdownList": [{
+                "currentUsageWithPrecision": 10.0,
+                "usageLimitWithPrecision": 100.0
+            }]
+        }))
+        .unwrap())]);
+        manager.get_usage_limits_for(1).await.unwrap();
+        assert!(manager.cached_usage_limits(1).is_some());
+
+        manager.report_failure(&CallContext::for_test(1), FailureKind::UpstreamAuth);
+        assert!(manager.snapshot().entries[0].disabled);
+        assert!(manager.cached_usage_limits(1).is_none());
+    }
+
     #[tokio::test]
     async fn test_multi_token_manager_quota_disabled_is_not_auto_recovered() {
         let config = Config::default();
@@ -1956,8 +5929,8 @@ mod tests {
         let manager =
             MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
 
-        manager.report_quota_exhausted(1);
-        manager.report_quota_exhausted(2);
+        manager.report_quota_exhausted(1, 1.0);
+        manager.report_quota_exhausted(2, 1.0);
         assert_eq!(manager.available_count(), 0);
 
         let err = manager.acquire_context(None).await.err().unwrap().to_string();
@@ -1969,6 +5942,96 @@ mod tests {
         assert_eq!(manager.available_count(), 0);
     }
 
+    /// 将所有凭据标记为因连续失败被自动禁用
+    fn disable_all_for_failures(manager: &MultiTokenManager) {
+        for e in manager.entries.lock().iter_mut() {
+            e.disabled = true;
+            e.disabled_reason = Some(DisabledReason::TooManyFailures);
+            e.failure_count = MAX_FAILURES_PER_CREDENTIAL;
+        }
+    }
+
+    fn self_heal_manager(mode: AutoRecoverMode) -> MultiTokenManager {
+        let mut config = Config::default();
+        config.auto_recover_all_disabled = mode;
+        let creds = vec![KiroCredentials::default(), KiroCredentials::default()];
+        MultiTokenManager::new(config, creds, None, None, false).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_self_heal_never_rejects_immediately() {
+        let manager = self_heal_manager(AutoRecoverMode::Never);
+        disable_all_for_failures(&manager);
+
+        let err = manager
+            .acquire_context(None)
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("所有凭据均已禁用"), "实际: {}", err);
+        assert_eq!(manager.available_count(), 0);
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.auto_recover_all_disabled, AutoRecoverMode::Never);
+        assert!(snapshot.last_self_heal_at.is_none());
+    }
+
+    #[test]
+    fn test_self_heal_always_recovers_every_time() {
+        let manager = self_heal_manager(AutoRecoverMode::Always);
+        let t0 = Utc::now();
+        assert!(!manager.try_self_heal(t0), "没有被自动禁用的凭据时不自愈");
+
+        for offset in [0, 1, 2] {
+            disable_all_for_failures(&manager);
+            assert!(manager.try_self_heal(t0 + Duration::seconds(offset)));
+            assert_eq!(manager.available_count(), 2);
+        }
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.auto_recover_all_disabled, AutoRecoverMode::Always);
+        assert_eq!(
+            snapshot.last_self_heal_at,
+            Some((t0 + Duration::seconds(2)).to_rfc3339())
+        );
+    }
+
+    #[test]
+    fn test_self_heal_with_backoff_interval_grows() {
+        let manager = self_heal_manager(AutoRecoverMode::WithBackoff);
+        let t0 = Utc::now();
+        let at = |secs: i64| t0 + Duration::seconds(secs);
+
+        disable_all_for_failures(&manager);
+        assert!(manager.try_self_heal(at(0)));
+
+        // 第一次自愈后间隔 30 秒，之后依次翻倍
+        disable_all_for_failures(&manager);
+        assert!(!manager.try_self_heal(at(29)));
+        assert_eq!(manager.available_count(), 0);
+        assert!(manager.try_self_heal(at(30)));
+
+        disable_all_for_failures(&manager);
+        assert!(!manager.try_self_heal(at(30 + 59)));
+        assert!(manager.try_self_heal(at(30 + 60)));
+
+        disable_all_for_failures(&manager);
+        assert!(!manager.try_self_heal(at(90 + 119)));
+        assert!(manager.try_self_heal(at(90 + 120)));
+        assert_eq!(
+            manager.snapshot().last_self_heal_at,
+            Some(at(210).to_rfc3339())
+        );
+
+        // 有请求成功后恢复初始间隔
+        manager.record_success(1, None, at(211));
+        disable_all_for_failures(&manager);
+        assert!(!manager.try_self_heal(at(210 + 29)));
+        assert!(manager.try_self_heal(at(210 + 30)));
+
+        assert_eq!(self_heal_backoff(1), SELF_HEAL_BACKOFF_BASE);
+        assert_eq!(self_heal_backoff(100), SELF_HEAL_BACKOFF_MAX);
+    }
+
     // ============ 凭据级 Region 优先级测试 ============
 
     #[test]
@@ -2121,4 +6184,323 @@ mod tests {
         assert_eq!(credentials.effective_auth_region(&config), "auth-only");
         assert_eq!(credentials.effective_api_region(&config), "api-only");
     }
+
+    #[tokio::test]
+    async fn test_refresh_history_capped() {
+        let mut config = Config::default();
+        config.min_refresh_interval_secs = 0;
+        let expiring = || KiroCredentials {
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::minutes(7)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(config, vec![expiring()], None, None, false).unwrap();
+        manager.stub_refresh_results(
+            (0..REFRESH_HISTORY_CAPACITY + 5)
+                .map(|_| Ok(expiring()))
+                .collect(),
+        );
+        for _ in 0..REFRESH_HISTORY_CAPACITY + 5 {
+            manager.acquire_context(None).await.unwrap();
+        }
+        assert_eq!(
+            manager.refresh_history(1).unwrap().len(),
+            REFRESH_HISTORY_CAPACITY
+        );
+    }
+
+    /// 凭据即将过期（下次取用时刷新）且以数组格式持久化的管理器
+    fn rotation_manager(dir: &Path) -> (MultiTokenManager, PathBuf) {
+        let mut config = Config::default();
+        config.min_refresh_interval_secs = 0;
+        let credentials = vec![KiroCredentials {
+            id: Some(1),
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::minutes(7)).to_rfc3339()),
+            ..Default::default()
+        }];
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("credentials.json");
+        std::fs::write(&path, serde_json::to_string(&credentials).unwrap()).unwrap();
+        let manager =
+            MultiTokenManager::new(config, credentials, None, Some(path.clone()), true).unwrap();
+        (manager, path)
+    }
+
+    fn refreshed_with(refresh_token: String) -> anyhow::Result<KiroCredentials> {
+        Ok(KiroCredentials {
+            id: Some(1),
+            access_token: Some("new-access".to_string()),
+            refresh_token: Some(refresh_token),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        })
+    }
+
+    /// 将凭据文件替换为同名目录，使后续回写失败
+    fn break_credentials_file(path: &Path) {
+        std::fs::remove_file(path).unwrap();
+        std::fs::create_dir(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_rotation_is_persisted() {
+        let dir = std::env::temp_dir().join(format!("kiro-rotation-{}", uuid::Uuid::new_v4()));
+        let (manager, path) = rotation_manager(&dir);
+        manager.stub_refresh_results(vec![refreshed_with("b".repeat(150))]);
+
+        manager.acquire_context(None).await.unwrap();
+
+        let saved: Vec<KiroCredentials> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0].refresh_token, Some("b".repeat(150)));
+        let entry = &manager.snapshot().entries[0];
+        assert!(!entry.rotation_unpersisted);
+        assert_eq!(entry.refresh_rotations, 1);
+        assert!(manager.refresh_history(1).unwrap()[0].rotated);
+        assert!(manager.events().is_empty());
+        assert!(!dir.join("kiro_rotated_credential_1.json").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_rotation_persist_failure_writes_emergency_copy() {
+        let dir = std::env::temp_dir().join(format!("kiro-rotation-{}", uuid::Uuid::new_v4()));
+        let (manager, path) = rotation_manager(&dir);
+        break_credentials_file(&path);
+        manager.stub_refresh_results(vec![refreshed_with("b".repeat(150))]);
+
+        // 回写失败不影响本次请求，新令牌保留在内存中
+        manager.acquire_context(None).await.unwrap();
+        assert_eq!(
+            manager.credentials_of(1).unwrap().refresh_token,
+            Some("b".repeat(150))
+        );
+
+        let entry = &manager.snapshot().entries[0];
+        assert!(entry.rotation_unpersisted);
+        assert_eq!(entry.refresh_rotations, 1);
+        let events = manager.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, AdminEventKind::RotationUnpersisted);
+        assert_eq!(events[0].credential_id, 1);
+
+        let emergency: KiroCredentials = serde_json::from_str(
+            &std::fs::read_to_string(dir.join("kiro_rotated_credential_1.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(emergency.refresh_token, Some("b".repeat(150)));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(dir.join("kiro_rotated_credential_1.json")).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        // 之后任意一次回写成功即清除标记
+        std::fs::remove_dir(&path).unwrap();
+        manager.persist_credentials().unwrap();
+        manager.flush_credentials().await.unwrap();
+        assert!(!manager.snapshot().entries[0].rotation_unpersisted);
+        let saved: Vec<KiroCredentials> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0].refresh_token, Some("b".repeat(150)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_without_rotation_leaves_flag_clear() {
+        let dir = std::env::temp_dir().join(format!("kiro-rotation-{}", uuid::Uuid::new_v4()));
+        let (manager, path) = rotation_manager(&dir);
+        // 未轮换时回写失败只记录警告
+        break_credentials_file(&path);
+        manager.stub_refresh_results(vec![refreshed_with("a".repeat(150))]);
+
+        manager.acquire_context(None).await.unwrap();
+
+        let entry = &manager.snapshot().entries[0];
+        assert!(!entry.rotation_unpersisted);
+        assert_eq!(entry.refresh_rotations, 0);
+        assert!(!manager.refresh_history(1).unwrap()[0].rotated);
+        assert!(manager.events().is_empty());
+        assert!(!dir.join("kiro_rotated_credential_1.json").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_acquire_context_refresh_lock_timeout() {
+        let mut config = Config::default();
+        config.token_refresh_lock_timeout_secs = 2;
+        let credentials = KiroCredentials {
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::minutes(5)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager = std::sync::Arc::new(
+            MultiTokenManager::new(config, vec![credentials], None, None, false).unwrap(),
+        );
+
+        // 模拟另一个请求的刷新被上游挂起：持有刷新锁 5 秒
+        let (locked_tx, locked_rx) = tokio::sync::oneshot::channel();
+        let holder = manager.clone();
+        tokio::spawn(async move {
+            let _guard = holder.refresh_lock.lock().await;
+            let _ = locked_tx.send(());
+            tokio::time::sleep(StdDuration::from_secs(5)).await;
+        });
+        locked_rx.await.unwrap();
+
+        let before = metrics::refresh_lock_timeouts(1);
+        let started = Instant::now();
+        let err = match manager.acquire_context(None).await {
+            Ok(_) => panic!("刷新锁超时时不应获取到上下文"),
+            Err(e) => e,
+        };
+        assert!(started.elapsed() < StdDuration::from_secs(3));
+        let timeout = err.downcast_ref::<RefreshLockTimeout>().unwrap();
+        assert_eq!(timeout.id, 1);
+        assert!(format!("{:#}", err).contains("Token refresh lock timeout after 2s"));
+        assert_eq!(metrics::refresh_lock_timeouts(1), before + 1);
+        // 刷新锁超时不计入失败次数
+        assert_eq!(manager.snapshot().entries[0].failure_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_liveness_checker_disables_dead_credentials() {
+        let mut config = Config::default();
+        config.liveness_check_idle_threshold_secs = 0;
+        let healthy = KiroCredentials {
+            access_token: Some("token".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        // 刷新令牌格式无效
+        let invalid = KiroCredentials {
+            refresh_token: Some("short".to_string()),
+            ..healthy.clone()
+        };
+        // Token 已过期，刷新端点返回 401（令牌已被撤销）
+        let revoked = KiroCredentials {
+            expires_at: Some((Utc::now() - Duration::minutes(5)).to_rfc3339()),
+            ..healthy.clone()
+        };
+        let manager = Arc::new(
+            MultiTokenManager::new(config, vec![invalid, revoked, healthy], None, None, false)
+                .unwrap(),
+        );
+        manager.stub_refresh_results(vec![Err(RefreshAuthenticationFailed {
+            message: "IdC 凭证已过期或无效，需要重新认证: 401".to_string(),
+        }
+        .into())]);
+
+        let interval = StdDuration::from_millis(100);
+        let checker = manager.start_liveness_checker(interval);
+        tokio::time::sleep(interval * 2).await;
+        checker.abort();
+
+        let disabled: Vec<bool> = manager
+            .snapshot()
+            .entries
+            .iter()
+            .map(|e| e.disabled)
+            .collect();
+        assert_eq!(disabled, vec![true, true, false]);
+        // 当前凭据被禁用后切换到仍可用的凭据
+        assert_eq!(*manager.current_id.lock(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_context_for_id() {
+        let valid = || KiroCredentials {
+            access_token: Some("token".to_string()),
+            expires_at: Some((Utc::now() + Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![valid(), valid()], None, None, false)
+                .unwrap();
+
+        let ctx = manager.acquire_context_for_id(2).await.unwrap();
+        assert_eq!(ctx.id, 2);
+        // 不影响正常负载均衡的当前凭据
+        assert_eq!(*manager.current_id.lock(), 1);
+
+        manager.set_disabled(2, true).unwrap();
+        assert!(manager.acquire_context_for_id(2).await.is_err());
+        assert!(manager.acquire_context_for_id(9).await.is_err());
+    }
+
+    #[test]
+    fn test_preview_context_does_not_refresh_or_switch() {
+        // 已过期的凭据：预览不会触发刷新，直接返回现有 Token
+        let expired = KiroCredentials {
+            access_token: Some("stale".to_string()),
+            refresh_token: Some("a".repeat(150)),
+            expires_at: Some((Utc::now() - Duration::hours(1)).to_rfc3339()),
+            priority: 1,
+            ..Default::default()
+        };
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![expired, KiroCredentials::default()],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        manager.set_disabled(2, true).unwrap();
+
+        let ctx = manager.preview_context(None, None).unwrap();
+        assert_eq!(ctx.id, 1);
+        assert_eq!(ctx.token, "stale");
+        assert!(manager.refresh_history(1).unwrap().is_empty());
+
+        assert_eq!(manager.preview_context(None, Some(1)).unwrap().id, 1);
+        assert!(manager.preview_context(None, Some(2)).is_err());
+        assert!(manager.preview_context(None, Some(9)).is_err());
+    }
+
+    #[test]
+    fn test_secondary_mode_skips_persistence() {
+        let dir = std::env::temp_dir().join(format!("kiro-secondary-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials.json");
+        let original = r#"[{"id":1,"refreshToken":"token","machineId":"m1"}]"#;
+        std::fs::write(&path, original).unwrap();
+
+        let credentials = vec![KiroCredentials {
+            id: Some(1),
+            refresh_token: Some("token".to_string()),
+            machine_id: Some("m1".to_string()),
+            ..Default::default()
+        }];
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            credentials,
+            None,
+            Some(path.clone()),
+            true,
+        )
+        .unwrap();
+        manager.enter_secondary_mode();
+        assert!(manager.is_secondary());
+
+        // 内存状态照常更新，但不回写凭据文件
+        manager.set_priority(1, 5).unwrap();
+        assert_eq!(manager.snapshot().entries[0].priority, 5);
+        assert!(!manager.persist_credentials().unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+
+        manager.save_stats();
+        assert!(!dir.join("kiro_stats.json").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,450 @@
+//! 按用户统计的请求用量
+//!
+//! 用户由请求 `metadata.user_id` 中稳定的部分（`__session` 之前）标识，缺失时归入 `anonymous`：
+//! - 每个请求进入时累加请求数，并按 UTC 自然日检查 `userLimits` 配置的每日请求上限
+//! - 请求结束时累加输入/输出 tokens 与按 `pricing` 估算的费用
+//!
+//! 由 MultiTokenManager 持有，随统计数据一起按 debounce 策略持久化到 `kiro_user_usage.json`。
+//! 最多跟踪 [`MAX_TRACKED_USERS`] 个用户，超出时淘汰最久未请求的用户。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::kiro::token_manager::MultiTokenManager;
+
+/// 未携带 metadata.user_id 的请求归入的用户
+pub const ANONYMOUS_USER: &str = "anonymous";
+
+/// 用户用量文件名（位于凭据文件所在目录）
+const USER_USAGE_FILE: &str = "kiro_user_usage.json";
+
+/// 用户标识最大长度（字符数），超出部分截断
+const MAX_USER_KEY_CHARS: usize = 128;
+
+/// 最多跟踪的用户数（user_id 由客户端提供，需防止无限增长）
+const MAX_TRACKED_USERS: usize = 10_000;
+
+/// 从 metadata.user_id 中提取稳定的用户标识
+///
+/// 支持的格式：
+/// - `user_xxx_account__session_<uuid>`：取 `__session` 之前的部分
+/// - `user_xxx_session_<uuid>`：取 `_session_` 之前的部分
+/// - JSON 字符串（如 `{"account_uuid": "...", "session_id": "..."}`）：依次取 account_uuid / user_id / device_id
+/// - 其他：整体作为用户标识
+pub fn user_key(user_id: Option<&str>) -> String {
+    let Some(raw) = user_id.map(str::trim).filter(|s| !s.is_empty()) else {
+        return ANONYMOUS_USER.to_string();
+    };
+
+    let json = raw
+        .starts_with('{')
+        .then(|| serde_json::from_str::<serde_json::Value>(raw).ok())
+        .flatten();
+    if let Some(serde_json::Value::Object(obj)) = json {
+        let nested = ["account_uuid", "user_id", "device_id"]
+            .iter()
+            .find_map(|k| {
+                obj.get(*k)
+                    .and_then(|v| v.as_str())
+                    .filter(|s| !s.trim().is_empty())
+            });
+        return match nested {
+            Some(nested) => user_key(Some(nested)),
+            None => ANONYMOUS_USER.to_string(),
+        };
+    }
+
+    // ASCII 小写不改变字节偏移，可直接用于切片
+    let lower = raw.to_ascii_lowercase();
+    let stable = match lower.find("__session").or_else(|| lower.find("_session_")) {
+        Some(pos) => raw[..pos].trim_end_matches('_').trim(),
+        None => raw,
+    };
+    if stable.is_empty() {
+        return ANONYMOUS_USER.to_string();
+    }
+    stable.chars().take(MAX_USER_KEY_CHARS).collect()
+}
+
+/// 单个用户的累计用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsageEntry {
+    /// 累计请求数
+    pub request_count: u64,
+    /// 累计输入 tokens
+    pub input_tokens: u64,
+    /// 累计输出 tokens
+    pub output_tokens: u64,
+    /// 累计估算费用
+    #[serde(default)]
+    pub estimated_cost: f64,
+    /// 最近一次请求时间（RFC3339 格式）
+    pub last_request_at: Option<String>,
+    /// 当前每日窗口（UTC 日期）
+    pub daily_window: Option<NaiveDate>,
+    /// 当前每日窗口内的请求数
+    pub daily_requests: u64,
+}
+
+/// 用户用量快照（Admin API 使用）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsageSnapshot {
+    /// 用户标识
+    pub user_id: String,
+    /// 累计请求数
+    pub request_count: u64,
+    /// 累计输入 tokens
+    pub input_tokens: u64,
+    /// 累计输出 tokens
+    pub output_tokens: u64,
+    /// 最近一次请求时间（RFC3339 格式）
+    pub last_request_at: Option<String>,
+    /// 今日（UTC）请求数
+    pub daily_requests: u64,
+    /// 每日请求上限（未配置时为 None）
+    pub daily_limit: Option<u64>,
+    /// 累计估算费用（未配置 pricing 时为 None）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+}
+
+/// 用户超出每日请求上限
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserLimitExceeded {
+    /// 用户标识
+    pub user: String,
+    /// 每日请求上限
+    pub limit: u64,
+    /// 窗口重置时间
+    pub resets_at: DateTime<Utc>,
+}
+
+impl fmt::Display for UserLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "用户 {} 今日请求次数已达上限 {}，将于 {} 重置",
+            self.user,
+            self.limit,
+            self.resets_at.to_rfc3339()
+        )
+    }
+}
+
+impl std::error::Error for UserLimitExceeded {}
+
+/// 按用户标识索引的用量统计
+pub struct UserUsageTracker {
+    entries: Mutex<HashMap<String, UserUsageEntry>>,
+    path: Option<PathBuf>,
+    /// 最多跟踪的用户数
+    max_users: usize,
+}
+
+impl UserUsageTracker {
+    /// 从缓存目录加载；`cache_dir` 为 None 时不持久化
+    pub fn load(cache_dir: Option<PathBuf>) -> Self {
+        let path = cache_dir.map(|d| d.join(USER_USAGE_FILE));
+        let mut entries = match path.as_ref().map(std::fs::read_to_string) {
+            Some(Ok(content)) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("解析用户用量统计失败，将忽略: {}", e);
+                HashMap::new()
+            }),
+            _ => HashMap::new(),
+        };
+        while entries.len() > MAX_TRACKED_USERS {
+            evict_least_recent(&mut entries, None);
+        }
+        Self {
+            entries: Mutex::new(entries),
+            path,
+            max_users: MAX_TRACKED_USERS,
+        }
+    }
+
+    /// 获取用户条目，新用户超出上限时先淘汰最久未请求的用户
+    fn entry_mut<'a>(
+        &self,
+        entries: &'a mut HashMap<String, UserUsageEntry>,
+        user: &str,
+    ) -> &'a mut UserUsageEntry {
+        if !entries.contains_key(user) && entries.len() >= self.max_users {
+            evict_least_recent(entries, Some(user));
+        }
+        entries.entry(user.to_string()).or_default()
+    }
+
+    /// 序列化当前数据，返回 (文件路径, JSON)；不持久化或无数据时返回 None
+    ///
+    /// 由统计数据落盘器与凭据统计一起写入
+    pub fn snapshot_json(&self) -> Option<(PathBuf, String)> {
+        let entries = self.entries.lock();
+        self.serialize(&entries)
+    }
+
+    fn serialize(&self, entries: &HashMap<String, UserUsageEntry>) -> Option<(PathBuf, String)> {
+        let path = self.path.as_ref()?;
+        if entries.is_empty() {
+            return None;
+        }
+        match serde_json::to_string_pretty(entries) {
+            Ok(json) => Some((path.clone(), json)),
+            Err(e) => {
+                tracing::warn!("序列化用户用量统计失败: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 记录一次请求；`daily_limit` 为当日请求上限，达到上限时拒绝且不计数
+    ///
+    /// 每日窗口按 `now` 所在的 UTC 自然日计算，跨日后自动清零
+    pub fn try_admit(
+        &self,
+        user: &str,
+        daily_limit: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<(), UserLimitExceeded> {
+        let today = now.date_naive();
+        let mut entries = self.entries.lock();
+        let entry = self.entry_mut(&mut entries, user);
+        if entry.daily_window != Some(today) {
+            entry.daily_window = Some(today);
+            entry.daily_requests = 0;
+        }
+
+        if let Some(limit) = daily_limit.filter(|limit| entry.daily_requests >= *limit) {
+            let resets_at = today
+                .checked_add_days(Days::new(1))
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .map(|d| d.and_utc())
+                .unwrap_or(now);
+            return Err(UserLimitExceeded {
+                user: user.to_string(),
+                limit,
+                resets_at,
+            });
+        }
+
+        entry.request_count += 1;
+        entry.daily_requests += 1;
+        entry.last_request_at = Some(now.to_rfc3339());
+        Ok(())
+    }
+
+    /// 累加请求结束时的 token 用量与估算费用
+    pub fn record_tokens(&self, user: &str, input_tokens: u64, output_tokens: u64, cost: f64) {
+        let mut entries = self.entries.lock();
+        let entry = self.entry_mut(&mut entries, user);
+        entry.input_tokens += input_tokens;
+        entry.output_tokens += output_tokens;
+        entry.estimated_cost += cost;
+    }
+
+    /// 获取所有用户的用量快照（按用户标识排序，`include_cost` 为 false 时不输出费用）
+    pub fn snapshot(
+        &self,
+        limits: &HashMap<String, u64>,
+        include_cost: bool,
+        now: DateTime<Utc>,
+    ) -> Vec<UserUsageSnapshot> {
+        let today = now.date_naive();
+        let entries = self.entries.lock();
+        let mut users: Vec<UserUsageSnapshot> = entries
+            .iter()
+            .map(|(user, e)| UserUsageSnapshot {
+                user_id: user.clone(),
+                request_count: e.request_count,
+                input_tokens: e.input_tokens,
+                output_tokens: e.output_tokens,
+                last_request_at: e.last_request_at.clone(),
+                daily_requests: if e.daily_window == Some(today) {
+                    e.daily_requests
+                } else {
+                    0
+                },
+                daily_limit: limits.get(user).copied(),
+                estimated_cost: include_cost.then_some(e.estimated_cost),
+            })
+            .collect();
+        users.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        users
+    }
+}
+
+/// 淘汰最近一次请求时间最早的用户（无请求时间的视为最早，`keep` 不参与淘汰）
+fn evict_least_recent(entries: &mut HashMap<String, UserUsageEntry>, keep: Option<&str>) {
+    let oldest = entries
+        .iter()
+        .filter(|(user, _)| Some(user.as_str()) != keep)
+        .min_by_key(|(_, e)| {
+            e.last_request_at
+                .as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        })
+        .map(|(user, _)| user.clone());
+    if let Some(user) = oldest {
+        entries.remove(&user);
+    }
+}
+
+/// 请求结束时回写 token 用量与估算费用（每个请求最多记录一次）
+pub struct UserUsageRecorder {
+    manager: Arc<MultiTokenManager>,
+    user: String,
+    /// 请求的模型名（用于匹配 pricing）
+    model: String,
+    /// 实际处理请求的凭据（调用上游后设置）
+    credential_id: Option<u64>,
+}
+
+impl UserUsageRecorder {
+    pub fn new(manager: Arc<MultiTokenManager>, user: String, model: String) -> Self {
+        Self {
+            manager,
+            user,
+            model,
+            credential_id: None,
+        }
+    }
+
+    /// 设置实际处理请求的凭据（估算费用同时计入该凭据）
+    pub fn with_credential(mut self, credential_id: Option<u64>) -> Self {
+        self.credential_id = credential_id;
+        self
+    }
+
+    /// 记录本次请求的输入/输出 tokens
+    pub fn record(self, input_tokens: i32, output_tokens: i32) {
+        self.manager.record_usage(
+            &self.user,
+            self.credential_id,
+            &self.model,
+            input_tokens,
+            output_tokens,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_user_key_variants() {
+        let cases = [
+            (None, "anonymous"),
+            (Some(""), "anonymous"),
+            (Some("   "), "anonymous"),
+            (
+                Some("user_abc_account__session_0b4445e1-f5be-49e1-87ce-62bbc28ad705"),
+                "user_abc_account",
+            ),
+            (Some("user_abc_account__session_"), "user_abc_account"),
+            (Some("  user_abc__SESSION_xyz "), "user_abc"),
+            (Some("user_abc_session_8bb5523b"), "user_abc"),
+            (Some("user_abc"), "user_abc"),
+            (Some("__session_only"), "anonymous"),
+            (
+                Some(r#"{"device_id":"dev1","account_uuid":"acc-1","session_id":"s1"}"#),
+                "acc-1",
+            ),
+            (Some(r#"{"device_id":"dev1","account_uuid":""}"#), "dev1"),
+            (Some(r#"{"session_id":"s1"}"#), "anonymous"),
+            (Some("{not json"), "{not json"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(user_key(input), expected, "{:?}", input);
+        }
+
+        let long = "u".repeat(500);
+        assert_eq!(user_key(Some(&long)).len(), MAX_USER_KEY_CHARS);
+    }
+
+    #[test]
+    fn test_daily_limit_resets_on_next_day() {
+        let tracker = UserUsageTracker::load(None);
+        for hour in 0..3 {
+            assert!(tracker.try_admit("user_a", Some(3), at(1, hour)).is_ok());
+        }
+
+        let err = tracker.try_admit("user_a", Some(3), at(1, 23)).unwrap_err();
+        assert_eq!(err.limit, 3);
+        assert_eq!(err.resets_at, at(2, 0));
+        // 其他用户不受影响
+        assert!(tracker.try_admit("user_b", Some(3), at(1, 23)).is_ok());
+
+        // 跨日后窗口清零
+        assert!(tracker.try_admit("user_a", Some(3), at(2, 0)).is_ok());
+
+        let limits = HashMap::from([("user_a".to_string(), 3)]);
+        let users = tracker.snapshot(&limits, false, at(2, 1));
+        assert_eq!(users[0].user_id, "user_a");
+        assert_eq!(users[0].request_count, 4);
+        assert_eq!(users[0].daily_requests, 1);
+        assert_eq!(users[0].daily_limit, Some(3));
+        assert_eq!(users[1].daily_limit, None);
+        assert_eq!(users[0].estimated_cost, None);
+        // 快照按当前日期计算今日请求数
+        assert_eq!(users[1].daily_requests, 0);
+    }
+
+    #[test]
+    fn test_least_recent_user_evicted_at_capacity() {
+        let mut tracker = UserUsageTracker::load(None);
+        tracker.max_users = 2;
+        tracker.try_admit("user_a", None, at(1, 0)).unwrap();
+        tracker.try_admit("user_b", None, at(1, 1)).unwrap();
+        tracker.try_admit("user_a", None, at(1, 2)).unwrap();
+        // 已跟踪用户不触发淘汰
+        tracker.record_tokens("user_b", 10, 1, 0.0);
+
+        tracker.try_admit("user_c", None, at(1, 3)).unwrap();
+        let users: Vec<String> = tracker
+            .snapshot(&HashMap::new(), false, at(1, 4))
+            .into_iter()
+            .map(|u| u.user_id)
+            .collect();
+        assert_eq!(users, ["user_a", "user_c"]);
+
+        // 仅记录 tokens 的新用户同样受上限约束
+        tracker.record_tokens("user_d", 10, 1, 0.0);
+        assert_eq!(tracker.entries.lock().len(), 2);
+        assert!(tracker.entries.lock().contains_key("user_d"));
+    }
+
+    #[test]
+    fn test_record_tokens_and_persistence() {
+        let dir = std::env::temp_dir().join(format!("kiro-user-usage-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tracker = UserUsageTracker::load(Some(dir.clone()));
+        tracker.try_admit("anonymous", None, at(1, 0)).unwrap();
+        tracker.record_tokens("anonymous", 100, 20, 0.25);
+        tracker.record_tokens("anonymous", 50, 5, 0.5);
+
+        let (path, json) = tracker.snapshot_json().unwrap();
+        std::fs::write(path, json).unwrap();
+        let loaded = UserUsageTracker::load(Some(dir.clone()));
+        let users = loaded.snapshot(&HashMap::new(), true, at(1, 12));
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].input_tokens, 150);
+        assert_eq!(users[0].output_tokens, 25);
+        assert_eq!(users[0].daily_requests, 1);
+        assert_eq!(users[0].estimated_cost, Some(0.75));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+//! kiro-rs 的全部模块，供二进制入口（`main.rs`）与基准测试（`benches/`）共用
+
+pub mod admin;
+pub mod admin_ui;
+pub mod anthropic;
+pub mod common;
+pub mod dry_run;
+pub mod http_client;
+pub mod kiro;
+pub mod metrics;
+pub mod model;
+pub mod service;
+pub mod token;
+pub mod version;
@@ -1,54 +1,163 @@
-mod admin;
-mod admin_ui;
-mod anthropic;
-mod common;
-mod http_client;
-mod kiro;
-mod model;
-pub mod token;
+use kiro_rs::{
+    admin, admin_ui, anthropic, common, dry_run, http_client, kiro, model, service, token, version,
+};
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use admin::report::{BalanceReporter, ReportSchedule, ReportWebhook};
 use clap::Parser;
-use kiro::model::credentials::{CredentialsConfig, KiroCredentials};
+use common::instance_lock::{InstanceLock, LockAcquisition};
+use common::ip_allowlist::{IpAllowlist, ip_allowlist_middleware};
+use common::listener::{self, BindTarget};
+use common::rotating_file::RotatingFileWriter;
+use kiro::mock::MockProvider;
+use kiro::model::credentials::{
+    CredentialsConfig, CredentialsMigration, KiroCredentials, LogSafeCredentials,
+};
 use kiro::provider::KiroProvider;
 use kiro::token_manager::MultiTokenManager;
-use model::arg::Args;
+use model::arg::{Args, Command};
 use model::config::Config;
+use version::VersionInfo;
 
 #[tokio::main]
 async fn main() {
     // 解析命令行参数
     let args = Args::parse();
 
+    // 以服务方式运行时日志写入文件，需先加载配置以确定日志路径
+    let service_config = args
+        .service_mode()
+        .then(|| load_config(&config_path_from(&args)));
+
     // 初始化日志
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
+    init_logging(service_config.as_ref());
+    if service_config.is_some() {
+        service::start_dispatcher();
+    }
 
     // 加载配置
-    let config_path = args
-        .config
-        .unwrap_or_else(|| Config::default_config_path().to_string());
-    let config = Config::load(&config_path).unwrap_or_else(|e| {
-        tracing::error!("加载配置失败: {}", e);
-        std::process::exit(1);
-    });
+    let config_path = config_path_from(&args);
+
+    // 安装 / 卸载系统服务
+    if let Some(Command::InstallService { dry_run } | Command::UninstallService { dry_run }) =
+        &args.command
+    {
+        let result = match &args.command {
+            Some(Command::InstallService { .. }) => {
+                service::ServiceSpec::current(&config_path, &credentials_path_from(&args))
+                    .and_then(|spec| service::install(&spec, *dry_run))
+            }
+            _ => service::uninstall(*dry_run),
+        };
+        if let Err(e) = result {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // 部署自检：校验配置与凭据后退出
+    if args.dry_run || args.dry_run_full {
+        let credentials_path = credentials_path_from(&args);
+        let report = dry_run::run(&config_path, &credentials_path, args.dry_run_full).await;
+        print!("{}", report.render());
+        std::process::exit(report.exit_code());
+    }
+
+    let config = service_config.unwrap_or_else(|| load_config(&config_path));
+
+    let mock_mode = args.mock_upstream || config.mock_mode;
+    let version_info = VersionInfo::from_config(&config, mock_mode);
+    tracing::info!("{}", version_info.banner());
+
+    // 模拟上游模式：不获取实例锁、不加载凭据
+    if mock_mode {
+        run_mock_server(config, version_info).await;
+        return;
+    }
+
+    let credentials_path = credentials_path_from(&args);
+
+    // 获取实例锁，防止多个实例同时回写同一个凭据文件
+    let lock_path = InstanceLock::path_for(&credentials_path);
+    let instance_lock = match InstanceLock::try_acquire(&lock_path) {
+        Ok(LockAcquisition::Acquired(lock)) => {
+            tracing::info!("已获取实例锁: {:?}", lock.path());
+            Some(lock)
+        }
+        Ok(LockAcquisition::HeldBy(pid)) if config.allow_secondary_instance => {
+            tracing::warn!(
+                "凭据文件已被实例 (PID {}) 锁定，以从实例模式启动：Token 刷新结果不回写，Admin 写操作将被拒绝",
+                pid
+            );
+            None
+        }
+        Ok(LockAcquisition::HeldBy(pid)) => {
+            tracing::error!(
+                "凭据文件已被另一个运行中的实例 (PID {}) 锁定: {:?}。请先停止该实例，或配置 allowSecondaryInstance 以从实例模式启动",
+                pid,
+                lock_path
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("获取实例锁失败: {:?}: {}", lock_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    // 设置了 KIRO_CREDENTIALS_JSON / KIRO_CREDENTIALS_BASE64 时优先从环境变量加载凭据
+    let credentials_env = CredentialsConfig::env_var_name();
+
+    // 迁移旧版单对象凭据文件（从实例不修改凭据文件）
+    if (args.migrate_credentials || config.auto_migrate_credentials) && credentials_env.is_none() {
+        if instance_lock.is_none() {
+            tracing::warn!("从实例模式启动，跳过凭据文件迁移");
+        } else {
+            match CredentialsConfig::migrate_to_multiple(&credentials_path, &config) {
+                Ok(CredentialsMigration::Migrated { backup_path }) => {
+                    tracing::info!(
+                        "已将凭据文件迁移为多凭据格式，原文件备份至 {:?}",
+                        backup_path
+                    );
+                }
+                Ok(CredentialsMigration::AlreadyMultiple) => {
+                    tracing::info!("凭据文件已是多凭据格式，无需迁移");
+                }
+                Err(e) => {
+                    tracing::error!("迁移凭据文件失败: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
     // 加载凭证（支持单对象或数组格式）
-    let credentials_path = args
-        .credentials
-        .unwrap_or_else(|| KiroCredentials::default_credentials_path().to_string());
     let credentials_config = CredentialsConfig::load(&credentials_path).unwrap_or_else(|e| {
         tracing::error!("加载凭证失败: {}", e);
         std::process::exit(1);
     });
 
+    // 来自环境变量的凭据无处回写：刷新后的 Token、Admin 修改与统计数据只保存在内存中
+    let persist_path = match credentials_env {
+        Some(var_name) => {
+            tracing::warn!(
+                "已从环境变量 {} 加载凭据，刷新后的 Token 与 Admin API 的修改不会持久化",
+                var_name
+            );
+            None
+        }
+        None => Some(credentials_path),
+    };
+
     // 判断是否为多凭据格式（用于刷新后回写）
     let is_multiple_format = credentials_config.is_multiple();
+    if !is_multiple_format && persist_path.is_some() {
+        tracing::warn!(
+            "凭据文件为旧版单对象格式，刷新后的 Token 不会回写（通过 Admin API 添加凭据时会自动升级为数组格式）；可使用 --migrate-credentials 或配置 autoMigrateCredentials 迁移为数组格式"
+        );
+    }
 
     // 转换为按优先级排序的凭据列表
     let credentials_list = credentials_config.into_sorted_credentials();
@@ -56,7 +165,10 @@ async fn main() {
 
     // 获取第一个凭据用于日志显示
     let first_credentials = credentials_list.first().cloned().unwrap_or_default();
-    tracing::debug!("主凭证: {:?}", first_credentials);
+    tracing::debug!(
+        "主凭证: {:?}",
+        LogSafeCredentials::new(&first_credentials, &config)
+    );
 
     // 获取 API Key
     let api_key = config.api_key.clone().unwrap_or_else(|| {
@@ -65,13 +177,7 @@ async fn main() {
     });
 
     // 构建代理配置
-    let proxy_config = config.proxy_url.as_ref().map(|url| {
-        let mut proxy = http_client::ProxyConfig::new(url);
-        if let (Some(username), Some(password)) = (&config.proxy_username, &config.proxy_password) {
-            proxy = proxy.with_auth(username, password);
-        }
-        proxy
-    });
+    let proxy_config = http_client::build_proxy_config(&config);
 
     if proxy_config.is_some() {
         tracing::info!("已配置 HTTP 代理: {}", config.proxy_url.as_ref().unwrap());
@@ -82,30 +188,59 @@ async fn main() {
         config.clone(),
         credentials_list,
         proxy_config.clone(),
-        Some(credentials_path.into()),
+        persist_path,
         is_multiple_format,
     )
     .unwrap_or_else(|e| {
         tracing::error!("创建 Token 管理器失败: {}", e);
         std::process::exit(1);
     });
+    if instance_lock.is_none() {
+        token_manager.enter_secondary_mode();
+    }
     let token_manager = Arc::new(token_manager);
+    if config.liveness_check_interval_secs > 0 {
+        token_manager.start_liveness_checker(std::time::Duration::from_secs(
+            config.liveness_check_interval_secs,
+        ));
+    }
     let kiro_provider = KiroProvider::with_proxy(token_manager.clone(), proxy_config.clone());
+    let client_pool = kiro_provider.client_pool();
 
     // 初始化 count_tokens 配置
     token::init_config(token::CountTokensConfig {
         api_url: config.count_tokens_api_url.clone(),
-        api_key: config.count_tokens_api_key.clone(),
-        auth_type: config.count_tokens_auth_type.clone(),
-        proxy: proxy_config,
+        api_urls: config.count_tokens_api_urls.clone(),
+        auth_type: token::AuthType::from_config(
+            &config.count_tokens_auth_type,
+            config.count_tokens_api_key.as_deref(),
+        ),
+        anthropic_version: config.count_tokens_anthropic_version.clone(),
+        proxy: proxy_config.clone(),
         tls_backend: config.tls_backend,
+        max_retries: config.count_tokens_max_retries,
+        initial_backoff_ms: config.count_tokens_initial_backoff_ms,
+        max_backoff_ms: config.count_tokens_max_backoff_ms,
     });
 
+    // 启动每日余额报告任务（如果配置了 reportSchedule）
+    if let Some(spec) = &config.report_schedule {
+        start_report_scheduler(spec, &config, &token_manager, proxy_config.as_ref());
+    }
+
+    // 消息请求速率限制器由 Anthropic API 与 Admin API（运行时状态迁移）共享
+    let rate_limiter = anthropic::rate_limit::RateLimiter::from_config(&config).map(Arc::new);
+
+    // 进行中的消息请求登记表由 Anthropic API 与 Admin API（列出、取消请求）共享
+    let active_requests = Arc::new(anthropic::active_requests::ActiveRequests::default());
+
     // 构建 Anthropic API 路由（从第一个凭据获取 profile_arn）
-    let anthropic_app = anthropic::create_router_with_provider(
+    let anthropic_app = anthropic::create_router_with_shared_rate_limiter(
         &api_key,
         Some(kiro_provider),
         first_credentials.profile_arn.clone(),
+        rate_limiter.clone(),
+        active_requests.clone(),
     );
 
     // 构建 Admin API 路由（如果配置了非空的 admin_api_key）
@@ -121,13 +256,46 @@ async fn main() {
             tracing::warn!("admin_api_key 配置为空，Admin API 未启用");
             anthropic_app
         } else {
-            let admin_service = admin::AdminService::new(token_manager.clone());
+            let admin_service = admin::AdminService::new(token_manager.clone())
+                .with_client_pool(client_pool)
+                .with_rate_limiter(rate_limiter)
+                .with_active_requests(active_requests);
             let admin_state = admin::AdminState::new(admin_key, admin_service);
             let admin_app = admin::create_admin_router(admin_state);
 
             // 创建 Admin UI 路由
             let admin_ui_app = admin_ui::create_admin_ui_router();
 
+            // 按来源 IP 限制 Admin API 与 Admin UI（adminAllowedIps 为空时不限制）
+            let allowlist = IpAllowlist::from_config(&config).unwrap_or_else(|e| {
+                tracing::error!("adminAllowedIps 配置无效: {}", e);
+                std::process::exit(1);
+            });
+            // /v1 下携带 X-Admin-Key 的请求同样受限
+            let (anthropic_app, admin_app, admin_ui_app) = match allowlist {
+                Some(allowlist) => {
+                    tracing::info!(
+                        "Admin 访问已限制来源 IP: {:?}（trustForwardedFor: {}）",
+                        config.admin_allowed_ips,
+                        config.trust_forwarded_for
+                    );
+                    let allowlist = Arc::new(allowlist);
+                    let layer = axum::middleware::from_fn_with_state(
+                        allowlist.clone(),
+                        ip_allowlist_middleware,
+                    );
+                    (
+                        anthropic_app.layer(axum::middleware::from_fn_with_state(
+                            allowlist,
+                            anthropic::admin_key_allowlist_middleware,
+                        )),
+                        admin_app.layer(layer.clone()),
+                        admin_ui_app.layer(layer),
+                    )
+                }
+                None => (anthropic_app, admin_app, admin_ui_app),
+            };
+
             tracing::info!("Admin API 已启用");
             tracing::info!("Admin UI 已启用: /admin");
             anthropic_app
@@ -137,15 +305,19 @@ async fn main() {
     } else {
         anthropic_app
     };
+    let app = with_version_endpoint(app, &config, version_info);
 
     // 启动服务器
-    let addr = format!("{}:{}", config.host, config.port);
-    tracing::info!("启动 Anthropic API 端点: {}", addr);
+    let target = BindTarget::from_config(&config);
+    tracing::info!("启动 Anthropic API 端点: {}", target);
     tracing::info!("API Key: {}***", &api_key[..(api_key.len() / 2)]);
     tracing::info!("可用 API:");
     tracing::info!("  GET  /v1/models");
     tracing::info!("  POST /v1/messages");
     tracing::info!("  POST /v1/messages/count_tokens");
+    if config.version_endpoint_enabled {
+        tracing::info!("  GET  /version");
+    }
     if admin_key_valid {
         tracing::info!("Admin API:");
         tracing::info!("  GET  /api/admin/credentials");
@@ -157,6 +329,212 @@ async fn main() {
         tracing::info!("  GET  /admin");
     }
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    serve(app, &target).await;
+
+    // 退出前等待排队中的凭据写入落盘
+    if let Err(e) = token_manager.flush_credentials().await {
+        tracing::error!("退出前凭据落盘失败: {}", e);
+    }
+
+    // 正常退出时释放实例锁（崩溃残留的锁由下次启动时的 PID 存活检测清理）
+    drop(instance_lock);
+    service::notify_stopped();
+}
+
+/// 初始化日志：服务模式下写入按大小轮转的日志文件，否则输出到标准输出
+fn init_logging(service_config: Option<&Config>) {
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let Some(config) = service_config else {
+        tracing_subscriber::fmt().with_env_filter(env_filter).init();
+        return;
+    };
+
+    let path = config.log_file_path();
+    let writer = RotatingFileWriter::open(
+        &path,
+        config.max_log_size_mb.saturating_mul(1024 * 1024),
+        config.keep_logs,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("打开日志文件失败: {:?}: {}", path, e);
+        std::process::exit(1);
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_ansi(false)
+        .with_writer(std::sync::Mutex::new(writer))
+        .init();
+}
+
+/// 加载配置，失败时直接退出（日志尚未初始化时同时输出到标准错误）
+fn load_config(path: &Path) -> Config {
+    Config::load(path).unwrap_or_else(|e| {
+        tracing::error!("加载配置失败: {}", e);
+        eprintln!("加载配置失败: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// 命令行指定或默认位置的配置文件路径
+fn config_path_from(args: &Args) -> PathBuf {
+    args.config
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_or_legacy_path(Config::default_config_path()))
+}
+
+/// 命令行指定或默认位置的凭据文件路径
+///
+/// 凭据来自环境变量且未指定路径时，返回默认配置目录下的路径（仅用于放置实例锁）
+fn credentials_path_from(args: &Args) -> PathBuf {
+    args.credentials
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            KiroCredentials::default_credentials_path()
+                .map(default_or_legacy_path)
+                .unwrap_or_else(|| model::config::default_config_file("credentials.json"))
+        })
+}
+
+/// 未通过命令行指定路径时使用的文件路径
+///
+/// 当前工作目录下存在同名文件（旧版默认位置）时继续使用，否则使用默认配置目录
+fn default_or_legacy_path(default: PathBuf) -> PathBuf {
+    let legacy = default.file_name().map(Path::new);
+    match legacy {
+        Some(legacy) if legacy.exists() => {
+            tracing::info!("使用当前目录下的 {:?}（默认位置: {:?}）", legacy, default);
+            legacy.to_path_buf()
+        }
+        _ => default,
+    }
+}
+
+/// 启动每日余额报告任务，配置无效时直接退出
+fn start_report_scheduler(
+    spec: &str,
+    config: &Config,
+    token_manager: &Arc<MultiTokenManager>,
+    proxy: Option<&http_client::ProxyConfig>,
+) {
+    let schedule = ReportSchedule::parse(spec).unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+    let Some(cache_dir) = token_manager.cache_dir() else {
+        tracing::warn!("无法确定缓存目录，余额报告未启用");
+        return;
+    };
+
+    let service = Arc::new(admin::AdminService::new(token_manager.clone()));
+    let mut reporter = BalanceReporter::new(service, &cache_dir);
+    if let Some(url) = &config.report_webhook_url {
+        let client = http_client::build_client(proxy, 30, config.tls_backend).unwrap_or_else(|e| {
+            tracing::error!("创建余额报告 Webhook HTTP Client 失败: {}", e);
+            std::process::exit(1);
+        });
+        reporter = reporter.with_webhook(ReportWebhook::new(
+            client,
+            url,
+            config.report_webhook_max_retries,
+        ));
+    }
+    admin::report::spawn_report_scheduler(schedule, reporter);
+    tracing::info!("每日余额报告已启用: {}", spec);
+}
+
+/// 以模拟上游模式启动（无需凭据文件，Admin API 不可用）
+async fn run_mock_server(config: Config, version_info: VersionInfo) {
+    let api_key = config.api_key.clone().unwrap_or_else(|| {
+        tracing::error!("配置文件中未设置 apiKey");
+        std::process::exit(1);
+    });
+
+    let provider = MockProvider::from_config(&config);
+    let app = anthropic::create_router_with_mock_provider(&api_key, provider, &config);
+    let app = with_version_endpoint(app, &config, version_info);
+
+    let target = BindTarget::from_config(&config);
+    tracing::warn!(
+        "已启用模拟上游模式，请求不会发往 Kiro（延迟 {}ms，错误率 {}）",
+        config.mock_latency_ms,
+        config.mock_error_rate
+    );
+    tracing::info!("启动 Anthropic API 端点: {}", target);
+    serve(app, &target).await;
+}
+
+/// 按配置挂载 `GET /version`
+fn with_version_endpoint(app: axum::Router, config: &Config, info: VersionInfo) -> axum::Router {
+    if config.version_endpoint_enabled {
+        app.merge(version::create_version_router(info))
+    } else {
+        app
+    }
+}
+
+/// 监听地址并运行服务，直到收到退出信号
+///
+/// 监听 Unix socket 时没有对端 IP，`adminAllowedIps` 需配合 `trustForwardedFor` 由反向代理传递来源 IP
+///（配置加载时校验，见 `Config::validate_admin_allowed_ips`）
+async fn serve(app: axum::Router, target: &BindTarget) {
+    match target {
+        BindTarget::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            // 附带对端地址（ConnectInfo），供 adminAllowedIps 校验来源 IP
+            let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+        }
+        BindTarget::Unix(path) => {
+            let (listener, _guard) = listener::bind_unix(path).unwrap_or_else(|e| {
+                tracing::error!("{:#}", e);
+                std::process::exit(1);
+            });
+            #[cfg(unix)]
+            axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+            #[cfg(not(unix))]
+            match listener {}
+        }
+    }
+}
+
+/// 等待 Ctrl+C、SIGTERM 或服务控制管理器的停止请求
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("监听 Ctrl+C 失败: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("监听 SIGTERM 失败: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+        _ = service::stop_requested() => {},
+    }
+    tracing::info!("收到退出信号，正在关闭服务...");
 }
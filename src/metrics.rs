@@ -0,0 +1,279 @@
+//! Prometheus 指标模块
+//!
+//! 轻量级的进程内指标注册表，以 Prometheus 文本格式（0.0.4）暴露：
+//! - 计数器（counter）：只增不减
+//! - 仪表（gauge）：记录当前值
+//! - 摘要（summary）：仅输出 `_sum` 与 `_count`，不计算分位数
+//!
+//! 指标名称与标签集中在本模块的记录函数中定义，调用方无需关心格式细节。
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+
+use axum::{http::header, response::IntoResponse};
+use parking_lot::Mutex;
+
+/// 标签集合（按插入顺序输出）
+type Labels = Vec<(&'static str, String)>;
+
+/// 单个指标族（样本 key 为名称后缀与标签，summary 使用 `_sum` / `_count` 后缀）
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    samples: BTreeMap<(&'static str, Labels), f64>,
+}
+
+/// 指标注册表
+#[derive(Default)]
+struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Registry {
+    fn update(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: &'static str,
+        sample: (&'static str, Labels),
+        apply: impl FnOnce(&mut f64),
+    ) {
+        let mut families = self.families.lock();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind,
+            samples: BTreeMap::new(),
+        });
+        apply(family.samples.entry(sample).or_insert(0.0));
+    }
+
+    fn add(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: &'static str,
+        labels: Labels,
+        value: f64,
+    ) {
+        self.update(name, help, kind, ("", labels), |v| *v += value);
+    }
+
+    fn set(&self, name: &'static str, help: &'static str, labels: Labels, value: f64) {
+        self.update(name, help, "gauge", ("", labels), |v| *v = value);
+    }
+
+    fn observe(&self, name: &'static str, help: &'static str, labels: Labels, value: f64) {
+        self.update(name, help, "summary", ("_sum", labels.clone()), |v| {
+            *v += value
+        });
+        self.update(name, help, "summary", ("_count", labels), |v| *v += 1.0);
+    }
+
+    #[cfg(test)]
+    fn get(&self, name: &str, labels: &[(&'static str, String)]) -> f64 {
+        self.get_sample(name, "", labels)
+    }
+
+    #[cfg(test)]
+    fn get_sample(
+        &self,
+        name: &str,
+        suffix: &'static str,
+        labels: &[(&'static str, String)],
+    ) -> f64 {
+        self.families
+            .lock()
+            .get(name)
+            .and_then(|f| f.samples.get(&(suffix, labels.to_vec())).copied())
+            .unwrap_or(0.0)
+    }
+
+    fn render(&self) -> String {
+        let families = self.families.lock();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, family.kind);
+            for ((suffix, labels), value) in &family.samples {
+                let _ = writeln!(out, "{}{}{} {}", name, suffix, format_labels(labels), value);
+            }
+        }
+        out
+    }
+}
+
+/// 格式化标签为 `{k="v",...}`，无标签时返回空字符串
+fn format_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    format!("{{{}}}", parts.join(","))
+}
+
+/// 按 Prometheus 文本格式转义标签值
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 全局注册表
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// 以 Prometheus 文本格式导出所有指标
+pub fn render() -> String {
+    registry().render()
+}
+
+/// GET /metrics
+///
+/// Prometheus 抓取端点
+pub async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        render(),
+    )
+}
+
+// ============ 指标记录 ============
+
+/// 上游重试次数指标名
+const UPSTREAM_RETRIES_TOTAL: &str = "upstream_retries_total";
+
+fn upstream_retry_labels(credential_id: u64, status_code: u16) -> Labels {
+    vec![
+        ("credential_id", credential_id.to_string()),
+        ("status_code", status_code.to_string()),
+    ]
+}
+
+/// 记录一次因上游瞬态错误（5xx）触发的重试
+pub fn record_upstream_retry(credential_id: u64, status_code: u16) {
+    registry().add(
+        UPSTREAM_RETRIES_TOTAL,
+        "Upstream requests retried after a transient HTTP error",
+        "counter",
+        upstream_retry_labels(credential_id, status_code),
+        1.0,
+    );
+}
+
+/// 读取上游重试次数（用于测试）
+#[cfg(test)]
+pub fn upstream_retries(credential_id: u64, status_code: u16) -> u64 {
+    registry().get(
+        UPSTREAM_RETRIES_TOTAL,
+        &upstream_retry_labels(credential_id, status_code),
+    ) as u64
+}
+
+/// 等待 Token 刷新锁超时次数指标名
+const REFRESH_LOCK_TIMEOUTS_TOTAL: &str = "kiro_refresh_lock_timeout_total";
+
+/// 记录一次等待 Token 刷新锁超时
+pub fn record_refresh_lock_timeout(credential_id: u64) {
+    registry().add(
+        REFRESH_LOCK_TIMEOUTS_TOTAL,
+        "Token refresh attempts abandoned after waiting too long for the refresh lock",
+        "counter",
+        vec![("credential_id", credential_id.to_string())],
+        1.0,
+    );
+}
+
+/// 读取等待刷新锁超时次数（用于测试）
+#[cfg(test)]
+pub fn refresh_lock_timeouts(credential_id: u64) -> u64 {
+    registry().get(
+        REFRESH_LOCK_TIMEOUTS_TOTAL,
+        &[("credential_id", credential_id.to_string())],
+    ) as u64
+}
+
+/// 等待上游并发许可的请求数指标名
+const QUEUE_DEPTH: &str = "kiro_queue_depth";
+/// 等待上游并发许可耗时指标名
+const QUEUE_WAIT_MS: &str = "kiro_queue_wait_ms";
+
+/// 更新当前排队等待的请求数
+pub fn set_queue_depth(depth: usize) {
+    registry().set(
+        QUEUE_DEPTH,
+        "Requests waiting for an upstream concurrency slot",
+        Vec::new(),
+        depth as f64,
+    );
+}
+
+/// 记录一次等待上游并发许可的耗时（毫秒），`outcome` 为 acquired 或 timeout
+pub fn observe_queue_wait(wait_ms: f64, outcome: &'static str) {
+    registry().observe(
+        QUEUE_WAIT_MS,
+        "Time spent waiting for an upstream concurrency slot in milliseconds",
+        vec![("outcome", outcome.to_string())],
+        wait_ms,
+    );
+}
+
+/// 读取排队等待耗时的观测次数（用于测试）
+#[cfg(test)]
+pub fn queue_wait_count(outcome: &'static str) -> u64 {
+    registry().get_sample(QUEUE_WAIT_MS, "_count", &[("outcome", outcome.to_string())]) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counter_with_labels() {
+        let registry = Registry::default();
+        let labels = vec![
+            ("credential_id", "1".to_string()),
+            ("status_code", "503".to_string()),
+        ];
+        registry.add("test_total", "help text", "counter", labels.clone(), 1.0);
+        registry.add("test_total", "help text", "counter", labels.clone(), 1.0);
+
+        let text = registry.render();
+        assert!(text.contains("# HELP test_total help text"));
+        assert!(text.contains("# TYPE test_total counter"));
+        assert!(text.contains("test_total{credential_id=\"1\",status_code=\"503\"} 2"));
+        assert_eq!(registry.get("test_total", &labels), 2.0);
+    }
+
+    #[test]
+    fn test_render_gauge_and_summary() {
+        let registry = Registry::default();
+        registry.set("depth", "gauge help", Vec::new(), 3.0);
+        registry.set("depth", "gauge help", Vec::new(), 1.0);
+        let labels = vec![("outcome", "acquired".to_string())];
+        registry.observe("wait_ms", "summary help", labels.clone(), 10.0);
+        registry.observe("wait_ms", "summary help", labels.clone(), 5.5);
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE depth gauge\ndepth 1\n"), "{}", text);
+        assert!(text.contains("# TYPE wait_ms summary"));
+        assert!(text.contains("wait_ms_sum{outcome=\"acquired\"} 15.5"));
+        assert!(text.contains("wait_ms_count{outcome=\"acquired\"} 2"));
+        assert_eq!(registry.get_sample("wait_ms", "_count", &labels), 2.0);
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
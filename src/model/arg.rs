@@ -1,14 +1,115 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// Anthropic <-> Kiro API 客户端
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// 配置文件路径
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub config: Option<String>,
 
     /// 凭证文件路径
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub credentials: Option<String>,
+
+    /// 启动前将旧版单对象凭据文件迁移为数组格式（原文件备份为 .bak）
+    #[arg(long)]
+    pub migrate_credentials: bool,
+
+    /// 使用本地模拟上游（无需凭据文件，用于客户端开发与集成测试）
+    #[arg(long)]
+    pub mock_upstream: bool,
+
+    /// 校验配置与凭据、检查监听地址可用后退出，不启动服务
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// 同 --dry-run，并使用第一个凭据查询使用额度以测试网络连通性
+    #[arg(long)]
+    pub dry_run_full: bool,
+
+    /// 子命令（省略时等同于 `run`）
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 子命令
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// 启动服务（默认行为）
+    Run {
+        /// 以系统服务方式运行：日志写入 logFile 并按大小轮转，而非输出到标准输出
+        #[arg(long)]
+        service: bool,
+    },
+
+    /// 安装为系统服务（Linux: systemd 用户服务；Windows: 需以 windows-service 特性编译）
+    InstallService {
+        /// 仅打印将要安装的服务定义，不做任何修改
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// 卸载已安装的系统服务
+    UninstallService {
+        /// 仅打印将要执行的操作，不做任何修改
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl Args {
+    /// 是否以服务方式运行（`run --service`）
+    pub fn service_mode(&self) -> bool {
+        matches!(self.command, Some(Command::Run { service: true }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("kiro-rs").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn test_run_is_default_command() {
+        let args = parse(&["-c", "config.json"]);
+        assert_eq!(args.command, None);
+        assert!(!args.service_mode());
+        assert_eq!(args.config.as_deref(), Some("config.json"));
+
+        let args = parse(&["run", "--service"]);
+        assert!(args.service_mode());
+    }
+
+    #[test]
+    fn test_service_subcommands_accept_global_paths() {
+        let args = parse(&[
+            "install-service",
+            "--dry-run",
+            "-c",
+            "/etc/kiro/config.json",
+            "--credentials",
+            "/etc/kiro/credentials.json",
+        ]);
+        assert_eq!(
+            args.command,
+            Some(Command::InstallService { dry_run: true })
+        );
+        assert_eq!(args.config.as_deref(), Some("/etc/kiro/config.json"));
+        assert_eq!(
+            args.credentials.as_deref(),
+            Some("/etc/kiro/credentials.json")
+        );
+        // 子命令的 --dry-run 不影响顶层的部署自检开关
+        assert!(!args.dry_run);
+
+        let args = parse(&["uninstall-service"]);
+        assert_eq!(
+            args.command,
+            Some(Command::UninstallService { dry_run: false })
+        );
+    }
 }
@@ -1,21 +1,232 @@
 use anyhow::Context;
+use http::HeaderValue;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+/// TLS 后端（`native-tls` 需要启用同名 Cargo 特性）
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum TlsBackend {
     Rustls,
     NativeTls,
 }
 
+impl<'de> Deserialize<'de> for TlsBackend {
+    /// 不区分大小写，接受 `rustls`、`native-tls` 与 `nativetls`
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match value.to_ascii_lowercase().as_str() {
+            "rustls" => Ok(Self::Rustls),
+            "native-tls" | "nativetls" => Ok(Self::NativeTls),
+            _ => Err(serde::de::Error::unknown_variant(
+                &value,
+                &["rustls", "native-tls"],
+            )),
+        }
+    }
+}
+
 impl Default for TlsBackend {
     fn default() -> Self {
         Self::Rustls
     }
 }
 
+/// 工具输入未通过 schema 校验时的处理策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToolInputValidationPolicy {
+    /// 原样输出，仅记录日志（非流式响应附加警告响应头）
+    #[default]
+    Warn,
+    /// 原样输出，并在 tool_use 块上标注 `is_error` 与 `validation_errors`
+    Annotate,
+    /// 尝试修正明显的类型问题（如数字被输出为字符串）后输出
+    Coerce,
+}
+
+/// 上游不支持的采样参数（temperature / top_p / top_k）的处理策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnsupportedParamsPolicy {
+    /// 丢弃参数，仅记录 debug 日志
+    #[default]
+    Ignore,
+    /// 丢弃参数，并在 `x-kiro-unsupported-params` 响应头中列出
+    Warn,
+    /// 返回 400 invalid_request_error
+    Reject,
+}
+
+/// 所有凭据均因连续失败被自动禁用时的自愈策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutoRecoverMode {
+    /// 每次都重置失败计数并重新启用（等价于重启）
+    #[default]
+    Always,
+    /// 同 always，但两次自愈之间的最小间隔按指数增长，有请求成功后恢复初始间隔
+    WithBackoff,
+    /// 不自愈，直接拒绝请求
+    Never,
+}
+
+/// 未配置 machineId 时自动生成机器码的来源
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MachineIdSource {
+    /// 由凭据的 refreshToken 派生（每个凭据不同，轮换凭据后随之改变）
+    #[default]
+    RefreshToken,
+    /// 由操作系统的机器标识派生（同一台机器上的所有凭据共用，不随凭据轮换改变），
+    /// 读取失败时回退到 refreshToken
+    System,
+}
+
+/// 预估的输入 tokens 超出上下文窗口时的处理策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PromptTooLongPolicy {
+    /// 在调用上游之前返回 400 invalid_request_error
+    Reject,
+    /// 仅记录日志，照常发送请求
+    #[default]
+    Warn,
+}
+
+/// 运营方系统提示词（`systemPrompt`）与客户端 `system` 的合并方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SystemPromptMode {
+    /// 替换客户端的系统提示词
+    #[default]
+    Replace,
+    /// 运营方提示词在前，客户端提示词在后
+    Prepend,
+    /// 客户端提示词在前，运营方提示词在后
+    Append,
+}
+
+/// 响应文本后处理配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PostProcessingConfig {
+    /// 按顺序应用于文本内容的过滤器（不作用于 thinking 与 tool_use）
+    #[serde(default)]
+    pub filters: Vec<TextFilterConfig>,
+}
+
+impl PostProcessingConfig {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+/// 出站提示词脱敏配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionConfig {
+    /// 脱敏规则（作用于发往上游的用户消息、系统提示词与工具结果文本，不作用于工具定义）
+    #[serde(default)]
+    pub rules: Vec<RedactionRuleConfig>,
+    /// 严格模式：`blocking` 规则命中时拒绝请求（400 invalid_request_error），而不是替换
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl RedactionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// 单条脱敏规则
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionRuleConfig {
+    /// 规则名称（唯一，出现在 `x-kiro-redactions` 响应头中）
+    pub name: String,
+    /// 正则表达式
+    pub pattern: String,
+    /// 替换文本（支持 `$1` 等捕获组引用）
+    #[serde(default = "default_redaction_replacement")]
+    pub replacement: String,
+    /// 严格模式下命中即拒绝请求
+    #[serde(default)]
+    pub blocking: bool,
+}
+
+fn default_redaction_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// 单个模型的估算价格（各项默认为 0，可组合使用）
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPrice {
+    /// 每次请求的固定费用
+    #[serde(default)]
+    pub per_request: f64,
+    /// 每 1000 输入 tokens 的费用
+    #[serde(default)]
+    pub input_per_1k: f64,
+    /// 每 1000 输出 tokens 的费用
+    #[serde(default)]
+    pub output_per_1k: f64,
+}
+
+/// 内置 Kiro 上游的后端名称（modelBackends 未匹配的模型使用该后端）
+pub const KIRO_BACKEND: &str = "kiro";
+
+/// 非流式响应体默认上限（8 MiB）
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 8 * 1024 * 1024;
+
+/// 单请求上游超时上限的默认值（秒，maxRequestTimeoutSecs）
+pub const DEFAULT_MAX_REQUEST_TIMEOUT_SECS: u64 = 3600;
+
+/// OpenAI 协议上游后端（如 vLLM 网关）
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenAiBackendConfig {
+    /// API 基础地址（如 `http://vllm.internal:8000/v1`），请求发往 `{baseUrl}/chat/completions`
+    pub base_url: String,
+    /// API 密钥（以 `Authorization: Bearer` 发送）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// 上游模型名（未配置时使用客户端请求的模型名）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// 未配置外部 count_tokens API 时的计数方式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensFallback {
+    /// 经 Kiro 上游计数（失败时仍回退到本地估算）
+    #[serde(default)]
+    pub use_upstream: bool,
+}
+
+/// 单个文本过滤器
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TextFilterConfig {
+    /// 正则替换（replacement 支持 `$1` 等捕获组引用）
+    #[serde(rename_all = "camelCase")]
+    Regex {
+        pattern: String,
+        #[serde(default)]
+        replacement: String,
+        /// 仅替换整个响应中的第一处匹配
+        #[serde(default)]
+        first_match_only: bool,
+    },
+    /// 移除首个文本块开头的固定前缀（仅在响应最开始处出现时生效）
+    StripPrefix { prefixes: Vec<String> },
+}
+
 /// KNA 应用配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -26,6 +237,10 @@ pub struct Config {
     #[serde(default = "default_port")]
     pub port: u16,
 
+    /// Unix domain socket 路径（仅 Unix 平台），设置后监听该 socket 而忽略 host/port
+    #[serde(default)]
+    pub unix_socket_path: Option<String>,
+
     #[serde(default = "default_region")]
     pub region: String,
 
@@ -45,6 +260,10 @@ pub struct Config {
     #[serde(default)]
     pub machine_id: Option<String>,
 
+    /// 未配置 machineId 时自动生成机器码的来源
+    #[serde(default)]
+    pub machine_id_source: MachineIdSource,
+
     #[serde(default)]
     pub api_key: Option<String>,
 
@@ -57,18 +276,70 @@ pub struct Config {
     #[serde(default = "default_tls_backend")]
     pub tls_backend: TlsBackend,
 
+    /// 上游 API 连接池中空闲连接的保留时间（秒），0 表示请求结束后不保留空闲连接
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+
+    /// 同时发往上游的消息请求上限，0 表示不限制
+    #[serde(default = "default_max_concurrent_upstream_requests")]
+    pub max_concurrent_upstream_requests: usize,
+
+    /// 等待上游并发许可的最长时间（秒），超时返回 503
+    #[serde(default = "default_queue_wait_timeout_secs")]
+    pub queue_wait_timeout_secs: u64,
+
+    /// 客户端通过 `x-kiro-timeout-secs` 请求头为单个请求设置上游超时的上限（秒）
+    #[serde(default = "default_max_request_timeout_secs")]
+    pub max_request_timeout_secs: u64,
+
+    /// 消息请求速率限制的容量（令牌桶容量或每个窗口的请求数），未配置时不限流
+    #[serde(default)]
+    pub rate_limit_capacity: Option<u32>,
+
+    /// 令牌桶模式下每秒补充的令牌数
+    #[serde(default = "default_rate_limit_refill_per_sec")]
+    pub rate_limit_refill_per_sec: f64,
+
+    /// 固定窗口长度（秒），配置后改用固定窗口计数（每个窗口最多 rateLimitCapacity 个请求）
+    #[serde(default)]
+    pub rate_limit_window_secs: Option<u64>,
+
     /// 外部 count_tokens API 地址（可选）
     #[serde(default)]
     pub count_tokens_api_url: Option<String>,
 
+    /// 外部 count_tokens API 地址列表（可选，按顺序尝试；非空时优先于 countTokensApiUrl）
+    #[serde(default)]
+    pub count_tokens_api_urls: Vec<String>,
+
     /// count_tokens API 密钥（可选）
     #[serde(default)]
     pub count_tokens_api_key: Option<String>,
 
-    /// count_tokens API 认证类型（可选，"x-api-key" 或 "bearer"，默认 "x-api-key"）
+    /// count_tokens API 认证类型（可选，"x-api-key"、"bearer"、"basic"、"header:<name>"、"passthrough" 或 "none"，默认 "x-api-key"）
     #[serde(default = "default_count_tokens_auth_type")]
     pub count_tokens_auth_type: String,
 
+    /// 发送给 count_tokens API 的 anthropic-version（可选，未配置时沿用客户端请求头）
+    #[serde(default)]
+    pub count_tokens_anthropic_version: Option<String>,
+
+    /// count_tokens API 可重试错误（429/5xx/连接失败）的最大重试次数
+    #[serde(default = "default_count_tokens_max_retries")]
+    pub count_tokens_max_retries: u32,
+
+    /// count_tokens API 首次重试退避时间（毫秒）
+    #[serde(default = "default_count_tokens_initial_backoff_ms")]
+    pub count_tokens_initial_backoff_ms: u64,
+
+    /// count_tokens API 最大退避时间（毫秒）
+    #[serde(default = "default_count_tokens_max_backoff_ms")]
+    pub count_tokens_max_backoff_ms: u64,
+
+    /// 未配置 count_tokens API 时的计数方式（默认本地估算）
+    #[serde(default)]
+    pub count_tokens_fallback: CountTokensFallback,
+
     /// HTTP 代理地址（可选）
     /// 支持格式: http://host:port, https://host:port, socks5://host:port
     #[serde(default)]
@@ -86,10 +357,234 @@ pub struct Config {
     #[serde(default)]
     pub admin_api_key: Option<String>,
 
-    /// 负载均衡模式（"priority" 或 "balanced"）
+    /// 允许访问 Admin API 与 Admin UI 的来源 IP / CIDR 网段，为空时不限制
+    #[serde(default)]
+    pub admin_allowed_ips: Vec<String>,
+
+    /// 是否信任 X-Forwarded-For 的第一跳作为来源 IP（仅在可信反向代理之后开启）
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+
+    /// 负载均衡模式（"priority"、"balanced" 或 "reset-aware"）
     #[serde(default = "default_load_balancing_mode")]
     pub load_balancing_mode: String,
 
+    /// 查询使用额度时的资源类型（getUsageLimits 的 resourceType 参数，余额按该类型的明细计算）
+    #[serde(default = "default_usage_resource_type")]
+    pub usage_resource_type: String,
+
+    /// 使用额度查询结果的缓存时间（秒），期间重复查询同一凭据直接复用上次结果，0 表示不缓存
+    #[serde(default = "default_usage_limits_cache_ttl_secs")]
+    pub usage_limits_cache_ttl_secs: u64,
+
+    /// 客户端未携带 anthropic-version 请求头时，响应中回显的默认版本
+    #[serde(default = "default_anthropic_version")]
+    pub anthropic_version: String,
+
+    /// 上游返回 500/502/503/504 时的最大重试次数（每次重试重新选择凭据）
+    #[serde(default = "default_max_upstream_retries")]
+    pub max_upstream_retries: usize,
+
+    /// 同一凭据两次 Token 刷新之间的最小间隔（秒），避免频繁刷新触发上游限流
+    #[serde(default = "default_min_refresh_interval_secs")]
+    pub min_refresh_interval_secs: u64,
+
+    /// 等待 Token 刷新锁的最长时间（秒），超时视为瞬态失败（不计入凭据失败次数）
+    #[serde(default = "default_token_refresh_lock_timeout_secs")]
+    pub token_refresh_lock_timeout_secs: u64,
+
+    /// Token 刷新失败后选择凭据时跳过该凭据的初始时间（秒），连续失败时指数增长；为 0 时不跳过
+    #[serde(default = "default_refresh_failure_backoff_secs")]
+    pub refresh_failure_backoff_secs: u64,
+
+    /// 刷新失败跳过时间的上限（秒）
+    #[serde(default = "default_refresh_failure_backoff_max_secs")]
+    pub refresh_failure_backoff_max_secs: u64,
+
+    /// 上游返回 401/403 时连续多少次后禁用凭据
+    #[serde(default = "default_auth_failure_threshold")]
+    pub auth_failure_threshold: u32,
+
+    /// 所有凭据均被自动禁用时的自愈策略（"always" / "with-backoff" / "never"）
+    #[serde(default)]
+    pub auto_recover_all_disabled: AutoRecoverMode,
+
+    /// 后台存活检查的间隔（秒），0 表示关闭
+    #[serde(default = "default_liveness_check_interval_secs")]
+    pub liveness_check_interval_secs: u64,
+
+    /// 存活检查只检查超过该时长（秒）未被使用的凭据
+    #[serde(default = "default_liveness_check_idle_threshold_secs")]
+    pub liveness_check_idle_threshold_secs: u64,
+
+    /// 是否在成功响应中透出 X-Credential-ID / X-Credential-Auth-Method 响应头（默认关闭）
+    #[serde(default)]
+    pub expose_credential_id_header: bool,
+
+    /// 客户端声明 `Accept-Encoding: gzip` 时压缩响应体（流式 SSE 响应除外，默认关闭）
+    #[serde(default)]
+    pub response_compression: bool,
+
+    /// 允许跨域访问 Anthropic API 的来源（未配置时允许任何来源；`"*"` 表示任何来源，`"null"` 匹配 file:// 页面）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cors_allowed_origins: Option<Vec<String>>,
+
+    /// 部署环境标识（为 `"production"` 时对过于宽松的配置输出警告）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+
+    /// 是否按工具的 input_schema 校验上游返回的 tool_use 输入
+    #[serde(default)]
+    pub validate_tool_inputs: bool,
+
+    /// 工具输入校验失败时的处理策略（"warn" / "annotate" / "coerce"）
+    #[serde(default)]
+    pub tool_input_validation_policy: ToolInputValidationPolicy,
+
+    /// 请求携带上游不支持的采样参数（temperature / top_p / top_k）时的处理策略
+    #[serde(default)]
+    pub unsupported_params_policy: UnsupportedParamsPolicy,
+
+    /// 是否修复上游返回的损坏工具参数 JSON（无法修复时降级为文本块）
+    #[serde(default = "default_repair_tool_inputs")]
+    pub repair_tool_inputs: bool,
+
+    /// JSON 模式下非流式响应不是合法 JSON 时，是否追加一轮纠正对话重试一次
+    #[serde(default)]
+    pub json_mode_retry: bool,
+
+    /// 非流式请求读取上游响应体的字节上限，超出时截断并以 max_tokens 结束
+    #[serde(default = "default_max_response_bytes")]
+    pub max_response_bytes: usize,
+
+    /// 是否暂存成功的非流式响应，供 `GET /v1/messages/{id}` 重新获取
+    #[serde(default)]
+    pub enable_response_retrieval: bool,
+
+    /// 暂存响应的保留时间（秒）
+    #[serde(default = "default_response_retention_secs")]
+    pub response_retention_secs: u64,
+
+    /// 暂存响应的条数上限（超出时淘汰最早暂存的响应）
+    #[serde(default = "default_response_store_max_entries")]
+    pub response_store_max_entries: usize,
+
+    /// 暂存响应的总字节上限（超出时淘汰最早暂存的响应）
+    #[serde(default = "default_response_store_max_bytes")]
+    pub response_store_max_bytes: usize,
+
+    /// 允许客户端使用的模型列表（未配置或为空时不限制）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_models: Option<Vec<String>>,
+
+    /// 运营方系统提示词（未配置或为空时原样使用客户端的 `system`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+
+    /// 运营方系统提示词与客户端 `system` 的合并方式
+    #[serde(default)]
+    pub system_prompt_mode: SystemPromptMode,
+
+    /// 凭据文件已被其他实例锁定时，是否以只读的从实例模式启动（默认直接退出）
+    #[serde(default)]
+    pub allow_secondary_instance: bool,
+
+    /// 是否允许使用普通 API Key 访问 `/v1/messages/dry-run`（默认仅接受 Admin API Key）
+    #[serde(default)]
+    pub dry_run_enabled: bool,
+
+    /// 透传模式：使用客户端 `Authorization: Bearer` 中的 Token 直接调用上游，不经过凭据管理
+    #[serde(default)]
+    pub passthrough_mode: bool,
+
+    /// 是否提供无需认证的 `GET /version` 端点（版本与构建信息，不含密钥）
+    #[serde(default = "default_version_endpoint_enabled")]
+    pub version_endpoint_enabled: bool,
+
+    /// 启动时自动将旧版单对象凭据文件迁移为数组格式（等同于 `--migrate-credentials`）
+    #[serde(default)]
+    pub auto_migrate_credentials: bool,
+
+    /// 是否在 DEBUG 日志中输出凭据的敏感字段（accessToken、refreshToken 等），默认脱敏为 `[REDACTED]`
+    #[serde(default)]
+    pub log_sensitive_fields: bool,
+
+    /// 以服务方式运行（`run --service`）时的日志文件路径，相对路径基于配置文件所在目录；未配置时为配置目录下的 `kiro.log`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<String>,
+
+    /// 单个日志文件的大小上限（MB），超出后轮转
+    #[serde(default = "default_max_log_size_mb")]
+    pub max_log_size_mb: u64,
+
+    /// 轮转后保留的历史日志文件数量（`kiro.log.1` ~ `kiro.log.N`）
+    #[serde(default = "default_keep_logs")]
+    pub keep_logs: usize,
+
+    /// 按用户（metadata.user_id 中 `__session` 之前的部分，缺失时为 `anonymous`）配置的每日请求上限
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub user_limits: HashMap<String, u64>,
+
+    /// 按模型名估算费用的价格表（键为精确模型名、`前缀*` 或默认的 `*`），未配置时不统计费用
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pricing: HashMap<String, ModelPrice>,
+
+    /// 按模型（映射后的 Kiro 模型 ID）限制请求的 max_tokens 上限（键为精确模型名、`前缀*` 或默认的 `default`/`*`），只降不升
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub max_tokens_cap: HashMap<String, u32>,
+
+    /// 预估输入 tokens 超过上下文窗口减去 max_tokens 时的处理策略（"reject" / "warn"）
+    #[serde(default)]
+    pub prompt_too_long_policy: PromptTooLongPolicy,
+
+    /// OpenAI 协议上游后端（键为后端名称，供 modelBackends 引用）
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub openai_backends: HashMap<String, OpenAiBackendConfig>,
+
+    /// 按模型选择上游后端（键为精确模型名、`前缀*` 或默认的 `default`/`*`，值为 `kiro` 或 openaiBackends 中的名称），未匹配的模型使用 Kiro
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub model_backends: HashMap<String, String>,
+
+    /// 放行的 anthropic-beta 特性（启用对应的等效行为并在响应头中回显）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passthrough_betas: Vec<String>,
+
+    /// 拒绝的 anthropic-beta 特性（请求携带时返回 400）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reject_betas: Vec<String>,
+
+    /// 响应文本后处理（正则替换、移除开头的固定前缀）
+    #[serde(default, skip_serializing_if = "PostProcessingConfig::is_empty")]
+    pub post_processing: PostProcessingConfig,
+
+    /// 出站提示词脱敏（按规则替换或拒绝发往上游的敏感内容）
+    #[serde(default, skip_serializing_if = "RedactionConfig::is_empty")]
+    pub redaction: RedactionConfig,
+
+    /// 模拟上游模式（等同于 `--mock-upstream`）：不加载凭据，由本地生成确定性响应
+    #[serde(default)]
+    pub mock_mode: bool,
+
+    /// 模拟上游的响应延迟（毫秒）
+    #[serde(default)]
+    pub mock_latency_ms: u64,
+
+    /// 模拟上游返回错误的概率（0.0 - 1.0）
+    #[serde(default)]
+    pub mock_error_rate: f64,
+
+    /// 每日余额报告的生成时间（`HH:MM` 本地时间，或 cron 风格的 `分 时 * * *`），未配置时不生成
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_schedule: Option<String>,
+
+    /// 余额报告生成后 POST 推送的 Webhook 地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_webhook_url: Option<String>,
+
+    /// Webhook 推送失败时的最大重试次数
+    #[serde(default = "default_report_webhook_max_retries")]
+    pub report_webhook_max_retries: u32,
+
     /// 配置文件路径（运行时元数据，不写入 JSON）
     #[serde(skip)]
     config_path: Option<PathBuf>,
@@ -124,45 +619,251 @@ fn default_count_tokens_auth_type() -> String {
     "x-api-key".to_string()
 }
 
+fn default_anthropic_version() -> String {
+    "2023-06-01".to_string()
+}
+
+fn default_max_upstream_retries() -> usize {
+    2
+}
+
+fn default_min_refresh_interval_secs() -> u64 {
+    60
+}
+
+fn default_token_refresh_lock_timeout_secs() -> u64 {
+    30
+}
+
+fn default_refresh_failure_backoff_secs() -> u64 {
+    30
+}
+
+fn default_refresh_failure_backoff_max_secs() -> u64 {
+    1800
+}
+
+fn default_auth_failure_threshold() -> u32 {
+    1
+}
+
+fn default_liveness_check_interval_secs() -> u64 {
+    900
+}
+
+fn default_liveness_check_idle_threshold_secs() -> u64 {
+    3600
+}
+
+fn default_count_tokens_max_retries() -> u32 {
+    2
+}
+
+fn default_count_tokens_initial_backoff_ms() -> u64 {
+    200
+}
+
+fn default_count_tokens_max_backoff_ms() -> u64 {
+    2_000
+}
+
+fn default_repair_tool_inputs() -> bool {
+    false
+}
+
+fn default_max_response_bytes() -> usize {
+    DEFAULT_MAX_RESPONSE_BYTES
+}
+
+fn default_response_retention_secs() -> u64 {
+    300
+}
+
+fn default_response_store_max_entries() -> usize {
+    1000
+}
+
+fn default_response_store_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_max_log_size_mb() -> u64 {
+    10
+}
+
+fn default_keep_logs() -> usize {
+    5
+}
+
+fn default_version_endpoint_enabled() -> bool {
+    true
+}
+
+fn default_report_webhook_max_retries() -> u32 {
+    3
+}
+
 fn default_tls_backend() -> TlsBackend {
     TlsBackend::Rustls
 }
 
+fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_max_concurrent_upstream_requests() -> usize {
+    10
+}
+
+fn default_queue_wait_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_request_timeout_secs() -> u64 {
+    DEFAULT_MAX_REQUEST_TIMEOUT_SECS
+}
+
+fn default_rate_limit_refill_per_sec() -> f64 {
+    1.0
+}
+
 fn default_load_balancing_mode() -> String {
     "priority".to_string()
 }
 
+fn default_usage_resource_type() -> String {
+    crate::kiro::model::usage_limits::DEFAULT_USAGE_RESOURCE_TYPE.to_string()
+}
+
+fn default_usage_limits_cache_ttl_secs() -> u64 {
+    60
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             host: default_host(),
             port: default_port(),
+            unix_socket_path: None,
             region: default_region(),
             auth_region: None,
             api_region: None,
             kiro_version: default_kiro_version(),
             machine_id: None,
+            machine_id_source: MachineIdSource::default(),
             api_key: None,
             system_version: default_system_version(),
             node_version: default_node_version(),
             tls_backend: default_tls_backend(),
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            max_concurrent_upstream_requests: default_max_concurrent_upstream_requests(),
+            queue_wait_timeout_secs: default_queue_wait_timeout_secs(),
+            max_request_timeout_secs: default_max_request_timeout_secs(),
+            rate_limit_capacity: None,
+            rate_limit_refill_per_sec: default_rate_limit_refill_per_sec(),
+            rate_limit_window_secs: None,
             count_tokens_api_url: None,
+            count_tokens_api_urls: Vec::new(),
             count_tokens_api_key: None,
             count_tokens_auth_type: default_count_tokens_auth_type(),
+            count_tokens_anthropic_version: None,
+            count_tokens_max_retries: default_count_tokens_max_retries(),
+            count_tokens_initial_backoff_ms: default_count_tokens_initial_backoff_ms(),
+            count_tokens_max_backoff_ms: default_count_tokens_max_backoff_ms(),
+            count_tokens_fallback: CountTokensFallback::default(),
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
             admin_api_key: None,
+            admin_allowed_ips: Vec::new(),
+            trust_forwarded_for: false,
             load_balancing_mode: default_load_balancing_mode(),
+            usage_resource_type: default_usage_resource_type(),
+            usage_limits_cache_ttl_secs: default_usage_limits_cache_ttl_secs(),
+            anthropic_version: default_anthropic_version(),
+            max_upstream_retries: default_max_upstream_retries(),
+            min_refresh_interval_secs: default_min_refresh_interval_secs(),
+            token_refresh_lock_timeout_secs: default_token_refresh_lock_timeout_secs(),
+            refresh_failure_backoff_secs: default_refresh_failure_backoff_secs(),
+            refresh_failure_backoff_max_secs: default_refresh_failure_backoff_max_secs(),
+            auth_failure_threshold: default_auth_failure_threshold(),
+            auto_recover_all_disabled: AutoRecoverMode::default(),
+            liveness_check_interval_secs: default_liveness_check_interval_secs(),
+            liveness_check_idle_threshold_secs: default_liveness_check_idle_threshold_secs(),
+            expose_credential_id_header: false,
+            response_compression: false,
+            cors_allowed_origins: None,
+            environment: None,
+            validate_tool_inputs: false,
+            tool_input_validation_policy: ToolInputValidationPolicy::default(),
+            unsupported_params_policy: UnsupportedParamsPolicy::default(),
+            repair_tool_inputs: default_repair_tool_inputs(),
+            json_mode_retry: false,
+            max_response_bytes: default_max_response_bytes(),
+            enable_response_retrieval: false,
+            response_retention_secs: default_response_retention_secs(),
+            response_store_max_entries: default_response_store_max_entries(),
+            response_store_max_bytes: default_response_store_max_bytes(),
+            allowed_models: None,
+            system_prompt: None,
+            system_prompt_mode: SystemPromptMode::default(),
+            allow_secondary_instance: false,
+            dry_run_enabled: false,
+            passthrough_mode: false,
+            version_endpoint_enabled: default_version_endpoint_enabled(),
+            auto_migrate_credentials: false,
+            log_sensitive_fields: false,
+            log_file: None,
+            max_log_size_mb: default_max_log_size_mb(),
+            keep_logs: default_keep_logs(),
+            user_limits: HashMap::new(),
+            pricing: HashMap::new(),
+            max_tokens_cap: HashMap::new(),
+            prompt_too_long_policy: PromptTooLongPolicy::default(),
+            openai_backends: HashMap::new(),
+            model_backends: HashMap::new(),
+            passthrough_betas: Vec::new(),
+            reject_betas: Vec::new(),
+            post_processing: PostProcessingConfig::default(),
+            redaction: RedactionConfig::default(),
+            mock_mode: false,
+            mock_latency_ms: 0,
+            mock_error_rate: 0.0,
+            report_schedule: None,
+            report_webhook_url: None,
+            report_webhook_max_retries: default_report_webhook_max_retries(),
             config_path: None,
         }
     }
 }
 
+/// 默认配置目录下的应用子目录名
+const APP_CONFIG_DIR: &str = "kiro";
+
+/// 默认配置目录下的文件路径
+///
+/// 默认配置目录：
+/// - Linux：`$XDG_CONFIG_HOME/kiro`，未设置时为 `~/.config/kiro`
+/// - macOS：`~/Library/Application Support/kiro`
+/// - Windows：`%APPDATA%\kiro`
+///
+/// 无法确定用户目录时为当前工作目录下的同名文件
+pub fn default_config_file(file_name: &str) -> PathBuf {
+    config_file_in(dirs::config_dir().as_deref(), file_name)
+}
+
+/// 以 `base`（系统配置目录，如 `$XDG_CONFIG_HOME`）为基准的配置文件路径
+fn config_file_in(base: Option<&Path>, file_name: &str) -> PathBuf {
+    base.map_or_else(
+        || PathBuf::from(file_name),
+        |base| base.join(APP_CONFIG_DIR).join(file_name),
+    )
+}
+
 impl Config {
-    /// 获取默认配置文件路径
-    pub fn default_config_path() -> &'static str {
-        "config.json"
+    /// 获取默认配置文件路径（见 [`default_config_file`]）
+    pub fn default_config_path() -> PathBuf {
+        default_config_file("config.json")
     }
 
     /// 获取有效的 Auth Region（用于 Token 刷新）
@@ -177,6 +878,20 @@ impl Config {
         self.api_region.as_deref().unwrap_or(&self.region)
     }
 
+    /// 检查模型是否在 allowed_models 白名单中（未配置或为空时允许所有模型）
+    ///
+    /// 比较时忽略大小写，并将版本号中的 `.` 视为 `-`（如 `claude-sonnet-4.5` 与 `claude-sonnet-4-5` 等价）
+    pub fn is_model_allowed(&self, model: &str) -> bool {
+        let normalize = |m: &str| m.trim().to_lowercase().replace('.', "-");
+        match self.allowed_models.as_deref() {
+            None | Some([]) => true,
+            Some(allowed) => {
+                let model = normalize(model);
+                allowed.iter().any(|m| normalize(m) == model)
+            }
+        }
+    }
+
     /// 从文件加载配置
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         let path = path.as_ref();
@@ -189,10 +904,174 @@ impl Config {
 
         let content = fs::read_to_string(path)?;
         let mut config: Config = serde_json::from_str(&content)?;
+        config.validate_post_processing()?;
+        config.validate_redaction()?;
+        config.validate_tls_backend()?;
+        config.validate_max_tokens_cap()?;
+        config.validate_max_response_bytes()?;
+        config.validate_response_retention()?;
+        config.validate_max_request_timeout()?;
+        config.validate_rate_limit()?;
+        config.validate_cors_allowed_origins()?;
+        config.validate_admin_allowed_ips()?;
+        config.validate_log_rotation()?;
+        config.validate_model_backends()?;
         config.config_path = Some(path.to_path_buf());
         Ok(config)
     }
 
+    /// 校验后处理过滤器中的正则表达式，避免运行时才发现配置错误
+    fn validate_post_processing(&self) -> anyhow::Result<()> {
+        for (i, filter) in self.post_processing.filters.iter().enumerate() {
+            if let TextFilterConfig::Regex { pattern, .. } = filter {
+                regex::Regex::new(pattern).with_context(|| {
+                    format!("postProcessing.filters[{}] 正则表达式无效: {}", i, pattern)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 编译脱敏规则，规则名无效、重复或正则无效时启动报错
+    fn validate_redaction(&self) -> anyhow::Result<()> {
+        crate::anthropic::redaction::Redactor::compile(&self.redaction).map(drop)
+    }
+
+    /// 校验所选 TLS 后端已编译进当前构建
+    fn validate_tls_backend(&self) -> anyhow::Result<()> {
+        if self.tls_backend == TlsBackend::NativeTls && !cfg!(feature = "native-tls") {
+            anyhow::bail!(
+                "tlsBackend 配置为 native-tls，但当前构建未启用 native-tls 特性，请使用 `cargo build --features native-tls` 重新编译"
+            );
+        }
+        Ok(())
+    }
+
+    /// 校验 max_tokens 上限均大于 0（为 0 的上限会拒绝所有输出）
+    fn validate_max_tokens_cap(&self) -> anyhow::Result<()> {
+        if let Some((pattern, _)) = self.max_tokens_cap.iter().find(|(_, cap)| **cap == 0) {
+            anyhow::bail!("maxTokensCap.{} 必须大于 0", pattern);
+        }
+        Ok(())
+    }
+
+    /// 校验响应体上限大于 0 且不超过事件流解码缓冲区上限
+    fn validate_max_response_bytes(&self) -> anyhow::Result<()> {
+        let max = crate::kiro::parser::decoder::DEFAULT_MAX_BUFFER_SIZE;
+        if self.max_response_bytes == 0 || self.max_response_bytes > max {
+            anyhow::bail!(
+                "maxResponseBytes 必须在 1 到 {} 之间，当前为 {}",
+                max,
+                self.max_response_bytes
+            );
+        }
+        Ok(())
+    }
+
+    /// 校验暂存响应的保留时间（同时作为后台清理的周期）与容量上限大于 0
+    fn validate_response_retention(&self) -> anyhow::Result<()> {
+        if self.response_retention_secs == 0 {
+            anyhow::bail!("responseRetentionSecs 必须大于 0");
+        }
+        if self.response_store_max_entries == 0 {
+            anyhow::bail!("responseStoreMaxEntries 必须大于 0");
+        }
+        if self.response_store_max_bytes == 0 {
+            anyhow::bail!("responseStoreMaxBytes 必须大于 0");
+        }
+        Ok(())
+    }
+
+    /// 校验单请求超时上限大于 0
+    fn validate_max_request_timeout(&self) -> anyhow::Result<()> {
+        if self.max_request_timeout_secs == 0 {
+            anyhow::bail!("maxRequestTimeoutSecs 必须大于 0");
+        }
+        Ok(())
+    }
+
+    /// 校验令牌桶补充速率为大于 0 的有限数
+    fn validate_rate_limit(&self) -> anyhow::Result<()> {
+        let refill = self.rate_limit_refill_per_sec;
+        if !refill.is_finite() || refill <= 0.0 {
+            anyhow::bail!(
+                "rateLimitRefillPerSec 必须为大于 0 的有限数，当前为 {}",
+                refill
+            );
+        }
+        Ok(())
+    }
+
+    /// 校验 CORS 允许来源均为合法的 `Origin` 头值
+    fn validate_cors_allowed_origins(&self) -> anyhow::Result<()> {
+        for origin in self.cors_allowed_origins.iter().flatten() {
+            if origin.trim().is_empty() || HeaderValue::from_str(origin.trim()).is_err() {
+                anyhow::bail!("corsAllowedOrigins 包含无效的来源: {:?}", origin);
+            }
+        }
+        Ok(())
+    }
+
+    /// 是否为生产环境（`environment` 为 `"production"`）
+    pub fn is_production(&self) -> bool {
+        self.environment
+            .as_deref()
+            .is_some_and(|env| env.trim().eq_ignore_ascii_case("production"))
+    }
+
+    /// 校验日志轮转大小大于 0（为 0 时每条日志都会触发轮转）
+    /// 校验来源 IP 限制可以生效：监听 Unix socket 时没有对端 IP，必须由反向代理通过 X-Forwarded-For 传递
+    fn validate_admin_allowed_ips(&self) -> anyhow::Result<()> {
+        if !self.admin_allowed_ips.is_empty()
+            && self.unix_socket_path.is_some()
+            && !self.trust_forwarded_for
+        {
+            anyhow::bail!(
+                "监听 unixSocketPath 时没有对端 IP，配置 adminAllowedIps 需同时开启 trustForwardedFor（由反向代理传递来源 IP），否则所有管理请求都会被拒绝"
+            );
+        }
+        Ok(())
+    }
+
+    fn validate_log_rotation(&self) -> anyhow::Result<()> {
+        if self.max_log_size_mb == 0 {
+            anyhow::bail!("maxLogSizeMb 必须大于 0");
+        }
+        Ok(())
+    }
+
+    /// 服务模式下的日志文件路径（相对路径基于配置文件所在目录）
+    pub fn log_file_path(&self) -> PathBuf {
+        let path = PathBuf::from(self.log_file.as_deref().unwrap_or("kiro.log"));
+        match self.config_path().and_then(Path::parent) {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        }
+    }
+
+    /// 校验 modelBackends 引用的后端均已定义
+    fn validate_model_backends(&self) -> anyhow::Result<()> {
+        for (pattern, backend) in &self.model_backends {
+            if backend != KIRO_BACKEND && !self.openai_backends.contains_key(backend) {
+                anyhow::bail!(
+                    "modelBackends.{} 引用了未定义的后端 {}（可选 {} 或 openaiBackends 中的名称）",
+                    pattern,
+                    backend,
+                    KIRO_BACKEND
+                );
+            }
+        }
+        for (name, backend) in &self.openai_backends {
+            if name == KIRO_BACKEND {
+                anyhow::bail!("openaiBackends 不能使用保留名称 {}", KIRO_BACKEND);
+            }
+            if backend.base_url.trim().is_empty() {
+                anyhow::bail!("openaiBackends.{}.baseUrl 不能为空", name);
+            }
+        }
+        Ok(())
+    }
+
     /// 获取配置文件路径（如果有）
     pub fn config_path(&self) -> Option<&Path> {
         self.config_path.as_deref()
@@ -206,7 +1085,194 @@ impl Config {
             .ok_or_else(|| anyhow::anyhow!("配置文件路径未知，无法保存配置"))?;
 
         let content = serde_json::to_string_pretty(self).context("序列化配置失败")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("创建配置目录失败: {}", dir.display()))?;
+        }
         fs::write(path, content).with_context(|| format!("写入配置文件失败: {}", path.display()))?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_path_relative_to_config_dir() {
+        let mut config = Config::default();
+        assert_eq!(config.max_log_size_mb, 10);
+        assert_eq!(config.keep_logs, 5);
+        assert_eq!(config.log_file_path(), PathBuf::from("kiro.log"));
+
+        config.config_path = Some(PathBuf::from("/etc/kiro/config.json"));
+        assert_eq!(config.log_file_path(), PathBuf::from("/etc/kiro/kiro.log"));
+        config.log_file = Some("logs/proxy.log".to_string());
+        assert_eq!(
+            config.log_file_path(),
+            PathBuf::from("/etc/kiro/logs/proxy.log")
+        );
+        config.log_file = Some("/var/log/kiro.log".to_string());
+        assert_eq!(config.log_file_path(), PathBuf::from("/var/log/kiro.log"));
+
+        let config: Config = serde_json::from_str(r#"{"maxLogSizeMb": 0}"#).unwrap();
+        assert!(config.validate_log_rotation().is_err());
+    }
+
+    #[test]
+    fn test_admin_allowed_ips_over_unix_socket_requires_forwarded_for() {
+        let config: Config = serde_json::from_str(
+            r#"{"adminAllowedIps": ["127.0.0.1"], "unixSocketPath": "/run/kiro.sock"}"#,
+        )
+        .unwrap();
+        let err = config.validate_admin_allowed_ips().unwrap_err().to_string();
+        assert!(err.contains("trustForwardedFor"), "{}", err);
+
+        let config: Config = serde_json::from_str(
+            r#"{"adminAllowedIps": ["127.0.0.1"], "unixSocketPath": "/run/kiro.sock", "trustForwardedFor": true}"#,
+        )
+        .unwrap();
+        assert!(config.validate_admin_allowed_ips().is_ok());
+
+        let config: Config = serde_json::from_str(r#"{"adminAllowedIps": ["127.0.0.1"]}"#).unwrap();
+        assert!(config.validate_admin_allowed_ips().is_ok());
+    }
+
+    #[test]
+    fn test_rate_limit_refill_must_be_positive() {
+        assert!(Config::default().validate_rate_limit().is_ok());
+        for refill in ["0", "-1.5"] {
+            let config: Config =
+                serde_json::from_str(&format!(r#"{{"rateLimitRefillPerSec": {}}}"#, refill))
+                    .unwrap();
+            let err = config.validate_rate_limit().unwrap_err().to_string();
+            assert!(err.contains("rateLimitRefillPerSec"), "{}", err);
+        }
+        let config = Config {
+            rate_limit_refill_per_sec: f64::NAN,
+            ..Config::default()
+        };
+        assert!(config.validate_rate_limit().is_err());
+    }
+
+    #[test]
+    fn test_max_tokens_cap_rejects_zero() {
+        let config: Config = serde_json::from_str(
+            r#"{"maxTokensCap": {"claude-sonnet-*": 8192, "default": 16384}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_tokens_cap["default"], 16384);
+        assert!(config.validate_max_tokens_cap().is_ok());
+
+        let config: Config = serde_json::from_str(r#"{"maxTokensCap": {"default": 0}}"#).unwrap();
+        let err = config.validate_max_tokens_cap().unwrap_err().to_string();
+        assert!(err.contains("maxTokensCap.default"), "{}", err);
+    }
+
+    #[test]
+    fn test_max_response_bytes_bounds() {
+        let config = Config::default();
+        assert_eq!(config.max_response_bytes, DEFAULT_MAX_RESPONSE_BYTES);
+        assert!(config.validate_max_response_bytes().is_ok());
+
+        for bytes in [0, 64 * 1024 * 1024] {
+            let config: Config =
+                serde_json::from_value(serde_json::json!({ "maxResponseBytes": bytes })).unwrap();
+            let err = config
+                .validate_max_response_bytes()
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("maxResponseBytes"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_cors_allowed_origins_validation() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "corsAllowedOrigins": ["https://admin.example.com", "null", "*"],
+            "environment": "Production"
+        }))
+        .unwrap();
+        assert!(config.validate_cors_allowed_origins().is_ok());
+        assert!(config.is_production());
+        assert!(!Config::default().is_production());
+
+        let config: Config =
+            serde_json::from_value(serde_json::json!({ "corsAllowedOrigins": ["bad\norigin"] }))
+                .unwrap();
+        let err = config
+            .validate_cors_allowed_origins()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("corsAllowedOrigins"), "{}", err);
+    }
+
+    #[test]
+    fn test_model_backends_must_reference_defined_backend() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "openaiBackends": {"vllm": {"baseUrl": "http://127.0.0.1:8000/v1"}},
+                "modelBackends": {"qwen-*": "vllm", "claude-*": "kiro"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate_model_backends().is_ok());
+
+        let config: Config =
+            serde_json::from_str(r#"{"modelBackends": {"qwen-*": "vllm"}}"#).unwrap();
+        let err = config.validate_model_backends().unwrap_err().to_string();
+        assert!(err.contains("modelBackends.qwen-*"), "{}", err);
+
+        let config: Config =
+            serde_json::from_str(r#"{"openaiBackends": {"kiro": {"baseUrl": "http://x"}}}"#)
+                .unwrap();
+        assert!(config.validate_model_backends().is_err());
+    }
+
+    #[test]
+    fn test_tls_backend_deserialize_case_insensitive() {
+        for (input, expected) in [
+            ("\"rustls\"", TlsBackend::Rustls),
+            ("\"RUSTLS\"", TlsBackend::Rustls),
+            ("\"native-tls\"", TlsBackend::NativeTls),
+            ("\"Native-TLS\"", TlsBackend::NativeTls),
+            ("\"nativetls\"", TlsBackend::NativeTls),
+        ] {
+            assert_eq!(
+                serde_json::from_str::<TlsBackend>(input).unwrap(),
+                expected,
+                "{}",
+                input
+            );
+        }
+        assert!(serde_json::from_str::<TlsBackend>("\"openssl\"").is_err());
+        assert_eq!(
+            serde_json::to_string(&TlsBackend::NativeTls).unwrap(),
+            "\"native-tls\""
+        );
+    }
+
+    #[test]
+    fn test_config_file_in_base_dir() {
+        let dir = std::env::temp_dir().join(format!("kiro-xdg-{}", uuid::Uuid::new_v4()));
+        let config_path = config_file_in(Some(&dir), "config.json");
+        assert_eq!(config_path, dir.join("kiro").join("config.json"));
+        assert_eq!(
+            config_file_in(Some(&dir), "credentials.json"),
+            dir.join("kiro").join("credentials.json")
+        );
+        assert_eq!(
+            config_file_in(None, "config.json"),
+            PathBuf::from("config.json")
+        );
+
+        // 保存时自动创建配置目录
+        let config = Config {
+            config_path: Some(config_path.clone()),
+            ..Config::default()
+        };
+        config.save().unwrap();
+        assert!(config_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! 系统服务集成（`install-service` / `uninstall-service` / `run --service`）
+//!
+//! Linux 上生成并安装 systemd 用户服务；Windows 上通过 `windows-service` 特性注册到服务控制管理器，
+//! 其他平台仅支持以普通进程运行。服务启动命令为 `kiro-rs --config <绝对路径> --credentials <绝对路径> run --service`。
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod systemd;
+#[cfg(all(windows, feature = "windows-service"))]
+mod windows;
+
+#[cfg(target_os = "linux")]
+use self::systemd as platform;
+#[cfg(all(windows, feature = "windows-service"))]
+use self::windows as platform;
+
+/// 服务名称（systemd 单元名 / Windows 服务名）
+pub const SERVICE_NAME: &str = "kiro-rs";
+
+/// 服务描述
+const SERVICE_DESCRIPTION: &str = "kiro-rs: Anthropic <-> Kiro API proxy";
+
+/// 服务的启动命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceSpec {
+    /// 可执行文件路径
+    pub executable: PathBuf,
+    /// 配置文件路径
+    pub config: PathBuf,
+    /// 凭据文件路径
+    pub credentials: PathBuf,
+}
+
+impl ServiceSpec {
+    /// 以当前可执行文件构建；路径均转换为绝对路径（服务的工作目录与当前目录不同）
+    pub fn current(config: &Path, credentials: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            executable: std::env::current_exe()?,
+            config: std::path::absolute(config)?,
+            credentials: std::path::absolute(credentials)?,
+        })
+    }
+
+    /// 服务启动参数（不含可执行文件本身）
+    pub fn arguments(&self) -> Vec<OsString> {
+        vec![
+            "--config".into(),
+            self.config.clone().into_os_string(),
+            "--credentials".into(),
+            self.credentials.clone().into_os_string(),
+            "run".into(),
+            "--service".into(),
+        ]
+    }
+
+    /// 服务的工作目录（配置文件所在目录）
+    pub fn working_directory(&self) -> &Path {
+        self.config.parent().unwrap_or(Path::new("/"))
+    }
+}
+
+/// 安装并启动服务；`dry_run` 时仅打印服务定义
+pub fn install(spec: &ServiceSpec, dry_run: bool) -> anyhow::Result<()> {
+    platform::install(spec, dry_run)
+}
+
+/// 停止并卸载服务；`dry_run` 时仅打印将要执行的操作
+pub fn uninstall(dry_run: bool) -> anyhow::Result<()> {
+    platform::uninstall(dry_run)
+}
+
+/// 以服务方式运行时，向服务控制管理器报告启动（仅 Windows，其他平台为空操作）
+pub fn start_dispatcher() {
+    #[cfg(all(windows, feature = "windows-service"))]
+    windows::start_dispatcher();
+}
+
+/// 等待服务控制管理器的停止请求（仅 Windows；其他平台由 SIGTERM 触发退出，永不完成）
+pub async fn stop_requested() {
+    #[cfg(all(windows, feature = "windows-service"))]
+    windows::stop_requested().await;
+    #[cfg(not(all(windows, feature = "windows-service")))]
+    std::future::pending::<()>().await;
+}
+
+/// 服务已完成退出前的清理，向服务控制管理器报告已停止（仅 Windows，其他平台为空操作）
+pub fn notify_stopped() {
+    #[cfg(all(windows, feature = "windows-service"))]
+    windows::notify_stopped();
+}
+
+/// 不支持服务注册的平台
+#[cfg(not(any(target_os = "linux", all(windows, feature = "windows-service"))))]
+mod platform {
+    use super::ServiceSpec;
+
+    fn unsupported() -> anyhow::Error {
+        if cfg!(windows) {
+            anyhow::anyhow!(
+                "当前构建未启用 windows-service 特性，请使用 `cargo build --features windows-service` 重新编译"
+            )
+        } else {
+            anyhow::anyhow!(
+                "当前平台不支持安装系统服务，请使用平台自带的服务管理工具运行 `kiro-rs run --service`"
+            )
+        }
+    }
+
+    pub fn install(_spec: &ServiceSpec, _dry_run: bool) -> anyhow::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn uninstall(_dry_run: bool) -> anyhow::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_arguments_run_in_service_mode() {
+        let spec = ServiceSpec {
+            executable: PathBuf::from("/opt/kiro/kiro-rs"),
+            config: PathBuf::from("/etc/kiro/config.json"),
+            credentials: PathBuf::from("/etc/kiro/credentials.json"),
+        };
+        let args: Vec<String> = spec
+            .arguments()
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "--config",
+                "/etc/kiro/config.json",
+                "--credentials",
+                "/etc/kiro/credentials.json",
+                "run",
+                "--service"
+            ]
+        );
+        assert_eq!(spec.working_directory(), Path::new("/etc/kiro"));
+
+        // 生成的启动参数能被命令行解析为服务模式
+        let parsed = <crate::model::arg::Args as clap::Parser>::try_parse_from(
+            std::iter::once(spec.executable.clone().into_os_string()).chain(spec.arguments()),
+        )
+        .unwrap();
+        assert!(parsed.service_mode());
+        assert_eq!(parsed.config.as_deref(), Some("/etc/kiro/config.json"));
+    }
+
+    #[test]
+    fn test_current_spec_uses_absolute_paths() {
+        let spec =
+            ServiceSpec::current(Path::new("config.json"), Path::new("credentials.json")).unwrap();
+        assert!(spec.executable.is_absolute());
+        assert!(spec.config.is_absolute());
+        assert!(spec.credentials.ends_with("credentials.json"));
+    }
+}
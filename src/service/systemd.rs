@@ -0,0 +1,212 @@
+//! systemd 用户服务
+//!
+//! 单元文件写入 `~/.config/systemd/user/kiro-rs.service`，通过 `systemctl --user` 启用，无需 root。
+//! 无人登录的服务器需执行 `loginctl enable-linger <用户>`，否则服务随用户会话结束而停止。
+
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context;
+
+use super::{SERVICE_DESCRIPTION, SERVICE_NAME, ServiceSpec};
+
+/// 单元文件名
+fn unit_file_name() -> String {
+    format!("{}.service", SERVICE_NAME)
+}
+
+/// 生成 systemd 单元文件内容
+pub fn render_unit(spec: &ServiceSpec) -> String {
+    let mut exec_start = quote(spec.executable.as_os_str());
+    for arg in spec.arguments() {
+        exec_start.push(' ');
+        exec_start.push_str(&quote(&arg));
+    }
+
+    let mut unit = String::new();
+    let _ = write!(
+        unit,
+        "[Unit]\n\
+         Description={description}\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         ExecStart={exec_start}\n\
+         WorkingDirectory={working_directory}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        description = SERVICE_DESCRIPTION,
+        working_directory = quote(spec.working_directory().as_os_str()),
+    );
+    unit
+}
+
+/// 按 systemd 命令行规则加引号：转义 `\` 与 `"`，`%` 与 `$` 双写以免被展开
+fn quote(arg: &OsStr) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.to_string_lossy().chars() {
+        match c {
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' | '$' => {
+                quoted.push(c);
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// systemd 用户单元目录
+fn user_unit_dir() -> anyhow::Result<PathBuf> {
+    dirs::config_dir()
+        .map(|d| d.join("systemd").join("user"))
+        .context("无法确定用户配置目录（HOME 未设置？）")
+}
+
+/// 写入单元文件，返回其路径
+pub fn write_unit(dir: &Path, contents: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(unit_file_name());
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// 删除单元文件，返回文件此前是否存在
+pub fn remove_unit(dir: &Path) -> std::io::Result<bool> {
+    match std::fs::remove_file(dir.join(unit_file_name())) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// 执行 `systemctl --user <args>`
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .context("执行 systemctl 失败")?;
+    if !status.success() {
+        anyhow::bail!("systemctl --user {} 失败: {}", args.join(" "), status);
+    }
+    Ok(())
+}
+
+/// 安装并启动 systemd 用户服务
+pub fn install(spec: &ServiceSpec, dry_run: bool) -> anyhow::Result<()> {
+    let dir = user_unit_dir()?;
+    let unit = render_unit(spec);
+    if dry_run {
+        println!("# {}", dir.join(unit_file_name()).display());
+        print!("{}", unit);
+        return Ok(());
+    }
+
+    let path =
+        write_unit(&dir, &unit).with_context(|| format!("写入单元文件失败: {}", dir.display()))?;
+    println!("已写入 {}", path.display());
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", &unit_file_name()])?;
+    println!(
+        "服务已启动，日志见 logFile（默认为配置目录下的 kiro.log）；无人登录时保持运行需执行 `loginctl enable-linger $USER`"
+    );
+    Ok(())
+}
+
+/// 停止并卸载 systemd 用户服务
+pub fn uninstall(dry_run: bool) -> anyhow::Result<()> {
+    let dir = user_unit_dir()?;
+    if dry_run {
+        println!("systemctl --user disable --now {}", unit_file_name());
+        println!("rm {}", dir.join(unit_file_name()).display());
+        println!("systemctl --user daemon-reload");
+        return Ok(());
+    }
+
+    // 服务未运行或未启用时 disable 可能失败，不影响删除单元文件
+    if let Err(e) = systemctl(&["disable", "--now", &unit_file_name()]) {
+        eprintln!("{:#}", e);
+    }
+    if remove_unit(&dir).with_context(|| format!("删除单元文件失败: {}", dir.display()))? {
+        systemctl(&["daemon-reload"])?;
+        println!("服务已卸载");
+    } else {
+        println!(
+            "未找到已安装的服务: {}",
+            dir.join(unit_file_name()).display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> ServiceSpec {
+        ServiceSpec {
+            executable: PathBuf::from("/opt/kiro rs/kiro-rs"),
+            config: PathBuf::from("/home/me/.config/kiro-rs/config.json"),
+            credentials: PathBuf::from("/home/me/.config/kiro-rs/100%\"creds\".json"),
+        }
+    }
+
+    #[test]
+    fn test_render_unit() {
+        let unit = render_unit(&spec());
+        assert!(unit.starts_with("[Unit]\n"));
+        assert!(unit.contains(
+            "ExecStart=\"/opt/kiro rs/kiro-rs\" \"--config\" \"/home/me/.config/kiro-rs/config.json\" \
+             \"--credentials\" \"/home/me/.config/kiro-rs/100%%\\\"creds\\\".json\" \"run\" \"--service\"\n"
+        ));
+        assert!(unit.contains("WorkingDirectory=\"/home/me/.config/kiro-rs\"\n"));
+        assert!(unit.contains("Restart=on-failure\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn test_quote_escapes_specifiers() {
+        assert_eq!(quote(OsStr::new("a b")), "\"a b\"");
+        assert_eq!(quote(OsStr::new("$HOME\\x")), "\"$$HOME\\\\x\"");
+    }
+
+    #[test]
+    fn test_write_and_remove_unit() {
+        let dir = std::env::temp_dir().join(format!("kiro-systemd-{}", uuid::Uuid::new_v4()));
+        let path = write_unit(&dir.join("user"), &render_unit(&spec())).unwrap();
+        assert_eq!(path.file_name().unwrap(), "kiro-rs.service");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            render_unit(&spec())
+        );
+
+        assert!(remove_unit(&dir.join("user")).unwrap());
+        assert!(!remove_unit(&dir.join("user")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// 需要可用的 systemd 用户会话，会实际安装并卸载服务
+    #[test]
+    #[ignore]
+    fn test_install_and_uninstall_user_service() {
+        let spec =
+            ServiceSpec::current(Path::new("config.json"), Path::new("credentials.json")).unwrap();
+        install(&spec, false).unwrap();
+        assert!(user_unit_dir().unwrap().join(unit_file_name()).exists());
+        uninstall(false).unwrap();
+        assert!(!user_unit_dir().unwrap().join(unit_file_name()).exists());
+    }
+}
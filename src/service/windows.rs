@@ -0,0 +1,169 @@
+//! Windows 服务（`windows-service` 特性）
+//!
+//! `install-service` 注册为开机自启的服务并立即启动（需要管理员权限）；以服务方式运行时，
+//! 服务控制分发器运行在独立线程中，收到停止请求后触发与 Ctrl+C 相同的优雅退出流程。
+
+use std::ffi::{OsStr, OsString};
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use super::{SERVICE_DESCRIPTION, SERVICE_NAME, ServiceSpec};
+
+/// 服务控制管理器发出的停止请求
+static STOP: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// 服务状态句柄（由服务控制分发器线程注册）
+static STATUS_HANDLE: OnceLock<ServiceStatusHandle> = OnceLock::new();
+
+/// 安装并启动 Windows 服务
+pub fn install(spec: &ServiceSpec, dry_run: bool) -> anyhow::Result<()> {
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: SERVICE_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: spec.executable.clone(),
+        launch_arguments: spec.arguments(),
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    if dry_run {
+        println!("服务名: {}", SERVICE_NAME);
+        println!("可执行文件: {}", spec.executable.display());
+        println!("启动参数: {:?}", spec.arguments());
+        println!("启动类型: 自动");
+        return Ok(());
+    }
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("连接服务控制管理器失败（需要以管理员身份运行）")?;
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .context("注册 Windows 服务失败")?;
+    service.set_description(SERVICE_DESCRIPTION)?;
+    service
+        .start::<&OsStr>(&[])
+        .context("启动 Windows 服务失败")?;
+    println!("服务 {} 已注册并启动", SERVICE_NAME);
+    Ok(())
+}
+
+/// 停止并删除 Windows 服务
+pub fn uninstall(dry_run: bool) -> anyhow::Result<()> {
+    if dry_run {
+        println!("将停止并删除服务: {}", SERVICE_NAME);
+        return Ok(());
+    }
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("连接服务控制管理器失败（需要以管理员身份运行）")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("打开 Windows 服务失败")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("停止 Windows 服务失败")?;
+    }
+    service.delete().context("删除 Windows 服务失败")?;
+    println!("服务 {} 已删除", SERVICE_NAME);
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// 在独立线程中运行服务控制分发器（非服务控制管理器启动时记录日志后按普通进程运行）
+pub fn start_dispatcher() {
+    std::thread::spawn(|| {
+        if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+            tracing::warn!("未由服务控制管理器启动，按普通进程运行: {}", e);
+        }
+    });
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = register_service() {
+        tracing::error!("注册服务控制处理器失败: {}", e);
+    }
+}
+
+fn register_service() -> windows_service::Result<()> {
+    let handle = service_control_handler::register(SERVICE_NAME, |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    ))?;
+    let _ = STATUS_HANDLE.set(handle);
+    Ok(())
+}
+
+fn status(state: ServiceState, controls_accepted: ServiceControlAccept) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+/// 等待服务控制管理器的停止请求
+pub async fn stop_requested() {
+    STOP.notified().await;
+}
+
+/// 报告服务已停止
+pub fn notify_stopped() {
+    let Some(handle) = STATUS_HANDLE.get() else {
+        return;
+    };
+    let stopped = status(ServiceState::Stopped, ServiceControlAccept::empty());
+    if let Err(e) = handle.set_service_status(stopped) {
+        tracing::warn!("报告服务停止状态失败: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 需要管理员权限，会实际注册并删除服务
+    #[test]
+    #[ignore]
+    fn test_install_and_uninstall_windows_service() {
+        let spec = ServiceSpec::current(
+            std::path::Path::new("config.json"),
+            std::path::Path::new("credentials.json"),
+        )
+        .unwrap();
+        install(&spec, false).unwrap();
+        uninstall(false).unwrap();
+    }
+}
@@ -10,33 +10,208 @@
 use crate::anthropic::types::{
     CountTokensRequest, CountTokensResponse, Message, SystemMessage, Tool,
 };
+use crate::common::auth;
 use crate::http_client::{ProxyConfig, build_client};
 use crate::model::config::TlsBackend;
+use axum::http::{HeaderMap, header};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 /// Count Tokens API 配置
 #[derive(Clone, Default)]
 pub struct CountTokensConfig {
-    /// 外部 count_tokens API 地址
+    /// 外部 count_tokens API 地址（单地址形式，向后兼容）
     pub api_url: Option<String>,
-    /// count_tokens API 密钥
-    pub api_key: Option<String>,
-    /// count_tokens API 认证类型（"x-api-key" 或 "bearer"）
-    pub auth_type: String,
+    /// 外部 count_tokens API 地址列表（按顺序尝试，首个成功即返回）
+    pub api_urls: Vec<String>,
+    /// count_tokens API 认证方式（含密钥）
+    pub auth_type: AuthType,
+    /// 发送给 count_tokens API 的 `anthropic-version`（未配置时沿用客户端请求头）
+    pub anthropic_version: Option<String>,
     /// 代理配置
     pub proxy: Option<ProxyConfig>,
 
     pub tls_backend: TlsBackend,
+
+    /// 单个地址遇到可重试错误（429/5xx/连接失败）时的最大重试次数
+    pub max_retries: u32,
+    /// 首次重试退避时间（毫秒）
+    pub initial_backoff_ms: u64,
+    /// 最大退避时间（毫秒）
+    pub max_backoff_ms: u64,
+}
+
+/// count_tokens API 认证方式
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthType {
+    /// `Authorization: Bearer <token>`
+    Bearer { token: String },
+    /// `Authorization: Basic <base64(username:password)>`
+    Basic { username: String, password: String },
+    /// 将密钥放在指定请求头中（默认 `x-api-key`）
+    ApiKey { header_name: String, key: String },
+    /// 转发客户端请求中的 `x-api-key` / `Authorization`（不使用配置的密钥）
+    Passthrough,
+    /// 不携带认证信息
+    #[default]
+    None,
+}
+
+impl AuthType {
+    /// 从配置（`countTokensAuthType`、`countTokensApiKey`）构建
+    ///
+    /// - `bearer`：密钥作为 Bearer Token
+    /// - `basic`：密钥格式为 `username:password`（无冒号时整体作为用户名）
+    /// - `x-api-key`：密钥放在 `x-api-key` 请求头
+    /// - `header:<name>`：密钥放在自定义请求头 `<name>`
+    /// - `passthrough`：转发客户端自身的认证头（忽略 `countTokensApiKey`）
+    /// - `none`，或未配置密钥：不携带认证信息
+    ///
+    /// 无法识别的类型按 `x-api-key` 处理
+    pub fn from_config(auth_type_str: &str, api_key: Option<&str>) -> AuthType {
+        let auth_type = auth_type_str.trim();
+        if auth_type.eq_ignore_ascii_case("passthrough") {
+            return AuthType::Passthrough;
+        }
+        let Some(key) = api_key.filter(|_| !auth_type.eq_ignore_ascii_case("none")) else {
+            return AuthType::None;
+        };
+        let api_key_header = |header_name: &str| AuthType::ApiKey {
+            header_name: header_name.to_string(),
+            key: key.to_string(),
+        };
+
+        match auth_type.to_ascii_lowercase().as_str() {
+            "bearer" => AuthType::Bearer {
+                token: key.to_string(),
+            },
+            "basic" => {
+                let (username, password) = key.split_once(':').unwrap_or((key, ""));
+                AuthType::Basic {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }
+            }
+            "x-api-key" => api_key_header("x-api-key"),
+            lower => match lower.strip_prefix("header:").map(str::trim) {
+                Some(name) if !name.is_empty() => api_key_header(name),
+                _ => {
+                    tracing::warn!(
+                        "未知的 countTokensAuthType: {}，按 x-api-key 处理",
+                        auth_type_str
+                    );
+                    api_key_header("x-api-key")
+                }
+            },
+        }
+    }
+
+    /// 为请求设置认证头（`Passthrough` 使用客户端转发的认证头）
+    pub fn apply_to_request(
+        &self,
+        builder: reqwest::RequestBuilder,
+        client: &ClientCountContext,
+    ) -> reqwest::RequestBuilder {
+        match self {
+            AuthType::Bearer { token } => builder.bearer_auth(token),
+            AuthType::Basic { username, password } => builder.basic_auth(username, Some(password)),
+            AuthType::ApiKey { header_name, key } => builder.header(header_name.as_str(), key),
+            AuthType::Passthrough => match &client.credential {
+                Some(ClientCredential::ApiKey(key)) => builder.header("x-api-key", key),
+                Some(ClientCredential::Authorization(value)) => {
+                    builder.header(header::AUTHORIZATION, value)
+                }
+                None => builder,
+            },
+            AuthType::None => builder,
+        }
+    }
+}
+
+/// 客户端请求携带的认证头
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientCredential {
+    /// `x-api-key`
+    ApiKey(String),
+    /// `Authorization`（原样转发）
+    Authorization(String),
+}
+
+impl ClientCredential {
+    /// 认证头中的密钥部分（`Authorization: Bearer` 去掉前缀）
+    fn key(&self) -> &str {
+        match self {
+            ClientCredential::ApiKey(key) => key,
+            ClientCredential::Authorization(value) => {
+                value.strip_prefix("Bearer ").unwrap_or(value)
+            }
+        }
+    }
+}
+
+/// 单次请求中可转发给 count_tokens API 的客户端信息
+#[derive(Debug, Clone, Default)]
+pub struct ClientCountContext {
+    credential: Option<ClientCredential>,
+    anthropic_version: Option<String>,
 }
 
+impl ClientCountContext {
+    /// 从客户端请求头提取（优先 `x-api-key`，其次 `Authorization`）
+    ///
+    /// 与 Admin API Key 相同的密钥一律不转发；`x-admin-key` 请求头从不读取
+    pub fn from_headers(headers: &HeaderMap, admin_api_key: Option<&str>) -> Self {
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let credential = header_value("x-api-key")
+            .map(ClientCredential::ApiKey)
+            .or_else(|| {
+                header_value(header::AUTHORIZATION.as_str()).map(ClientCredential::Authorization)
+            })
+            .filter(|credential| {
+                !admin_api_key
+                    .filter(|k| !k.is_empty())
+                    .is_some_and(|admin| auth::constant_time_eq(credential.key(), admin))
+            });
+        Self {
+            credential,
+            anthropic_version: header_value("anthropic-version"),
+        }
+    }
+}
+
+impl CountTokensConfig {
+    /// 规范化为多地址形式
+    ///
+    /// `api_urls` 为空且配置了 `api_url` 时，将 `api_url` 作为唯一地址
+    fn normalized(mut self) -> Self {
+        self.api_urls.retain(|url| !url.trim().is_empty());
+        if self.api_urls.is_empty() {
+            self.api_urls
+                .extend(self.api_url.clone().filter(|url| !url.trim().is_empty()));
+        }
+        self
+    }
+}
+
+/// 单个远程 count_tokens API 地址的请求超时（秒）
+const REMOTE_COUNT_TOKENS_TIMEOUT_SECS: u64 = 10;
+
 /// 全局配置存储
 static COUNT_TOKENS_CONFIG: OnceLock<CountTokensConfig> = OnceLock::new();
 
 /// 初始化 count_tokens 配置
 ///
-/// 应在应用启动时调用一次
+/// 应在应用启动时调用一次，同时接受 `api_url` 和 `api_urls` 两种形式
 pub fn init_config(config: CountTokensConfig) {
-    let _ = COUNT_TOKENS_CONFIG.set(config);
+    let _ = COUNT_TOKENS_CONFIG.set(config.normalized());
 }
 
 /// 获取配置
@@ -102,6 +277,19 @@ pub fn count_tokens(text: &str) -> u64 {
     acc_token
 }
 
+/// 输入 tokens 计数来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenCountSource {
+    /// 远程 count_tokens API
+    Remote,
+    /// 远程结果缓存命中
+    Cache,
+    /// 本地计算（未配置远程 API）
+    Local,
+    /// 远程 API 全部失败后回退到本地计算
+    LocalFallback,
+}
+
 /// 估算请求的输入 tokens
 ///
 /// 优先调用远程 API，失败时回退到本地计算
@@ -110,76 +298,262 @@ pub(crate) fn count_all_tokens(
     system: Option<Vec<SystemMessage>>,
     messages: Vec<Message>,
     tools: Option<Vec<Tool>>,
+    client: &ClientCountContext,
 ) -> u64 {
+    count_all_tokens_with_source(model, system, messages, tools, client).0
+}
+
+/// 是否配置了外部 count_tokens API
+pub(crate) fn remote_api_configured() -> bool {
+    get_config().is_some_and(|config| !config.api_urls.is_empty())
+}
+
+/// 估算请求的输入 tokens，并返回计数来源
+pub(crate) fn count_all_tokens_with_source(
+    model: String,
+    system: Option<Vec<SystemMessage>>,
+    messages: Vec<Message>,
+    tools: Option<Vec<Tool>>,
+    client: &ClientCountContext,
+) -> (u64, TokenCountSource) {
     // 检查是否配置了远程 API
     if let Some(config) = get_config() {
-        if let Some(api_url) = &config.api_url {
-            // 尝试调用远程 API
-            let result = tokio::task::block_in_place(|| {
-                tokio::runtime::Handle::current().block_on(call_remote_count_tokens(
-                    api_url, config, model, &system, &messages, &tools,
-                ))
+        if !config.api_urls.is_empty() {
+            let request = CountTokensRequest {
+                model, // 模型名称用于 token 计算
+                messages,
+                system,
+                tools,
+            };
+            return tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current()
+                    .block_on(count_tokens_remote_or_local(config, request, client))
             });
+        }
+    }
+
+    // 本地计算
+    (
+        count_all_tokens_local(system, messages, tools),
+        TokenCountSource::Local,
+    )
+}
+
+/// 远程计数（带缓存），全部失败时回退到本地计算
+async fn count_tokens_remote_or_local(
+    config: &CountTokensConfig,
+    request: CountTokensRequest,
+    client: &ClientCountContext,
+) -> (u64, TokenCountSource) {
+    // passthrough 模式下按客户端密钥隔离缓存，避免不同租户共享结果
+    let partition = match config.auth_type {
+        AuthType::Passthrough => client.credential.as_ref().map(ClientCredential::key),
+        _ => None,
+    };
+    let cache_key = request_cache_key(&request, partition);
+    if let Some(tokens) = cache_key.as_deref().and_then(cache_get) {
+        tracing::debug!("count_tokens 缓存命中: {}", tokens);
+        return (tokens, TokenCountSource::Cache);
+    }
+
+    match call_remote_count_tokens_with_fallback(config, &request, client).await {
+        Ok(tokens) => {
+            tracing::debug!("远程 count_tokens API 返回: {}", tokens);
+            if let Some(key) = cache_key {
+                cache_put(key, tokens);
+            }
+            (tokens, TokenCountSource::Remote)
+        }
+        Err(e) => {
+            tracing::warn!("远程 count_tokens API 调用失败，回退到本地计算: {}", e);
+            (
+                count_all_tokens_local(request.system, request.messages, request.tools),
+                TokenCountSource::LocalFallback,
+            )
+        }
+    }
+}
+
+/// 远程计数结果缓存有效期
+///
+/// Claude Code 会以几乎相同的负载反复调用 count_tokens，短期缓存即可避免重复请求
+const COUNT_TOKENS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// 远程计数结果缓存最大条目数
+const COUNT_TOKENS_CACHE_MAX_ENTRIES: usize = 1024;
+
+/// 远程计数结果缓存（请求哈希 -> (写入时间, tokens)）
+static COUNT_TOKENS_CACHE: OnceLock<Mutex<HashMap<String, (Instant, u64)>>> = OnceLock::new();
+
+fn count_tokens_cache() -> &'static Mutex<HashMap<String, (Instant, u64)>> {
+    COUNT_TOKENS_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// 计算请求体（及缓存分区）的 SHA-256 哈希作为缓存键
+fn request_cache_key(request: &CountTokensRequest, partition: Option<&str>) -> Option<String> {
+    let bytes = serde_json::to_vec(request).ok()?;
+    let mut hasher = Sha256::new();
+    if let Some(partition) = partition {
+        hasher.update(partition.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(&bytes);
+    Some(hex::encode(hasher.finalize()))
+}
+
+fn cache_get(key: &str) -> Option<u64> {
+    let cache = count_tokens_cache().lock();
+    cache
+        .get(key)
+        .filter(|(cached_at, _)| cached_at.elapsed() < COUNT_TOKENS_CACHE_TTL)
+        .map(|(_, tokens)| *tokens)
+}
+
+fn cache_put(key: String, tokens: u64) {
+    let mut cache = count_tokens_cache().lock();
+    if cache.len() >= COUNT_TOKENS_CACHE_MAX_ENTRIES {
+        cache.retain(|_, (cached_at, _)| cached_at.elapsed() < COUNT_TOKENS_CACHE_TTL);
+        if cache.len() >= COUNT_TOKENS_CACHE_MAX_ENTRIES {
+            cache.clear();
+        }
+    }
+    cache.insert(key, (Instant::now(), tokens));
+}
+
+/// 远程 count_tokens 调用错误
+#[derive(Debug)]
+enum RemoteCountError {
+    /// 可重试（429、5xx、连接失败或超时）
+    Retryable(String),
+    /// 不可重试（其他 4xx、响应解析失败等）
+    Permanent(String),
+}
+
+impl std::fmt::Display for RemoteCountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteCountError::Retryable(msg) | RemoteCountError::Permanent(msg) => {
+                write!(f, "{}", msg)
+            }
+        }
+    }
+}
+
+/// 计算第 `attempt` 次重试前的退避时间（指数退避 + 抖动）
+fn retry_backoff(config: &CountTokensConfig, attempt: u32) -> Duration {
+    let exp = config
+        .initial_backoff_ms
+        .saturating_mul(2u64.saturating_pow(attempt.min(16)));
+    let backoff = exp.min(config.max_backoff_ms);
+    let jitter_max = (backoff / 4).max(1);
+    let jitter = fastrand::u64(0..=jitter_max);
+    Duration::from_millis(backoff.saturating_add(jitter))
+}
+
+/// 按顺序调用远程 count_tokens API，首个成功即返回
+///
+/// 单个地址遇到可重试错误时按退避策略重试 `max_retries` 次，
+/// 全部失败时返回的错误会列出每个已尝试的地址及其失败原因
+async fn call_remote_count_tokens_with_fallback(
+    config: &CountTokensConfig,
+    request: &CountTokensRequest,
+    client_context: &ClientCountContext,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let client = build_client(
+        config.proxy.as_ref(),
+        REMOTE_COUNT_TOKENS_TIMEOUT_SECS,
+        config.tls_backend,
+    )?;
 
-            match result {
-                Ok(tokens) => {
-                    tracing::debug!("远程 count_tokens API 返回: {}", tokens);
-                    return tokens;
+    let mut failures: Vec<String> = Vec::new();
+    for api_url in &config.api_urls {
+        let mut attempt = 0;
+        loop {
+            match call_remote_count_tokens(&client, api_url, config, request, client_context).await
+            {
+                Ok(tokens) => return Ok(tokens),
+                Err(RemoteCountError::Retryable(e)) if attempt < config.max_retries => {
+                    let delay = retry_backoff(config, attempt);
+                    attempt += 1;
+                    tracing::warn!(
+                        "count_tokens API 调用失败（第 {}/{} 次重试，{}ms 后）: {}: {}",
+                        attempt,
+                        config.max_retries,
+                        delay.as_millis(),
+                        api_url,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
                 }
                 Err(e) => {
-                    tracing::warn!("远程 count_tokens API 调用失败，回退到本地计算: {}", e);
+                    tracing::warn!(
+                        "count_tokens API 调用失败，尝试下一个地址: {}: {}",
+                        api_url,
+                        e
+                    );
+                    failures.push(format!("{}: {}", api_url, e));
+                    break;
                 }
             }
         }
     }
 
-    // 本地计算
-    count_all_tokens_local(system, messages, tools)
+    Err(format!(
+        "所有 count_tokens API 均调用失败（已尝试 {} 个）: [{}]",
+        failures.len(),
+        failures.join("; ")
+    )
+    .into())
 }
 
-/// 调用远程 count_tokens API
+/// 调用单个远程 count_tokens API
 async fn call_remote_count_tokens(
+    client: &reqwest::Client,
     api_url: &str,
     config: &CountTokensConfig,
-    model: String,
-    system: &Option<Vec<SystemMessage>>,
-    messages: &Vec<Message>,
-    tools: &Option<Vec<Tool>>,
-) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
-    let client = build_client(config.proxy.as_ref(), 300, config.tls_backend)?;
-
-    // 构建请求体
-    let request = CountTokensRequest {
-        model: model, // 模型名称用于 token 计算
-        messages: messages.clone(),
-        system: system.clone(),
-        tools: tools.clone(),
-    };
-
-    // 构建请求
-    let mut req_builder = client.post(api_url);
-
-    // 设置认证头
-    if let Some(api_key) = &config.api_key {
-        if config.auth_type == "bearer" {
-            req_builder = req_builder.header("Authorization", format!("Bearer {}", api_key));
-        } else {
-            req_builder = req_builder.header("x-api-key", api_key);
-        }
+    request: &CountTokensRequest,
+    client_context: &ClientCountContext,
+) -> Result<u64, RemoteCountError> {
+    // 构建请求并设置认证头
+    let mut req_builder = config
+        .auth_type
+        .apply_to_request(client.post(api_url), client_context);
+    let anthropic_version = config
+        .anthropic_version
+        .as_deref()
+        .or(client_context.anthropic_version.as_deref());
+    if let Some(version) = anthropic_version {
+        req_builder = req_builder.header("anthropic-version", version);
     }
 
     // 发送请求
     let response = req_builder
         .header("Content-Type", "application/json")
-        .json(&request)
+        .json(request)
         .send()
-        .await?;
+        .await
+        .map_err(|e| {
+            if e.is_connect() || e.is_timeout() {
+                RemoteCountError::Retryable(e.to_string())
+            } else {
+                RemoteCountError::Permanent(e.to_string())
+            }
+        })?;
 
-    if !response.status().is_success() {
-        return Err(format!("API 返回错误状态: {}", response.status()).into());
+    let status = response.status();
+    if !status.is_success() {
+        let msg = format!("API 返回错误状态: {}", status);
+        return Err(if status.as_u16() == 429 || status.is_server_error() {
+            RemoteCountError::Retryable(msg)
+        } else {
+            RemoteCountError::Permanent(msg)
+        });
     }
 
-    let result: CountTokensResponse = response.json().await?;
+    let result: CountTokensResponse = response
+        .json()
+        .await
+        .map_err(|e| RemoteCountError::Permanent(e.to_string()))?;
     Ok(result.input_tokens as u64)
 }
 
@@ -243,3 +617,385 @@ pub(crate) fn estimate_output_tokens(content: &[serde_json::Value]) -> i32 {
 
     total.max(1)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, http::StatusCode, response::IntoResponse, routing::post};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// 启动本地测试服务器，返回其基础地址
+    async fn spawn_server(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    /// 前 `failures` 次返回 `status`，之后返回 `tokens`，并统计请求次数
+    async fn spawn_flaky_server(
+        status: StatusCode,
+        failures: usize,
+        tokens: i32,
+    ) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = Router::new().route(
+            "/count",
+            post(move || {
+                let counter = counter.clone();
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                        (status, Json(serde_json::json!({}))).into_response()
+                    } else {
+                        Json(serde_json::json!({ "input_tokens": tokens })).into_response()
+                    }
+                }
+            }),
+        );
+        (format!("{}/count", spawn_server(router).await), hits)
+    }
+
+    fn test_config(api_urls: Vec<String>, max_retries: u32) -> CountTokensConfig {
+        CountTokensConfig {
+            api_urls,
+            auth_type: AuthType::from_config("x-api-key", Some("test-key")),
+            max_retries,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 5,
+            ..Default::default()
+        }
+    }
+
+    fn test_request(text: &str) -> CountTokensRequest {
+        CountTokensRequest {
+            model: "claude-sonnet-4".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: serde_json::json!(text),
+            }],
+            system: None,
+            tools: None,
+        }
+    }
+
+    fn auth_headers(auth_type: &str, api_key: Option<&str>) -> reqwest::header::HeaderMap {
+        AuthType::from_config(auth_type, api_key)
+            .apply_to_request(
+                reqwest::Client::new().post("http://localhost/count"),
+                &ClientCountContext::default(),
+            )
+            .build()
+            .unwrap()
+            .headers()
+            .clone()
+    }
+
+    #[test]
+    fn test_auth_type_bearer() {
+        let headers = auth_headers("Bearer", Some("sk-1"));
+        assert_eq!(headers["authorization"], "Bearer sk-1");
+        assert!(headers.get("x-api-key").is_none());
+    }
+
+    #[test]
+    fn test_auth_type_basic_is_base64_encoded() {
+        assert_eq!(
+            AuthType::from_config("basic", Some("user:p:ss")),
+            AuthType::Basic {
+                username: "user".to_string(),
+                password: "p:ss".to_string(),
+            }
+        );
+        let headers = auth_headers("basic", Some("user:pass"));
+        // base64("user:pass")
+        assert_eq!(headers["authorization"], "Basic dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn test_auth_type_api_key_headers() {
+        assert_eq!(auth_headers("x-api-key", Some("sk-1"))["x-api-key"], "sk-1");
+        let headers = auth_headers("header:X-Custom-Key", Some("sk-2"));
+        assert_eq!(headers["x-custom-key"], "sk-2");
+        assert!(headers.get("authorization").is_none());
+        // 未知类型沿用 x-api-key
+        assert_eq!(auth_headers("unknown", Some("sk-3"))["x-api-key"], "sk-3");
+    }
+
+    #[test]
+    fn test_auth_type_none_without_key() {
+        assert_eq!(AuthType::from_config("bearer", None), AuthType::None);
+        assert_eq!(AuthType::from_config("none", Some("sk-1")), AuthType::None);
+        assert!(auth_headers("bearer", None).get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_normalized_uses_api_url_when_api_urls_empty() {
+        let config = CountTokensConfig {
+            api_url: Some("http://a".to_string()),
+            ..Default::default()
+        }
+        .normalized();
+        assert_eq!(config.api_urls, vec!["http://a".to_string()]);
+    }
+
+    #[test]
+    fn test_normalized_prefers_api_urls() {
+        let config = CountTokensConfig {
+            api_url: Some("http://a".to_string()),
+            api_urls: vec!["http://b".to_string(), "http://c".to_string()],
+            ..Default::default()
+        }
+        .normalized();
+        assert_eq!(
+            config.api_urls,
+            vec!["http://b".to_string(), "http://c".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff_is_capped() {
+        let config = CountTokensConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 400,
+            ..Default::default()
+        };
+        assert!(retry_backoff(&config, 0) >= Duration::from_millis(100));
+        // 上限 400ms + 最多 1/4 抖动
+        assert!(retry_backoff(&config, 10) <= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_remote_count_tokens_falls_back_to_next_url() {
+        let (failing, _) =
+            spawn_flaky_server(StatusCode::INTERNAL_SERVER_ERROR, usize::MAX, 0).await;
+        let (healthy, _) = spawn_flaky_server(StatusCode::OK, 0, 42).await;
+
+        let config = test_config(vec![failing, healthy], 0);
+        let tokens = call_remote_count_tokens_with_fallback(
+            &config,
+            &test_request("fallback"),
+            &ClientCountContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tokens, 42);
+    }
+
+    #[tokio::test]
+    async fn test_remote_count_tokens_reports_all_failures() {
+        let (first, _) = spawn_flaky_server(StatusCode::BAD_GATEWAY, usize::MAX, 0).await;
+        let (second, _) = spawn_flaky_server(StatusCode::NOT_FOUND, usize::MAX, 0).await;
+
+        let config = test_config(vec![first.clone(), second.clone()], 0);
+        let err = call_remote_count_tokens_with_fallback(
+            &config,
+            &test_request("failures"),
+            &ClientCountContext::default(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains(&first), "{}", err);
+        assert!(err.contains(&second), "{}", err);
+        assert!(err.contains("502"), "{}", err);
+        assert!(err.contains("404"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_remote_count_tokens_retries_then_succeeds() {
+        let (url, hits) = spawn_flaky_server(StatusCode::TOO_MANY_REQUESTS, 2, 7).await;
+
+        let config = test_config(vec![url], 2);
+        let tokens = call_remote_count_tokens_with_fallback(
+            &config,
+            &test_request("retry"),
+            &ClientCountContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tokens, 7);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_remote_count_tokens_does_not_retry_client_error() {
+        let (url, hits) = spawn_flaky_server(StatusCode::BAD_REQUEST, usize::MAX, 0).await;
+
+        let config = test_config(vec![url], 3);
+        assert!(
+            call_remote_count_tokens_with_fallback(
+                &config,
+                &test_request("permanent"),
+                &ClientCountContext::default(),
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_exhausted_falls_back_to_local() {
+        let (url, hits) = spawn_flaky_server(StatusCode::SERVICE_UNAVAILABLE, usize::MAX, 0).await;
+        let request = test_request("exhausted");
+        let expected = count_all_tokens_local(None, request.messages.clone(), None);
+
+        let config = test_config(vec![url], 2);
+        let (tokens, source) =
+            count_tokens_remote_or_local(&config, request, &ClientCountContext::default()).await;
+        assert_eq!(source, TokenCountSource::LocalFallback);
+        assert_eq!(tokens, expected);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_hit_cache() {
+        let (url, hits) = spawn_flaky_server(StatusCode::OK, 0, 99).await;
+
+        let config = test_config(vec![url], 0);
+        let first = count_tokens_remote_or_local(
+            &config,
+            test_request("cache"),
+            &ClientCountContext::default(),
+        )
+        .await;
+        let second = count_tokens_remote_or_local(
+            &config,
+            test_request("cache"),
+            &ClientCountContext::default(),
+        )
+        .await;
+        assert_eq!(first, (99, TokenCountSource::Remote));
+        assert_eq!(second, (99, TokenCountSource::Cache));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// 记录收到的请求头，固定返回 `tokens`
+    async fn spawn_recording_server() -> (String, Arc<Mutex<Vec<HeaderMap>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorder = received.clone();
+        let router = Router::new().route(
+            "/count",
+            post(move |headers: HeaderMap| {
+                let recorder = recorder.clone();
+                async move {
+                    recorder.lock().push(headers);
+                    Json(serde_json::json!({ "input_tokens": 5 }))
+                }
+            }),
+        );
+        (format!("{}/count", spawn_server(router).await), received)
+    }
+
+    fn client_headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_auth_type_passthrough_ignores_configured_key() {
+        assert_eq!(
+            AuthType::from_config("Passthrough", None),
+            AuthType::Passthrough
+        );
+        assert_eq!(
+            AuthType::from_config("passthrough", Some("sk-1")),
+            AuthType::Passthrough
+        );
+    }
+
+    #[test]
+    fn test_client_context_never_forwards_admin_key() {
+        let admin = Some("admin-secret");
+        let context = |pairs: &[(&'static str, &str)]| {
+            ClientCountContext::from_headers(&client_headers(pairs), admin).credential
+        };
+
+        assert_eq!(
+            context(&[("x-api-key", "sk-a"), ("authorization", "Bearer sk-b")]),
+            Some(ClientCredential::ApiKey("sk-a".to_string()))
+        );
+        assert_eq!(
+            context(&[("authorization", "Bearer sk-b")]),
+            Some(ClientCredential::Authorization("Bearer sk-b".to_string()))
+        );
+        assert_eq!(context(&[("x-api-key", "admin-secret")]), None);
+        assert_eq!(context(&[("authorization", "Bearer admin-secret")]), None);
+        assert_eq!(context(&[("x-admin-key", "admin-secret")]), None);
+    }
+
+    #[tokio::test]
+    async fn test_remote_count_tokens_auth_header_per_mode() {
+        let (url, received) = spawn_recording_server().await;
+        let client = ClientCountContext::from_headers(
+            &client_headers(&[
+                ("x-api-key", "sk-tenant"),
+                ("x-admin-key", "admin-secret"),
+                ("anthropic-version", "2023-06-01"),
+            ]),
+            Some("admin-secret"),
+        );
+        let bearer_client = ClientCountContext::from_headers(
+            &client_headers(&[("authorization", "Bearer sk-tenant-2")]),
+            Some("admin-secret"),
+        );
+        let admin_client = ClientCountContext::from_headers(
+            &client_headers(&[("x-api-key", "admin-secret")]),
+            Some("admin-secret"),
+        );
+
+        let cases = [
+            ("bearer", &client, "authorization", Some("Bearer sk-config")),
+            ("x-api-key", &client, "x-api-key", Some("sk-config")),
+            ("passthrough", &client, "x-api-key", Some("sk-tenant")),
+            (
+                "passthrough",
+                &bearer_client,
+                "authorization",
+                Some("Bearer sk-tenant-2"),
+            ),
+            ("passthrough", &admin_client, "x-api-key", None),
+        ];
+        for (i, (auth_type, client, header_name, expected)) in cases.into_iter().enumerate() {
+            let mut config = test_config(vec![url.clone()], 0);
+            config.auth_type = AuthType::from_config(auth_type, Some("sk-config"));
+            let tokens =
+                call_remote_count_tokens_with_fallback(&config, &test_request("modes"), client)
+                    .await
+                    .unwrap();
+            assert_eq!(tokens, 5);
+
+            let headers = received.lock()[i].clone();
+            assert_eq!(
+                headers.get(header_name).map(|v| v.to_str().unwrap()),
+                expected,
+                "mode {}",
+                auth_type
+            );
+            assert!(headers.get("x-admin-key").is_none());
+            if auth_type == "passthrough" {
+                assert!(!format!("{:?}", headers).contains("sk-config"));
+            }
+        }
+
+        // anthropic-version：配置优先，未配置时沿用客户端请求头
+        let headers = received.lock()[0].clone();
+        assert_eq!(headers["anthropic-version"], "2023-06-01");
+        let mut config = test_config(vec![url], 0);
+        config.anthropic_version = Some("2024-01-01".to_string());
+        call_remote_count_tokens_with_fallback(&config, &test_request("modes"), &client)
+            .await
+            .unwrap();
+        assert_eq!(
+            received.lock().last().unwrap()["anthropic-version"],
+            "2024-01-01"
+        );
+    }
+}